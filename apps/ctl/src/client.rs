@@ -0,0 +1,341 @@
+//! Typed Control API client
+//!
+//! One method per endpoint, taking and returning the server's own request and
+//! response types. Error responses become errors carrying the server's message.
+
+use aaeq_core::{DspSettings, EqPreset, ManagedDevice, Mapping, Profile, TrackMeta};
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::StreamExt;
+use reqwest::{Method, StatusCode};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::CertificateDer;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use stream_server::control_api::types::*;
+use stream_server::StreamEvent;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+
+/// Where `aaeqd` serves the Control API by default
+pub const DEFAULT_URL: &str = "http://127.0.0.1:8080";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for the event stream's replay of what's playing
+const REPLAY_WAIT: Duration = Duration::from_millis(500);
+
+/// A Control API server
+#[derive(Clone)]
+pub struct Client {
+    base: String,
+    token: Option<String>,
+    http: reqwest::Client,
+    /// Certificates trusted besides the usual roots
+    ca_certs: Vec<CertificateDer<'static>>,
+}
+
+impl Client {
+    /// A client for the server at `url` (`http://` or `https://`)
+    pub fn new(url: &str) -> Result<Self> {
+        let base = url.trim().trim_end_matches('/').to_string();
+        if !base.starts_with("http://") && !base.starts_with("https://") {
+            bail!("Server URL must start with http:// or https:// (got '{}')", url);
+        }
+        let mut client = Self {
+            base,
+            token: None,
+            http: reqwest::Client::new(),
+            ca_certs: Vec::new(),
+        };
+        client.http = client.build_http()?;
+        Ok(client)
+    }
+
+    /// Send this bearer token with every request
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Also trust the certificates in this PEM, e.g. the server's self-signed one
+    pub fn with_ca_cert(mut self, pem: &[u8]) -> Result<Self> {
+        let certs = CertificateDer::pem_slice_iter(pem)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .context("Invalid CA certificate")?;
+        if certs.is_empty() {
+            bail!("No certificate in the CA certificate file");
+        }
+        self.ca_certs.extend(certs);
+        self.http = self.build_http()?;
+        Ok(self)
+    }
+
+    pub fn url(&self) -> &str {
+        &self.base
+    }
+
+    fn build_http(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder().timeout(REQUEST_TIMEOUT);
+        for cert in &self.ca_certs {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_der(cert)?);
+        }
+        Ok(builder.build()?)
+    }
+
+    async fn request<T: DeserializeOwned>(&self, method: Method, path: &str, body: Option<&impl Serialize>) -> Result<T> {
+        let mut request = self.http.request(method.clone(), format!("{}{}", self.base, path));
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", self.base))?;
+
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            bail!("{}", error_message(status, &text));
+        }
+        serde_json::from_str(&text).with_context(|| format!("Unexpected response to {} {}", method, path))
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.request(Method::GET, path, None::<&()>).await
+    }
+
+    async fn post<T: DeserializeOwned>(&self, path: &str, body: Option<&impl Serialize>) -> Result<T> {
+        self.request(Method::POST, path, body).await
+    }
+
+    pub async fn outputs(&self) -> Result<OutputsResponse> {
+        self.get("/v1/outputs").await
+    }
+
+    pub async fn select_output(&self, request: &SelectOutputRequest) -> Result<SelectOutputResponse> {
+        self.post("/v1/outputs/select", Some(request)).await
+    }
+
+    pub async fn start_output(&self) -> Result<SuccessResponse> {
+        self.post("/v1/outputs/start", None::<&()>).await
+    }
+
+    pub async fn stop_output(&self) -> Result<SuccessResponse> {
+        self.post("/v1/outputs/stop", None::<&()>).await
+    }
+
+    pub async fn metrics(&self) -> Result<MetricsResponse> {
+        self.get("/v1/outputs/metrics").await
+    }
+
+    pub async fn presets(&self) -> Result<PresetsResponse> {
+        self.get("/v1/presets").await
+    }
+
+    pub async fn preset(&self, name: &str) -> Result<EqPreset> {
+        self.get(&format!("/v1/presets/{}", encode(name))).await
+    }
+
+    pub async fn save_preset(&self, name: &str, request: &PresetRequest) -> Result<EqPreset> {
+        self.request(Method::PUT, &format!("/v1/presets/{}", encode(name)), Some(request))
+            .await
+    }
+
+    pub async fn apply_preset(&self, name: &str) -> Result<SuccessResponse> {
+        self.post(&format!("/v1/presets/{}/apply", encode(name)), None::<&()>).await
+    }
+
+    pub async fn profiles(&self) -> Result<ProfilesResponse> {
+        self.get("/v1/profiles").await
+    }
+
+    pub async fn create_profile(&self, request: &ProfileRequest) -> Result<Profile> {
+        self.post("/v1/profiles", Some(request)).await
+    }
+
+    pub async fn activate_profile(&self, id: i64) -> Result<SuccessResponse> {
+        self.post(&format!("/v1/profiles/{}/activate", id), None::<&()>).await
+    }
+
+    /// A profile's mapping rules (the active profile's without one)
+    pub async fn mappings(&self, profile_id: Option<i64>) -> Result<Vec<Mapping>> {
+        self.get(&format!("/v1/mappings{}", profile_query(profile_id))).await
+    }
+
+    pub async fn save_mapping(&self, request: &MappingRequest) -> Result<Mapping> {
+        self.post("/v1/mappings", Some(request)).await
+    }
+
+    pub async fn dsp(&self, profile_id: Option<i64>) -> Result<DspSettings> {
+        self.get(&format!("/v1/dsp{}", profile_query(profile_id))).await
+    }
+
+    /// Change some of a profile's DSP settings, by field name
+    pub async fn update_dsp(&self, profile_id: Option<i64>, changes: &Map<String, Value>) -> Result<DspSettings> {
+        self.request(Method::PATCH, &format!("/v1/dsp{}", profile_query(profile_id)), Some(changes))
+            .await
+    }
+
+    pub async fn devices(&self, profile_id: Option<i64>) -> Result<Vec<ManagedDevice>> {
+        self.get(&format!("/v1/devices{}", profile_query(profile_id))).await
+    }
+
+    pub async fn create_device(&self, request: &DeviceRequest) -> Result<ManagedDevice> {
+        self.post("/v1/devices", Some(request)).await
+    }
+
+    /// Subscribe to `/v1/events`, only to `types` if any are given
+    pub async fn events(&self, types: &[String]) -> Result<Events> {
+        let scheme = if self.base.starts_with("https://") { "wss://" } else { "ws://" };
+        let host = self.base.split_once("://").map_or(self.base.as_str(), |(_, rest)| rest);
+        let mut url = format!("{}{}/v1/events", scheme, host);
+        if !types.is_empty() {
+            url.push_str(&format!("?types={}", encode(&types.join(","))));
+        }
+
+        let mut request = url.as_str().into_client_request()?;
+        if let Some(token) = &self.token {
+            request
+                .headers_mut()
+                .insert("Authorization", HeaderValue::from_str(&format!("Bearer {}", token))?);
+        }
+        let connector = match scheme {
+            "wss://" => Some(Connector::Rustls(Arc::new(self.tls_config()?))),
+            _ => None,
+        };
+        let (socket, _) = tokio_tungstenite::connect_async_tls_with_config(request, None, false, connector)
+            .await
+            .map_err(|e| anyhow!("Failed to open the event stream at {}: {}", url, e))?;
+        Ok(Events { socket })
+    }
+
+    /// The playing track and its preset, as the event stream tells new clients
+    pub async fn now_playing(&self) -> Result<NowPlaying> {
+        let types = ["track_changed".to_string(), "preset_applied".to_string()];
+        let mut events = self.events(&types).await?;
+        let mut now_playing = NowPlaying::default();
+        // The latest of each is sent right after connecting; anything later is news
+        while let Ok(Some(event)) = tokio::time::timeout(REPLAY_WAIT, events.next()).await {
+            match event? {
+                StreamEvent::TrackChanged { track } => now_playing.track = Some(track),
+                StreamEvent::PresetApplied { preset, .. } => now_playing.preset = Some(preset),
+                _ => {}
+            }
+            if now_playing.track.is_some() && now_playing.preset.is_some() {
+                break;
+            }
+        }
+        Ok(now_playing)
+    }
+
+    fn tls_config(&self) -> Result<rustls::ClientConfig> {
+        let mut roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        for cert in &self.ca_certs {
+            roots.add(cert.clone())?;
+        }
+        Ok(
+            rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        )
+    }
+}
+
+/// What's playing, from [`Client::now_playing`]
+#[derive(Debug, Clone, Default)]
+pub struct NowPlaying {
+    pub track: Option<TrackMeta>,
+    pub preset: Option<String>,
+}
+
+/// An open `/v1/events` subscription
+pub struct Events {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Events {
+    /// The next event, or `None` once the server closes the stream
+    pub async fn next(&mut self) -> Option<Result<StreamEvent>> {
+        while let Some(message) = self.socket.next().await {
+            match message {
+                Ok(Message::Text(text)) => {
+                    return Some(serde_json::from_str(&text).context("Unexpected event"));
+                }
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        None
+    }
+}
+
+fn profile_query(profile_id: Option<i64>) -> String {
+    profile_id.map(|id| format!("?profile_id={}", id)).unwrap_or_default()
+}
+
+/// Percent-encode a path segment or query value
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The server's `error` (and `details`) from an error response, else the status
+fn error_message(status: StatusCode, body: &str) -> String {
+    let message = serde_json::from_str::<ErrorResponse>(body)
+        .map(|e| match e.details {
+            Some(details) => format!("{}: {}", e.error, details),
+            None => e.error,
+        })
+        .or_else(|_| serde_json::from_str::<SuccessResponse>(body).map(|r| r.message))
+        .unwrap_or_else(|_| status.to_string());
+    match status {
+        StatusCode::UNAUTHORIZED => format!("{} (pass a token with --token or AAEQ_TOKEN)", message),
+        StatusCode::FORBIDDEN => format!("{} (this needs a control token)", message),
+        _ => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_validates_url() {
+        assert_eq!(Client::new("http://aaeq.local:8080/").unwrap().url(), "http://aaeq.local:8080");
+        assert!(Client::new("aaeq.local:8080").is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("Bass Booster"), "Bass%20Booster");
+        assert_eq!(encode("R&B/Soul"), "R%26B%2FSoul");
+        assert_eq!(encode("Flat"), "Flat");
+    }
+
+    #[test]
+    fn test_error_message() {
+        let body = r#"{"error":"Preset 'X' not found","details":null}"#;
+        assert_eq!(error_message(StatusCode::NOT_FOUND, body), "Preset 'X' not found");
+        let body = r#"{"success":false,"message":"No active output"}"#;
+        assert_eq!(error_message(StatusCode::BAD_REQUEST, body), "No active output");
+        assert!(error_message(StatusCode::UNAUTHORIZED, "").contains("--token"));
+    }
+}
@@ -0,0 +1,172 @@
+//! Configuration export and import
+//!
+//! An export is a JSON document of the custom presets and, per profile, its
+//! mapping rules, DSP settings and devices. Importing merges it into another
+//! server: profiles are matched by name and created if missing, presets and
+//! rules with the same name or key are replaced, and nothing is deleted.
+
+use crate::client::Client;
+use aaeq_core::EqPreset;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use stream_server::control_api::types::*;
+
+/// Format of [`ConfigExport`]; imports of newer versions are refused
+pub const EXPORT_VERSION: u32 = 1;
+
+/// DSP settings fields that belong to the server's database rather than the settings
+const DSP_RECORD_FIELDS: [&str; 4] = ["id", "profile_id", "created_at", "updated_at"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigExport {
+    pub version: u32,
+    /// Custom presets only; built-in ones exist everywhere
+    pub presets: Vec<EqPreset>,
+    pub profiles: Vec<ProfileExport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileExport {
+    pub name: String,
+    pub icon: String,
+    pub color: String,
+    /// Without `profile_id`, applied to the profile of this name
+    pub mappings: Vec<MappingRequest>,
+    pub dsp: Map<String, Value>,
+    pub devices: Vec<DeviceRequest>,
+}
+
+/// What an import changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub presets: usize,
+    pub profiles_created: usize,
+    pub mappings: usize,
+    pub devices: usize,
+}
+
+/// Read everything an import can restore
+pub async fn export_config(client: &Client) -> Result<ConfigExport> {
+    let mut presets = Vec::new();
+    for name in client.presets().await?.custom {
+        presets.push(client.preset(&name).await?);
+    }
+
+    let mut profiles = Vec::new();
+    for profile in client.profiles().await?.profiles {
+        let id = profile.id;
+        let mappings = client
+            .mappings(id)
+            .await?
+            .into_iter()
+            .map(|mapping| MappingRequest {
+                scope: mapping.scope,
+                key: mapping.key_normalized,
+                preset: mapping.preset_name,
+                profile_id: None,
+            })
+            .collect();
+
+        let Value::Object(mut dsp) = serde_json::to_value(client.dsp(id).await?)? else {
+            bail!("Unexpected DSP settings");
+        };
+        dsp.retain(|field, _| !DSP_RECORD_FIELDS.contains(&field.as_str()));
+
+        let devices = client
+            .devices(id)
+            .await?
+            .into_iter()
+            .map(|device| DeviceRequest {
+                name: device.name,
+                protocol: device.protocol,
+                address: device.address,
+                favorite: device.favorite,
+                profile_id: None,
+            })
+            .collect();
+
+        profiles.push(ProfileExport {
+            name: profile.name,
+            icon: profile.icon,
+            color: profile.color,
+            mappings,
+            dsp,
+            devices,
+        });
+    }
+
+    Ok(ConfigExport {
+        version: EXPORT_VERSION,
+        presets,
+        profiles,
+    })
+}
+
+/// Merge an export into the server's configuration
+pub async fn import_config(client: &Client, config: &ConfigExport) -> Result<ImportSummary> {
+    if config.version > EXPORT_VERSION {
+        bail!(
+            "This export is version {}; this aaeqctl reads up to version {}",
+            config.version,
+            EXPORT_VERSION
+        );
+    }
+    let mut summary = ImportSummary::default();
+
+    // Presets first, so mappings can refer to them
+    for preset in &config.presets {
+        let request = PresetRequest {
+            bands: preset.bands.clone(),
+        };
+        client.save_preset(&preset.name, &request).await?;
+        summary.presets += 1;
+    }
+
+    let existing = client.profiles().await?.profiles;
+    for profile in &config.profiles {
+        let id = match existing.iter().find(|p| p.name == profile.name) {
+            Some(found) => found.id,
+            None => {
+                let request = ProfileRequest {
+                    name: Some(profile.name.clone()),
+                    icon: Some(profile.icon.clone()),
+                    color: Some(profile.color.clone()),
+                };
+                summary.profiles_created += 1;
+                client.create_profile(&request).await?.id
+            }
+        };
+
+        for mapping in &profile.mappings {
+            let request = MappingRequest {
+                profile_id: id,
+                ..mapping.clone()
+            };
+            client.save_mapping(&request).await?;
+            summary.mappings += 1;
+        }
+
+        if !profile.dsp.is_empty() {
+            client.update_dsp(id, &profile.dsp).await?;
+        }
+
+        let devices = client.devices(id).await?;
+        for device in &profile.devices {
+            let known = devices
+                .iter()
+                .any(|d| d.protocol == device.protocol && d.address == device.address);
+            if known {
+                continue;
+            }
+            let request = DeviceRequest {
+                profile_id: id,
+                ..device.clone()
+            };
+            client.create_device(&request).await?;
+            summary.devices += 1;
+        }
+    }
+
+    Ok(summary)
+}
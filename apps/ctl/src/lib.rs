@@ -0,0 +1,13 @@
+//! Command-line client for the AAEQ Control API
+//!
+//! `aaeqctl` drives a running `aaeqd` (or anything else serving the Control
+//! API) from a terminal: outputs and metrics, presets, profiles, mapping the
+//! playing track, configuration export/import and the event stream. Requests
+//! and responses use the server's own types from
+//! `stream_server::control_api::types`, so the two can't drift apart.
+
+mod client;
+mod export;
+
+pub use client::{Client, Events, NowPlaying, DEFAULT_URL};
+pub use export::{export_config, import_config, ConfigExport, ImportSummary, ProfileExport, EXPORT_VERSION};
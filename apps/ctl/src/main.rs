@@ -0,0 +1,505 @@
+use aaeq_core::{Scope, TrackMeta};
+use aaeq_ctl::{export_config, import_config, Client, ConfigExport, DEFAULT_URL};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::path::PathBuf;
+use stream_server::control_api::types::*;
+use stream_server::{OutputConfig, SampleFormat};
+
+/// aaeqctl - control a running AAEQ from the command line
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Control API address
+    #[arg(long, env = "AAEQ_URL", default_value = DEFAULT_URL, global = true)]
+    url: String,
+
+    /// Bearer token, when the server requires one
+    #[arg(long, env = "AAEQ_TOKEN", hide_env_values = true, global = true)]
+    token: Option<String>,
+
+    /// PEM certificate to trust, e.g. the server's generated control-api.crt
+    #[arg(long, env = "AAEQ_CA_CERT", global = true)]
+    ca_cert: Option<PathBuf>,
+
+    /// Print the server's JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// What's playing, the preset, the profile and the output
+    Status,
+    /// List, select, start and stop outputs
+    Outputs {
+        #[command(subcommand)]
+        command: Option<OutputCommand>,
+    },
+    /// Show the active output's metrics
+    Metrics,
+    /// List, show and apply presets
+    Presets {
+        #[command(subcommand)]
+        command: Option<PresetCommand>,
+    },
+    /// List and switch profiles
+    Profiles {
+        #[command(subcommand)]
+        command: Option<ProfileCommand>,
+    },
+    /// Map the playing track's song, album or genre to a preset
+    Map {
+        scope: MapScope,
+        preset: String,
+        /// Profile name or id (defaults to the active one)
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// List a profile's mapping rules
+    Mappings {
+        /// Profile name or id (defaults to the active one)
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// Write presets, profiles, mappings, DSP settings and devices as JSON
+    Export {
+        /// File to write (standard output without one)
+        file: Option<PathBuf>,
+    },
+    /// Merge an export into the server's configuration
+    Import { file: PathBuf },
+    /// Print events as JSON lines until interrupted
+    Events {
+        /// Only these event types, e.g. track_changed,preset_applied
+        #[arg(long, value_delimiter = ',')]
+        types: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum OutputCommand {
+    /// List outputs (the default)
+    List,
+    /// Make an output the active one
+    Select {
+        name: String,
+        /// Device to open, for outputs that need one
+        #[arg(long)]
+        device: Option<String>,
+        #[arg(long)]
+        sample_rate: Option<u32>,
+        /// F64, F32, S24LE or S16LE
+        #[arg(long, value_parser = parse_format)]
+        format: Option<SampleFormat>,
+        #[arg(long)]
+        buffer_ms: Option<u32>,
+    },
+    /// Start streaming to the active output
+    Start,
+    /// Stop streaming
+    Stop,
+}
+
+#[derive(Subcommand, Debug)]
+enum PresetCommand {
+    /// List preset names (the default)
+    List,
+    /// Show a preset's bands
+    Show { name: String },
+    /// Apply a preset until the track changes
+    Apply { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileCommand {
+    /// List profiles, marking the active one (the default)
+    List,
+    /// Switch to a profile by name or id
+    Use { profile: String },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum MapScope {
+    Song,
+    Album,
+    Genre,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let mut client = Client::new(&args.url)?;
+    if let Some(token) = &args.token {
+        client = client.with_token(token);
+    }
+    if let Some(path) = &args.ca_cert {
+        let pem = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        client = client.with_ca_cert(&pem)?;
+    }
+    let out = Output { json: args.json };
+
+    match args.command {
+        Command::Status => status(&client, out).await,
+        Command::Outputs { command } => outputs(&client, out, command.unwrap_or(OutputCommand::List)).await,
+        Command::Metrics => metrics(&client, out).await,
+        Command::Presets { command } => presets(&client, out, command.unwrap_or(PresetCommand::List)).await,
+        Command::Profiles { command } => profiles(&client, out, command.unwrap_or(ProfileCommand::List)).await,
+        Command::Map { scope, preset, profile } => map(&client, out, scope, &preset, profile.as_deref()).await,
+        Command::Mappings { profile } => mappings(&client, out, profile.as_deref()).await,
+        Command::Export { file } => {
+            let config = serde_json::to_string_pretty(&export_config(&client).await?)?;
+            match file {
+                Some(path) => {
+                    std::fs::write(&path, config + "\n").with_context(|| format!("Failed to write {}", path.display()))?;
+                    eprintln!("Exported to {}", path.display());
+                }
+                None => println!("{}", config),
+            }
+            Ok(())
+        }
+        Command::Import { file } => {
+            let text = std::fs::read_to_string(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+            let config: ConfigExport = serde_json::from_str(&text).context("Not an aaeqctl export")?;
+            let summary = import_config(&client, &config).await?;
+            println!(
+                "Imported {} preset(s), {} mapping(s) and {} device(s); created {} profile(s)",
+                summary.presets, summary.mappings, summary.devices, summary.profiles_created
+            );
+            Ok(())
+        }
+        Command::Events { types } => {
+            let mut events = client.events(&types).await?;
+            while let Some(event) = events.next().await {
+                println!("{}", serde_json::to_string(&event?)?);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Prints either text or the server's JSON
+#[derive(Clone, Copy)]
+struct Output {
+    json: bool,
+}
+
+impl Output {
+    /// Print `value` as JSON, or `text` of it
+    fn print<T: Serialize>(&self, value: &T, text: impl FnOnce(&T) -> String) -> Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(value)?);
+        } else {
+            let text = text(value);
+            if !text.is_empty() {
+                println!("{}", text.trim_end());
+            }
+        }
+        Ok(())
+    }
+
+    fn done(&self, response: &SuccessResponse) -> Result<()> {
+        self.print(response, |response| response.message.clone())
+    }
+}
+
+async fn status(client: &Client, out: Output) -> Result<()> {
+    let now_playing = client.now_playing().await?;
+    let metrics = client.metrics().await?;
+    // Profiles are only served with a database
+    let profile = client.profiles().await.ok().and_then(|profiles| {
+        profiles
+            .profiles
+            .into_iter()
+            .find(|profile| profile.id == Some(profiles.active_id))
+            .map(|profile| profile.name)
+    });
+
+    let status = serde_json::json!({
+        "track": now_playing.track,
+        "preset": now_playing.preset,
+        "profile": profile,
+        "metrics": metrics,
+    });
+    out.print(&status, |_| {
+        let playing = now_playing.track.as_ref().map(describe_track);
+        let output = match &metrics.output_name {
+            Some(name) => {
+                let state = if metrics.streaming { "streaming" } else { "idle" };
+                match (metrics.sample_rate, &metrics.format) {
+                    (Some(rate), Some(format)) => format!("{} ({}, {} Hz {})", name, state, rate, format),
+                    _ => format!("{} ({})", name, state),
+                }
+            }
+            None => "None".to_string(),
+        };
+        format!(
+            "Playing  {}\nPreset   {}\nProfile  {}\nOutput   {}",
+            playing.as_deref().unwrap_or("Nothing"),
+            now_playing.preset.as_deref().unwrap_or("-"),
+            profile.as_deref().unwrap_or("-"),
+            output
+        )
+    })
+}
+
+fn describe_track(track: &TrackMeta) -> String {
+    let mut text = format!("{} – {}", track.artist, track.title);
+    if !track.album.is_empty() {
+        text.push_str(&format!(" ({})", track.album));
+    }
+    text
+}
+
+async fn outputs(client: &Client, out: Output, command: OutputCommand) -> Result<()> {
+    match command {
+        OutputCommand::List => {
+            let response = client.outputs().await?;
+            out.print(&response, |response| {
+                response
+                    .outputs
+                    .iter()
+                    .map(|output| {
+                        let marker = if output.is_active { "*" } else { " " };
+                        let detail = match &output.config {
+                            Some(config) if output.is_open => {
+                                format!("open  {} Hz {:?}, {} ms", config.sample_rate, config.format, output.latency_ms)
+                            }
+                            _ => "closed".to_string(),
+                        };
+                        format!("{} {:<12} {}\n", marker, output.name, detail)
+                    })
+                    .collect()
+            })
+        }
+        OutputCommand::Select {
+            name,
+            device,
+            sample_rate,
+            format,
+            buffer_ms,
+        } => {
+            // Start from the output's current settings, as the web UI does
+            let current = client
+                .outputs()
+                .await?
+                .outputs
+                .into_iter()
+                .find(|output| output.name == name)
+                .and_then(|output| output.config);
+            let mut config = current.unwrap_or_else(OutputConfig::default);
+            config.sample_rate = sample_rate.unwrap_or(config.sample_rate);
+            config.format = format.unwrap_or(config.format);
+            config.buffer_ms = buffer_ms.unwrap_or(config.buffer_ms);
+
+            let response = client.select_output(&SelectOutputRequest { name, device, config }).await?;
+            out.print(&response, |response| response.message.clone())
+        }
+        OutputCommand::Start => out.done(&client.start_output().await?),
+        OutputCommand::Stop => out.done(&client.stop_output().await?),
+    }
+}
+
+fn parse_format(format: &str) -> Result<SampleFormat> {
+    match format.to_ascii_uppercase().as_str() {
+        "F64" => Ok(SampleFormat::F64),
+        "F32" => Ok(SampleFormat::F32),
+        "S24LE" => Ok(SampleFormat::S24LE),
+        "S16LE" => Ok(SampleFormat::S16LE),
+        _ => bail!("expected F64, F32, S24LE or S16LE"),
+    }
+}
+
+async fn metrics(client: &Client, out: Output) -> Result<()> {
+    let metrics = client.metrics().await?;
+    out.print(&metrics, |m| {
+        format!(
+            "output          {}\nstreaming       {}\nsample rate     {}\nformat          {}\nlatency         {} ms\n\
+             buffer fill     {:.0}%\nframes written  {}\nbytes written   {}\nunderruns       {}\noverruns        {}",
+            m.output_name.as_deref().unwrap_or("-"),
+            if m.streaming { "yes" } else { "no" },
+            m.sample_rate.map(|rate| format!("{} Hz", rate)).unwrap_or_else(|| "-".to_string()),
+            m.format.as_deref().unwrap_or("-"),
+            m.latency_ms,
+            m.buffer_fill * 100.0,
+            m.frames_written,
+            m.bytes_written,
+            m.underruns,
+            m.overruns
+        )
+    })
+}
+
+async fn presets(client: &Client, out: Output, command: PresetCommand) -> Result<()> {
+    match command {
+        PresetCommand::List => {
+            let presets = client.presets().await?;
+            out.print(&presets, |presets| {
+                let builtin = presets.builtin.iter().map(|name| format!("{}\n", name));
+                let custom = presets.custom.iter().map(|name| format!("{}  (custom)\n", name));
+                builtin.chain(custom).collect()
+            })
+        }
+        PresetCommand::Show { name } => {
+            let preset = client.preset(&name).await?;
+            out.print(&preset, |preset| {
+                preset
+                    .bands
+                    .iter()
+                    .map(|band| format!("{:>6} Hz  {:+5.1} dB\n", band.frequency, band.gain))
+                    .collect()
+            })
+        }
+        PresetCommand::Apply { name } => out.done(&client.apply_preset(&name).await?),
+    }
+}
+
+/// The id of a profile given by name or id
+async fn resolve_profile(client: &Client, profile: &str) -> Result<(i64, String)> {
+    let profiles = client.profiles().await?.profiles;
+    profiles
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(profile))
+        .or_else(|| profiles.iter().find(|p| p.id.map(|id| id.to_string()).as_deref() == Some(profile)))
+        .and_then(|p| Some((p.id?, p.name.clone())))
+        .ok_or_else(|| anyhow!("No profile '{}'", profile))
+}
+
+async fn profiles(client: &Client, out: Output, command: ProfileCommand) -> Result<()> {
+    match command {
+        ProfileCommand::List => {
+            let response = client.profiles().await?;
+            out.print(&response, |response| {
+                response
+                    .profiles
+                    .iter()
+                    .map(|profile| {
+                        let marker = if profile.id == Some(response.active_id) { "*" } else { " " };
+                        format!("{} {:>3}  {} {}\n", marker, profile.id.unwrap_or_default(), profile.icon, profile.name)
+                    })
+                    .collect()
+            })
+        }
+        ProfileCommand::Use { profile } => {
+            let (id, _) = resolve_profile(client, &profile).await?;
+            out.done(&client.activate_profile(id).await?)
+        }
+    }
+}
+
+/// The mapping key for the track in `scope`, as the resolver matches it
+fn mapping_key(scope: MapScope, track: &TrackMeta) -> Result<(Scope, String)> {
+    let required = |value: &str, what: &str| {
+        if value.trim().is_empty() {
+            Err(anyhow!("The playing track has no {}", what))
+        } else {
+            Ok(())
+        }
+    };
+    required(&track.artist, "artist")?;
+    match scope {
+        MapScope::Song => {
+            required(&track.title, "title")?;
+            Ok((Scope::Song, track.song_key()))
+        }
+        MapScope::Album => {
+            required(&track.album, "album")?;
+            Ok((Scope::Album, track.album_key()))
+        }
+        MapScope::Genre => {
+            required(&track.genre, "genre")?;
+            Ok((Scope::Genre, track.genre_key()))
+        }
+    }
+}
+
+async fn map(client: &Client, out: Output, scope: MapScope, preset: &str, profile: Option<&str>) -> Result<()> {
+    let track = client
+        .now_playing()
+        .await?
+        .track
+        .ok_or_else(|| anyhow!("Nothing is playing"))?;
+    let (scope, key) = mapping_key(scope, &track)?;
+    let profile = match profile {
+        Some(profile) => Some(resolve_profile(client, profile).await?),
+        None => None,
+    };
+
+    let request = MappingRequest {
+        scope,
+        key: Some(key),
+        preset: preset.to_string(),
+        profile_id: profile.as_ref().map(|(id, _)| *id),
+    };
+    let mapping = client.save_mapping(&request).await?;
+    out.print(&mapping, |mapping| {
+        let profile = profile.map(|(_, name)| format!(" in {}", name)).unwrap_or_default();
+        format!(
+            "Mapped {} '{}' to {}{}",
+            mapping.scope.as_str(),
+            mapping.key_normalized.as_deref().unwrap_or_default(),
+            mapping.preset_name,
+            profile
+        )
+    })
+}
+
+async fn mappings(client: &Client, out: Output, profile: Option<&str>) -> Result<()> {
+    let profile_id = match profile {
+        Some(profile) => Some(resolve_profile(client, profile).await?.0),
+        None => None,
+    };
+    let mappings = client.mappings(profile_id).await?;
+    out.print(&mappings, |mappings| {
+        mappings
+            .iter()
+            .map(|mapping| {
+                format!(
+                    "{:<7}  {:<40}  {}\n",
+                    mapping.scope.as_str(),
+                    mapping.key_normalized.as_deref().unwrap_or("*"),
+                    mapping.preset_name
+                )
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapping_key() {
+        let track = TrackMeta {
+            artist: "Miles Davis".to_string(),
+            title: "So What".to_string(),
+            album: "Kind of Blue".to_string(),
+            genre: "Jazz".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(mapping_key(MapScope::Song, &track).unwrap(), (Scope::Song, "miles davis - so what".to_string()));
+        assert_eq!(
+            mapping_key(MapScope::Album, &track).unwrap(),
+            (Scope::Album, "miles davis - kind of blue".to_string())
+        );
+        assert_eq!(mapping_key(MapScope::Genre, &track).unwrap(), (Scope::Genre, "jazz".to_string()));
+
+        let single = TrackMeta {
+            album: String::new(),
+            ..track
+        };
+        assert!(mapping_key(MapScope::Album, &single).is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(parse_format("s24le").unwrap(), SampleFormat::S24LE);
+        assert!(parse_format("mp3").is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! aaeqctl tests against a Control API served from a fresh database: the
+//! typed client, export/import between two servers, and the binary itself.
+
+use aaeq_core::{EqBand, Scope, TrackMeta};
+use aaeq_ctl::{export_config, import_config, Client, ImportSummary};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::json;
+use std::process::Output;
+use std::sync::{Arc, Mutex};
+use stream_server::control_api::types::*;
+use stream_server::*;
+use tokio::sync::RwLock;
+
+/// Records what the API asked it to do
+#[derive(Default)]
+struct TestController {
+    presets: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl ProfileController for TestController {
+    async fn activate_profile(&self, _profile_id: i64) -> Result<()> {
+        Ok(())
+    }
+
+    async fn apply_preset(&self, preset: &str) -> Result<String> {
+        self.presets.lock().unwrap().push(preset.to_string());
+        Ok(preset.to_string())
+    }
+}
+
+async fn start_server(name: &str, controller: Arc<TestController>) -> ControlServer {
+    let dir = std::env::temp_dir().join(format!("aaeq-ctl-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let pool = aaeq_persistence::init_db(&dir.join("aaeq.db")).await.unwrap();
+
+    let manager = Arc::new(RwLock::new(OutputManager::new()));
+    let mut server = ControlServer::new("127.0.0.1:0".parse().unwrap(), manager)
+        .with_database(pool)
+        .with_profile_controller(controller);
+    server.start().await.unwrap();
+    server
+}
+
+fn kind_of_blue() -> TrackMeta {
+    TrackMeta {
+        artist: "Miles Davis".to_string(),
+        title: "So What".to_string(),
+        album: "Kind of Blue".to_string(),
+        genre: "Jazz".to_string(),
+        ..Default::default()
+    }
+}
+
+/// Run aaeqctl against `server`
+async fn aaeqctl(server: &ControlServer, args: &[&str]) -> Output {
+    let url = format!("http://{}", server.addr());
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    tokio::task::spawn_blocking(move || {
+        std::process::Command::new(env!("CARGO_BIN_EXE_aaeqctl"))
+            .args(["--url", &url])
+            .args(args)
+            .env_remove("AAEQ_TOKEN")
+            .output()
+            .unwrap()
+    })
+    .await
+    .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[tokio::test]
+async fn test_client() {
+    let mut server = start_server("client", Arc::new(TestController::default())).await;
+    let client = Client::new(&format!("http://{}", server.addr())).unwrap();
+
+    let presets = client.presets().await.unwrap();
+    assert!(presets.builtin.iter().any(|name| name == "Flat"));
+    assert!(presets.custom.is_empty());
+
+    let profiles = client.profiles().await.unwrap();
+    assert_eq!(profiles.profiles.len(), 2);
+    assert_eq!(profiles.active_id, 1);
+
+    // Nothing is playing until a track event
+    assert!(client.now_playing().await.unwrap().track.is_none());
+    server.events().publish(StreamEvent::TrackChanged { track: kind_of_blue() });
+    assert_eq!(client.now_playing().await.unwrap().track.unwrap().title, "So What");
+
+    // Server errors come back with their message
+    let error = client.preset("Nope").await.unwrap_err();
+    assert_eq!(error.to_string(), "Preset 'Nope' not found");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_export_import() {
+    let mut source = start_server("export", Arc::new(TestController::default())).await;
+    let client = Client::new(&format!("http://{}", source.addr())).unwrap();
+
+    let bands = vec![
+        EqBand {
+            frequency: 60,
+            gain: 3.0,
+        },
+        EqBand {
+            frequency: 8000,
+            gain: -2.0,
+        },
+    ];
+    client.save_preset("Warm", &PresetRequest { bands }).await.unwrap();
+    let car = client
+        .create_profile(&ProfileRequest {
+            name: Some("Car".to_string()),
+            icon: Some("🚗".to_string()),
+            color: None,
+        })
+        .await
+        .unwrap();
+    client
+        .save_mapping(&MappingRequest {
+            scope: Scope::Genre,
+            key: Some("Jazz".to_string()),
+            preset: "Warm".to_string(),
+            profile_id: car.id,
+        })
+        .await
+        .unwrap();
+    let dsp = json!({"crossfeed_enabled": true, "buffer_ms": 200});
+    client.update_dsp(car.id, dsp.as_object().unwrap()).await.unwrap();
+    client
+        .create_device(&DeviceRequest {
+            name: "Car stereo".to_string(),
+            protocol: "Dlna".to_string(),
+            address: "192.168.1.50".to_string(),
+            favorite: true,
+            profile_id: car.id,
+        })
+        .await
+        .unwrap();
+
+    let export = export_config(&client).await.unwrap();
+    assert_eq!(export.presets.len(), 1);
+    let exported_car = export.profiles.iter().find(|p| p.name == "Car").unwrap();
+    assert!(!exported_car.dsp.contains_key("profile_id"));
+
+    let mut target = start_server("import", Arc::new(TestController::default())).await;
+    let target_client = Client::new(&format!("http://{}", target.addr())).unwrap();
+    let summary = import_config(&target_client, &export).await.unwrap();
+    assert_eq!(
+        summary,
+        ImportSummary {
+            presets: 1,
+            profiles_created: 1,
+            mappings: 1,
+            devices: 1,
+        }
+    );
+
+    let imported = export_config(&target_client).await.unwrap();
+    assert_eq!(serde_json::to_value(&imported).unwrap(), serde_json::to_value(&export).unwrap());
+
+    // Importing again replaces rather than duplicates
+    let summary = import_config(&target_client, &export).await.unwrap();
+    assert_eq!(summary.profiles_created, 0);
+    assert_eq!(summary.devices, 0);
+    let again = export_config(&target_client).await.unwrap();
+    assert_eq!(serde_json::to_value(&again).unwrap(), serde_json::to_value(&export).unwrap());
+
+    // Newer exports are refused
+    let newer = aaeq_ctl::ConfigExport {
+        version: aaeq_ctl::EXPORT_VERSION + 1,
+        ..export
+    };
+    assert!(import_config(&target_client, &newer).await.is_err());
+
+    source.stop().await;
+    target.stop().await;
+}
+
+#[tokio::test]
+async fn test_binary() {
+    let controller = Arc::new(TestController::default());
+    let mut server = start_server("binary", controller.clone()).await;
+    let client = Client::new(&format!("http://{}", server.addr())).unwrap();
+
+    // Nothing to map before a track plays
+    let output = aaeqctl(&server, &["map", "album", "Jazz"]).await;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Nothing is playing"));
+
+    server.events().publish(StreamEvent::TrackChanged { track: kind_of_blue() });
+    let text = stdout(&aaeqctl(&server, &["map", "album", "Jazz"]).await);
+    assert!(text.contains("miles davis - kind of blue"), "{}", text);
+    let mappings = client.mappings(None).await.unwrap();
+    assert_eq!(mappings.len(), 1);
+    assert_eq!(mappings[0].scope, Scope::Album);
+    assert_eq!(mappings[0].preset_name, "Jazz");
+
+    stdout(&aaeqctl(&server, &["presets", "apply", "Rock"]).await);
+    assert_eq!(*controller.presets.lock().unwrap(), ["Rock"]);
+
+    stdout(&aaeqctl(&server, &["profiles", "use", "headphones"]).await);
+    assert_eq!(client.profiles().await.unwrap().active_id, 2);
+    assert!(!aaeqctl(&server, &["profiles", "use", "Nowhere"]).await.status.success());
+
+    let status = stdout(&aaeqctl(&server, &["status"]).await);
+    assert!(status.contains("Miles Davis – So What (Kind of Blue)"), "{}", status);
+    assert!(status.contains("Headphones"), "{}", status);
+
+    let outputs: serde_json::Value = serde_json::from_str(&stdout(&aaeqctl(&server, &["--json", "outputs"]).await)).unwrap();
+    assert!(outputs["outputs"].is_array());
+
+    // Export to a file and back
+    let file = std::env::temp_dir().join(format!("aaeq-ctl-export-{}.json", std::process::id()));
+    stdout(&aaeqctl(&server, &["export", file.to_str().unwrap()]).await);
+    let text = stdout(&aaeqctl(&server, &["import", file.to_str().unwrap()]).await);
+    assert!(text.contains("1 mapping(s)"), "{}", text);
+    let _ = std::fs::remove_file(file);
+
+    server.stop().await;
+}
@@ -0,0 +1,241 @@
+//! Daemon configuration file (TOML)
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use stream_server::{SampleFormat, TlsConfig};
+
+/// Everything `aaeqd` reads from its config file
+///
+/// Every section is optional; an empty file watches the system media
+/// session and serves the control API on localhost without streaming.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Profile to use; defaults to the profile active in the desktop app
+    pub profile: Option<String>,
+    /// How often to check the playing track, in milliseconds
+    pub poll_interval_ms: Option<u64>,
+    pub device: DeviceConfig,
+    pub stream: StreamConfig,
+    pub api: ApiConfig,
+}
+
+/// Where now-playing metadata comes from, and where presets go when not streaming
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// WiiM device address; without it the system media session is watched
+    pub host: Option<String>,
+    /// Name used in logs
+    pub label: Option<String>,
+}
+
+/// Output targets for the DSP stream
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputTarget {
+    /// Sound card; `device` is the output device name (default device if unset)
+    #[default]
+    LocalDac,
+    /// DLNA renderer found by discovery; `device` is its friendly name
+    Dlna,
+    /// AirPlay receiver found by discovery; `device` is its name
+    Airplay,
+    /// AAEQ node, by discovered name or `host:port`
+    Anp,
+    /// AES67 multicast; `device` is the group and port, e.g. `239.69.1.1:5004`
+    Aes67,
+    /// Record to a WAV/FLAC/W64 file; `device` is the path
+    File,
+}
+
+/// Capture → DSP → sink streaming
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamConfig {
+    pub enabled: bool,
+    /// Capture device; defaults to the last one used in the desktop app
+    pub input: Option<String>,
+    pub output: OutputTarget,
+    /// Output device name, address or path (see [`OutputTarget`])
+    pub device: Option<String>,
+    /// Capture rate; defaults to the profile's DSP sample rate
+    pub sample_rate: Option<u32>,
+    pub format: Option<SampleFormat>,
+    /// Defaults to the profile's DSP buffer size
+    pub buffer_ms: Option<u32>,
+    /// For the file output: `device` is a directory and each track is recorded to its own file
+    pub split_tracks: bool,
+}
+
+/// HTTP control API
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub bind: SocketAddr,
+    /// Require a bearer token on every request but `/v1/health`
+    pub auth: bool,
+    /// Serve HTTPS; with no certificate given, a self-signed one is generated in `tls_dir`
+    pub tls: bool,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// Where a generated certificate is kept; defaults to the database's directory
+    pub tls_dir: Option<PathBuf>,
+    /// Extra host names or addresses a generated certificate is valid for
+    pub tls_names: Vec<String>,
+    /// Origins of browser UIs allowed to call the API (`*` for any)
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bind: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            auth: false,
+            tls: false,
+            tls_cert: None,
+            tls_key: None,
+            tls_dir: None,
+            tls_names: Vec::new(),
+            cors_origins: Vec::new(),
+        }
+    }
+}
+
+impl ApiConfig {
+    /// The certificate to serve, generating a self-signed one if TLS is on without one
+    pub fn tls_config(&self) -> Result<Option<TlsConfig>> {
+        if !self.tls {
+            return Ok(None);
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Ok(Some(TlsConfig::new(cert, key))),
+            (None, None) => {
+                let dir = match &self.tls_dir {
+                    Some(dir) => dir.clone(),
+                    None => aaeq_persistence::default_db_path()?
+                        .parent()
+                        .map(Path::to_path_buf)
+                        .context("Database path has no directory")?,
+                };
+                Ok(Some(TlsConfig::self_signed(&dir, &self.tls_names)?))
+            }
+            _ => anyhow::bail!("api.tls_cert and api.tls_key must be set together"),
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Read and parse a config file
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval_ms
+            .map(|ms| Duration::from_millis(ms.max(100)))
+            .unwrap_or(Self::DEFAULT_POLL_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config() {
+        let config = DaemonConfig::parse("").unwrap();
+        assert_eq!(config, DaemonConfig::default());
+        assert!(!config.stream.enabled);
+        assert!(config.api.enabled);
+        assert_eq!(config.api.bind, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(config.poll_interval(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_full_config() {
+        let config = DaemonConfig::parse(
+            r#"
+            profile = "Living Room"
+            poll_interval_ms = 500
+
+            [device]
+            host = "192.168.1.50"
+
+            [stream]
+            enabled = true
+            input = "Monitor of Built-in Audio"
+            output = "aes67"
+            device = "239.69.1.1:5004"
+            sample_rate = 96000
+            format = "S24LE"
+
+            [api]
+            bind = "0.0.0.0:8090"
+            auth = true
+            cors_origins = ["http://localhost:5173"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.profile.as_deref(), Some("Living Room"));
+        assert_eq!(config.poll_interval(), Duration::from_millis(500));
+        assert_eq!(config.device.host.as_deref(), Some("192.168.1.50"));
+        assert_eq!(config.stream.output, OutputTarget::Aes67);
+        assert_eq!(config.stream.sample_rate, Some(96000));
+        assert_eq!(config.stream.format, Some(SampleFormat::S24LE));
+        assert_eq!(config.stream.buffer_ms, None);
+        assert_eq!(config.api.bind, "0.0.0.0:8090".parse().unwrap());
+        assert!(config.api.auth);
+        assert!(!config.api.tls);
+        assert_eq!(config.api.cors_origins, vec!["http://localhost:5173".to_string()]);
+    }
+
+    #[test]
+    fn test_tls_config() {
+        assert!(ApiConfig::default().tls_config().unwrap().is_none());
+
+        let api = DaemonConfig::parse("[api]\ntls = true\ntls_cert = \"/etc/aaeq/api.crt\"\ntls_key = \"/etc/aaeq/api.key\"\n")
+            .unwrap()
+            .api;
+        let tls = api.tls_config().unwrap().unwrap();
+        assert_eq!(tls.cert_path, PathBuf::from("/etc/aaeq/api.crt"));
+
+        let api = DaemonConfig::parse("[api]\ntls = true\ntls_cert = \"/etc/aaeq/api.crt\"\n").unwrap().api;
+        assert!(api.tls_config().is_err());
+    }
+
+    #[test]
+    fn test_split_tracks() {
+        let config = DaemonConfig::parse("[stream]\noutput = \"file\"\ndevice = \"/srv/rec\"\nsplit_tracks = true\n").unwrap();
+        assert_eq!(config.stream.output, OutputTarget::File);
+        assert!(config.stream.split_tracks);
+        assert!(!DaemonConfig::default().stream.split_tracks);
+    }
+
+    #[test]
+    fn test_example_config_parses() {
+        let config = DaemonConfig::parse(include_str!("../aaeqd.example.toml")).unwrap();
+        assert_eq!(config.stream.output, OutputTarget::Dlna);
+        assert!(!config.stream.enabled);
+    }
+
+    #[test]
+    fn test_rejects_unknown_keys() {
+        assert!(DaemonConfig::parse("[stream]\noutptu = \"file\"\n").is_err());
+        assert!(DaemonConfig::parse("[stream]\noutput = \"cassette\"\n").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! The daemon: track watcher, DSP stream, control API and MQTT bridge together
+
+use crate::config::DaemonConfig;
+use crate::profile::{Profile, FALLBACK_PRESET};
+use crate::stream::DaemonStreamer;
+use crate::watcher::TrackWatcher;
+use aaeq_core::preset_library::list_known_presets;
+use aaeq_device_wiim::WiimController;
+use aaeq_media_session::create_media_session;
+use aaeq_persistence::{AppSettingsRepository, CustomEqPresetRepository, ProfileRepository};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use sqlx::SqlitePool;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use stream_server::{
+    ControlServer, EventBus, MqttBridge, MqttHandle, MqttHandler, MqttState, OutputManager, ProfileController,
+    StreamController, Telemetry,
+};
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tracing::{info, warn};
+
+/// Running daemon state
+pub struct Daemon {
+    config: DaemonConfig,
+    /// Shared with the control API, which can switch profile and apply presets
+    watcher: Arc<Mutex<TrackWatcher>>,
+    pool: SqlitePool,
+    manager: Arc<RwLock<OutputManager>>,
+    /// Shared with the control API, which can start and stop it too
+    stream: Arc<DaemonStreamer>,
+    /// Tracks, presets, outputs and meters, for `/v1/events` clients
+    events: EventBus,
+    /// Poll failures, discovery times and stream health, for `/metrics`
+    telemetry: Telemetry,
+    control: Option<ControlServer>,
+    mqtt: Option<MqttHandle>,
+}
+impl Daemon {
+    /// Load the profile and watch the configured device (or the system media session)
+    pub async fn new(config: DaemonConfig, pool: SqlitePool) -> Result<Self> {
+        let profile = Profile::load(pool, config.profile.as_deref()).await?;
+        info!("Using profile '{}'", profile.name);
+
+        let mut watcher = TrackWatcher::new(profile).with_media_session(create_media_session());
+        if let Some(host) = &config.device.host {
+            let label = config.device.label.clone().unwrap_or_else(|| host.clone());
+            info!("Watching WiiM device {} at {}", label, host);
+            watcher = watcher.with_device(Arc::new(WiimController::new(label, host.clone())));
+        }
+        Ok(Self::with_watcher(config, watcher))
+    }
+
+    /// Use a prepared watcher, e.g. with another device controller
+    pub fn with_watcher(config: DaemonConfig, watcher: TrackWatcher) -> Self {
+        let events = EventBus::new();
+        let telemetry = Telemetry::new();
+        let mut manager = OutputManager::new();
+        manager.set_events(events.clone());
+        let manager = Arc::new(RwLock::new(manager));
+
+        let profile = watcher.profile();
+        let stream = DaemonStreamer::new(
+            manager.clone(),
+            config.stream.clone(),
+            profile.settings.clone(),
+            profile.pool().clone(),
+        )
+        .with_events(events.clone())
+        .with_telemetry(telemetry.clone());
+        let pool = profile.pool().clone();
+        let watcher = watcher.with_events(events.clone()).with_telemetry(telemetry.clone());
+        Self {
+            config,
+            pool,
+            watcher: Arc::new(Mutex::new(watcher)),
+            manager,
+            stream: Arc::new(stream),
+            events,
+            telemetry,
+            control: None,
+            mqtt: None,
+        }
+    }
+
+    /// Start the control API, the MQTT bridge if it's enabled in the app
+    /// settings and, if enabled, streaming
+    pub async fn start(&mut self) -> Result<()> {
+        // The profile's default until a track picks a preset
+        {
+            let watcher = self.watcher.lock().await;
+            let profile = watcher.profile();
+            let preset_name = profile.rules.default_preset.as_deref().unwrap_or(FALLBACK_PRESET);
+            self.stream.set_preset(profile.load_curve(preset_name).await?)?;
+        }
+
+        if self.config.api.enabled {
+            let profiles = self.profiles();
+            let mut control = ControlServer::new(self.config.api.bind, self.manager.clone())
+                .with_events(self.events.clone())
+                .with_telemetry(self.telemetry.clone())
+                .with_stream_controller(self.stream.clone())
+                .with_database(self.pool.clone())
+                .with_profile_controller(Arc::new(profiles))
+                .with_cors(self.config.api.cors_origins.clone());
+            if self.config.api.auth {
+                control = control.with_auth(self.pool.clone());
+            }
+            if let Some(tls) = self.config.api.tls_config()? {
+                control = control.with_tls(tls);
+            }
+            control.start().await.context("Failed to start control API")?;
+            self.control = Some(control);
+        }
+
+        let mqtt = AppSettingsRepository::new(self.pool.clone()).get_mqtt_settings().await?;
+        if mqtt.enabled {
+            info!("Publishing to MQTT broker {}:{} under '{}'", mqtt.host, mqtt.port, mqtt.base_topic);
+            let handler = DaemonMqtt {
+                profiles: self.profiles(),
+                manager: self.manager.clone(),
+            };
+            let bridge = MqttBridge::new(mqtt, Arc::new(handler)).with_events(self.events.clone());
+            self.mqtt = Some(bridge.start().context("Failed to start MQTT bridge")?);
+        }
+
+        if self.config.stream.enabled {
+            self.start_stream().await?;
+        }
+        Ok(())
+    }
+
+    /// Capture from the configured input and stream it through the profile's DSP
+    pub async fn start_stream(&mut self) -> Result<()> {
+        self.stream.start_capture(None).await
+    }
+
+    /// Stream audio from `input` instead of a capture device
+    pub async fn start_stream_from(&mut self, input: mpsc::Receiver<Vec<f64>>) -> Result<()> {
+        self.stream.start_from(input).await
+    }
+
+    pub async fn stop_stream(&mut self) -> Result<()> {
+        self.stream.stop_stream().await
+    }
+
+    /// Check the playing track once and apply its preset
+    pub async fn poll(&mut self) -> Result<Option<String>> {
+        poll_watcher(&self.watcher, &self.stream).await
+    }
+
+    /// Poll until the task is cancelled
+    pub async fn run(&mut self) -> Result<()> {
+        let mut interval = tokio::time::interval(self.config.poll_interval());
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut failing = false;
+        loop {
+            interval.tick().await;
+
+            if self.stream.has_ended() {
+                warn!("Stream ended");
+                self.stop_stream().await?;
+            }
+
+            match self.poll().await {
+                Ok(_) => failing = false,
+                // Log the first failure of a run, not every poll
+                Err(e) if !failing => {
+                    warn!("{:#}", e);
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Stop streaming, the control API and the MQTT bridge
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.stop_stream().await?;
+        if let Some(mut mqtt) = self.mqtt.take() {
+            mqtt.stop().await;
+        }
+        if let Some(mut control) = self.control.take() {
+            control.stop().await;
+        }
+        Ok(())
+    }
+
+    pub fn watcher(&self) -> Arc<Mutex<TrackWatcher>> {
+        self.watcher.clone()
+    }
+
+    /// Events sent to `/v1/events` clients
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// Counters reported at the control API's `/metrics`
+    pub fn telemetry(&self) -> Telemetry {
+        self.telemetry.clone()
+    }
+
+    pub fn output_manager(&self) -> Arc<RwLock<OutputManager>> {
+        self.manager.clone()
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_streaming()
+    }
+
+    /// Address the control API is listening on
+    pub fn control_addr(&self) -> Option<SocketAddr> {
+        self.control.as_ref().map(|control| control.addr())
+    }
+
+    fn profiles(&self) -> DaemonProfiles {
+        DaemonProfiles {
+            watcher: self.watcher.clone(),
+            stream: self.stream.clone(),
+        }
+    }
+}
+
+/// Poll the watcher without holding it while the device answers, so the
+/// control API and MQTT aren't kept waiting on a slow or offline device
+async fn poll_watcher(watcher: &Mutex<TrackWatcher>, stream: &DaemonStreamer) -> Result<Option<String>> {
+    let source = watcher.lock().await.source();
+    let playing = source.now_playing().await;
+    watcher.lock().await.update(playing, Some(stream)).await
+}
+
+/// Switches profile and applies presets for the control API and MQTT
+struct DaemonProfiles {
+    watcher: Arc<Mutex<TrackWatcher>>,
+    stream: Arc<DaemonStreamer>,
+}
+
+#[async_trait]
+impl ProfileController for DaemonProfiles {
+    async fn activate_profile(&self, profile_id: i64) -> Result<()> {
+        let pool = self.watcher.lock().await.profile().pool().clone();
+        let profile = Profile::load_by_id(pool, profile_id).await?;
+        self.stream.set_settings(profile.settings.clone()).await?;
+
+        self.watcher.lock().await.set_profile(profile);
+        // Apply the new profile's preset now rather than on the next poll
+        if let Err(e) = poll_watcher(&self.watcher, &self.stream).await {
+            warn!("{:#}", e);
+        }
+        Ok(())
+    }
+
+    async fn apply_preset(&self, preset: &str) -> Result<String> {
+        self.watcher.lock().await.apply_now(preset, Some(&self.stream)).await
+    }
+}
+
+/// Reports state to and takes commands from the MQTT bridge
+struct DaemonMqtt {
+    profiles: DaemonProfiles,
+    manager: Arc<RwLock<OutputManager>>,
+}
+
+#[async_trait]
+impl MqttHandler for DaemonMqtt {
+    async fn state(&self) -> Result<MqttState> {
+        let (track, preset, profile, pool) = {
+            let watcher = self.profiles.watcher.lock().await;
+            (
+                watcher.current_track().cloned(),
+                watcher.current_preset().map(str::to_string),
+                watcher.profile().name.clone(),
+                watcher.profile().pool().clone(),
+            )
+        };
+        let profiles = ProfileRepository::new(pool.clone()).list_all().await?;
+        let mut presets: Vec<String> = list_known_presets().into_iter().map(str::to_string).collect();
+        presets.extend(CustomEqPresetRepository::new(pool).list_names().await?);
+        let output = self.manager.read().await.active_sink_status().map(|status| status.name.to_string());
+
+        Ok(MqttState {
+            track,
+            preset,
+            profile: Some(profile),
+            profiles: profiles.into_iter().map(|profile| profile.name).collect(),
+            presets,
+            output,
+            streaming: self.profiles.stream.is_streaming(),
+            volume: Some(self.profiles.stream.volume()),
+        })
+    }
+
+    async fn activate_profile(&self, name: &str) -> Result<()> {
+        let pool = self.profiles.watcher.lock().await.profile().pool().clone();
+        let id = ProfileRepository::new(pool.clone())
+            .get_by_name(name)
+            .await?
+            .and_then(|profile| profile.id)
+            .ok_or_else(|| anyhow!("No profile named '{}'", name))?;
+        AppSettingsRepository::new(pool).set_active_profile_id(id).await?;
+        self.profiles.activate_profile(id).await
+    }
+
+    async fn apply_preset(&self, name: &str) -> Result<()> {
+        self.profiles.apply_preset(name).await.map(|_| ())
+    }
+
+    async fn set_streaming(&self, streaming: bool) -> Result<()> {
+        let stream = &self.profiles.stream;
+        match (streaming, stream.is_streaming()) {
+            (true, false) => stream.start_capture(None).await,
+            (false, true) => stream.stop_stream().await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn set_volume(&self, volume: f32) -> Result<()> {
+        self.profiles.stream.set_volume(volume);
+        Ok(())
+    }
+}
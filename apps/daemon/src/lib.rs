@@ -0,0 +1,19 @@
+//! Headless AAEQ daemon
+//!
+//! Runs the desktop app's orchestration without a UI: watches the playing
+//! track on a WiiM device or the system media session, resolves its preset
+//! with the profile's mapping rules from the shared database, and applies it
+//! on the device or to a DSP stream captured from a local input. The control
+//! API is served alongside.
+
+mod config;
+mod daemon;
+mod profile;
+mod stream;
+mod watcher;
+
+pub use config::{ApiConfig, DaemonConfig, DeviceConfig, OutputTarget, StreamConfig};
+pub use daemon::Daemon;
+pub use profile::{Profile, FALLBACK_PRESET};
+pub use stream::{create_sink, stream_output_config, DaemonStreamer, DspStream};
+pub use watcher::{TrackSource, TrackWatcher};
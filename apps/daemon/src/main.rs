@@ -0,0 +1,245 @@
+use aaeq_core::TokenScope;
+use aaeq_daemon::{Daemon, DaemonConfig};
+use aaeq_persistence::{ApiTokenRepository, AppSettingsRepository};
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use chrono::{TimeZone, Utc};
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+
+/// Environment variable `aaeqd mqtt enable` reads the broker password from
+const MQTT_PASSWORD_ENV: &str = "AAEQ_MQTT_PASSWORD";
+
+/// AAEQ Daemon - headless rule-based EQ switching and DSP streaming
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Config file (TOML); without it the defaults are used
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// AAEQ database (defaults to the desktop app's database)
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage Control API tokens
+    #[command(subcommand)]
+    Token(TokenCommand),
+    /// Configure the MQTT bridge (Home Assistant integration)
+    #[command(subcommand)]
+    Mqtt(MqttCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum TokenCommand {
+    /// Create a token and print it (it can't be shown again)
+    Create {
+        /// What the token is for, e.g. "phone"
+        name: String,
+        /// "read" to only read state, "control" to also change it
+        #[arg(long, default_value = "read", value_parser = ["read", "control"])]
+        scope: String,
+    },
+    /// List tokens
+    List,
+    /// Revoke a token by id
+    Revoke { id: i64 },
+}
+
+#[derive(Subcommand, Debug)]
+enum MqttCommand {
+    /// Show the MQTT settings
+    Show,
+    /// Publish to a broker from the next start, changing the settings given
+    ///
+    /// The password is taken from --password-stdin, --password-file or the
+    /// AAEQ_MQTT_PASSWORD environment variable, never the command line, where
+    /// other users could see it. Like the rest of the MQTT settings it is
+    /// stored unencrypted in the database's app settings, so keep the
+    /// database readable only by you.
+    Enable {
+        #[arg(long)]
+        host: Option<String>,
+        #[arg(long)]
+        port: Option<u16>,
+        /// Empty to connect without credentials
+        #[arg(long)]
+        username: Option<String>,
+        /// Read the password from the first line of stdin (an empty line clears it)
+        #[arg(long, conflicts_with = "password_file")]
+        password_stdin: bool,
+        /// Read the password from the first line of a file
+        #[arg(long)]
+        password_file: Option<PathBuf>,
+        /// Prefix of the state and command topics
+        #[arg(long)]
+        base_topic: Option<String>,
+        /// Home Assistant's discovery prefix
+        #[arg(long)]
+        discovery_prefix: Option<String>,
+    },
+    /// Stop publishing from the next start
+    Disable,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
+        )
+        .init();
+
+    let db_path = match args.db {
+        Some(path) => {
+            if !path.exists() {
+                bail!("Database {} does not exist", path.display());
+            }
+            path
+        }
+        None => aaeq_persistence::default_db_path()?,
+    };
+    tracing::info!("Using database {}", db_path.display());
+    let pool = aaeq_persistence::init_db(&db_path).await?;
+
+    match args.command {
+        Some(Command::Token(command)) => return run_token_command(&pool, command).await,
+        Some(Command::Mqtt(command)) => return run_mqtt_command(&pool, command).await,
+        None => {}
+    }
+
+    let mut config = match &args.config {
+        Some(path) => DaemonConfig::load(path)?,
+        None => DaemonConfig::default(),
+    };
+    // A generated certificate lives next to the database
+    if config.api.tls_dir.is_none() {
+        config.api.tls_dir = db_path.parent().map(|dir| dir.to_path_buf());
+    }
+
+    let mut daemon = Daemon::new(config, pool).await?;
+    daemon.start().await?;
+
+    let result = tokio::select! {
+        result = daemon.run() => result,
+        _ = tokio::signal::ctrl_c() => {
+            tracing::info!("Shutting down");
+            Ok(())
+        }
+    };
+    daemon.shutdown().await?;
+    result
+}
+
+async fn run_token_command(pool: &SqlitePool, command: TokenCommand) -> Result<()> {
+    match command {
+        TokenCommand::Create { name, scope } => {
+            let scope: TokenScope = scope.parse()?;
+            let issued = stream_server::issue_token(pool, &name, scope).await?;
+            println!("Created {} token '{}' (id {})", scope.as_str(), name, issued.info.id.unwrap_or_default());
+            println!("{}", issued.token);
+            eprintln!("Store it now: only its hash is kept, so it can't be shown again.");
+        }
+        TokenCommand::List => {
+            let tokens = ApiTokenRepository::new(pool.clone()).list_all().await?;
+            if tokens.is_empty() {
+                println!("No tokens");
+            }
+            for token in tokens {
+                let status = match (token.revoked_at, token.last_used_at) {
+                    (Some(at), _) => format!("revoked {}", format_time(at)),
+                    (None, Some(at)) => format!("last used {}", format_time(at)),
+                    (None, None) => "never used".to_string(),
+                };
+                println!(
+                    "{:>4}  {:<7}  {:<24}  created {}, {}",
+                    token.id.unwrap_or_default(),
+                    token.scope.as_str(),
+                    token.name,
+                    format_time(token.created_at),
+                    status
+                );
+            }
+        }
+        TokenCommand::Revoke { id } => {
+            if !ApiTokenRepository::new(pool.clone()).revoke(id).await? {
+                bail!("No active token with id {}", id);
+            }
+            println!("Revoked token {}", id);
+        }
+    }
+    Ok(())
+}
+
+async fn run_mqtt_command(pool: &SqlitePool, command: MqttCommand) -> Result<()> {
+    let repo = AppSettingsRepository::new(pool.clone());
+    let mut settings = repo.get_mqtt_settings().await?;
+    match command {
+        MqttCommand::Show => {}
+        MqttCommand::Enable {
+            host,
+            port,
+            username,
+            password_stdin,
+            password_file,
+            base_topic,
+            discovery_prefix,
+        } => {
+            settings.enabled = true;
+            settings.host = host.unwrap_or(settings.host);
+            settings.port = port.unwrap_or(settings.port);
+            if let Some(username) = username {
+                settings.username = Some(username).filter(|username| !username.is_empty());
+            }
+            if let Some(password) = read_mqtt_password(password_stdin, password_file.as_deref())? {
+                settings.password = Some(password).filter(|password| !password.is_empty());
+            }
+            settings.base_topic = base_topic.unwrap_or(settings.base_topic);
+            settings.discovery_prefix = discovery_prefix.unwrap_or(settings.discovery_prefix);
+            repo.set_mqtt_settings(&settings).await?;
+        }
+        MqttCommand::Disable => {
+            settings.enabled = false;
+            repo.set_mqtt_settings(&settings).await?;
+        }
+    }
+
+    println!("MQTT bridge {}", if settings.enabled { "enabled" } else { "disabled" });
+    println!("  broker:           {}:{}", settings.host, settings.port);
+    println!("  username:         {}", settings.username.as_deref().unwrap_or("(none)"));
+    println!("  base topic:       {}", settings.base_topic);
+    println!("  discovery prefix: {}", settings.discovery_prefix);
+    Ok(())
+}
+
+/// The MQTT password from stdin, a file or `AAEQ_MQTT_PASSWORD`, if one is given
+fn read_mqtt_password(from_stdin: bool, file: Option<&Path>) -> Result<Option<String>> {
+    let text = if from_stdin {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).context("Failed to read the password from stdin")?;
+        line
+    } else if let Some(file) = file {
+        std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file.display()))?
+    } else {
+        match std::env::var(MQTT_PASSWORD_ENV) {
+            Ok(password) => password,
+            Err(_) => return Ok(None),
+        }
+    };
+    Ok(Some(text.lines().next().unwrap_or_default().to_string()))
+}
+
+fn format_time(timestamp: i64) -> String {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
@@ -0,0 +1,61 @@
+//! The listening profile the daemon runs with: DSP settings, mapping rules and EQ curves
+
+use aaeq_core::{resolve_preset, resolve_preset_with_rule, DspSettings, EqPreset, Resolution, RulesIndex, TrackMeta};
+use aaeq_persistence::{load_preset_curve, load_rules, LoadedProfile};
+use anyhow::Result;
+use sqlx::SqlitePool;
+
+pub use aaeq_persistence::FALLBACK_PRESET;
+
+/// A profile loaded from the database
+pub struct Profile {
+    pub id: i64,
+    pub name: String,
+    pub settings: DspSettings,
+    pub rules: RulesIndex,
+    pool: SqlitePool,
+}
+
+impl Profile {
+    /// Load a profile by name, or the desktop app's active profile
+    pub async fn load(pool: SqlitePool, profile_name: Option<&str>) -> Result<Self> {
+        let profile = LoadedProfile::load(&pool, profile_name).await?;
+        Ok(Self::from_loaded(pool, profile))
+    }
+
+    /// Load a profile by id, e.g. when the control API switches profile
+    pub async fn load_by_id(pool: SqlitePool, id: i64) -> Result<Self> {
+        let profile = LoadedProfile::load_by_id(&pool, id).await?;
+        Ok(Self::from_loaded(pool, profile))
+    }
+
+    fn from_loaded(pool: SqlitePool, profile: LoadedProfile) -> Self {
+        let LoadedProfile { id, name, settings, rules } = profile;
+        Self { id, name, settings, rules, pool }
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    /// Re-read the mapping rules, picking up edits made in the desktop app
+    pub async fn reload_rules(&mut self) -> Result<()> {
+        self.rules = load_rules(&self.pool, self.id).await?;
+        Ok(())
+    }
+
+    /// Preset name for a track (genre overrides must already be applied)
+    pub fn resolve_preset(&self, track: &TrackMeta) -> String {
+        resolve_preset(track, &self.rules, FALLBACK_PRESET)
+    }
+
+    /// Preset for a track and the rule that chose it
+    pub fn resolve(&self, track: &TrackMeta) -> Resolution {
+        resolve_preset_with_rule(track, &self.rules, FALLBACK_PRESET)
+    }
+
+    /// Look up a preset's EQ curve: built-in, then custom, then generated
+    pub async fn load_curve(&self, preset_name: &str) -> Result<EqPreset> {
+        load_preset_curve(Some(&self.pool), preset_name).await
+    }
+}
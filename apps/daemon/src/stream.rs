@@ -0,0 +1,481 @@
+//! DSP streaming: captured audio through the profile's chain to the active sink
+
+use crate::config::{OutputTarget, StreamConfig};
+use crate::profile::FALLBACK_PRESET;
+use aaeq_core::preset_library::get_known_preset_curve;
+use aaeq_core::{DspSettings, EqPreset};
+use aaeq_persistence::AppSettingsRepository;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use sqlx::SqlitePool;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use stream_server::anp::{discover_nodes, AnpNode};
+use stream_server::dlna::find_device_by_name;
+use stream_server::control_api::routes::RouteConfig;
+use stream_server::{
+    Aes67Sink, AirPlaySink, AnpSink, DlnaMode, DlnaSink, EventBus, FileSink, LocalDacInput, LocalDacSink, OutputConfig,
+    OutputManager, OutputSink, ProfileChain, SampleFormat, StreamController, StreamProcessor, StreamPump, Telemetry,
+    TrackFollower, SYSTEM_MIX_INPUT,
+};
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// How long to look for a DLNA, AirPlay or ANP device by name
+const DISCOVERY_TIMEOUT_SECS: u64 = 5;
+
+/// Port the DLNA sink serves the stream on
+const DLNA_BIND_ADDR: &str = "0.0.0.0:8090";
+
+/// Capture/output settings for a stream: the configured values, else the profile's
+pub fn stream_output_config(config: &StreamConfig, settings: &DspSettings) -> OutputConfig {
+    OutputConfig {
+        sample_rate: config.sample_rate.unwrap_or(settings.sample_rate),
+        channels: 2,
+        format: config.format.unwrap_or(SampleFormat::S24LE),
+        buffer_ms: config.buffer_ms.unwrap_or(settings.buffer_ms),
+        exclusive: false,
+    }
+}
+
+/// Build the sink a stream config names, discovering network devices by name
+///
+/// Discovery times are recorded in `telemetry`.
+pub async fn create_sink(config: &StreamConfig, telemetry: &Telemetry) -> Result<Box<dyn OutputSink>> {
+    let device = config.device.clone();
+    let required = || {
+        device
+            .clone()
+            .ok_or_else(|| anyhow!("Output {:?} needs `device` in the [stream] config", config.output))
+    };
+
+    let sink: Box<dyn OutputSink> = match config.output {
+        OutputTarget::LocalDac => Box::new(LocalDacSink::new(device.clone())),
+        OutputTarget::Dlna => {
+            let name = required()?;
+            let found = telemetry
+                .time_discovery("dlna", find_device_by_name(&name, DISCOVERY_TIMEOUT_SECS))
+                .await?
+                .ok_or_else(|| anyhow!("DLNA device '{}' not found", name))?;
+            Box::new(DlnaSink::with_device(found, DLNA_BIND_ADDR.parse()?, DlnaMode::Push))
+        }
+        OutputTarget::Airplay => {
+            let name = required()?;
+            let found = telemetry
+                .time_discovery("airplay", AirPlaySink::discover(DISCOVERY_TIMEOUT_SECS))
+                .await?
+                .into_iter()
+                .find(|d| d.name == name)
+                .ok_or_else(|| anyhow!("AirPlay device '{}' not found", name))?;
+            let mut sink = AirPlaySink::new();
+            sink.set_device(found);
+            Box::new(sink)
+        }
+        OutputTarget::Anp => {
+            let name = required()?;
+            let node = match name.parse::<SocketAddr>() {
+                Ok(address) => AnpNode::from_address(&name, address),
+                Err(_) => telemetry
+                    .time_discovery("anp", discover_nodes(DISCOVERY_TIMEOUT_SECS))
+                    .await?
+                    .into_iter()
+                    .find(|n| n.name == name)
+                    .ok_or_else(|| anyhow!("ANP node '{}' not found", name))?,
+            };
+            Box::new(AnpSink::new(node))
+        }
+        OutputTarget::Aes67 => {
+            let group = required()?;
+            let destination = group
+                .parse()
+                .with_context(|| format!("Invalid AES67 destination '{}' (expected group:port)", group))?;
+            Box::new(Aes67Sink::new(destination))
+        }
+        OutputTarget::File => Box::new(FileSink::new(required()?).with_split_tracks(config.split_tracks)),
+    };
+    Ok(sink)
+}
+
+/// A running stream; presets and volume can be changed without interrupting the audio
+pub struct DspStream {
+    presets: mpsc::Sender<EqPreset>,
+    /// Linear gain after the chain, as `f32` bits
+    volume: Arc<AtomicU32>,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+    manager: Arc<RwLock<OutputManager>>,
+}
+
+impl DspStream {
+    /// Stream `input` through the profile's chain to the output last selected
+    /// in `manager` (e.g. through the control API), else to the configured sink
+    ///
+    /// `input` is interleaved stereo at the [`stream_output_config`] rate; the
+    /// sink opens at the chain's output rate. The EQ starts with `preset`.
+    /// Meters and clip counts are published on `events`, if given; discovery
+    /// times and the resampler's latency are recorded in `telemetry`.
+    pub async fn open(
+        manager: Arc<RwLock<OutputManager>>,
+        config: &StreamConfig,
+        settings: &DspSettings,
+        preset: &EqPreset,
+        input: mpsc::Receiver<Vec<f64>>,
+        events: Option<EventBus>,
+        telemetry: &Telemetry,
+    ) -> Result<Self> {
+        let mut output = stream_output_config(config, settings);
+        let sample_rate = output.sample_rate;
+        let mut chain = ProfileChain::new(settings, sample_rate, output.channels as usize)?;
+        chain.set_preset(preset);
+        output.sample_rate = chain.output_rate().unwrap_or(sample_rate);
+
+        let reopened = manager.write().await.reopen().await?;
+        let name = if reopened {
+            // Keep the selected output's format, at the rate the chain produces
+            let mut manager = manager.write().await;
+            let active = manager.active_sink_status().ok_or_else(|| anyhow!("No active output"))?;
+            let current = active.config.unwrap_or_else(|| output.clone());
+            output = OutputConfig {
+                sample_rate: output.sample_rate,
+                channels: output.channels,
+                ..current.clone()
+            };
+            if output.sample_rate != current.sample_rate || output.channels != current.channels {
+                manager
+                    .select_sink_by_name(active.name, output.clone())
+                    .await
+                    .with_context(|| format!("Failed to reopen {} output", active.name))?;
+            }
+            active.name
+        } else {
+            let sink = create_sink(config, telemetry).await?;
+            let name = sink.name();
+            let mut manager = manager.write().await;
+            manager.register_sink(sink);
+            let idx = manager.sink_count() - 1;
+            manager
+                .select_sink(idx, output.clone())
+                .await
+                .with_context(|| format!("Failed to open {} output", name))?;
+            name
+        };
+        info!(
+            "Streaming {} Hz to {} output at {} Hz {:?}",
+            sample_rate, name, output.sample_rate, output.format
+        );
+        telemetry.set_resampler_latency(chain.resampler_latency_ms());
+
+        Ok(Self::start(manager, chain, output, input, events))
+    }
+
+    /// Process `input` with `chain` and write it to the manager's active sink
+    ///
+    /// The sink must already be open with `output`, at the chain's output
+    /// rate. Track changes published on `events` are passed on to it.
+    pub fn start(
+        manager: Arc<RwLock<OutputManager>>,
+        chain: ProfileChain,
+        output: OutputConfig,
+        input: mpsc::Receiver<Vec<f64>>,
+        events: Option<EventBus>,
+    ) -> Self {
+        let (presets, preset_rx) = mpsc::channel::<EqPreset>(8);
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let volume = Arc::new(AtomicU32::new(1.0f32.to_bits()));
+        let processor = ChainProcessor {
+            chain,
+            presets: preset_rx,
+            gain: volume.clone(),
+        };
+        let mut pump = StreamPump::new(manager.clone(), output)
+            .with_track_follower(TrackFollower::new(events.as_ref()))
+            .with_processor(Box::new(processor));
+        if let Some(events) = events {
+            pump = pump.with_events(events);
+        }
+        let sink = manager.clone();
+
+        let task = tokio::spawn(async move {
+            pump.run(input, shutdown_rx).await;
+            if let Err(e) = sink.write().await.drain().await {
+                warn!("Failed to drain sink: {:#}", e);
+            }
+        });
+
+        Self {
+            presets,
+            volume,
+            shutdown: Some(shutdown),
+            task,
+            manager,
+        }
+    }
+
+    /// Switch the EQ curve
+    pub fn set_preset(&self, preset: EqPreset) -> Result<()> {
+        self.presets.try_send(preset).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => anyhow!("Stream is busy switching presets"),
+            mpsc::error::TrySendError::Closed(_) => anyhow!("Stream has stopped"),
+        })
+    }
+
+    /// Scale the output by `volume` (0.0 to 1.0)
+    pub fn set_volume(&self, volume: f32) {
+        self.volume.store(volume.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+
+    /// Stop streaming and close the sink
+    pub async fn stop(mut self) -> Result<()> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        self.finish().await
+    }
+
+    /// Wait for the input to end, then close the sink
+    pub async fn finish(self) -> Result<()> {
+        let _ = self.task.await;
+        self.manager.write().await.close_active().await
+    }
+}
+
+/// The profile's chain as the pump's processor, switching presets and
+/// applying the volume between blocks
+struct ChainProcessor {
+    chain: ProfileChain,
+    presets: mpsc::Receiver<EqPreset>,
+    /// Linear gain after the chain, as `f32` bits
+    gain: Arc<AtomicU32>,
+}
+
+impl ChainProcessor {
+    fn apply_gain(&self, mut samples: Vec<f64>) -> Vec<f64> {
+        let gain = f32::from_bits(self.gain.load(Ordering::Relaxed)) as f64;
+        if gain != 1.0 {
+            samples.iter_mut().for_each(|sample| *sample *= gain);
+        }
+        samples
+    }
+}
+
+impl StreamProcessor for ChainProcessor {
+    fn process(&mut self, samples: &[f64]) -> Result<Vec<f64>> {
+        while let Ok(preset) = self.presets.try_recv() {
+            self.chain.set_preset(&preset);
+        }
+        let processed = self.chain.process(samples)?;
+        Ok(self.apply_gain(processed))
+    }
+
+    fn clip_count(&self) -> u64 {
+        self.chain.clip_count()
+    }
+
+    fn finish(&mut self) -> Result<Vec<f64>> {
+        let tail = self.chain.finish()?;
+        Ok(self.apply_gain(tail))
+    }
+}
+
+/// The daemon's DSP stream, started and stopped by the daemon itself or
+/// through the control API's `/v1/outputs/start` and `/v1/outputs/stop`
+///
+/// Remembers the current preset and volume so a stream started later begins with them.
+pub struct DaemonStreamer {
+    manager: Arc<RwLock<OutputManager>>,
+    config: StreamConfig,
+    settings: Mutex<DspSettings>,
+    pool: SqlitePool,
+    preset: Mutex<Option<EqPreset>>,
+    volume: Mutex<f32>,
+    /// Per-track files asked for by the control API route, overriding the config
+    split_tracks: Mutex<Option<bool>>,
+    running: Mutex<Option<Running>>,
+    events: Option<EventBus>,
+    telemetry: Telemetry,
+}
+
+struct Running {
+    stream: DspStream,
+    capture: Option<Capture>,
+}
+
+/// The audio capture feeding a stream
+struct Capture {
+    stop: mpsc::Sender<()>,
+    input: Option<String>,
+}
+
+impl DaemonStreamer {
+    pub fn new(manager: Arc<RwLock<OutputManager>>, config: StreamConfig, settings: DspSettings, pool: SqlitePool) -> Self {
+        Self {
+            manager,
+            config,
+            settings: Mutex::new(settings),
+            pool,
+            preset: Mutex::new(None),
+            volume: Mutex::new(1.0),
+            split_tracks: Mutex::new(None),
+            running: Mutex::new(None),
+            events: None,
+            telemetry: Telemetry::new(),
+        }
+    }
+
+    /// Publish the stream's meters and clip counts on `events`
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Record discovery times and resampler latency in `telemetry`
+    pub fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
+
+    /// Capture from `input`, else the configured input, else the last one used in the desktop app
+    pub async fn start_capture(&self, input: Option<String>) -> Result<()> {
+        let input = match input.or_else(|| self.config.input.clone()) {
+            Some(input) => Some(input),
+            None => AppSettingsRepository::new(self.pool.clone()).get_last_input_device().await?,
+        };
+        let (tx, rx) = mpsc::channel(32);
+        let capture_config = stream_output_config(&self.config, &self.settings.lock().unwrap());
+        let stop = LocalDacInput::start_capture(input.clone(), capture_config, tx).context("Failed to start capture")?;
+
+        let capture = Capture {
+            stop: stop.clone(),
+            input,
+        };
+        self.start(rx, Some(capture)).await.inspect_err(|_| {
+            let _ = stop.try_send(());
+        })
+    }
+
+    /// Stream audio from `input` instead of a capture device
+    pub async fn start_from(&self, input: mpsc::Receiver<Vec<f64>>) -> Result<()> {
+        self.start(input, None).await
+    }
+
+    async fn start(&self, input: mpsc::Receiver<Vec<f64>>, capture: Option<Capture>) -> Result<()> {
+        self.stop_stream().await?;
+
+        let preset = self.preset.lock().unwrap().clone();
+        let preset = match preset {
+            Some(preset) => preset,
+            None => get_known_preset_curve(FALLBACK_PRESET).ok_or_else(|| anyhow!("No '{}' preset", FALLBACK_PRESET))?,
+        };
+        let settings = self.settings.lock().unwrap().clone();
+        let mut config = self.config.clone();
+        if let Some(split) = *self.split_tracks.lock().unwrap() {
+            config.split_tracks = split;
+        }
+        let stream = DspStream::open(
+            self.manager.clone(),
+            &config,
+            &settings,
+            &preset,
+            input,
+            self.events.clone(),
+            &self.telemetry,
+        )
+        .await?;
+        stream.set_volume(self.volume());
+        *self.running.lock().unwrap() = Some(Running { stream, capture });
+        Ok(())
+    }
+
+    /// Use `preset` from now on, switching a running stream over to it
+    pub fn set_preset(&self, preset: EqPreset) -> Result<()> {
+        *self.preset.lock().unwrap() = Some(preset.clone());
+        match self.running.lock().unwrap().as_ref() {
+            Some(running) => running.stream.set_preset(preset),
+            None => Ok(()),
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        *self.volume.lock().unwrap()
+    }
+
+    /// Scale the output by `volume` (0.0 to 1.0) from now on, including a running stream
+    pub fn set_volume(&self, volume: f32) {
+        let volume = volume.clamp(0.0, 1.0);
+        *self.volume.lock().unwrap() = volume;
+        if let Some(running) = self.running.lock().unwrap().as_ref() {
+            running.stream.set_volume(volume);
+        }
+    }
+
+    /// Use another profile's DSP settings
+    ///
+    /// A capture stream restarts on the same input to pick them up; a stream
+    /// fed by the caller keeps its settings until it's next started.
+    pub async fn set_settings(&self, settings: DspSettings) -> Result<()> {
+        *self.settings.lock().unwrap() = settings;
+        let input = match self.running.lock().unwrap().as_ref() {
+            Some(Running {
+                stream,
+                capture: Some(capture),
+            }) if stream.is_running() => Some(capture.input.clone()),
+            _ => None,
+        };
+        match input {
+            Some(input) => self.start_capture(input).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Whether a stream was started and its input has since ended
+    pub fn has_ended(&self) -> bool {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|running| !running.stream.is_running())
+    }
+
+    /// Stop capture and streaming, closing the sink
+    pub async fn stop_stream(&self) -> Result<()> {
+        let running = self.running.lock().unwrap().take();
+        let Some(running) = running else {
+            return Ok(());
+        };
+        self.telemetry.set_resampler_latency(None);
+        if let Some(capture) = running.capture {
+            let _ = capture.stop.send(()).await;
+        }
+        running.stream.stop().await
+    }
+}
+
+#[async_trait]
+impl StreamController for DaemonStreamer {
+    async fn start(&self, route: &RouteConfig) -> Result<()> {
+        if self.is_streaming() {
+            return Ok(());
+        }
+        *self.split_tracks.lock().unwrap() = route.split_tracks;
+        let input = route.input.clone().filter(|input| input != SYSTEM_MIX_INPUT);
+        self.start_capture(input).await
+    }
+
+    async fn stop(&self) -> Result<()> {
+        self.stop_stream().await
+    }
+
+    fn is_streaming(&self) -> bool {
+        self.running
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|running| running.stream.is_running())
+    }
+}
@@ -0,0 +1,256 @@
+//! Track watching: poll what's playing, resolve its preset and apply it
+
+use crate::profile::{Profile, FALLBACK_PRESET};
+use crate::stream::DaemonStreamer;
+use aaeq_core::{DeviceController, TrackMeta};
+use aaeq_media_session::MediaSession;
+use aaeq_persistence::GenreOverrideRepository;
+use anyhow::{anyhow, Result};
+use stream_server::{EventBus, StreamController, StreamEvent, Telemetry};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// Titles a WiiM device reports while playing AAEQ's own DSP stream
+const DSP_STREAM_TITLES: [&str; 2] = ["AAEQ Stream", "414145512053747265616D"];
+
+/// Where a [`TrackWatcher`] reads the playing track from
+///
+/// Cheap to clone, so the device can be polled without holding the watcher.
+#[derive(Clone)]
+pub struct TrackSource {
+    device: Option<Arc<dyn DeviceController>>,
+    media_session: Option<Arc<dyn MediaSession>>,
+    telemetry: Option<Telemetry>,
+}
+
+impl TrackSource {
+    /// Read the playing track from the device, else from the media session
+    pub async fn now_playing(&self) -> Result<Option<TrackMeta>> {
+        if let Some(device) = &self.device {
+            match device.get_now_playing().await {
+                Ok(track) if !DSP_STREAM_TITLES.contains(&track.title.as_str()) => return Ok(Some(track)),
+                // The device is playing our stream; the real track is in the media session
+                Ok(_) => debug!("Device is playing the DSP stream, checking media session"),
+                Err(e) => {
+                    if let Some(telemetry) = &self.telemetry {
+                        telemetry.record_poll_failure(device.id());
+                    }
+                    return Err(e.context(format!("Failed to read now playing from {}", device.id())));
+                }
+            }
+        }
+
+        let Some(session) = &self.media_session else {
+            return Ok(None);
+        };
+        Ok(session.get_current_track()?.map(|metadata| {
+            let genre = metadata.genre.unwrap_or_else(|| "Unknown".to_string());
+            TrackMeta {
+                artist: metadata.artist,
+                title: metadata.title,
+                album: metadata.album,
+                genre: genre.clone(),
+                device_genre: genre,
+                album_art_url: metadata.album_art_url,
+            }
+        }))
+    }
+}
+
+/// Follows the playing track and keeps the matching preset applied
+///
+/// Tracks come from the device when there is one, else from the system
+/// media session. While streaming, presets go to the DSP stream; otherwise
+/// they are applied on the device, and the stream keeps them for when it starts.
+pub struct TrackWatcher {
+    profile: Profile,
+    device: Option<Arc<dyn DeviceController>>,
+    media_session: Option<Arc<dyn MediaSession>>,
+    last_track_key: Option<String>,
+    current_track: Option<TrackMeta>,
+    current_preset: Option<String>,
+    events: Option<EventBus>,
+    telemetry: Option<Telemetry>,
+}
+
+impl TrackWatcher {
+    pub fn new(profile: Profile) -> Self {
+        Self {
+            profile,
+            device: None,
+            media_session: None,
+            last_track_key: None,
+            current_track: None,
+            current_preset: None,
+            events: None,
+            telemetry: None,
+        }
+    }
+
+    /// Read tracks from and apply presets to a device
+    pub fn with_device(mut self, device: Arc<dyn DeviceController>) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Read tracks from the system media session (also used while a device plays the DSP stream)
+    pub fn with_media_session(mut self, session: Box<dyn MediaSession>) -> Self {
+        self.media_session = Some(Arc::from(session));
+        self
+    }
+
+    /// Publish track changes and applied presets on `events`
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Count failed device polls in `telemetry`
+    pub fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    fn publish(&self, event: StreamEvent) {
+        if let Some(events) = &self.events {
+            events.publish(event);
+        }
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn current_track(&self) -> Option<&TrackMeta> {
+        self.current_track.as_ref()
+    }
+
+    pub fn current_preset(&self) -> Option<&str> {
+        self.current_preset.as_deref()
+    }
+
+    /// Where to read the playing track from
+    pub fn source(&self) -> TrackSource {
+        TrackSource {
+            device: self.device.clone(),
+            media_session: self.media_session.clone(),
+            telemetry: self.telemetry.clone(),
+        }
+    }
+
+    /// Check the playing track once
+    ///
+    /// Returns the preset applied if the track changed to one that needs a
+    /// different preset. Nothing playing is not an error.
+    pub async fn poll(&mut self, stream: Option<&DaemonStreamer>) -> Result<Option<String>> {
+        let playing = self.source().now_playing().await;
+        self.update(playing, stream).await
+    }
+
+    /// Act on what [`TrackSource::now_playing`] read, as [`poll`](Self::poll) does
+    pub async fn update(
+        &mut self,
+        playing: Result<Option<TrackMeta>>,
+        stream: Option<&DaemonStreamer>,
+    ) -> Result<Option<String>> {
+        let playing = playing.inspect_err(|_| self.reset())?;
+        let Some(mut track) = playing else {
+            return Ok(None);
+        };
+
+        track.device_genre = track.genre.clone();
+        let genre_repo = GenreOverrideRepository::new(self.profile.pool().clone());
+        if let Some(genre) = genre_repo.get(&track.song_key()).await? {
+            track.genre = genre;
+        }
+
+        let track_key = track.track_key();
+        self.current_track = Some(track.clone());
+        if self.last_track_key.as_deref() == Some(&track_key) {
+            return Ok(None);
+        }
+        info!("Track changed: {} - {}", track.artist, track.title);
+        self.last_track_key = Some(track_key);
+        self.publish(StreamEvent::TrackChanged { track: track.clone() });
+
+        // Mappings may have been edited in the desktop app since the last track
+        if let Err(e) = self.profile.reload_rules().await {
+            warn!("Failed to reload mapping rules: {:#}", e);
+        }
+        let resolution = self.profile.resolve(&track);
+        if self.current_preset.as_deref() == Some(&resolution.preset) {
+            return Ok(None);
+        }
+
+        let applied = self.apply(&resolution.preset, stream).await?;
+        info!("Applied preset: {}", applied);
+        self.current_preset = Some(applied.clone());
+        self.publish(StreamEvent::PresetApplied {
+            preset: applied.clone(),
+            rule: Some(resolution),
+        });
+        Ok(Some(applied))
+    }
+
+    /// Apply a preset now; the next track change resolves presets as usual again
+    pub async fn apply_now(&mut self, preset: &str, stream: Option<&DaemonStreamer>) -> Result<String> {
+        let applied = self.apply(preset, stream).await?;
+        info!("Applied preset: {}", applied);
+        self.current_preset = Some(applied.clone());
+        self.publish(StreamEvent::PresetApplied {
+            preset: applied.clone(),
+            rule: None,
+        });
+        Ok(applied)
+    }
+
+    /// Switch to another profile; the next poll applies its preset for the playing track
+    pub fn set_profile(&mut self, profile: Profile) {
+        info!("Switched to profile '{}'", profile.name);
+        self.profile = profile;
+        self.reset();
+    }
+
+    /// Forget the current track so the next poll re-applies its preset
+    pub fn reset(&mut self) {
+        self.last_track_key = None;
+        self.current_preset = None;
+    }
+
+    /// Apply a preset to the stream or device, falling back to Flat if it's unavailable
+    async fn apply(&self, preset: &str, stream: Option<&DaemonStreamer>) -> Result<String> {
+        if let Some(stream) = stream {
+            let streaming = stream.is_streaming();
+            let (name, curve) = match self.profile.load_curve(preset).await {
+                Ok(curve) => (preset, curve),
+                Err(e) => {
+                    // Only the stream needs a curve; the device may still have the preset
+                    if streaming {
+                        warn!("{:#}, using '{}'", e, FALLBACK_PRESET);
+                    }
+                    (FALLBACK_PRESET, self.profile.load_curve(FALLBACK_PRESET).await?)
+                }
+            };
+            stream.set_preset(curve)?;
+            if streaming {
+                return Ok(name.to_string());
+            }
+        }
+
+        let Some(device) = &self.device else {
+            // Nothing to apply to; the preset is only reported
+            return Ok(preset.to_string());
+        };
+        match device.apply_preset(preset).await {
+            Ok(()) => Ok(preset.to_string()),
+            Err(e) => {
+                warn!("Preset '{}' not available on {}: {:#}. Using '{}'", preset, device.id(), e, FALLBACK_PRESET);
+                device
+                    .apply_preset(FALLBACK_PRESET)
+                    .await
+                    .map_err(|e| anyhow!("Failed to apply '{}': {:#}", FALLBACK_PRESET, e))?;
+                Ok(FALLBACK_PRESET.to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,627 @@
+//! Daemon tests: a mock device and media session feed tracks, presets are
+//! resolved from a real database and applied to the device or a DSP stream.
+
+use aaeq_core::{DeviceController, EqPreset, Mapping, MqttSettings, Scope, TrackMeta};
+use aaeq_daemon::*;
+use aaeq_media_session::{MediaMetadata, MediaSession};
+use aaeq_persistence::{init_db, AppSettingsRepository, GenreOverrideRepository, MappingRepository};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use stream_server::{SampleFormat, SinkState, StreamEvent};
+use tokio::sync::mpsc;
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aaeq-daemon-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn track(artist: &str, title: &str, genre: &str) -> TrackMeta {
+    TrackMeta {
+        artist: artist.to_string(),
+        title: title.to_string(),
+        album: "Album".to_string(),
+        genre: genre.to_string(),
+        ..Default::default()
+    }
+}
+
+/// A device that plays whatever the test sets and only has some presets
+struct MockDevice {
+    track: Mutex<Result<TrackMeta, String>>,
+    presets: Vec<String>,
+    applied: Mutex<Vec<String>>,
+}
+
+impl MockDevice {
+    fn new(presets: &[&str]) -> Arc<Self> {
+        Arc::new(Self {
+            track: Mutex::new(Err("nothing yet".to_string())),
+            presets: presets.iter().map(|p| p.to_string()).collect(),
+            applied: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn play(&self, track: TrackMeta) {
+        *self.track.lock().unwrap() = Ok(track);
+    }
+
+    fn disconnect(&self) {
+        *self.track.lock().unwrap() = Err("connection refused".to_string());
+    }
+
+    fn applied(&self) -> Vec<String> {
+        self.applied.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl DeviceController for MockDevice {
+    fn id(&self) -> &str {
+        "mock"
+    }
+
+    async fn get_now_playing(&self) -> Result<TrackMeta> {
+        self.track.lock().unwrap().clone().map_err(|e| anyhow!(e))
+    }
+
+    async fn list_presets(&self) -> Result<Vec<String>> {
+        Ok(self.presets.clone())
+    }
+
+    async fn apply_preset(&self, preset_name: &str) -> Result<()> {
+        if !self.presets.iter().any(|p| p == preset_name) {
+            return Err(anyhow!("no preset {}", preset_name));
+        }
+        self.applied.lock().unwrap().push(preset_name.to_string());
+        Ok(())
+    }
+
+    async fn get_current_eq(&self) -> Result<Option<EqPreset>> {
+        Ok(None)
+    }
+
+    async fn set_custom_eq(&self, _preset: &EqPreset) -> Result<()> {
+        Ok(())
+    }
+
+    async fn is_online(&self) -> bool {
+        true
+    }
+}
+
+/// A device that answers a now playing request only when the test lets it
+#[derive(Default)]
+struct StalledDevice {
+    asked: tokio::sync::Notify,
+    answer: tokio::sync::Notify,
+}
+
+#[async_trait]
+impl DeviceController for StalledDevice {
+    fn id(&self) -> &str {
+        "stalled"
+    }
+
+    async fn get_now_playing(&self) -> Result<TrackMeta> {
+        self.asked.notify_one();
+        self.answer.notified().await;
+        Ok(track("Band", "Loud", "Rock"))
+    }
+
+    async fn list_presets(&self) -> Result<Vec<String>> {
+        Ok(vec!["Rock".to_string()])
+    }
+
+    async fn apply_preset(&self, _preset_name: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_current_eq(&self) -> Result<Option<EqPreset>> {
+        Ok(None)
+    }
+
+    async fn set_custom_eq(&self, _preset: &EqPreset) -> Result<()> {
+        Ok(())
+    }
+
+    async fn is_online(&self) -> bool {
+        true
+    }
+}
+
+struct MockSession(Option<TrackMeta>);
+
+impl MediaSession for MockSession {
+    fn get_current_track(&self) -> Result<Option<MediaMetadata>> {
+        Ok(self.0.clone().map(|t| MediaMetadata {
+            title: t.title,
+            artist: t.artist,
+            album: t.album,
+            album_art_url: None,
+            genre: Some(t.genre),
+        }))
+    }
+
+    fn is_playing(&self) -> bool {
+        self.0.is_some()
+    }
+
+    fn list_active_players(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+async fn add_mapping(pool: &SqlitePool, scope: Scope, key: &str, preset: &str) {
+    MappingRepository::new(pool.clone())
+        .create(&Mapping {
+            id: None,
+            scope,
+            key_normalized: Some(key.to_string()),
+            preset_name: preset.to_string(),
+            profile_id: 1,
+            created_at: 0,
+            updated_at: 0,
+        })
+        .await
+        .unwrap();
+}
+
+async fn test_db(dir: &Path) -> SqlitePool {
+    let pool = init_db(&dir.join("aaeq.db")).await.unwrap();
+    add_mapping(&pool, Scope::Genre, "rock", "Rock").await;
+    add_mapping(&pool, Scope::Genre, "jazz", "Jazz").await;
+    add_mapping(&pool, Scope::Song, "band - bass song", "Bass Booster").await;
+    pool
+}
+
+#[tokio::test]
+async fn test_device_presets_follow_tracks() {
+    let dir = test_dir("device");
+    let pool = test_db(&dir).await;
+    let device = MockDevice::new(&["Flat", "Rock", "Bass Booster"]);
+    let mut watcher = TrackWatcher::new(Profile::load(pool.clone(), None).await.unwrap()).with_device(device.clone());
+
+    // Errors from the device are reported, nothing is applied
+    assert!(watcher.poll(None).await.is_err());
+
+    device.play(track("Band", "Loud", "Rock"));
+    assert_eq!(watcher.poll(None).await.unwrap().as_deref(), Some("Rock"));
+    // Same track again: nothing to do
+    assert_eq!(watcher.poll(None).await.unwrap(), None);
+
+    // Song rules win over genre rules
+    device.play(track("Band", "Bass Song", "Rock"));
+    assert_eq!(watcher.poll(None).await.unwrap().as_deref(), Some("Bass Booster"));
+
+    // Jazz isn't on the device, so Flat is applied instead
+    device.play(track("Trio", "Blue", "Jazz"));
+    assert_eq!(watcher.poll(None).await.unwrap().as_deref(), Some("Flat"));
+    assert_eq!(watcher.current_preset(), Some("Flat"));
+
+    // A genre override set in the desktop app is used on the next track change
+    GenreOverrideRepository::new(pool.clone())
+        .upsert(&track("Trio", "Other", "").song_key(), "Rock")
+        .await
+        .unwrap();
+    device.play(track("Trio", "Other", "Jazz"));
+    assert_eq!(watcher.poll(None).await.unwrap().as_deref(), Some("Rock"));
+    assert_eq!(watcher.current_track().unwrap().device_genre, "Jazz");
+
+    // Mappings added while running are picked up
+    add_mapping(&pool, Scope::Song, "trio - new", "Bass Booster").await;
+    device.play(track("Trio", "New", "Jazz"));
+    assert_eq!(watcher.poll(None).await.unwrap().as_deref(), Some("Bass Booster"));
+
+    // After losing the device, the preset is applied again on reconnect
+    device.disconnect();
+    assert!(watcher.poll(None).await.is_err());
+    device.play(track("Trio", "New", "Jazz"));
+    assert_eq!(watcher.poll(None).await.unwrap().as_deref(), Some("Bass Booster"));
+
+    assert_eq!(device.applied(), ["Rock", "Bass Booster", "Flat", "Rock", "Bass Booster", "Bass Booster"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_media_session_used_while_device_plays_dsp_stream() {
+    let dir = test_dir("session");
+    let pool = test_db(&dir).await;
+    let device = MockDevice::new(&["Flat", "Rock"]);
+    device.play(track("", "AAEQ Stream", ""));
+
+    let mut watcher = TrackWatcher::new(Profile::load(pool, None).await.unwrap())
+        .with_device(device.clone())
+        .with_media_session(Box::new(MockSession(Some(track("Band", "Loud", "Rock")))));
+    assert_eq!(watcher.poll(None).await.unwrap().as_deref(), Some("Rock"));
+    assert_eq!(watcher.current_track().unwrap().title, "Loud");
+
+    // Without a device or session there is simply nothing playing
+    let pool = init_db(&dir.join("empty.db")).await.unwrap();
+    let mut idle = TrackWatcher::new(Profile::load(pool, None).await.unwrap());
+    assert_eq!(idle.poll(None).await.unwrap(), None);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_poll_releases_watcher_while_device_answers() {
+    let dir = test_dir("stalled");
+    let pool = test_db(&dir).await;
+    let device = Arc::new(StalledDevice::default());
+    let watcher = TrackWatcher::new(Profile::load(pool, None).await.unwrap()).with_device(device.clone());
+    let mut daemon = Daemon::with_watcher(DaemonConfig::parse("").unwrap(), watcher);
+    let shared = daemon.watcher();
+
+    let poll = daemon.poll();
+    tokio::pin!(poll);
+    tokio::select! {
+        _ = &mut poll => panic!("poll finished before the device answered"),
+        _ = device.asked.notified() => {}
+    }
+    // The control API can use the watcher while the device is slow to answer
+    assert!(shared.try_lock().is_ok());
+    device.answer.notify_one();
+    assert_eq!(poll.await.unwrap().as_deref(), Some("Rock"));
+    assert_eq!(shared.lock().await.current_track().unwrap().title, "Loud");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Interleaved stereo 64 Hz sine at 48 kHz
+fn bass_tone(frames: usize, offset: usize) -> Vec<f64> {
+    (offset..offset + frames)
+        .flat_map(|i| {
+            let s = (2.0 * std::f64::consts::PI * 64.0 * i as f64 / 48000.0).sin() * 0.25;
+            [s, s]
+        })
+        .collect()
+}
+
+/// Samples of the `data` chunk of a 24-bit WAV
+fn read_wav_24(path: &Path) -> Vec<f64> {
+    let bytes = std::fs::read(path).unwrap();
+    let data = bytes.windows(4).position(|w| w == b"data").unwrap() + 8;
+    bytes[data..]
+        .chunks_exact(3)
+        .map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64 / 8388608.0)
+        .collect()
+}
+
+fn rms(samples: &[f64]) -> f64 {
+    (samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64).sqrt()
+}
+
+async fn wait_for_frames(daemon: &Daemon, frames: u64) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while daemon.output_manager().read().await.active_sink_stats().map(|s| s.frames_written) != Some(frames) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("stream did not write all frames");
+}
+
+#[tokio::test]
+async fn test_stream_switches_preset_and_serves_control_api() {
+    let dir = test_dir("stream");
+    let pool = test_db(&dir).await;
+    let output = dir.join("out.wav");
+
+    let config = DaemonConfig::parse(&format!(
+        r#"
+        [stream]
+        output = "file"
+        device = "{}"
+        format = "S24LE"
+
+        [api]
+        bind = "127.0.0.1:0"
+        "#,
+        output.display()
+    ))
+    .unwrap();
+    assert_eq!(config.stream.format, Some(SampleFormat::S24LE));
+
+    let device = MockDevice::new(&["Flat"]);
+    let watcher = TrackWatcher::new(Profile::load(pool, None).await.unwrap()).with_device(device.clone());
+    let mut daemon = Daemon::with_watcher(config, watcher);
+    let mut events = daemon.events().subscribe();
+
+    // Streaming from a channel instead of a capture device
+    daemon.start().await.unwrap();
+    assert!(!daemon.is_streaming());
+    let (tx, rx) = mpsc::channel(64);
+    daemon.start_stream_from(rx).await.unwrap();
+    assert!(daemon.is_streaming());
+
+    let addr = daemon.control_addr().unwrap();
+    let outputs: serde_json::Value = reqwest::get(format!("http://{}/v1/outputs", addr))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(outputs["active"], "file");
+    let metrics: serde_json::Value = reqwest::get(format!("http://{}/v1/outputs/metrics", addr))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(metrics["streaming"], true);
+    assert_eq!(metrics["format"], "S24LE");
+
+    // Flat to start with, then the track's preset is applied to the stream, not the device
+    for block in 0..20 {
+        tx.send(bass_tone(480, block * 480)).await.unwrap();
+    }
+    wait_for_frames(&daemon, 20 * 480).await;
+    assert!(daemon.poll().await.is_err());
+    device.play(track("Band", "Bass Song", "Rock"));
+    assert_eq!(daemon.poll().await.unwrap().as_deref(), Some("Bass Booster"));
+    assert!(device.applied().is_empty());
+
+    // The output opening, the track and the preset with the rule that chose it were published
+    let mut published = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if !matches!(event, StreamEvent::Meters(_)) {
+            published.push(event);
+        }
+    }
+    assert!(matches!(&published[0], StreamEvent::SinkState { sink, state: SinkState::Opened, .. } if sink == "file"));
+    assert!(matches!(&published[1], StreamEvent::TrackChanged { track } if track.title == "Bass Song"));
+    match &published[2] {
+        StreamEvent::PresetApplied { preset, rule: Some(rule) } => {
+            assert_eq!(preset, "Bass Booster");
+            assert_eq!(rule.scope, Scope::Song);
+            assert_eq!(rule.key.as_deref(), Some("band - bass song"));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    for block in 20..40 {
+        tx.send(bass_tone(480, block * 480)).await.unwrap();
+    }
+    drop(tx);
+
+    wait_for_frames(&daemon, 40 * 480).await;
+
+    // Stream health for Prometheus, including the failed poll
+    let scrape = reqwest::get(format!("http://{}/metrics", addr)).await.unwrap().text().await.unwrap();
+    for line in [
+        "aaeq_sink_frames_written_total{sink=\"file\"} 19200",
+        "aaeq_device_poll_failures_total{device=\"mock\"} 1",
+        "aaeq_preset_switches_total 1",
+    ] {
+        assert!(scrape.lines().any(|l| l == line), "missing {:?} in\n{}", line, scrape);
+    }
+
+    // Stopping through the control API stops the daemon's stream
+    let stopped = reqwest::Client::new()
+        .post(format!("http://{}/v1/outputs/stop", addr))
+        .send()
+        .await
+        .unwrap();
+    assert!(stopped.status().is_success());
+    assert!(!daemon.is_streaming());
+    assert_eq!(daemon.output_manager().read().await.active_sink_name(), None);
+
+    let samples = read_wav_24(&output);
+    assert_eq!(samples.len(), 40 * 480 * 2);
+    let (flat, boosted) = (rms(&samples[..9600]), rms(&samples[28800..]));
+    assert!(boosted > flat * 1.5, "flat {} vs boosted {}", flat, boosted);
+
+    // Restarting reuses the output rather than adding another
+    let (tx, rx) = mpsc::channel(64);
+    daemon.start_stream_from(rx).await.unwrap();
+    tx.send(bass_tone(480, 0)).await.unwrap();
+    assert!(daemon.is_streaming());
+    assert_eq!(daemon.output_manager().read().await.list_sinks(), ["file"]);
+    daemon.shutdown().await.unwrap();
+    assert!(!daemon.is_streaming());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+async fn wait_for_file(path: &Path) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !path.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("{} was not created", path.display()));
+}
+
+#[tokio::test]
+async fn test_stream_records_a_file_per_track() {
+    let dir = test_dir("split");
+    let pool = test_db(&dir).await;
+    let recordings = dir.join("recordings");
+
+    let config = DaemonConfig::parse(&format!(
+        r#"
+        [stream]
+        output = "file"
+        device = "{}"
+        format = "S24LE"
+        split_tracks = true
+
+        [api]
+        enabled = false
+        "#,
+        recordings.display()
+    ))
+    .unwrap();
+
+    let device = MockDevice::new(&["Flat"]);
+    let watcher = TrackWatcher::new(Profile::load(pool, None).await.unwrap()).with_device(device.clone());
+    let mut daemon = Daemon::with_watcher(config, watcher);
+    daemon.start().await.unwrap();
+    let (tx, rx) = mpsc::channel(64);
+    daemon.start_stream_from(rx).await.unwrap();
+
+    // Each track the watcher publishes starts a file named after it
+    let first = recordings.join("001 - Band - Loud.wav");
+    let second = recordings.join("002 - Trio - Blue.wav");
+    device.play(track("Band", "Loud", "Rock"));
+    daemon.poll().await.unwrap();
+    wait_for_file(&first).await;
+    for block in 0..10 {
+        tx.send(bass_tone(480, block * 480)).await.unwrap();
+    }
+    wait_for_frames(&daemon, 10 * 480).await;
+
+    device.play(track("Trio", "Blue", "Jazz"));
+    daemon.poll().await.unwrap();
+    wait_for_file(&second).await;
+    for block in 10..30 {
+        tx.send(bass_tone(480, block * 480)).await.unwrap();
+    }
+    drop(tx);
+    wait_for_frames(&daemon, 30 * 480).await;
+    daemon.shutdown().await.unwrap();
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&recordings).unwrap().map(|entry| entry.unwrap().path()).collect();
+    files.sort();
+    assert_eq!(files, [first.clone(), second.clone()]);
+    assert_eq!(read_wav_24(&first).len(), 10 * 480 * 2);
+    assert_eq!(read_wav_24(&second).len(), 20 * 480 * 2);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_control_api_switches_profile_and_applies_presets() {
+    let dir = test_dir("profiles");
+    let pool = test_db(&dir).await;
+    let config = DaemonConfig::parse("[api]\nbind = \"127.0.0.1:0\"\n").unwrap();
+
+    let device = MockDevice::new(&["Flat", "Rock", "Jazz"]);
+    let watcher = TrackWatcher::new(Profile::load(pool, None).await.unwrap()).with_device(device.clone());
+    let mut daemon = Daemon::with_watcher(config, watcher);
+    daemon.start().await.unwrap();
+    let api = format!("http://{}", daemon.control_addr().unwrap());
+    let client = reqwest::Client::new();
+
+    device.play(track("Band", "Loud", "Rock"));
+    assert_eq!(daemon.poll().await.unwrap().as_deref(), Some("Rock"));
+
+    // Applied until the track changes
+    let applied = client.post(format!("{}/v1/presets/Jazz/apply", api)).send().await.unwrap();
+    assert!(applied.status().is_success());
+    assert_eq!(daemon.watcher().lock().await.current_preset(), Some("Jazz"));
+    assert_eq!(daemon.poll().await.unwrap(), None);
+
+    // Headphones map rock to Jazz; switching applies it to the playing track right away
+    let mapping = client
+        .post(format!("{}/v1/mappings", api))
+        .json(&serde_json::json!({"scope": "genre", "key": "Rock", "preset": "Jazz", "profile_id": 2}))
+        .send()
+        .await
+        .unwrap();
+    assert!(mapping.status().is_success());
+    device.play(track("Band", "Louder", "Rock"));
+    let activated = client.post(format!("{}/v1/profiles/2/activate", api)).send().await.unwrap();
+    assert!(activated.status().is_success());
+    assert_eq!(daemon.watcher().lock().await.profile().name, "Headphones");
+    assert_eq!(device.applied(), ["Rock", "Jazz", "Jazz"]);
+
+    let profiles: serde_json::Value = client.get(format!("{}/v1/profiles", api)).send().await.unwrap().json().await.unwrap();
+    assert_eq!(profiles["active_id"], 2);
+
+    daemon.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_control_api_requires_token_over_tls() {
+    let dir = test_dir("auth");
+    let pool = test_db(&dir).await;
+    let config = DaemonConfig::parse(&format!(
+        "[api]\nbind = \"127.0.0.1:0\"\nauth = true\ntls = true\ntls_dir = \"{}\"\n",
+        dir.display()
+    ))
+    .unwrap();
+    let token = stream_server::issue_token(&pool, "test", aaeq_core::TokenScope::Read).await.unwrap();
+
+    let watcher = TrackWatcher::new(Profile::load(pool, None).await.unwrap()).with_device(MockDevice::new(&["Flat"]));
+    let mut daemon = Daemon::with_watcher(config, watcher);
+    daemon.start().await.unwrap();
+
+    // The generated certificate is trusted like any other
+    let cert = std::fs::read(dir.join(stream_server::tls::CERT_FILE)).unwrap();
+    let client = reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(&cert).unwrap())
+        .build()
+        .unwrap();
+    let api = format!("https://localhost:{}", daemon.control_addr().unwrap().port());
+
+    let denied = client.get(format!("{}/v1/profiles", api)).send().await.unwrap();
+    assert_eq!(denied.status().as_u16(), 401);
+    let profiles = client.get(format!("{}/v1/profiles", api)).bearer_auth(&token.token).send().await.unwrap();
+    assert!(profiles.status().is_success());
+    let apply = client
+        .post(format!("{}/v1/presets/Flat/apply", api))
+        .bearer_auth(&token.token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(apply.status().as_u16(), 403);
+
+    daemon.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_mqtt_bridge_starts_from_app_settings() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = test_dir("mqtt");
+    let pool = test_db(&dir).await;
+    let broker = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let settings = MqttSettings {
+        enabled: true,
+        host: "127.0.0.1".to_string(),
+        port: broker.local_addr().unwrap().port(),
+        username: Some("ha".to_string()),
+        ..Default::default()
+    };
+    let repo = AppSettingsRepository::new(pool.clone());
+    assert_eq!(repo.get_mqtt_settings().await.unwrap(), MqttSettings::default());
+    repo.set_mqtt_settings(&settings).await.unwrap();
+    assert_eq!(repo.get_mqtt_settings().await.unwrap(), settings);
+
+    let config = DaemonConfig::parse("[api]\nenabled = false\n").unwrap();
+    let watcher = TrackWatcher::new(Profile::load(pool, None).await.unwrap()).with_device(MockDevice::new(&["Flat"]));
+    let mut daemon = Daemon::with_watcher(config, watcher);
+    daemon.start().await.unwrap();
+
+    // The bridge connects with its last will, then subscribes and publishes discovery
+    let (mut client, _) = tokio::time::timeout(Duration::from_secs(5), broker.accept()).await.unwrap().unwrap();
+    let mut received = Vec::new();
+    let mut buf = [0u8; 4096];
+    let n = client.read(&mut buf).await.unwrap();
+    received.extend_from_slice(&buf[..n]);
+    assert_eq!(received[0], 0x10, "expected CONNECT");
+    client.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+
+    let contains = |bytes: &[u8], text: &str| bytes.windows(text.len()).any(|w| w == text.as_bytes());
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !contains(&received, "homeassistant/select/aaeq/profile/config") {
+            let n = client.read(&mut buf).await.unwrap();
+            assert!(n > 0, "bridge disconnected");
+            received.extend_from_slice(&buf[..n]);
+        }
+    })
+    .await
+    .expect("no discovery config published");
+    assert!(contains(&received, "aaeq/status"));
+    assert!(contains(&received, "aaeq/preset/set"));
+    assert!(contains(&received, "Headphones"));
+
+    daemon.shutdown().await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
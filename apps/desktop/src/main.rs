@@ -10,7 +10,6 @@ use global_hotkey::{
     GlobalHotKeyEvent, GlobalHotKeyManager,
 };
 use single_instance::SingleInstanceGuard;
-use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use tray_icon::{
     menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
@@ -43,7 +42,7 @@ async fn main() -> Result<()> {
     }
 
     // Get database path early (before logging, so we know where to put the log file)
-    let db_path = get_db_path()?;
+    let db_path = aaeq_persistence::default_db_path()?;
 
     // Create parent directory if it doesn't exist
     if let Some(parent) = db_path.parent() {
@@ -458,25 +457,3 @@ fn parse_key_code(key_str: &str) -> Option<Code> {
         _ => None,
     }
 }
-
-/// Get the database path (platform-specific)
-fn get_db_path() -> Result<PathBuf> {
-    let config_dir = if cfg!(target_os = "windows") {
-        dirs::config_dir()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get config directory"))?
-            .join("AAEQ")
-    } else if cfg!(target_os = "macos") {
-        dirs::home_dir()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get home directory"))?
-            .join("Library")
-            .join("Application Support")
-            .join("AAEQ")
-    } else {
-        // Linux
-        dirs::config_dir()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get config directory"))?
-            .join("aaeq")
-    };
-
-    Ok(config_dir.join("aaeq.db"))
-}
@@ -0,0 +1,149 @@
+//! mDNS advertisement of the node (ANP §3)
+
+use anyhow::{Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::net::SocketAddr;
+use stream_server::sinks::anp::ANP_SERVICE_TYPE;
+use tracing::{debug, info};
+
+/// Static part of the TXT record
+#[derive(Debug, Clone)]
+pub struct Advertisement {
+    pub name: String,
+    pub uuid: String,
+    pub sample_rates: Vec<u32>,
+    pub formats: Vec<String>,
+    pub channels: u16,
+    /// Abbreviated core features, e.g. `pll,crc,vol`
+    pub features: Vec<String>,
+    pub control_addr: SocketAddr,
+    pub dac: String,
+}
+
+impl Advertisement {
+    /// TXT properties in the recommended order, uuid first (§3.1)
+    pub fn txt_properties(&self, state: &str, volume: f32) -> Vec<(String, String)> {
+        let join = |items: &[String]| items.join(",");
+        let mut txt = vec![
+            ("uuid".to_string(), self.uuid.clone()),
+            ("v".to_string(), "0.4.0".to_string()),
+            (
+                "sr".to_string(),
+                self.sample_rates.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(","),
+            ),
+            ("bd".to_string(), join(&self.formats)),
+            ("ch".to_string(), self.channels.to_string()),
+            ("ft".to_string(), join(&self.features)),
+        ];
+        // A wildcard bind has no single reachable address; browsers fall
+        // back to the resolved service address
+        if !self.control_addr.ip().is_unspecified() {
+            txt.push(("ctrl".to_string(), format!("ws://{}", self.control_addr)));
+        }
+        txt.push(("st".to_string(), state.to_string()));
+        txt.push(("vol".to_string(), ((volume.clamp(0.0, 1.0) * 100.0).round() as u8).to_string()));
+        txt.push(("dac".to_string(), self.dac.chars().take(32).collect()));
+        txt.push(("hw".to_string(), hardware_abbreviation().to_string()));
+        txt
+    }
+}
+
+/// Platform abbreviation for the `hw` TXT field
+fn hardware_abbreviation() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "x64",
+        "aarch64" => "arm64",
+        "arm" => "arm",
+        other => other,
+    }
+}
+
+/// Keeps the node registered on mDNS and refreshes its state fields
+pub struct Advertiser {
+    daemon: ServiceDaemon,
+    advertisement: Advertisement,
+    fullname: String,
+    published: Option<(String, u8)>,
+}
+
+impl Advertiser {
+    pub fn start(advertisement: Advertisement) -> Result<Self> {
+        let daemon = ServiceDaemon::new().context("Failed to start mDNS daemon")?;
+        let mut advertiser = Self {
+            fullname: format!("{}.{}", advertisement.name, ANP_SERVICE_TYPE),
+            daemon,
+            advertisement,
+            published: None,
+        };
+        advertiser.update("idle", 1.0)?;
+        info!("Advertising ANP node '{}' on mDNS", advertiser.advertisement.name);
+        Ok(advertiser)
+    }
+
+    /// Re-announce the node if its state or volume changed
+    pub fn update(&mut self, state: &str, volume: f32) -> Result<()> {
+        let published = (state.to_string(), (volume.clamp(0.0, 1.0) * 100.0).round() as u8);
+        if self.published.as_ref() == Some(&published) {
+            return Ok(());
+        }
+
+        let ad = &self.advertisement;
+        let host = format!("aaeq-node-{}.local.", ad.uuid.split('-').next().unwrap_or("anp"));
+        let properties = ad.txt_properties(state, volume);
+        let info = ServiceInfo::new(
+            ANP_SERVICE_TYPE,
+            &ad.name,
+            &host,
+            "",
+            ad.control_addr.port(),
+            properties.as_slice(),
+        )
+        .context("Invalid mDNS service info")?
+        .enable_addr_auto();
+
+        self.daemon.register(info).context("Failed to register mDNS service")?;
+        debug!("mDNS record updated: st={} vol={}", published.0, published.1);
+        self.published = Some(published);
+        Ok(())
+    }
+}
+
+impl Drop for Advertiser {
+    fn drop(&mut self) {
+        self.daemon.unregister(&self.fullname).ok();
+        self.daemon.shutdown().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_txt_record_order_and_fields() {
+        let ad = Advertisement {
+            name: "Kitchen".to_string(),
+            uuid: "550e8400-e29b-41d4-a716-446655440000".to_string(),
+            sample_rates: vec![44100, 48000],
+            formats: vec!["S16".to_string(), "S24".to_string()],
+            channels: 2,
+            features: vec!["pll".to_string(), "crc".to_string()],
+            control_addr: "10.0.0.10:7443".parse().unwrap(),
+            dac: "USB DAC".to_string(),
+        };
+
+        let txt = ad.txt_properties("play", 0.75);
+        assert_eq!(txt[0], ("uuid".to_string(), ad.uuid.clone()));
+        let get = |key: &str| txt.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+        assert_eq!(get("sr"), Some("44100,48000"));
+        assert_eq!(get("ft"), Some("pll,crc"));
+        assert_eq!(get("ctrl"), Some("ws://10.0.0.10:7443"));
+        assert_eq!(get("vol"), Some("75"));
+
+        let wildcard = Advertisement {
+            control_addr: "0.0.0.0:7443".parse().unwrap(),
+            ..ad
+        };
+        assert!(!wildcard.txt_properties("idle", 1.0).iter().any(|(k, _)| k == "ctrl"));
+    }
+}
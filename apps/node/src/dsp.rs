@@ -0,0 +1,349 @@
+//! Remote DSP (ANP §12)
+//!
+//! Servers that negotiate `dsp_transfer` push their active profile to the
+//! node, which runs it with the same DSP chain the server would use. This
+//! lets the EQ run next to the DAC while the server streams untouched audio.
+
+use std::collections::{BTreeMap, HashMap};
+use stream_server::sinks::anp::{
+    fir_checksum, AppliedDsp, ControlMessage, ConvolutionRequest, ConvolutionResponse, DspError, DspHealth,
+    DspStatus, DspUpdate, DspUpdateAck,
+};
+use stream_server::{DspChain, FirFilter};
+use tracing::{info, warn};
+
+/// Longest FIR filter the direct-form convolution runs in real time
+pub const DEFAULT_MAX_FIR_TAPS: usize = 4096;
+
+struct CachedFilter {
+    sample_rate: u32,
+    taps: Vec<f32>,
+}
+
+/// DSP chain driven by `dsp_update`
+pub struct NodeDsp {
+    chain: DspChain,
+    max_fir_taps: usize,
+    filters: HashMap<String, CachedFilter>,
+    /// Profile waiting for its convolution filter
+    pending: Option<DspUpdate>,
+    profile_hash: u32,
+}
+
+impl NodeDsp {
+    pub fn new(sample_rate: u32, channels: usize, max_fir_taps: usize) -> Self {
+        Self {
+            chain: DspChain::new(sample_rate, channels),
+            max_fir_taps,
+            filters: HashMap::new(),
+            pending: None,
+            profile_hash: 0,
+        }
+    }
+
+    /// Apply a profile
+    ///
+    /// Returns the `dsp_update_ack`, or a `convolution_request` when the
+    /// profile needs a filter that isn't cached yet; the ack then follows
+    /// the server's `convolution_response`.
+    pub fn update(&mut self, update: DspUpdate) -> ControlMessage {
+        if let Some(convolution) = update.convolution.as_ref().filter(|c| c.enabled) {
+            if !self.filters.contains_key(&convolution.filter_id) {
+                info!("Requesting convolution filter '{}'", convolution.filter_id);
+                let request = ConvolutionRequest {
+                    cmd: "get_ir_url".to_string(),
+                    filter_id: convolution.filter_id.clone(),
+                };
+                self.pending = Some(update);
+                return ControlMessage::ConvolutionRequest(request);
+            }
+        }
+        ControlMessage::DspUpdateAck(self.apply(&update, None))
+    }
+
+    /// Cache a filter sent by the server and apply the profile waiting for it
+    pub fn filter_received(&mut self, response: ConvolutionResponse) -> Option<ControlMessage> {
+        let error = match &response.taps {
+            None => Some(format!(
+                "Convolution: downloading '{}' is not supported, send taps inline",
+                response.ir_url.as_deref().unwrap_or(&response.filter_id)
+            )),
+            Some(taps) if fir_checksum(taps) != response.checksum => {
+                Some(format!("Convolution: checksum mismatch for '{}'", response.filter_id))
+            }
+            Some(taps) => {
+                self.filters.insert(
+                    response.filter_id.clone(),
+                    CachedFilter {
+                        sample_rate: response.sample_rate,
+                        taps: taps.clone(),
+                    },
+                );
+                None
+            }
+        };
+
+        let pending = self.pending.take_if(|p| {
+            p.convolution.as_ref().is_some_and(|c| c.filter_id == response.filter_id)
+        })?;
+        Some(ControlMessage::DspUpdateAck(self.apply(&pending, error)))
+    }
+
+    /// The server couldn't provide the filter: apply the rest of the profile
+    pub fn filter_unavailable(&mut self, message: &str) -> Option<ControlMessage> {
+        let pending = self.pending.take()?;
+        Some(ControlMessage::DspUpdateAck(
+            self.apply(&pending, Some(format!("Convolution: {}", message))),
+        ))
+    }
+
+    fn apply(&mut self, update: &DspUpdate, filter_error: Option<String>) -> DspUpdateAck {
+        let mut applied = AppliedDsp::default();
+        let mut errors = Vec::new();
+        let mut fallback = BTreeMap::new();
+        let mut fail = |code: &str, message: String| {
+            warn!("DSP profile {}: {}", update.profile_id, message);
+            errors.push(DspError {
+                code: code.to_string(),
+                message,
+            });
+        };
+
+        self.chain.set_headroom_db(update.headroom_db);
+        applied.headroom = true;
+
+        // Any band the chain can't realise leaves the whole EQ to the server,
+        // rather than playing a different curve than the user chose
+        let bands = update.eq_bands();
+        let nyquist = self.chain.sample_rate() as f64 / 2.0;
+        let invalid = bands
+            .iter()
+            .filter(|b| b.frequency <= 0.0 || b.frequency >= nyquist || b.q <= 0.0)
+            .count();
+        if invalid == 0 {
+            self.chain.set_eq(&bands);
+            applied.equalizer = true;
+        } else {
+            self.chain.set_eq(&[]);
+            fail("E501", format!("Equalizer: {} band(s) outside 0-{} Hz or with Q <= 0", invalid, nyquist));
+            fallback.insert("equalizer".to_string(), "server_side".to_string());
+        }
+
+        match update.dither() {
+            Ok(dither) => {
+                self.chain.set_dither(dither);
+                applied.dithering = true;
+            }
+            Err(e) => {
+                self.chain.set_dither(None);
+                fail("E501", format!("Dithering: {}", e));
+                fallback.insert("dithering".to_string(), "server_side".to_string());
+            }
+        }
+
+        self.chain.set_fir(None);
+        if let Some(convolution) = update.convolution.as_ref().filter(|c| c.enabled) {
+            match (self.filters.get(&convolution.filter_id), filter_error) {
+                (_, Some(message)) => fail("E502", message),
+                (None, None) => fail("E502", format!("Convolution: filter '{}' unavailable", convolution.filter_id)),
+                (Some(filter), None) if filter.sample_rate != self.chain.sample_rate() => fail(
+                    "E502",
+                    format!(
+                        "Convolution: filter is for {} Hz, stream is {} Hz",
+                        filter.sample_rate,
+                        self.chain.sample_rate()
+                    ),
+                ),
+                (Some(filter), None) if filter.taps.len() > self.max_fir_taps => fail(
+                    "E503",
+                    format!(
+                        "Convolution: {} taps exceeds this node's limit of {}",
+                        filter.taps.len(),
+                        self.max_fir_taps
+                    ),
+                ),
+                (Some(filter), None) => {
+                    self.chain.set_fir(Some(FirFilter::new(
+                        &filter.taps,
+                        self.chain.channels(),
+                        convolution.delay_samples,
+                        convolution.gain_db,
+                    )));
+                    applied.convolution = true;
+                }
+            }
+            if !applied.convolution {
+                fallback.insert("convolution".to_string(), "server_side".to_string());
+            }
+        }
+
+        let status = if errors.is_empty() {
+            DspStatus::Success
+        } else if applied.equalizer || applied.headroom || applied.dithering || applied.convolution {
+            DspStatus::Partial
+        } else {
+            DspStatus::Failed
+        };
+        self.profile_hash = update.profile_hash();
+        info!(
+            "Applied DSP profile {} '{}' ({:?}, {} EQ band(s))",
+            update.profile_id,
+            update.profile_name,
+            status,
+            if applied.equalizer { bands.len() } else { 0 }
+        );
+
+        DspUpdateAck {
+            profile_id: update.profile_id,
+            status,
+            profile_hash: self.profile_hash,
+            applied,
+            errors,
+            fallback,
+        }
+    }
+
+    /// Process interleaved audio in-place
+    pub fn process(&mut self, samples: &mut [f64]) {
+        self.chain.process(samples);
+    }
+
+    /// Clear filter state after the buffer was flushed
+    pub fn reset(&mut self) {
+        self.chain.reset();
+    }
+
+    pub fn health(&self) -> DspHealth {
+        DspHealth {
+            current_profile_hash: self.profile_hash,
+            eq_active: self.chain.eq_active(),
+            convolution_active: self.chain.convolution_active(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stream_server::sinks::anp::{BandType, ConvolutionConfig, DspBand, EqualizerConfig};
+
+    fn profile() -> DspUpdate {
+        DspUpdate {
+            profile_id: 42,
+            profile_name: "Rock".to_string(),
+            headroom_db: -3.0,
+            dithering: "tpdf_24bit".to_string(),
+            equalizer: EqualizerConfig {
+                name: "Rock".to_string(),
+                enabled: true,
+                bands: vec![DspBand {
+                    frequency: 62.0,
+                    gain: 4.0,
+                    q: 0.7,
+                    band_type: BandType::LowShelf,
+                }],
+            },
+            convolution: None,
+        }
+    }
+
+    fn with_filter(update: DspUpdate) -> DspUpdate {
+        DspUpdate {
+            convolution: Some(ConvolutionConfig {
+                enabled: true,
+                filter_id: "room".to_string(),
+                delay_samples: 0,
+                gain_db: 0.0,
+            }),
+            ..update
+        }
+    }
+
+    fn response(taps: Vec<f32>) -> ConvolutionResponse {
+        ConvolutionResponse {
+            filter_id: "room".to_string(),
+            format: "f32le".to_string(),
+            sample_rate: 48000,
+            length_samples: taps.len() as u32,
+            checksum: fir_checksum(&taps),
+            taps: Some(taps),
+            ..Default::default()
+        }
+    }
+
+    fn expect_ack(message: Option<ControlMessage>) -> DspUpdateAck {
+        match message {
+            Some(ControlMessage::DspUpdateAck(ack)) => ack,
+            other => panic!("expected dsp_update_ack, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        let mut dsp = NodeDsp::new(48000, 2, DEFAULT_MAX_FIR_TAPS);
+        let ack = expect_ack(Some(dsp.update(profile())));
+
+        assert_eq!(ack.status, DspStatus::Success);
+        assert_eq!(ack.profile_hash, profile().profile_hash());
+        assert!(ack.applied.equalizer && ack.applied.headroom && ack.applied.dithering);
+        assert!(!ack.applied.convolution);
+        assert!(dsp.health().eq_active);
+        assert_eq!(dsp.health().current_profile_hash, ack.profile_hash);
+    }
+
+    #[test]
+    fn test_invalid_band_leaves_eq_to_server() {
+        let mut dsp = NodeDsp::new(48000, 2, DEFAULT_MAX_FIR_TAPS);
+        let mut update = profile();
+        update.equalizer.bands[0].frequency = 30000.0;
+        let ack = expect_ack(Some(dsp.update(update)));
+
+        assert_eq!(ack.status, DspStatus::Partial);
+        assert!(!ack.applied.equalizer);
+        assert_eq!(ack.errors[0].code, "E501");
+        assert_eq!(ack.fallback["equalizer"], "server_side");
+        assert!(!dsp.health().eq_active);
+    }
+
+    #[test]
+    fn test_convolution_filter_is_requested_then_applied() {
+        let mut dsp = NodeDsp::new(48000, 2, DEFAULT_MAX_FIR_TAPS);
+        let ControlMessage::ConvolutionRequest(request) = dsp.update(with_filter(profile())) else {
+            panic!("expected convolution_request");
+        };
+        assert_eq!(request.filter_id, "room");
+
+        let ack = expect_ack(dsp.filter_received(response(vec![1.0, 0.5])));
+        assert_eq!(ack.status, DspStatus::Success);
+        assert!(ack.applied.convolution);
+        assert!(dsp.health().convolution_active);
+
+        // Cached filters don't need another round trip
+        assert!(matches!(dsp.update(with_filter(profile())), ControlMessage::DspUpdateAck(_)));
+    }
+
+    #[test]
+    fn test_convolution_failures_fall_back_to_server() {
+        let mut dsp = NodeDsp::new(48000, 2, 4);
+        dsp.update(with_filter(profile()));
+        let ack = expect_ack(dsp.filter_received(response(vec![0.1; 8])));
+        assert_eq!(ack.status, DspStatus::Partial);
+        assert_eq!(ack.errors[0].code, "E503");
+        assert_eq!(ack.fallback["convolution"], "server_side");
+        assert!(ack.applied.equalizer);
+
+        let mut dsp = NodeDsp::new(48000, 2, 4);
+        dsp.update(with_filter(profile()));
+        let mut corrupt = response(vec![1.0]);
+        corrupt.checksum = fir_checksum(&[0.5]);
+        let ack = expect_ack(dsp.filter_received(corrupt));
+        assert_eq!(ack.errors[0].code, "E502");
+        assert!(!dsp.health().convolution_active);
+
+        let mut dsp = NodeDsp::new(48000, 2, 4);
+        dsp.update(with_filter(profile()));
+        let ack = expect_ack(dsp.filter_unavailable("unknown filter"));
+        assert_eq!(ack.errors[0].code, "E502");
+        assert!(ack.applied.equalizer);
+    }
+}
@@ -0,0 +1,48 @@
+//! Persistent node identity (ANP §3.3)
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Default location of the stored node UUID
+pub fn default_uuid_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("aaeq")
+        .join("node-uuid")
+}
+
+/// Load the node UUID from `path`, generating and storing one on first run
+pub fn load_or_create_uuid(path: &Path) -> Result<String> {
+    if let Ok(stored) = std::fs::read_to_string(path) {
+        if let Ok(uuid) = uuid::Uuid::parse_str(stored.trim()) {
+            return Ok(uuid.to_string());
+        }
+    }
+
+    let uuid = uuid::Uuid::new_v4().to_string();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::write(path, &uuid).with_context(|| format!("Failed to store node UUID in {}", path.display()))?;
+    info!("Generated node UUID {} ({})", uuid, path.display());
+    Ok(uuid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuid_is_persistent() {
+        let path = std::env::temp_dir()
+            .join(format!("aaeq-node-identity-{}", std::process::id()))
+            .join("node-uuid");
+
+        let first = load_or_create_uuid(&path).unwrap();
+        let second = load_or_create_uuid(&path).unwrap();
+        assert_eq!(first, second);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
@@ -0,0 +1,278 @@
+//! Jitter buffer for incoming ANP RTP audio (ANP §9)
+
+use anyhow::{bail, Result};
+use std::collections::VecDeque;
+use stream_server::sinks::anp::{BufferConfig, RtpPacket, PT_L16, PT_L24};
+
+/// Jitter buffer state (§9.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferState {
+    /// No audio received since the session started or the buffer was flushed
+    Empty,
+    /// Waiting for the start threshold before (re)starting playback
+    Filling,
+    Playing,
+}
+
+/// Lifetime counters reported in health telemetry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JitterStats {
+    pub packets_received: u64,
+    pub packets_lost: u64,
+    /// Packets that arrived after a later one had already been buffered
+    pub late_packets: u64,
+    pub bytes_received: u64,
+    pub underruns: u64,
+    pub overruns: u64,
+}
+
+/// Buffers packets in arrival order, conceals gaps with silence and gates
+/// playback on the start threshold
+pub struct JitterBuffer {
+    sample_rate: u32,
+    channels: usize,
+    config: BufferConfig,
+    samples: VecDeque<f64>,
+    state: BufferState,
+    next_sequence: Option<u16>,
+    next_timestamp: Option<u32>,
+    stats: JitterStats,
+}
+
+impl JitterBuffer {
+    pub fn new(sample_rate: u32, channels: usize, config: BufferConfig) -> Self {
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            config,
+            samples: VecDeque::new(),
+            state: BufferState::Empty,
+            next_sequence: None,
+            next_timestamp: None,
+            stats: JitterStats::default(),
+        }
+    }
+
+    /// Add a received packet, concealing any lost packets before it
+    pub fn push(&mut self, packet: &RtpPacket) -> Result<()> {
+        let bytes_per_sample = match packet.payload_type {
+            PT_L24 => 3,
+            PT_L16 => 2,
+            other => bail!("Unsupported RTP payload type {}", other),
+        };
+        let frame_bytes = bytes_per_sample * self.channels;
+        let frames = (packet.payload.len() / frame_bytes) as u32;
+
+        if let (Some(sequence), Some(timestamp)) = (self.next_sequence, self.next_timestamp) {
+            let gap = packet.sequence.wrapping_sub(sequence) as i16;
+            if gap < 0 {
+                self.stats.late_packets += 1;
+                return Ok(());
+            }
+            if gap > 0 {
+                self.stats.packets_lost += gap as u64;
+                let missing = packet.timestamp.wrapping_sub(timestamp) as usize;
+                let missing = missing.min(self.ms_to_frames(self.config.max_ms));
+                self.samples.extend(std::iter::repeat_n(0.0, missing * self.channels));
+            }
+        }
+
+        self.next_sequence = Some(packet.sequence.wrapping_add(1));
+        self.next_timestamp = Some(packet.timestamp.wrapping_add(frames));
+        self.stats.packets_received += 1;
+        self.stats.bytes_received += packet.payload.len() as u64;
+
+        self.samples.extend(decode_samples(&packet.payload, bytes_per_sample));
+
+        if self.buffered_ms() > self.config.max_ms as f32 {
+            let keep = self.ms_to_frames(self.config.target_ms) * self.channels;
+            let excess = self.samples.len().saturating_sub(keep);
+            self.samples.drain(..excess);
+            self.stats.overruns += 1;
+        }
+
+        if self.state == BufferState::Empty {
+            self.state = BufferState::Filling;
+        }
+        if self.state == BufferState::Filling && self.buffered_ms() >= self.config.start_threshold_ms as f32 {
+            self.state = BufferState::Playing;
+        }
+        Ok(())
+    }
+
+    /// Take `frames` interleaved frames for playback
+    ///
+    /// Returns `None` while filling. Running dry counts an underrun and
+    /// re-enters the filling state.
+    pub fn pull(&mut self, frames: usize) -> Option<Vec<f64>> {
+        if self.state != BufferState::Playing {
+            return None;
+        }
+        let wanted = frames * self.channels;
+        if self.samples.len() < wanted {
+            self.stats.underruns += 1;
+            self.state = BufferState::Filling;
+            return None;
+        }
+        Some(self.samples.drain(..wanted).collect())
+    }
+
+    /// Drop all buffered audio and forget the stream position
+    pub fn flush(&mut self) {
+        self.samples.clear();
+        self.state = BufferState::Empty;
+        self.next_sequence = None;
+        self.next_timestamp = None;
+    }
+
+    pub fn state(&self) -> BufferState {
+        self.state
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        self.stats
+    }
+
+    pub fn config(&self) -> &BufferConfig {
+        &self.config
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn buffered_frames(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    pub fn buffered_ms(&self) -> f32 {
+        self.buffered_frames() as f32 * 1000.0 / self.sample_rate as f32
+    }
+
+    pub fn target_frames(&self) -> usize {
+        self.ms_to_frames(self.config.target_ms)
+    }
+
+    /// Fill relative to the target, as reported in `buffer_fill_percent`
+    pub fn fill_percent(&self) -> f32 {
+        if self.config.target_ms == 0 {
+            return 0.0;
+        }
+        self.buffered_ms() / self.config.target_ms as f32 * 100.0
+    }
+
+    fn ms_to_frames(&self, ms: u32) -> usize {
+        (self.sample_rate as u64 * ms as u64 / 1000) as usize
+    }
+}
+
+/// Decode big-endian L16/L24 samples into normalized floats
+fn decode_samples(payload: &[u8], bytes_per_sample: usize) -> impl Iterator<Item = f64> + '_ {
+    payload.chunks_exact(bytes_per_sample).map(move |sample| match bytes_per_sample {
+        3 => (i32::from_be_bytes([sample[0], sample[1], sample[2], 0]) >> 8) as f64 / 8_388_608.0,
+        _ => i16::from_be_bytes([sample[0], sample[1]]) as f64 / 32_768.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 10 ms of stereo L16 at 48 kHz per packet
+    const FRAMES: u32 = 480;
+
+    fn packet(sequence: u16, value: i16) -> RtpPacket {
+        let payload = (0..FRAMES * 2).flat_map(|_| value.to_be_bytes()).collect();
+        RtpPacket {
+            payload_type: PT_L16,
+            marker: false,
+            sequence,
+            timestamp: sequence as u32 * FRAMES,
+            ssrc: 1,
+            extensions: Vec::new(),
+            payload,
+        }
+    }
+
+    fn buffer() -> JitterBuffer {
+        JitterBuffer::new(48000, 2, BufferConfig::with_target(60, 20, 100))
+    }
+
+    #[test]
+    fn test_start_threshold_gates_playback() {
+        let mut jitter = buffer();
+        assert_eq!(jitter.state(), BufferState::Empty);
+
+        // Start threshold is 40 ms
+        for seq in 0..3 {
+            jitter.push(&packet(seq, 1000)).unwrap();
+        }
+        assert_eq!(jitter.state(), BufferState::Filling);
+        assert!(jitter.pull(480).is_none());
+
+        jitter.push(&packet(3, 1000)).unwrap();
+        assert_eq!(jitter.state(), BufferState::Playing);
+        assert_eq!(jitter.buffered_ms(), 40.0);
+
+        let samples = jitter.pull(480).unwrap();
+        assert_eq!(samples.len(), 960);
+        assert!((samples[0] - 1000.0 / 32768.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_lost_packets_are_concealed() {
+        let mut jitter = buffer();
+        jitter.push(&packet(0, 1000)).unwrap();
+        jitter.push(&packet(3, 1000)).unwrap();
+
+        assert_eq!(jitter.stats().packets_lost, 2);
+        assert_eq!(jitter.buffered_frames(), 4 * FRAMES as usize);
+
+        // Duplicates and stragglers are dropped
+        jitter.push(&packet(2, 1000)).unwrap();
+        assert_eq!(jitter.stats().late_packets, 1);
+        assert_eq!(jitter.buffered_frames(), 4 * FRAMES as usize);
+    }
+
+    #[test]
+    fn test_underrun_and_overrun() {
+        let mut jitter = buffer();
+        for seq in 0..4 {
+            jitter.push(&packet(seq, 0)).unwrap();
+        }
+        assert!(jitter.pull(4 * 480).is_some());
+        assert!(jitter.pull(480).is_none());
+        assert_eq!(jitter.stats().underruns, 1);
+        assert_eq!(jitter.state(), BufferState::Filling);
+
+        // Exceeding max_ms trims back to the target
+        for seq in 4..20 {
+            jitter.push(&packet(seq, 0)).unwrap();
+        }
+        assert!(jitter.stats().overruns > 0);
+        assert!(jitter.buffered_ms() <= 100.0);
+    }
+
+    #[test]
+    fn test_decode_l24() {
+        let samples: Vec<f64> = decode_samples(&[0x7F, 0xFF, 0xFF, 0x80, 0x00, 0x00], 3).collect();
+        assert!((samples[0] - 8_388_607.0 / 8_388_608.0).abs() < 1e-12);
+        assert_eq!(samples[1], -1.0);
+    }
+
+    #[test]
+    fn test_flush_resets_stream_position() {
+        let mut jitter = buffer();
+        jitter.push(&packet(10, 0)).unwrap();
+        jitter.flush();
+        assert_eq!(jitter.state(), BufferState::Empty);
+        assert_eq!(jitter.buffered_frames(), 0);
+
+        jitter.push(&packet(500, 0)).unwrap();
+        assert_eq!(jitter.stats().packets_lost, 0);
+    }
+}
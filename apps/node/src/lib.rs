@@ -0,0 +1,24 @@
+//! Headless AAEQ Node Protocol (ANP) receiver
+//!
+//! Advertises itself over mDNS, accepts a control session from an AAEQ
+//! server, buffers the incoming RTP stream and plays it on a local output,
+//! correcting clock drift with a micro-PLL and reporting health telemetry.
+//! Servers can also push their DSP profile for the node to run locally.
+
+mod advertise;
+mod dsp;
+mod identity;
+mod jitter;
+mod node;
+mod output;
+mod pll;
+mod volume;
+
+pub use advertise::{Advertisement, Advertiser};
+pub use dsp::{NodeDsp, DEFAULT_MAX_FIR_TAPS};
+pub use identity::{default_uuid_path, load_or_create_uuid};
+pub use jitter::{BufferState, JitterBuffer, JitterStats};
+pub use node::{Node, NodeConfig, NodeState};
+pub use output::{NullSink, OutputKind};
+pub use pll::{measure_drift_ppm, MicroPll};
+pub use volume::SoftwareVolume;
@@ -0,0 +1,142 @@
+use aaeq_node::{default_uuid_path, load_or_create_uuid, Node, NodeConfig, OutputKind, DEFAULT_MAX_FIR_TAPS};
+use anyhow::{bail, Result};
+use clap::{Parser, ValueEnum};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use stream_server::sinks::anp::{discover_nodes, DEFAULT_CONTROL_PORT};
+use stream_server::{LocalDacSink, ResamplerQuality};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Output {
+    /// Local sound card
+    Dac,
+    /// Discard audio
+    Null,
+    /// Record to a file (requires --file)
+    File,
+}
+
+/// AAEQ Node - headless ANP audio receiver
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Name advertised to AAEQ servers
+    #[arg(long, default_value = "AAEQ Node")]
+    name: String,
+
+    /// Address to bind the control and RTP sockets to
+    #[arg(long, default_value = "0.0.0.0")]
+    bind: IpAddr,
+
+    /// WebSocket control port
+    #[arg(long, default_value_t = DEFAULT_CONTROL_PORT)]
+    port: u16,
+
+    /// UDP port for RTP audio (RTCP uses the next port)
+    #[arg(long, default_value_t = 5004)]
+    rtp_port: u16,
+
+    /// Where to play received audio
+    #[arg(long, value_enum, default_value_t = Output::Dac)]
+    output: Output,
+
+    /// Output device name for --output dac
+    #[arg(long)]
+    device: Option<String>,
+
+    /// File path for --output file (.wav, .flac or .w64)
+    #[arg(long)]
+    file: Option<PathBuf>,
+
+    /// Disable micro-PLL drift correction
+    #[arg(long)]
+    no_pll: bool,
+
+    /// Don't accept DSP profiles from the server
+    #[arg(long)]
+    no_dsp: bool,
+
+    /// Longest convolution filter to run locally, in taps
+    #[arg(long, default_value_t = DEFAULT_MAX_FIR_TAPS)]
+    max_fir_taps: usize,
+
+    /// Do not advertise the node over mDNS
+    #[arg(long)]
+    no_mdns: bool,
+
+    /// File holding the persistent node UUID
+    #[arg(long)]
+    uuid_file: Option<PathBuf>,
+
+    /// List output devices and exit
+    #[arg(long)]
+    list_devices: bool,
+
+    /// List ANP nodes on the network and exit
+    #[arg(long)]
+    discover: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
+        )
+        .init();
+
+    if args.list_devices {
+        for device in LocalDacSink::list_devices()? {
+            println!("{}", device);
+        }
+        return Ok(());
+    }
+
+    if args.discover {
+        for node in discover_nodes(3).await? {
+            println!(
+                "{} ({}) at {} [{}]",
+                node.name,
+                node.uuid,
+                node.control_url,
+                node.state.as_deref().unwrap_or("unknown")
+            );
+        }
+        return Ok(());
+    }
+
+    let output = match args.output {
+        Output::Dac => OutputKind::Dac { device: args.device },
+        Output::Null => OutputKind::Null,
+        Output::File => match args.file {
+            Some(path) => OutputKind::File { path },
+            None => bail!("--output file requires --file <PATH>"),
+        },
+    };
+
+    let uuid_path = args.uuid_file.unwrap_or_else(default_uuid_path);
+    let config = NodeConfig {
+        name: args.name,
+        uuid: load_or_create_uuid(&uuid_path)?,
+        control_addr: SocketAddr::new(args.bind, args.port),
+        rtp_port: args.rtp_port,
+        output,
+        micro_pll: !args.no_pll,
+        resampler_quality: ResamplerQuality::Balanced,
+        dsp_transfer: !args.no_dsp,
+        max_fir_taps: args.max_fir_taps,
+        advertise: !args.no_mdns,
+        ..Default::default()
+    };
+
+    let node = Node::bind(config).await?;
+    tokio::select! {
+        result = node.run() => result,
+        _ = tokio::signal::ctrl_c() => {
+            tracing::info!("Shutting down");
+            Ok(())
+        }
+    }
+}
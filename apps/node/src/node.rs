@@ -0,0 +1,775 @@
+//! ANP node: control channel, RTP reception and paced playout
+
+use crate::advertise::{Advertisement, Advertiser};
+use crate::dsp::{NodeDsp, DEFAULT_MAX_FIR_TAPS};
+use crate::jitter::{BufferState, JitterBuffer};
+use crate::output::OutputKind;
+use crate::pll::{measure_drift_ppm, MicroPll};
+use crate::volume::SoftwareVolume;
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use stream_server::sinks::anp::{
+    BufferHealth, ClockSync, ConnectionHealth, ControlMessage, ErrorCounters, ErrorReport, ErrorSeverity, Health,
+    Integrity, LatencyBreakdown, NodeCapabilities, PlaybackHealth, RtpPacket, SessionAccept, SessionInit,
+    VolumeHealth, VolumeResult, DEFAULT_CONTROL_PORT, PROTOCOL_VERSION,
+};
+use stream_server::{AudioBlock, OutputConfig, OutputSink, Resampler, ResamplerQuality, SampleFormat};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::{debug, error, info, warn};
+
+/// Interval between `health` reports (§8.1)
+const HEALTH_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the server has to answer `session_init`
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Audio written to the output ahead of real time
+const OUTPUT_LEAD: Duration = Duration::from_millis(40);
+/// Playout chunk when the PLL resampler is bypassed (10 ms at 48 kHz)
+const BYPASS_CHUNK_FRAMES: usize = 480;
+
+/// Node configuration
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// Friendly name used as the mDNS instance name
+    pub name: String,
+    /// Persistent node UUID
+    pub uuid: String,
+    /// Address of the WebSocket control server
+    pub control_addr: SocketAddr,
+    /// UDP port for RTP (RTCP uses the next port); 0 picks a free port
+    pub rtp_port: u16,
+    pub output: OutputKind,
+    pub sample_rates: Vec<u32>,
+    /// Supported formats, e.g. `S24LE`
+    pub formats: Vec<String>,
+    pub channels: u16,
+    pub buffer_range_ms: [u32; 2],
+    /// Offer the `micro_pll` feature and correct drift with the resampler
+    pub micro_pll: bool,
+    pub resampler_quality: ResamplerQuality,
+    /// Offer `dsp_transfer` and run the server's DSP profile locally
+    pub dsp_transfer: bool,
+    /// Longest convolution filter accepted with `dsp_transfer`
+    pub max_fir_taps: usize,
+    /// Advertise the node over mDNS
+    pub advertise: bool,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            name: "AAEQ Node".to_string(),
+            uuid: uuid::Uuid::new_v4().to_string(),
+            control_addr: SocketAddr::from(([0, 0, 0, 0], DEFAULT_CONTROL_PORT)),
+            rtp_port: 5004,
+            output: OutputKind::Dac { device: None },
+            sample_rates: vec![44100, 48000, 88200, 96000, 176400, 192000],
+            formats: vec!["S24LE".to_string(), "S16LE".to_string()],
+            channels: 2,
+            buffer_range_ms: [50, 500],
+            micro_pll: true,
+            resampler_quality: ResamplerQuality::Balanced,
+            dsp_transfer: true,
+            max_fir_taps: DEFAULT_MAX_FIR_TAPS,
+            advertise: true,
+        }
+    }
+}
+
+impl NodeConfig {
+    fn features(&self) -> Vec<String> {
+        let mut features = vec!["crc_verify", "volume_control", "gapless", "capabilities"];
+        if self.micro_pll {
+            features.insert(0, "micro_pll");
+        }
+        features.into_iter().map(String::from).collect()
+    }
+
+    fn optional_features(&self) -> Vec<String> {
+        let mut features = vec!["rtcp_sr"];
+        if self.dsp_transfer {
+            features.extend(["dsp_transfer", "convolution"]);
+        }
+        features.into_iter().map(String::from).collect()
+    }
+
+    fn capabilities(&self) -> NodeCapabilities {
+        NodeCapabilities {
+            hardware: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            dac_name: self.output.describe(),
+            max_sample_rate: self.sample_rates.iter().copied().max().unwrap_or(48000),
+            supported_formats: self.formats.clone(),
+            native_format: self.formats.first().cloned().unwrap_or_else(|| "S24LE".to_string()),
+            max_channels: self.channels,
+            buffer_range_ms: self.buffer_range_ms,
+            ..Default::default()
+        }
+    }
+
+    fn session_init(&self, rtp_port: u16) -> SessionInit {
+        SessionInit {
+            protocol_version: PROTOCOL_VERSION.to_string(),
+            node_uuid: self.uuid.clone(),
+            features: self.features(),
+            optional_features: self.optional_features(),
+            latency_comp: true,
+            node_capabilities: self.capabilities(),
+            rtp_port: Some(rtp_port),
+        }
+    }
+
+    fn advertisement(&self) -> Advertisement {
+        let abbreviate = |feature: &str| {
+            match feature {
+                "micro_pll" => "pll",
+                "crc_verify" => "crc",
+                "volume_control" => "vol",
+                "gapless" => "gap",
+                "capabilities" => "cap",
+                other => other,
+            }
+            .to_string()
+        };
+        Advertisement {
+            name: self.name.clone(),
+            uuid: self.uuid.clone(),
+            sample_rates: self.sample_rates.clone(),
+            formats: self.formats.iter().map(|f| f.trim_end_matches("LE").to_string()).collect(),
+            channels: self.channels,
+            features: self.features().iter().map(|f| abbreviate(f)).collect(),
+            control_addr: self.control_addr,
+            dac: self.output.describe(),
+        }
+    }
+}
+
+/// Playback state reported in health and mDNS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    Idle,
+    Buffering,
+    Playing,
+    Paused,
+}
+
+impl NodeState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeState::Idle => "idle",
+            NodeState::Buffering => "buffering",
+            NodeState::Playing => "playing",
+            NodeState::Paused => "paused",
+        }
+    }
+
+    /// Abbreviation for the `st` TXT field (§3.2)
+    fn txt(&self) -> &'static str {
+        match self {
+            NodeState::Idle | NodeState::Paused => "idle",
+            NodeState::Buffering => "buf",
+            NodeState::Playing => "play",
+        }
+    }
+}
+
+/// State of the stream negotiated with the connected server
+struct ActiveSession {
+    accept: SessionAccept,
+    started: Instant,
+    jitter: JitterBuffer,
+    /// Present when the server negotiated `dsp_transfer`
+    dsp: Option<NodeDsp>,
+    volume: SoftwareVolume,
+    paused: bool,
+    stopped: bool,
+    clock: ClockSync,
+    crc_ok: u64,
+    crc_fail: u64,
+    last_crc_fail_seq: Option<u16>,
+    sink_underruns: u64,
+    dac_latency_ms: f32,
+    pipeline_latency_ms: f32,
+}
+
+impl ActiveSession {
+    fn new(accept: SessionAccept, max_fir_taps: usize) -> Self {
+        let config = &accept.recommended_config;
+        let jitter = JitterBuffer::new(config.sample_rate, config.channels as usize, accept.buffer);
+        let dsp = accept
+            .optional_features
+            .iter()
+            .any(|f| f == "dsp_transfer")
+            .then(|| NodeDsp::new(config.sample_rate, config.channels as usize, max_fir_taps));
+        let volume = SoftwareVolume::new(
+            config.sample_rate,
+            accept.volume.curve_type,
+            accept.volume.initial_level,
+            accept.volume.mute,
+        );
+        Self {
+            accept,
+            started: Instant::now(),
+            jitter,
+            dsp,
+            volume,
+            paused: false,
+            stopped: false,
+            clock: ClockSync::default(),
+            crc_ok: 0,
+            crc_fail: 0,
+            last_crc_fail_seq: None,
+            sink_underruns: 0,
+            dac_latency_ms: 0.0,
+            pipeline_latency_ms: 0.0,
+        }
+    }
+
+    fn state(&self) -> NodeState {
+        if self.paused {
+            NodeState::Paused
+        } else if self.stopped {
+            NodeState::Idle
+        } else if self.jitter.state() == BufferState::Playing {
+            NodeState::Playing
+        } else {
+            NodeState::Buffering
+        }
+    }
+
+    /// Verify and buffer an RTP packet from the server
+    fn receive(&mut self, packet: &RtpPacket) -> Result<()> {
+        if packet.ssrc != self.accept.rtp_config.ssrc {
+            bail!("Unexpected SSRC {:#010x}", packet.ssrc);
+        }
+        if self.paused || self.stopped {
+            return Ok(());
+        }
+
+        let extensions = self.accept.rtp_extensions;
+        if extensions.crc32.enabled {
+            if let Some(crc) = packet.crc32(extensions.crc32.extension_id) {
+                if crc == crc32fast::hash(&packet.payload) {
+                    self.crc_ok += 1;
+                } else {
+                    // Corrupt audio is worse than a gap: drop it and let
+                    // the jitter buffer conceal the missing packet
+                    self.crc_fail += 1;
+                    self.last_crc_fail_seq = Some(packet.sequence);
+                    warn!("CRC mismatch in RTP packet {}", packet.sequence);
+                    return Ok(());
+                }
+            }
+        }
+        if extensions.gapless.enabled {
+            if let Some(markers) = packet.track_markers(extensions.gapless.extension_id) {
+                if markers.track_end {
+                    debug!("Track end at RTP timestamp {}", packet.timestamp);
+                }
+                if markers.track_start {
+                    debug!("Track start at RTP timestamp {}", packet.timestamp);
+                }
+            }
+        }
+
+        self.jitter.push(packet)
+    }
+
+    fn health(&self) -> Health {
+        let stats = self.jitter.stats();
+        let fill_percent = self.jitter.fill_percent();
+        let jitter_ms = self.jitter.buffered_ms();
+        let underruns = stats.underruns + self.sink_underruns;
+
+        Health {
+            timestamp_us: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or(0),
+            connection: ConnectionHealth {
+                state: "connected".to_string(),
+                uptime_seconds: self.started.elapsed().as_secs(),
+                packets_received: stats.packets_received,
+                packets_lost: stats.packets_lost,
+                bytes_received: stats.bytes_received,
+            },
+            playback: PlaybackHealth {
+                state: self.state().as_str().to_string(),
+                buffer_ms: jitter_ms,
+                buffer_health: BufferHealth::from_fill_percent(fill_percent),
+                buffer_fill_percent: fill_percent,
+            },
+            latency: LatencyBreakdown {
+                network_ms: 0.0,
+                jitter_buffer_ms: jitter_ms,
+                dac_ms: self.dac_latency_ms,
+                pipeline_ms: self.pipeline_latency_ms,
+                total_ms: jitter_ms + self.dac_latency_ms + self.pipeline_latency_ms,
+            },
+            clock_sync: self.clock.clone(),
+            integrity: Integrity {
+                crc_ok: self.crc_ok,
+                crc_fail: self.crc_fail,
+                last_crc_fail_seq: self.last_crc_fail_seq,
+            },
+            errors: ErrorCounters {
+                xruns: underruns,
+                buffer_underruns: underruns,
+                buffer_overruns: stats.overruns,
+                last_xrun_timestamp_us: None,
+            },
+            volume: VolumeHealth {
+                level: self.volume.level(),
+                mute: self.volume.mute(),
+                hardware_control: false,
+                gain_db: self.volume.gain_db(),
+            },
+            dsp: self.dsp.as_ref().map(|d| d.health()).unwrap_or_default(),
+        }
+    }
+
+    fn volume_result(&self, status: Option<&str>) -> VolumeResult {
+        VolumeResult {
+            status: status.map(String::from),
+            level: self.volume.level(),
+            mute: self.volume.mute(),
+            gain_db: self.volume.gain_db(),
+            ramp_complete: Some(self.volume.ramp_complete()),
+            hardware_control: Some(false),
+            dac_volume_db: None,
+            curve_type: Some(self.volume.curve()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    connected: bool,
+    session: Option<ActiveSession>,
+}
+
+type Sessions = Arc<Mutex<Shared>>;
+
+/// A running ANP node: accepts one server at a time on its control port
+pub struct Node {
+    config: NodeConfig,
+    listener: TcpListener,
+    rtp_socket: Arc<UdpSocket>,
+    shared: Sessions,
+}
+
+impl Node {
+    /// Bind the control and RTP sockets
+    pub async fn bind(config: NodeConfig) -> Result<Self> {
+        let listener = TcpListener::bind(config.control_addr)
+            .await
+            .with_context(|| format!("Failed to bind control port {}", config.control_addr))?;
+        let rtp_socket = UdpSocket::bind(SocketAddr::new(config.control_addr.ip(), config.rtp_port))
+            .await
+            .with_context(|| format!("Failed to bind RTP port {}", config.rtp_port))?;
+
+        let mut config = config;
+        config.control_addr = listener.local_addr()?;
+        config.rtp_port = rtp_socket.local_addr()?.port();
+
+        Ok(Self {
+            config,
+            listener,
+            rtp_socket: Arc::new(rtp_socket),
+            shared: Arc::new(Mutex::new(Shared::default())),
+        })
+    }
+
+    pub fn config(&self) -> &NodeConfig {
+        &self.config
+    }
+
+    pub fn control_addr(&self) -> SocketAddr {
+        self.config.control_addr
+    }
+
+    pub fn rtp_port(&self) -> u16 {
+        self.config.rtp_port
+    }
+
+    /// Serve servers until the task is cancelled
+    pub async fn run(self) -> Result<()> {
+        let mut advertiser = if self.config.advertise {
+            match Advertiser::start(self.config.advertisement()) {
+                Ok(advertiser) => Some(advertiser),
+                Err(e) => {
+                    warn!("mDNS advertisement unavailable: {:#}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        info!(
+            "ANP node '{}' ({}) listening on ws://{} (RTP port {})",
+            self.config.name, self.config.uuid, self.config.control_addr, self.config.rtp_port
+        );
+
+        let rtp = receive_rtp(self.rtp_socket.clone(), self.shared.clone());
+        tokio::pin!(rtp);
+        let mut refresh = tokio::time::interval(HEALTH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => {
+                    let (stream, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!("Control connection failed: {}", e);
+                            continue;
+                        }
+                    };
+                    let busy = std::mem::replace(&mut self.shared.lock().unwrap().connected, true);
+                    let config = self.config.clone();
+                    let shared = self.shared.clone();
+                    tokio::spawn(async move {
+                        if busy {
+                            reject_busy(stream).await;
+                            return;
+                        }
+                        info!("Server connected from {}", peer);
+                        if let Err(e) = serve(stream, &config, &shared).await {
+                            warn!("Session with {} ended: {:#}", peer, e);
+                        }
+                        let mut shared = shared.lock().unwrap();
+                        shared.session = None;
+                        shared.connected = false;
+                        info!("Server {} disconnected", peer);
+                    });
+                }
+                _ = refresh.tick() => {
+                    if let Some(advertiser) = advertiser.as_mut() {
+                        let (state, volume) = {
+                            let shared = self.shared.lock().unwrap();
+                            match shared.session.as_ref() {
+                                Some(session) => (session.state(), session.volume.level()),
+                                None => (NodeState::Idle, 1.0),
+                            }
+                        };
+                        if let Err(e) = advertiser.update(state.txt(), volume) {
+                            debug!("mDNS update failed: {:#}", e);
+                        }
+                    }
+                }
+                _ = &mut rtp => bail!("RTP receiver stopped"),
+            }
+        }
+    }
+}
+
+/// Turn away a second server while one is connected
+async fn reject_busy(stream: TcpStream) {
+    if let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await {
+        let report = ErrorReport::new("E103", ErrorSeverity::Fatal, "Node is already streaming from another server");
+        ws.send(Message::text(ControlMessage::Error(report).to_json())).await.ok();
+        ws.close(None).await.ok();
+    }
+}
+
+async fn send(ws: &mut WebSocketStream<TcpStream>, message: ControlMessage) -> Result<()> {
+    ws.send(Message::text(message.to_json())).await.context("Failed to send control message")
+}
+
+/// Run one control session: negotiate, stream until stopped, then clean up
+async fn serve(stream: TcpStream, config: &NodeConfig, shared: &Sessions) -> Result<()> {
+    let mut ws = tokio_tungstenite::accept_async(stream).await.context("WebSocket handshake failed")?;
+    send(&mut ws, ControlMessage::SessionInit(config.session_init(config.rtp_port))).await?;
+
+    let accept = tokio::time::timeout(ACCEPT_TIMEOUT, wait_for_accept(&mut ws))
+        .await
+        .map_err(|_| anyhow!("Timed out waiting for session_accept"))??;
+    info!(
+        "Session {} accepted: {} Hz {} ch, buffer {} ms, features {:?}",
+        accept.session_id,
+        accept.recommended_config.sample_rate,
+        accept.recommended_config.channels,
+        accept.buffer.target_ms,
+        accept.active_features
+    );
+
+    let mut sink = config.output.create_sink();
+    let output_config = OutputConfig {
+        sample_rate: accept.recommended_config.sample_rate,
+        channels: accept.recommended_config.channels,
+        format: if accept.recommended_config.format == "S16LE" { SampleFormat::S16LE } else { SampleFormat::S24LE },
+        buffer_ms: accept.buffer.target_ms,
+        exclusive: false,
+    };
+    if let Err(e) = sink.open(output_config).await {
+        let report = ErrorReport::new("E303", ErrorSeverity::Fatal, format!("Failed to open output: {:#}", e));
+        send(&mut ws, ControlMessage::Error(report)).await.ok();
+        return Err(e);
+    }
+
+    let playout_settings = Playout {
+        sample_rate: accept.recommended_config.sample_rate,
+        channels: accept.recommended_config.channels as usize,
+        pll: (config.micro_pll && accept.has_feature("micro_pll")).then_some(accept.micro_pll),
+        quality: config.resampler_quality,
+    };
+    shared.lock().unwrap().session = Some(ActiveSession::new(accept, config.max_fir_taps));
+
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let playout = tokio::spawn(playout_settings.run(sink, shared.clone(), stop_rx));
+
+    let result = control_loop(&mut ws, shared).await;
+
+    stop_tx.send(true).ok();
+    match playout.await {
+        Ok(Err(e)) => error!("Playout failed: {:#}", e),
+        Err(e) => error!("Playout task panicked: {}", e),
+        Ok(Ok(())) => {}
+    }
+    result
+}
+
+async fn wait_for_accept(ws: &mut WebSocketStream<TcpStream>) -> Result<SessionAccept> {
+    while let Some(message) = ws.next().await {
+        let text = match message? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        for message in ControlMessage::parse_all(&text)? {
+            match message {
+                ControlMessage::SessionAccept(accept) => return Ok(accept),
+                ControlMessage::Error(report) => {
+                    bail!("Server rejected session: {} {}", report.code, report.message)
+                }
+                other => debug!("Ignoring {:?} before session_accept", other),
+            }
+        }
+    }
+    bail!("Server closed the connection before accepting the session")
+}
+
+/// Answer server commands and report health until the server disconnects
+async fn control_loop(ws: &mut WebSocketStream<TcpStream>, shared: &Sessions) -> Result<()> {
+    let mut health = tokio::time::interval(HEALTH_INTERVAL);
+    health.tick().await;
+
+    loop {
+        tokio::select! {
+            message = ws.next() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                };
+                let messages = match ControlMessage::parse_all(&text) {
+                    Ok(messages) => messages,
+                    Err(e) => {
+                        let report = ErrorReport::new("E203", ErrorSeverity::Warning, e.to_string());
+                        send(ws, ControlMessage::Error(report)).await?;
+                        continue;
+                    }
+                };
+                for message in messages {
+                    if let Some(reply) = handle_command(message, shared) {
+                        send(ws, reply).await?;
+                    }
+                }
+            }
+            _ = health.tick() => {
+                let report = shared.lock().unwrap().session.as_ref().map(|s| s.health());
+                if let Some(report) = report {
+                    send(ws, ControlMessage::Health(report)).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Apply a server command, returning the reply to send
+fn handle_command(message: ControlMessage, shared: &Sessions) -> Option<ControlMessage> {
+    let mut shared = shared.lock().unwrap();
+    let session = shared.session.as_mut()?;
+
+    match message {
+        ControlMessage::VolumeSet(set) => {
+            let status = if (0.0..=1.0).contains(&set.level) { "ok" } else { "clamped" };
+            session.volume.set(set.level, set.mute, set.ramp_ms, set.ramp_shape);
+            Some(ControlMessage::VolumeResult(session.volume_result(Some(status))))
+        }
+        ControlMessage::VolumeGet {} => Some(ControlMessage::VolumeResult(session.volume_result(None))),
+        ControlMessage::StreamPause {} => {
+            session.paused = true;
+            session.jitter.flush();
+            if let Some(dsp) = session.dsp.as_mut() {
+                dsp.reset();
+            }
+            Some(ControlMessage::StreamPaused { buffer_flushed: true })
+        }
+        ControlMessage::StreamResume {} => {
+            session.paused = false;
+            None
+        }
+        ControlMessage::StreamStop {} => {
+            session.stopped = true;
+            session.jitter.flush();
+            Some(ControlMessage::StreamStopped {})
+        }
+        ControlMessage::GetStatus {} => Some(ControlMessage::Health(session.health())),
+        ControlMessage::DspUpdate(update) => match session.dsp.as_mut() {
+            Some(dsp) => Some(dsp.update(update)),
+            None => {
+                let report = ErrorReport::new("E501", ErrorSeverity::Warning, "dsp_transfer was not negotiated");
+                Some(ControlMessage::Error(report))
+            }
+        },
+        ControlMessage::ConvolutionResponse(response) => session.dsp.as_mut()?.filter_received(response),
+        ControlMessage::Error(report) => {
+            warn!("Server error {}: {}", report.code, report.message);
+            // A convolution_request the server couldn't serve
+            match (report.code.as_str(), session.dsp.as_mut()) {
+                ("E502", Some(dsp)) => dsp.filter_unavailable(&report.message),
+                _ => None,
+            }
+        }
+        other => {
+            debug!("Ignoring unexpected control message {:?}", other);
+            None
+        }
+    }
+}
+
+/// Receive RTP packets for whichever session is active
+async fn receive_rtp(socket: Arc<UdpSocket>, shared: Sessions) {
+    let mut buf = vec![0u8; 2048];
+    loop {
+        let len = match socket.recv(&mut buf).await {
+            Ok(len) => len,
+            Err(e) => {
+                // ICMP errors from earlier sends surface here on some platforms
+                debug!("RTP receive error: {}", e);
+                continue;
+            }
+        };
+        let packet = match RtpPacket::parse(&buf[..len]) {
+            Ok(packet) => packet,
+            Err(e) => {
+                debug!("Ignoring invalid RTP packet: {}", e);
+                continue;
+            }
+        };
+
+        let mut shared = shared.lock().unwrap();
+        if let Some(session) = shared.session.as_mut() {
+            if let Err(e) = session.receive(&packet) {
+                debug!("Dropped RTP packet {}: {}", packet.sequence, e);
+            }
+        }
+    }
+}
+
+/// Paced playout from the jitter buffer through the PLL resampler
+struct Playout {
+    sample_rate: u32,
+    channels: usize,
+    /// PLL parameters when drift correction was negotiated
+    pll: Option<stream_server::sinks::anp::MicroPllConfig>,
+    quality: ResamplerQuality,
+}
+
+impl Playout {
+    async fn run(self, mut sink: Box<dyn OutputSink>, shared: Sessions, mut stop: watch::Receiver<bool>) -> Result<()> {
+        let mut resampler = match self.pll {
+            Some(_) => Some(Resampler::new_adjustable(self.quality, self.sample_rate, self.channels)?),
+            None => None,
+        };
+        let mut pll = self.pll.map(MicroPll::new);
+        let chunk_frames = resampler.as_ref().map_or(BYPASS_CHUNK_FRAMES, |r| r.chunk_frames());
+        let adjustment_interval = pll
+            .as_ref()
+            .map(|p| Duration::from_millis(p.config().adjustment_interval_ms.max(1) as u64));
+
+        {
+            let mut shared = shared.lock().unwrap();
+            if let Some(session) = shared.session.as_mut() {
+                session.dac_latency_ms = sink.latency_ms() as f32;
+                session.pipeline_latency_ms = resampler.as_ref().map_or(0.0, |r| r.latency_ms());
+            }
+        }
+
+        let started = Instant::now();
+        let mut frames_written: u64 = 0;
+        let mut last_adjustment = Instant::now();
+        let mut fill_sum = 0.0;
+        let mut fill_count = 0u32;
+        let silence = vec![0.0; chunk_frames * self.channels];
+
+        loop {
+            // Stay OUTPUT_LEAD ahead of the wall clock
+            let due = started + Duration::from_secs_f64(frames_written as f64 / self.sample_rate as f64);
+            let wake = due.checked_sub(OUTPUT_LEAD).unwrap_or(due);
+            tokio::select! {
+                _ = tokio::time::sleep_until(wake.into()) => {}
+                _ = stop.changed() => break,
+            }
+
+            let (chunk, fill) = {
+                let mut shared = shared.lock().unwrap();
+                let Some(session) = shared.session.as_mut() else { break };
+                let mut chunk = session.jitter.pull(chunk_frames);
+                if let Some(samples) = chunk.as_mut() {
+                    if let Some(dsp) = session.dsp.as_mut() {
+                        dsp.process(samples);
+                    }
+                    session.volume.apply(samples, self.channels);
+                }
+                let fill = (chunk.is_some()).then(|| {
+                    (session.jitter.buffered_frames() as f64, session.jitter.target_frames() as f64)
+                });
+                session.sink_underruns = sink.stats().underruns;
+                (chunk, fill)
+            };
+
+            // Micro-PLL: average the fill over each interval, then adjust (§7)
+            if let (Some(pll), Some(interval), Some((actual, expected))) = (pll.as_mut(), adjustment_interval, fill) {
+                fill_sum += actual;
+                fill_count += 1;
+                if last_adjustment.elapsed() >= interval {
+                    let average = fill_sum / fill_count as f64;
+                    let ratio = pll.update(measure_drift_ppm(average, expected));
+                    if let Some(resampler) = resampler.as_mut() {
+                        resampler.set_ratio_relative(1.0 / ratio)?;
+                    }
+
+                    let mut shared = shared.lock().unwrap();
+                    if let Some(session) = shared.session.as_mut() {
+                        session.clock = ClockSync {
+                            drift_ppm: pll.drift_ppm(),
+                            phase_us: (average - expected) / self.sample_rate as f64 * 1_000_000.0,
+                            pll_state: pll.state(),
+                            adjustment_ppm: pll.adjustment_ppm(),
+                        };
+                    }
+                    last_adjustment = Instant::now();
+                    fill_sum = 0.0;
+                    fill_count = 0;
+                }
+            }
+
+            let output = match (chunk, resampler.as_mut()) {
+                (Some(samples), Some(resampler)) => resampler.process(&samples)?,
+                (Some(samples), None) => samples,
+                (None, _) => silence.clone(),
+            };
+            sink.write(AudioBlock::new(&output, self.sample_rate, self.channels as u16)).await?;
+            frames_written += (output.len() / self.channels) as u64;
+        }
+
+        sink.close().await
+    }
+}
@@ -0,0 +1,118 @@
+//! Playback outputs for the node
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use stream_server::{AudioBlock, FileSink, LocalDacSink, OutputConfig, OutputSink, SinkStats};
+
+/// Where the node plays received audio
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputKind {
+    /// Local sound card, optionally by device name
+    Dac { device: Option<String> },
+    /// Discard audio (useful for testing and monitoring)
+    Null,
+    /// Record to a file, overwritten by each session (WAV, FLAC or W64 by extension)
+    File { path: PathBuf },
+}
+
+impl OutputKind {
+    /// Create a sink for this output
+    pub fn create_sink(&self) -> Box<dyn OutputSink> {
+        match self {
+            OutputKind::Dac { device } => Box::new(LocalDacSink::new(device.clone())),
+            OutputKind::Null => Box::new(NullSink::default()),
+            OutputKind::File { path } => Box::new(FileSink::new(path.clone())),
+        }
+    }
+
+    /// Short name shown in logs and the `dac` TXT field
+    pub fn describe(&self) -> String {
+        match self {
+            OutputKind::Dac { device: Some(device) } => device.clone(),
+            OutputKind::Dac { device: None } => "Default output".to_string(),
+            OutputKind::Null => "Null output".to_string(),
+            OutputKind::File { path } => format!("File {}", path.display()),
+        }
+    }
+}
+
+/// Sink that accepts and discards audio
+#[derive(Default)]
+pub struct NullSink {
+    config: Option<OutputConfig>,
+    frames_written: u64,
+}
+
+#[async_trait]
+impl OutputSink for NullSink {
+    fn name(&self) -> &'static str {
+        "null"
+    }
+
+    async fn open(&mut self, cfg: OutputConfig) -> Result<()> {
+        self.config = Some(cfg);
+        Ok(())
+    }
+
+    async fn write(&mut self, block: AudioBlock<'_>) -> Result<()> {
+        if self.config.is_none() {
+            return Err(anyhow!("Sink not open"));
+        }
+        self.frames_written += block.num_frames() as u64;
+        Ok(())
+    }
+
+    async fn drain(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.config = None;
+        Ok(())
+    }
+
+    fn latency_ms(&self) -> u32 {
+        0
+    }
+
+    fn is_open(&self) -> bool {
+        self.config.is_some()
+    }
+
+    fn stats(&self) -> SinkStats {
+        SinkStats {
+            frames_written: self.frames_written,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stream_server::SampleFormat;
+
+    #[tokio::test]
+    async fn test_file_output_patches_header() {
+        let path = std::env::temp_dir().join(format!("aaeq-node-{}.wav", std::process::id()));
+        let mut sink = OutputKind::File { path: path.clone() }.create_sink();
+        sink.open(OutputConfig {
+            format: SampleFormat::S16LE,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let frames = vec![0.25; 960];
+        sink.write(AudioBlock::new(&frames, 48000, 2)).await.unwrap();
+        assert_eq!(sink.stats().frames_written, 480);
+        sink.close().await.unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(bytes.len(), 44 + 1920);
+        assert_eq!(&bytes[40..44], &1920u32.to_le_bytes());
+        assert_eq!(&bytes[22..24], &2u16.to_le_bytes());
+    }
+}
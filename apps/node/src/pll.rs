@@ -0,0 +1,213 @@
+//! Micro-PLL clock drift correction (ANP §7)
+//!
+//! The node measures how far its jitter buffer drifts from the expected fill,
+//! smooths the measurement with an EMA and nudges the playback rate by a few
+//! ppm so that the DAC clock follows the server clock without audible steps.
+
+use stream_server::sinks::anp::{MicroPllConfig, PllState};
+
+/// Drift below this is treated as noise and leaves the adjustment unchanged
+const DEAD_ZONE_PPM: f64 = 1.0;
+/// SEEKING → LOCKED once |drift| stays below this for [`LOCK_HOLD_MS`]
+const LOCK_THRESHOLD_PPM: f64 = 5.0;
+const LOCK_HOLD_MS: u32 = 5_000;
+/// LOCKED → UNLOCKED once |drift| stays above this for [`UNLOCK_HOLD_MS`]
+const UNLOCK_THRESHOLD_PPM: f64 = 20.0;
+const UNLOCK_HOLD_MS: u32 = 2_000;
+
+/// Drift of an actual buffer fill from the expected one, in ppm (§7.2)
+pub fn measure_drift_ppm(actual_frames: f64, expected_frames: f64) -> f64 {
+    if expected_frames <= 0.0 {
+        return 0.0;
+    }
+    (actual_frames - expected_frames) / expected_frames * 1_000_000.0
+}
+
+/// Slew-limited playback rate controller with lock detection
+#[derive(Debug, Clone)]
+pub struct MicroPll {
+    config: MicroPllConfig,
+    alpha: f64,
+    smoothed_drift_ppm: Option<f64>,
+    adjustment_ppm: f64,
+    state: PllState,
+    /// How long the condition for the next state transition has held
+    condition_ms: u32,
+}
+
+impl MicroPll {
+    pub fn new(config: MicroPllConfig) -> Self {
+        Self {
+            alpha: 2.0 / (config.ema_window.max(1) as f64 + 1.0),
+            config,
+            smoothed_drift_ppm: None,
+            adjustment_ppm: 0.0,
+            state: PllState::Seeking,
+            condition_ms: 0,
+        }
+    }
+
+    /// Feed one drift measurement taken every `adjustment_interval_ms`
+    ///
+    /// Returns the playback ratio to apply: values above 1.0 consume input
+    /// faster (the buffer is filling up), values below 1.0 slow down.
+    pub fn update(&mut self, drift_ppm: f64) -> f64 {
+        if !self.config.enabled {
+            return 1.0;
+        }
+
+        let smoothed = match self.smoothed_drift_ppm {
+            Some(previous) => self.alpha * drift_ppm + (1.0 - self.alpha) * previous,
+            None => drift_ppm,
+        };
+        self.smoothed_drift_ppm = Some(smoothed);
+
+        if smoothed.abs() >= DEAD_ZONE_PPM {
+            let desired = smoothed.clamp(-self.config.ppm_limit, self.config.ppm_limit);
+            let max_delta =
+                self.config.slew_rate_ppm_per_sec * self.config.adjustment_interval_ms as f64 / 1000.0;
+            self.adjustment_ppm += (desired - self.adjustment_ppm).clamp(-max_delta, max_delta);
+        }
+
+        self.update_state(smoothed.abs());
+        self.ratio()
+    }
+
+    fn update_state(&mut self, drift: f64) {
+        let interval = self.config.adjustment_interval_ms;
+        match self.state {
+            PllState::Seeking => {
+                self.condition_ms = if drift < LOCK_THRESHOLD_PPM { self.condition_ms + interval } else { 0 };
+                if self.condition_ms >= LOCK_HOLD_MS {
+                    self.state = PllState::Locked;
+                    self.condition_ms = 0;
+                }
+            }
+            PllState::Locked => {
+                self.condition_ms = if drift > UNLOCK_THRESHOLD_PPM { self.condition_ms + interval } else { 0 };
+                if self.condition_ms >= UNLOCK_HOLD_MS {
+                    self.state = PllState::Unlocked;
+                    self.condition_ms = 0;
+                }
+            }
+            PllState::Unlocked => {
+                self.state = PllState::Seeking;
+                self.condition_ms = 0;
+            }
+        }
+    }
+
+    /// Playback ratio for the current adjustment
+    pub fn ratio(&self) -> f64 {
+        1.0 + self.adjustment_ppm / 1_000_000.0
+    }
+
+    /// EMA-smoothed drift in ppm
+    pub fn drift_ppm(&self) -> f64 {
+        self.smoothed_drift_ppm.unwrap_or(0.0)
+    }
+
+    pub fn adjustment_ppm(&self) -> f64 {
+        self.adjustment_ppm
+    }
+
+    pub fn state(&self) -> PllState {
+        self.state
+    }
+
+    pub fn config(&self) -> &MicroPllConfig {
+        &self.config
+    }
+
+    /// Forget the measurement history, e.g. after the buffer was flushed
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn updates_for(ms: u32) -> u32 {
+        ms / MicroPllConfig::default().adjustment_interval_ms
+    }
+
+    #[test]
+    fn test_measure_drift() {
+        assert_eq!(measure_drift_ppm(1_000_100.0, 1_000_000.0), 100.0);
+        assert_eq!(measure_drift_ppm(999_950.0, 1_000_000.0), -50.0);
+        assert_eq!(measure_drift_ppm(10.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_adjustment_is_slew_limited_and_clamped() {
+        let mut pll = MicroPll::new(MicroPllConfig::default());
+
+        // 10 ppm/s at 100 ms intervals moves at most 1 ppm per update
+        pll.update(1000.0);
+        assert!((pll.adjustment_ppm() - 1.0).abs() < 1e-9);
+
+        for _ in 0..updates_for(60_000) {
+            pll.update(1000.0);
+        }
+        assert_eq!(pll.adjustment_ppm(), 150.0);
+        assert!((pll.ratio() - 1.000_15).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_dead_zone_holds_adjustment() {
+        let mut pll = MicroPll::new(MicroPllConfig::default());
+        for _ in 0..10 {
+            pll.update(0.5);
+        }
+        assert_eq!(pll.adjustment_ppm(), 0.0);
+        assert_eq!(pll.ratio(), 1.0);
+    }
+
+    #[test]
+    fn test_negative_drift_slows_playback() {
+        let mut pll = MicroPll::new(MicroPllConfig::default());
+        for _ in 0..20 {
+            pll.update(-30.0);
+        }
+        assert!(pll.adjustment_ppm() < 0.0);
+        assert!(pll.ratio() < 1.0);
+    }
+
+    #[test]
+    fn test_lock_state_machine() {
+        let mut pll = MicroPll::new(MicroPllConfig::default());
+        assert_eq!(pll.state(), PllState::Seeking);
+
+        for _ in 0..updates_for(4_900) {
+            pll.update(2.0);
+        }
+        assert_eq!(pll.state(), PllState::Seeking);
+        pll.update(2.0);
+        assert_eq!(pll.state(), PllState::Locked);
+
+        // A short excursion doesn't unlock
+        for _ in 0..updates_for(1_000) {
+            pll.update(100.0);
+        }
+        assert_eq!(pll.state(), PllState::Locked);
+        for _ in 0..updates_for(1_000) {
+            pll.update(100.0);
+        }
+        assert_eq!(pll.state(), PllState::Unlocked);
+
+        pll.update(100.0);
+        assert_eq!(pll.state(), PllState::Seeking);
+    }
+
+    #[test]
+    fn test_disabled_pll_is_transparent() {
+        let mut pll = MicroPll::new(MicroPllConfig {
+            enabled: false,
+            ..Default::default()
+        });
+        assert_eq!(pll.update(500.0), 1.0);
+        assert_eq!(pll.adjustment_ppm(), 0.0);
+    }
+}
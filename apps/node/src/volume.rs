@@ -0,0 +1,183 @@
+//! Software volume with ramped changes (ANP §5)
+
+use stream_server::sinks::anp::{RampShape, VolumeCurve};
+
+#[derive(Debug, Clone, Copy)]
+struct Ramp {
+    from: f32,
+    to: f32,
+    shape: RampShape,
+    total_frames: u64,
+    elapsed_frames: u64,
+}
+
+impl Ramp {
+    /// Volume level after `elapsed` frames of the ramp (§5.3)
+    fn level_at(&self, elapsed: u64) -> f32 {
+        let progress = (elapsed as f32 / self.total_frames as f32).min(1.0);
+        let eased = match self.shape {
+            RampShape::Linear => progress,
+            RampShape::SCurve => 3.0 * progress.powi(2) - 2.0 * progress.powi(3),
+            // tau = ramp / 5, so the ramp is ~99% complete at its end
+            RampShape::Exponential if progress < 1.0 => 1.0 - (-5.0 * progress).exp(),
+            RampShape::Exponential => 1.0,
+        };
+        self.from + (self.to - self.from) * eased
+    }
+}
+
+/// Volume applied in the node's playout path
+#[derive(Debug, Clone)]
+pub struct SoftwareVolume {
+    sample_rate: u32,
+    curve: VolumeCurve,
+    level: f32,
+    mute: bool,
+    /// Level currently being played, which trails `level` during a ramp
+    current: f32,
+    ramp: Option<Ramp>,
+}
+
+impl SoftwareVolume {
+    pub fn new(sample_rate: u32, curve: VolumeCurve, level: f32, mute: bool) -> Self {
+        let level = level.clamp(0.0, 1.0);
+        Self {
+            sample_rate,
+            curve,
+            level,
+            mute,
+            current: level,
+            ramp: None,
+        }
+    }
+
+    /// Change the target level, fading over `ramp_ms` when non-zero
+    pub fn set(&mut self, level: f32, mute: bool, ramp_ms: u32, shape: RampShape) {
+        self.level = level.clamp(0.0, 1.0);
+        self.mute = mute;
+
+        let target = self.effective_level();
+        let total_frames = self.sample_rate as u64 * ramp_ms as u64 / 1000;
+        if total_frames == 0 {
+            self.current = target;
+            self.ramp = None;
+        } else {
+            self.ramp = Some(Ramp {
+                from: self.current,
+                to: target,
+                shape,
+                total_frames,
+                elapsed_frames: 0,
+            });
+        }
+    }
+
+    /// Scale interleaved samples in place, advancing any ramp in progress
+    pub fn apply(&mut self, samples: &mut [f64], channels: usize) {
+        let channels = channels.max(1);
+        match self.ramp.as_mut() {
+            Some(ramp) => {
+                for frame in samples.chunks_mut(channels) {
+                    let gain = level_to_gain(self.curve, ramp.level_at(ramp.elapsed_frames));
+                    frame.iter_mut().for_each(|s| *s *= gain);
+                    ramp.elapsed_frames += 1;
+                }
+                self.current = ramp.level_at(ramp.elapsed_frames);
+                if ramp.elapsed_frames >= ramp.total_frames {
+                    self.current = ramp.to;
+                    self.ramp = None;
+                }
+            }
+            None => {
+                let gain = level_to_gain(self.curve, self.current);
+                if gain != 1.0 {
+                    samples.iter_mut().for_each(|s| *s *= gain);
+                }
+            }
+        }
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn mute(&self) -> bool {
+        self.mute
+    }
+
+    pub fn curve(&self) -> VolumeCurve {
+        self.curve
+    }
+
+    pub fn ramp_complete(&self) -> bool {
+        self.ramp.is_none()
+    }
+
+    /// Gain of the target level in dB, `None` when muted or silent
+    pub fn gain_db(&self) -> Option<f32> {
+        Some(self.curve.gain_db(self.effective_level())).filter(|db| db.is_finite())
+    }
+
+    fn effective_level(&self) -> f32 {
+        if self.mute {
+            0.0
+        } else {
+            self.level
+        }
+    }
+}
+
+fn level_to_gain(curve: VolumeCurve, level: f32) -> f64 {
+    let db = curve.gain_db(level);
+    if db.is_finite() {
+        10f64.powf(db as f64 / 20.0)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unity_and_mute() {
+        let mut volume = SoftwareVolume::new(48000, VolumeCurve::Logarithmic, 1.0, false);
+        let mut samples = vec![0.5; 8];
+        volume.apply(&mut samples, 2);
+        assert_eq!(samples, vec![0.5; 8]);
+        assert_eq!(volume.gain_db(), Some(0.0));
+
+        volume.set(1.0, true, 0, RampShape::Linear);
+        volume.apply(&mut samples, 2);
+        assert_eq!(samples, vec![0.0; 8]);
+        assert_eq!(volume.gain_db(), None);
+    }
+
+    #[test]
+    fn test_logarithmic_half_level() {
+        let mut volume = SoftwareVolume::new(48000, VolumeCurve::Logarithmic, 0.5, false);
+        let mut samples = vec![1.0; 2];
+        volume.apply(&mut samples, 2);
+        // 40 * log10(0.5) ≈ -12 dB
+        assert!((samples[0] - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_ramp_reaches_target() {
+        let mut volume = SoftwareVolume::new(1000, VolumeCurve::Linear, 0.0, false);
+        volume.set(1.0, false, 100, RampShape::SCurve);
+        assert!(!volume.ramp_complete());
+
+        let mut samples = vec![1.0; 100];
+        volume.apply(&mut samples, 1);
+        assert_eq!(samples[0], 0.0);
+        assert!(samples[50] > 0.4 && samples[50] < 0.6);
+        assert!(samples.windows(2).all(|w| w[1] >= w[0]));
+        assert!(volume.ramp_complete());
+
+        let mut after = vec![1.0; 4];
+        volume.apply(&mut after, 1);
+        assert_eq!(after, vec![1.0; 4]);
+    }
+}
@@ -0,0 +1,189 @@
+//! End-to-end tests: the stream-server ANP sink streaming to a real node
+
+use aaeq_node::{Node, NodeConfig, OutputKind};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use stream_server::anp::{AnpNode, DspStatus, DspUpdate, FirFilterData, PllState};
+use stream_server::{AnpSink, AudioBlock, OutputConfig, OutputSink, SampleFormat};
+use tokio::task::JoinHandle;
+
+/// 10 ms of stereo audio at 48 kHz
+const FRAMES_PER_WRITE: usize = 480;
+
+fn output_config(format: SampleFormat) -> OutputConfig {
+    OutputConfig {
+        sample_rate: 48000,
+        channels: 2,
+        format,
+        buffer_ms: 100,
+        exclusive: false,
+    }
+}
+
+async fn start_node(output: OutputKind, micro_pll: bool) -> (SocketAddr, JoinHandle<anyhow::Result<()>>) {
+    let node = Node::bind(NodeConfig {
+        name: "Test Node".to_string(),
+        control_addr: "127.0.0.1:0".parse().unwrap(),
+        rtp_port: 0,
+        output,
+        micro_pll,
+        advertise: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let addr = node.control_addr();
+    (addr, tokio::spawn(node.run()))
+}
+
+/// Write `seconds` of a constant level through the sink in real time
+async fn stream(sink: &mut AnpSink, seconds: f64, level: f64) {
+    let block = vec![level; FRAMES_PER_WRITE * 2];
+    for _ in 0..(seconds * 100.0) as usize {
+        sink.write(AudioBlock::new(&block, 48000, 2)).await.unwrap();
+    }
+}
+
+fn temp_wav(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("aaeq-node-{}-{}.wav", name, std::process::id()))
+}
+
+#[tokio::test]
+async fn test_node_plays_stream_to_file() {
+    let path = temp_wav("play");
+    let (addr, node) = start_node(OutputKind::File { path: path.clone() }, false).await;
+
+    let mut sink = AnpSink::new(AnpNode::from_address("Test Node", addr));
+    sink.open(output_config(SampleFormat::S16LE)).await.unwrap();
+    assert_eq!(sink.session().unwrap().recommended_config.channels, 2);
+    assert!(!sink.session().unwrap().has_feature("micro_pll"));
+
+    stream(&mut sink, 1.0, 0.25).await;
+    sink.close().await.unwrap();
+
+    // The node closes the file once the control session ends
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(&bytes[..4], b"RIFF");
+    let samples: Vec<i16> = bytes[44..]
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    let expected = (0.25 * 32767.0) as i16;
+    let played = samples.iter().filter(|&&s| (s - expected).abs() <= 3).count();
+    assert!(played > 48000, "only {} samples of the stream were played", played);
+    assert!(samples.iter().all(|&s| s == 0 || (s - expected).abs() <= 3));
+
+    node.abort();
+}
+
+#[tokio::test]
+async fn test_health_telemetry_reaches_server() {
+    let (addr, node) = start_node(OutputKind::Null, true).await;
+
+    let mut sink = AnpSink::new(AnpNode::from_address("Test Node", addr));
+    sink.open(output_config(SampleFormat::S24LE)).await.unwrap();
+    assert!(sink.session().unwrap().has_feature("micro_pll"));
+
+    stream(&mut sink, 2.5, 0.1).await;
+
+    let health = sink.health().expect("node sends health every second");
+    assert!(health.connection.packets_received > 100);
+    assert_eq!(health.connection.packets_lost, 0);
+    assert!(health.integrity.crc_ok > 0);
+    assert_eq!(health.integrity.crc_fail, 0);
+    assert_eq!(health.playback.state, "playing");
+    assert!(health.playback.buffer_ms > 0.0);
+    assert!(health.clock_sync.adjustment_ppm.abs() <= 150.0);
+    assert_ne!(health.clock_sync.pll_state, PllState::Unlocked);
+    assert!(health.latency.pipeline_ms > 0.0);
+    assert!(sink.stats().buffer_fill > 0.0);
+
+    sink.close().await.unwrap();
+    node.abort();
+}
+
+#[tokio::test]
+async fn test_volume_roundtrip() {
+    let (addr, node) = start_node(OutputKind::Null, true).await;
+
+    let mut sink = AnpSink::new(AnpNode::from_address("Test Node", addr)).with_volume(0.8);
+    sink.open(output_config(SampleFormat::S24LE)).await.unwrap();
+    sink.set_volume(0.5, false, 50).unwrap();
+
+    let mut confirmed = None;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        confirmed = sink.node_volume();
+        if confirmed.is_some() {
+            break;
+        }
+    }
+    let result = confirmed.expect("node answers volume_set");
+    assert_eq!(result.level, 0.5);
+    assert!(!result.mute);
+    assert_eq!(result.hardware_control, Some(false));
+    assert!(result.gain_db.unwrap() < 0.0);
+
+    sink.close().await.unwrap();
+    node.abort();
+}
+
+#[tokio::test]
+async fn test_busy_node_rejects_second_server() {
+    let (addr, node) = start_node(OutputKind::Null, true).await;
+
+    let mut first = AnpSink::new(AnpNode::from_address("Test Node", addr));
+    first.open(output_config(SampleFormat::S24LE)).await.unwrap();
+
+    let mut second = AnpSink::new(AnpNode::from_address("Test Node", addr));
+    assert!(second.open(output_config(SampleFormat::S24LE)).await.is_err());
+
+    // Once the first server leaves, the node is available again
+    first.close().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    second.open(output_config(SampleFormat::S24LE)).await.unwrap();
+    second.close().await.unwrap();
+
+    node.abort();
+}
+
+#[tokio::test]
+async fn test_dsp_profile_is_applied_by_node() {
+    let (addr, node) = start_node(OutputKind::Null, false).await;
+
+    let mut sink = AnpSink::new(AnpNode::from_address("Test Node", addr));
+    let dsp = sink.remote_dsp();
+    dsp.add_filter("room", FirFilterData { sample_rate: 48000, taps: vec![0.5; 64] });
+
+    // Queued before the session exists, sent once it's negotiated
+    let preset = aaeq_core::EqPreset {
+        name: "Bass".to_string(),
+        bands: vec![aaeq_core::EqBand { frequency: 80, gain: 3.0 }],
+        curve_data: None,
+    };
+    let profile = DspUpdate::from_preset(42, "Bass - Test Node", &preset).with_convolution("room", 0, -6.0);
+    dsp.push(profile.clone()).unwrap();
+
+    sink.open(output_config(SampleFormat::S24LE)).await.unwrap();
+    assert!(dsp.is_available());
+    stream(&mut sink, 1.5, 0.1).await;
+
+    let ack = dsp.last_ack().expect("node acknowledges dsp_update");
+    assert_eq!(ack.profile_id, 42);
+    assert_eq!(ack.status, DspStatus::Success);
+    assert_eq!(ack.profile_hash, profile.profile_hash());
+    assert!(ack.applied.equalizer && ack.applied.convolution);
+    assert!(dsp.eq_offloaded());
+
+    let health = sink.health().unwrap();
+    assert_eq!(health.dsp.current_profile_hash, profile.profile_hash());
+    assert!(health.dsp.eq_active && health.dsp.convolution_active);
+
+    sink.close().await.unwrap();
+    assert!(!dsp.is_available());
+    node.abort();
+}
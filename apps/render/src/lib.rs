@@ -0,0 +1,14 @@
+//! Offline rendering through an AAEQ profile
+//!
+//! Decodes audio files, resolves each file's preset from its tags with the
+//! profile's mapping rules, runs the profile's DSP chain (EQ, enhancers,
+//! dynamics, resampler, dither) and writes the result to WAV or FLAC.
+//! With a fixed dither seed the output is bit-for-bit reproducible.
+
+mod profile;
+mod render;
+mod writer;
+
+pub use profile::RenderProfile;
+pub use render::{output_path, render_file, RenderOptions, RenderSummary};
+pub use writer::{OutputFormat, PcmWriter};
@@ -0,0 +1,180 @@
+use aaeq_core::TrackMeta;
+use aaeq_render::{output_path, render_file, OutputFormat, RenderOptions, RenderProfile};
+use anyhow::{bail, Result};
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+use stream_server::{load_playlist, read_track_meta};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Wav,
+    Flac,
+}
+
+/// AAEQ Render - process audio files offline through an AAEQ profile
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Audio files, directories or playlists (m3u/m3u8/pls)
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Directory to write rendered files to
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    /// Profile to render with (defaults to the active profile)
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Use this preset for every file instead of the profile's mapping rules
+    #[arg(long)]
+    preset: Option<String>,
+
+    /// AAEQ database (defaults to the desktop app's database)
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    /// Output container
+    #[arg(long, value_enum, default_value_t = Format::Flac)]
+    format: Format,
+
+    /// Output bit depth (16 or 24)
+    #[arg(long, default_value_t = 24, value_parser = parse_bits)]
+    bits: u8,
+
+    /// Output sample rate (defaults to the profile's resampler setting)
+    #[arg(long)]
+    rate: Option<u32>,
+
+    /// Don't dither, even if the profile enables it
+    #[arg(long)]
+    no_dither: bool,
+
+    /// Dither noise seed; renders with the same seed are bit-identical
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Replace existing output files
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Print the preset each file resolves to without rendering
+    #[arg(long)]
+    dry_run: bool,
+}
+
+fn parse_bits(value: &str) -> Result<u8, String> {
+    match value {
+        "16" => Ok(16),
+        "24" => Ok(24),
+        _ => Err("bit depth must be 16 or 24".to_string()),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
+        )
+        .init();
+
+    let profile = match args.db {
+        Some(path) => {
+            if !path.exists() {
+                bail!("Database {} does not exist", path.display());
+            }
+            RenderProfile::load(aaeq_persistence::init_db(&path).await?, args.profile.as_deref()).await?
+        }
+        None => {
+            let path = aaeq_persistence::default_db_path()?;
+            if path.exists() {
+                RenderProfile::load(aaeq_persistence::init_db(&path).await?, args.profile.as_deref()).await?
+            } else if let Some(name) = &args.profile {
+                bail!("Profile '{}' requested but no database found at {}", name, path.display());
+            } else {
+                tracing::warn!("No database at {}, rendering with default settings", path.display());
+                RenderProfile::defaults()
+            }
+        }
+    };
+    tracing::info!("Rendering with profile '{}'", profile.name);
+
+    let mut files = Vec::new();
+    for input in &args.inputs {
+        files.extend(load_playlist(input)?);
+    }
+    if files.is_empty() {
+        bail!("No audio files found");
+    }
+
+    let options = RenderOptions {
+        format: match args.format {
+            Format::Wav => OutputFormat::Wav,
+            Format::Flac => OutputFormat::Flac,
+        },
+        bits: args.bits,
+        rate: args.rate,
+        dither: !args.no_dither,
+        seed: args.seed,
+    };
+
+    if !args.dry_run {
+        std::fs::create_dir_all(&args.output_dir)?;
+    }
+
+    let mut failed = 0;
+    for file in &files {
+        let meta = read_track_meta(file).unwrap_or_else(|e| {
+            tracing::warn!("Failed to read tags from {}: {}", file.display(), e);
+            TrackMeta::default()
+        });
+        let preset_name = match &args.preset {
+            Some(name) => name.clone(),
+            None => profile.resolve_preset(&meta).await?,
+        };
+
+        if args.dry_run {
+            println!("{} -> {}", file.display(), preset_name);
+            continue;
+        }
+
+        let output = output_path(file, &args.output_dir, options.format);
+        if output.exists() && !args.overwrite {
+            tracing::warn!("Skipping {}: {} exists (use --overwrite)", file.display(), output.display());
+            continue;
+        }
+
+        let preset = profile.load_curve(&preset_name).await?;
+        match render_file(file, &output, &profile.settings, &preset, &options).await {
+            Ok(summary) => {
+                tracing::info!(
+                    "{} -> {} [{}] {} frames at {} Hz{}",
+                    file.display(),
+                    output.display(),
+                    preset_name,
+                    summary.frames,
+                    summary.sample_rate,
+                    if summary.clipped > 0 {
+                        format!(", {} clipped samples", summary.clipped)
+                    } else {
+                        String::new()
+                    }
+                );
+            }
+            Err(e) => {
+                tracing::error!("Failed to render {}: {:#}", file.display(), e);
+                let _ = std::fs::remove_file(&output);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} file(s) failed to render", failed, files.len());
+    }
+    Ok(())
+}
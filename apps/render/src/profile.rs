@@ -0,0 +1,53 @@
+//! Profile lookup: DSP settings, mapping rules and EQ curves from the database
+
+use aaeq_core::{resolve_preset, DspSettings, EqPreset, RulesIndex, TrackMeta};
+use aaeq_persistence::{load_preset_curve, GenreOverrideRepository, LoadedProfile, FALLBACK_PRESET};
+use anyhow::Result;
+use sqlx::SqlitePool;
+
+/// Everything needed to render with one listening profile
+pub struct RenderProfile {
+    pub name: String,
+    pub settings: DspSettings,
+    pub rules: RulesIndex,
+    pool: Option<SqlitePool>,
+}
+
+impl RenderProfile {
+    /// Default DSP settings and no mapping rules, for use without a database
+    pub fn defaults() -> Self {
+        Self {
+            name: "Default".to_string(),
+            settings: DspSettings::default(),
+            rules: RulesIndex::default(),
+            pool: None,
+        }
+    }
+
+    /// Load a profile by name, or the desktop app's active profile
+    pub async fn load(pool: SqlitePool, profile_name: Option<&str>) -> Result<Self> {
+        let profile = LoadedProfile::load(&pool, profile_name).await?;
+        Ok(Self {
+            name: profile.name,
+            settings: profile.settings,
+            rules: profile.rules,
+            pool: Some(pool),
+        })
+    }
+
+    /// Resolve a track's preset name, applying any stored genre override first
+    pub async fn resolve_preset(&self, meta: &TrackMeta) -> Result<String> {
+        let mut meta = meta.clone();
+        if let Some(pool) = &self.pool {
+            if let Some(genre) = GenreOverrideRepository::new(pool.clone()).get(&meta.song_key()).await? {
+                meta.genre = genre;
+            }
+        }
+        Ok(resolve_preset(&meta, &self.rules, FALLBACK_PRESET))
+    }
+
+    /// Look up a preset's EQ curve: built-in, then custom, then generated
+    pub async fn load_curve(&self, preset_name: &str) -> Result<EqPreset> {
+        load_preset_curve(self.pool.as_ref(), preset_name).await
+    }
+}
@@ -0,0 +1,115 @@
+//! Render one file through a profile's DSP chain
+
+use crate::writer::{OutputFormat, PcmWriter};
+use aaeq_core::{DspSettings, EqPreset};
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use stream_server::{read_track_info, FileInput, OutputConfig, ProfileChain, SampleFormat};
+use tokio::sync::mpsc;
+
+/// Output options that override the profile's settings
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub format: OutputFormat,
+    /// Output bit depth (16 or 24)
+    pub bits: u8,
+    /// Output sample rate; `None` keeps the profile's resampler setting
+    pub rate: Option<u32>,
+    /// Dither to the output bit depth if the profile enables dither
+    pub dither: bool,
+    /// Dither noise seed; the same seed gives bit-identical output
+    pub seed: u64,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Flac,
+            bits: 24,
+            rate: None,
+            dither: true,
+            seed: 0,
+        }
+    }
+}
+
+/// Result of rendering one file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderSummary {
+    pub frames: u64,
+    pub sample_rate: u32,
+    /// Samples that clipped in the headroom stage
+    pub clipped: u64,
+}
+
+/// Output file for `input`: `<dir>/<input stem>.<format extension>`
+pub fn output_path(input: &Path, dir: &Path, format: OutputFormat) -> PathBuf {
+    let stem = input.file_stem().unwrap_or(input.as_os_str());
+    dir.join(stem).with_extension(format.extension())
+}
+
+/// Decode `input`, process it with `settings` and `preset`, and write `output`
+pub async fn render_file(
+    input: &Path,
+    output: &Path,
+    settings: &DspSettings,
+    preset: &EqPreset,
+    options: &RenderOptions,
+) -> Result<RenderSummary> {
+    let info = read_track_info(input)?;
+
+    let mut settings = settings.clone();
+    if let Some(rate) = options.rate {
+        settings.resample_enabled = true;
+        settings.target_sample_rate = rate;
+    }
+    settings.target_bits = options.bits;
+    settings.dither_enabled &= options.dither;
+
+    let mut chain = ProfileChain::new(&settings, info.sample_rate, 2)?.with_seed(options.seed);
+    chain.set_preset(preset);
+    let sample_rate = chain.output_rate().unwrap_or(info.sample_rate);
+
+    // Fail before rendering when the file is known to be too long for the format
+    if let Some(frames) = info.frames {
+        let output_frames = (frames as u128 * sample_rate as u128).div_ceil(info.sample_rate as u128) as u64;
+        options
+            .format
+            .check_length(output_frames, 2, options.bits)
+            .map_err(|e| anyhow!("Can't render {}: {}", input.display(), e))?;
+    }
+
+    let mut writer = PcmWriter::create(output, options.format, sample_rate, 2, options.bits)?;
+
+    // Decode at the file's own rate; any rate change happens in the chain
+    let cfg = OutputConfig {
+        sample_rate: info.sample_rate,
+        channels: 2,
+        format: SampleFormat::F64,
+        buffer_ms: 0,
+        exclusive: false,
+    };
+    let (tx, mut rx) = mpsc::channel(16);
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+    let _stop = FileInput::new(&[input])?
+        .with_realtime(false)
+        .start_playback(cfg, tx, events_tx)?;
+
+    while let Some(block) = rx.recv().await {
+        writer.write(&chain.process(&block)?)?;
+    }
+    writer.write(&chain.finish()?)?;
+
+    while let Ok(event) = events_rx.try_recv() {
+        if let stream_server::FileInputEvent::TrackFailed { error, .. } = event {
+            return Err(anyhow!("Failed to decode {}: {}", input.display(), error));
+        }
+    }
+
+    let frames = writer.finish()?;
+    Ok(RenderSummary {
+        frames,
+        sample_rate,
+        clipped: chain.clip_count(),
+    })
+}
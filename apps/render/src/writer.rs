@@ -0,0 +1,192 @@
+//! WAV and FLAC file output
+
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use stream_server::dlna::{create_wav_header, FlacEncoder};
+use stream_server::{OutputConfig, SampleFormat};
+
+/// Most audio a WAV file can hold: its sizes are 32-bit, and the RIFF size
+/// includes 36 bytes of header
+const MAX_WAV_DATA_BYTES: u64 = u32::MAX as u64 - 36;
+
+/// Rendered file container
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Wav,
+    Flac,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "wav",
+            OutputFormat::Flac => "flac",
+        }
+    }
+
+    /// Fail if `frames` of audio won't fit in this format
+    pub fn check_length(&self, frames: u64, channels: u16, bits: u8) -> Result<()> {
+        let bytes = frames.saturating_mul(channels as u64 * bits as u64 / 8);
+        if *self == OutputFormat::Wav && bytes > MAX_WAV_DATA_BYTES {
+            bail!(
+                "{:.1} GB of audio is over the WAV limit of 4 GB, use FLAC (--format flac)",
+                bytes as f64 / 1e9
+            );
+        }
+        Ok(())
+    }
+}
+
+enum Encoder {
+    Wav,
+    Flac(FlacEncoder),
+}
+
+/// Writes interleaved samples as 16/24-bit integer PCM
+///
+/// Samples are rounded to the nearest level without dither; the DSP chain
+/// dithers beforehand when the profile asks for it.
+pub struct PcmWriter {
+    file: BufWriter<File>,
+    encoder: Encoder,
+    sample_rate: u32,
+    channels: u16,
+    bits: u8,
+    frames: u64,
+    data_bytes: u64,
+    scratch: Vec<i32>,
+}
+
+impl PcmWriter {
+    pub fn create(path: &Path, format: OutputFormat, sample_rate: u32, channels: u16, bits: u8) -> Result<Self> {
+        if !matches!(bits, 16 | 24) {
+            bail!("Unsupported bit depth {} (use 16 or 24)", bits);
+        }
+
+        let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let encoder = match format {
+            OutputFormat::Wav => Encoder::Wav,
+            OutputFormat::Flac => Encoder::Flac(FlacEncoder::new(sample_rate, channels, bits)),
+        };
+        let mut writer = Self {
+            file: BufWriter::new(file),
+            encoder,
+            sample_rate,
+            channels,
+            bits,
+            frames: 0,
+            data_bytes: 0,
+            scratch: Vec::new(),
+        };
+        let header = writer.header();
+        writer.file.write_all(&header)?;
+        Ok(writer)
+    }
+
+    /// Stream header; sizes are patched in by [`PcmWriter::finish`]
+    fn header(&self) -> Vec<u8> {
+        match &self.encoder {
+            Encoder::Wav => {
+                let format = if self.bits == 16 { SampleFormat::S16LE } else { SampleFormat::S24LE };
+                let config = OutputConfig {
+                    sample_rate: self.sample_rate,
+                    channels: self.channels,
+                    format,
+                    ..Default::default()
+                };
+                create_wav_header(&config, self.data_bytes as u32)
+            }
+            Encoder::Flac(encoder) => encoder.header_with_length(self.frames),
+        }
+    }
+
+    pub fn write(&mut self, samples: &[f64]) -> Result<()> {
+        let scale = (1i64 << (self.bits - 1)) as f64;
+        let max = scale - 1.0;
+        self.scratch.clear();
+        self.scratch
+            .extend(samples.iter().map(|&s| (s * scale).round().clamp(-scale, max) as i32));
+        self.frames += (samples.len() / self.channels as usize) as u64;
+
+        match &mut self.encoder {
+            Encoder::Wav => {
+                let bytes = self.bits as usize / 8;
+                if self.data_bytes + (self.scratch.len() * bytes) as u64 > MAX_WAV_DATA_BYTES {
+                    bail!("WAV output exceeds 4 GB, use FLAC (--format flac)");
+                }
+                for sample in &self.scratch {
+                    self.file.write_all(&sample.to_le_bytes()[..bytes])?;
+                }
+                self.data_bytes += (self.scratch.len() * bytes) as u64;
+            }
+            Encoder::Flac(encoder) => {
+                let frames = encoder.encode(&self.scratch);
+                self.file.write_all(&frames)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush buffered audio and patch the header; returns the frames written
+    pub fn finish(mut self) -> Result<u64> {
+        if let Encoder::Flac(encoder) = &mut self.encoder {
+            let tail = encoder.flush();
+            self.file.write_all(&tail)?;
+        }
+
+        let header = self.header();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.flush()?;
+        Ok(self.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_quantization_and_header() {
+        let path = std::env::temp_dir().join(format!("aaeq-render-writer-{}.wav", std::process::id()));
+        let mut writer = PcmWriter::create(&path, OutputFormat::Wav, 44100, 2, 16).unwrap();
+        writer.write(&[0.5, -0.5, 1.5, -1.5]).unwrap();
+        assert_eq!(writer.finish().unwrap(), 2);
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[4..8], &44u32.to_le_bytes());
+        assert_eq!(&bytes[34..36], &16u16.to_le_bytes());
+        assert_eq!(&bytes[40..44], &8u32.to_le_bytes());
+        let samples: Vec<i16> = bytes[44..]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, vec![16384, -16384, 32767, -32768]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_wav_size_limit() {
+        // Just under 4 GB of 24-bit stereo, and a frame more
+        let frames = MAX_WAV_DATA_BYTES / 6;
+        assert!(OutputFormat::Wav.check_length(frames, 2, 24).is_ok());
+        let error = OutputFormat::Wav.check_length(frames + 1, 2, 24).unwrap_err();
+        assert!(error.to_string().contains("use FLAC"), "{}", error);
+        assert!(OutputFormat::Flac.check_length(frames + 1, 2, 24).is_ok());
+
+        // Writing stops before the data outgrows the header's sizes
+        let path = std::env::temp_dir().join(format!("aaeq-render-limit-{}.wav", std::process::id()));
+        let mut writer = PcmWriter::create(&path, OutputFormat::Wav, 44100, 2, 16).unwrap();
+        writer.data_bytes = MAX_WAV_DATA_BYTES - 4;
+        writer.write(&[0.5, -0.5]).unwrap();
+        assert!(writer.write(&[0.5, -0.5]).is_err());
+        drop(writer);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 44 + 4);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,169 @@
+//! Render tests: process generated WAV files and check the written output.
+
+use aaeq_core::{DspSettings, EqPreset, Mapping, Scope, TrackMeta};
+use aaeq_persistence::{init_db, MappingRepository};
+use aaeq_render::*;
+use std::path::{Path, PathBuf};
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aaeq-render-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Interleaved stereo 16-bit ramp
+fn ramp(frames: usize) -> Vec<i16> {
+    (0..frames * 2).map(|i| ((i % 2000) as i16 - 1000) * 16).collect()
+}
+
+/// Write a 16-bit stereo WAV
+fn write_wav(path: &Path, sample_rate: u32, samples: &[i16]) {
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let mut wav = b"RIFF".to_vec();
+    wav.extend_from_slice(&((36 + data.len()) as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 4).to_le_bytes());
+    wav.extend_from_slice(&4u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&data);
+    std::fs::write(path, wav).unwrap();
+}
+
+/// Samples of a 16-bit WAV written by `PcmWriter`
+fn read_wav_16(path: &Path) -> Vec<i16> {
+    std::fs::read(path).unwrap()[44..]
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect()
+}
+
+/// Settings that leave the audio untouched
+fn transparent() -> DspSettings {
+    DspSettings {
+        headroom_db: 0.0,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_transparent_render_is_bit_exact() {
+    let dir = test_dir("exact");
+    let input = dir.join("in.wav");
+    let samples = ramp(5000);
+    write_wav(&input, 44100, &samples);
+
+    let options = RenderOptions {
+        format: OutputFormat::Wav,
+        bits: 16,
+        ..Default::default()
+    };
+    let output = output_path(&input, &dir.join("out"), OutputFormat::Wav);
+    std::fs::create_dir_all(output.parent().unwrap()).unwrap();
+    let summary = render_file(&input, &output, &transparent(), &EqPreset::default(), &options)
+        .await
+        .unwrap();
+
+    assert_eq!(summary, RenderSummary { frames: 5000, sample_rate: 44100, clipped: 0 });
+    assert_eq!(read_wav_16(&output), samples);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_flac_output_with_resampling() {
+    let dir = test_dir("flac");
+    let input = dir.join("in.wav");
+    write_wav(&input, 44100, &ramp(44100));
+
+    let options = RenderOptions {
+        rate: Some(48000),
+        ..Default::default()
+    };
+    let output = dir.join("out.flac");
+    let summary = render_file(&input, &output, &transparent(), &EqPreset::default(), &options)
+        .await
+        .unwrap();
+    assert_eq!(summary.frames, 48000);
+    assert_eq!(summary.sample_rate, 48000);
+
+    // The header carries the final length
+    let info = stream_server::read_track_info(&output).unwrap();
+    assert_eq!(info.sample_rate, 48000);
+    assert_eq!(info.frames, Some(48000));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_seeded_renders_are_identical() {
+    let dir = test_dir("seed");
+    let input = dir.join("in.wav");
+    write_wav(&input, 48000, &ramp(10000));
+
+    let settings = DspSettings {
+        dither_enabled: true,
+        tube_warmth_enabled: true,
+        ..Default::default()
+    };
+    let preset = aaeq_core::preset_library::get_preset_curve("Rock").unwrap();
+    let render = |name: &str, seed: u64| {
+        let output = dir.join(name);
+        let input = input.clone();
+        let settings = settings.clone();
+        let preset = preset.clone();
+        async move {
+            let options = RenderOptions {
+                format: OutputFormat::Wav,
+                bits: 16,
+                seed,
+                ..Default::default()
+            };
+            render_file(&input, &output, &settings, &preset, &options).await.unwrap();
+            std::fs::read(&output).unwrap()
+        }
+    };
+
+    let first = render("a.wav", 7).await;
+    assert_eq!(first, render("b.wav", 7).await);
+    assert_ne!(first, render("c.wav", 8).await);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_profile_rules_resolve_presets() {
+    let dir = test_dir("rules");
+    let pool = init_db(&dir.join("aaeq.db")).await.unwrap();
+    MappingRepository::new(pool.clone())
+        .create(&Mapping {
+            id: None,
+            scope: Scope::Genre,
+            key_normalized: Some("jazz".to_string()),
+            preset_name: "Jazz".to_string(),
+            profile_id: 1,
+            created_at: 0,
+            updated_at: 0,
+        })
+        .await
+        .unwrap();
+
+    let profile = RenderProfile::load(pool, None).await.unwrap();
+    assert_eq!(profile.name, "Default");
+    assert_eq!(profile.settings.headroom_db, -3.0);
+
+    let jazz = TrackMeta {
+        genre: "Jazz".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(profile.resolve_preset(&jazz).await.unwrap(), "Jazz");
+    assert_eq!(profile.resolve_preset(&TrackMeta::default()).await.unwrap(), "Flat");
+    assert_eq!(profile.load_curve("Jazz").await.unwrap().name, "Jazz");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -1,10 +1,11 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 /// DSP configuration settings for a profile
 ///
 /// Stores audio processing parameters like sample rate, buffer size,
 /// headroom control, dithering, and resampling settings. Each profile can have its own DSP configuration.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct DspSettings {
     pub id: Option<i64>,
     pub profile_id: i64,
@@ -2,6 +2,7 @@ pub mod models;
 pub mod traits;
 pub mod resolver;
 pub mod dsp_settings;
+pub mod preset_library;
 
 pub use models::*;
 pub use traits::*;
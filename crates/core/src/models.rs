@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
+use utoipa::ToSchema;
 
 /// Track metadata extracted from the device
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -44,7 +45,7 @@ pub fn normalize_key(input: &str) -> String {
 }
 
 /// Scope of a mapping rule (precedence: Song > Album > Genre > Default)
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Scope {
     Song,
@@ -91,7 +92,7 @@ impl FromStr for Scope {
 }
 
 /// A mapping rule that associates a key with a preset (scoped by profile)
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct Mapping {
     pub id: Option<i64>,
     pub scope: Scope,
@@ -103,7 +104,7 @@ pub struct Mapping {
 }
 
 /// A listening profile (e.g., "Default", "Headphones", "Car")
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct Profile {
     pub id: Option<i64>,
     pub name: String,
@@ -127,14 +128,14 @@ fn default_profile_color() -> String {
 }
 
 /// EQ band configuration for creating/editing presets
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct EqBand {
     pub frequency: u32,  // Hz
     pub gain: f32,       // dB, typically -12.0 to +12.0
 }
 
 /// Bezier curve control points for graphical EQ editing
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct BezierCurveData {
     /// Control points in normalized space (x: 0-1 log freq, y: -12 to +12 dB)
     pub control_points: Vec<(f32, f32)>,
@@ -144,7 +145,7 @@ pub struct BezierCurveData {
 }
 
 /// A complete EQ preset with all bands
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct EqPreset {
     pub name: String,
     pub bands: Vec<EqBand>,
@@ -201,7 +202,7 @@ pub struct DspSinkSettings {
 
 /// Managed device for manual entry and favorites (per profile)
 /// Supports WiiM API, DLNA, AirPlay, Local DAC, and future ANP protocol
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ManagedDevice {
     pub id: Option<i64>,
     pub profile_id: i64,
@@ -215,6 +216,84 @@ pub struct ManagedDevice {
     pub updated_at: i64,
 }
 
+/// What an API token may do (Control includes Read)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// Only read state (GET requests and the event stream)
+    Read,
+    /// Also change outputs, presets and settings
+    Control,
+}
+
+impl TokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Control => "control",
+        }
+    }
+
+    /// Whether a token with this scope may do what `required` allows
+    pub fn allows(&self, required: TokenScope) -> bool {
+        match required {
+            TokenScope::Read => true,
+            TokenScope::Control => *self == TokenScope::Control,
+        }
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = ParseScopeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "read" => Ok(TokenScope::Read),
+            "control" => Ok(TokenScope::Control),
+            _ => Err(ParseScopeError),
+        }
+    }
+}
+
+/// A bearer token for the Control API (only its hash is stored)
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ApiToken {
+    pub id: Option<i64>,
+    pub name: String,
+    pub scope: TokenScope,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+/// Connection settings for the MQTT bridge (Home Assistant integration)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MqttSettings {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prefix of AAEQ's own state and command topics
+    pub base_topic: String,
+    /// Where Home Assistant looks for discovery configs
+    pub discovery_prefix: String,
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            base_topic: "aaeq".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+}
+
 impl DspSinkSettings {
     /// Create default settings for LocalDac
     pub fn default_local_dac() -> Self {
@@ -284,4 +363,14 @@ mod tests {
         assert_eq!(track.album_key(), "pink floyd - the dark side of the moon");
         assert_eq!(track.genre_key(), "progressive rock");
     }
+
+    #[test]
+    fn test_token_scope() {
+        assert!(TokenScope::Control.allows(TokenScope::Read));
+        assert!(TokenScope::Control.allows(TokenScope::Control));
+        assert!(TokenScope::Read.allows(TokenScope::Read));
+        assert!(!TokenScope::Read.allows(TokenScope::Control));
+        assert_eq!("Control".parse::<TokenScope>(), Ok(TokenScope::Control));
+        assert!("admin".parse::<TokenScope>().is_err());
+    }
 }
@@ -1,4 +1,5 @@
 use crate::models::{Mapping, Scope, TrackMeta};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 /// Index of mapping rules for fast lookup
@@ -42,34 +43,48 @@ impl RulesIndex {
     }
 }
 
+/// A resolved preset and the rule that chose it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resolution {
+    pub preset: String,
+    /// Scope of the winning rule; `Default` for the default rule or the fallback
+    pub scope: Scope,
+    /// Normalized key of the winning rule (`None` for the default rule or the fallback)
+    pub key: Option<String>,
+}
+
 /// Resolve the appropriate preset for a given track using the hierarchy:
 /// Song > Album > Genre > Default
 pub fn resolve_preset(meta: &TrackMeta, rules: &RulesIndex, fallback: &str) -> String {
-    // 1. Check song-specific rule
-    let song_key = meta.song_key();
-    if let Some(preset) = rules.song_rules.get(&song_key) {
-        tracing::debug!("Matched song rule: {} -> {}", song_key, preset);
-        return preset.clone();
-    }
-
-    // 2. Check album-specific rule
-    let album_key = meta.album_key();
-    if let Some(preset) = rules.album_rules.get(&album_key) {
-        tracing::debug!("Matched album rule: {} -> {}", album_key, preset);
-        return preset.clone();
-    }
+    resolve_preset_with_rule(meta, rules, fallback).preset
+}
 
-    // 3. Check genre-specific rule
-    let genre_key = meta.genre_key();
-    if let Some(preset) = rules.genre_rules.get(&genre_key) {
-        tracing::debug!("Matched genre rule: {} -> {}", genre_key, preset);
-        return preset.clone();
+/// Like [`resolve_preset`], also reporting which rule won
+pub fn resolve_preset_with_rule(meta: &TrackMeta, rules: &RulesIndex, fallback: &str) -> Resolution {
+    let keyed = [
+        (Scope::Song, meta.song_key(), &rules.song_rules),
+        (Scope::Album, meta.album_key(), &rules.album_rules),
+        (Scope::Genre, meta.genre_key(), &rules.genre_rules),
+    ];
+    for (scope, key, scope_rules) in keyed {
+        if let Some(preset) = scope_rules.get(&key) {
+            tracing::debug!("Matched {} rule: {} -> {}", scope.as_str(), key, preset);
+            return Resolution {
+                preset: preset.clone(),
+                scope,
+                key: Some(key),
+            };
+        }
     }
 
-    // 4. Use default from rules or fallback
+    // Use default from rules or fallback
     let default = rules.default_preset.as_deref().unwrap_or(fallback);
     tracing::debug!("Using default preset: {}", default);
-    default.to_string()
+    Resolution {
+        preset: default.to_string(),
+        scope: Scope::Default,
+        key: None,
+    }
 }
 
 #[cfg(test)]
@@ -163,4 +178,27 @@ mod tests {
         let result = resolve_preset(&track, &rules, "Fallback");
         assert_eq!(result, "Fallback");
     }
+
+    #[test]
+    fn test_resolve_preset_with_rule() {
+        let track = TrackMeta {
+            artist: "Miles Davis".to_string(),
+            title: "So What".to_string(),
+            album: "Kind of Blue".to_string(),
+            genre: "Jazz".to_string(),
+            ..Default::default()
+        };
+
+        let mut rules = RulesIndex::default();
+        rules.genre_rules.insert("jazz".to_string(), "Jazz".to_string());
+        let resolution = resolve_preset_with_rule(&track, &rules, "Fallback");
+        assert_eq!(resolution.preset, "Jazz");
+        assert_eq!(resolution.scope, Scope::Genre);
+        assert_eq!(resolution.key.as_deref(), Some("jazz"));
+
+        let resolution = resolve_preset_with_rule(&TrackMeta::default(), &rules, "Fallback");
+        assert_eq!(resolution.preset, "Fallback");
+        assert_eq!(resolution.scope, Scope::Default);
+        assert_eq!(resolution.key, None);
+    }
 }
@@ -2,9 +2,31 @@ use anyhow::Result;
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
 use sqlx::migrate::MigrateDatabase;
 use sqlx::Row;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+/// Get the database path used by the desktop app (platform-specific)
+pub fn default_db_path() -> Result<PathBuf> {
+    let config_dir = if cfg!(target_os = "windows") {
+        dirs::config_dir()
+            .ok_or_else(|| anyhow::anyhow!("Failed to get config directory"))?
+            .join("AAEQ")
+    } else if cfg!(target_os = "macos") {
+        dirs::home_dir()
+            .ok_or_else(|| anyhow::anyhow!("Failed to get home directory"))?
+            .join("Library")
+            .join("Application Support")
+            .join("AAEQ")
+    } else {
+        // Linux
+        dirs::config_dir()
+            .ok_or_else(|| anyhow::anyhow!("Failed to get config directory"))?
+            .join("aaeq")
+    };
+
+    Ok(config_dir.join("aaeq.db"))
+}
+
 /// Initialize database connection and run migrations
 pub async fn init_db(db_path: &Path) -> Result<SqlitePool> {
     let db_url = format!("sqlite://{}", db_path.display());
@@ -626,6 +648,74 @@ async fn run_migrations(pool: &SqlitePool) -> Result<()> {
         tracing::info!("Added 11 DSP enhancer columns to dsp_profile_settings table");
     }
 
+    // Migration 018: Add api_tokens table for Control API authentication
+    let api_tokens_table_exists = sqlx::query(
+        "SELECT COUNT(*) as count FROM sqlite_master WHERE type='table' AND name='api_tokens'"
+    )
+    .fetch_one(pool)
+    .await?
+    .get::<i32, _>("count") > 0;
+
+    if !api_tokens_table_exists {
+        tracing::info!("Creating api_tokens table for Control API authentication");
+
+        sqlx::query(r#"
+            CREATE TABLE api_tokens (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                token_hash TEXT NOT NULL UNIQUE,
+                scope TEXT NOT NULL CHECK(scope IN ('read', 'control')),
+                created_at INTEGER NOT NULL,
+                last_used_at INTEGER,
+                revoked_at INTEGER
+            )
+        "#)
+        .execute(pool)
+        .await?;
+
+        tracing::info!("Created api_tokens table");
+    }
+
+    // Migration 019: Add MQTT bridge settings to app_settings
+    let mqtt_columns_exist = sqlx::query(
+        "SELECT COUNT(*) as count FROM pragma_table_info('app_settings') WHERE name = 'mqtt_enabled'"
+    )
+    .fetch_one(pool)
+    .await?
+    .get::<i32, _>("count") > 0;
+
+    if !mqtt_columns_exist {
+        sqlx::query("ALTER TABLE app_settings ADD COLUMN mqtt_enabled INTEGER NOT NULL DEFAULT 0")
+            .execute(pool)
+            .await?;
+
+        sqlx::query("ALTER TABLE app_settings ADD COLUMN mqtt_host TEXT")
+            .execute(pool)
+            .await?;
+
+        sqlx::query("ALTER TABLE app_settings ADD COLUMN mqtt_port INTEGER")
+            .execute(pool)
+            .await?;
+
+        sqlx::query("ALTER TABLE app_settings ADD COLUMN mqtt_username TEXT")
+            .execute(pool)
+            .await?;
+
+        sqlx::query("ALTER TABLE app_settings ADD COLUMN mqtt_password TEXT")
+            .execute(pool)
+            .await?;
+
+        sqlx::query("ALTER TABLE app_settings ADD COLUMN mqtt_base_topic TEXT")
+            .execute(pool)
+            .await?;
+
+        sqlx::query("ALTER TABLE app_settings ADD COLUMN mqtt_discovery_prefix TEXT")
+            .execute(pool)
+            .await?;
+
+        tracing::info!("Added MQTT columns to app_settings");
+    }
+
     tracing::info!("Database migrations completed");
     Ok(())
 }
@@ -1,5 +1,7 @@
 pub mod db;
+pub mod profile;
 pub mod repository_simple;
 
 pub use db::*;
+pub use profile::*;
 pub use repository_simple::*;
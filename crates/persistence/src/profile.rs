@@ -0,0 +1,96 @@
+//! Profile and preset curve lookup shared by the headless apps
+
+use crate::{AppSettingsRepository, CustomEqPresetRepository, DspSettingsRepository, MappingRepository, ProfileRepository};
+use aaeq_core::preset_library::{generate_default_curve, get_known_preset_curve};
+use aaeq_core::{DspSettings, EqPreset, RulesIndex};
+use anyhow::{anyhow, Result};
+use sqlx::SqlitePool;
+
+/// The built-in profile every database has
+pub const DEFAULT_PROFILE_ID: i64 = 1;
+
+/// Preset used when no rule matches
+pub const FALLBACK_PRESET: &str = "Flat";
+
+/// A profile with its DSP settings and mapping rules
+pub struct LoadedProfile {
+    pub id: i64,
+    pub name: String,
+    pub settings: DspSettings,
+    pub rules: RulesIndex,
+}
+
+impl LoadedProfile {
+    /// Load a profile by name, or the desktop app's active profile
+    pub async fn load(pool: &SqlitePool, profile_name: Option<&str>) -> Result<Self> {
+        let profile_repo = ProfileRepository::new(pool.clone());
+        let profile = match profile_name {
+            Some(name) => profile_repo
+                .get_by_name(name)
+                .await?
+                .ok_or_else(|| anyhow!("Profile '{}' not found", name))?,
+            None => {
+                let active_id = active_profile_id(pool).await?;
+                profile_repo
+                    .get_by_id(active_id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Active profile {} not found", active_id))?
+            }
+        };
+        Self::from_record(pool, profile).await
+    }
+
+    /// Load a profile by id
+    pub async fn load_by_id(pool: &SqlitePool, id: i64) -> Result<Self> {
+        let profile = ProfileRepository::new(pool.clone())
+            .get_by_id(id)
+            .await?
+            .ok_or_else(|| anyhow!("Profile {} not found", id))?;
+        Self::from_record(pool, profile).await
+    }
+
+    async fn from_record(pool: &SqlitePool, profile: aaeq_core::Profile) -> Result<Self> {
+        let id = profile.id.ok_or_else(|| anyhow!("Profile '{}' has no id", profile.name))?;
+
+        let settings = DspSettingsRepository::new(pool.clone())
+            .get_by_profile(id)
+            .await?
+            .unwrap_or_else(|| DspSettings::new_for_profile(id));
+
+        Ok(Self {
+            id,
+            name: profile.name,
+            settings,
+            rules: load_rules(pool, id).await?,
+        })
+    }
+}
+
+/// The desktop app's active profile, or the built-in one if none is set
+pub async fn active_profile_id(pool: &SqlitePool) -> Result<i64> {
+    Ok(AppSettingsRepository::new(pool.clone())
+        .get_active_profile_id()
+        .await?
+        .unwrap_or(DEFAULT_PROFILE_ID))
+}
+
+/// A profile's mapping rules
+pub async fn load_rules(pool: &SqlitePool, profile_id: i64) -> Result<RulesIndex> {
+    let mappings = MappingRepository::new(pool.clone()).list_by_profile(profile_id).await?;
+    Ok(RulesIndex::from_mappings(mappings))
+}
+
+/// Look up a preset's EQ curve: built-in, then custom (if there is a database), then generated
+pub async fn load_preset_curve(pool: Option<&SqlitePool>, preset_name: &str) -> Result<EqPreset> {
+    if let Some(preset) = get_known_preset_curve(preset_name) {
+        return Ok(preset);
+    }
+
+    if let Some(pool) = pool {
+        if let Some(preset) = CustomEqPresetRepository::new(pool.clone()).get_by_name(preset_name).await? {
+            return Ok(preset);
+        }
+    }
+
+    generate_default_curve(preset_name).ok_or_else(|| anyhow!("Unknown preset '{}'", preset_name))
+}
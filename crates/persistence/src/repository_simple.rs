@@ -1,4 +1,4 @@
-use aaeq_core::{Device, DspSettings, DspSinkSettings, Mapping, Profile, Scope};
+use aaeq_core::{ApiToken, Device, DspSettings, DspSinkSettings, Mapping, MqttSettings, Profile, Scope, TokenScope};
 use anyhow::Result;
 use sqlx::{Row, SqlitePool};
 use chrono::Utc;
@@ -641,6 +641,76 @@ impl AppSettingsRepository {
 
         Ok(())
     }
+
+    // MQTT bridge settings
+    pub async fn get_mqtt_settings(&self) -> Result<MqttSettings> {
+        let row = sqlx::query(
+            "SELECT mqtt_enabled, mqtt_host, mqtt_port, mqtt_username, mqtt_password, mqtt_base_topic, mqtt_discovery_prefix
+             FROM app_settings WHERE id = 1"
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let defaults = MqttSettings::default();
+        let Some(row) = row else {
+            return Ok(defaults);
+        };
+
+        Ok(MqttSettings {
+            enabled: row.get::<i32, _>("mqtt_enabled") != 0,
+            host: row.get::<Option<String>, _>("mqtt_host").unwrap_or(defaults.host),
+            port: row.get::<Option<i64>, _>("mqtt_port").map(|p| p as u16).unwrap_or(defaults.port),
+            username: row.get("mqtt_username"),
+            password: row.get("mqtt_password"),
+            base_topic: row.get::<Option<String>, _>("mqtt_base_topic").unwrap_or(defaults.base_topic),
+            discovery_prefix: row
+                .get::<Option<String>, _>("mqtt_discovery_prefix")
+                .unwrap_or(defaults.discovery_prefix),
+        })
+    }
+
+    pub async fn set_mqtt_settings(&self, settings: &MqttSettings) -> Result<()> {
+        let now = Utc::now().timestamp();
+        let enabled = if settings.enabled { 1 } else { 0 };
+
+        // Try to update existing row first
+        let result = sqlx::query(
+            "UPDATE app_settings SET mqtt_enabled = ?, mqtt_host = ?, mqtt_port = ?, mqtt_username = ?, mqtt_password = ?,
+             mqtt_base_topic = ?, mqtt_discovery_prefix = ?, updated_at = ? WHERE id = 1"
+        )
+        .bind(enabled)
+        .bind(&settings.host)
+        .bind(settings.port as i64)
+        .bind(&settings.username)
+        .bind(&settings.password)
+        .bind(&settings.base_topic)
+        .bind(&settings.discovery_prefix)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        // If no row was updated, insert a new one
+        if result.rows_affected() == 0 {
+            sqlx::query(
+                "INSERT INTO app_settings (id, mqtt_enabled, mqtt_host, mqtt_port, mqtt_username, mqtt_password,
+                 mqtt_base_topic, mqtt_discovery_prefix, created_at, updated_at)
+                 VALUES (1, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            )
+            .bind(enabled)
+            .bind(&settings.host)
+            .bind(settings.port as i64)
+            .bind(&settings.username)
+            .bind(&settings.password)
+            .bind(&settings.base_topic)
+            .bind(&settings.discovery_prefix)
+            .bind(now)
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        Ok(())
+    }
 }
 
 /// Repository for tracking last applied state
@@ -1423,6 +1493,26 @@ impl ManagedDeviceRepository {
         Ok(result.get(0))
     }
 
+    /// Update a device's name, protocol, address and favorite status by ID
+    pub async fn update(&self, id: i64, device: &aaeq_core::ManagedDevice) -> Result<()> {
+        let now = Utc::now().timestamp();
+        let favorite = if device.favorite { 1 } else { 0 };
+
+        sqlx::query(
+            "UPDATE managed_devices SET name = ?, protocol = ?, address = ?, favorite = ?, updated_at = ? WHERE id = ?"
+        )
+        .bind(&device.name)
+        .bind(&device.protocol)
+        .bind(&device.address)
+        .bind(favorite)
+        .bind(now)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
     /// Update last_seen timestamp for a device
     pub async fn update_last_seen(&self, id: i64, last_seen: i64) -> Result<()> {
         let now = Utc::now().timestamp();
@@ -1474,3 +1564,120 @@ impl ManagedDeviceRepository {
         Ok(())
     }
 }
+
+/// Repository for Control API tokens
+pub struct ApiTokenRepository {
+    pool: SqlitePool,
+}
+
+impl ApiTokenRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Store a new token by its hash
+    pub async fn create(&self, name: &str, token_hash: &str, scope: TokenScope) -> Result<i64> {
+        let now = Utc::now().timestamp();
+
+        let result = sqlx::query(
+            "INSERT INTO api_tokens (name, token_hash, scope, created_at) VALUES (?, ?, ?, ?)"
+        )
+        .bind(name)
+        .bind(token_hash)
+        .bind(scope.as_str())
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Get a token by ID
+    pub async fn get_by_id(&self, id: i64) -> Result<Option<ApiToken>> {
+        let row = sqlx::query(
+            r#"SELECT id, name, scope, created_at, last_used_at, revoked_at
+               FROM api_tokens WHERE id = ?"#
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|r| Self::from_row(&r)).transpose()
+    }
+
+    /// Find a token that has not been revoked by its hash
+    pub async fn get_active_by_hash(&self, token_hash: &str) -> Result<Option<ApiToken>> {
+        let row = sqlx::query(
+            r#"SELECT id, name, scope, created_at, last_used_at, revoked_at
+               FROM api_tokens WHERE token_hash = ? AND revoked_at IS NULL"#
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|r| Self::from_row(&r)).transpose()
+    }
+
+    /// List all tokens, including revoked ones
+    pub async fn list_all(&self) -> Result<Vec<ApiToken>> {
+        let rows = sqlx::query(
+            r#"SELECT id, name, scope, created_at, last_used_at, revoked_at
+               FROM api_tokens ORDER BY id"#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(Self::from_row).collect()
+    }
+
+    /// Whether any token is still usable
+    pub async fn has_active(&self) -> Result<bool> {
+        let count: i64 = sqlx::query("SELECT COUNT(*) FROM api_tokens WHERE revoked_at IS NULL")
+            .fetch_one(&self.pool)
+            .await?
+            .get(0);
+
+        Ok(count > 0)
+    }
+
+    /// Revoke a token; returns false if there was no such active token
+    pub async fn revoke(&self, id: i64) -> Result<bool> {
+        let now = Utc::now().timestamp();
+
+        let result = sqlx::query(
+            "UPDATE api_tokens SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL"
+        )
+        .bind(now)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Record that a token was just used
+    pub async fn touch_last_used(&self, id: i64) -> Result<()> {
+        let now = Utc::now().timestamp();
+
+        sqlx::query("UPDATE api_tokens SET last_used_at = ? WHERE id = ?")
+            .bind(now)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    fn from_row(r: &sqlx::sqlite::SqliteRow) -> Result<ApiToken> {
+        let scope: String = r.get(2);
+        Ok(ApiToken {
+            id: Some(r.get(0)),
+            name: r.get(1),
+            scope: TokenScope::from_str(&scope)
+                .map_err(|_| anyhow::anyhow!("Invalid token scope: {}", scope))?,
+            created_at: r.get(3),
+            last_used_at: r.get(4),
+            revoked_at: r.get(5),
+        })
+    }
+}
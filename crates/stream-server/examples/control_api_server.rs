@@ -9,10 +9,12 @@
 /// curl http://localhost:8080/v1/health
 /// curl http://localhost:8080/v1/outputs
 /// curl http://localhost:8080/v1/capabilities
+/// curl http://localhost:8080/v1/openapi.json
 use anyhow::Result;
 use std::sync::Arc;
 use stream_server::*;
 use tokio::sync::RwLock;
+use utoipa::OpenApi;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -46,15 +48,15 @@ async fn main() -> Result<()> {
     println!("✓ Control API server started on {}\n", bind_addr);
     println!("API Endpoints:");
     println!("──────────────────────────────────────────────────");
-    println!("  GET  http://localhost:8080/v1/health");
-    println!("  GET  http://localhost:8080/v1/outputs");
-    println!("  POST http://localhost:8080/v1/outputs/select");
-    println!("  POST http://localhost:8080/v1/outputs/start");
-    println!("  POST http://localhost:8080/v1/outputs/stop");
-    println!("  GET  http://localhost:8080/v1/outputs/metrics");
-    println!("  GET  http://localhost:8080/v1/route");
-    println!("  POST http://localhost:8080/v1/route");
-    println!("  GET  http://localhost:8080/v1/capabilities");
+    // Listed from the OpenAPI document, so they're never out of date
+    let document = serde_json::to_value(ApiDoc::openapi())?;
+    for (path, item) in document["paths"].as_object().into_iter().flatten() {
+        for (method, operation) in item.as_object().into_iter().flatten() {
+            let summary = operation["summary"].as_str().unwrap_or_default();
+            println!("  {:<6} http://localhost:8080{:<28} {}", method.to_uppercase(), path, summary);
+        }
+    }
+    println!("  (profiles, mappings, presets, dsp and devices need with_database; tokens need with_auth)");
     println!();
 
     println!("Example commands:");
@@ -68,6 +70,9 @@ async fn main() -> Result<()> {
     println!("# Get capabilities");
     println!("curl http://localhost:8080/v1/capabilities");
     println!();
+    println!("# Get the OpenAPI document, e.g. to generate a client");
+    println!("curl http://localhost:8080/v1/openapi.json");
+    println!();
     println!("# Select local DAC output");
     println!(r#"curl -X POST http://localhost:8080/v1/outputs/select \"#);
     println!(r#"  -H "Content-Type: application/json" \"#);
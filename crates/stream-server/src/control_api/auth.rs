@@ -0,0 +1,322 @@
+/// Bearer-token authentication for the Control API
+///
+/// Tokens are random strings shown once when issued; only their SHA-256 hash
+/// is stored, in the `api_tokens` table. Each token has a scope: `read` tokens
+/// may only read (GET requests and the event stream), `control` tokens may
+/// also change things. Clients send the token as `Authorization: Bearer <token>`;
+/// the `/v1/events` WebSocket upgrade also takes an `access_token` query
+/// parameter, as browsers can't set headers on WebSockets. `/v1/health`,
+/// `/v1/openapi.json` and the web UI's static files stay open.
+use super::library::{success, ApiError, ApiResult};
+use super::routes::ApiRouter;
+use super::types::*;
+use super::web::is_web_ui_path;
+use aaeq_core::{ApiToken, TokenScope};
+use aaeq_persistence::ApiTokenRepository;
+use anyhow::Result;
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get},
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// Prefix of every token, so they're recognisable in config files and logs
+const TOKEN_PREFIX: &str = "aaeq_";
+
+/// Random bytes in a token
+const TOKEN_BYTES: usize = 32;
+
+/// Reachable without a token, for health checks, load balancers and client generators
+const OPEN_PATHS: [&str; 2] = ["/v1/health", "/v1/openapi.json"];
+
+/// The only path that takes the token as a query parameter
+const EVENTS_PATH: &str = "/v1/events";
+
+/// How often a token's `last_used_at` is written, rather than on every request
+const TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// State of the [`require_token`] middleware
+#[derive(Clone)]
+pub struct AuthState {
+    pool: SqlitePool,
+    /// When each token's use was last written
+    touched: Arc<Mutex<HashMap<i64, Instant>>>,
+}
+
+impl AuthState {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self {
+            pool,
+            touched: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Whether a use of token `id` is due to be written
+    fn should_touch(&self, id: i64) -> bool {
+        let now = Instant::now();
+        let mut touched = self.touched.lock().unwrap();
+        if touched.get(&id).is_some_and(|last| now.duration_since(*last) < TOUCH_INTERVAL) {
+            return false;
+        }
+        touched.insert(id, now);
+        true
+    }
+}
+
+/// Generate a new random token
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, hex::encode(bytes))
+}
+
+/// The hash a token is stored and looked up by
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Generate a token and store it with `scope`
+pub async fn issue_token(pool: &SqlitePool, name: &str, scope: TokenScope) -> Result<IssuedToken> {
+    let token = generate_token();
+    let repo = ApiTokenRepository::new(pool.clone());
+    let id = repo.create(name, &hash_token(&token), scope).await?;
+    let info = repo
+        .get_by_id(id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Token {} vanished after being created", id))?;
+
+    Ok(IssuedToken { info, token })
+}
+
+/// The scope a request needs, or none if it's open
+pub fn required_scope(method: &Method, path: &str) -> Option<TokenScope> {
+    if OPEN_PATHS.contains(&path) || is_web_ui_path(path) {
+        return None;
+    }
+    // Listing tokens reveals who has access, so it's for controllers only
+    if path.starts_with("/v1/tokens") {
+        return Some(TokenScope::Control);
+    }
+    match *method {
+        Method::GET | Method::HEAD | Method::OPTIONS => Some(TokenScope::Read),
+        _ => Some(TokenScope::Control),
+    }
+}
+
+/// The token a request carries, from the `Authorization` header, or the
+/// `access_token` parameter of an event stream upgrade
+fn request_token(request: &Request) -> Option<&str> {
+    if let Some(value) = request.headers().get(header::AUTHORIZATION) {
+        let (scheme, token) = value.to_str().ok()?.split_once(' ')?;
+        return scheme.eq_ignore_ascii_case("bearer").then(|| token.trim());
+    }
+    if !is_events_upgrade(request) {
+        return None;
+    }
+    request
+        .uri()
+        .query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("access_token="))
+}
+
+/// A WebSocket upgrade of the event stream
+fn is_events_upgrade(request: &Request) -> bool {
+    request.uri().path() == EVENTS_PATH
+        && request
+            .headers()
+            .get(header::UPGRADE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+fn unauthorized(error: &str) -> Response {
+    let mut response = ApiError::new(StatusCode::UNAUTHORIZED, error).into_response();
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+    response
+}
+
+/// Middleware rejecting requests without a token of the scope they need
+///
+/// Token use is recorded at most once per [`TOUCH_INTERVAL`], so busy
+/// clients don't write to the database on every request.
+pub async fn require_token(State(state): State<AuthState>, request: Request, next: Next) -> Response {
+    let Some(required) = required_scope(request.method(), request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    let Some(token) = request_token(&request) else {
+        return unauthorized("Missing bearer token");
+    };
+
+    let repo = ApiTokenRepository::new(state.pool.clone());
+    let api_token = match repo.get_active_by_hash(&hash_token(token)).await {
+        Ok(Some(api_token)) => api_token,
+        Ok(None) => {
+            debug!("Rejected unknown or revoked token for {} {}", request.method(), request.uri().path());
+            return unauthorized("Invalid or revoked token");
+        }
+        Err(e) => return ApiError::internal("Failed to check token", e).into_response(),
+    };
+
+    if !api_token.scope.allows(required) {
+        return ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("Token '{}' has {} scope; this needs {}", api_token.name, api_token.scope.as_str(), required.as_str()),
+        )
+        .into_response();
+    }
+
+    if let Some(id) = api_token.id.filter(|id| state.should_touch(*id)) {
+        if let Err(e) = repo.touch_last_used(id).await {
+            warn!("Failed to record use of token {}: {:#}", id, e);
+        }
+    }
+
+    next.run(request).await
+}
+
+/// Create the router for managing tokens
+pub fn create_token_router(pool: SqlitePool) -> ApiRouter {
+    ApiRouter::default()
+        .route("/v1/tokens", get(list_tokens).post(create_token))
+        .route("/v1/tokens/:id", delete(revoke_token))
+        .with_state(pool)
+}
+
+/// GET /v1/tokens - List tokens, including revoked ones
+#[utoipa::path(get, path = "/v1/tokens", tag = "tokens", responses(
+    (status = 200, description = "Tokens, without their secrets", body = Vec<ApiToken>),
+))]
+async fn list_tokens(State(pool): State<SqlitePool>) -> ApiResult {
+    debug!("GET /v1/tokens");
+    let tokens: Vec<ApiToken> = ApiTokenRepository::new(pool).list_all().await?;
+    Ok(Json(tokens).into_response())
+}
+
+/// POST /v1/tokens - Issue a token
+#[utoipa::path(post, path = "/v1/tokens", tag = "tokens", request_body = TokenRequest, responses(
+    (status = 201, description = "Token issued; the token itself is only shown here", body = IssuedToken),
+    (status = 400, description = "Missing name", body = ErrorResponse),
+))]
+async fn create_token(State(pool): State<SqlitePool>, Json(req): Json<TokenRequest>) -> ApiResult {
+    info!("POST /v1/tokens: {} ({})", req.name, req.scope.as_str());
+
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::bad_request("Token name must not be empty"));
+    }
+    let issued = issue_token(&pool, name, req.scope).await?;
+
+    Ok((StatusCode::CREATED, Json(issued)).into_response())
+}
+
+/// DELETE /v1/tokens/:id - Revoke a token
+#[utoipa::path(delete, path = "/v1/tokens/{id}", tag = "tokens", params(
+    ("id" = i64, Path, description = "Token id"),
+), responses(
+    (status = 200, description = "Token revoked", body = SuccessResponse),
+    (status = 404, description = "No active token with that id", body = ErrorResponse),
+))]
+async fn revoke_token(State(pool): State<SqlitePool>, Path(id): Path<i64>) -> ApiResult {
+    info!("DELETE /v1/tokens/{}", id);
+
+    if !ApiTokenRepository::new(pool).revoke(id).await? {
+        return Err(ApiError::not_found(format!("No active token with id {}", id)));
+    }
+    Ok(success(format!("Revoked token {}", id)))
+}
+
+/// Log a hint when auth is on but nobody could get in
+pub async fn warn_if_no_tokens(pool: &SqlitePool) {
+    match ApiTokenRepository::new(pool.clone()).has_active().await {
+        Ok(true) => {}
+        Ok(false) => warn!("Control API auth is enabled but no tokens exist; every request will be rejected"),
+        Err(e) => error!("Failed to check for API tokens: {:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    #[test]
+    fn test_generated_tokens() {
+        let token = generate_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + TOKEN_BYTES * 2);
+        assert_ne!(token, generate_token());
+
+        let hash = hash_token(&token);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_token(&token));
+        assert_ne!(hash, hash_token(&generate_token()));
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(required_scope(&Method::GET, "/v1/health"), None);
+        assert_eq!(required_scope(&Method::GET, "/v1/openapi.json"), None);
+        assert_eq!(required_scope(&Method::GET, "/ui/app.js"), None);
+        assert_eq!(required_scope(&Method::GET, "/v1/outputs"), Some(TokenScope::Read));
+        assert_eq!(required_scope(&Method::GET, "/v1/events"), Some(TokenScope::Read));
+        assert_eq!(required_scope(&Method::POST, "/v1/outputs/start"), Some(TokenScope::Control));
+        assert_eq!(required_scope(&Method::PATCH, "/v1/dsp"), Some(TokenScope::Control));
+        assert_eq!(required_scope(&Method::GET, "/v1/tokens"), Some(TokenScope::Control));
+    }
+
+    #[test]
+    fn test_request_token() {
+        let request = |builder: axum::http::request::Builder| builder.body(Body::empty()).unwrap();
+
+        let req = request(Request::builder().uri("/v1/outputs").header("Authorization", "Bearer abc"));
+        assert_eq!(request_token(&req), Some("abc"));
+
+        let req = request(Request::builder().uri("/v1/outputs").header("Authorization", "bearer  abc "));
+        assert_eq!(request_token(&req), Some("abc"));
+
+        let req = request(Request::builder().uri("/v1/outputs").header("Authorization", "Basic abc"));
+        assert_eq!(request_token(&req), None);
+
+        // The query parameter only counts for the event stream's WebSocket upgrade
+        let req = request(
+            Request::builder()
+                .uri("/v1/events?types=meters&access_token=abc")
+                .header("Upgrade", "websocket"),
+        );
+        assert_eq!(request_token(&req), Some("abc"));
+
+        let req = request(Request::builder().uri("/v1/events?access_token=abc"));
+        assert_eq!(request_token(&req), None);
+
+        let req = request(Request::builder().uri("/v1/outputs?access_token=abc").header("Upgrade", "websocket"));
+        assert_eq!(request_token(&req), None);
+
+        let req = request(Request::builder().uri("/v1/events").header("Upgrade", "websocket"));
+        assert_eq!(request_token(&req), None);
+    }
+
+    #[tokio::test]
+    async fn test_token_use_is_throttled() {
+        let pool = SqlitePool::connect_lazy("sqlite::memory:").unwrap();
+        let state = AuthState::new(pool);
+        assert!(state.should_touch(1));
+        assert!(!state.should_touch(1));
+        assert!(state.should_touch(2));
+
+        state.touched.lock().unwrap().insert(1, Instant::now() - TOUCH_INTERVAL);
+        assert!(state.should_touch(1));
+    }
+}
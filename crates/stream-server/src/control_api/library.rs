@@ -0,0 +1,850 @@
+/// Profiles, mappings, presets, DSP settings and devices for the Control API
+///
+/// Served from the desktop app's database when the server is given one with
+/// [`ControlServer::with_database`](super::ControlServer::with_database).
+/// Switching profile and applying presets take effect live through a
+/// [`ProfileController`], if one is set.
+use super::routes::ApiRouter;
+use super::types::*;
+use crate::dsp::exclusivity::{get_enabled_effects, validate_toggle};
+use aaeq_core::preset_library::{get_known_preset_curve, list_known_presets};
+use aaeq_core::{normalize_key, DspSettings, EqPreset, ManagedDevice, Mapping, Profile, Scope};
+use aaeq_persistence::{
+    active_profile_id, AppSettingsRepository, CustomEqPresetRepository, DspSettingsRepository, ManagedDeviceRepository,
+    MappingRepository, ProfileRepository, DEFAULT_PROFILE_ID, FALLBACK_PRESET,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use tracing::{debug, error, info};
+
+/// Defaults for new profiles, as in the desktop app
+const DEFAULT_PROFILE_ICON: &str = "📁";
+const DEFAULT_PROFILE_COLOR: &str = "#4A90E2";
+
+const DEVICE_PROTOCOLS: [&str; 5] = ["WiimApi", "LocalDac", "Dlna", "AirPlay", "AnpNode"];
+
+/// EQ band limits of the desktop preset editor
+const BAND_GAIN_DB: RangeInclusive<f32> = -12.0..=12.0;
+const BAND_FREQUENCY_HZ: RangeInclusive<u32> = 20..=20000;
+
+const SAMPLE_RATES: [u32; 6] = [44100, 48000, 88200, 96000, 176400, 192000];
+const BUFFER_MS: RangeInclusive<u32> = 10..=2000;
+const HEADROOM_DB: RangeInclusive<f32> = -24.0..=0.0;
+
+/// DSP settings fields that can't be changed through the API
+const READ_ONLY_DSP_FIELDS: [&str; 4] = ["id", "profile_id", "created_at", "updated_at"];
+
+/// Applies profile and preset changes to what's playing
+#[async_trait]
+pub trait ProfileController: Send + Sync {
+    /// Switch to a profile, already saved as the active one
+    ///
+    /// Also called when the active profile's DSP settings change, so they take effect.
+    async fn activate_profile(&self, profile_id: i64) -> Result<()>;
+
+    /// Apply a preset until the track changes, returning the preset actually applied
+    async fn apply_preset(&self, preset: &str) -> Result<String>;
+}
+
+/// Shared state of the library routes
+#[derive(Clone)]
+pub struct LibraryState {
+    pub pool: SqlitePool,
+    pub controller: Option<Arc<dyn ProfileController>>,
+}
+
+/// Selects the profile for mappings, DSP settings and devices (defaults to the active one)
+#[derive(Debug, Deserialize)]
+struct ProfileQuery {
+    profile_id: Option<i64>,
+}
+
+/// An error answered with an [`ErrorResponse`]
+pub(super) struct ApiError {
+    status: StatusCode,
+    error: String,
+    details: Option<String>,
+}
+
+impl ApiError {
+    pub(super) fn new(status: StatusCode, error: impl Into<String>) -> Self {
+        Self {
+            status,
+            error: error.into(),
+            details: None,
+        }
+    }
+
+    pub(super) fn bad_request(error: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, error)
+    }
+
+    pub(super) fn not_found(error: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, error)
+    }
+
+    fn conflict(error: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, error)
+    }
+
+    pub(super) fn internal(error: impl Into<String>, e: anyhow::Error) -> Self {
+        let error = error.into();
+        error!("{}: {:#}", error, e);
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            error,
+            details: Some(format!("{:#}", e)),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::internal("Database error", e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let response = ErrorResponse {
+            error: self.error,
+            details: self.details,
+        };
+        (self.status, Json(response)).into_response()
+    }
+}
+
+pub(super) type ApiResult = std::result::Result<Response, ApiError>;
+
+pub(super) fn success(message: String) -> Response {
+    Json(SuccessResponse { success: true, message }).into_response()
+}
+
+/// Create the router for the library resources
+pub fn create_library_router(state: LibraryState) -> ApiRouter {
+    ApiRouter::default()
+        .route("/v1/profiles", get(list_profiles).post(create_profile))
+        .route("/v1/profiles/:id", get(get_profile).put(update_profile).delete(delete_profile))
+        .route("/v1/profiles/:id/activate", post(activate_profile))
+        .route("/v1/mappings", get(list_mappings).post(save_mapping))
+        .route("/v1/mappings/:id", delete(delete_mapping))
+        .route("/v1/presets", get(list_presets))
+        .route("/v1/presets/:name", get(get_preset).put(save_preset).delete(delete_preset))
+        .route("/v1/presets/:name/apply", post(apply_preset))
+        .route("/v1/dsp", get(get_dsp).patch(update_dsp))
+        .route("/v1/devices", get(list_devices).post(create_device))
+        .route("/v1/devices/:id", get(get_device).put(update_device).delete(delete_device))
+        .with_state(state)
+}
+
+async fn find_profile(pool: &SqlitePool, id: i64) -> std::result::Result<Profile, ApiError> {
+    ProfileRepository::new(pool.clone())
+        .get_by_id(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Profile {} not found", id)))
+}
+
+/// The requested profile, else the active one
+async fn target_profile(pool: &SqlitePool, id: Option<i64>) -> std::result::Result<Profile, ApiError> {
+    let id = match id {
+        Some(id) => id,
+        None => active_profile_id(pool).await?,
+    };
+    find_profile(pool, id).await
+}
+
+fn profile_id(profile: &Profile) -> i64 {
+    profile.id.unwrap_or(DEFAULT_PROFILE_ID)
+}
+
+/// A trimmed, non-empty name
+fn required_name(name: Option<&str>, what: &str) -> std::result::Result<String, ApiError> {
+    match name.map(str::trim) {
+        Some(name) if !name.is_empty() => Ok(name.to_string()),
+        _ => Err(ApiError::bad_request(format!("{} is required", what))),
+    }
+}
+
+/// Tell the controller about a profile switch; the switch is saved either way
+async fn notify_profile(state: &LibraryState, profile_id: i64) -> std::result::Result<(), ApiError> {
+    if let Some(controller) = &state.controller {
+        controller
+            .activate_profile(profile_id)
+            .await
+            .map_err(|e| ApiError::internal("Failed to switch profile", e))?;
+    }
+    Ok(())
+}
+
+/// GET /v1/profiles - List profiles and the active one
+#[utoipa::path(get, path = "/v1/profiles", tag = "profiles", responses(
+    (status = 200, description = "Profiles and the active one", body = ProfilesResponse),
+))]
+async fn list_profiles(State(state): State<LibraryState>) -> ApiResult {
+    debug!("GET /v1/profiles");
+
+    let response = ProfilesResponse {
+        profiles: ProfileRepository::new(state.pool.clone()).list_all().await?,
+        active_id: active_profile_id(&state.pool).await?,
+    };
+    Ok(Json(response).into_response())
+}
+
+/// POST /v1/profiles - Create a profile
+#[utoipa::path(post, path = "/v1/profiles", tag = "profiles", request_body = ProfileRequest, responses(
+    (status = 201, description = "Profile created", body = Profile),
+    (status = 400, description = "Missing name", body = ErrorResponse),
+    (status = 409, description = "Name taken", body = ErrorResponse),
+))]
+async fn create_profile(State(state): State<LibraryState>, Json(req): Json<ProfileRequest>) -> ApiResult {
+    info!("POST /v1/profiles: {:?}", req.name);
+
+    let name = required_name(req.name.as_deref(), "Profile name")?;
+    let repo = ProfileRepository::new(state.pool.clone());
+    if repo.get_by_name(&name).await?.is_some() {
+        return Err(ApiError::conflict(format!("Profile '{}' already exists", name)));
+    }
+
+    let now = Utc::now().timestamp();
+    let id = repo
+        .create(&Profile {
+            id: None,
+            name,
+            is_builtin: false,
+            icon: req.icon.unwrap_or_else(|| DEFAULT_PROFILE_ICON.to_string()),
+            color: req.color.unwrap_or_else(|| DEFAULT_PROFILE_COLOR.to_string()),
+            created_at: now,
+            updated_at: now,
+        })
+        .await?;
+
+    let profile = find_profile(&state.pool, id).await?;
+    Ok((StatusCode::CREATED, Json(profile)).into_response())
+}
+
+/// GET /v1/profiles/:id - Get a profile
+#[utoipa::path(get, path = "/v1/profiles/{id}", tag = "profiles", params(
+    ("id" = i64, Path, description = "Profile id"),
+), responses(
+    (status = 200, description = "The profile", body = Profile),
+    (status = 404, description = "Profile not found", body = ErrorResponse),
+))]
+async fn get_profile(State(state): State<LibraryState>, Path(id): Path<i64>) -> ApiResult {
+    debug!("GET /v1/profiles/{}", id);
+    Ok(Json(find_profile(&state.pool, id).await?).into_response())
+}
+
+/// PUT /v1/profiles/:id - Rename a profile or change its icon or color
+#[utoipa::path(put, path = "/v1/profiles/{id}", tag = "profiles", request_body = ProfileRequest, params(
+    ("id" = i64, Path, description = "Profile id"),
+), responses(
+    (status = 200, description = "Updated profile", body = Profile),
+    (status = 404, description = "Profile not found", body = ErrorResponse),
+    (status = 409, description = "Built-in profile or name taken", body = ErrorResponse),
+))]
+async fn update_profile(
+    State(state): State<LibraryState>,
+    Path(id): Path<i64>,
+    Json(req): Json<ProfileRequest>,
+) -> ApiResult {
+    info!("PUT /v1/profiles/{}", id);
+
+    let profile = find_profile(&state.pool, id).await?;
+    if profile.is_builtin {
+        return Err(ApiError::conflict(format!("Built-in profile '{}' can't be changed", profile.name)));
+    }
+
+    let repo = ProfileRepository::new(state.pool.clone());
+    let name = match req.name.as_deref() {
+        Some(name) => required_name(Some(name), "Profile name")?,
+        None => profile.name.clone(),
+    };
+    if name != profile.name && repo.get_by_name(&name).await?.is_some() {
+        return Err(ApiError::conflict(format!("Profile '{}' already exists", name)));
+    }
+    let icon = req.icon.unwrap_or(profile.icon);
+    let color = req.color.unwrap_or(profile.color);
+    repo.update(id, &name, &icon, &color).await?;
+
+    Ok(Json(find_profile(&state.pool, id).await?).into_response())
+}
+
+/// DELETE /v1/profiles/:id - Delete a profile with its mappings, DSP settings and devices
+///
+/// Deleting the active profile switches to the default one.
+#[utoipa::path(delete, path = "/v1/profiles/{id}", tag = "profiles", params(
+    ("id" = i64, Path, description = "Profile id"),
+), responses(
+    (status = 200, description = "Profile deleted", body = SuccessResponse),
+    (status = 404, description = "Profile not found", body = ErrorResponse),
+    (status = 409, description = "Built-in profile", body = ErrorResponse),
+))]
+async fn delete_profile(State(state): State<LibraryState>, Path(id): Path<i64>) -> ApiResult {
+    info!("DELETE /v1/profiles/{}", id);
+
+    let profile = find_profile(&state.pool, id).await?;
+    if profile.is_builtin {
+        return Err(ApiError::conflict(format!("Built-in profile '{}' can't be deleted", profile.name)));
+    }
+
+    if active_profile_id(&state.pool).await? == id {
+        AppSettingsRepository::new(state.pool.clone())
+            .set_active_profile_id(DEFAULT_PROFILE_ID)
+            .await?;
+        notify_profile(&state, DEFAULT_PROFILE_ID).await?;
+    }
+    ProfileRepository::new(state.pool.clone()).delete(id).await?;
+
+    Ok(success(format!("Deleted profile '{}'", profile.name)))
+}
+
+/// POST /v1/profiles/:id/activate - Make a profile the active one
+#[utoipa::path(post, path = "/v1/profiles/{id}/activate", tag = "profiles", params(
+    ("id" = i64, Path, description = "Profile id"),
+), responses(
+    (status = 200, description = "Profile active", body = SuccessResponse),
+    (status = 404, description = "Profile not found", body = ErrorResponse),
+))]
+async fn activate_profile(State(state): State<LibraryState>, Path(id): Path<i64>) -> ApiResult {
+    info!("POST /v1/profiles/{}/activate", id);
+
+    let profile = find_profile(&state.pool, id).await?;
+    AppSettingsRepository::new(state.pool.clone())
+        .set_active_profile_id(id)
+        .await?;
+    notify_profile(&state, id).await?;
+
+    Ok(success(format!("Active profile: {}", profile.name)))
+}
+
+/// GET /v1/mappings - List a profile's mapping rules
+#[utoipa::path(get, path = "/v1/mappings", tag = "mappings", params(
+    ("profile_id" = Option<i64>, Query, description = "Profile to use; defaults to the active one"),
+), responses(
+    (status = 200, description = "Mapping rules", body = Vec<Mapping>),
+    (status = 404, description = "Profile not found", body = ErrorResponse),
+))]
+async fn list_mappings(State(state): State<LibraryState>, Query(query): Query<ProfileQuery>) -> ApiResult {
+    debug!("GET /v1/mappings: {:?}", query.profile_id);
+
+    let profile = target_profile(&state.pool, query.profile_id).await?;
+    let mappings = MappingRepository::new(state.pool.clone())
+        .list_by_profile(profile_id(&profile))
+        .await?;
+    Ok(Json(mappings).into_response())
+}
+
+/// POST /v1/mappings - Create or replace the rule for a scope and key
+#[utoipa::path(post, path = "/v1/mappings", tag = "mappings", request_body = MappingRequest, responses(
+    (status = 200, description = "Saved rule", body = Mapping),
+    (status = 400, description = "Missing or unexpected key", body = ErrorResponse),
+    (status = 404, description = "Profile not found", body = ErrorResponse),
+))]
+async fn save_mapping(State(state): State<LibraryState>, Json(req): Json<MappingRequest>) -> ApiResult {
+    info!("POST /v1/mappings: {} {:?} -> {}", req.scope.as_str(), req.key, req.preset);
+
+    let profile = target_profile(&state.pool, req.profile_id).await?;
+    let preset = required_name(Some(&req.preset), "Preset")?;
+    let key = match (&req.scope, req.key.as_deref()) {
+        (Scope::Default, None) => None,
+        (Scope::Default, Some(_)) => return Err(ApiError::bad_request("The default rule has no key")),
+        (_, key) => Some(normalize_key(&required_name(key, "Mapping key")?)),
+    };
+
+    let repo = MappingRepository::new(state.pool.clone());
+    let profile_id = profile_id(&profile);
+    if req.scope == Scope::Default {
+        // A null key never conflicts, so replace the default rule by hand
+        for existing in repo.list_by_profile(profile_id).await? {
+            if let (Scope::Default, Some(id)) = (&existing.scope, existing.id) {
+                repo.delete(id).await?;
+            }
+        }
+    }
+
+    let now = Utc::now().timestamp();
+    let id = repo
+        .upsert(&Mapping {
+            id: None,
+            scope: req.scope,
+            key_normalized: key,
+            preset_name: preset,
+            profile_id,
+            created_at: now,
+            updated_at: now,
+        })
+        .await?;
+
+    let mapping = repo
+        .list_by_profile(profile_id)
+        .await?
+        .into_iter()
+        .find(|mapping| mapping.id == Some(id))
+        .ok_or_else(|| ApiError::not_found(format!("Mapping {} not found", id)))?;
+    Ok(Json(mapping).into_response())
+}
+
+/// DELETE /v1/mappings/:id - Delete a mapping rule
+#[utoipa::path(delete, path = "/v1/mappings/{id}", tag = "mappings", params(
+    ("id" = i64, Path, description = "Mapping id"),
+), responses(
+    (status = 200, description = "Rule deleted", body = SuccessResponse),
+    (status = 404, description = "Mapping not found", body = ErrorResponse),
+))]
+async fn delete_mapping(State(state): State<LibraryState>, Path(id): Path<i64>) -> ApiResult {
+    info!("DELETE /v1/mappings/{}", id);
+
+    let repo = MappingRepository::new(state.pool.clone());
+    if !repo.list_all().await?.iter().any(|mapping| mapping.id == Some(id)) {
+        return Err(ApiError::not_found(format!("Mapping {} not found", id)));
+    }
+    repo.delete(id).await?;
+
+    Ok(success(format!("Deleted mapping {}", id)))
+}
+
+/// GET /v1/presets - List built-in and custom preset names
+#[utoipa::path(get, path = "/v1/presets", tag = "presets", responses(
+    (status = 200, description = "Preset names", body = PresetsResponse),
+))]
+async fn list_presets(State(state): State<LibraryState>) -> ApiResult {
+    debug!("GET /v1/presets");
+
+    let response = PresetsResponse {
+        builtin: list_known_presets().into_iter().map(str::to_string).collect(),
+        custom: CustomEqPresetRepository::new(state.pool.clone()).list_names().await?,
+    };
+    Ok(Json(response).into_response())
+}
+
+/// GET /v1/presets/:name - Get a preset's EQ bands
+#[utoipa::path(get, path = "/v1/presets/{name}", tag = "presets", params(
+    ("name" = String, Path, description = "Preset name"),
+), responses(
+    (status = 200, description = "The preset", body = EqPreset),
+    (status = 404, description = "Preset not found", body = ErrorResponse),
+))]
+async fn get_preset(State(state): State<LibraryState>, Path(name): Path<String>) -> ApiResult {
+    debug!("GET /v1/presets/{}", name);
+
+    let preset = match get_known_preset_curve(&name) {
+        Some(preset) => Some(preset),
+        None => CustomEqPresetRepository::new(state.pool.clone()).get_by_name(&name).await?,
+    };
+    let preset = preset.ok_or_else(|| ApiError::not_found(format!("Preset '{}' not found", name)))?;
+    Ok(Json(preset).into_response())
+}
+
+fn validate_bands(preset: &EqPreset) -> std::result::Result<(), ApiError> {
+    if preset.bands.is_empty() {
+        return Err(ApiError::bad_request("A preset needs at least one band"));
+    }
+    for band in &preset.bands {
+        if !BAND_FREQUENCY_HZ.contains(&band.frequency) {
+            return Err(ApiError::bad_request(format!(
+                "Band frequency {} Hz is outside {}-{} Hz",
+                band.frequency,
+                BAND_FREQUENCY_HZ.start(),
+                BAND_FREQUENCY_HZ.end()
+            )));
+        }
+        if !BAND_GAIN_DB.contains(&band.gain) {
+            return Err(ApiError::bad_request(format!(
+                "Band gain {} dB is outside {} to {} dB",
+                band.gain,
+                BAND_GAIN_DB.start(),
+                BAND_GAIN_DB.end()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// PUT /v1/presets/:name - Create or replace a custom preset
+#[utoipa::path(put, path = "/v1/presets/{name}", tag = "presets", request_body = PresetRequest, params(
+    ("name" = String, Path, description = "Preset name"),
+), responses(
+    (status = 200, description = "Preset replaced", body = EqPreset),
+    (status = 201, description = "Preset created", body = EqPreset),
+    (status = 400, description = "Bands out of range", body = ErrorResponse),
+    (status = 409, description = "Built-in preset", body = ErrorResponse),
+))]
+async fn save_preset(
+    State(state): State<LibraryState>,
+    Path(name): Path<String>,
+    Json(req): Json<PresetRequest>,
+) -> ApiResult {
+    info!("PUT /v1/presets/{}", name);
+
+    let name = required_name(Some(&name), "Preset name")?;
+    if get_known_preset_curve(&name).is_some() {
+        return Err(ApiError::conflict(format!("'{}' is a built-in preset", name)));
+    }
+    let preset = EqPreset {
+        name,
+        bands: req.bands,
+        curve_data: None,
+    };
+    validate_bands(&preset)?;
+
+    let repo = CustomEqPresetRepository::new(state.pool.clone());
+    let created = repo.get_by_name(&preset.name).await?.is_none();
+    repo.upsert(&preset).await?;
+
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(preset)).into_response())
+}
+
+/// DELETE /v1/presets/:name - Delete a custom preset; mappings using it revert to Flat
+#[utoipa::path(delete, path = "/v1/presets/{name}", tag = "presets", params(
+    ("name" = String, Path, description = "Preset name"),
+), responses(
+    (status = 200, description = "Preset deleted", body = SuccessResponse),
+    (status = 404, description = "Preset not found", body = ErrorResponse),
+    (status = 409, description = "Built-in preset", body = ErrorResponse),
+))]
+async fn delete_preset(State(state): State<LibraryState>, Path(name): Path<String>) -> ApiResult {
+    info!("DELETE /v1/presets/{}", name);
+
+    if get_known_preset_curve(&name).is_some() {
+        return Err(ApiError::conflict(format!("Built-in preset '{}' can't be deleted", name)));
+    }
+    let repo = CustomEqPresetRepository::new(state.pool.clone());
+    if repo.get_by_name(&name).await?.is_none() {
+        return Err(ApiError::not_found(format!("Preset '{}' not found", name)));
+    }
+    repo.delete(&name).await?;
+    let updated = MappingRepository::new(state.pool.clone())
+        .update_preset_references(&name, FALLBACK_PRESET)
+        .await?;
+
+    Ok(success(format!(
+        "Deleted preset '{}' ({} mapping(s) now use '{}')",
+        name, updated, FALLBACK_PRESET
+    )))
+}
+
+/// POST /v1/presets/:name/apply - Apply a preset now, until the track changes
+#[utoipa::path(post, path = "/v1/presets/{name}/apply", tag = "presets", params(
+    ("name" = String, Path, description = "Preset name"),
+), responses(
+    (status = 200, description = "Preset applied", body = SuccessResponse),
+    (status = 409, description = "Nothing to apply presets to", body = ErrorResponse),
+))]
+async fn apply_preset(State(state): State<LibraryState>, Path(name): Path<String>) -> ApiResult {
+    info!("POST /v1/presets/{}/apply", name);
+
+    let controller = state
+        .controller
+        .as_ref()
+        .ok_or_else(|| ApiError::conflict("Nothing to apply presets to"))?;
+    let applied = controller
+        .apply_preset(&name)
+        .await
+        .map_err(|e| ApiError::internal("Failed to apply preset", e))?;
+
+    Ok(success(format!("Applied preset '{}'", applied)))
+}
+
+/// GET /v1/dsp - Get a profile's DSP settings
+#[utoipa::path(get, path = "/v1/dsp", tag = "dsp", params(
+    ("profile_id" = Option<i64>, Query, description = "Profile to use; defaults to the active one"),
+), responses(
+    (status = 200, description = "DSP settings", body = DspSettings),
+    (status = 404, description = "Profile not found", body = ErrorResponse),
+))]
+async fn get_dsp(State(state): State<LibraryState>, Query(query): Query<ProfileQuery>) -> ApiResult {
+    debug!("GET /v1/dsp: {:?}", query.profile_id);
+
+    let profile = target_profile(&state.pool, query.profile_id).await?;
+    Ok(Json(dsp_settings(&state.pool, profile_id(&profile)).await?).into_response())
+}
+
+async fn dsp_settings(pool: &SqlitePool, profile_id: i64) -> Result<DspSettings> {
+    Ok(DspSettingsRepository::new(pool.clone())
+        .get_by_profile(profile_id)
+        .await?
+        .unwrap_or_else(|| DspSettings::new_for_profile(profile_id)))
+}
+
+/// Check ranges and that no two mutually exclusive effects are enabled
+fn validate_dsp(settings: &DspSettings) -> std::result::Result<(), ApiError> {
+    for (field, rate) in [
+        ("sample_rate", settings.sample_rate),
+        ("target_sample_rate", settings.target_sample_rate),
+    ] {
+        if !SAMPLE_RATES.contains(&rate) {
+            return Err(ApiError::bad_request(format!("Unsupported {} {} Hz", field, rate)));
+        }
+    }
+    if !BUFFER_MS.contains(&settings.buffer_ms) {
+        return Err(ApiError::bad_request(format!(
+            "buffer_ms must be {}-{}",
+            BUFFER_MS.start(),
+            BUFFER_MS.end()
+        )));
+    }
+    if !HEADROOM_DB.contains(&settings.headroom_db) {
+        return Err(ApiError::bad_request(format!(
+            "headroom_db must be {} to {}",
+            HEADROOM_DB.start(),
+            HEADROOM_DB.end()
+        )));
+    }
+    for effect in get_enabled_effects(settings) {
+        if let Err(conflict) = validate_toggle(effect, settings) {
+            return Err(ApiError {
+                status: StatusCode::CONFLICT,
+                error: conflict.message(),
+                details: Some(conflict.suggestion()),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// PATCH /v1/dsp - Change some of a profile's DSP settings
+///
+/// The body holds the fields to change, e.g. `{"crossfeed_enabled": true}`.
+#[utoipa::path(patch, path = "/v1/dsp", tag = "dsp", request_body(content = Object, description = "The DSP settings fields to change, e.g. `{\"crossfeed_enabled\": true}`"), params(
+    ("profile_id" = Option<i64>, Query, description = "Profile to use; defaults to the active one"),
+), responses(
+    (status = 200, description = "Updated settings", body = DspSettings),
+    (status = 400, description = "Unknown, read-only or out-of-range field", body = ErrorResponse),
+    (status = 404, description = "Profile not found", body = ErrorResponse),
+    (status = 409, description = "Mutually exclusive effects enabled", body = ErrorResponse),
+))]
+async fn update_dsp(
+    State(state): State<LibraryState>,
+    Query(query): Query<ProfileQuery>,
+    Json(changes): Json<serde_json::Map<String, serde_json::Value>>,
+) -> ApiResult {
+    info!("PATCH /v1/dsp: {:?}", changes.keys().collect::<Vec<_>>());
+
+    let profile = target_profile(&state.pool, query.profile_id).await?;
+    let profile_id = profile_id(&profile);
+    let current = dsp_settings(&state.pool, profile_id).await?;
+
+    let serde_json::Value::Object(mut fields) = serde_json::to_value(&current).map_err(anyhow::Error::from)? else {
+        unreachable!("DspSettings serializes to an object");
+    };
+    for (field, value) in changes {
+        if READ_ONLY_DSP_FIELDS.contains(&field.as_str()) {
+            return Err(ApiError::bad_request(format!("'{}' can't be changed", field)));
+        }
+        if !fields.contains_key(&field) {
+            return Err(ApiError::bad_request(format!("Unknown DSP setting '{}'", field)));
+        }
+        fields.insert(field, value);
+    }
+    let settings: DspSettings = serde_json::from_value(serde_json::Value::Object(fields)).map_err(|e| ApiError {
+        status: StatusCode::BAD_REQUEST,
+        error: "Invalid DSP settings".to_string(),
+        details: Some(e.to_string()),
+    })?;
+    validate_dsp(&settings)?;
+
+    DspSettingsRepository::new(state.pool.clone()).upsert(&settings).await?;
+    if active_profile_id(&state.pool).await? == profile_id {
+        notify_profile(&state, profile_id).await?;
+    }
+
+    Ok(Json(dsp_settings(&state.pool, profile_id).await?).into_response())
+}
+
+async fn find_device(pool: &SqlitePool, id: i64) -> std::result::Result<ManagedDevice, ApiError> {
+    ManagedDeviceRepository::new(pool.clone())
+        .get_by_id(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("Device {} not found", id)))
+}
+
+fn validate_device(req: &DeviceRequest) -> std::result::Result<(String, String), ApiError> {
+    let name = required_name(Some(&req.name), "Device name")?;
+    let address = required_name(Some(&req.address), "Device address")?;
+    if !DEVICE_PROTOCOLS.contains(&req.protocol.as_str()) {
+        return Err(ApiError {
+            status: StatusCode::BAD_REQUEST,
+            error: format!("Unknown protocol '{}'", req.protocol),
+            details: Some(format!("Expected one of {}", DEVICE_PROTOCOLS.join(", "))),
+        });
+    }
+    Ok((name, address))
+}
+
+/// GET /v1/devices - List a profile's saved devices
+#[utoipa::path(get, path = "/v1/devices", tag = "devices", params(
+    ("profile_id" = Option<i64>, Query, description = "Profile to use; defaults to the active one"),
+), responses(
+    (status = 200, description = "Saved devices", body = Vec<ManagedDevice>),
+    (status = 404, description = "Profile not found", body = ErrorResponse),
+))]
+async fn list_devices(State(state): State<LibraryState>, Query(query): Query<ProfileQuery>) -> ApiResult {
+    debug!("GET /v1/devices: {:?}", query.profile_id);
+
+    let profile = target_profile(&state.pool, query.profile_id).await?;
+    let devices = ManagedDeviceRepository::new(state.pool.clone())
+        .list_by_profile(profile_id(&profile))
+        .await?;
+    Ok(Json(devices).into_response())
+}
+
+/// POST /v1/devices - Save a device (updates the one with the same protocol and address)
+#[utoipa::path(post, path = "/v1/devices", tag = "devices", request_body = DeviceRequest, responses(
+    (status = 201, description = "Device saved", body = ManagedDevice),
+    (status = 400, description = "Missing field or unknown protocol", body = ErrorResponse),
+    (status = 404, description = "Profile not found", body = ErrorResponse),
+))]
+async fn create_device(State(state): State<LibraryState>, Json(req): Json<DeviceRequest>) -> ApiResult {
+    info!("POST /v1/devices: {} {} at {}", req.protocol, req.name, req.address);
+
+    let (name, address) = validate_device(&req)?;
+    let profile = target_profile(&state.pool, req.profile_id).await?;
+    let now = Utc::now().timestamp();
+    let id = ManagedDeviceRepository::new(state.pool.clone())
+        .upsert(&ManagedDevice {
+            id: None,
+            profile_id: profile_id(&profile),
+            name,
+            protocol: req.protocol,
+            address,
+            source: "Manual".to_string(),
+            favorite: req.favorite,
+            last_seen: None,
+            created_at: now,
+            updated_at: now,
+        })
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(find_device(&state.pool, id).await?)).into_response())
+}
+
+/// GET /v1/devices/:id - Get a saved device
+#[utoipa::path(get, path = "/v1/devices/{id}", tag = "devices", params(
+    ("id" = i64, Path, description = "Device id"),
+), responses(
+    (status = 200, description = "The device", body = ManagedDevice),
+    (status = 404, description = "Device not found", body = ErrorResponse),
+))]
+async fn get_device(State(state): State<LibraryState>, Path(id): Path<i64>) -> ApiResult {
+    debug!("GET /v1/devices/{}", id);
+    Ok(Json(find_device(&state.pool, id).await?).into_response())
+}
+
+/// PUT /v1/devices/:id - Change a saved device
+#[utoipa::path(put, path = "/v1/devices/{id}", tag = "devices", request_body = DeviceRequest, params(
+    ("id" = i64, Path, description = "Device id"),
+), responses(
+    (status = 200, description = "Updated device", body = ManagedDevice),
+    (status = 400, description = "Missing field or unknown protocol", body = ErrorResponse),
+    (status = 404, description = "Device not found", body = ErrorResponse),
+    (status = 409, description = "Address taken", body = ErrorResponse),
+))]
+async fn update_device(
+    State(state): State<LibraryState>,
+    Path(id): Path<i64>,
+    Json(req): Json<DeviceRequest>,
+) -> ApiResult {
+    info!("PUT /v1/devices/{}", id);
+
+    let mut device = find_device(&state.pool, id).await?;
+    let (name, address) = validate_device(&req)?;
+    device.name = name;
+    device.protocol = req.protocol;
+    device.address = address;
+    device.favorite = req.favorite;
+
+    let repo = ManagedDeviceRepository::new(state.pool.clone());
+    let taken = repo
+        .list_by_profile(device.profile_id)
+        .await?
+        .into_iter()
+        .any(|other| other.id != Some(id) && other.protocol == device.protocol && other.address == device.address);
+    if taken {
+        return Err(ApiError::conflict(format!(
+            "Another {} device is saved at {}",
+            device.protocol, device.address
+        )));
+    }
+    repo.update(id, &device).await?;
+
+    Ok(Json(find_device(&state.pool, id).await?).into_response())
+}
+
+/// DELETE /v1/devices/:id - Forget a saved device
+#[utoipa::path(delete, path = "/v1/devices/{id}", tag = "devices", params(
+    ("id" = i64, Path, description = "Device id"),
+), responses(
+    (status = 200, description = "Device forgotten", body = SuccessResponse),
+    (status = 404, description = "Device not found", body = ErrorResponse),
+))]
+async fn delete_device(State(state): State<LibraryState>, Path(id): Path<i64>) -> ApiResult {
+    info!("DELETE /v1/devices/{}", id);
+
+    let device = find_device(&state.pool, id).await?;
+    ManagedDeviceRepository::new(state.pool.clone()).delete(id).await?;
+
+    Ok(success(format!("Deleted device '{}'", device.name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> DspSettings {
+        DspSettings::default()
+    }
+
+    #[test]
+    fn test_validate_dsp_ranges() {
+        assert!(validate_dsp(&settings()).is_ok());
+
+        let mut bad_rate = settings();
+        bad_rate.sample_rate = 12345;
+        assert_eq!(validate_dsp(&bad_rate).unwrap_err().status, StatusCode::BAD_REQUEST);
+
+        let mut bad_buffer = settings();
+        bad_buffer.buffer_ms = 0;
+        assert_eq!(validate_dsp(&bad_buffer).unwrap_err().status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_validate_dsp_exclusivity() {
+        let mut stacked = settings();
+        stacked.stereo_width_enabled = true;
+        stacked.crossfeed_enabled = true;
+        stacked.room_ambience_enabled = true;
+        stacked.tube_warmth_enabled = true;
+        stacked.limiter_enabled = true;
+        assert!(validate_dsp(&stacked).is_ok());
+
+        let mut conflicting = stacked.clone();
+        conflicting.compressor_enabled = true;
+        let error = validate_dsp(&conflicting).unwrap_err();
+        assert_eq!(error.status, StatusCode::CONFLICT);
+        assert!(error.error.contains("Compressor") || error.error.contains("Limiter"));
+    }
+
+    #[test]
+    fn test_validate_bands() {
+        let mut preset = EqPreset::default();
+        assert!(validate_bands(&preset).is_ok());
+
+        preset.bands[0].gain = 13.0;
+        assert!(validate_bands(&preset).is_err());
+
+        preset.bands.clear();
+        assert!(validate_bands(&preset).is_err());
+    }
+}
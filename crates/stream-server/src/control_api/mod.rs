@@ -1,9 +1,21 @@
 /// HTTP Control API for Stream Server
 ///
 /// Provides REST endpoints for controlling audio output routing and configuration
+pub mod auth;
+pub mod library;
+pub mod openapi;
 pub mod routes;
 pub mod server;
+pub mod streaming;
+pub mod tls;
 pub mod types;
+pub mod web;
 
+pub use auth::{generate_token, hash_token, issue_token};
+pub use openapi::ApiDoc;
+pub use library::{create_library_router, LibraryState, ProfileController};
+pub use routes::ApiRouter;
 pub use server::ControlServer;
+pub use streaming::{CaptureStreamer, InputStarter, StreamController, FILE_INPUT, SYSTEM_MIX_INPUT};
+pub use tls::TlsConfig;
 pub use types::*;
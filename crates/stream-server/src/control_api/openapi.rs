@@ -0,0 +1,193 @@
+/// OpenAPI document for the Control API
+///
+/// Generated from the handlers' `#[utoipa::path]` attributes and the request
+/// and response types, and served at `/v1/openapi.json` so integrations can
+/// generate clients. The library routes (which need a database) and
+/// `/v1/tokens` (which needs auth) are documented whether or not a server
+/// serves them.
+use super::{auth, library, routes};
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// Name of the bearer token security scheme
+const BEARER_SCHEME: &str = "bearer";
+
+/// The Control API's OpenAPI document; see [`OpenApi::openapi`]
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "AAEQ Control API",
+        description = "Control audio outputs and routing, and manage profiles, mappings, presets, DSP settings and devices.",
+        contact(name = "AAEQ", url = "https://github.com/jaschadub/AAEQ")
+    ),
+    paths(
+        routes::list_outputs,
+        routes::select_output,
+        routes::start_output,
+        routes::stop_output,
+        routes::get_metrics,
+        routes::get_route,
+        routes::set_route,
+        routes::get_capabilities,
+        routes::health_check,
+        routes::events_socket,
+        routes::prometheus_metrics,
+        routes::openapi_json,
+        library::list_profiles,
+        library::create_profile,
+        library::get_profile,
+        library::update_profile,
+        library::delete_profile,
+        library::activate_profile,
+        library::list_mappings,
+        library::save_mapping,
+        library::delete_mapping,
+        library::list_presets,
+        library::get_preset,
+        library::save_preset,
+        library::delete_preset,
+        library::apply_preset,
+        library::get_dsp,
+        library::update_dsp,
+        library::list_devices,
+        library::create_device,
+        library::get_device,
+        library::update_device,
+        library::delete_device,
+        auth::list_tokens,
+        auth::create_token,
+        auth::revoke_token,
+    ),
+    modifiers(&BearerAuth, &TrimSummaries),
+    security(("bearer" = [])),
+    tags(
+        (name = "outputs", description = "Output sinks and streaming"),
+        (name = "route", description = "Input to output routing"),
+        (name = "events", description = "Live event feed"),
+        (name = "profiles", description = "Listening profiles (needs a database)"),
+        (name = "mappings", description = "Song, album and genre preset rules (needs a database)"),
+        (name = "presets", description = "EQ presets (needs a database)"),
+        (name = "dsp", description = "Per-profile DSP settings (needs a database)"),
+        (name = "devices", description = "Saved devices (needs a database)"),
+        (name = "tokens", description = "API tokens (needs auth)"),
+        (name = "system", description = "Health, metrics and this document"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Adds the bearer token scheme the document's `security` refers to
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let mut http = Http::new(HttpAuthScheme::Bearer);
+        http.description = Some("Only checked when the server runs with auth; read tokens may only GET".to_string());
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(BEARER_SCHEME, SecurityScheme::Http(http));
+    }
+}
+
+/// Drops the "GET /v1/outputs - " the handlers' doc comments start with from their summaries
+struct TrimSummaries;
+
+impl Modify for TrimSummaries {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for item in openapi.paths.paths.values_mut() {
+            let operations = [&mut item.get, &mut item.post, &mut item.put, &mut item.patch, &mut item.delete];
+            for operation in operations.into_iter().flatten() {
+                let summary = operation.summary.as_deref().and_then(|summary| summary.split_once(" - "));
+                if let Some((_, summary)) = summary {
+                    operation.summary = Some(summary.to_string());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    const METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+
+    /// Every `.route(...)` the API routers register, as (method, OpenAPI path)
+    fn routed() -> BTreeSet<(String, String)> {
+        let sources = [include_str!("routes.rs"), include_str!("library.rs"), include_str!("auth.rs")];
+        let mut routes = BTreeSet::new();
+        for line in sources.iter().flat_map(|source| source.lines()) {
+            let Some(rest) = line.trim().strip_prefix(".route(\"") else {
+                continue;
+            };
+            let (path, handlers) = rest.split_once('"').unwrap();
+            // `/v1/presets/:name` is `/v1/presets/{name}` in OpenAPI
+            let path: Vec<String> = path
+                .split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(param) => format!("{{{}}}", param),
+                    None => segment.to_string(),
+                })
+                .collect();
+            for handler in handlers.trim_start_matches(',').trim().split('.') {
+                let method = handler.split('(').next().unwrap().trim();
+                assert!(METHODS.contains(&method), "unexpected method '{}' in: {}", method, line);
+                routes.insert((method.to_string(), path.join("/")));
+            }
+        }
+        routes
+    }
+
+    /// Every operation in the document, as (method, path)
+    fn documented() -> BTreeSet<(String, String)> {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let mut operations = BTreeSet::new();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for method in METHODS {
+                if item.get(method).is_some() {
+                    operations.insert((method.to_string(), path.clone()));
+                }
+            }
+        }
+        operations
+    }
+
+    #[test]
+    fn test_document_matches_routers() {
+        let routed = routed();
+        let documented = documented();
+        assert!(routed.len() > 30, "found only {} routes", routed.len());
+        assert_eq!(
+            routed.difference(&documented).collect::<Vec<_>>(),
+            Vec::<&(String, String)>::new(),
+            "routes missing from the OpenAPI document"
+        );
+        assert_eq!(
+            documented.difference(&routed).collect::<Vec<_>>(),
+            Vec::<&(String, String)>::new(),
+            "documented operations no router serves"
+        );
+    }
+
+    #[test]
+    fn test_document_components() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+        assert_eq!(document["components"]["securitySchemes"][BEARER_SCHEME]["scheme"], "bearer");
+
+        // Types used by the handlers are collected, with the ones they contain
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+        for name in ["OutputsResponse", "OutputConfig", "SampleFormat", "Profile", "EqPreset", "DspSettings", "IssuedToken"] {
+            assert!(schemas.contains_key(name), "missing schema {}", name);
+        }
+        assert_eq!(schemas["Scope"]["enum"], serde_json::json!(["song", "album", "genre", "default"]));
+
+        let list_outputs = &document["paths"]["/v1/outputs"]["get"];
+        assert_eq!(list_outputs["summary"], "List all output sinks and their status");
+        assert_eq!(list_outputs["operationId"], "list_outputs");
+
+        // Health and this document are open
+        assert_eq!(document["paths"]["/v1/health"]["get"]["security"], serde_json::json!([{}]));
+    }
+}
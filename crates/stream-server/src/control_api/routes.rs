@@ -1,16 +1,28 @@
 /// Route handlers for the Control API
+use super::openapi::ApiDoc;
+use super::streaming::{StreamController, FILE_INPUT};
 use super::types::*;
+use crate::events::{EventBus, StreamEvent};
 use crate::manager::OutputManager;
+use crate::telemetry::{ExpositionFormat, Telemetry};
+use crate::types::OutputConfig;
+use anyhow::Result;
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Json, Response},
-    routing::{get, post},
+    routing::{get, post, MethodRouter},
     Router,
 };
+use serde::Deserialize;
 use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
 use tokio::sync::RwLock;
 use tracing::{debug, error, info};
+use utoipa::OpenApi;
 
 /// Shared application state
 #[derive(Clone)]
@@ -18,9 +30,12 @@ pub struct AppState {
     pub manager: Arc<RwLock<OutputManager>>,
     pub metrics: Arc<RwLock<Metrics>>,
     pub route_config: Arc<RwLock<RouteConfig>>,
+    pub stream: Arc<dyn StreamController>,
+    pub events: EventBus,
+    pub telemetry: Telemetry,
 }
 
-/// Metrics tracking
+/// Metrics of the audio flow into the active output, reset when it starts
 #[derive(Debug, Clone, Default)]
 pub struct Metrics {
     pub underruns: u64,
@@ -34,11 +49,60 @@ pub struct RouteConfig {
     pub input: Option<String>,
     pub output: Option<String>,
     pub device: Option<String>,
+    /// Files, directories or playlists for the `File` input
+    pub files: Vec<String>,
+    /// Per-track files for the `file` output, if the route asked
+    pub split_tracks: Option<bool>,
+}
+
+/// A router that keeps the paths of its routes, so the server can list what it serves
+pub struct ApiRouter<S = ()> {
+    router: Router<S>,
+    paths: Vec<&'static str>,
+}
+
+impl<S: Clone + Send + Sync + 'static> Default for ApiRouter<S> {
+    fn default() -> Self {
+        Self {
+            router: Router::new(),
+            paths: Vec::new(),
+        }
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> ApiRouter<S> {
+    pub fn route(mut self, path: &'static str, method_router: MethodRouter<S>) -> Self {
+        self.router = self.router.route(path, method_router);
+        self.paths.push(path);
+        self
+    }
+
+    pub fn with_state<S2: Clone + Send + Sync + 'static>(self, state: S) -> ApiRouter<S2> {
+        ApiRouter {
+            router: self.router.with_state(state),
+            paths: self.paths,
+        }
+    }
+
+    pub fn merge(mut self, other: ApiRouter<S>) -> Self {
+        self.router = self.router.merge(other.router);
+        self.paths.extend(other.paths);
+        self
+    }
+
+    /// Paths in axum's syntax, e.g. `/v1/presets/:name`
+    pub fn paths(&self) -> &[&'static str] {
+        &self.paths
+    }
+
+    pub fn into_router(self) -> Router<S> {
+        self.router
+    }
 }
 
 /// Create the API router
-pub fn create_router(state: AppState) -> Router {
-    Router::new()
+pub fn create_router(state: AppState) -> ApiRouter {
+    ApiRouter::default()
         .route("/v1/outputs", get(list_outputs))
         .route("/v1/outputs/select", post(select_output))
         .route("/v1/outputs/start", post(start_output))
@@ -47,53 +111,52 @@ pub fn create_router(state: AppState) -> Router {
         .route("/v1/route", get(get_route).post(set_route))
         .route("/v1/capabilities", get(get_capabilities))
         .route("/v1/health", get(health_check))
+        .route("/v1/events", get(events_socket))
+        .route("/metrics", get(prometheus_metrics))
+        .route("/v1/openapi.json", get(openapi_json))
         .with_state(state)
 }
 
 /// GET /v1/outputs - List all output sinks and their status
+#[utoipa::path(get, path = "/v1/outputs", tag = "outputs", responses(
+    (status = 200, description = "Registered sinks", body = OutputsResponse),
+))]
 async fn list_outputs(State(state): State<AppState>) -> Response {
     debug!("GET /v1/outputs");
 
     let manager = state.manager.read().await;
-    let sink_names = manager.list_sinks();
-    let active_name = manager.active_sink_name();
-
-    let mut outputs = Vec::new();
-    for name in sink_names.iter() {
-        // Get sink info - in a real implementation, we'd need to enhance OutputManager
-        // to expose more details about each sink
-        let is_active = active_name == Some(name);
-
-        outputs.push(OutputInfo {
-            name: name.to_string(),
-            is_open: false, // Would need to track this in manager
-            is_active,
-            config: None,   // Would need to store configs
-            latency_ms: 0,  // Would need to query sink
-        });
-    }
+    let outputs = manager
+        .sink_status()
+        .into_iter()
+        .map(|status| OutputInfo {
+            name: status.name.to_string(),
+            is_open: status.is_open,
+            is_active: status.is_active,
+            config: status.config,
+            latency_ms: status.latency_ms,
+        })
+        .collect();
 
     let response = OutputsResponse {
         outputs,
-        active: active_name.map(|s| s.to_string()),
+        active: manager.active_sink_name().map(|s| s.to_string()),
     };
 
     Json(response).into_response()
 }
 
 /// POST /v1/outputs/select - Select and configure an output sink
+#[utoipa::path(post, path = "/v1/outputs/select", tag = "outputs", request_body = SelectOutputRequest, responses(
+    (status = 200, description = "Output selected", body = SelectOutputResponse),
+    (status = 500, description = "The sink couldn't be opened", body = ErrorResponse),
+))]
 async fn select_output(
     State(state): State<AppState>,
     Json(req): Json<SelectOutputRequest>,
 ) -> Response {
     info!("POST /v1/outputs/select: {}", req.name);
 
-    let mut manager = state.manager.write().await;
-
-    // Try to select the sink by name
-    let result = manager.select_sink_by_name(&req.name, req.config).await;
-
-    match result {
+    match select(&state, &req.name, req.config).await {
         Ok(_) => {
             let response = SelectOutputResponse {
                 success: true,
@@ -121,27 +184,86 @@ async fn select_output(
     }
 }
 
-/// POST /v1/outputs/start - Start the active output (if not already streaming)
-async fn start_output(State(_state): State<AppState>) -> Response {
+/// Select a sink, moving the audio flow over to it if it was streaming
+async fn select(state: &AppState, name: &str, config: OutputConfig) -> Result<()> {
+    let streaming = state.stream.is_streaming();
+    if streaming {
+        state.stream.stop().await?;
+    }
+
+    state.manager.write().await.select_sink_by_name(name, config).await?;
+
+    if streaming {
+        let route = state.route_config.read().await.clone();
+        state.stream.start(&route).await?;
+    }
+    Ok(())
+}
+
+/// POST /v1/outputs/start - Start streaming the route's input to the active output
+///
+/// Reopens the last selected output if it was stopped; 409 if there is none.
+#[utoipa::path(post, path = "/v1/outputs/start", tag = "outputs", responses(
+    (status = 200, description = "Streaming", body = SuccessResponse),
+    (status = 409, description = "No output selected", body = ErrorResponse),
+    (status = 500, description = "The output or input couldn't be started", body = ErrorResponse),
+))]
+async fn start_output(State(state): State<AppState>) -> Response {
     info!("POST /v1/outputs/start");
 
-    // In a real implementation, this would start audio streaming
-    // For now, we return success as opening the sink starts it
-    let response = SuccessResponse {
-        success: true,
-        message: "Output already started (started on select)".to_string(),
+    if state.stream.is_streaming() {
+        let response = SuccessResponse {
+            success: true,
+            message: "Output already streaming".to_string(),
+        };
+        return Json(response).into_response();
+    }
+
+    let route = state.route_config.read().await.clone();
+    let reopened = state.manager.write().await.reopen().await;
+    let result = match reopened {
+        Ok(_) => state.stream.start(&route).await,
+        Err(e) => Err(e),
     };
 
-    Json(response).into_response()
+    match result {
+        Ok(_) => {
+            let response = SuccessResponse {
+                success: true,
+                message: "Output started".to_string(),
+            };
+            Json(response).into_response()
+        }
+        Err(e) => {
+            error!("Failed to start output: {}", e);
+            // Nothing to stream to: no output was ever selected, or it couldn't be opened
+            let status = match state.manager.read().await.active_sink_name() {
+                Some(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                None => StatusCode::CONFLICT,
+            };
+            let response = ErrorResponse {
+                error: "Failed to start output".to_string(),
+                details: Some(e.to_string()),
+            };
+            (status, Json(response)).into_response()
+        }
+    }
 }
 
-/// POST /v1/outputs/stop - Stop the active output
+/// POST /v1/outputs/stop - Stop streaming and close the active output
+#[utoipa::path(post, path = "/v1/outputs/stop", tag = "outputs", responses(
+    (status = 200, description = "Stopped", body = SuccessResponse),
+    (status = 500, description = "The output couldn't be closed", body = ErrorResponse),
+))]
 async fn stop_output(State(state): State<AppState>) -> Response {
     info!("POST /v1/outputs/stop");
 
-    let mut manager = state.manager.write().await;
+    let result = match state.stream.stop().await {
+        Ok(_) => state.manager.write().await.close_active().await,
+        Err(e) => Err(e),
+    };
 
-    match manager.close_active().await {
+    match result {
         Ok(_) => {
             let response = SuccessResponse {
                 success: true,
@@ -161,65 +283,118 @@ async fn stop_output(State(state): State<AppState>) -> Response {
 }
 
 /// GET /v1/outputs/metrics - Get metrics for the active output
+#[utoipa::path(get, path = "/v1/outputs/metrics", tag = "outputs", responses(
+    (status = 200, description = "Active output metrics", body = MetricsResponse),
+))]
 async fn get_metrics(State(state): State<AppState>) -> Response {
     debug!("GET /v1/outputs/metrics");
 
-    let manager = state.manager.read().await;
-    let metrics = state.metrics.read().await;
-
-    let output_name = manager.active_sink_name().map(|s| s.to_string());
+    let status = state.manager.read().await.active_sink_status();
+    let metrics = state.metrics.read().await.clone();
+    let config = status.as_ref().and_then(|status| status.config.as_ref());
+    let stats = status.as_ref().map(|status| status.stats.clone()).unwrap_or_default();
 
     let response = MetricsResponse {
-        output_name,
-        sample_rate: None, // Would need to store active config
-        channels: None,
-        format: None,
-        latency_ms: 0, // Would need to query active sink
-        underruns: metrics.underruns,
-        overruns: metrics.overruns,
+        output_name: status.as_ref().map(|status| status.name.to_string()),
+        sample_rate: config.map(|config| config.sample_rate),
+        channels: config.map(|config| config.channels),
+        format: config.map(|config| format!("{:?}", config.format)),
+        latency_ms: status.as_ref().map_or(0, |status| status.latency_ms),
+        // Counted both by the flow (input side) and by the sink itself
+        underruns: metrics.underruns + stats.underruns,
+        overruns: metrics.overruns + stats.overruns,
         bytes_written: metrics.bytes_written,
+        frames_written: stats.frames_written,
+        buffer_fill: stats.buffer_fill,
+        streaming: state.stream.is_streaming(),
     };
 
     Json(response).into_response()
 }
 
+/// GET /metrics - Stream health for Prometheus, as OpenMetrics when the scraper asks for it
+#[utoipa::path(get, path = "/metrics", tag = "system", responses(
+    (status = 200, description = "Prometheus text exposition format, or OpenMetrics if accepted", body = String, content_type = "text/plain"),
+))]
+async fn prometheus_metrics(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    debug!("GET /metrics");
+
+    let accept = headers.get(header::ACCEPT).and_then(|accept| accept.to_str().ok()).unwrap_or_default();
+    let format = ExpositionFormat::from_accept(accept);
+    let sinks = state.manager.read().await.sink_status();
+    let flow = state.metrics.read().await.clone();
+    let body = state.telemetry.render(&sinks, &flow, state.stream.is_streaming(), format);
+
+    ([(header::CONTENT_TYPE, format.content_type())], body).into_response()
+}
+
+/// GET /v1/openapi.json - OpenAPI document describing this API
+#[utoipa::path(get, path = "/v1/openapi.json", tag = "system", security(()), responses(
+    (status = 200, description = "OpenAPI 3 document", body = Object),
+))]
+async fn openapi_json() -> Response {
+    debug!("GET /v1/openapi.json");
+    Json(ApiDoc::openapi()).into_response()
+}
+
 /// GET /v1/route - Get current routing configuration
+#[utoipa::path(get, path = "/v1/route", tag = "route", responses(
+    (status = 200, description = "Current route", body = RouteResponse),
+))]
 async fn get_route(State(state): State<AppState>) -> Response {
     debug!("GET /v1/route");
 
     let route = state.route_config.read().await;
-    let manager = state.manager.read().await;
 
     let response = RouteResponse {
         input: route.input.clone(),
         output: route.output.clone(),
         device: route.device.clone(),
-        is_active: manager.active_sink_name().is_some(),
+        files: route.files.clone(),
+        split_tracks: route.split_tracks,
+        is_active: state.stream.is_streaming(),
     };
 
     Json(response).into_response()
 }
 
 /// POST /v1/route - Set routing configuration
+#[utoipa::path(post, path = "/v1/route", tag = "route", request_body = RouteRequest, responses(
+    (status = 200, description = "Route saved, and the output selected if a config was given", body = SuccessResponse),
+    (status = 400, description = "`input: \"File\"` without files", body = ErrorResponse),
+    (status = 500, description = "The output couldn't be selected", body = ErrorResponse),
+))]
 async fn set_route(
     State(state): State<AppState>,
     Json(req): Json<RouteRequest>,
 ) -> Response {
     info!("POST /v1/route: {} -> {}", req.input, req.output);
 
+    let files = req.files.unwrap_or_default();
+    if req.input == FILE_INPUT && files.is_empty() {
+        let response = ErrorResponse {
+            error: "Invalid route".to_string(),
+            details: Some(format!("Input \"{}\" needs files to play", FILE_INPUT)),
+        };
+        return (StatusCode::BAD_REQUEST, Json(response)).into_response();
+    }
+
     // Update route configuration
     {
         let mut route = state.route_config.write().await;
         route.input = Some(req.input.clone());
         route.output = Some(req.output.clone());
         route.device = req.device.clone();
+        route.files = files;
+        route.split_tracks = req.split_tracks;
+    }
+    if let Some(split) = req.split_tracks {
+        state.manager.write().await.set_split_tracks(&req.output, split);
     }
 
     // If config provided, select the output
     if let Some(config) = req.config {
-        let mut manager = state.manager.write().await;
-
-        match manager.select_sink_by_name(&req.output, config).await {
+        match select(&state, &req.output, config).await {
             Ok(_) => {
                 let response = SuccessResponse {
                     success: true,
@@ -247,6 +422,9 @@ async fn set_route(
 }
 
 /// GET /v1/capabilities - Get supported capabilities for each output type
+#[utoipa::path(get, path = "/v1/capabilities", tag = "outputs", responses(
+    (status = 200, description = "What each output type supports", body = CapabilitiesResponse),
+))]
 async fn get_capabilities(State(_state): State<AppState>) -> Response {
     debug!("GET /v1/capabilities");
 
@@ -254,6 +432,10 @@ async fn get_capabilities(State(_state): State<AppState>) -> Response {
         OutputCapability::for_local_dac(),
         OutputCapability::for_dlna(),
         OutputCapability::for_airplay(),
+        OutputCapability::for_snapcast(),
+        OutputCapability::for_anp(),
+        OutputCapability::for_aes67(),
+        OutputCapability::for_file(),
     ];
 
     let response = CapabilitiesResponse {
@@ -264,11 +446,14 @@ async fn get_capabilities(State(_state): State<AppState>) -> Response {
 }
 
 /// GET /v1/health - Health check endpoint
+#[utoipa::path(get, path = "/v1/health", tag = "system", security(()), responses(
+    (status = 200, description = "The server is up", body = HealthResponse),
+))]
 async fn health_check() -> Response {
-    let response = serde_json::json!({
-        "status": "healthy",
-        "version": env!("CARGO_PKG_VERSION"),
-    });
+    let response = HealthResponse {
+        status: "healthy".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
 
     Json(response).into_response()
 }
@@ -292,5 +477,105 @@ mod tests {
         let airplay = OutputCapability::for_airplay();
         assert_eq!(airplay.name, "airplay");
         assert!(airplay.requires_device_discovery);
+
+        let snapcast = OutputCapability::for_snapcast();
+        assert_eq!(snapcast.name, "snapcast");
+        assert!(!snapcast.requires_device_discovery);
+
+        let anp = OutputCapability::for_anp();
+        assert_eq!(anp.name, "anp");
+        assert!(anp.requires_device_discovery);
+
+        let aes67 = OutputCapability::for_aes67();
+        assert_eq!(aes67.name, "aes67");
+        assert!(!aes67.requires_device_discovery);
+
+        let file = OutputCapability::for_file();
+        assert_eq!(file.name, "file");
+        assert!(!file.requires_device_discovery);
+    }
+}
+
+/// Selects the events `/v1/events` sends
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    /// Comma-separated event types, e.g. `track_changed,preset_applied`
+    types: Option<String>,
+}
+
+/// GET /v1/events - WebSocket feed of events as JSON text messages
+#[utoipa::path(get, path = "/v1/events", tag = "events", params(
+    ("types" = Option<String>, Query, description = "Comma-separated event types to receive, e.g. `track_changed,preset_applied`"),
+), responses(
+    (status = 101, description = "WebSocket of JSON events, starting with the latest track and preset"),
+))]
+async fn events_socket(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let types: Option<Vec<String>> = query.types.map(|types| {
+        types
+            .split(',')
+            .map(|kind| kind.trim().to_string())
+            .filter(|kind| !kind.is_empty())
+            .collect()
+    });
+    info!("GET /v1/events: {:?}", types);
+
+    // Subscribe now so nothing is missed while the connection upgrades
+    let events = state.events.subscribe();
+    let latest = state.events.latest();
+    ws.on_upgrade(move |socket| forward_events(socket, events, latest, types))
+}
+
+/// Send the latest track and preset, then events as they happen until the client goes away
+///
+/// Clients only listen.
+async fn forward_events(
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<StreamEvent>,
+    latest: Vec<StreamEvent>,
+    types: Option<Vec<String>>,
+) {
+    for event in latest {
+        if !send_event(&mut socket, &event, types.as_deref()).await {
+            return;
+        }
+    }
+
+    loop {
+        let event = tokio::select! {
+            received = events.recv() => match received {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => StreamEvent::Lagged { missed },
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        if !send_event(&mut socket, &event, types.as_deref()).await {
+            break;
+        }
+    }
+    debug!("Event client disconnected");
+}
+
+/// Send an event if the client wants it; false once the client is gone
+async fn send_event(socket: &mut WebSocket, event: &StreamEvent, types: Option<&[String]>) -> bool {
+    // Clients always hear that they missed something
+    let wanted = types.is_none_or(|types| types.iter().any(|kind| kind == event.kind()));
+    if !wanted && !matches!(event, StreamEvent::Lagged { .. }) {
+        return true;
+    }
+    match serde_json::to_string(event) {
+        Ok(text) => socket.send(Message::Text(text)).await.is_ok(),
+        Err(e) => {
+            error!("Failed to encode {} event: {}", event.kind(), e);
+            true
+        }
     }
 }
@@ -1,18 +1,44 @@
 /// Control API Server implementation
+use super::auth::{create_token_router, require_token, warn_if_no_tokens, AuthState};
+use super::library::{create_library_router, LibraryState, ProfileController};
 use super::routes::{create_router, AppState, Metrics, RouteConfig};
+use super::streaming::{CaptureStreamer, StreamController};
+use super::tls::{serve_tls, TlsConfig};
+use super::web::create_web_router;
+use crate::events::EventBus;
 use crate::manager::OutputManager;
-use anyhow::Result;
+use crate::telemetry::Telemetry;
+use anyhow::{Context, Result};
+use axum::http::{header, HeaderValue, Method};
+use sqlx::SqlitePool;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tracing::{info, error};
 
 /// HTTP Control API Server
 pub struct ControlServer {
     addr: SocketAddr,
     manager: Arc<RwLock<OutputManager>>,
+    metrics: Arc<RwLock<Metrics>>,
+    route_config: Arc<RwLock<RouteConfig>>,
+    /// Defaults to a [`CaptureStreamer`] when started
+    stream: Option<Arc<dyn StreamController>>,
+    events: EventBus,
+    telemetry: Telemetry,
+    pool: Option<SqlitePool>,
+    profiles: Option<Arc<dyn ProfileController>>,
+    /// Tokens are checked against this database when set
+    auth: Option<SqlitePool>,
+    tls: Option<TlsConfig>,
+    cors_origins: Vec<String>,
+    /// Paths of the API routes being served
+    api_paths: Vec<&'static str>,
     server_handle: Option<JoinHandle<()>>,
+    /// Counts clips and preset switches from the events for `/metrics`
+    telemetry_handle: Option<JoinHandle<()>>,
 }
 
 impl ControlServer {
@@ -21,37 +47,173 @@ impl ControlServer {
     /// # Arguments
     /// * `addr` - Address to bind to (e.g., "127.0.0.1:8080")
     /// * `manager` - Shared output manager
+    ///
+    /// `/v1/outputs/start` captures the route's input with a [`CaptureStreamer`].
+    /// The built-in web UI is served at `/`.
     pub fn new(addr: SocketAddr, manager: Arc<RwLock<OutputManager>>) -> Self {
         Self {
             addr,
             manager,
+            metrics: Arc::new(RwLock::new(Metrics::default())),
+            route_config: Arc::new(RwLock::new(RouteConfig::default())),
+            stream: None,
+            events: EventBus::new(),
+            telemetry: Telemetry::new(),
+            pool: None,
+            profiles: None,
+            auth: None,
+            tls: None,
+            cors_origins: Vec::new(),
+            api_paths: Vec::new(),
             server_handle: None,
+            telemetry_handle: None,
         }
     }
 
+    /// Start and stop the audio flow with another controller
+    pub fn with_stream_controller(mut self, stream: Arc<dyn StreamController>) -> Self {
+        self.stream = Some(stream);
+        self
+    }
+
+    /// Send `events` to `/v1/events` clients, e.g. to share them with the code driving playback
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Events sent to `/v1/events` clients, for custom stream controllers to publish on
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// Report these counters at `/metrics`, e.g. to share them with the code polling and discovering devices
+    pub fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
+
+    /// Counters reported at `/metrics`
+    pub fn telemetry(&self) -> Telemetry {
+        self.telemetry.clone()
+    }
+
+    /// Serve profiles, mappings, presets, DSP settings and devices from this database
+    pub fn with_database(mut self, pool: SqlitePool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Switch profile and apply presets live through this controller
+    pub fn with_profile_controller(mut self, profiles: Arc<dyn ProfileController>) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
+
+    /// Require a bearer token stored in this database on every request but `/v1/health`, `/v1/openapi.json` and the web UI
+    ///
+    /// Also serves `/v1/tokens` for managing tokens.
+    pub fn with_auth(mut self, pool: SqlitePool) -> Self {
+        self.auth = Some(pool);
+        self
+    }
+
+    /// Serve HTTPS with this certificate instead of plain HTTP
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Let browser pages from these origins call the API (`*` allows any origin)
+    pub fn with_cors(mut self, origins: Vec<String>) -> Self {
+        self.cors_origins = origins;
+        self
+    }
+
+    /// Flow metrics reported by `/v1/outputs/metrics`, for custom stream controllers to update
+    pub fn metrics(&self) -> Arc<RwLock<Metrics>> {
+        self.metrics.clone()
+    }
+
     /// Start the control server
     pub async fn start(&mut self) -> Result<()> {
         info!("Starting Control API server on {}", self.addr);
 
+        let stream = match &self.stream {
+            Some(stream) => stream.clone(),
+            None => {
+                let capture = CaptureStreamer::new(self.manager.clone(), self.metrics.clone())
+                    .with_events(self.events.clone());
+                let stream: Arc<dyn StreamController> = Arc::new(capture);
+                self.stream = Some(stream.clone());
+                stream
+            }
+        };
+
+        // Outputs opening and closing are announced to event clients
+        self.manager.write().await.set_events(self.events.clone());
+
         let state = AppState {
             manager: self.manager.clone(),
-            metrics: Arc::new(RwLock::new(Metrics::default())),
-            route_config: Arc::new(RwLock::new(RouteConfig::default())),
+            metrics: self.metrics.clone(),
+            route_config: self.route_config.clone(),
+            stream,
+            events: self.events.clone(),
+            telemetry: self.telemetry.clone(),
         };
 
-        let app = create_router(state);
+        let mut api = create_router(state);
+        if let Some(pool) = &self.pool {
+            api = api.merge(create_library_router(LibraryState {
+                pool: pool.clone(),
+                controller: self.profiles.clone(),
+            }));
+        }
+        if let Some(pool) = &self.auth {
+            api = api.merge(create_token_router(pool.clone()));
+        }
+        self.api_paths = api.paths().to_vec();
+
+        let mut app = api.into_router().merge(create_web_router());
+        if let Some(pool) = &self.auth {
+            warn_if_no_tokens(pool).await;
+            app = app.layer(axum::middleware::from_fn_with_state(AuthState::new(pool.clone()), require_token));
+        }
+        // Outside the auth check, so preflight requests (which carry no token) are answered
+        if !self.cors_origins.is_empty() {
+            app = app.layer(cors_layer(&self.cors_origins)?);
+        }
 
         let listener = tokio::net::TcpListener::bind(self.addr).await?;
-        info!("Control API listening on {}", self.addr);
+        // Resolves port 0 to the port actually bound
+        self.addr = listener.local_addr()?;
 
-        // Spawn server task
-        let handle = tokio::spawn(async move {
-            if let Err(e) = axum::serve(listener, app).await {
-                error!("Control API server error: {}", e);
+        let handle = match &self.tls {
+            Some(tls) => {
+                let acceptor = tls.acceptor()?;
+                info!(
+                    "Control API listening on https://{} (certificate SHA-256 {})",
+                    self.addr,
+                    tls.fingerprint()?
+                );
+                tokio::spawn(async move {
+                    if let Err(e) = serve_tls(listener, acceptor, app).await {
+                        error!("Control API server error: {}", e);
+                    }
+                })
             }
-        });
+            None => {
+                info!("Control API listening on http://{}", self.addr);
+                tokio::spawn(async move {
+                    if let Err(e) = axum::serve(listener, app).await {
+                        error!("Control API server error: {}", e);
+                    }
+                })
+            }
+        };
 
         self.server_handle = Some(handle);
+        self.telemetry_handle = Some(self.telemetry.watch(&self.events));
 
         Ok(())
     }
@@ -62,12 +224,42 @@ impl ControlServer {
             info!("Stopping Control API server");
             handle.abort();
         }
+        if let Some(handle) = self.telemetry_handle.take() {
+            handle.abort();
+        }
+        if let Some(stream) = &self.stream {
+            if let Err(e) = stream.stop().await {
+                error!("Failed to stop streaming: {}", e);
+            }
+        }
     }
 
-    /// Get the server address
+    /// Get the server address (the bound address once started)
     pub fn addr(&self) -> SocketAddr {
         self.addr
     }
+
+    /// Paths of the API routes being served (not the web UI's), in axum's
+    /// syntax, e.g. `/v1/presets/:name`; empty until started
+    pub fn api_paths(&self) -> &[&'static str] {
+        &self.api_paths
+    }
+}
+
+/// CORS for the given origins, allowing the methods and headers the API uses
+fn cors_layer(origins: &[String]) -> Result<CorsLayer> {
+    let layer = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]);
+    if origins.iter().any(|origin| origin == "*") {
+        return Ok(layer.allow_origin(Any));
+    }
+
+    let origins = origins
+        .iter()
+        .map(|origin| HeaderValue::from_str(origin).with_context(|| format!("Invalid CORS origin: {}", origin)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(layer.allow_origin(AllowOrigin::list(origins)))
 }
 
 impl Drop for ControlServer {
@@ -75,6 +267,9 @@ impl Drop for ControlServer {
         if let Some(handle) = self.server_handle.take() {
             handle.abort();
         }
+        if let Some(handle) = self.telemetry_handle.take() {
+            handle.abort();
+        }
     }
 }
 
@@ -90,4 +285,11 @@ mod tests {
 
         assert_eq!(server.addr(), addr);
     }
+
+    #[test]
+    fn test_cors_origins() {
+        assert!(cors_layer(&["*".to_string()]).is_ok());
+        assert!(cors_layer(&["http://localhost:5173".to_string()]).is_ok());
+        assert!(cors_layer(&["http://bad\norigin".to_string()]).is_err());
+    }
 }
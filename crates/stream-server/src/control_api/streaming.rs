@@ -0,0 +1,215 @@
+/// Audio flow control for the Control API
+///
+/// `/v1/outputs/start` and `/v1/outputs/stop` drive a [`StreamController`].
+/// The default, [`CaptureStreamer`], captures the route's input (or plays its
+/// files) and writes it to the manager's active sink, keeping the API's
+/// [`Metrics`] current and publishing meter frames of what it streams.
+use super::routes::{Metrics, RouteConfig};
+use crate::events::{EventBus, TrackFollower};
+use crate::input::{FileInput, FileInputEvent, LocalDacInput};
+use crate::manager::SharedOutputManager;
+use crate::pump::{Chunk, StreamPump};
+use crate::types::OutputConfig;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Route input meaning the default capture device
+pub const SYSTEM_MIX_INPUT: &str = "SystemMix";
+
+/// Route input that plays the route's files with [`FileInput`]
+pub const FILE_INPUT: &str = "File";
+
+/// Blocks of captured audio queued for the output
+const INPUT_QUEUE_BLOCKS: usize = 32;
+
+/// Starts and stops the audio flowing into the active output
+#[async_trait]
+pub trait StreamController: Send + Sync {
+    /// Start feeding the route's input to the active output
+    async fn start(&self, route: &RouteConfig) -> Result<()>;
+
+    /// Stop the flow; the output stays open
+    async fn stop(&self) -> Result<()>;
+
+    fn is_streaming(&self) -> bool;
+}
+
+/// Starts an input feeding interleaved samples to the sender, returning a
+/// sender that stops it (the signature of [`LocalDacInput::start_capture`])
+pub type InputStarter =
+    Arc<dyn Fn(Option<String>, OutputConfig, mpsc::Sender<Vec<f64>>) -> Result<mpsc::Sender<()>> + Send + Sync>;
+
+/// Streams a capture device, or the route's files, to the active sink
+pub struct CaptureStreamer {
+    manager: SharedOutputManager,
+    metrics: Arc<RwLock<Metrics>>,
+    start_input: InputStarter,
+    events: Option<EventBus>,
+    flow: Mutex<Option<Flow>>,
+}
+
+struct Flow {
+    stop_input: mpsc::Sender<()>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl CaptureStreamer {
+    /// Capture with [`LocalDacInput`]; the route's input names the device
+    pub fn new(manager: SharedOutputManager, metrics: Arc<RwLock<Metrics>>) -> Self {
+        Self {
+            manager,
+            metrics,
+            start_input: Arc::new(LocalDacInput::start_capture),
+            events: None,
+            flow: Mutex::new(None),
+        }
+    }
+
+    /// Use another input, e.g. a test signal
+    pub fn with_input(mut self, start_input: InputStarter) -> Self {
+        self.start_input = start_input;
+        self
+    }
+
+    /// Publish meter frames of the streamed audio, and the tracks files play, on `events`
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Play `files` with [`FileInput`], sending each track as a [`Chunk::Track`]
+    /// at the frame it starts
+    fn start_files(&self, files: &[String], config: OutputConfig, tx: mpsc::Sender<Chunk>) -> Result<mpsc::Sender<()>> {
+        let input = FileInput::new(files)?;
+        let channels = config.channels.max(1) as usize;
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let (audio_tx, mut audio_rx) = mpsc::channel(INPUT_QUEUE_BLOCKS);
+        let stop_input = input.start_playback(config, audio_tx, events_tx)?;
+
+        tokio::spawn(async move {
+            // Tracks waiting for their first frame, with where it starts
+            let mut upcoming = VecDeque::new();
+            let mut frames_sent = 0u64;
+            loop {
+                let mut samples: Vec<f64> = tokio::select! {
+                    // The player sends a track's event before its audio
+                    biased;
+                    Some(event) = events_rx.recv() => {
+                        match event {
+                            FileInputEvent::TrackStarted { meta, position, .. } => upcoming.push_back((position, meta)),
+                            FileInputEvent::TrackFailed { path, error, .. } => {
+                                warn!("Skipping {}: {}", path.display(), error);
+                            }
+                            FileInputEvent::Finished { .. } => {}
+                        }
+                        continue;
+                    }
+                    Some(samples) = audio_rx.recv() => samples,
+                    else => break,
+                };
+
+                // Blocks run across track boundaries; split them where each track starts
+                let mut chunks = Vec::new();
+                while let Some((position, _)) = upcoming.front() {
+                    let offset = position.saturating_sub(frames_sent) as usize * channels;
+                    if offset >= samples.len() {
+                        break;
+                    }
+                    let rest = samples.split_off(offset);
+                    frames_sent += (samples.len() / channels) as u64;
+                    if !samples.is_empty() {
+                        chunks.push(Chunk::Audio(samples));
+                    }
+                    let (_, track) = upcoming.pop_front().unwrap();
+                    chunks.push(Chunk::Track(track));
+                    samples = rest;
+                }
+                frames_sent += (samples.len() / channels) as u64;
+                chunks.push(Chunk::Audio(samples));
+
+                for chunk in chunks {
+                    if tx.send(chunk).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(stop_input)
+    }
+}
+
+#[async_trait]
+impl StreamController for CaptureStreamer {
+    async fn start(&self, route: &RouteConfig) -> Result<()> {
+        if self.is_streaming() {
+            return Ok(());
+        }
+        // Clean up a flow whose input ended
+        self.stop().await?;
+
+        let config = self
+            .manager
+            .read()
+            .await
+            .active_sink_config()
+            .cloned()
+            .ok_or_else(|| anyhow!("No output selected"))?;
+        let files = route.input.as_deref() == Some(FILE_INPUT);
+        let device = route.input.clone().filter(|input| input != SYSTEM_MIX_INPUT && !files);
+
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let mut pump = StreamPump::new(self.manager.clone(), config.clone()).with_metrics(self.metrics.clone());
+        if let Some(events) = &self.events {
+            pump = pump.with_events(events.clone());
+        }
+        let (stop_input, task) = if files {
+            // The files' tracks come with their audio
+            let (tx, rx) = mpsc::channel(INPUT_QUEUE_BLOCKS);
+            let stop_input = self.start_files(&route.files, config, tx)?;
+            (stop_input, tokio::spawn(pump.run(rx, shutdown_rx)))
+        } else {
+            let (tx, rx) = mpsc::channel(INPUT_QUEUE_BLOCKS);
+            let stop_input = (self.start_input)(device.clone(), config, tx)?;
+            let pump = pump.with_track_follower(TrackFollower::new(self.events.as_ref()));
+            (stop_input, tokio::spawn(pump.run(rx, shutdown_rx)))
+        };
+        *self.metrics.write().await = Metrics::default();
+        let source = match (files, device.as_deref()) {
+            (true, _) => format!("{} file source(s)", route.files.len()),
+            (false, device) => device.unwrap_or("default input").to_string(),
+        };
+        info!("Streaming {} to the active output", source);
+
+        *self.flow.lock().unwrap() = Some(Flow {
+            stop_input,
+            shutdown,
+            task,
+        });
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<()> {
+        let flow = self.flow.lock().unwrap().take();
+        if let Some(flow) = flow {
+            let _ = flow.stop_input.try_send(());
+            let _ = flow.shutdown.send(());
+            let _ = flow.task.await;
+            info!("Streaming stopped");
+        }
+        Ok(())
+    }
+
+    fn is_streaming(&self) -> bool {
+        self.flow
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|flow| !flow.task.is_finished())
+    }
+}
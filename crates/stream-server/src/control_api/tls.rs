@@ -0,0 +1,237 @@
+/// TLS for the Control API
+///
+/// The server can use any PEM certificate and key. On first run it can also
+/// generate a self-signed ECDSA P-256 certificate for `localhost`, the
+/// loopback addresses, the host name and any extra names given; clients pin
+/// it by its SHA-256 fingerprint, which is logged at startup.
+use anyhow::{Context, Result};
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::service::TowerToHyperService;
+use rand::RngCore;
+use rcgen::{
+    CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+    SerialNumber, PKCS_ECDSA_P256_SHA256,
+};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{self, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info};
+
+/// File names of a generated certificate and key
+pub const CERT_FILE: &str = "control-api.crt";
+pub const KEY_FILE: &str = "control-api.key";
+
+/// How long a generated certificate is valid
+const VALIDITY_DAYS: i64 = 3650;
+
+/// Common name of a generated certificate
+const COMMON_NAME: &str = "AAEQ Control API";
+
+/// Where the server's certificate chain and private key are
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    /// Use the certificate in `dir`, generating a self-signed one there first if there is none
+    ///
+    /// `extra_names` are host names or IP addresses the certificate is also valid for.
+    pub fn self_signed(dir: &Path, extra_names: &[String]) -> Result<Self> {
+        let config = Self::new(dir.join(CERT_FILE), dir.join(KEY_FILE));
+        if config.cert_path.exists() && config.key_path.exists() {
+            return Ok(config);
+        }
+
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let (cert_pem, key_pem) = generate_self_signed(&default_names(extra_names))?;
+        write_private(&config.key_path, key_pem.as_bytes())?;
+        std::fs::write(&config.cert_path, cert_pem)
+            .with_context(|| format!("Failed to write {}", config.cert_path.display()))?;
+        info!("Generated self-signed Control API certificate at {}", config.cert_path.display());
+
+        Ok(config)
+    }
+
+    /// SHA-256 fingerprint of the server certificate, as colon-separated hex
+    pub fn fingerprint(&self) -> Result<String> {
+        let cert = self.load_certs()?.into_iter().next().context("No certificate found")?;
+        Ok(fingerprint(&cert))
+    }
+
+    fn load_certs(&self) -> Result<Vec<CertificateDer<'static>>> {
+        CertificateDer::pem_file_iter(&self.cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("Failed to read certificate {}", self.cert_path.display()))
+    }
+
+    /// A TLS acceptor serving this certificate over HTTP/2 and HTTP/1.1
+    pub fn acceptor(&self) -> Result<TlsAcceptor> {
+        let certs = self.load_certs()?;
+        let key = PrivateKeyDer::from_pem_file(&self.key_path)
+            .with_context(|| format!("Failed to read private key {}", self.key_path.display()))?;
+
+        let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("Certificate and private key don't match")?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Serve `app` over TLS on `listener` until the task is aborted
+pub async fn serve_tls(listener: TcpListener, acceptor: TlsAcceptor, app: Router) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(app.clone());
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Control API connection from {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// `localhost`, the loopback addresses and this host's name, plus `extra_names`
+fn default_names(extra_names: &[String]) -> Vec<String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+    if let Some(host) = hostname::get().ok().and_then(|h| h.into_string().ok()) {
+        names.push(host);
+    }
+    names.extend(extra_names.iter().cloned());
+
+    let mut unique = Vec::new();
+    for name in names {
+        if !name.is_empty() && !unique.contains(&name) {
+            unique.push(name);
+        }
+    }
+    unique
+}
+
+/// Generate a self-signed certificate for `names`, returning the certificate and PKCS#8 key as PEM
+///
+/// The certificate is for servers only: not a CA, with the digital signature
+/// key usage and the server authentication extended key usage.
+pub fn generate_self_signed(names: &[String]) -> Result<(String, String)> {
+    let mut params = CertificateParams::new(names.to_vec())
+        .context("Certificate names must be ASCII host names or IP addresses")?;
+    let mut subject = DistinguishedName::new();
+    subject.push(DnType::CommonName, COMMON_NAME);
+    params.distinguished_name = subject;
+
+    let mut serial = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut serial);
+    // Positive, and without a leading zero byte
+    serial[0] = (serial[0] & 0x7f) | 0x40;
+    params.serial_number = Some(SerialNumber::from_slice(&serial));
+
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(VALIDITY_DAYS);
+    params.is_ca = IsCa::ExplicitNoCa;
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+
+    let key_pair = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).context("Failed to generate key pair")?;
+    let cert = params.self_signed(&key_pair).context("Failed to sign certificate")?;
+
+    Ok((cert.pem(), key_pair.serialize_pem()))
+}
+
+fn fingerprint(cert: &[u8]) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Write a file only its owner can read
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_is_for_servers_only() {
+        let (cert, _) = generate_self_signed(&["localhost".to_string(), "127.0.0.1".to_string()]).unwrap();
+        let der = CertificateDer::from_pem_slice(cert.as_bytes()).unwrap();
+        let contains = |bytes: &[u8]| der.windows(bytes.len()).any(|window| window == bytes);
+        // basicConstraints, keyUsage and extKeyUsage with id-kp-serverAuth
+        assert!(contains(&[0x06, 0x03, 0x55, 0x1d, 0x13]));
+        assert!(contains(&[0x06, 0x03, 0x55, 0x1d, 0x0f]));
+        assert!(contains(&[0x06, 0x03, 0x55, 0x1d, 0x25]));
+        assert!(contains(&[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01]));
+
+        assert!(generate_self_signed(&["bad name\u{e9}".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_self_signed_certificate_loads() {
+        let dir = std::env::temp_dir().join(format!("aaeq-tls-test-{}", std::process::id()));
+        let config = TlsConfig::self_signed(&dir, &["aaeq.local".to_string(), "192.168.1.20".to_string()]).unwrap();
+        let fingerprint = config.fingerprint().unwrap();
+        assert_eq!(fingerprint.len(), 32 * 3 - 1);
+        assert!(config.acceptor().is_ok());
+
+        // Generated once, then reused
+        let again = TlsConfig::self_signed(&dir, &[]).unwrap();
+        assert_eq!(again.fingerprint().unwrap(), fingerprint);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&config.key_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,16 +1,18 @@
 /// Type definitions for the Control API
 use crate::types::OutputConfig;
+use aaeq_core::{ApiToken, EqBand, Profile, Scope, TokenScope};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 /// Response for GET /v1/outputs
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct OutputsResponse {
     pub outputs: Vec<OutputInfo>,
     pub active: Option<String>,
 }
 
 /// Information about an available output sink
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct OutputInfo {
     pub name: String,
     pub is_open: bool,
@@ -20,7 +22,7 @@ pub struct OutputInfo {
 }
 
 /// Request for POST /v1/outputs/select
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SelectOutputRequest {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -29,7 +31,7 @@ pub struct SelectOutputRequest {
 }
 
 /// Response for POST /v1/outputs/select
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SelectOutputResponse {
     pub success: bool,
     pub message: String,
@@ -37,7 +39,7 @@ pub struct SelectOutputResponse {
 }
 
 /// Response for GET /v1/outputs/metrics
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct MetricsResponse {
     pub output_name: Option<String>,
     pub sample_rate: Option<u32>,
@@ -47,36 +49,53 @@ pub struct MetricsResponse {
     pub underruns: u64,
     pub overruns: u64,
     pub bytes_written: u64,
+    /// Frames written to the active sink
+    pub frames_written: u64,
+    /// Active sink's buffer fill level (0.0 to 1.0)
+    pub buffer_fill: f32,
+    /// Whether audio is flowing into the active sink
+    pub streaming: bool,
 }
 
 /// Request for POST /v1/route
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct RouteRequest {
-    pub input: String,  // "SystemMix" | "App" | "File"
-    pub output: String, // "dlna" | "dac" | "airplay"
+    pub input: String,  // "SystemMix" | capture device name | "File"
+    pub output: String, // "dlna" | "dac" | "airplay" | "snapcast" | "anp" | "aes67" | "file"
     #[serde(skip_serializing_if = "Option::is_none")]
     pub device: Option<String>,
+    /// Files, directories or playlists to play; required for `input: "File"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<String>>,
+    /// For the `file` output: record each track to its own file in the output's directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split_tracks: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub config: Option<OutputConfig>,
 }
 
 /// Response for GET /v1/route
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct RouteResponse {
     pub input: Option<String>,
     pub output: Option<String>,
     pub device: Option<String>,
+    /// What `input: "File"` plays
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split_tracks: Option<bool>,
     pub is_active: bool,
 }
 
 /// Response for GET /v1/capabilities
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CapabilitiesResponse {
     pub outputs: Vec<OutputCapability>,
 }
 
 /// Capability information for an output type
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct OutputCapability {
     pub name: String,
     pub supported_sample_rates: Vec<u32>,
@@ -87,15 +106,97 @@ pub struct OutputCapability {
     pub requires_device_discovery: bool,
 }
 
+/// Response for GET /v1/profiles
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ProfilesResponse {
+    pub profiles: Vec<Profile>,
+    pub active_id: i64,
+}
+
+/// Request for POST /v1/profiles and PUT /v1/profiles/:id
+///
+/// `name` is required to create; fields left out keep their current value on update.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ProfileRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+/// Request for POST /v1/mappings
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MappingRequest {
+    pub scope: Scope,
+    /// "artist - title" for songs, "artist - album" for albums, the genre for genres; none for the default rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub preset: String,
+    /// Defaults to the active profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile_id: Option<i64>,
+}
+
+/// Response for GET /v1/presets
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PresetsResponse {
+    pub builtin: Vec<String>,
+    pub custom: Vec<String>,
+}
+
+/// Request for PUT /v1/presets/:name
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PresetRequest {
+    pub bands: Vec<EqBand>,
+}
+
+/// Request for POST /v1/devices
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeviceRequest {
+    pub name: String,
+    pub protocol: String, // "WiimApi" | "LocalDac" | "Dlna" | "AirPlay" | "AnpNode"
+    pub address: String,
+    #[serde(default)]
+    pub favorite: bool,
+    /// Defaults to the active profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile_id: Option<i64>,
+}
+
+/// Request for POST /v1/tokens
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TokenRequest {
+    pub name: String,
+    pub scope: TokenScope,
+}
+
+/// Response for POST /v1/tokens; the token itself is only ever shown here
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct IssuedToken {
+    #[serde(flatten)]
+    pub info: ApiToken,
+    pub token: String,
+}
+
+/// Response for GET /v1/health
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct HealthResponse {
+    /// Always "healthy" when the server answers
+    pub status: String,
+    pub version: String,
+}
+
 /// Generic success response
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SuccessResponse {
     pub success: bool,
     pub message: String,
 }
 
 /// Error response
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ErrorResponse {
     pub error: String,
     pub details: Option<String>,
@@ -141,4 +242,57 @@ impl OutputCapability {
             requires_device_discovery: true,
         }
     }
+
+    pub fn for_snapcast() -> Self {
+        Self {
+            name: "snapcast".to_string(),
+            supported_sample_rates: vec![44100, 48000],
+            supported_formats: vec!["S16LE".to_string()],
+            min_channels: 1,
+            max_channels: 2,
+            supports_exclusive: false,
+            requires_device_discovery: false,
+        }
+    }
+
+    pub fn for_anp() -> Self {
+        Self {
+            name: "anp".to_string(),
+            supported_sample_rates: vec![44100, 48000, 88200, 96000, 192000],
+            supported_formats: vec!["S16LE".to_string(), "S24LE".to_string()],
+            min_channels: 1,
+            max_channels: 2,
+            supports_exclusive: false,
+            requires_device_discovery: true,
+        }
+    }
+
+    pub fn for_aes67() -> Self {
+        Self {
+            name: "aes67".to_string(),
+            supported_sample_rates: vec![44100, 48000, 96000],
+            supported_formats: vec!["S16LE".to_string(), "S24LE".to_string()],
+            min_channels: 1,
+            max_channels: 8,
+            supports_exclusive: false,
+            requires_device_discovery: false,
+        }
+    }
+
+    pub fn for_file() -> Self {
+        Self {
+            name: "file".to_string(),
+            supported_sample_rates: vec![44100, 48000, 88200, 96000, 176400, 192000],
+            supported_formats: vec![
+                "F64".to_string(),
+                "F32".to_string(),
+                "S24LE".to_string(),
+                "S16LE".to_string(),
+            ],
+            min_channels: 1,
+            max_channels: 8,
+            supports_exclusive: false,
+            requires_device_discovery: false,
+        }
+    }
 }
@@ -0,0 +1,53 @@
+/// Built-in web UI
+///
+/// A small remote for phones and browsers: now playing, the active preset and
+/// its EQ curve, a preset picker, profiles and output selection. The static
+/// files are compiled into the binary and served at `/` and `/ui/`; the page
+/// itself uses the REST API and `/v1/events` like any other client.
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+const INDEX_HTML: &str = include_str!("../../web/index.html");
+const APP_JS: &str = include_str!("../../web/app.js");
+const STYLE_CSS: &str = include_str!("../../web/style.css");
+
+/// Paths of the UI's static files, which are served without a token
+pub fn is_web_ui_path(path: &str) -> bool {
+    path == "/" || path.starts_with("/ui/")
+}
+
+/// Create the router serving the web UI
+pub fn create_web_router() -> Router {
+    Router::new()
+        .route("/", get(|| async { asset("text/html; charset=utf-8", INDEX_HTML) }))
+        .route("/ui/app.js", get(|| async { asset("text/javascript; charset=utf-8", APP_JS) }))
+        .route("/ui/style.css", get(|| async { asset("text/css; charset=utf-8", STYLE_CSS) }))
+}
+
+fn asset(content_type: &'static str, body: &'static str) -> Response {
+    // Revalidate, so an upgraded server never pairs a new page with an old script
+    ([(header::CONTENT_TYPE, content_type), (header::CACHE_CONTROL, "no-cache")], body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_web_ui_paths() {
+        assert!(is_web_ui_path("/"));
+        assert!(is_web_ui_path("/ui/app.js"));
+        assert!(!is_web_ui_path("/v1/outputs"));
+        assert!(!is_web_ui_path("/uix"));
+    }
+
+    #[test]
+    fn test_page_references_assets() {
+        assert!(INDEX_HTML.contains("/ui/app.js"));
+        assert!(INDEX_HTML.contains("/ui/style.css"));
+    }
+}
@@ -0,0 +1,145 @@
+/// Core DSP chain module
+///
+/// Bundles the processing a DSP profile describes (headroom, parametric EQ,
+/// FIR convolution and dither) so that the same chain can run on the server
+/// or on a remote node the profile was pushed to.
+use super::{Dither, EqProcessor, FirFilter, HeadroomControl, ParametricBand};
+
+/// Headroom → EQ → convolution → dither
+pub struct DspChain {
+    sample_rate: u32,
+    channels: usize,
+    headroom: HeadroomControl,
+    eq: EqProcessor,
+    fir: Option<FirFilter>,
+    dither: Option<Dither>,
+}
+
+impl DspChain {
+    /// Create a transparent chain (0 dB headroom, no EQ, no dither)
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let mut headroom = HeadroomControl::new();
+        headroom.set_headroom_db(0.0);
+
+        Self {
+            sample_rate,
+            channels,
+            headroom,
+            eq: EqProcessor::new(sample_rate, channels),
+            fir: None,
+            dither: None,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Set headroom in dB (0 to -6)
+    pub fn set_headroom_db(&mut self, db: f32) {
+        self.headroom.set_headroom_db(db);
+    }
+
+    pub fn headroom_db(&self) -> f32 {
+        self.headroom.headroom_db()
+    }
+
+    /// Replace the EQ bands; an empty slice disables the EQ
+    pub fn set_eq(&mut self, bands: &[ParametricBand]) {
+        self.eq.load_parametric(bands);
+    }
+
+    /// Replace the convolution filter
+    pub fn set_fir(&mut self, fir: Option<FirFilter>) {
+        self.fir = fir;
+    }
+
+    /// Replace the dither stage
+    pub fn set_dither(&mut self, dither: Option<Dither>) {
+        self.dither = dither;
+    }
+
+    pub fn eq_active(&self) -> bool {
+        self.eq.is_enabled() && self.eq.band_count() > 0
+    }
+
+    pub fn convolution_active(&self) -> bool {
+        self.fir.is_some()
+    }
+
+    pub fn dither_active(&self) -> bool {
+        self.dither.is_some()
+    }
+
+    /// Process an interleaved buffer in-place
+    pub fn process(&mut self, buffer: &mut [f64]) {
+        self.headroom.process(buffer);
+        self.eq.process(buffer);
+        if let Some(fir) = &mut self.fir {
+            fir.process(buffer);
+        }
+        if let Some(dither) = &mut self.dither {
+            dither.process(buffer);
+        }
+    }
+
+    /// Clear filter state, e.g. after a seek or buffer flush
+    pub fn reset(&mut self) {
+        self.eq.reset();
+        if let Some(fir) = &mut self.fir {
+            fir.reset();
+        }
+        if let Some(dither) = &mut self.dither {
+            dither.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::FilterType;
+
+    #[test]
+    fn test_new_chain_is_transparent() {
+        let mut chain = DspChain::new(48000, 2);
+        let mut buffer = vec![0.5, -0.25, 0.1, 0.0];
+        chain.process(&mut buffer);
+
+        assert_eq!(buffer, vec![0.5, -0.25, 0.1, 0.0]);
+        assert!(!chain.eq_active());
+        assert!(!chain.convolution_active());
+    }
+
+    #[test]
+    fn test_headroom_and_fir() {
+        let mut chain = DspChain::new(48000, 1);
+        chain.set_headroom_db(-6.0);
+        chain.set_fir(Some(FirFilter::new(&[0.0, 1.0], 1, 0, 0.0)));
+        assert!(chain.convolution_active());
+
+        let mut buffer = vec![1.0, 0.0];
+        chain.process(&mut buffer);
+        assert_eq!(buffer[0], 0.0);
+        assert!((buffer[1] - 0.501).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_eq_can_be_cleared() {
+        let mut chain = DspChain::new(48000, 2);
+        chain.set_eq(&[ParametricBand {
+            filter_type: FilterType::LowShelf,
+            frequency: 100.0,
+            gain_db: 4.0,
+            q: 0.707,
+        }]);
+        assert!(chain.eq_active());
+
+        chain.set_eq(&[]);
+        assert!(!chain.eq_active());
+    }
+}
@@ -86,6 +86,12 @@ impl Dither {
         }
     }
 
+    /// Seed the noise generator so the output is reproducible
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
     /// Process stereo interleaved samples
     pub fn process(&mut self, samples: &mut [f64]) {
         if self.mode == DitherMode::None && self.shaping == NoiseShaping::None {
@@ -296,6 +302,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_seeded_dither_is_reproducible() {
+        let input: Vec<f64> = (0..64).map(|i| (i as f64 * 0.1).sin() * 0.01).collect();
+        let mut a = input.clone();
+        let mut b = input.clone();
+        Dither::new(DitherMode::Triangular, NoiseShaping::SecondOrder, 16).with_seed(7).process(&mut a);
+        Dither::new(DitherMode::Triangular, NoiseShaping::SecondOrder, 16).with_seed(7).process(&mut b);
+        assert_eq!(a, b);
+    }
+
     #[test]
     fn test_tpdf_dither() {
         let mut dither = Dither::new(DitherMode::Triangular, NoiseShaping::None, 16);
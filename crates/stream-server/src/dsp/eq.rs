@@ -57,9 +57,47 @@ impl BiquadFilter {
         self.a2 = (1.0 - alpha / a) / a0;
     }
 
+    /// Configure as a low shelf boosting or cutting below `frequency`
+    ///
+    /// `q` sets the slope of the transition; 0.707 gives the steepest shelf
+    /// without overshoot.
+    pub fn set_low_shelf(&mut self, frequency: f64, gain_db: f64, q: f64, sample_rate: f64) {
+        self.set_shelf(frequency, gain_db, q, sample_rate, false);
+    }
+
+    /// Configure as a high shelf boosting or cutting above `frequency`
+    pub fn set_high_shelf(&mut self, frequency: f64, gain_db: f64, q: f64, sample_rate: f64) {
+        self.set_shelf(frequency, gain_db, q, sample_rate, true);
+    }
+
+    /// Use coefficients computed elsewhere, normalized so that a0 = 1
+    pub fn set_coefficients(&mut self, b: [f64; 3], a: [f64; 2]) {
+        [self.b0, self.b1, self.b2] = b;
+        [self.a1, self.a2] = a;
+    }
+
+    fn set_shelf(&mut self, frequency: f64, gain_db: f64, q: f64, sample_rate: f64, high: bool) {
+        let a = 10_f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let cos_w0 = w0.cos();
+        let sin_w0 = w0.sin();
+        let alpha = sin_w0 / (2.0 * q);
+        let two_sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+        // RBJ cookbook shelf coefficients; the high shelf mirrors the low
+        // one by flipping the sign of the cosine terms
+        let sign = if high { -1.0 } else { 1.0 };
+        let a0 = (a + 1.0) + sign * (a - 1.0) * cos_w0 + two_sqrt_a_alpha;
+        self.b0 = a * ((a + 1.0) - sign * (a - 1.0) * cos_w0 + two_sqrt_a_alpha) / a0;
+        self.b1 = sign * 2.0 * a * ((a - 1.0) - sign * (a + 1.0) * cos_w0) / a0;
+        self.b2 = a * ((a + 1.0) - sign * (a - 1.0) * cos_w0 - two_sqrt_a_alpha) / a0;
+        self.a1 = -sign * 2.0 * ((a - 1.0) + sign * (a + 1.0) * cos_w0) / a0;
+        self.a2 = ((a + 1.0) + sign * (a - 1.0) * cos_w0 - two_sqrt_a_alpha) / a0;
+    }
+
     /// Process a single sample for a given channel
     #[inline]
-    fn process_sample(&mut self, sample: f64, channel: usize) -> f64 {
+    pub(crate) fn process_sample(&mut self, sample: f64, channel: usize) -> f64 {
         // Direct Form II Transposed
         let output = self.b0 * sample + self.z1[channel];
         self.z1[channel] = self.b1 * sample - self.a1 * output + self.z2[channel];
@@ -76,6 +114,24 @@ impl BiquadFilter {
     }
 }
 
+/// Shape of a parametric EQ band
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterType {
+    #[default]
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+/// One band of a fully parametric EQ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParametricBand {
+    pub filter_type: FilterType,
+    pub frequency: f64,
+    pub gain_db: f64,
+    pub q: f64,
+}
+
 /// Multi-band parametric EQ processor
 pub struct EqProcessor {
     filters: Vec<BiquadFilter>,
@@ -118,6 +174,29 @@ impl EqProcessor {
         self.enabled = !self.filters.is_empty();
     }
 
+    /// Load bands with individual shapes and Q factors
+    ///
+    /// Bands at or above Nyquist can't be realised and are skipped.
+    pub fn load_parametric(&mut self, bands: &[ParametricBand]) {
+        self.filters.clear();
+        let sample_rate = self.sample_rate as f64;
+
+        for band in bands {
+            if band.frequency <= 0.0 || band.frequency >= sample_rate / 2.0 || band.q <= 0.0 {
+                continue;
+            }
+            let mut filter = BiquadFilter::new(self.channels);
+            match band.filter_type {
+                FilterType::Peaking => filter.set_peaking(band.frequency, band.gain_db, band.q, sample_rate),
+                FilterType::LowShelf => filter.set_low_shelf(band.frequency, band.gain_db, band.q, sample_rate),
+                FilterType::HighShelf => filter.set_high_shelf(band.frequency, band.gain_db, band.q, sample_rate),
+            }
+            self.filters.push(filter);
+        }
+
+        self.enabled = !self.filters.is_empty();
+    }
+
     /// Apply EQ to an interleaved audio buffer
     ///
     /// # Arguments
@@ -279,4 +358,52 @@ mod tests {
         }
         assert!(changed, "EQ should modify the signal");
     }
+
+    /// Steady-state gain of a filter for a sine at `frequency`
+    fn tone_gain(filter_type: FilterType, frequency: f64) -> f64 {
+        let mut processor = EqProcessor::new(48000, 1);
+        processor.load_parametric(&[ParametricBand {
+            filter_type,
+            frequency: 1000.0,
+            gain_db: 6.0,
+            q: 0.707,
+        }]);
+
+        let mut buffer: Vec<f64> = (0..48000)
+            .map(|i| (2.0 * PI * frequency * i as f64 / 48000.0).sin())
+            .collect();
+        processor.process(&mut buffer);
+        buffer[24000..].iter().fold(0.0_f64, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn test_shelf_filters() {
+        let boost = 10_f64.powf(6.0 / 20.0);
+
+        // A low shelf boosts the bass and leaves the treble alone
+        assert!((tone_gain(FilterType::LowShelf, 50.0) - boost).abs() < 0.05);
+        assert!((tone_gain(FilterType::LowShelf, 15000.0) - 1.0).abs() < 0.05);
+
+        // A high shelf does the opposite
+        assert!((tone_gain(FilterType::HighShelf, 50.0) - 1.0).abs() < 0.05);
+        assert!((tone_gain(FilterType::HighShelf, 15000.0) - boost).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_load_parametric_skips_invalid_bands() {
+        let mut processor = EqProcessor::new(48000, 2);
+        let band = ParametricBand {
+            filter_type: FilterType::Peaking,
+            frequency: 1000.0,
+            gain_db: 3.0,
+            q: 1.4,
+        };
+        processor.load_parametric(&[
+            band,
+            ParametricBand { frequency: 30000.0, ..band },
+            ParametricBand { q: 0.0, ..band },
+        ]);
+        assert_eq!(processor.band_count(), 1);
+        assert!(processor.is_enabled());
+    }
 }
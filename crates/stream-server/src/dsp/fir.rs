@@ -0,0 +1,125 @@
+//! FIR convolution module
+//!
+//! Direct-form convolution for room correction and other measured filters.
+//! Cost grows with the tap count, so this is intended for filters of a few
+//! thousand taps; longer impulse responses need partitioned FFT convolution.
+
+/// Convert dB to linear gain
+#[inline]
+fn db_to_linear(db: f32) -> f64 {
+    10_f64.powf(db as f64 / 20.0)
+}
+
+/// Multi-channel FIR filter with optional delay and output gain
+#[derive(Debug, Clone)]
+pub struct FirFilter {
+    /// Taps with the delay prepended as leading zeros
+    taps: Vec<f64>,
+    channels: usize,
+    /// Circular history per channel, `taps.len()` samples each
+    history: Vec<Vec<f64>>,
+    position: usize,
+}
+
+impl FirFilter {
+    /// Create a filter from impulse response taps
+    ///
+    /// # Arguments
+    /// * `taps` - Impulse response at the stream's sample rate
+    /// * `channels` - Number of interleaved channels to filter
+    /// * `delay_samples` - Extra latency added before the response
+    /// * `gain_db` - Gain applied to the response
+    pub fn new(taps: &[f32], channels: usize, delay_samples: u32, gain_db: f32) -> Self {
+        let gain = db_to_linear(gain_db);
+        let mut delayed = vec![0.0; delay_samples as usize];
+        delayed.extend(taps.iter().map(|&t| t as f64 * gain));
+        if delayed.is_empty() {
+            delayed.push(gain);
+        }
+
+        Self {
+            history: vec![vec![0.0; delayed.len()]; channels],
+            taps: delayed,
+            channels,
+            position: 0,
+        }
+    }
+
+    /// Number of taps, including the delay
+    pub fn len(&self) -> usize {
+        self.taps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.taps.is_empty()
+    }
+
+    /// Filter an interleaved buffer in-place
+    pub fn process(&mut self, buffer: &mut [f64]) {
+        let len = self.taps.len();
+
+        for frame in buffer.chunks_exact_mut(self.channels) {
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let history = &mut self.history[ch];
+                history[self.position] = *sample;
+
+                // history[position - k] pairs with taps[k]
+                let (newer, older) = history.split_at(self.position + 1);
+                let mut acc = 0.0;
+                for (tap, x) in self.taps.iter().zip(newer.iter().rev().chain(older.iter().rev())) {
+                    acc += tap * x;
+                }
+                *sample = acc;
+            }
+            self.position = (self.position + 1) % len;
+        }
+    }
+
+    /// Clear the filter history
+    pub fn reset(&mut self) {
+        for history in &mut self.history {
+            history.iter_mut().for_each(|s| *s = 0.0);
+        }
+        self.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_impulse_response() {
+        let mut fir = FirFilter::new(&[1.0, 0.5, 0.25], 2, 0, 0.0);
+        let mut buffer = vec![0.0; 10];
+        buffer[0] = 1.0; // Left impulse
+        buffer[1] = -1.0; // Right impulse
+        fir.process(&mut buffer);
+
+        assert_eq!(buffer, vec![1.0, -1.0, 0.5, -0.5, 0.25, -0.25, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_delay_and_gain() {
+        let mut fir = FirFilter::new(&[1.0], 1, 2, -6.0);
+        assert_eq!(fir.len(), 3);
+
+        let mut buffer = vec![1.0, 0.0, 0.0, 0.0];
+        fir.process(&mut buffer);
+        assert_eq!(buffer[..2], [0.0, 0.0]);
+        assert!((buffer[2] - 0.501).abs() < 0.001);
+        assert_eq!(buffer[3], 0.0);
+    }
+
+    #[test]
+    fn test_state_carries_across_buffers() {
+        let mut fir = FirFilter::new(&[0.5, 0.5], 1, 0, 0.0);
+        let mut first = vec![1.0, 1.0];
+        let mut second = vec![0.0, 0.0];
+        fir.process(&mut first);
+        fir.process(&mut second);
+
+        assert_eq!(first, vec![0.5, 1.0]);
+        assert_eq!(second, vec![0.5, 0.0]);
+    }
+}
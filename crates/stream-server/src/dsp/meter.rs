@@ -0,0 +1,230 @@
+/// Level metering: RMS, peak and momentary loudness
+///
+/// Loudness follows ITU-R BS.1770: K-weighted mean square over a sliding
+/// 400 ms window, summed over channels (surround channels are weighted like
+/// the front ones) and reported in LUFS. No gating is applied, so this is the
+/// momentary loudness, not the integrated loudness of a programme.
+use super::eq::BiquadFilter;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Levels below this are reported as this (silence has no finite dB value)
+pub const METER_FLOOR_DB: f64 = -120.0;
+
+/// Length of the momentary loudness window
+const MOMENTARY_WINDOW_MS: u32 = 400;
+
+/// The window is updated in steps of this length (75% overlap)
+const MOMENTARY_STEP_MS: u32 = 100;
+
+/// Levels measured since the previous reading
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeterReading {
+    /// RMS level per channel (dBFS)
+    pub rms_dbfs: Vec<f64>,
+    /// Sample peak per channel (dBFS)
+    pub peak_dbfs: Vec<f64>,
+    /// Momentary loudness over the last 400 ms (LUFS)
+    pub lufs_momentary: f64,
+}
+
+/// Measures interleaved audio for level meters
+pub struct LevelMeter {
+    channels: usize,
+    /// High-shelf stage of the K-weighting filter
+    shelf: BiquadFilter,
+    /// High-pass (RLB) stage of the K-weighting filter
+    highpass: BiquadFilter,
+    step_frames: usize,
+    steps_per_window: usize,
+    /// K-weighted sum of squares per channel of the step in progress
+    step_energy: Vec<f64>,
+    step_pos: usize,
+    /// Mean square summed over channels of each completed step
+    steps: VecDeque<f64>,
+    /// Since the last reading
+    sum_squares: Vec<f64>,
+    peaks: Vec<f64>,
+    frames: usize,
+}
+
+impl LevelMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let fs = sample_rate.max(1) as f64;
+
+        // BS.1770 K-weighting, recomputed for the sample rate (as in libebur128)
+        let mut shelf = BiquadFilter::new(channels);
+        let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (PI * f0 / fs).tan();
+        let vh = 10_f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        shelf.set_coefficients(
+            [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let mut highpass = BiquadFilter::new(channels);
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (PI * f0 / fs).tan();
+        let a0 = 1.0 + k / q + k * k;
+        highpass.set_coefficients(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        Self {
+            channels,
+            shelf,
+            highpass,
+            step_frames: (sample_rate.max(1) * MOMENTARY_STEP_MS / 1000).max(1) as usize,
+            steps_per_window: (MOMENTARY_WINDOW_MS / MOMENTARY_STEP_MS) as usize,
+            step_energy: vec![0.0; channels],
+            step_pos: 0,
+            steps: VecDeque::new(),
+            sum_squares: vec![0.0; channels],
+            peaks: vec![0.0; channels],
+            frames: 0,
+        }
+    }
+
+    /// Measure a block of interleaved samples
+    pub fn process(&mut self, samples: &[f64]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (ch, &sample) in frame.iter().enumerate() {
+                self.sum_squares[ch] += sample * sample;
+                self.peaks[ch] = self.peaks[ch].max(sample.abs());
+
+                let weighted = self.highpass.process_sample(self.shelf.process_sample(sample, ch), ch);
+                self.step_energy[ch] += weighted * weighted;
+            }
+            self.frames += 1;
+
+            self.step_pos += 1;
+            if self.step_pos == self.step_frames {
+                let mean_square = self.step_energy.iter().sum::<f64>() / self.step_frames as f64;
+                self.steps.push_back(mean_square);
+                if self.steps.len() > self.steps_per_window {
+                    self.steps.pop_front();
+                }
+                self.step_energy.iter_mut().for_each(|energy| *energy = 0.0);
+                self.step_pos = 0;
+            }
+        }
+    }
+
+    /// Momentary loudness over the last 400 ms (less until that much has been measured)
+    pub fn lufs_momentary(&self) -> f64 {
+        if self.steps.is_empty() {
+            return METER_FLOOR_DB;
+        }
+        let mean_square = self.steps.iter().sum::<f64>() / self.steps.len() as f64;
+        if mean_square > 0.0 {
+            (-0.691 + 10.0 * mean_square.log10()).max(METER_FLOOR_DB)
+        } else {
+            METER_FLOOR_DB
+        }
+    }
+
+    /// Levels since the previous reading; starts measuring RMS and peak afresh
+    pub fn take_reading(&mut self) -> MeterReading {
+        let frames = self.frames.max(1) as f64;
+        let reading = MeterReading {
+            rms_dbfs: self.sum_squares.iter().map(|sum| to_db((sum / frames).sqrt())).collect(),
+            peak_dbfs: self.peaks.iter().map(|&peak| to_db(peak)).collect(),
+            lufs_momentary: self.lufs_momentary(),
+        };
+        self.sum_squares.iter_mut().for_each(|sum| *sum = 0.0);
+        self.peaks.iter_mut().for_each(|peak| *peak = 0.0);
+        self.frames = 0;
+        reading
+    }
+
+    /// Forget everything measured so far
+    pub fn reset(&mut self) {
+        self.shelf.reset();
+        self.highpass.reset();
+        self.step_energy.iter_mut().for_each(|energy| *energy = 0.0);
+        self.step_pos = 0;
+        self.steps.clear();
+        self.take_reading();
+    }
+}
+
+fn to_db(level: f64) -> f64 {
+    if level > 0.0 {
+        (20.0 * level.log10()).max(METER_FLOOR_DB)
+    } else {
+        METER_FLOOR_DB
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo_sine(frequency: f64, amplitude: f64, sample_rate: u32, seconds: f64) -> Vec<f64> {
+        let frames = (sample_rate as f64 * seconds) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let s = amplitude * (2.0 * PI * frequency * i as f64 / sample_rate as f64).sin();
+                [s, s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rms_and_peak() {
+        let mut meter = LevelMeter::new(48000, 2);
+        meter.process(&stereo_sine(1000.0, 0.5, 48000, 0.5));
+        let reading = meter.take_reading();
+
+        // A sine's RMS is 3 dB below its peak
+        assert!((reading.peak_dbfs[0] - -6.02).abs() < 0.05);
+        assert!((reading.rms_dbfs[1] - -9.03).abs() < 0.05);
+
+        // Readings start afresh
+        assert_eq!(meter.take_reading().peak_dbfs, vec![METER_FLOOR_DB; 2]);
+    }
+
+    #[test]
+    fn test_momentary_loudness_of_1k_sine() {
+        // BS.1770: a stereo 1 kHz sine at -20 dBFS reads about -20 LUFS
+        for sample_rate in [44100, 48000, 96000] {
+            let mut meter = LevelMeter::new(sample_rate, 2);
+            meter.process(&stereo_sine(1000.0, 0.1, sample_rate, 1.0));
+            let lufs = meter.lufs_momentary();
+            assert!((lufs - -20.0).abs() < 0.1, "{} Hz: {} LUFS", sample_rate, lufs);
+        }
+    }
+
+    #[test]
+    fn test_momentary_window_follows_signal() {
+        let mut meter = LevelMeter::new(48000, 2);
+        meter.process(&stereo_sine(1000.0, 0.1, 48000, 1.0));
+        // 400 ms of silence pushes the tone out of the window (all but the filters' decay)
+        meter.process(&vec![0.0; 48000 * 2 * 2 / 5]);
+        assert!(meter.lufs_momentary() < -60.0);
+    }
+
+    #[test]
+    fn test_k_weighting_rolls_off_lows() {
+        let mut low = LevelMeter::new(48000, 2);
+        low.process(&stereo_sine(20.0, 0.1, 48000, 2.0));
+        let mut mid = LevelMeter::new(48000, 2);
+        mid.process(&stereo_sine(1000.0, 0.1, 48000, 2.0));
+        assert!(mid.lufs_momentary() - low.lufs_momentary() > 10.0);
+    }
+
+    #[test]
+    fn test_silence() {
+        let mut meter = LevelMeter::new(48000, 2);
+        assert_eq!(meter.lufs_momentary(), METER_FLOOR_DB);
+        meter.process(&[0.0; 9600]);
+        let reading = meter.take_reading();
+        assert_eq!(reading.rms_dbfs, vec![METER_FLOOR_DB; 2]);
+        assert_eq!(reading.lufs_momentary, METER_FLOOR_DB);
+    }
+}
@@ -1,18 +1,26 @@
 /// DSP (Digital Signal Processing) modules
 ///
 /// Contains real-time audio processing components:
+/// - Chain: Headroom, EQ, convolution and dither as one profile-driven chain
 /// - Dither: High-quality dithering and noise shaping for bit-depth reduction
 /// - EQ: Parametric equalization with biquad IIR filters
+/// - FIR: Direct-form convolution with measured impulse responses
 /// - Headroom: Gain control and clipping prevention
+/// - Meter: RMS, peak and momentary loudness (LUFS) for level meters
+/// - Profile: A profile's full enhancer chain for offline rendering
 /// - Resampler: High-quality sample rate conversion with sinc interpolation
 ///
 /// DSP Enhancers & Filters:
 /// - Tone/Character: Tube Warmth, Tape Saturation, Transformer, Exciter, Transient Enhancer
 /// - Dynamic Processors: Compressor, Limiter, Expander/Noise Gate
 /// - Spatial/Psychoacoustic: Stereo Width, Crossfeed, Room Ambience
+pub mod chain;
 pub mod dither;
 pub mod eq;
+pub mod fir;
 pub mod headroom;
+pub mod meter;
+pub mod profile;
 pub mod resampler;
 
 // DSP Enhancers & Filters
@@ -30,9 +38,13 @@ pub mod room_ambience;
 pub mod exclusivity;
 
 // Re-export commonly used types for convenience
+pub use chain::DspChain;
 pub use dither::{Dither, DitherMode, NoiseShaping};
-pub use eq::{BiquadFilter, EqProcessor};
+pub use eq::{BiquadFilter, EqProcessor, FilterType, ParametricBand};
+pub use fir::FirFilter;
 pub use headroom::HeadroomControl;
+pub use meter::{LevelMeter, MeterReading, METER_FLOOR_DB};
+pub use profile::{parse_dither_mode, parse_noise_shaping, parse_resampler_quality, ProfileChain};
 pub use resampler::{Resampler, ResamplerQuality};
 
 // Re-export DSP enhancers and filters
@@ -0,0 +1,326 @@
+/// Profile DSP chain module
+///
+/// Runs a profile's `DspSettings` and EQ preset in the same fixed order as
+/// the desktop streaming pipeline (expander, headroom, tone, EQ, dynamics,
+/// spatial, exciter, resampler, dither), for rendering files offline.
+use super::{
+    Compressor, Crossfeed, Dither, DitherMode, EqProcessor, Exciter, Expander, HeadroomControl, Limiter, NoiseShaping,
+    Resampler, ResamplerQuality, RoomAmbience, StereoWidth, TapeSaturation, Transformer, TransientEnhancer,
+    TubeWarmth,
+};
+use aaeq_core::{DspSettings, EqPreset};
+use anyhow::Result;
+
+/// Parse a stored dither mode, defaulting to TPDF like the desktop app
+pub fn parse_dither_mode(name: &str) -> DitherMode {
+    match name {
+        "None" => DitherMode::None,
+        "Rectangular" => DitherMode::Rectangular,
+        "Gaussian" => DitherMode::Gaussian,
+        _ => DitherMode::Triangular,
+    }
+}
+
+/// Parse a stored noise shaping curve, defaulting to none
+pub fn parse_noise_shaping(name: &str) -> NoiseShaping {
+    match name {
+        "FirstOrder" => NoiseShaping::FirstOrder,
+        "SecondOrder" => NoiseShaping::SecondOrder,
+        "Gesemann" => NoiseShaping::Gesemann,
+        _ => NoiseShaping::None,
+    }
+}
+
+/// Parse a stored resampler quality, defaulting to balanced
+pub fn parse_resampler_quality(name: &str) -> ResamplerQuality {
+    match name {
+        "Fast" => ResamplerQuality::Fast,
+        "High" => ResamplerQuality::High,
+        "Ultra" => ResamplerQuality::Ultra,
+        _ => ResamplerQuality::Balanced,
+    }
+}
+
+/// Every processor a profile can enable, in pipeline order
+pub struct ProfileChain {
+    channels: usize,
+    expander: Expander,
+    headroom: HeadroomControl,
+    tube_warmth: TubeWarmth,
+    tape_saturation: TapeSaturation,
+    transformer: Transformer,
+    transient_enhancer: TransientEnhancer,
+    eq: EqProcessor,
+    compressor: Compressor,
+    limiter: Limiter,
+    stereo_width: StereoWidth,
+    crossfeed: Crossfeed,
+    room_ambience: RoomAmbience,
+    exciter: Exciter,
+    resampler: Option<Resampler>,
+    /// Processed samples waiting for a full resampler chunk
+    pending: Vec<f64>,
+    dither: Option<Dither>,
+    frames_in: u64,
+    frames_out: u64,
+}
+
+impl ProfileChain {
+    /// Build the chain for audio at `sample_rate`
+    ///
+    /// Resamples to `settings.target_sample_rate` when `resample_enabled`,
+    /// and dithers to `settings.target_bits` when `dither_enabled`. The EQ
+    /// starts flat; see [`ProfileChain::set_preset`].
+    pub fn new(settings: &DspSettings, sample_rate: u32, channels: usize) -> Result<Self> {
+        let mut headroom = HeadroomControl::new();
+        headroom.set_headroom_db(settings.headroom_db);
+        headroom.set_auto_compensate(settings.auto_compensate);
+        headroom.set_clip_detection(settings.clip_detection);
+
+        let mut tube_warmth = TubeWarmth::new();
+        tube_warmth.set_enabled(settings.tube_warmth_enabled);
+        let mut tape_saturation = TapeSaturation::new();
+        tape_saturation.set_enabled(settings.tape_saturation_enabled);
+        let mut transformer = Transformer::new();
+        transformer.set_enabled(settings.transformer_enabled);
+        let mut transient_enhancer = TransientEnhancer::new();
+        transient_enhancer.set_enabled(settings.transient_enhancer_enabled);
+
+        let mut compressor = Compressor::new();
+        compressor.set_enabled(settings.compressor_enabled);
+        let mut limiter = Limiter::new();
+        limiter.set_enabled(settings.limiter_enabled);
+        let mut expander = Expander::new();
+        expander.set_enabled(settings.expander_enabled);
+
+        let mut stereo_width = StereoWidth::new();
+        stereo_width.set_enabled(settings.stereo_width_enabled);
+        let mut crossfeed = Crossfeed::new();
+        crossfeed.set_enabled(settings.crossfeed_enabled);
+        let mut room_ambience = RoomAmbience::new();
+        room_ambience.set_enabled(settings.room_ambience_enabled);
+
+        let mut exciter = Exciter::new();
+        exciter.set_enabled(settings.exciter_enabled);
+
+        let resampler = if settings.resample_enabled && settings.target_sample_rate != sample_rate {
+            Some(Resampler::new(
+                parse_resampler_quality(&settings.resample_quality),
+                sample_rate,
+                settings.target_sample_rate,
+                channels,
+            )?)
+        } else {
+            None
+        };
+
+        let dither = settings.dither_enabled.then(|| {
+            Dither::new(
+                parse_dither_mode(&settings.dither_mode),
+                parse_noise_shaping(&settings.noise_shaping),
+                settings.target_bits,
+            )
+        });
+
+        Ok(Self {
+            channels,
+            expander,
+            headroom,
+            tube_warmth,
+            tape_saturation,
+            transformer,
+            transient_enhancer,
+            eq: EqProcessor::new(sample_rate, channels),
+            compressor,
+            limiter,
+            stereo_width,
+            crossfeed,
+            room_ambience,
+            exciter,
+            resampler,
+            pending: Vec::new(),
+            dither,
+            frames_in: 0,
+            frames_out: 0,
+        })
+    }
+
+    /// Seed the dither noise so renders are reproducible
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.dither = self.dither.map(|d| d.with_seed(seed));
+        self
+    }
+
+    /// Sample rate of the processed output
+    pub fn output_rate(&self) -> Option<u32> {
+        self.resampler.as_ref().map(|r| r.output_rate())
+    }
+
+    /// Load the EQ curve
+    pub fn set_preset(&mut self, preset: &EqPreset) {
+        self.eq.load_preset(preset);
+    }
+
+    /// Process interleaved samples
+    ///
+    /// With resampling enabled, output is produced in whole resampler chunks,
+    /// so it may be shorter than the input until [`ProfileChain::finish`].
+    pub fn process(&mut self, samples: &[f64]) -> Result<Vec<f64>> {
+        let mut buffer = samples.to_vec();
+        self.frames_in += (buffer.len() / self.channels) as u64;
+
+        // 1. Expander (gate/noise reduction before processing)
+        self.expander.process(&mut buffer);
+
+        // 2. Headroom (volume reduction to prevent clipping)
+        self.headroom.process(&mut buffer);
+
+        // 3. Tone enhancers (mutually exclusive - only one should be enabled)
+        self.tube_warmth.process(&mut buffer);
+        self.tape_saturation.process(&mut buffer);
+        self.transformer.process(&mut buffer);
+        self.transient_enhancer.process(&mut buffer);
+
+        // 4. EQ
+        self.eq.process(&mut buffer);
+
+        // 5. Dynamics (compressor/limiter after EQ)
+        self.compressor.process(&mut buffer);
+        self.limiter.process(&mut buffer);
+
+        // 6. Spatial effects (stereo processing)
+        if self.channels == 2 {
+            self.stereo_width.process_stereo(&mut buffer);
+            self.crossfeed.process_stereo(&mut buffer);
+        }
+        self.room_ambience.process(&mut buffer);
+
+        // 7. Exciter (high frequency enhancement)
+        self.exciter.process(&mut buffer);
+
+        let output = match self.resampler.as_mut() {
+            Some(resampler) => {
+                self.pending.extend_from_slice(&buffer);
+                let chunk = resampler.chunk_frames() * self.channels;
+                let mut output = Vec::new();
+                while self.pending.len() >= chunk {
+                    let rest = self.pending.split_off(chunk);
+                    let block = std::mem::replace(&mut self.pending, rest);
+                    output.extend(resampler.process(&block)?);
+                }
+                output
+            }
+            None => buffer,
+        };
+
+        Ok(self.finish_block(output))
+    }
+
+    /// Flush the resampler, trimming the padding so the output length
+    /// matches the input duration
+    pub fn finish(&mut self) -> Result<Vec<f64>> {
+        let Some(resampler) = self.resampler.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let mut output = Vec::new();
+        if !self.pending.is_empty() {
+            let mut block = std::mem::take(&mut self.pending);
+            block.resize(resampler.chunk_frames() * self.channels, 0.0);
+            output = resampler.process(&block)?;
+        }
+
+        let expected = self.frames_in * resampler.output_rate() as u64 / resampler.input_rate() as u64;
+        let remaining = expected.saturating_sub(self.frames_out) as usize;
+        output.truncate(remaining * self.channels);
+        Ok(self.finish_block(output))
+    }
+
+    fn finish_block(&mut self, mut output: Vec<f64>) -> Vec<f64> {
+        // Dither last, right before quantization
+        if let Some(dither) = &mut self.dither {
+            dither.process(&mut output);
+        }
+        self.frames_out += (output.len() / self.channels) as u64;
+        output
+    }
+
+    /// Samples that clipped in the headroom stage
+    pub fn clip_count(&self) -> u64 {
+        self.headroom.clip_count()
+    }
+
+    /// Latency the resampler adds, when the chain resamples
+    pub fn resampler_latency_ms(&self) -> Option<f32> {
+        self.resampler.as_ref().map(|r| r.latency_ms())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frames: usize, rate: u32) -> Vec<f64> {
+        (0..frames)
+            .flat_map(|i| {
+                let s = (2.0 * std::f64::consts::PI * 1000.0 * i as f64 / rate as f64).sin() * 0.5;
+                [s, s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_headroom_only_by_default() {
+        let settings = DspSettings {
+            headroom_db: -6.0,
+            ..Default::default()
+        };
+        let mut chain = ProfileChain::new(&settings, 48000, 2).unwrap();
+        assert_eq!(chain.output_rate(), None);
+        assert_eq!(chain.resampler_latency_ms(), None);
+
+        let output = chain.process(&[0.5, -0.5]).unwrap();
+        assert!((output[0] - 0.2506).abs() < 0.001);
+        assert!((output[1] + 0.2506).abs() < 0.001);
+        assert!(chain.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_resampled_length_matches_duration() {
+        let settings = DspSettings {
+            resample_enabled: true,
+            target_sample_rate: 48000,
+            ..Default::default()
+        };
+        let mut chain = ProfileChain::new(&settings, 44100, 2).unwrap();
+        assert_eq!(chain.output_rate(), Some(48000));
+        assert!(chain.resampler_latency_ms().unwrap() > 0.0);
+
+        let mut output = Vec::new();
+        for block in sine(44100, 44100).chunks(700 * 2) {
+            output.extend(chain.process(block).unwrap());
+        }
+        output.extend(chain.finish().unwrap());
+        assert_eq!(output.len(), 48000 * 2);
+    }
+
+    #[test]
+    fn test_seeded_render_is_deterministic() {
+        let settings = DspSettings {
+            dither_enabled: true,
+            target_bits: 16,
+            compressor_enabled: true,
+            ..Default::default()
+        };
+        let render = || {
+            let mut chain = ProfileChain::new(&settings, 48000, 2).unwrap().with_seed(1);
+            chain.set_preset(&EqPreset::default());
+            chain.process(&sine(4800, 48000)).unwrap()
+        };
+
+        let first = render();
+        assert_eq!(first, render());
+        // Dithered output sits on the 16-bit grid
+        assert!(first.iter().all(|s| (s * 32768.0).fract() == 0.0));
+    }
+}
@@ -74,12 +74,18 @@ impl ResamplerQuality {
     }
 }
 
+/// Number of input frames consumed by each call to [`Resampler::process`]
+pub const CHUNK_FRAMES: usize = 1024;
+
+/// Largest relative ratio change accepted by an adjustable resampler (±1%)
+const MAX_RELATIVE_ADJUSTMENT: f64 = 1.01;
 
 /// High-quality sample rate converter
 pub struct Resampler {
     quality: ResamplerQuality,
     input_rate: u32,
     output_rate: u32,
+    channels: usize,
     resampler: Option<SincFixedIn<f64>>,
 }
 
@@ -94,13 +100,12 @@ impl Resampler {
     pub fn new(quality: ResamplerQuality, input_rate: u32, output_rate: u32, channels: usize) -> Result<Self> {
         let resampler = if input_rate != output_rate {
             let params = quality.get_params();
-            let chunk_size = 1024; // Process 1024 frames at a time
 
             let resampler = SincFixedIn::<f64>::new(
                 output_rate as f64 / input_rate as f64,
                 2.0, // Max resample ratio difference
                 params,
-                chunk_size,
+                CHUNK_FRAMES,
                 channels,
             ).context("Failed to create resampler")?;
 
@@ -113,11 +118,53 @@ impl Resampler {
             quality,
             input_rate,
             output_rate,
+            channels,
             resampler,
         })
     }
 
-    /// Process interleaved stereo samples
+    /// Create a 1:1 resampler whose ratio can be nudged at runtime
+    ///
+    /// Unlike [`Resampler::new`], the resampler is always active so that
+    /// clock drift correction (e.g. the ANP micro-PLL) can speed up or slow
+    /// down playback by a few ppm with [`Resampler::set_ratio_relative`].
+    pub fn new_adjustable(quality: ResamplerQuality, sample_rate: u32, channels: usize) -> Result<Self> {
+        let resampler = SincFixedIn::<f64>::new(
+            1.0,
+            MAX_RELATIVE_ADJUSTMENT,
+            quality.get_params(),
+            CHUNK_FRAMES,
+            channels,
+        ).context("Failed to create adjustable resampler")?;
+
+        Ok(Self {
+            quality,
+            input_rate: sample_rate,
+            output_rate: sample_rate,
+            channels,
+            resampler: Some(resampler),
+        })
+    }
+
+    /// Adjust the output/input ratio relative to the nominal one
+    ///
+    /// A value of 1.0001 produces 100 ppm more output frames per chunk. The
+    /// change is ramped over the next chunk to avoid audible steps.
+    pub fn set_ratio_relative(&mut self, relative: f64) -> Result<()> {
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler
+                .set_resample_ratio_relative(relative, true)
+                .context("Failed to adjust resample ratio")?;
+        }
+        Ok(())
+    }
+
+    /// Number of input frames required per [`Resampler::process`] call when active
+    pub fn chunk_frames(&self) -> usize {
+        CHUNK_FRAMES
+    }
+
+    /// Process interleaved samples
     ///
     /// Converts from input sample rate to output sample rate.
     /// Returns the resampled data, which may have a different length.
@@ -130,7 +177,7 @@ impl Resampler {
         let resampler = self.resampler.as_mut().unwrap();
 
         // Convert interleaved samples to planar format (rubato expects planar)
-        let num_channels = 2; // Stereo
+        let num_channels = self.channels;
         let num_frames = samples.len() / num_channels;
 
         let mut planar_input = vec![vec![0.0; num_frames]; num_channels];
@@ -183,9 +230,7 @@ impl Resampler {
     /// Latency is determined by the chunk size (1024 frames) and input sample rate.
     pub fn latency_ms(&self) -> f32 {
         if self.resampler.is_some() {
-            // Chunk size is 1024 frames (defined in new())
-            const CHUNK_SIZE: f32 = 1024.0;
-            (CHUNK_SIZE / self.input_rate as f32) * 1000.0
+            (CHUNK_FRAMES as f32 / self.input_rate as f32) * 1000.0
         } else {
             0.0
         }
@@ -236,6 +281,28 @@ mod tests {
         assert!(resampler.is_active());
     }
 
+    #[test]
+    fn test_adjustable_ratio_changes_output_length() {
+        let mut resampler = Resampler::new_adjustable(ResamplerQuality::Fast, 48000, 2).unwrap();
+        assert!(resampler.is_active());
+
+        let input = vec![0.0; resampler.chunk_frames() * 2];
+        let nominal: usize = (0..8).map(|_| resampler.process(&input).unwrap().len()).sum();
+
+        resampler.set_ratio_relative(1.005).unwrap();
+        let faster: usize = (0..8).map(|_| resampler.process(&input).unwrap().len()).sum();
+
+        assert!(faster > nominal, "Raising the ratio should produce more output");
+        assert!(resampler.set_ratio_relative(1.5).is_err());
+    }
+
+    #[test]
+    fn test_mono_processing() {
+        let mut resampler = Resampler::new(ResamplerQuality::Fast, 48000, 44100, 1).unwrap();
+        let output = resampler.process(&vec![0.0; CHUNK_FRAMES]).unwrap();
+        assert!(!output.is_empty() && output.len() < CHUNK_FRAMES);
+    }
+
     #[test]
     fn test_quality_presets() {
         let qualities = [
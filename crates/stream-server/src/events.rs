@@ -0,0 +1,310 @@
+/// Live events for Control API clients
+///
+/// Whatever drives playback publishes [`StreamEvent`]s on an [`EventBus`];
+/// the Control API forwards them to WebSocket clients on `/v1/events`.
+/// Publishing without subscribers costs next to nothing. The latest track and
+/// preset are kept, so new clients can be told what's playing right away.
+use crate::dsp::{LevelMeter, MeterReading};
+use crate::types::OutputConfig;
+use aaeq_core::{Resolution, TrackMeta};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Events buffered per subscriber before a slow one starts missing them
+const EVENT_CAPACITY: usize = 256;
+
+/// How often meter frames are published at most
+pub const METER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Something that happened, as sent to clients (tagged by `type`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    /// A new track started playing
+    TrackChanged { track: TrackMeta },
+    /// A preset was applied; `rule` is the mapping rule that chose it, if one did
+    PresetApplied { preset: String, rule: Option<Resolution> },
+    /// An output opened or closed
+    SinkState {
+        sink: String,
+        state: SinkState,
+        config: Option<OutputConfig>,
+    },
+    /// The DSP chain clipped since the last clipping event
+    Clipping { clip_count: u64 },
+    /// Levels of the audio being streamed, throttled to [`METER_INTERVAL`]
+    Meters(MeterReading),
+    /// This client fell behind and missed some events
+    Lagged { missed: u64 },
+}
+
+impl StreamEvent {
+    /// The `type` tag, for filtering
+    pub fn kind(&self) -> &'static str {
+        match self {
+            StreamEvent::TrackChanged { .. } => "track_changed",
+            StreamEvent::PresetApplied { .. } => "preset_applied",
+            StreamEvent::SinkState { .. } => "sink_state",
+            StreamEvent::Clipping { .. } => "clipping",
+            StreamEvent::Meters(_) => "meters",
+            StreamEvent::Lagged { .. } => "lagged",
+        }
+    }
+
+    /// Whether this describes what's current until the next one of its kind
+    fn is_state(&self) -> bool {
+        matches!(self, StreamEvent::TrackChanged { .. } | StreamEvent::PresetApplied { .. })
+    }
+}
+
+/// State of an output in a [`StreamEvent::SinkState`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkState {
+    Opened,
+    Closed,
+}
+
+/// Broadcasts events to every subscriber
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<StreamEvent>,
+    /// The latest track and preset events
+    latest: Arc<Mutex<Vec<StreamEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            tx,
+            latest: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn publish(&self, event: StreamEvent) {
+        if event.is_state() {
+            if let Ok(mut latest) = self.latest.lock() {
+                latest.retain(|previous| previous.kind() != event.kind());
+                latest.push(event.clone());
+            }
+        }
+        // No subscribers is fine
+        let _ = self.tx.send(event);
+    }
+
+    /// The latest `track_changed` and `preset_applied` events, oldest first
+    pub fn latest(&self) -> Vec<StreamEvent> {
+        self.latest.lock().map(|latest| latest.clone()).unwrap_or_default()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StreamEvent> {
+        self.tx.subscribe()
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.tx.receiver_count() > 0
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Follows [`StreamEvent::TrackChanged`] for a streaming loop, starting with
+/// the track playing when it's created
+pub struct TrackFollower {
+    events: Option<broadcast::Receiver<StreamEvent>>,
+    current: Option<TrackMeta>,
+}
+
+impl TrackFollower {
+    /// Follow `events`; without a bus [`TrackFollower::next`] never resolves
+    pub fn new(events: Option<&EventBus>) -> Self {
+        let current = events.and_then(|events| {
+            events.latest().into_iter().find_map(|event| match event {
+                StreamEvent::TrackChanged { track } => Some(track),
+                _ => None,
+            })
+        });
+        Self {
+            events: events.map(EventBus::subscribe),
+            current,
+        }
+    }
+
+    /// Wait for the next track; cancel safe, so it can be a `select!` branch
+    pub async fn next(&mut self) -> TrackMeta {
+        if let Some(track) = self.current.take() {
+            return track;
+        }
+        while let Some(events) = &mut self.events {
+            match events.recv().await {
+                Ok(StreamEvent::TrackChanged { track }) => return track,
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => self.events = None,
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+/// Meters audio as it streams, publishing meter frames and clip counts
+///
+/// Audio is only measured while someone is subscribed.
+pub struct MeterPublisher {
+    events: EventBus,
+    meter: LevelMeter,
+    interval: Duration,
+    last_published: Instant,
+    clip_count: u64,
+}
+
+impl MeterPublisher {
+    pub fn new(events: EventBus, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            events,
+            meter: LevelMeter::new(sample_rate, channels),
+            interval: METER_INTERVAL,
+            last_published: Instant::now(),
+            clip_count: 0,
+        }
+    }
+
+    /// Publish meter frames this often instead
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Measure a block of interleaved samples
+    pub fn process(&mut self, samples: &[f64]) {
+        if !self.events.has_subscribers() {
+            return;
+        }
+        self.meter.process(samples);
+        if self.last_published.elapsed() >= self.interval {
+            self.events.publish(StreamEvent::Meters(self.meter.take_reading()));
+            self.last_published = Instant::now();
+        }
+    }
+
+    /// Report the chain's clip count, publishing it if it grew
+    pub fn report_clips(&mut self, clip_count: u64) {
+        if clip_count > self.clip_count {
+            self.events.publish(StreamEvent::Clipping { clip_count });
+        }
+        self.clip_count = clip_count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        let event = StreamEvent::SinkState {
+            sink: "file".to_string(),
+            state: SinkState::Opened,
+            config: None,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "sink_state");
+        assert_eq!(json["state"], "opened");
+        assert_eq!(json["type"], event.kind());
+
+        let meters = StreamEvent::Meters(MeterReading {
+            rms_dbfs: vec![-20.0, -21.0],
+            peak_dbfs: vec![-17.0, -18.0],
+            lufs_momentary: -19.5,
+        });
+        let json = serde_json::to_value(&meters).unwrap();
+        assert_eq!(json["type"], "meters");
+        assert_eq!(json["rms_dbfs"][1], -21.0);
+        assert_eq!(serde_json::from_value::<StreamEvent>(json).unwrap(), meters);
+    }
+
+    #[test]
+    fn test_latest_state_kept() {
+        let events = EventBus::new();
+        assert!(events.latest().is_empty());
+
+        let track = |title: &str| StreamEvent::TrackChanged {
+            track: TrackMeta {
+                title: title.to_string(),
+                ..Default::default()
+            },
+        };
+        let preset = StreamEvent::PresetApplied {
+            preset: "Rock".to_string(),
+            rule: None,
+        };
+        events.publish(track("One"));
+        events.publish(preset.clone());
+        events.publish(StreamEvent::Clipping { clip_count: 1 });
+        events.publish(track("Two"));
+        assert_eq!(events.latest(), vec![preset, track("Two")]);
+    }
+
+    #[tokio::test]
+    async fn test_track_follower() {
+        let events = EventBus::new();
+        let track = |title: &str| TrackMeta {
+            title: title.to_string(),
+            ..Default::default()
+        };
+        events.publish(StreamEvent::TrackChanged { track: track("One") });
+
+        // The current track first, then only track changes
+        let mut follower = TrackFollower::new(Some(&events));
+        events.publish(StreamEvent::Clipping { clip_count: 1 });
+        events.publish(StreamEvent::TrackChanged { track: track("Two") });
+        assert_eq!(follower.next().await, track("One"));
+        assert_eq!(follower.next().await, track("Two"));
+
+        let mut idle = TrackFollower::new(None);
+        let waited = tokio::time::timeout(Duration::from_millis(10), idle.next()).await;
+        assert!(waited.is_err());
+    }
+
+    #[test]
+    fn test_meter_publisher_throttles() {
+        let events = EventBus::new();
+        let mut rx = events.subscribe();
+        let mut meters = MeterPublisher::new(events, 48000, 2).with_interval(Duration::from_secs(3600));
+        meters.last_published = Instant::now() - Duration::from_secs(3600);
+
+        // The first block is due, the rest wait for the interval
+        for _ in 0..10 {
+            meters.process(&[0.5; 960]);
+        }
+        assert!(matches!(rx.try_recv(), Ok(StreamEvent::Meters(_))));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_clips_published_when_they_grow() {
+        let events = EventBus::new();
+        let mut rx = events.subscribe();
+        let mut meters = MeterPublisher::new(events, 48000, 2);
+        meters.report_clips(0);
+        meters.report_clips(3);
+        meters.report_clips(3);
+        assert_eq!(rx.try_recv().unwrap(), StreamEvent::Clipping { clip_count: 3 });
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_idle_without_subscribers() {
+        let events = EventBus::new();
+        let mut meters = MeterPublisher::new(events.clone(), 48000, 2).with_interval(Duration::ZERO);
+        meters.process(&[0.5; 960]);
+        let mut rx = events.subscribe();
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! AES67 / RTP receiver input
+//!
+//! Joins the multicast group (or listens on the unicast port) of an L16/L24
+//! RTP stream, described by its SDP or found with SAP through
+//! [`discover_streams`](crate::sinks::aes67::discover_streams), and sends it
+//! down the same `Vec<f64>` channel as the other inputs. With an
+//! [`Aes67Sink`](crate::sinks::Aes67Sink) on the output, AAEQ can sit in a
+//! pro-audio network path as an EQ insert.
+//!
+//! Packets are reordered with the RTP jitter buffer; lost packets are
+//! replaced with silence, going by the RTP timestamps, so the stream keeps
+//! its timing.
+use crate::dsp::ResamplerQuality;
+use crate::input::file::StreamConverter;
+use crate::sinks::aes67::{bind_multicast, Aes67Stream};
+use crate::sinks::airplay::{parse_rtp_packet, JitterBuffer};
+use crate::types::OutputConfig;
+use anyhow::{Context, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Longest gap filled with silence; anything longer is treated as a restart
+const MAX_CONCEALMENT_MS: u32 = 100;
+
+/// Input that receives an AES67 (or any L16/L24 RTP) stream
+#[derive(Debug, Clone)]
+pub struct Aes67Receiver {
+    stream: Aes67Stream,
+    interface: Ipv4Addr,
+    quality: ResamplerQuality,
+}
+
+impl Aes67Receiver {
+    /// Receive the given stream
+    pub fn new(stream: Aes67Stream) -> Self {
+        Self {
+            stream,
+            interface: Ipv4Addr::UNSPECIFIED,
+            quality: ResamplerQuality::default(),
+        }
+    }
+
+    /// Receive the stream an SDP describes
+    pub fn from_sdp(sdp: &str) -> Result<Self> {
+        Ok(Self::new(Aes67Stream::parse_sdp(sdp)?))
+    }
+
+    /// Network interface (by address) to join the multicast group on
+    pub fn with_interface(mut self, interface: Ipv4Addr) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    /// Resampler quality when the output rate isn't the stream's
+    pub fn with_resampler_quality(mut self, quality: ResamplerQuality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// The stream being received
+    pub fn stream(&self) -> &Aes67Stream {
+        &self.stream
+    }
+
+    /// Start receiving
+    ///
+    /// # Arguments
+    /// * `cfg` - Output sample rate and channel count to convert to
+    /// * `tx` - Channel to send received audio samples
+    pub async fn start(self, cfg: OutputConfig, tx: mpsc::Sender<Vec<f64>>) -> Result<Aes67ReceiverHandle> {
+        let socket = bind_multicast(self.stream.destination, self.interface)
+            .with_context(|| format!("Failed to receive AES67 stream on {}", self.stream.destination))?;
+        let local_addr = socket.local_addr()?;
+        let converter = StreamConverter::new(self.stream.channels as usize, self.stream.sample_rate, &cfg, self.quality)?;
+
+        let counters = Arc::new(Counters::default());
+        info!(
+            "Receiving AES67 stream '{}' ({}/{}/{}) on {}",
+            self.stream.name,
+            self.stream.encoding.as_str(),
+            self.stream.sample_rate,
+            self.stream.channels,
+            self.stream.destination
+        );
+        let task = tokio::spawn(receive(socket, self.stream, converter, tx, counters.clone()));
+
+        Ok(Aes67ReceiverHandle {
+            local_addr,
+            counters,
+            task,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    received: AtomicU64,
+    lost: AtomicU64,
+}
+
+/// A running [`Aes67Receiver`]
+pub struct Aes67ReceiverHandle {
+    local_addr: SocketAddr,
+    counters: Arc<Counters>,
+    task: JoinHandle<()>,
+}
+
+impl Aes67ReceiverHandle {
+    /// Address the socket is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// RTP packets of the stream received so far
+    pub fn packets_received(&self) -> u64 {
+        self.counters.received.load(Ordering::Relaxed)
+    }
+
+    /// Packets never received, replaced with silence
+    pub fn packets_lost(&self) -> u64 {
+        self.counters.lost.load(Ordering::Relaxed)
+    }
+
+    /// Leave the group and stop receiving
+    pub async fn stop(mut self) -> Result<()> {
+        self.task.abort();
+        let _ = (&mut self.task).await;
+        info!("AES67 receiver stopped");
+        Ok(())
+    }
+}
+
+impl Drop for Aes67ReceiverHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn receive(
+    socket: UdpSocket,
+    stream: Aes67Stream,
+    mut converter: StreamConverter,
+    tx: mpsc::Sender<Vec<f64>>,
+    counters: Arc<Counters>,
+) {
+    let channels = stream.channels.max(1) as usize;
+    let frames_per_packet = stream.frames_per_packet() as u32;
+    let max_gap = stream.sample_rate * MAX_CONCEALMENT_MS / 1000;
+    let mut jitter = JitterBuffer::default();
+    // RTP timestamp the next packet should start at
+    let mut next_timestamp: Option<u32> = None;
+    let mut buf = vec![0u8; 9000];
+
+    loop {
+        let len = match socket.recv(&mut buf).await {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("AES67 socket error: {}", e);
+                break;
+            }
+        };
+        let Some((payload_type, packet)) = parse_rtp_packet(&buf[..len]) else {
+            continue;
+        };
+        if payload_type != stream.payload_type {
+            continue;
+        }
+        counters.received.fetch_add(1, Ordering::Relaxed);
+
+        let (ready, _) = jitter.insert(packet.sequence_number, (packet.timestamp, packet.payload.to_vec()));
+        for (timestamp, payload) in ready {
+            let mut samples = Vec::new();
+            let gap = next_timestamp.map_or(0, |next| timestamp.wrapping_sub(next) as i32);
+            if gap > 0 && gap as u32 <= max_gap {
+                counters
+                    .lost
+                    .fetch_add((gap as u32).div_ceil(frames_per_packet) as u64, Ordering::Relaxed);
+                samples.resize(gap as usize * channels, 0.0);
+            } else if gap != 0 {
+                debug!("AES67 stream timestamp jumped by {}, resyncing", gap);
+            }
+
+            let mut decoded = stream.encoding.decode(&payload);
+            let frames = decoded.len() / channels;
+            decoded.truncate(frames * channels);
+            samples.extend(decoded);
+            next_timestamp = Some(timestamp.wrapping_add(frames as u32));
+
+            let block = match converter.push(&samples) {
+                Ok(block) => block,
+                Err(e) => {
+                    warn!("AES67 receiver resampling failed: {:#}", e);
+                    return;
+                }
+            };
+            if !block.is_empty() && tx.send(block).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::aes67::Aes67Encoding;
+    use crate::sinks::airplay::JITTER_MAX_PENDING;
+    use crate::types::SampleFormat;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    fn packet(seq: u16, timestamp: u32, samples: &[i16]) -> Vec<u8> {
+        let mut packet = vec![0x80, 97];
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 1]);
+        packet.extend(samples.iter().flat_map(|s| s.to_be_bytes()));
+        packet
+    }
+
+    #[tokio::test]
+    async fn test_reorders_and_conceals_loss() {
+        let mut stream = Aes67Stream::new("Test", Ipv4Addr::LOCALHOST, "127.0.0.1:0".parse().unwrap());
+        stream.encoding = Aes67Encoding::L16;
+        stream.payload_type = 97;
+        stream.channels = 1;
+        let cfg = OutputConfig {
+            sample_rate: 48000,
+            channels: 1,
+            format: SampleFormat::F64,
+            buffer_ms: 50,
+            exclusive: false,
+        };
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let input = Aes67Receiver::new(stream).start(cfg, tx).await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let dest = input.local_addr();
+
+        // 3 frames per packet; 2 arrives before 1, a packet with another
+        // payload type is ignored, and 3 is lost (given up on once the jitter
+        // buffer is full)
+        let half = 16384i16;
+        let mut packets = vec![
+            packet(0, 1000, &[half; 3]),
+            packet(2, 1006, &[-half; 3]),
+            packet(1, 1003, &[half; 3]),
+            vec![0x80, 96, 0, 9, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2],
+        ];
+        for seq in 4..=4 + JITTER_MAX_PENDING as u16 {
+            packets.push(packet(seq, 1000 + seq as u32 * 3, &[half; 3]));
+        }
+        for packet in &packets {
+            sender.send_to(packet, dest).await.unwrap();
+        }
+
+        let mut received = Vec::new();
+        while received.len() < 15 {
+            received.extend(timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap());
+        }
+        received.truncate(15);
+        let expected: Vec<f64> = [0.5; 6].into_iter().chain([-0.5; 3]).chain([0.0; 3]).chain([0.5; 3]).collect();
+        assert_eq!(received, expected);
+        assert_eq!(input.packets_received(), packets.len() as u64 - 1);
+        assert_eq!(input.packets_lost(), 1);
+
+        input.stop().await.unwrap();
+    }
+}
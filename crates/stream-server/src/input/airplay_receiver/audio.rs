@@ -0,0 +1,246 @@
+//! RTP audio of a RAOP session: reordering, decryption, decoding and output
+//!
+//! Senders pace the stream in realtime, so packets are passed on as soon as
+//! they are in order; gaps are asked for again over the control port and
+//! skipped if the retransmission doesn't arrive in time.
+use super::Shared;
+use crate::input::file::StreamConverter;
+use crate::sinks::airplay::{parse_audio_packet, retransmit_request, AirPlayAuth, AlacConfig, JitterBuffer};
+use anyhow::{anyhow, Context, Result};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_ALAC};
+use symphonia::core::formats::Packet;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// Audio format announced in the SDP
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum StreamFormat {
+    /// Apple Lossless with the magic cookie rebuilt from the `fmtp`
+    Alac { config: AlacConfig, cookie: Vec<u8> },
+    /// Uncompressed big-endian 16-bit PCM
+    L16 { sample_rate: u32, channels: u16 },
+}
+
+impl StreamFormat {
+    pub(super) fn sample_rate(&self) -> u32 {
+        match self {
+            Self::Alac { config, .. } => config.sample_rate,
+            Self::L16 { sample_rate, .. } => *sample_rate,
+        }
+    }
+
+    pub(super) fn channels(&self) -> usize {
+        match self {
+            Self::Alac { config, .. } => config.channels as usize,
+            Self::L16 { channels, .. } => *channels as usize,
+        }
+    }
+}
+
+/// Sent from the RTSP session to its audio task
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum AudioCommand {
+    /// Drop buffered audio; the sender restarts the stream (seek, skip)
+    Flush,
+    /// Linear gain for the sender's volume
+    Gain(f64),
+}
+
+/// Decrypts and decodes packet payloads to interleaved samples
+struct PacketDecoder {
+    auth: Option<AirPlayAuth>,
+    alac: Option<Box<dyn Decoder>>,
+    buffer: Option<SampleBuffer<f64>>,
+}
+
+impl PacketDecoder {
+    fn new(format: &StreamFormat, auth: Option<AirPlayAuth>) -> Result<Self> {
+        let alac = match format {
+            StreamFormat::Alac { cookie, .. } => {
+                let mut params = CodecParameters::new();
+                params
+                    .for_codec(CODEC_TYPE_ALAC)
+                    .with_extra_data(cookie.clone().into_boxed_slice());
+                let decoder = symphonia::default::get_codecs()
+                    .make(&params, &DecoderOptions::default())
+                    .context("Failed to create ALAC decoder")?;
+                Some(decoder)
+            }
+            StreamFormat::L16 { .. } => None,
+        };
+
+        Ok(Self {
+            auth,
+            alac,
+            buffer: None,
+        })
+    }
+
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<f64>> {
+        let payload = match &self.auth {
+            Some(auth) => auth.decrypt_audio(payload)?,
+            None => payload.to_vec(),
+        };
+
+        let Some(decoder) = self.alac.as_mut() else {
+            return Ok(payload
+                .chunks_exact(2)
+                .map(|s| i16::from_be_bytes([s[0], s[1]]) as f64 / 32768.0)
+                .collect());
+        };
+
+        let decoded = decoder
+            .decode(&Packet::new_from_boxed_slice(0, 0, 0, payload.into_boxed_slice()))
+            .map_err(|e| anyhow!("ALAC decode failed: {}", e))?;
+        let spec = *decoded.spec();
+        let needed = decoded.capacity() as u64;
+        let buffer = match &mut self.buffer {
+            Some(buffer) if buffer.capacity() as u64 >= needed => buffer,
+            buffer => buffer.insert(SampleBuffer::new(needed, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        Ok(buffer.samples().to_vec())
+    }
+
+    fn reset(&mut self) {
+        if let Some(decoder) = self.alac.as_mut() {
+            decoder.reset();
+        }
+    }
+}
+
+/// Sockets and stream parameters a SETUP hands to the audio task
+pub(super) struct AudioStream {
+    pub session_id: u64,
+    pub format: StreamFormat,
+    pub auth: Option<AirPlayAuth>,
+    pub audio: UdpSocket,
+    pub control: UdpSocket,
+    /// Sender's control port, where retransmit requests go
+    pub client_control: Option<SocketAddr>,
+    pub gain: f64,
+}
+
+/// Receive, decode and pass on the session's audio until the session ends
+pub(super) async fn run(shared: Arc<Shared>, stream: AudioStream, mut commands: mpsc::UnboundedReceiver<AudioCommand>) {
+    let AudioStream {
+        session_id,
+        format,
+        auth,
+        audio,
+        control,
+        client_control,
+        mut gain,
+    } = stream;
+
+    let pipeline = PacketDecoder::new(&format, auth).and_then(|decoder| {
+        let output = StreamConverter::new(format.channels(), format.sample_rate(), &shared.cfg, shared.quality)?;
+        Ok((decoder, output))
+    });
+    let (mut decoder, mut output) = match pipeline {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            warn!("AirPlay receiver can't play stream: {:#}", e);
+            return;
+        }
+    };
+    let mut jitter = JitterBuffer::default();
+    let mut shutdown = shared.shutdown.subscribe();
+    let mut audio_buf = [0u8; 2048];
+    let mut control_buf = [0u8; 2048];
+
+    loop {
+        let (ready, missing) = tokio::select! {
+            received = audio.recv(&mut audio_buf) => match received {
+                Ok(len) => match parse_audio_packet(&audio_buf[..len]) {
+                    Some(packet) => jitter.insert(packet.sequence_number, packet.payload.to_vec()),
+                    None => continue,
+                },
+                Err(e) => {
+                    warn!("AirPlay audio socket error: {}", e);
+                    break;
+                }
+            },
+            // Retransmitted packets; sync packets don't parse as audio
+            received = control.recv(&mut control_buf) => match received {
+                Ok(len) => match parse_audio_packet(&control_buf[..len]) {
+                    Some(packet) => jitter.insert(packet.sequence_number, packet.payload.to_vec()),
+                    None => continue,
+                },
+                Err(e) => {
+                    warn!("AirPlay control socket error: {}", e);
+                    break;
+                }
+            },
+            command = commands.recv() => {
+                match command {
+                    Some(AudioCommand::Flush) => {
+                        jitter.reset();
+                        decoder.reset();
+                        output.clear();
+                    }
+                    Some(AudioCommand::Gain(g)) => gain = g,
+                    None => break,
+                }
+                continue;
+            }
+            _ = shutdown.changed() => break,
+        };
+
+        if let (Some((first, count)), Some(dest)) = (missing, client_control) {
+            debug!("Requesting retransmit of {} AirPlay packets from {}", count, first);
+            if let Err(e) = control.send_to(&retransmit_request(first, count), dest).await {
+                warn!("Failed to request retransmit: {}", e);
+            }
+        }
+
+        for payload in ready {
+            let samples = match decoder.decode(&payload) {
+                Ok(samples) => samples,
+                Err(e) => {
+                    // A corrupt packet is a short dropout, not the end of the stream
+                    warn!("Skipping AirPlay packet: {:#}", e);
+                    continue;
+                }
+            };
+            let mut block = match output.push(&samples) {
+                Ok(block) => block,
+                Err(e) => {
+                    warn!("AirPlay receiver resampling failed: {:#}", e);
+                    return;
+                }
+            };
+            // Only the most recent sender is heard
+            if block.is_empty() || shared.active_session.load(Ordering::SeqCst) != session_id {
+                continue;
+            }
+            if gain != 1.0 {
+                block.iter_mut().for_each(|s| *s *= gain);
+            }
+            if shared.tx.send(block).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_l16_decode() {
+        let format = StreamFormat::L16 {
+            sample_rate: 44100,
+            channels: 2,
+        };
+        let mut decoder = PacketDecoder::new(&format, None).unwrap();
+        let samples = decoder.decode(&[0x40, 0x00, 0xC0, 0x00]).unwrap();
+        assert_eq!(samples, vec![0.5, -0.5]);
+    }
+}
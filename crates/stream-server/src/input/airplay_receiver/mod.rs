@@ -0,0 +1,300 @@
+//! AirPlay (RAOP) receiver input
+//!
+//! Advertises `_raop._tcp` so phones and Macs can pick AAEQ as an AirPlay
+//! speaker, then decrypts and decodes the ALAC (or L16) stream and sends it
+//! down the same `Vec<f64>` channel as the other inputs. That makes AAEQ a
+//! transparent EQ insert for any AirPlay source. DMAP metadata is reported as
+//! a `TrackMeta` with [`AirPlayReceiverEvent::TrackChanged`], so preset rules
+//! resolve as they would for a local player.
+//!
+//! The RTSP session runs on the same RTP/DMAP/ALAC pieces as the AirPlay
+//! sink, from the other end. Encrypted streams need the RSA private key
+//! senders expect (the AirPort Express key), which AAEQ doesn't bundle;
+//! without one only unencrypted streams are accepted (`et=0`).
+mod audio;
+mod session;
+
+use crate::dsp::ResamplerQuality;
+use crate::types::OutputConfig;
+use aaeq_core::TrackMeta;
+use anyhow::{Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use rsa::RsaPrivateKey;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// mDNS service type AirPlay audio senders browse for
+pub const RAOP_SERVICE_TYPE: &str = "_raop._tcp.local.";
+
+/// What senders did with the receiver
+#[derive(Debug, Clone, PartialEq)]
+pub enum AirPlayReceiverEvent {
+    /// A sender announced a stream; it takes over from any previous sender
+    SessionStarted { client: SocketAddr },
+    /// DMAP track metadata
+    TrackChanged { meta: TrackMeta },
+    /// Cover artwork (`image/jpeg` or `image/png`)
+    Artwork { content_type: String, data: Vec<u8> },
+    /// Sender volume (0.0 - 1.0, 0.0 mutes); the gain is applied before the audio is sent
+    VolumeChanged { volume: f32 },
+    /// The sender tore the session down or disconnected
+    SessionEnded { client: SocketAddr },
+}
+
+/// Input that plays what AirPlay senders stream to it
+#[derive(Clone)]
+pub struct AirPlayReceiver {
+    name: String,
+    bind_addr: SocketAddr,
+    hw_addr: [u8; 6],
+    private_key: Option<RsaPrivateKey>,
+    advertise: bool,
+    quality: ResamplerQuality,
+}
+
+impl AirPlayReceiver {
+    /// Create a receiver that serves RTSP on `bind_addr` (AirPlay uses port 5000)
+    ///
+    /// The hardware address senders see is derived from the hostname and
+    /// `name`, so the speaker keeps its identity across restarts.
+    pub fn new(name: String, bind_addr: SocketAddr) -> Self {
+        let host = hostname::get()
+            .map(|h| h.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "aaeq".to_string());
+        let id = Uuid::new_v5(&Uuid::NAMESPACE_DNS, format!("{}/airplay/{}", host, name).as_bytes());
+        let mut hw_addr = [0u8; 6];
+        hw_addr.copy_from_slice(&id.as_bytes()[..6]);
+        // Locally administered, unicast
+        hw_addr[0] = (hw_addr[0] | 0x02) & 0xFE;
+
+        Self {
+            name,
+            bind_addr,
+            hw_addr,
+            private_key: None,
+            advertise: true,
+            quality: ResamplerQuality::default(),
+        }
+    }
+
+    /// Accept encrypted streams, and answer `Apple-Challenge`, with this key
+    ///
+    /// See [`crate::airplay::AirPlayAuth::parse_rsa_private_key_pem`].
+    pub fn with_rsa_private_key(mut self, private_key: RsaPrivateKey) -> Self {
+        self.private_key = Some(private_key);
+        self
+    }
+
+    /// Announce the receiver over mDNS (default); disable to only serve RTSP,
+    /// e.g. for tests
+    pub fn with_advertise(mut self, advertise: bool) -> Self {
+        self.advertise = advertise;
+        self
+    }
+
+    /// Resampler quality when the output rate isn't the stream's (44.1 kHz)
+    pub fn with_resampler_quality(mut self, quality: ResamplerQuality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// TXT record of the `_raop._tcp` service
+    fn txt_properties(&self) -> Vec<(String, String)> {
+        let encryption = if self.private_key.is_some() { "0,1" } else { "0" };
+        [
+            ("txtvers", "1"),
+            ("ch", "2"),
+            ("cn", "0,1"),
+            ("et", encryption),
+            ("md", "0,1,2"),
+            ("pw", "false"),
+            ("sr", "44100"),
+            ("ss", "16"),
+            ("tp", "UDP"),
+            ("vn", "65537"),
+            ("vs", "366.0"),
+            ("da", "true"),
+            ("sv", "false"),
+            ("am", "AAEQ"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+    }
+
+    /// Start accepting senders
+    ///
+    /// # Arguments
+    /// * `cfg` - Output sample rate and channel count to convert to
+    /// * `tx` - Channel to send decoded audio samples
+    /// * `events` - Channel for session, metadata and volume changes
+    pub async fn start(
+        self,
+        cfg: OutputConfig,
+        tx: mpsc::Sender<Vec<f64>>,
+        events: mpsc::UnboundedSender<AirPlayReceiverEvent>,
+    ) -> Result<AirPlayReceiverHandle> {
+        let listener = TcpListener::bind(self.bind_addr)
+            .await
+            .with_context(|| format!("Failed to bind AirPlay receiver to {}", self.bind_addr))?;
+        let local_addr = listener.local_addr()?;
+
+        let mdns = if self.advertise {
+            match self.register(local_addr.port()) {
+                Ok(registration) => Some(registration),
+                Err(e) => {
+                    warn!("Failed to advertise AirPlay receiver: {:#}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let shared = Arc::new(Shared {
+            hw_addr: self.hw_addr,
+            private_key: self.private_key,
+            cfg,
+            quality: self.quality,
+            tx,
+            events,
+            active_session: AtomicU64::new(0),
+            next_session: AtomicU64::new(1),
+            shutdown: watch::channel(false).0,
+        });
+
+        let accept_shared = shared.clone();
+        let accept_task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        debug!("AirPlay sender connected from {}", peer);
+                        tokio::spawn(session::run(accept_shared.clone(), stream, peer));
+                    }
+                    Err(e) => {
+                        warn!("AirPlay receiver accept failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        info!("AirPlay receiver '{}' listening on {}", self.name, local_addr);
+        Ok(AirPlayReceiverHandle {
+            shared,
+            local_addr,
+            accept_task,
+            mdns,
+        })
+    }
+
+    /// Register the `_raop._tcp` service, named `<hw addr>@<name>` as senders expect
+    fn register(&self, port: u16) -> Result<(ServiceDaemon, String)> {
+        let hw: String = self.hw_addr.iter().map(|b| format!("{:02X}", b)).collect();
+        let instance = format!("{}@{}", hw, self.name);
+        let host = format!("aaeq-{}.local.", hw.to_lowercase());
+
+        let daemon = ServiceDaemon::new().context("Failed to start mDNS daemon")?;
+        let info = ServiceInfo::new(
+            RAOP_SERVICE_TYPE,
+            &instance,
+            &host,
+            "",
+            port,
+            self.txt_properties().as_slice(),
+        )
+        .context("Invalid mDNS service info")?
+        .enable_addr_auto();
+        let fullname = info.get_fullname().to_string();
+        daemon.register(info).context("Failed to register mDNS service")?;
+
+        info!("Advertising AirPlay receiver '{}' on mDNS", self.name);
+        Ok((daemon, fullname))
+    }
+}
+
+/// A running [`AirPlayReceiver`]
+pub struct AirPlayReceiverHandle {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    accept_task: JoinHandle<()>,
+    mdns: Option<(ServiceDaemon, String)>,
+}
+
+impl AirPlayReceiverHandle {
+    /// Address RTSP is served on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Hardware address senders identify the receiver by
+    pub fn hw_addr(&self) -> [u8; 6] {
+        self.shared.hw_addr
+    }
+
+    /// Stop advertising, end any session and stop accepting senders
+    pub async fn stop(mut self) -> Result<()> {
+        if let Some((daemon, fullname)) = self.mdns.take() {
+            daemon.unregister(&fullname).ok();
+            daemon.shutdown().ok();
+        }
+        self.accept_task.abort();
+        self.shared.shutdown.send_replace(true);
+        info!("AirPlay receiver stopped");
+        Ok(())
+    }
+}
+
+impl Drop for AirPlayReceiverHandle {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+        self.shared.shutdown.send_replace(true);
+    }
+}
+
+/// State shared by the sessions
+struct Shared {
+    hw_addr: [u8; 6],
+    private_key: Option<RsaPrivateKey>,
+    cfg: OutputConfig,
+    quality: ResamplerQuality,
+    tx: mpsc::Sender<Vec<f64>>,
+    events: mpsc::UnboundedSender<AirPlayReceiverEvent>,
+    /// Session whose audio is played; a newly announced stream takes over
+    active_session: AtomicU64,
+    next_session: AtomicU64,
+    shutdown: watch::Sender<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_txt_record_advertises_encryption_with_key() {
+        let receiver = AirPlayReceiver::new("Den".to_string(), "0.0.0.0:5000".parse().unwrap());
+        let get = |txt: &[(String, String)], key: &str| {
+            txt.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone())
+        };
+
+        let txt = receiver.txt_properties();
+        assert_eq!(txt[0], ("txtvers".to_string(), "1".to_string()));
+        assert_eq!(get(&txt, "et").as_deref(), Some("0"));
+        assert_eq!(get(&txt, "cn").as_deref(), Some("0,1"));
+
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 512).unwrap();
+        let txt = receiver.clone().with_rsa_private_key(key).txt_properties();
+        assert_eq!(get(&txt, "et").as_deref(), Some("0,1"));
+
+        // Stable, locally administered unicast address
+        let again = AirPlayReceiver::new("Den".to_string(), "0.0.0.0:5000".parse().unwrap());
+        assert_eq!(receiver.hw_addr, again.hw_addr);
+        assert_eq!(receiver.hw_addr[0] & 0x03, 0x02);
+    }
+}
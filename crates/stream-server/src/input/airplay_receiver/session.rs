@@ -0,0 +1,446 @@
+//! RTSP control connection of one AirPlay sender
+use super::audio::{self, AudioCommand, AudioStream, StreamFormat};
+use super::{AirPlayReceiverEvent, Shared};
+use crate::sinks::airplay::{
+    apple_response, db_to_volume, decode_dmap_metadata, parse_fmtp, volume_to_db, AirPlayAuth, TimingServer,
+};
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// `Server` header; senders key quirks off the AirTunes version
+const SERVER: &str = "AirTunes/366.0";
+
+/// Methods answered in `Public`
+const METHODS: &str = "ANNOUNCE, SETUP, RECORD, PAUSE, FLUSH, TEARDOWN, OPTIONS, GET_PARAMETER, SET_PARAMETER";
+
+/// Output latency reported on RECORD, in frames (250 ms at 44.1 kHz)
+const AUDIO_LATENCY: u32 = 11025;
+
+/// Largest request body accepted (artwork is the biggest)
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// A request from the sender
+#[derive(Debug, Default)]
+struct Request {
+    method: String,
+    /// Header names are lowercased
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    fn content_type(&self) -> &str {
+        self.header("content-type").unwrap_or("")
+    }
+}
+
+/// A response to the sender
+struct Response {
+    code: u16,
+    reason: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn ok() -> Self {
+        Self::status(200, "OK")
+    }
+
+    fn status(code: u16, reason: &'static str) -> Self {
+        Self {
+            code,
+            reason,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+/// Read one request; `None` when the sender closed the connection
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Request>> {
+    let mut line = String::new();
+    // Tolerate blank lines between requests
+    while line.trim().is_empty() {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+    }
+
+    let method = line
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| anyhow!("Invalid RTSP request line: {}", line.trim()))?
+        .to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length: usize = headers
+        .get("content-length")
+        .map(|l| l.parse())
+        .transpose()
+        .context("Invalid Content-Length")?
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        return Err(anyhow!("RTSP body of {} bytes is too large", length));
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Some(Request { method, headers, body }))
+}
+
+fn encode_response(response: &Response, cseq: Option<&str>) -> Vec<u8> {
+    let mut head = format!("RTSP/1.0 {} {}\r\n", response.code, response.reason);
+    if let Some(cseq) = cseq {
+        head.push_str(&format!("CSeq: {}\r\n", cseq));
+    }
+    head.push_str(&format!("Server: {}\r\n", SERVER));
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !response.body.is_empty() {
+        head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    }
+    head.push_str("\r\n");
+
+    let mut out = head.into_bytes();
+    out.extend_from_slice(&response.body);
+    out
+}
+
+/// Parse the ANNOUNCE SDP into the stream format and its AES key and IV
+fn parse_sdp(sdp: &str) -> Result<(StreamFormat, Option<(String, String)>)> {
+    let attribute = |name: &str| {
+        sdp.lines()
+            .filter_map(|line| line.trim().strip_prefix("a="))
+            .find_map(|attr| attr.strip_prefix(name)?.strip_prefix(':'))
+            .map(str::trim)
+    };
+
+    // `96 AppleLossless` or `96 L16/44100/2`
+    let rtpmap = attribute("rtpmap").ok_or_else(|| anyhow!("SDP has no rtpmap"))?;
+    let encoding = rtpmap.split_whitespace().nth(1).unwrap_or("");
+    let format = if encoding.eq_ignore_ascii_case("AppleLossless") {
+        let fmtp = attribute("fmtp").ok_or_else(|| anyhow!("ALAC stream without fmtp"))?;
+        let (config, cookie) = parse_fmtp(fmtp)?;
+        StreamFormat::Alac { config, cookie }
+    } else if let Some(params) = encoding.strip_prefix("L16/") {
+        let mut params = params.split('/');
+        StreamFormat::L16 {
+            sample_rate: params.next().and_then(|r| r.parse().ok()).unwrap_or(44100),
+            channels: params.next().and_then(|c| c.parse().ok()).unwrap_or(2),
+        }
+    } else {
+        return Err(anyhow!("Unsupported AirPlay encoding: {}", rtpmap));
+    };
+
+    let keys = attribute("rsaaeskey").zip(attribute("aesiv"));
+    Ok((format, keys.map(|(key, iv)| (key.to_string(), iv.to_string()))))
+}
+
+/// Value of a `Transport` header parameter such as `control_port`
+fn transport_param<'a>(transport: &'a str, key: &str) -> Option<&'a str> {
+    transport
+        .split(';')
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim() == key)
+        .map(|(_, value)| value.trim())
+}
+
+/// A running audio task and the timing server that goes with it
+struct AudioTask {
+    commands: mpsc::UnboundedSender<AudioCommand>,
+    task: JoinHandle<()>,
+    _timing: TimingServer,
+}
+
+impl Drop for AudioTask {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+struct Session {
+    shared: Arc<Shared>,
+    peer: SocketAddr,
+    local_ip: IpAddr,
+    /// Non-zero once a stream is announced
+    id: u64,
+    announced: Option<(StreamFormat, Option<AirPlayAuth>)>,
+    audio: Option<AudioTask>,
+    volume: f32,
+}
+
+impl Session {
+    async fn handle(&mut self, request: &Request) -> Result<Response> {
+        match request.method.as_str() {
+            "OPTIONS" => Ok(Response::ok().header("Public", METHODS)),
+            "ANNOUNCE" => self.announce(request),
+            "SETUP" => self.setup(request).await,
+            "RECORD" => Ok(Response::ok().header("Audio-Latency", AUDIO_LATENCY.to_string())),
+            "FLUSH" | "PAUSE" => {
+                self.command(AudioCommand::Flush);
+                Ok(Response::ok())
+            }
+            "SET_PARAMETER" => self.set_parameter(request),
+            "GET_PARAMETER" => Ok(Response {
+                body: format!("volume: {:.6}\r\n", volume_to_db(self.volume)).into_bytes(),
+                ..Response::ok().header("Content-Type", "text/parameters")
+            }),
+            "TEARDOWN" => {
+                self.end();
+                Ok(Response::ok().header("Connection", "close"))
+            }
+            _ => Ok(Response::status(501, "Not Implemented")),
+        }
+    }
+
+    fn announce(&mut self, request: &Request) -> Result<Response> {
+        let (format, keys) = parse_sdp(&String::from_utf8_lossy(&request.body))?;
+        let auth = match (keys, &self.shared.private_key) {
+            (None, _) => None,
+            (Some((key, iv)), Some(private_key)) => Some(AirPlayAuth::from_sdp_keys(private_key, &key, &iv)?),
+            (Some(_), None) => return Err(anyhow!("Encrypted stream but no RSA private key configured")),
+        };
+        debug!("AirPlay stream from {}: {:?}", self.peer, format);
+
+        // A new announce on the same connection restarts the stream
+        self.audio = None;
+        self.announced = Some((format, auth));
+        if self.id == 0 {
+            self.id = self.shared.next_session.fetch_add(1, Ordering::SeqCst);
+            let _ = self.shared.events.send(AirPlayReceiverEvent::SessionStarted { client: self.peer });
+            info!("AirPlay session started by {}", self.peer);
+        }
+        self.shared.active_session.store(self.id, Ordering::SeqCst);
+        Ok(Response::ok())
+    }
+
+    async fn setup(&mut self, request: &Request) -> Result<Response> {
+        let (format, auth) = self
+            .announced
+            .clone()
+            .ok_or_else(|| anyhow!("SETUP before ANNOUNCE"))?;
+        let transport = request.header("transport").unwrap_or("");
+        let client_control = transport_param(transport, "control_port")
+            .and_then(|port| port.parse().ok())
+            .map(|port| SocketAddr::new(self.peer.ip(), port));
+
+        let audio_socket = UdpSocket::bind(SocketAddr::new(self.local_ip, 0)).await?;
+        let control_socket = UdpSocket::bind(SocketAddr::new(self.local_ip, 0)).await?;
+        let timing = TimingServer::new(0).await?;
+        let reply = format!(
+            "RTP/AVP/UDP;unicast;mode=record;server_port={};control_port={};timing_port={}",
+            audio_socket.local_addr()?.port(),
+            control_socket.local_addr()?.port(),
+            timing.local_port()
+        );
+
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let stream = AudioStream {
+            session_id: self.id,
+            format,
+            auth,
+            audio: audio_socket,
+            control: control_socket,
+            client_control,
+            gain: self.gain(),
+        };
+        let task = tokio::spawn(audio::run(self.shared.clone(), stream, commands_rx));
+        self.audio = Some(AudioTask {
+            commands,
+            task,
+            _timing: timing,
+        });
+
+        Ok(Response::ok()
+            .header("Transport", reply)
+            .header("Session", "1")
+            .header("Audio-Jack-Status", "connected; type=analog"))
+    }
+
+    fn set_parameter(&mut self, request: &Request) -> Result<Response> {
+        let content_type = request.content_type();
+        if content_type.starts_with("text/parameters") {
+            let body = String::from_utf8_lossy(&request.body);
+            for (name, value) in body.lines().filter_map(|line| line.split_once(':')) {
+                if name.trim() != "volume" {
+                    // `progress` is the only other parameter; playback isn't seekable here
+                    continue;
+                }
+                let db: f32 = value.trim().parse().context("Invalid volume")?;
+                self.volume = db_to_volume(db);
+                self.command(AudioCommand::Gain(self.gain()));
+                let _ = self.shared.events.send(AirPlayReceiverEvent::VolumeChanged { volume: self.volume });
+            }
+        } else if content_type.starts_with("application/x-dmap-tagged") {
+            let meta = decode_dmap_metadata(&request.body);
+            let _ = self.shared.events.send(AirPlayReceiverEvent::TrackChanged { meta });
+        } else if content_type.starts_with("image/") {
+            if !request.body.is_empty() {
+                let _ = self.shared.events.send(AirPlayReceiverEvent::Artwork {
+                    content_type: content_type.to_string(),
+                    data: request.body.clone(),
+                });
+            }
+        } else {
+            debug!("Ignoring AirPlay parameter of type {:?}", content_type);
+        }
+        Ok(Response::ok())
+    }
+
+    /// Gain for the sender's volume, with the same squared taper as the DLNA renderer
+    fn gain(&self) -> f64 {
+        (self.volume as f64).powi(2)
+    }
+
+    fn command(&self, command: AudioCommand) {
+        if let Some(audio) = &self.audio {
+            let _ = audio.commands.send(command);
+        }
+    }
+
+    /// Stop the stream and report the session over
+    fn end(&mut self) {
+        self.audio = None;
+        self.announced = None;
+        if self.id != 0 {
+            let _ = self.shared.active_session.compare_exchange(self.id, 0, Ordering::SeqCst, Ordering::SeqCst);
+            let _ = self.shared.events.send(AirPlayReceiverEvent::SessionEnded { client: self.peer });
+            info!("AirPlay session from {} ended", self.peer);
+            self.id = 0;
+        }
+    }
+}
+
+/// Serve one sender's RTSP connection until it closes or the receiver stops
+pub(super) async fn run(shared: Arc<Shared>, stream: TcpStream, peer: SocketAddr) {
+    let local_ip = match stream.local_addr() {
+        Ok(addr) => addr.ip(),
+        Err(e) => {
+            warn!("AirPlay connection from {} failed: {}", peer, e);
+            return;
+        }
+    };
+    let mut shutdown = shared.shutdown.subscribe();
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let mut session = Session {
+        shared,
+        peer,
+        local_ip,
+        id: 0,
+        announced: None,
+        audio: None,
+        volume: 1.0,
+    };
+
+    loop {
+        let request = tokio::select! {
+            request = read_request(&mut reader) => request,
+            _ = shutdown.changed() => break,
+        };
+        let request = match request {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Bad RTSP request from {}: {:#}", peer, e);
+                break;
+            }
+        };
+        debug!("AirPlay {} from {}", request.method, peer);
+
+        let mut response = session.handle(&request).await.unwrap_or_else(|e| {
+            warn!("AirPlay {} from {} failed: {:#}", request.method, peer, e);
+            Response::status(400, "Bad Request")
+        });
+
+        // Prove we hold the key senders expect, if we have it
+        if let (Some(challenge), Some(key)) = (request.header("apple-challenge"), &session.shared.private_key) {
+            match apple_response(key, challenge, local_ip, &session.shared.hw_addr) {
+                Ok(signature) => response.headers.push(("Apple-Response", signature)),
+                Err(e) => warn!("Failed to answer Apple-Challenge: {:#}", e),
+            }
+        }
+
+        let bytes = encode_response(&response, request.header("cseq"));
+        if let Err(e) = write.write_all(&bytes).await {
+            warn!("Failed to answer AirPlay sender {}: {}", peer, e);
+            break;
+        }
+        if request.method == "TEARDOWN" {
+            break;
+        }
+    }
+
+    session.end();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let raw = b"\r\nSET_PARAMETER rtsp://10.0.0.2/1 RTSP/1.0\r\nCSeq: 7\r\nContent-Type: text/parameters\r\nContent-Length: 20\r\n\r\nvolume: -15.000000\r\n";
+        let mut reader = BufReader::new(&raw[..]);
+
+        let request = read_request(&mut reader).await.unwrap().unwrap();
+        assert_eq!(request.method, "SET_PARAMETER");
+        assert_eq!(request.header("cseq"), Some("7"));
+        assert_eq!(request.content_type(), "text/parameters");
+        assert_eq!(request.body, b"volume: -15.000000\r\n");
+
+        assert!(read_request(&mut reader).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_sdp() {
+        let sdp = "v=0\r\nm=audio 0 RTP/AVP 96\r\na=rtpmap:96 AppleLossless\r\na=fmtp:96 352 0 16 40 10 14 2 255 0 0 44100\r\na=rsaaeskey:abc\r\na=aesiv:def\r\n";
+        let (format, keys) = parse_sdp(sdp).unwrap();
+        assert_eq!(format.sample_rate(), 44100);
+        assert_eq!(format.channels(), 2);
+        assert_eq!(keys, Some(("abc".to_string(), "def".to_string())));
+
+        let (format, keys) = parse_sdp("a=rtpmap:96 L16/48000/1\r\n").unwrap();
+        assert_eq!(format, StreamFormat::L16 { sample_rate: 48000, channels: 1 });
+        assert!(keys.is_none());
+
+        assert!(parse_sdp("a=rtpmap:96 mpeg4-generic/44100/2\r\n").is_err());
+    }
+
+    #[test]
+    fn test_transport_param() {
+        let transport = "RTP/AVP/UDP;unicast;interleaved=0-1;mode=record;control_port=6001;timing_port=6002";
+        assert_eq!(transport_param(transport, "control_port"), Some("6001"));
+        assert_eq!(transport_param(transport, "server_port"), None);
+    }
+}
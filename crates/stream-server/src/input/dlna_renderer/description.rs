@@ -0,0 +1,453 @@
+//! Device and service descriptions for the MediaRenderer
+//!
+//! The SCPDs are built from the action and state variable tables below, so
+//! the advertised interface and the SOAP handlers in `mod.rs` stay in sync.
+use crate::sinks::dlna::didl::escape_xml;
+
+pub const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+pub const RENDERING_CONTROL: &str = "urn:schemas-upnp-org:service:RenderingControl:1";
+pub const CONNECTION_MANAGER: &str = "urn:schemas-upnp-org:service:ConnectionManager:1";
+pub const MEDIA_RENDERER: &str = "urn:schemas-upnp-org:device:MediaRenderer:1";
+
+/// Formats the renderer accepts, as ConnectionManager `SinkProtocolInfo`
+pub const SINK_PROTOCOL_INFO: &str = "http-get:*:audio/flac:*,http-get:*:audio/x-flac:*,\
+http-get:*:audio/wav:*,http-get:*:audio/wave:*,http-get:*:audio/x-wav:*,\
+http-get:*:audio/mpeg:*,http-get:*:audio/mp4:*,http-get:*:audio/x-m4a:*,http-get:*:audio/aac:*,\
+http-get:*:audio/ogg:*,http-get:*:audio/x-ogg:*";
+
+/// (argument name, direction, related state variable)
+type Argument = (&'static str, &'static str, &'static str);
+
+struct Action {
+    name: &'static str,
+    arguments: &'static [Argument],
+}
+
+struct StateVariable {
+    name: &'static str,
+    data_type: &'static str,
+    send_events: bool,
+    allowed: &'static [&'static str],
+    range: Option<(u32, u32)>,
+}
+
+const fn var(name: &'static str, data_type: &'static str) -> StateVariable {
+    StateVariable {
+        name,
+        data_type,
+        send_events: false,
+        allowed: &[],
+        range: None,
+    }
+}
+
+const fn allowed(name: &'static str, values: &'static [&'static str]) -> StateVariable {
+    StateVariable {
+        name,
+        data_type: "string",
+        send_events: false,
+        allowed: values,
+        range: None,
+    }
+}
+
+const fn evented(name: &'static str) -> StateVariable {
+    StateVariable {
+        name,
+        data_type: "string",
+        send_events: true,
+        allowed: &[],
+        range: None,
+    }
+}
+
+const INSTANCE_ID: Argument = ("InstanceID", "in", "A_ARG_TYPE_InstanceID");
+
+const AV_TRANSPORT_ACTIONS: &[Action] = &[
+    Action {
+        name: "SetAVTransportURI",
+        arguments: &[
+            INSTANCE_ID,
+            ("CurrentURI", "in", "AVTransportURI"),
+            ("CurrentURIMetaData", "in", "AVTransportURIMetaData"),
+        ],
+    },
+    Action {
+        name: "SetNextAVTransportURI",
+        arguments: &[
+            INSTANCE_ID,
+            ("NextURI", "in", "NextAVTransportURI"),
+            ("NextURIMetaData", "in", "NextAVTransportURIMetaData"),
+        ],
+    },
+    Action {
+        name: "GetMediaInfo",
+        arguments: &[
+            INSTANCE_ID,
+            ("NrTracks", "out", "NumberOfTracks"),
+            ("MediaDuration", "out", "CurrentMediaDuration"),
+            ("CurrentURI", "out", "AVTransportURI"),
+            ("CurrentURIMetaData", "out", "AVTransportURIMetaData"),
+            ("NextURI", "out", "NextAVTransportURI"),
+            ("NextURIMetaData", "out", "NextAVTransportURIMetaData"),
+            ("PlayMedium", "out", "PlaybackStorageMedium"),
+            ("RecordMedium", "out", "RecordStorageMedium"),
+            ("WriteStatus", "out", "RecordMediumWriteStatus"),
+        ],
+    },
+    Action {
+        name: "GetTransportInfo",
+        arguments: &[
+            INSTANCE_ID,
+            ("CurrentTransportState", "out", "TransportState"),
+            ("CurrentTransportStatus", "out", "TransportStatus"),
+            ("CurrentSpeed", "out", "TransportPlaySpeed"),
+        ],
+    },
+    Action {
+        name: "GetPositionInfo",
+        arguments: &[
+            INSTANCE_ID,
+            ("Track", "out", "CurrentTrack"),
+            ("TrackDuration", "out", "CurrentTrackDuration"),
+            ("TrackMetaData", "out", "CurrentTrackMetaData"),
+            ("TrackURI", "out", "CurrentTrackURI"),
+            ("RelTime", "out", "RelativeTimePosition"),
+            ("AbsTime", "out", "AbsoluteTimePosition"),
+            ("RelCount", "out", "RelativeCounterPosition"),
+            ("AbsCount", "out", "AbsoluteCounterPosition"),
+        ],
+    },
+    Action {
+        name: "GetDeviceCapabilities",
+        arguments: &[
+            INSTANCE_ID,
+            ("PlayMedia", "out", "PossiblePlaybackStorageMedia"),
+            ("RecMedia", "out", "PossibleRecordStorageMedia"),
+            ("RecQualityModes", "out", "PossibleRecordQualityModes"),
+        ],
+    },
+    Action {
+        name: "GetTransportSettings",
+        arguments: &[
+            INSTANCE_ID,
+            ("PlayMode", "out", "CurrentPlayMode"),
+            ("RecQualityMode", "out", "CurrentRecordQualityMode"),
+        ],
+    },
+    Action {
+        name: "GetCurrentTransportActions",
+        arguments: &[INSTANCE_ID, ("Actions", "out", "CurrentTransportActions")],
+    },
+    Action {
+        name: "Stop",
+        arguments: &[INSTANCE_ID],
+    },
+    Action {
+        name: "Play",
+        arguments: &[INSTANCE_ID, ("Speed", "in", "TransportPlaySpeed")],
+    },
+    Action {
+        name: "Pause",
+        arguments: &[INSTANCE_ID],
+    },
+    Action {
+        name: "Seek",
+        arguments: &[
+            INSTANCE_ID,
+            ("Unit", "in", "A_ARG_TYPE_SeekMode"),
+            ("Target", "in", "A_ARG_TYPE_SeekTarget"),
+        ],
+    },
+    Action {
+        name: "Next",
+        arguments: &[INSTANCE_ID],
+    },
+    Action {
+        name: "Previous",
+        arguments: &[INSTANCE_ID],
+    },
+];
+
+const AV_TRANSPORT_VARIABLES: &[StateVariable] = &[
+    allowed(
+        "TransportState",
+        &["STOPPED", "PLAYING", "PAUSED_PLAYBACK", "TRANSITIONING", "NO_MEDIA_PRESENT"],
+    ),
+    allowed("TransportStatus", &["OK", "ERROR_OCCURRED"]),
+    allowed("PlaybackStorageMedium", &["NONE", "NETWORK"]),
+    allowed("RecordStorageMedium", &["NOT_IMPLEMENTED"]),
+    var("PossiblePlaybackStorageMedia", "string"),
+    var("PossibleRecordStorageMedia", "string"),
+    allowed("CurrentPlayMode", &["NORMAL"]),
+    allowed("TransportPlaySpeed", &["1"]),
+    allowed("RecordMediumWriteStatus", &["NOT_IMPLEMENTED"]),
+    allowed("CurrentRecordQualityMode", &["NOT_IMPLEMENTED"]),
+    var("PossibleRecordQualityModes", "string"),
+    var("NumberOfTracks", "ui4"),
+    var("CurrentTrack", "ui4"),
+    var("CurrentTrackDuration", "string"),
+    var("CurrentMediaDuration", "string"),
+    var("CurrentTrackMetaData", "string"),
+    var("CurrentTrackURI", "string"),
+    var("AVTransportURI", "string"),
+    var("AVTransportURIMetaData", "string"),
+    var("NextAVTransportURI", "string"),
+    var("NextAVTransportURIMetaData", "string"),
+    var("RelativeTimePosition", "string"),
+    var("AbsoluteTimePosition", "string"),
+    var("RelativeCounterPosition", "i4"),
+    var("AbsoluteCounterPosition", "i4"),
+    var("CurrentTransportActions", "string"),
+    evented("LastChange"),
+    allowed("A_ARG_TYPE_SeekMode", &["REL_TIME"]),
+    var("A_ARG_TYPE_SeekTarget", "string"),
+    var("A_ARG_TYPE_InstanceID", "ui4"),
+];
+
+const RENDERING_CONTROL_ACTIONS: &[Action] = &[
+    Action {
+        name: "ListPresets",
+        arguments: &[INSTANCE_ID, ("CurrentPresetNameList", "out", "PresetNameList")],
+    },
+    Action {
+        name: "SelectPreset",
+        arguments: &[INSTANCE_ID, ("PresetName", "in", "A_ARG_TYPE_PresetName")],
+    },
+    Action {
+        name: "GetMute",
+        arguments: &[
+            INSTANCE_ID,
+            ("Channel", "in", "A_ARG_TYPE_Channel"),
+            ("CurrentMute", "out", "Mute"),
+        ],
+    },
+    Action {
+        name: "SetMute",
+        arguments: &[
+            INSTANCE_ID,
+            ("Channel", "in", "A_ARG_TYPE_Channel"),
+            ("DesiredMute", "in", "Mute"),
+        ],
+    },
+    Action {
+        name: "GetVolume",
+        arguments: &[
+            INSTANCE_ID,
+            ("Channel", "in", "A_ARG_TYPE_Channel"),
+            ("CurrentVolume", "out", "Volume"),
+        ],
+    },
+    Action {
+        name: "SetVolume",
+        arguments: &[
+            INSTANCE_ID,
+            ("Channel", "in", "A_ARG_TYPE_Channel"),
+            ("DesiredVolume", "in", "Volume"),
+        ],
+    },
+];
+
+const RENDERING_CONTROL_VARIABLES: &[StateVariable] = &[
+    var("PresetNameList", "string"),
+    evented("LastChange"),
+    var("Mute", "boolean"),
+    StateVariable {
+        name: "Volume",
+        data_type: "ui2",
+        send_events: false,
+        allowed: &[],
+        range: Some((0, 100)),
+    },
+    allowed("A_ARG_TYPE_Channel", &["Master"]),
+    var("A_ARG_TYPE_InstanceID", "ui4"),
+    allowed("A_ARG_TYPE_PresetName", &["FactoryDefaults"]),
+];
+
+const CONNECTION_MANAGER_ACTIONS: &[Action] = &[
+    Action {
+        name: "GetProtocolInfo",
+        arguments: &[("Source", "out", "SourceProtocolInfo"), ("Sink", "out", "SinkProtocolInfo")],
+    },
+    Action {
+        name: "GetCurrentConnectionIDs",
+        arguments: &[("ConnectionIDs", "out", "CurrentConnectionIDs")],
+    },
+    Action {
+        name: "GetCurrentConnectionInfo",
+        arguments: &[
+            ("ConnectionID", "in", "A_ARG_TYPE_ConnectionID"),
+            ("RcsID", "out", "A_ARG_TYPE_RcsID"),
+            ("AVTransportID", "out", "A_ARG_TYPE_AVTransportID"),
+            ("ProtocolInfo", "out", "A_ARG_TYPE_ProtocolInfo"),
+            ("PeerConnectionManager", "out", "A_ARG_TYPE_ConnectionManager"),
+            ("PeerConnectionID", "out", "A_ARG_TYPE_ConnectionID"),
+            ("Direction", "out", "A_ARG_TYPE_Direction"),
+            ("Status", "out", "A_ARG_TYPE_ConnectionStatus"),
+        ],
+    },
+];
+
+const CONNECTION_MANAGER_VARIABLES: &[StateVariable] = &[
+    evented("SourceProtocolInfo"),
+    evented("SinkProtocolInfo"),
+    evented("CurrentConnectionIDs"),
+    allowed(
+        "A_ARG_TYPE_ConnectionStatus",
+        &["OK", "ContentFormatMismatch", "InsufficientBandwidth", "UnreliableChannel", "Unknown"],
+    ),
+    var("A_ARG_TYPE_ConnectionManager", "string"),
+    allowed("A_ARG_TYPE_Direction", &["Input", "Output"]),
+    var("A_ARG_TYPE_ProtocolInfo", "string"),
+    var("A_ARG_TYPE_ConnectionID", "i4"),
+    var("A_ARG_TYPE_AVTransportID", "i4"),
+    var("A_ARG_TYPE_RcsID", "i4"),
+];
+
+/// Root device description advertising a MediaRenderer
+pub fn generate_renderer_description(device_uuid: &str, friendly_name: &str) -> String {
+    let services: String = [
+        (RENDERING_CONTROL, "RenderingControl"),
+        (CONNECTION_MANAGER, "ConnectionManager"),
+        (AV_TRANSPORT, "AVTransport"),
+    ]
+    .iter()
+    .map(|(service_type, name)| {
+        format!(
+            r#"
+      <service>
+        <serviceType>{service_type}</serviceType>
+        <serviceId>urn:upnp-org:serviceId:{name}</serviceId>
+        <SCPDURL>/upnp/{name}.xml</SCPDURL>
+        <controlURL>/upnp/control/{name}</controlURL>
+        <eventSubURL>/upnp/event/{name}</eventSubURL>
+      </service>"#
+        )
+    })
+    .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0" xmlns:dlna="urn:schemas-dlna-org:device-1-0">
+  <specVersion>
+    <major>1</major>
+    <minor>0</minor>
+  </specVersion>
+  <device>
+    <deviceType>{MEDIA_RENDERER}</deviceType>
+    <friendlyName>{friendly_name}</friendlyName>
+    <manufacturer>AAEQ</manufacturer>
+    <manufacturerURL>https://github.com/jaschadub/AAEQ</manufacturerURL>
+    <modelDescription>Adaptive Audio Equalizer renderer with DSP</modelDescription>
+    <modelName>AAEQ Renderer</modelName>
+    <modelNumber>1.0</modelNumber>
+    <modelURL>https://github.com/jaschadub/AAEQ</modelURL>
+    <UDN>{device_uuid}</UDN>
+    <dlna:X_DLNADOC>DMR-1.50</dlna:X_DLNADOC>
+    <serviceList>{services}
+    </serviceList>
+  </device>
+</root>"#,
+        friendly_name = escape_xml(friendly_name),
+    )
+}
+
+pub fn generate_av_transport_scpd() -> String {
+    scpd(AV_TRANSPORT_ACTIONS, AV_TRANSPORT_VARIABLES)
+}
+
+pub fn generate_rendering_control_scpd() -> String {
+    scpd(RENDERING_CONTROL_ACTIONS, RENDERING_CONTROL_VARIABLES)
+}
+
+pub fn generate_connection_manager_scpd() -> String {
+    scpd(CONNECTION_MANAGER_ACTIONS, CONNECTION_MANAGER_VARIABLES)
+}
+
+fn scpd(actions: &[Action], variables: &[StateVariable]) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<scpd xmlns="urn:schemas-upnp-org:service-1-0">
+  <specVersion>
+    <major>1</major>
+    <minor>0</minor>
+  </specVersion>
+  <actionList>"#,
+    );
+
+    for action in actions {
+        xml.push_str(&format!("\n    <action>\n      <name>{}</name>\n      <argumentList>", action.name));
+        for (name, direction, related) in action.arguments {
+            xml.push_str(&format!(
+                "\n        <argument><name>{}</name><direction>{}</direction><relatedStateVariable>{}</relatedStateVariable></argument>",
+                name, direction, related
+            ));
+        }
+        xml.push_str("\n      </argumentList>\n    </action>");
+    }
+    xml.push_str("\n  </actionList>\n  <serviceStateTable>");
+
+    for variable in variables {
+        xml.push_str(&format!(
+            "\n    <stateVariable sendEvents=\"{}\">\n      <name>{}</name>\n      <dataType>{}</dataType>",
+            if variable.send_events { "yes" } else { "no" },
+            variable.name,
+            variable.data_type
+        ));
+        if !variable.allowed.is_empty() {
+            xml.push_str("\n      <allowedValueList>");
+            for value in variable.allowed {
+                xml.push_str(&format!("<allowedValue>{}</allowedValue>", value));
+            }
+            xml.push_str("</allowedValueList>");
+        }
+        if let Some((min, max)) = variable.range {
+            xml.push_str(&format!(
+                "\n      <allowedValueRange><minimum>{}</minimum><maximum>{}</maximum><step>1</step></allowedValueRange>",
+                min, max
+            ));
+        }
+        xml.push_str("\n    </stateVariable>");
+    }
+    xml.push_str("\n  </serviceStateTable>\n</scpd>");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scpd_references_declared_variables() {
+        for (actions, variables) in [
+            (AV_TRANSPORT_ACTIONS, AV_TRANSPORT_VARIABLES),
+            (RENDERING_CONTROL_ACTIONS, RENDERING_CONTROL_VARIABLES),
+            (CONNECTION_MANAGER_ACTIONS, CONNECTION_MANAGER_VARIABLES),
+        ] {
+            for action in actions {
+                for (name, _, related) in action.arguments {
+                    assert!(
+                        variables.iter().any(|v| v.name == *related),
+                        "{}.{} refers to undeclared {}",
+                        action.name,
+                        name,
+                        related
+                    );
+                }
+            }
+        }
+
+        let xml = generate_rendering_control_scpd();
+        assert!(xml.contains("<name>SetVolume</name>"));
+        assert!(xml.contains("<maximum>100</maximum>"));
+    }
+
+    #[test]
+    fn test_renderer_description() {
+        let xml = generate_renderer_description("uuid:1234", "Living Room & Kitchen");
+        assert!(xml.contains("<deviceType>urn:schemas-upnp-org:device:MediaRenderer:1</deviceType>"));
+        assert!(xml.contains("Living Room &amp; Kitchen"));
+        assert!(xml.contains("<controlURL>/upnp/control/RenderingControl</controlURL>"));
+        assert!(!xml.contains("ContentDirectory"));
+    }
+}
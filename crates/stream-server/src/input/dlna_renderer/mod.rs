@@ -0,0 +1,851 @@
+//! DLNA MediaRenderer input
+//!
+//! Advertises AAEQ as a UPnP MediaRenderer so control points (BubbleUPnP,
+//! mconnect, Windows "Cast to Device", ...) can send tracks to it. The
+//! renderer fetches the URI it's given, decodes it with the file input's
+//! decoders and sends the audio down the same `Vec<f64>` channel as the other
+//! inputs, so it plays through the DSP chain to whichever sink is selected.
+//!
+//! DIDL-Lite metadata from the control point is turned into a `TrackMeta`
+//! (falling back to the stream's own tags) and reported with
+//! [`DlnaRendererEvent::TrackChanged`] for preset rule matching.
+mod description;
+mod playback;
+mod soap;
+
+use crate::dsp::ResamplerQuality;
+use crate::sinks::dlna::didl::{parse_didl_lite, MediaMetadata};
+use crate::sinks::dlna::SsdpServer;
+use crate::types::OutputConfig;
+use aaeq_core::TrackMeta;
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{any, get, post},
+    Router,
+};
+use description::{AV_TRANSPORT, CONNECTION_MANAGER, MEDIA_RENDERER, RENDERING_CONTROL, SINK_PROTOCOL_INFO};
+use soap::{SoapRequest, UpnpError};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// How long an event subscription lasts without renewal
+const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(1800);
+
+const AV_TRANSPORT_EVENTS: &str = "urn:schemas-upnp-org:metadata-1-0/AVT/";
+const RENDERING_CONTROL_EVENTS: &str = "urn:schemas-upnp-org:metadata-1-0/RCS/";
+
+/// AVTransport state as reported to control points
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportState {
+    NoMediaPresent,
+    Stopped,
+    /// The URI is being fetched and opened
+    Transitioning,
+    Playing,
+    PausedPlayback,
+}
+
+impl TransportState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransportState::NoMediaPresent => "NO_MEDIA_PRESENT",
+            TransportState::Stopped => "STOPPED",
+            TransportState::Transitioning => "TRANSITIONING",
+            TransportState::Playing => "PLAYING",
+            TransportState::PausedPlayback => "PAUSED_PLAYBACK",
+        }
+    }
+}
+
+/// What control points did with the renderer
+#[derive(Debug, Clone, PartialEq)]
+pub enum DlnaRendererEvent {
+    /// A track was opened and is about to play
+    TrackChanged { uri: String, meta: TrackMeta },
+    TransportStateChanged(TransportState),
+    /// Volume (0-100) or mute changed; the gain is applied before the audio is sent
+    VolumeChanged { volume: u8, muted: bool },
+    /// The URI couldn't be fetched or decoded
+    PlaybackFailed { uri: String, error: String },
+}
+
+/// Input that plays whatever UPnP control points send it
+#[derive(Debug, Clone)]
+pub struct DlnaRenderer {
+    friendly_name: String,
+    bind_addr: SocketAddr,
+    uuid: String,
+    advertise: bool,
+    quality: ResamplerQuality,
+}
+
+impl DlnaRenderer {
+    /// Create a renderer that serves its control endpoints on `bind_addr`
+    ///
+    /// The device UUID is derived from the hostname and `friendly_name`, so
+    /// control points recognise the renderer across restarts.
+    pub fn new(friendly_name: String, bind_addr: SocketAddr) -> Self {
+        let host = hostname::get()
+            .map(|h| h.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "aaeq".to_string());
+        let uuid = Uuid::new_v5(&Uuid::NAMESPACE_DNS, format!("{}/renderer/{}", host, friendly_name).as_bytes());
+
+        Self {
+            friendly_name,
+            bind_addr,
+            uuid: format!("uuid:{}", uuid),
+            advertise: true,
+            quality: ResamplerQuality::default(),
+        }
+    }
+
+    /// Use a specific device UUID (`uuid:...`)
+    pub fn with_uuid(mut self, uuid: String) -> Self {
+        self.uuid = uuid;
+        self
+    }
+
+    /// Announce the renderer over SSDP (default); disable to only serve the
+    /// control endpoints, e.g. for tests
+    pub fn with_advertise(mut self, advertise: bool) -> Self {
+        self.advertise = advertise;
+        self
+    }
+
+    /// Resampler quality for streams whose rate differs from the output
+    pub fn with_resampler_quality(mut self, quality: ResamplerQuality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Start serving control points
+    ///
+    /// # Arguments
+    /// * `cfg` - Output sample rate and channel count to convert to
+    /// * `tx` - Channel to send decoded audio samples, paced in realtime
+    /// * `events` - Channel for track, transport and volume changes
+    pub async fn start(
+        self,
+        cfg: OutputConfig,
+        tx: mpsc::Sender<Vec<f64>>,
+        events: mpsc::UnboundedSender<DlnaRendererEvent>,
+    ) -> Result<DlnaRendererHandle> {
+        let listener = tokio::net::TcpListener::bind(self.bind_addr)
+            .await
+            .with_context(|| format!("Failed to bind DLNA renderer to {}", self.bind_addr))?;
+        let local_addr = listener.local_addr()?;
+
+        let shared = Arc::new(Shared {
+            uuid: self.uuid.clone(),
+            friendly_name: self.friendly_name.clone(),
+            cfg,
+            quality: self.quality,
+            tx,
+            events,
+            state: Mutex::new(RendererState::default()),
+            position: AtomicU64::new(0),
+            paused: watch::channel(false).0,
+            av_transport_changed: watch::channel(()).0,
+            rendering_control_changed: watch::channel(()).0,
+            subscriptions: Mutex::new(Vec::new()),
+            client: reqwest::Client::builder().timeout(Duration::from_secs(5)).build()?,
+        });
+
+        let app = Router::new()
+            .route("/device.xml", get(device_description_handler))
+            .route("/upnp/AVTransport.xml", get(|| async { xml(StatusCode::OK, description::generate_av_transport_scpd()) }))
+            .route(
+                "/upnp/RenderingControl.xml",
+                get(|| async { xml(StatusCode::OK, description::generate_rendering_control_scpd()) }),
+            )
+            .route(
+                "/upnp/ConnectionManager.xml",
+                get(|| async { xml(StatusCode::OK, description::generate_connection_manager_scpd()) }),
+            )
+            .route("/upnp/control/:service", post(control_handler))
+            .route("/upnp/event/:service", any(event_handler))
+            .with_state(shared.clone());
+
+        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+        tokio::spawn(async move {
+            tokio::select! {
+                result = axum::serve(listener, app) => {
+                    if let Err(e) = result {
+                        error!("DLNA renderer server error: {}", e);
+                    }
+                }
+                _ = shutdown_rx.recv() => {}
+            }
+        });
+
+        let notifiers = vec![
+            tokio::spawn(notify_task(shared.clone(), Service::AVTransport)),
+            tokio::spawn(notify_task(shared.clone(), Service::RenderingControl)),
+        ];
+
+        let ssdp_server = if self.advertise {
+            let mut ssdp_server = SsdpServer::new(self.uuid.clone(), self.friendly_name.clone(), local_addr.port())
+                .with_targets(&[MEDIA_RENDERER, AV_TRANSPORT, CONNECTION_MANAGER, RENDERING_CONTROL]);
+            match ssdp_server.start().await {
+                Ok(()) => Some(ssdp_server),
+                Err(e) => {
+                    warn!("Failed to start SSDP server for DLNA renderer: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        info!("DLNA renderer '{}' listening on {}", self.friendly_name, local_addr);
+        Ok(DlnaRendererHandle {
+            shared,
+            local_addr,
+            shutdown_tx,
+            notifiers,
+            ssdp_server,
+        })
+    }
+}
+
+/// A running [`DlnaRenderer`]
+pub struct DlnaRendererHandle {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    shutdown_tx: mpsc::Sender<()>,
+    notifiers: Vec<JoinHandle<()>>,
+    ssdp_server: Option<SsdpServer>,
+}
+
+impl DlnaRendererHandle {
+    /// Address the control endpoints are served on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn uuid(&self) -> &str {
+        &self.shared.uuid
+    }
+
+    pub fn state(&self) -> TransportState {
+        self.shared.lock().transport
+    }
+
+    /// Current volume (0-100) and mute
+    pub fn volume(&self) -> (u8, bool) {
+        let state = self.shared.lock();
+        (state.volume, state.muted)
+    }
+
+    /// Stop playback, say goodbye over SSDP and shut the server down
+    pub async fn stop(mut self) -> Result<()> {
+        {
+            let mut state = self.shared.lock();
+            self.shared.stop_playback(&mut state);
+        }
+        if let Some(mut ssdp_server) = self.ssdp_server.take() {
+            ssdp_server.stop().await?;
+        }
+        for notifier in &self.notifiers {
+            notifier.abort();
+        }
+        let _ = self.shutdown_tx.send(()).await;
+        info!("DLNA renderer '{}' stopped", self.shared.friendly_name);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Service {
+    AVTransport,
+    RenderingControl,
+    ConnectionManager,
+}
+
+impl Service {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "AVTransport" => Some(Service::AVTransport),
+            "RenderingControl" => Some(Service::RenderingControl),
+            "ConnectionManager" => Some(Service::ConnectionManager),
+            _ => None,
+        }
+    }
+
+    fn service_type(self) -> &'static str {
+        match self {
+            Service::AVTransport => AV_TRANSPORT,
+            Service::RenderingControl => RENDERING_CONTROL,
+            Service::ConnectionManager => CONNECTION_MANAGER,
+        }
+    }
+}
+
+/// A URI set by a control point
+#[derive(Debug, Clone)]
+struct Media {
+    uri: String,
+    /// DIDL-Lite as sent, echoed back in `GetMediaInfo`/`GetPositionInfo`
+    metadata: String,
+    didl: Option<MediaMetadata>,
+}
+
+impl Media {
+    fn new(uri: &str, metadata: &str) -> Self {
+        Self {
+            uri: uri.to_string(),
+            metadata: metadata.to_string(),
+            didl: parse_didl_lite(metadata),
+        }
+    }
+}
+
+struct RendererState {
+    transport: TransportState,
+    /// `TransportStatus`: OK or ERROR_OCCURRED
+    status: &'static str,
+    current: Option<Media>,
+    next: Option<Media>,
+    /// Duration of the current track in seconds, from DIDL-Lite or the stream
+    duration: Option<u64>,
+    volume: u8,
+    muted: bool,
+    /// Bumped whenever playback starts or stops, so stale playbacks exit
+    generation: u64,
+    /// Dropping this stops the decode thread
+    stop_tx: Option<mpsc::Sender<()>>,
+}
+
+impl Default for RendererState {
+    fn default() -> Self {
+        Self {
+            transport: TransportState::NoMediaPresent,
+            status: "OK",
+            current: None,
+            next: None,
+            duration: None,
+            volume: 100,
+            muted: false,
+            generation: 0,
+            stop_tx: None,
+        }
+    }
+}
+
+struct Subscription {
+    sid: String,
+    service: Service,
+    callbacks: Vec<String>,
+    seq: u32,
+    expires: Instant,
+}
+
+type ActionResult = std::result::Result<Vec<(&'static str, String)>, UpnpError>;
+
+/// State shared by the HTTP handlers, playback and event notifiers
+struct Shared {
+    uuid: String,
+    friendly_name: String,
+    cfg: OutputConfig,
+    quality: ResamplerQuality,
+    tx: mpsc::Sender<Vec<f64>>,
+    events: mpsc::UnboundedSender<DlnaRendererEvent>,
+    state: Mutex<RendererState>,
+    /// Frames of the current track sent to `tx`
+    position: AtomicU64,
+    paused: watch::Sender<bool>,
+    av_transport_changed: watch::Sender<()>,
+    rendering_control_changed: watch::Sender<()>,
+    subscriptions: Mutex<Vec<Subscription>>,
+    client: reqwest::Client,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, RendererState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_transport(&self, state: &mut RendererState, transport: TransportState) {
+        if state.transport != transport {
+            debug!("DLNA renderer: {} -> {}", state.transport.as_str(), transport.as_str());
+            state.transport = transport;
+            let _ = self.events.send(DlnaRendererEvent::TransportStateChanged(transport));
+        }
+        self.av_transport_changed.send_replace(());
+    }
+
+    fn stop_playback(&self, state: &mut RendererState) {
+        state.generation += 1;
+        state.stop_tx = None;
+        self.position.store(0, Ordering::Relaxed);
+        self.paused.send_replace(false);
+    }
+
+    /// Start playing `state.current` from the beginning
+    fn play_current(self: &Arc<Self>, state: &mut RendererState) {
+        let Some(media) = state.current.clone() else { return };
+        self.stop_playback(state);
+
+        let (stop_tx, stop_rx) = mpsc::channel(1);
+        state.stop_tx = Some(stop_tx);
+        state.status = "OK";
+        state.duration = media
+            .didl
+            .as_ref()
+            .and_then(|d| d.duration.as_deref())
+            .and_then(soap::parse_time)
+            .map(|d| d as u64);
+        self.set_transport(state, TransportState::Transitioning);
+
+        info!("DLNA renderer playing {}", media.uri);
+        playback::spawn(self.clone(), media, state.generation, stop_rx);
+    }
+
+    fn handle_action(self: &Arc<Self>, service: Service, request: &SoapRequest) -> ActionResult {
+        if service != Service::ConnectionManager && !matches!(request.arg("InstanceID"), "0" | "") {
+            return Err(soap::INVALID_INSTANCE_ID);
+        }
+
+        match service {
+            Service::AVTransport => self.av_transport_action(request),
+            Service::RenderingControl => self.rendering_control_action(request),
+            Service::ConnectionManager => connection_manager_action(request),
+        }
+    }
+
+    fn av_transport_action(self: &Arc<Self>, request: &SoapRequest) -> ActionResult {
+        let mut state = self.lock();
+        let state = &mut *state;
+
+        match request.action.as_str() {
+            "SetAVTransportURI" => {
+                let uri = request.arg("CurrentURI");
+                if uri.is_empty() {
+                    return Err(soap::INVALID_ARGS);
+                }
+                state.current = Some(Media::new(uri, request.arg("CurrentURIMetaData")));
+
+                // Switching tracks while playing carries on playing
+                if matches!(state.transport, TransportState::Playing | TransportState::Transitioning) {
+                    self.play_current(state);
+                } else {
+                    self.stop_playback(state);
+                    self.set_transport(state, TransportState::Stopped);
+                }
+            }
+            "SetNextAVTransportURI" => {
+                let uri = request.arg("NextURI");
+                state.next = (!uri.is_empty()).then(|| Media::new(uri, request.arg("NextURIMetaData")));
+                self.av_transport_changed.send_replace(());
+            }
+            "Play" => match state.transport {
+                TransportState::PausedPlayback => {
+                    self.paused.send_replace(false);
+                    self.set_transport(state, TransportState::Playing);
+                }
+                TransportState::Playing | TransportState::Transitioning => {}
+                TransportState::Stopped => self.play_current(state),
+                TransportState::NoMediaPresent => return Err(soap::TRANSITION_NOT_AVAILABLE),
+            },
+            "Pause" => match state.transport {
+                TransportState::Playing | TransportState::Transitioning => {
+                    self.paused.send_replace(true);
+                    self.set_transport(state, TransportState::PausedPlayback);
+                }
+                TransportState::PausedPlayback => {}
+                _ => return Err(soap::TRANSITION_NOT_AVAILABLE),
+            },
+            "Stop" => {
+                if state.transport == TransportState::NoMediaPresent {
+                    return Err(soap::TRANSITION_NOT_AVAILABLE);
+                }
+                self.stop_playback(state);
+                self.set_transport(state, TransportState::Stopped);
+            }
+            "Next" => {
+                let next = state.next.take().ok_or(soap::TRANSITION_NOT_AVAILABLE)?;
+                state.current = Some(next);
+                self.play_current(state);
+            }
+            "Previous" => return Err(soap::TRANSITION_NOT_AVAILABLE),
+            "Seek" => return Err(soap::SEEK_MODE_NOT_SUPPORTED),
+            "GetTransportInfo" => {
+                return Ok(vec![
+                    ("CurrentTransportState", state.transport.as_str().to_string()),
+                    ("CurrentTransportStatus", state.status.to_string()),
+                    ("CurrentSpeed", "1".to_string()),
+                ]);
+            }
+            "GetPositionInfo" => {
+                let elapsed = self.position.load(Ordering::Relaxed) / self.cfg.sample_rate.max(1) as u64;
+                let current = state.current.as_ref();
+                return Ok(vec![
+                    ("Track", if current.is_some() { "1" } else { "0" }.to_string()),
+                    ("TrackDuration", soap::format_time(state.duration.unwrap_or(0))),
+                    ("TrackMetaData", current.map(|m| m.metadata.clone()).unwrap_or_default()),
+                    ("TrackURI", current.map(|m| m.uri.clone()).unwrap_or_default()),
+                    ("RelTime", soap::format_time(elapsed)),
+                    ("AbsTime", soap::format_time(elapsed)),
+                    ("RelCount", i32::MAX.to_string()),
+                    ("AbsCount", i32::MAX.to_string()),
+                ]);
+            }
+            "GetMediaInfo" => {
+                let current = state.current.as_ref();
+                let next = state.next.as_ref();
+                return Ok(vec![
+                    ("NrTracks", if current.is_some() { "1" } else { "0" }.to_string()),
+                    ("MediaDuration", soap::format_time(state.duration.unwrap_or(0))),
+                    ("CurrentURI", current.map(|m| m.uri.clone()).unwrap_or_default()),
+                    ("CurrentURIMetaData", current.map(|m| m.metadata.clone()).unwrap_or_default()),
+                    ("NextURI", next.map(|m| m.uri.clone()).unwrap_or_default()),
+                    ("NextURIMetaData", next.map(|m| m.metadata.clone()).unwrap_or_default()),
+                    ("PlayMedium", if current.is_some() { "NETWORK" } else { "NONE" }.to_string()),
+                    ("RecordMedium", "NOT_IMPLEMENTED".to_string()),
+                    ("WriteStatus", "NOT_IMPLEMENTED".to_string()),
+                ]);
+            }
+            "GetTransportSettings" => {
+                return Ok(vec![
+                    ("PlayMode", "NORMAL".to_string()),
+                    ("RecQualityMode", "NOT_IMPLEMENTED".to_string()),
+                ]);
+            }
+            "GetDeviceCapabilities" => {
+                return Ok(vec![
+                    ("PlayMedia", "NETWORK".to_string()),
+                    ("RecMedia", "NOT_IMPLEMENTED".to_string()),
+                    ("RecQualityModes", "NOT_IMPLEMENTED".to_string()),
+                ]);
+            }
+            "GetCurrentTransportActions" => {
+                return Ok(vec![("Actions", transport_actions(state))]);
+            }
+            _ => return Err(soap::INVALID_ACTION),
+        }
+        Ok(Vec::new())
+    }
+
+    fn rendering_control_action(&self, request: &SoapRequest) -> ActionResult {
+        let mut state = self.lock();
+        match request.action.as_str() {
+            "GetVolume" => return Ok(vec![("CurrentVolume", state.volume.to_string())]),
+            "GetMute" => return Ok(vec![("CurrentMute", if state.muted { "1" } else { "0" }.to_string())]),
+            "ListPresets" => return Ok(vec![("CurrentPresetNameList", "FactoryDefaults".to_string())]),
+            "SetVolume" => {
+                state.volume = request
+                    .arg("DesiredVolume")
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|v| *v <= 100)
+                    .ok_or(soap::INVALID_ARGS)?;
+            }
+            "SetMute" => {
+                state.muted = match request.arg("DesiredMute") {
+                    "1" | "true" | "True" => true,
+                    "0" | "false" | "False" => false,
+                    _ => return Err(soap::INVALID_ARGS),
+                };
+            }
+            "SelectPreset" => {
+                if request.arg("PresetName") != "FactoryDefaults" {
+                    return Err(soap::INVALID_ARGS);
+                }
+                state.volume = 100;
+                state.muted = false;
+            }
+            _ => return Err(soap::INVALID_ACTION),
+        }
+
+        let _ = self.events.send(DlnaRendererEvent::VolumeChanged {
+            volume: state.volume,
+            muted: state.muted,
+        });
+        self.rendering_control_changed.send_replace(());
+        Ok(Vec::new())
+    }
+
+    /// Body of the event sent to subscribers of `service`
+    fn event_body(&self, service: Service) -> String {
+        let state = self.lock();
+        match service {
+            Service::AVTransport => {
+                let current = state.current.as_ref();
+                let change = soap::last_change(
+                    AV_TRANSPORT_EVENTS,
+                    &[
+                        ("TransportState", None, state.transport.as_str().to_string()),
+                        ("TransportStatus", None, state.status.to_string()),
+                        ("CurrentTransportActions", None, transport_actions(&state)),
+                        ("NumberOfTracks", None, if current.is_some() { "1" } else { "0" }.to_string()),
+                        ("AVTransportURI", None, current.map(|m| m.uri.clone()).unwrap_or_default()),
+                        ("AVTransportURIMetaData", None, current.map(|m| m.metadata.clone()).unwrap_or_default()),
+                        ("CurrentTrackURI", None, current.map(|m| m.uri.clone()).unwrap_or_default()),
+                        ("CurrentTrackMetaData", None, current.map(|m| m.metadata.clone()).unwrap_or_default()),
+                        ("CurrentTrackDuration", None, soap::format_time(state.duration.unwrap_or(0))),
+                        ("NextAVTransportURI", None, state.next.as_ref().map(|m| m.uri.clone()).unwrap_or_default()),
+                    ],
+                );
+                soap::property_set(&[("LastChange", change)])
+            }
+            Service::RenderingControl => {
+                let change = soap::last_change(
+                    RENDERING_CONTROL_EVENTS,
+                    &[
+                        ("Volume", Some("Master"), state.volume.to_string()),
+                        ("Mute", Some("Master"), if state.muted { "1" } else { "0" }.to_string()),
+                        ("PresetNameList", None, "FactoryDefaults".to_string()),
+                    ],
+                );
+                soap::property_set(&[("LastChange", change)])
+            }
+            Service::ConnectionManager => soap::property_set(&[
+                ("SourceProtocolInfo", String::new()),
+                ("SinkProtocolInfo", SINK_PROTOCOL_INFO.to_string()),
+                ("CurrentConnectionIDs", "0".to_string()),
+            ]),
+        }
+    }
+
+    /// Send the current state of `service` to its subscribers
+    async fn notify_subscribers(&self, service: Service) {
+        let body = self.event_body(service);
+        let targets: Vec<(String, Vec<String>, u32)> = {
+            let mut subscriptions = self.subscriptions.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            subscriptions.retain(|s| s.expires > now);
+            subscriptions
+                .iter_mut()
+                .filter(|s| s.service == service)
+                .map(|s| {
+                    let seq = s.seq;
+                    s.seq = s.seq.checked_add(1).unwrap_or(1);
+                    (s.sid.clone(), s.callbacks.clone(), seq)
+                })
+                .collect()
+        };
+
+        for (sid, callbacks, seq) in targets {
+            send_event(&self.client, &callbacks, &sid, seq, &body).await;
+        }
+    }
+}
+
+fn transport_actions(state: &RendererState) -> String {
+    let actions = match state.transport {
+        TransportState::NoMediaPresent => "",
+        TransportState::Stopped => "Play",
+        TransportState::Transitioning | TransportState::Playing => "Pause,Stop",
+        TransportState::PausedPlayback => "Play,Stop",
+    };
+    match (state.next.is_some(), actions.is_empty()) {
+        (true, true) => "Next".to_string(),
+        (true, false) => format!("{},Next", actions),
+        (false, _) => actions.to_string(),
+    }
+}
+
+fn connection_manager_action(request: &SoapRequest) -> ActionResult {
+    match request.action.as_str() {
+        "GetProtocolInfo" => Ok(vec![("Source", String::new()), ("Sink", SINK_PROTOCOL_INFO.to_string())]),
+        "GetCurrentConnectionIDs" => Ok(vec![("ConnectionIDs", "0".to_string())]),
+        "GetCurrentConnectionInfo" => {
+            if request.arg("ConnectionID") != "0" {
+                return Err(soap::INVALID_CONNECTION);
+            }
+            Ok(vec![
+                ("RcsID", "0".to_string()),
+                ("AVTransportID", "0".to_string()),
+                ("ProtocolInfo", String::new()),
+                ("PeerConnectionManager", String::new()),
+                ("PeerConnectionID", "-1".to_string()),
+                ("Direction", "Input".to_string()),
+                ("Status", "OK".to_string()),
+            ])
+        }
+        _ => Err(soap::INVALID_ACTION),
+    }
+}
+
+/// Send a GENA `NOTIFY`, trying each callback URL until one accepts it
+async fn send_event(client: &reqwest::Client, callbacks: &[String], sid: &str, seq: u32, body: &str) {
+    let method = reqwest::Method::from_bytes(b"NOTIFY").expect("NOTIFY is a valid method");
+    for callback in callbacks {
+        let result = client
+            .request(method.clone(), callback)
+            .header("CONTENT-TYPE", r#"text/xml; charset="utf-8""#)
+            .header("NT", "upnp:event")
+            .header("NTS", "upnp:propchange")
+            .header("SID", sid)
+            .header("SEQ", seq.to_string())
+            .body(body.to_string())
+            .send()
+            .await;
+        match result {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => debug!("Event to {} rejected: {}", callback, response.status()),
+            Err(e) => debug!("Failed to send event to {}: {}", callback, e),
+        }
+    }
+}
+
+async fn notify_task(shared: Arc<Shared>, service: Service) {
+    let mut changed = match service {
+        Service::RenderingControl => shared.rendering_control_changed.subscribe(),
+        _ => shared.av_transport_changed.subscribe(),
+    };
+    while changed.changed().await.is_ok() {
+        shared.notify_subscribers(service).await;
+    }
+}
+
+fn xml(status: StatusCode, body: String) -> Response {
+    (status, [(header::CONTENT_TYPE, r#"text/xml; charset="utf-8""#)], body).into_response()
+}
+
+async fn device_description_handler(State(shared): State<Arc<Shared>>) -> Response {
+    xml(
+        StatusCode::OK,
+        description::generate_renderer_description(&shared.uuid, &shared.friendly_name),
+    )
+}
+
+async fn control_handler(State(shared): State<Arc<Shared>>, Path(name): Path<String>, body: String) -> Response {
+    let Some(service) = Service::from_name(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(request) = soap::parse_soap_request(&body) else {
+        return xml(StatusCode::INTERNAL_SERVER_ERROR, soap::soap_fault(soap::INVALID_ACTION));
+    };
+
+    debug!("DLNA renderer {}#{}", name, request.action);
+    match shared.handle_action(service, &request) {
+        Ok(args) => xml(
+            StatusCode::OK,
+            soap::soap_response(service.service_type(), &request.action, &args),
+        ),
+        Err(e) => {
+            debug!("{}#{} failed: {} {}", name, request.action, e.code, e.description);
+            xml(StatusCode::INTERNAL_SERVER_ERROR, soap::soap_fault(e))
+        }
+    }
+}
+
+/// GENA `SUBSCRIBE`/`UNSUBSCRIBE`
+async fn event_handler(
+    State(shared): State<Arc<Shared>>,
+    Path(name): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    let Some(service) = Service::from_name(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+    let mut subscriptions = shared.subscriptions.lock().unwrap_or_else(|e| e.into_inner());
+
+    match method.as_str() {
+        "SUBSCRIBE" => {
+            let sid = match header("sid") {
+                // Renewal
+                Some(sid) => {
+                    let Some(subscription) = subscriptions.iter_mut().find(|s| s.sid == sid) else {
+                        return StatusCode::PRECONDITION_FAILED.into_response();
+                    };
+                    subscription.expires = Instant::now() + SUBSCRIPTION_TIMEOUT;
+                    sid.to_string()
+                }
+                None => {
+                    let callbacks = parse_callbacks(header("callback").unwrap_or(""));
+                    if callbacks.is_empty() || header("nt") != Some("upnp:event") {
+                        return StatusCode::PRECONDITION_FAILED.into_response();
+                    }
+
+                    let sid = format!("uuid:{}", Uuid::new_v4());
+                    subscriptions.push(Subscription {
+                        sid: sid.clone(),
+                        service,
+                        callbacks: callbacks.clone(),
+                        seq: 1,
+                        expires: Instant::now() + SUBSCRIPTION_TIMEOUT,
+                    });
+
+                    // Initial event with the full state
+                    let shared = shared.clone();
+                    let initial_sid = sid.clone();
+                    tokio::spawn(async move {
+                        let body = shared.event_body(service);
+                        send_event(&shared.client, &callbacks, &initial_sid, 0, &body).await;
+                    });
+                    debug!("{} subscribed to {} events", sid, name);
+                    sid
+                }
+            };
+
+            (
+                StatusCode::OK,
+                [
+                    ("SID", sid),
+                    ("TIMEOUT", format!("Second-{}", SUBSCRIPTION_TIMEOUT.as_secs())),
+                ],
+            )
+                .into_response()
+        }
+        "UNSUBSCRIBE" => {
+            let Some(sid) = header("sid") else {
+                return StatusCode::PRECONDITION_FAILED.into_response();
+            };
+            let before = subscriptions.len();
+            subscriptions.retain(|s| s.sid != sid);
+            if subscriptions.len() == before {
+                StatusCode::PRECONDITION_FAILED.into_response()
+            } else {
+                StatusCode::OK.into_response()
+            }
+        }
+        _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+    }
+}
+
+/// Parse a GENA `CALLBACK` header: one or more `<url>`
+fn parse_callbacks(header: &str) -> Vec<String> {
+    header
+        .split(['<', '>'])
+        .map(str::trim)
+        .filter(|url| url.starts_with("http://"))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_callbacks() {
+        assert_eq!(
+            parse_callbacks("<http://192.168.1.5:49152/event> <http://10.0.0.2/cb>"),
+            vec!["http://192.168.1.5:49152/event", "http://10.0.0.2/cb"]
+        );
+        assert!(parse_callbacks("http-get://nope").is_empty());
+    }
+
+    #[test]
+    fn test_transport_actions() {
+        let mut state = RendererState::default();
+        assert_eq!(transport_actions(&state), "");
+        state.transport = TransportState::Playing;
+        assert_eq!(transport_actions(&state), "Pause,Stop");
+        state.next = Some(Media::new("http://host/next.flac", ""));
+        assert_eq!(transport_actions(&state), "Pause,Stop,Next");
+    }
+}
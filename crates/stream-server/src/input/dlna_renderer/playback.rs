@@ -0,0 +1,252 @@
+//! Fetching, decoding and pacing the URI a control point set
+//!
+//! The stream is fetched and decoded on its own thread as fast as the
+//! forwarder accepts it; the forwarder paces blocks to the wall clock, holds
+//! them while paused and applies the renderer volume.
+use super::{DlnaRendererEvent, Media, Shared, TransportState};
+use crate::input::file::{decode_stream, open_source, OpenTrack};
+use aaeq_core::TrackMeta;
+use anyhow::{anyhow, Context, Result};
+use std::io::Read;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use symphonia::core::io::ReadOnlySource;
+use symphonia::core::probe::Hint;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, warn};
+
+/// How far playback may run ahead of the wall clock
+const REALTIME_LEAD: Duration = Duration::from_millis(100);
+
+/// HTTP response body as a symphonia source
+struct HttpBody(Mutex<reqwest::blocking::Response>);
+
+impl Read for HttpBody {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.get_mut().unwrap_or_else(|e| e.into_inner()).read(buf)
+    }
+}
+
+/// Start playing `media` as playback `generation`
+pub(super) fn spawn(shared: Arc<Shared>, media: Media, generation: u64, stop_rx: mpsc::Receiver<()>) {
+    let (block_tx, block_rx) = mpsc::channel(4);
+    let (done_tx, done_rx) = oneshot::channel();
+
+    let decoder_shared = shared.clone();
+    let uri = media.uri.clone();
+    let spawned = std::thread::Builder::new()
+        .name("dlna-renderer".to_string())
+        .spawn(move || {
+            let result = open_uri(&media.uri).and_then(|track| {
+                decoder_shared.track_opened(generation, &media, &track);
+                decode_stream(
+                    track,
+                    &media.uri,
+                    &decoder_shared.cfg,
+                    decoder_shared.quality,
+                    block_tx,
+                    stop_rx,
+                )
+            });
+            let _ = done_tx.send(result);
+        });
+    if let Err(e) = spawned {
+        error!("Failed to spawn DLNA renderer thread: {}", e);
+    }
+
+    tokio::spawn(forward(shared, uri, generation, block_rx, done_rx));
+}
+
+/// GET `uri` and open it for decoding
+fn open_uri(uri: &str) -> Result<OpenTrack> {
+    // No overall timeout: the body is read for as long as the track plays
+    let client = reqwest::blocking::Client::builder()
+        .timeout(None)
+        .connect_timeout(Duration::from_secs(10))
+        .build()?;
+    let response = client
+        .get(uri)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to fetch {}", uri))?;
+
+    let mut hint = Hint::new();
+    if let Some(mime) = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    {
+        hint.mime_type(mime.split(';').next().unwrap_or(mime).trim());
+    }
+    if let Some(extension) = std::path::Path::new(response.url().path())
+        .extension()
+        .and_then(|e| e.to_str())
+    {
+        hint.with_extension(extension);
+    }
+
+    let source = ReadOnlySource::new(HttpBody(Mutex::new(response)));
+    open_source(Box::new(source), &hint, uri)
+}
+
+/// Overlay the control point's metadata on the stream's tags
+///
+/// Control points usually know more than the stream (a library genre, album
+/// art), but fields they leave out are taken from the tags.
+fn merge_meta(didl: TrackMeta, tags: TrackMeta) -> TrackMeta {
+    let pick = |preferred: String, fallback: String| if preferred.is_empty() { fallback } else { preferred };
+    TrackMeta {
+        artist: pick(didl.artist, tags.artist),
+        title: pick(didl.title, tags.title),
+        album: pick(didl.album, tags.album),
+        genre: pick(didl.genre, tags.genre),
+        device_genre: pick(didl.device_genre, tags.device_genre),
+        album_art_url: didl.album_art_url.or(tags.album_art_url),
+    }
+}
+
+impl Shared {
+    fn track_opened(&self, generation: u64, media: &Media, track: &OpenTrack) {
+        let meta = match &media.didl {
+            Some(didl) => merge_meta(didl.to_track_meta(), track.meta.clone()),
+            None => track.meta.clone(),
+        };
+
+        let mut state = self.lock();
+        if state.generation != generation {
+            return;
+        }
+        if state.duration.is_none() {
+            state.duration = track.frames.map(|f| f / track.sample_rate.max(1) as u64);
+        }
+        let _ = self.events.send(DlnaRendererEvent::TrackChanged {
+            uri: media.uri.clone(),
+            meta,
+        });
+        self.av_transport_changed.send_replace(());
+    }
+
+    /// The first block of playback `generation` is ready
+    fn started(&self, generation: u64) {
+        let mut state = self.lock();
+        if state.generation == generation && state.transport == TransportState::Transitioning {
+            self.set_transport(&mut state, TransportState::Playing);
+        }
+    }
+
+    /// Gain for the current volume, or `None` if `generation` is stale
+    fn gain(&self, generation: u64) -> Option<f64> {
+        let state = self.lock();
+        if state.generation != generation {
+            return None;
+        }
+        // Squared taper so the low half of the slider is usable
+        Some(if state.muted { 0.0 } else { (state.volume as f64 / 100.0).powi(2) })
+    }
+
+    fn finished(self: &Arc<Self>, uri: &str, generation: u64, result: Result<bool>) {
+        let mut state = self.lock();
+        if state.generation != generation {
+            return;
+        }
+        state.stop_tx = None;
+
+        match result {
+            Ok(true) => {
+                if let Some(next) = state.next.take() {
+                    state.current = Some(next);
+                    self.play_current(&mut state);
+                    return;
+                }
+            }
+            Ok(false) => return,
+            Err(e) => {
+                warn!("DLNA renderer failed to play {}: {:#}", uri, e);
+                state.status = "ERROR_OCCURRED";
+                let _ = self.events.send(DlnaRendererEvent::PlaybackFailed {
+                    uri: uri.to_string(),
+                    error: format!("{:#}", e),
+                });
+            }
+        }
+        self.stop_playback(&mut state);
+        self.set_transport(&mut state, TransportState::Stopped);
+    }
+}
+
+/// Pass decoded blocks on in realtime, honouring pause and volume
+async fn forward(
+    shared: Arc<Shared>,
+    uri: String,
+    generation: u64,
+    mut rx: mpsc::Receiver<Vec<f64>>,
+    done_rx: oneshot::Receiver<Result<bool>>,
+) {
+    let channels = shared.cfg.channels.max(1) as usize;
+    let sample_rate = shared.cfg.sample_rate.max(1) as f64;
+    let mut paused = shared.paused.subscribe();
+    // Wall clock and frame count playback is paced from; reset on resume
+    let mut clock: Option<(Instant, u64)> = None;
+    let mut frames = 0u64;
+
+    while let Some(mut block) = rx.recv().await {
+        if frames == 0 {
+            shared.started(generation);
+        }
+        while *paused.borrow_and_update() {
+            clock = None;
+            if paused.changed().await.is_err() {
+                return;
+            }
+        }
+        let Some(gain) = shared.gain(generation) else {
+            return;
+        };
+
+        let (start, start_frames) = *clock.get_or_insert((Instant::now(), frames));
+        let due = start + Duration::from_secs_f64((frames - start_frames) as f64 / sample_rate);
+        if let Some(wait) = due.checked_duration_since(Instant::now() + REALTIME_LEAD) {
+            tokio::time::sleep(wait).await;
+        }
+
+        if gain != 1.0 {
+            block.iter_mut().for_each(|s| *s *= gain);
+        }
+        frames += (block.len() / channels) as u64;
+        shared.position.store(frames, Ordering::Relaxed);
+        if shared.tx.send(block).await.is_err() {
+            return;
+        }
+    }
+
+    let result = done_rx
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("Playback thread exited")));
+    shared.finished(&uri, generation, result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_meta_prefers_didl() {
+        let didl = TrackMeta {
+            title: "So What".to_string(),
+            genre: "Jazz".to_string(),
+            ..Default::default()
+        };
+        let tags = TrackMeta {
+            artist: "Miles Davis".to_string(),
+            title: "so_what".to_string(),
+            genre: "Other".to_string(),
+            ..Default::default()
+        };
+
+        let meta = merge_meta(didl, tags);
+        assert_eq!(meta.title, "So What");
+        assert_eq!(meta.artist, "Miles Davis");
+        assert_eq!(meta.genre, "Jazz");
+    }
+}
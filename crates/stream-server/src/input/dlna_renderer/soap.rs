@@ -0,0 +1,233 @@
+//! SOAP control messages and GENA event bodies for the renderer services
+use crate::sinks::dlna::didl::escape_xml;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::HashMap;
+
+/// An action invoked by a control point
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoapRequest {
+    pub action: String,
+    pub args: HashMap<String, String>,
+}
+
+impl SoapRequest {
+    /// Argument value, or an empty string if it wasn't sent
+    pub fn arg(&self, name: &str) -> &str {
+        self.args.get(name).map(String::as_str).unwrap_or("")
+    }
+}
+
+/// UPnP error returned as a SOAP fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpnpError {
+    pub code: u16,
+    pub description: &'static str,
+}
+
+pub const INVALID_ACTION: UpnpError = UpnpError { code: 401, description: "Invalid Action" };
+pub const INVALID_ARGS: UpnpError = UpnpError { code: 402, description: "Invalid Args" };
+pub const TRANSITION_NOT_AVAILABLE: UpnpError = UpnpError { code: 701, description: "Transition not available" };
+pub const SEEK_MODE_NOT_SUPPORTED: UpnpError = UpnpError { code: 710, description: "Seek mode not supported" };
+pub const INVALID_CONNECTION: UpnpError = UpnpError { code: 706, description: "Invalid connection reference" };
+pub const INVALID_INSTANCE_ID: UpnpError = UpnpError { code: 718, description: "Invalid InstanceID" };
+
+/// Parse a SOAP envelope into the action name and its arguments
+///
+/// Argument values are unescaped, so `CurrentURIMetaData` comes back as the
+/// DIDL-Lite document the control point sent.
+pub fn parse_soap_request(xml: &str) -> Option<SoapRequest> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut in_body = false;
+    let mut action: Option<String> = None;
+    let mut args = HashMap::new();
+    let mut field: Option<String> = None;
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                if !in_body {
+                    in_body = name == "Body";
+                } else if action.is_none() {
+                    action = Some(name);
+                } else {
+                    args.insert(name.clone(), String::new());
+                    field = Some(name);
+                }
+            }
+            Ok(Event::Empty(e)) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                if !in_body {
+                    continue;
+                }
+                if action.is_none() {
+                    action = Some(name);
+                } else {
+                    args.insert(name, String::new());
+                }
+            }
+            Ok(Event::Text(e)) => {
+                if let (Some(name), Ok(text)) = (&field, e.unescape()) {
+                    args.insert(name.clone(), text.into_owned());
+                }
+            }
+            Ok(Event::CData(e)) => {
+                if let Some(name) = &field {
+                    args.insert(name.clone(), String::from_utf8_lossy(&e).into_owned());
+                }
+            }
+            Ok(Event::End(e)) => {
+                if field.is_some() {
+                    field = None;
+                } else if action.is_some() || e.local_name().as_ref() == b"Body" {
+                    break;
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    action.map(|action| SoapRequest { action, args })
+}
+
+/// Response envelope for a successful action
+pub fn soap_response(service_type: &str, action: &str, args: &[(&str, String)]) -> String {
+    let body: String = args
+        .iter()
+        .map(|(name, value)| format!("<{name}>{}</{name}>", escape_xml(value)))
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+  <s:Body>
+    <u:{action}Response xmlns:u="{service_type}">{body}</u:{action}Response>
+  </s:Body>
+</s:Envelope>"#
+    )
+}
+
+/// Fault envelope for a failed action (sent with HTTP 500)
+pub fn soap_fault(error: UpnpError) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+  <s:Body>
+    <s:Fault>
+      <faultcode>s:Client</faultcode>
+      <faultstring>UPnPError</faultstring>
+      <detail>
+        <UPnPError xmlns="urn:schemas-upnp-org:control-1-0">
+          <errorCode>{}</errorCode>
+          <errorDescription>{}</errorDescription>
+        </UPnPError>
+      </detail>
+    </s:Fault>
+  </s:Body>
+</s:Envelope>"#,
+        error.code, error.description
+    )
+}
+
+/// `LastChange` value for AVTransport or RenderingControl
+///
+/// `namespace` is the service's event metadata namespace; each variable is
+/// `(name, channel, value)`, with `channel` set for RenderingControl's
+/// per-channel variables.
+pub fn last_change(namespace: &str, variables: &[(&str, Option<&str>, String)]) -> String {
+    let body: String = variables
+        .iter()
+        .map(|(name, channel, value)| match channel {
+            Some(channel) => format!(r#"<{name} channel="{channel}" val="{}"/>"#, escape_xml(value)),
+            None => format!(r#"<{name} val="{}"/>"#, escape_xml(value)),
+        })
+        .collect();
+    format!(r#"<Event xmlns="{namespace}"><InstanceID val="0">{body}</InstanceID></Event>"#)
+}
+
+/// GENA `NOTIFY` body carrying the given state variables
+pub fn property_set(properties: &[(&str, String)]) -> String {
+    let body: String = properties
+        .iter()
+        .map(|(name, value)| format!("<e:property><{name}>{}</{name}></e:property>", escape_xml(value)))
+        .collect();
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0">{body}</e:propertyset>"#
+    )
+}
+
+/// Format seconds as the `H:MM:SS` UPnP uses for times and durations
+pub fn format_time(seconds: u64) -> String {
+    format!("{}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60)
+}
+
+/// Parse an `H:MM:SS[.F]` time into seconds
+pub fn parse_time(time: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+    for part in time.trim().split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_soap_request() {
+        let xml = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+  <s:Body>
+    <u:SetAVTransportURI xmlns:u="urn:schemas-upnp-org:service:AVTransport:1">
+      <InstanceID>0</InstanceID>
+      <CurrentURI>http://host/track.flac?a=1&amp;b=2</CurrentURI>
+      <CurrentURIMetaData>&lt;DIDL-Lite&gt;&lt;/DIDL-Lite&gt;</CurrentURIMetaData>
+    </u:SetAVTransportURI>
+  </s:Body>
+</s:Envelope>"#;
+
+        let request = parse_soap_request(xml).unwrap();
+        assert_eq!(request.action, "SetAVTransportURI");
+        assert_eq!(request.arg("InstanceID"), "0");
+        assert_eq!(request.arg("CurrentURI"), "http://host/track.flac?a=1&b=2");
+        assert_eq!(request.arg("CurrentURIMetaData"), "<DIDL-Lite></DIDL-Lite>");
+        assert_eq!(request.arg("Missing"), "");
+
+        let empty = r#"<s:Envelope xmlns:s="x"><s:Body><u:Stop xmlns:u="y"><InstanceID>0</InstanceID><Speed/></u:Stop></s:Body></s:Envelope>"#;
+        let request = parse_soap_request(empty).unwrap();
+        assert_eq!(request.action, "Stop");
+        assert!(request.args.contains_key("Speed"));
+
+        assert!(parse_soap_request("not xml").is_none());
+    }
+
+    #[test]
+    fn test_last_change_is_escaped_once_per_level() {
+        let change = last_change(
+            "urn:schemas-upnp-org:metadata-1-0/RCS/",
+            &[("Volume", Some("Master"), "40".to_string())],
+        );
+        assert_eq!(
+            change,
+            r#"<Event xmlns="urn:schemas-upnp-org:metadata-1-0/RCS/"><InstanceID val="0"><Volume channel="Master" val="40"/></InstanceID></Event>"#
+        );
+
+        let body = property_set(&[("LastChange", change)]);
+        assert!(body.contains("<LastChange>&lt;Event xmlns=&quot;"));
+    }
+
+    #[test]
+    fn test_times() {
+        assert_eq!(format_time(0), "0:00:00");
+        assert_eq!(format_time(3725), "1:02:05");
+        assert_eq!(parse_time("1:02:05"), Some(3725.0));
+        assert_eq!(parse_time("0:03:21.500"), Some(201.5));
+        assert_eq!(parse_time("abc"), None);
+    }
+}
@@ -0,0 +1,667 @@
+//! File playback input
+//!
+//! Decodes local files (FLAC, WAV, MP3, AAC/M4A, Ogg Vorbis) and playlists
+//! into the same `Vec<f64>` channel the capture inputs use, so the DSP chain
+//! and sinks don't care where the audio came from. Tags are read into a
+//! `TrackMeta` for each track, letting preset rules resolve without a media
+//! player.
+//!
+//! Playback is gapless: encoder delay and padding are trimmed, and
+//! consecutive tracks at the same sample rate are joined sample-accurately
+//! through a single resampler.
+use crate::dsp::{Resampler, ResamplerQuality};
+use crate::types::OutputConfig;
+use aaeq_core::TrackMeta;
+use anyhow::{anyhow, Context, Result};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// File extensions picked up when a directory is given as a source
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["flac", "wav", "mp3", "m4a", "aac", "mp4", "ogg", "oga"];
+
+/// Frames per block sent on the audio channel
+const BLOCK_FRAMES: usize = 1024;
+
+/// How far realtime playback may run ahead of the wall clock
+const REALTIME_LEAD: Duration = Duration::from_millis(100);
+
+/// Progress reported while a [`FileInput`] plays
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileInputEvent {
+    /// A track started
+    ///
+    /// `position` is the output frame its first sample lands on; it is
+    /// approximate when the track is resampled.
+    TrackStarted {
+        index: usize,
+        path: PathBuf,
+        meta: TrackMeta,
+        sample_rate: u32,
+        position: u64,
+    },
+    /// A track couldn't be opened and was skipped
+    TrackFailed {
+        index: usize,
+        path: PathBuf,
+        error: String,
+    },
+    /// The playlist ended; `frames` is the total number of frames sent
+    Finished { frames: u64 },
+}
+
+/// Input source that plays local files
+#[derive(Debug, Clone)]
+pub struct FileInput {
+    tracks: Vec<PathBuf>,
+    realtime: bool,
+    quality: ResamplerQuality,
+}
+
+impl FileInput {
+    /// Create an input for the given files
+    ///
+    /// Playlists (`.m3u`, `.m3u8`, `.pls`) and directories are expanded; see
+    /// [`load_playlist`].
+    pub fn new<P: AsRef<Path>>(sources: &[P]) -> Result<Self> {
+        let mut tracks = Vec::new();
+        for source in sources {
+            tracks.extend(load_playlist(source.as_ref())?);
+        }
+        if tracks.is_empty() {
+            return Err(anyhow!("No playable files found"));
+        }
+
+        Ok(Self {
+            tracks,
+            realtime: true,
+            quality: ResamplerQuality::default(),
+        })
+    }
+
+    /// Pace output to the wall clock (default), or decode as fast as the
+    /// receiver accepts blocks, e.g. for offline rendering
+    pub fn with_realtime(mut self, realtime: bool) -> Self {
+        self.realtime = realtime;
+        self
+    }
+
+    /// Resampler quality for files whose rate differs from the output
+    pub fn with_resampler_quality(mut self, quality: ResamplerQuality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub fn tracks(&self) -> &[PathBuf] {
+        &self.tracks
+    }
+
+    /// Start playback on a dedicated thread
+    ///
+    /// # Arguments
+    /// * `cfg` - Output sample rate and channel count to convert to
+    /// * `tx` - Channel to send decoded audio samples
+    /// * `events` - Channel for track changes; may be dropped by the receiver
+    ///
+    /// # Returns
+    /// A stop_sender channel - send `()` (or drop it) to stop playback.
+    pub fn start_playback(
+        self,
+        cfg: OutputConfig,
+        tx: mpsc::Sender<Vec<f64>>,
+        events: mpsc::UnboundedSender<FileInputEvent>,
+    ) -> Result<mpsc::Sender<()>> {
+        info!(
+            "Starting file playback: {} track(s) at {} Hz, {} ch{}",
+            self.tracks.len(),
+            cfg.sample_rate,
+            cfg.channels,
+            if self.realtime { "" } else { " (offline)" }
+        );
+
+        let (stop_tx, stop_rx) = mpsc::channel::<()>(1);
+        std::thread::Builder::new()
+            .name("file-input".to_string())
+            .spawn(move || {
+                let mut player = Player {
+                    output: Output {
+                        tx,
+                        stop_rx,
+                        realtime: self.realtime,
+                        sample_rate: cfg.sample_rate,
+                        started: Instant::now(),
+                        frames_sent: 0,
+                    },
+                    channels: cfg.channels.max(1) as usize,
+                    quality: self.quality,
+                    resampler: None,
+                    pending: Vec::new(),
+                };
+
+                match player.play(&self.tracks, &events) {
+                    Ok(()) => {
+                        info!("File playback finished ({} frames)", player.output.frames_sent);
+                        let _ = events.send(FileInputEvent::Finished {
+                            frames: player.output.frames_sent,
+                        });
+                    }
+                    Err(Stopped) => info!("File playback stopped"),
+                }
+            })?;
+
+        Ok(stop_tx)
+    }
+}
+
+/// Expand a playlist, directory or single file into a list of tracks
+///
+/// `.m3u`/`.m3u8` entries and `.pls` `FileN=` entries are resolved relative
+/// to the playlist; URLs are skipped. Directories yield their supported
+/// files sorted by name.
+pub fn load_playlist(path: &Path) -> Result<Vec<PathBuf>> {
+    if path.is_dir() {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read directory {}", path.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && is_supported(p))
+            .collect();
+        files.sort();
+        return Ok(files);
+    }
+
+    let extension = extension(path);
+    if !matches!(extension.as_str(), "m3u" | "m3u8" | "pls") {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read playlist {}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let entries = text.lines().map(str::trim).filter_map(|line| {
+        if extension == "pls" {
+            let (key, value) = line.split_once('=')?;
+            key.starts_with("File").then_some(value.trim())
+        } else {
+            (!line.is_empty() && !line.starts_with('#')).then_some(line)
+        }
+    });
+
+    Ok(entries
+        .filter(|entry| !entry.contains("://"))
+        .map(|entry| base.join(entry))
+        .collect())
+}
+
+/// Tags and stream parameters of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackInfo {
+    pub meta: TrackMeta,
+    pub sample_rate: u32,
+    /// Length in frames, when the container declares it
+    pub frames: Option<u64>,
+}
+
+/// Read a file's tags into a `TrackMeta`
+///
+/// The title falls back to the file name when the file has no tags.
+pub fn read_track_meta(path: &Path) -> Result<TrackMeta> {
+    Ok(open_track(path)?.meta)
+}
+
+/// Read a file's tags, sample rate and length without decoding it
+pub fn read_track_info(path: &Path) -> Result<TrackInfo> {
+    let track = open_track(path)?;
+    Ok(TrackInfo {
+        frames: track.frames,
+        sample_rate: track.sample_rate,
+        meta: track.meta,
+    })
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+fn is_supported(path: &Path) -> bool {
+    SUPPORTED_EXTENSIONS.contains(&extension(path).as_str())
+}
+
+/// An opened file or stream, ready to decode
+pub(crate) struct OpenTrack {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    pub(crate) sample_rate: u32,
+    pub(crate) frames: Option<u64>,
+    pub(crate) meta: TrackMeta,
+}
+
+fn open_track(path: &Path) -> Result<OpenTrack> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut track = open_source(Box::new(file), &hint, &path.display().to_string())?;
+    if track.meta.title.is_empty() {
+        track.meta.title = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+    }
+    Ok(track)
+}
+
+/// Probe a file or network stream; `name` is only used in errors
+pub(crate) fn open_source(source: Box<dyn MediaSource>, hint: &Hint, name: &str) -> Result<OpenTrack> {
+    let stream = MediaSourceStream::new(source, Default::default());
+
+    // Gapless mode trims encoder delay and padding (LAME/iTunes headers)
+    let format_options = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+    let mut probed = symphonia::default::get_probe()
+        .format(hint, stream, &format_options, &MetadataOptions::default())
+        .with_context(|| format!("Unsupported format: {}", name))?;
+
+    // Tags may come before the container (ID3v2) or inside it; later wins
+    let mut meta = TrackMeta::default();
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        apply_tags(&mut meta, revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        apply_tags(&mut meta, revision);
+    }
+    meta.device_genre = meta.genre.clone();
+
+    let format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| anyhow!("No audio track in {}", name))?;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or_else(|| anyhow!("Unknown sample rate in {}", name))?;
+    let frames = track.codec_params.n_frames;
+    let decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .with_context(|| format!("Unsupported codec in {}", name))?;
+
+    Ok(OpenTrack {
+        track_id: track.id,
+        format,
+        decoder,
+        sample_rate,
+        frames,
+        meta,
+    })
+}
+
+fn apply_tags(meta: &mut TrackMeta, revision: &MetadataRevision) {
+    let mut album_artist = None;
+    for tag in revision.tags() {
+        // RIFF INFO strings keep their NUL terminator
+        let value = tag.value.to_string().trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string();
+        if value.is_empty() {
+            continue;
+        }
+        match tag.std_key {
+            Some(StandardTagKey::Artist) => meta.artist = value,
+            Some(StandardTagKey::AlbumArtist) => album_artist = Some(value),
+            Some(StandardTagKey::TrackTitle) => meta.title = value,
+            Some(StandardTagKey::Album) => meta.album = value,
+            Some(StandardTagKey::Genre) => meta.genre = value,
+            _ => {}
+        }
+    }
+    if meta.artist.is_empty() {
+        meta.artist = album_artist.unwrap_or_default();
+    }
+}
+
+/// Playback was stopped by the caller or the receiver went away
+struct Stopped;
+
+/// Sends blocks downstream, pacing them in realtime mode
+struct Output {
+    tx: mpsc::Sender<Vec<f64>>,
+    stop_rx: mpsc::Receiver<()>,
+    realtime: bool,
+    sample_rate: u32,
+    started: Instant,
+    frames_sent: u64,
+}
+
+impl Output {
+    fn send(&mut self, block: Vec<f64>, channels: usize) -> Result<(), Stopped> {
+        if block.is_empty() {
+            return Ok(());
+        }
+
+        if self.realtime {
+            let due = self.started
+                + Duration::from_secs_f64(self.frames_sent as f64 / self.sample_rate as f64);
+            if let Some(wait) = due.checked_duration_since(Instant::now() + REALTIME_LEAD) {
+                std::thread::sleep(wait);
+            }
+        }
+
+        // Dropping the stop sender stops playback too, like the capture inputs
+        if !matches!(self.stop_rx.try_recv(), Err(mpsc::error::TryRecvError::Empty)) {
+            return Err(Stopped);
+        }
+
+        self.frames_sent += (block.len() / channels) as u64;
+        self.tx.blocking_send(block).map_err(|_| Stopped)
+    }
+}
+
+struct Player {
+    output: Output,
+    channels: usize,
+    quality: ResamplerQuality,
+    /// Resampler for the current source rate, kept across tracks for gapless joins
+    resampler: Option<Resampler>,
+    /// Decoded samples at the source rate, not yet a full block
+    pending: Vec<f64>,
+}
+
+impl Player {
+    fn play(&mut self, tracks: &[PathBuf], events: &mpsc::UnboundedSender<FileInputEvent>) -> Result<(), Stopped> {
+        for (index, path) in tracks.iter().enumerate() {
+            let track = match open_track(path) {
+                Ok(track) => track,
+                Err(e) => {
+                    warn!("Skipping {}: {:#}", path.display(), e);
+                    let _ = events.send(FileInputEvent::TrackFailed {
+                        index,
+                        path: path.clone(),
+                        error: format!("{:#}", e),
+                    });
+                    continue;
+                }
+            };
+
+            if self.resampler.as_ref().map(|r| r.input_rate()) != Some(track.sample_rate) {
+                self.flush()?;
+                match Resampler::new(self.quality, track.sample_rate, self.output.sample_rate, self.channels) {
+                    Ok(resampler) => self.resampler = Some(resampler),
+                    Err(e) => {
+                        error!("Failed to create resampler for {}: {}", path.display(), e);
+                        let _ = events.send(FileInputEvent::TrackFailed {
+                            index,
+                            path: path.clone(),
+                            error: e.to_string(),
+                        });
+                        continue;
+                    }
+                }
+            }
+
+            let pending_frames = (self.pending.len() / self.channels) as u64;
+            let position = self.output.frames_sent
+                + pending_frames * self.output.sample_rate as u64 / track.sample_rate as u64;
+            info!(
+                "Playing {} ({} - {}, {} Hz)",
+                path.display(),
+                track.meta.artist,
+                track.meta.title,
+                track.sample_rate
+            );
+            let _ = events.send(FileInputEvent::TrackStarted {
+                index,
+                path: path.clone(),
+                meta: track.meta.clone(),
+                sample_rate: track.sample_rate,
+                position,
+            });
+
+            self.decode(track, &path.display().to_string())?;
+        }
+
+        self.flush()
+    }
+
+    fn decode(&mut self, mut track: OpenTrack, name: &str) -> Result<(), Stopped> {
+        let mut buffer: Option<SampleBuffer<f64>> = None;
+
+        loop {
+            let packet = match track.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => {
+                    warn!("Stopped reading {}: {}", name, e);
+                    break;
+                }
+            };
+            if packet.track_id() != track.track_id {
+                continue;
+            }
+
+            let decoded = match track.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(e)) => {
+                    // Corrupt packets are skipped rather than ending the track
+                    warn!("Skipping undecodable packet in {}: {}", name, e);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Stopped decoding {}: {}", name, e);
+                    break;
+                }
+            };
+
+            let spec = *decoded.spec();
+            let needed = decoded.capacity() as u64;
+            let buffer = match &mut buffer {
+                Some(buffer) if buffer.capacity() as u64 >= needed => buffer,
+                buffer => buffer.insert(SampleBuffer::new(needed, spec)),
+            };
+            buffer.copy_interleaved_ref(decoded);
+
+            map_channels(buffer.samples(), spec.channels.count(), self.channels, &mut self.pending);
+            self.send_blocks()?;
+        }
+
+        Ok(())
+    }
+
+    /// Send every complete block in `pending`
+    fn send_blocks(&mut self) -> Result<(), Stopped> {
+        let block_len = BLOCK_FRAMES * self.channels;
+        while self.pending.len() >= block_len {
+            let rest = self.pending.split_off(block_len);
+            let block = std::mem::replace(&mut self.pending, rest);
+            self.send(block)?;
+        }
+        Ok(())
+    }
+
+    /// Send what's left at the current rate, e.g. before the rate changes
+    fn flush(&mut self) -> Result<(), Stopped> {
+        self.send_blocks()?;
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut block = std::mem::take(&mut self.pending);
+        let resampling = self.resampler.as_ref().is_some_and(|r| r.is_active());
+        if resampling {
+            // The resampler takes fixed-size blocks; pad the tail with silence
+            block.resize(BLOCK_FRAMES * self.channels, 0.0);
+        }
+        self.send(block)
+    }
+
+    fn send(&mut self, block: Vec<f64>) -> Result<(), Stopped> {
+        let block = match self.resampler.as_mut() {
+            Some(resampler) => match resampler.process(&block) {
+                Ok(resampled) => resampled,
+                Err(e) => {
+                    error!("Resampling failed: {}", e);
+                    return Ok(());
+                }
+            },
+            None => block,
+        };
+        self.output.send(block, self.channels)
+    }
+}
+
+/// Decode an opened stream into `tx` as fast as it is consumed, converting
+/// to the rate and channel count in `cfg`
+///
+/// Runs on the calling thread. Returns `Ok(false)` if `stop_rx` fired or the
+/// receiver went away before the end.
+pub(crate) fn decode_stream(
+    track: OpenTrack,
+    name: &str,
+    cfg: &OutputConfig,
+    quality: ResamplerQuality,
+    tx: mpsc::Sender<Vec<f64>>,
+    stop_rx: mpsc::Receiver<()>,
+) -> Result<bool> {
+    let channels = cfg.channels.max(1) as usize;
+    let resampler = Resampler::new(quality, track.sample_rate, cfg.sample_rate, channels)?;
+    let mut player = Player {
+        output: Output {
+            tx,
+            stop_rx,
+            realtime: false,
+            sample_rate: cfg.sample_rate,
+            started: Instant::now(),
+            frames_sent: 0,
+        },
+        channels,
+        quality,
+        resampler: Some(resampler),
+        pending: Vec::new(),
+    };
+
+    Ok(player.decode(track, name).and_then(|()| player.flush()).is_ok())
+}
+
+/// Append interleaved samples converted to `out_channels`
+///
+/// Mono is duplicated to every channel; extra channels are dropped, or
+/// averaged when the output is mono.
+pub(crate) fn map_channels(samples: &[f64], in_channels: usize, out_channels: usize, out: &mut Vec<f64>) {
+    if in_channels == out_channels {
+        out.extend_from_slice(samples);
+        return;
+    }
+
+    for frame in samples.chunks_exact(in_channels) {
+        if in_channels == 1 {
+            out.extend(std::iter::repeat_n(frame[0], out_channels));
+        } else if out_channels == 1 {
+            out.push(frame.iter().sum::<f64>() / in_channels as f64);
+        } else {
+            out.extend((0..out_channels).map(|ch| frame.get(ch).copied().unwrap_or(0.0)));
+        }
+    }
+}
+
+/// Converts a stream's audio to the output channel count and rate
+pub(crate) struct StreamConverter {
+    in_channels: usize,
+    out_channels: usize,
+    resampler: Resampler,
+    pending: Vec<f64>,
+}
+
+impl StreamConverter {
+    pub(crate) fn new(in_channels: usize, sample_rate: u32, cfg: &OutputConfig, quality: ResamplerQuality) -> Result<Self> {
+        let out_channels = cfg.channels.max(1) as usize;
+        Ok(Self {
+            in_channels: in_channels.max(1),
+            out_channels,
+            resampler: Resampler::new(quality, sample_rate, cfg.sample_rate, out_channels)?,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Convert interleaved samples; the resampler holds back partial chunks
+    pub(crate) fn push(&mut self, samples: &[f64]) -> Result<Vec<f64>> {
+        map_channels(samples, self.in_channels, self.out_channels, &mut self.pending);
+        if !self.resampler.is_active() {
+            return Ok(std::mem::take(&mut self.pending));
+        }
+
+        let chunk = self.resampler.chunk_frames() * self.out_channels;
+        let mut out = Vec::new();
+        while self.pending.len() >= chunk {
+            let input: Vec<f64> = self.pending.drain(..chunk).collect();
+            out.extend(self.resampler.process(&input)?);
+        }
+        Ok(out)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_channels() {
+        let mut out = Vec::new();
+        map_channels(&[0.5, -0.5], 1, 2, &mut out);
+        assert_eq!(out, vec![0.5, 0.5, -0.5, -0.5]);
+
+        let mut out = Vec::new();
+        map_channels(&[0.2, 0.4, 0.6, 0.8], 2, 1, &mut out);
+        assert!((out[0] - 0.3).abs() < 1e-9 && (out[1] - 0.7).abs() < 1e-9);
+
+        let mut out = Vec::new();
+        map_channels(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6], 6, 2, &mut out);
+        assert_eq!(out, vec![0.1, 0.2]);
+    }
+
+    #[test]
+    fn test_load_playlists() {
+        let dir = std::env::temp_dir().join(format!("aaeq-playlist-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let m3u = dir.join("list.m3u8");
+        std::fs::write(&m3u, "#EXTM3U\n#EXTINF:123,Artist - Title\nb.flac\n\nhttp://radio/stream\n/music/a.mp3\n").unwrap();
+        assert_eq!(
+            load_playlist(&m3u).unwrap(),
+            vec![dir.join("b.flac"), PathBuf::from("/music/a.mp3")]
+        );
+
+        let pls = dir.join("list.pls");
+        std::fs::write(&pls, "[playlist]\nFile1=one.wav\nTitle1=One\nFile2=two.ogg\nNumberOfEntries=2\n").unwrap();
+        assert_eq!(load_playlist(&pls).unwrap(), vec![dir.join("one.wav"), dir.join("two.ogg")]);
+
+        // Directories list supported files in name order
+        let _ = std::fs::remove_file(&m3u);
+        let _ = std::fs::remove_file(&pls);
+        for name in ["02.flac", "cover.jpg", "01.mp3"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        assert_eq!(load_playlist(&dir).unwrap(), vec![dir.join("01.mp3"), dir.join("02.flac")]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
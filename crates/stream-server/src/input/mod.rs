@@ -11,6 +11,20 @@ use cpal::traits::{DeviceTrait, HostTrait};
 use tokio::sync::mpsc;
 use tracing::{error, info, warn};
 
+pub mod aes67_receiver;
+pub mod airplay_receiver;
+pub mod dlna_renderer;
+pub mod file;
+#[cfg(target_os = "linux")]
+pub mod virtual_sink;
+
+pub use aes67_receiver::{Aes67Receiver, Aes67ReceiverHandle};
+pub use airplay_receiver::{AirPlayReceiver, AirPlayReceiverEvent, AirPlayReceiverHandle, RAOP_SERVICE_TYPE};
+pub use dlna_renderer::{DlnaRenderer, DlnaRendererEvent, DlnaRendererHandle, TransportState};
+pub use file::{load_playlist, read_track_info, read_track_meta, FileInput, FileInputEvent, TrackInfo};
+#[cfg(target_os = "linux")]
+pub use virtual_sink::{VirtualSink, VirtualSinkHandle};
+
 // Windows-specific imports for WASAPI loopback
 #[cfg(target_os = "windows")]
 use wasapi::*;
@@ -18,6 +32,12 @@ use wasapi::*;
 /// Input source that captures from system audio (loopback/monitor device)
 pub struct LocalDacInput;
 
+/// Device entry that creates AAEQ's own virtual sink and captures it (Linux)
+///
+/// Applications play to the "AAEQ" output while capture runs; see [`VirtualSink`].
+#[cfg(target_os = "linux")]
+pub const VIRTUAL_SINK_DEVICE: &str = "🔊 AAEQ Virtual Sink (play to \"AAEQ\")";
+
 /// Wrapper around cpal::Stream that implements Send
 /// This is safe because we manage the stream carefully and don't actually
 /// access it from multiple threads - we just need to move it for lifetime management
@@ -47,6 +67,12 @@ impl LocalDacInput {
             }
         }
 
+        // AAEQ's own virtual sink, created when capture starts
+        #[cfg(target_os = "linux")]
+        if VirtualSink::is_available() {
+            devices.push(VIRTUAL_SINK_DEVICE.to_string());
+        }
+
         // Add ALSA-configured capture devices
         // Check if aaeq_monitor or aaeq_capture exists (configured in .asoundrc)
         if let Ok(output) = std::process::Command::new("arecord")
@@ -98,8 +124,14 @@ impl LocalDacInput {
                             }
                         }
 
+                        // The virtual sink's own monitor is covered by its entry
+                        #[cfg(target_os = "linux")]
+                        let own_monitor = name == format!("{}.monitor", virtual_sink::DEFAULT_SINK_NAME);
+                        #[cfg(not(target_os = "linux"))]
+                        let own_monitor = false;
+
                         // Check if it's a monitor device
-                        if name.contains(".monitor") {
+                        if name.contains(".monitor") && !own_monitor {
                             // Don't add if already added via ALSA
                             if !devices.iter().any(|d| d.contains(&name)) {
                                 devices.push(format!("🔊 {} ({})", name, description));
@@ -167,6 +199,11 @@ impl LocalDacInput {
             device_name.as_deref().unwrap_or("default")
         );
 
+        #[cfg(target_os = "linux")]
+        if device_name.as_deref() == Some(VIRTUAL_SINK_DEVICE) {
+            return Self::start_virtual_sink_capture(cfg, tx);
+        }
+
         // On Windows, check if this is a loopback device
         #[cfg(target_os = "windows")]
         {
@@ -459,6 +496,22 @@ impl LocalDacInput {
         Ok(final_stop_tx)
     }
 
+    /// Create the virtual sink and capture its monitor until stopped
+    #[cfg(target_os = "linux")]
+    fn start_virtual_sink_capture(cfg: OutputConfig, tx: mpsc::Sender<Vec<f64>>) -> Result<mpsc::Sender<()>> {
+        let handle = VirtualSink::new(virtual_sink::DEFAULT_SINK_NAME.to_string()).start(cfg, tx)?;
+        info!("Capturing from virtual sink '{}'", handle.sink_name());
+
+        // The sink is removed when the handle drops, on stop or when the sender is dropped
+        let (stop_tx, mut stop_rx) = mpsc::channel::<()>(1);
+        std::thread::spawn(move || {
+            let _ = stop_rx.blocking_recv();
+            handle.stop();
+        });
+
+        Ok(stop_tx)
+    }
+
     // Windows-specific: List WASAPI loopback devices (system audio capture)
     #[cfg(target_os = "windows")]
     fn list_windows_loopback_devices() -> Result<Vec<String>> {
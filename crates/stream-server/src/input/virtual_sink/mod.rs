@@ -0,0 +1,511 @@
+//! Virtual sink capture on PulseAudio/PipeWire
+//!
+//! Creates a named null sink on the local sound server, which shows up as an
+//! output device applications can play to, and records its monitor directly.
+//! The sink only exists while capture runs: it is unloaded on stop, and a sink
+//! left behind by a crashed run is removed before a new one is created. This
+//! replaces the manual `setup-audio-loopback.sh` step.
+//!
+//! Both servers are driven over the PulseAudio native protocol (PipeWire via
+//! `pipewire-pulse`), so no client libraries are needed.
+mod protocol;
+
+use crate::types::OutputConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use protocol::{Connection, TagReader, INVALID_INDEX, SAMPLE_FLOAT32LE};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+/// Default sink name (`aaeq`, shown to applications as "AAEQ")
+pub const DEFAULT_SINK_NAME: &str = "aaeq";
+
+/// Requested capture latency
+const FRAGMENT: Duration = Duration::from_millis(20);
+
+/// How often the capture thread checks for stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A null sink to create and capture from
+#[derive(Debug, Clone)]
+pub struct VirtualSink {
+    name: String,
+    description: String,
+    server: Option<PathBuf>,
+}
+
+impl VirtualSink {
+    /// Sink with the given name (as used in `pactl`), described as "AAEQ"
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            description: "AAEQ".to_string(),
+            server: None,
+        }
+    }
+
+    /// Name applications show for the sink
+    pub fn with_description(mut self, description: String) -> Self {
+        self.description = description;
+        self
+    }
+
+    /// Sound server socket, instead of `$PULSE_SERVER` or `$XDG_RUNTIME_DIR/pulse/native`
+    pub fn with_server(mut self, path: PathBuf) -> Self {
+        self.server = Some(path);
+        self
+    }
+
+    /// Whether a PulseAudio or PipeWire socket is present to create sinks on
+    pub fn is_available() -> bool {
+        protocol::server_path().is_some_and(|path| path.exists())
+    }
+
+    /// Name of the sink
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Name of the monitor source capture reads from
+    pub fn monitor_name(&self) -> String {
+        format!("{}.monitor", self.name)
+    }
+
+    /// `module-null-sink` arguments
+    ///
+    /// The sink name is limited to `[A-Za-z0-9_.-]`, so it can't add arguments
+    /// of its own.
+    fn module_args(&self, cfg: &OutputConfig) -> Result<String> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-');
+        if self.name.is_empty() || !self.name.chars().all(valid) {
+            bail!(
+                "Invalid virtual sink name '{}': use only letters, digits, '_', '.' and '-'",
+                self.name
+            );
+        }
+        // Quotes would end the property list value early
+        let description: String = self.description.chars().filter(|c| *c != '"' && *c != '\'').collect();
+        Ok(format!(
+            "sink_name={} sink_properties='device.description=\"{}\"' rate={} channels={}",
+            self.name, description, cfg.sample_rate, cfg.channels
+        ))
+    }
+
+    /// Create the sink and start capturing its monitor
+    ///
+    /// # Arguments
+    /// * `cfg` - Sample rate and channel count of the sink and capture
+    /// * `tx` - Channel to send captured samples; blocks are dropped if it's full
+    pub fn start(self, cfg: OutputConfig, tx: mpsc::Sender<Vec<f64>>) -> Result<VirtualSinkHandle> {
+        let args = self.module_args(&cfg)?;
+        let server = self
+            .server
+            .clone()
+            .or_else(protocol::server_path)
+            .ok_or_else(|| anyhow!("No PulseAudio or PipeWire server found (XDG_RUNTIME_DIR is not set)"))?;
+        let mut connection = Connection::connect(&server, "AAEQ")?;
+
+        self.remove_stale(&mut connection)?;
+
+        let reply = connection
+            .request(protocol::COMMAND_LOAD_MODULE, |ts| {
+                ts.put_string(Some("module-null-sink")).put_string(Some(&args));
+            })
+            .with_context(|| format!("Failed to create virtual sink '{}'", self.name))?;
+        let module = TagReader::new(&reply).get_u32()?;
+        info!("Created virtual sink '{}' (module {})", self.name, module);
+
+        let channel = match self.create_record_stream(&mut connection, &cfg) {
+            Ok(channel) => channel,
+            Err(e) => {
+                unload(&mut connection, module);
+                return Err(e);
+            }
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let name = self.name.clone();
+        let thread = std::thread::Builder::new()
+            .name("aaeq-virtual-sink".to_string())
+            .spawn(move || {
+                capture(&mut connection, channel, &tx, &thread_stop);
+                let _ = connection.request(protocol::COMMAND_DELETE_RECORD_STREAM, |ts| {
+                    ts.put_u32(channel);
+                });
+                unload(&mut connection, module);
+                info!("Removed virtual sink '{}'", name);
+            })?;
+
+        Ok(VirtualSinkHandle {
+            name: self.name,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Unload null sinks of the same name left behind by a run that didn't stop cleanly
+    ///
+    /// A sink something still plays to or records from is left alone, and
+    /// capture fails instead of cutting it off.
+    fn remove_stale(&self, connection: &mut Connection) -> Result<()> {
+        let reply = connection.request(protocol::COMMAND_GET_MODULE_INFO_LIST, |_| {})?;
+        let ours = format!("sink_name={}", self.name);
+
+        let mut modules = TagReader::new(&reply);
+        let mut stale = Vec::new();
+        while !modules.is_empty() {
+            let index = modules.get_u32()?;
+            let name = modules.get_string()?.unwrap_or_default();
+            let argument = modules.get_string()?.unwrap_or_default();
+            modules.get_u32()?; // n_used
+            modules.skip()?; // auto_unload, or the proplist on newer servers
+            if name == "module-null-sink" && argument.split_whitespace().any(|arg| arg == ours) {
+                stale.push(index);
+            }
+        }
+
+        if stale.is_empty() {
+            return Ok(());
+        }
+        let in_use = modules_in_use(connection)?;
+        if let Some(module) = stale.iter().find(|module| in_use.contains(module)) {
+            bail!(
+                "Virtual sink '{}' (module {}) is already in use; stop what plays to or records from it first",
+                self.name,
+                module
+            );
+        }
+
+        for module in stale {
+            warn!("Removing stale virtual sink '{}' (module {})", self.name, module);
+            unload(connection, module);
+        }
+        Ok(())
+    }
+
+    /// Record the sink's monitor as float samples; returns the stream's channel
+    fn create_record_stream(&self, connection: &mut Connection, cfg: &OutputConfig) -> Result<u32> {
+        let channels = cfg.channels.clamp(1, 32) as u8;
+        let fragment_bytes = (cfg.sample_rate as f64 * FRAGMENT.as_secs_f64()) as u32 * channels as u32 * 4;
+        let monitor = self.monitor_name();
+
+        let reply = connection
+            .request(protocol::COMMAND_CREATE_RECORD_STREAM, |ts| {
+                ts.put_sample_spec(SAMPLE_FLOAT32LE, channels, cfg.sample_rate)
+                    .put_channel_map(channels)
+                    .put_u32(INVALID_INDEX)
+                    .put_string(Some(&monitor))
+                    .put_u32(u32::MAX) // maxlength: server default
+                    .put_bool(false) // corked
+                    .put_u32(fragment_bytes)
+                    // no_remap, no_remix, fix_format, fix_rate, fix_channels
+                    .put_bool(false)
+                    .put_bool(false)
+                    .put_bool(false)
+                    .put_bool(false)
+                    .put_bool(false)
+                    .put_bool(true) // no_move: stay on our monitor
+                    .put_bool(false) // variable_rate
+                    .put_bool(false) // peak_detect
+                    .put_bool(true) // adjust_latency
+                    .put_proplist(&[("media.name", "AAEQ capture")])
+                    .put_u32(INVALID_INDEX); // direct_on_input
+            })
+            .with_context(|| format!("Failed to record from '{}'", monitor))?;
+        TagReader::new(&reply).get_u32()
+    }
+}
+
+/// Modules whose sink has playback streams or whose monitor is being recorded
+///
+/// Reads the version 13 sink, sink input and source output info lists.
+fn modules_in_use(connection: &mut Connection) -> Result<HashSet<u32>> {
+    let skip = |reader: &mut TagReader, values: usize| (0..values).try_for_each(|_| reader.skip());
+
+    // Owning module of each sink and monitor source
+    let mut sinks = HashMap::new();
+    let mut monitors = HashMap::new();
+    let reply = connection.request(protocol::COMMAND_GET_SINK_INFO_LIST, |_| {})?;
+    let mut reader = TagReader::new(&reply);
+    while !reader.is_empty() {
+        let index = reader.get_u32()?;
+        skip(&mut reader, 4)?; // name, description, sample spec, channel map
+        let module = reader.get_u32()?;
+        skip(&mut reader, 2)?; // volume, mute
+        let monitor = reader.get_u32()?;
+        skip(&mut reader, 6)?; // monitor name, latency, driver, flags, proplist, configured latency
+        sinks.insert(index, module);
+        monitors.insert(monitor, module);
+    }
+
+    let mut in_use = HashSet::new();
+    let reply = connection.request(protocol::COMMAND_GET_SINK_INPUT_INFO_LIST, |_| {})?;
+    let mut reader = TagReader::new(&reply);
+    while !reader.is_empty() {
+        skip(&mut reader, 4)?; // index, name, module, client
+        let sink = reader.get_u32()?;
+        // sample spec, channel map, volume, buffer and sink latency, resample method, driver, mute, proplist
+        skip(&mut reader, 9)?;
+        in_use.extend(sinks.get(&sink));
+    }
+
+    let reply = connection.request(protocol::COMMAND_GET_SOURCE_OUTPUT_INFO_LIST, |_| {})?;
+    let mut reader = TagReader::new(&reply);
+    while !reader.is_empty() {
+        skip(&mut reader, 4)?; // index, name, module, client
+        let source = reader.get_u32()?;
+        // sample spec, channel map, buffer and source latency, resample method, driver, proplist
+        skip(&mut reader, 7)?;
+        in_use.extend(monitors.get(&source));
+    }
+    Ok(in_use)
+}
+
+fn unload(connection: &mut Connection, module: u32) {
+    if let Err(e) = connection.request(protocol::COMMAND_UNLOAD_MODULE, |ts| {
+        ts.put_u32(module);
+    }) {
+        warn!("Failed to unload module {}: {:#}", module, e);
+    }
+}
+
+/// Pass recorded audio on until stopped or the receiver goes away
+fn capture(connection: &mut Connection, channel: u32, tx: &mpsc::Sender<Vec<f64>>, stop: &AtomicBool) {
+    // Bytes of a sample split across packets
+    let mut carry: Vec<u8> = Vec::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        let packet = match connection.next_data(POLL_INTERVAL) {
+            Ok(Some(packet)) if packet.channel == Some(channel) => packet,
+            Ok(_) => continue,
+            Err(e) => {
+                error!("Virtual sink capture failed: {:#}", e);
+                return;
+            }
+        };
+
+        carry.extend_from_slice(&packet.payload);
+        let whole = carry.len() - carry.len() % 4;
+        let samples: Vec<f64> = carry[..whole]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        carry.drain(..whole);
+
+        match tx.try_send(samples) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => debug!("Virtual sink capture dropped a block"),
+            Err(mpsc::error::TrySendError::Closed(_)) => return,
+        }
+    }
+}
+
+/// A running [`VirtualSink`]; the sink is removed when stopped or dropped
+pub struct VirtualSinkHandle {
+    name: String,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl VirtualSinkHandle {
+    /// Name of the sink applications play to
+    pub fn sink_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Stop capturing and remove the sink
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for VirtualSinkHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::protocol::*;
+    use super::*;
+    use crate::types::SampleFormat;
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+    use std::sync::Mutex;
+
+    /// Stand-in sound server: answers the commands capture uses, records
+    /// them, and sends one block of audio once the stream is created
+    ///
+    /// A stale `aaeq_test` sink (module 7, sink 2, monitor 5) is left over;
+    /// if `recorded`, something still records its monitor.
+    fn mock_server(listener: UnixListener, log: Arc<Mutex<Vec<String>>>, recorded: bool) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = PacketReader::default();
+        while let Ok(Some(packet)) = reader.next(&mut stream) {
+            let mut request = TagReader::new(&packet.payload);
+            let command = request.get_u32().unwrap();
+            let tag = request.get_u32().unwrap();
+
+            let mut reply = TagStruct::command(COMMAND_REPLY, tag);
+            let mut audio = None;
+            match command {
+                COMMAND_AUTH => {
+                    assert_eq!(request.get_u32().unwrap(), PROTOCOL_VERSION);
+                    assert_eq!(request.get_arbitrary().unwrap().len(), COOKIE_LENGTH);
+                    reply.put_u32(PROTOCOL_VERSION);
+                }
+                COMMAND_SET_CLIENT_NAME => {
+                    reply.put_u32(1);
+                }
+                COMMAND_GET_MODULE_INFO_LIST => {
+                    for (index, argument) in [(3, "sink_name=other"), (7, "sink_name=aaeq_test rate=48000")] {
+                        reply
+                            .put_u32(index)
+                            .put_string(Some("module-null-sink"))
+                            .put_string(Some(argument))
+                            .put_u32(0)
+                            .put_proplist(&[]);
+                    }
+                }
+                // Fields the client skips are filled with whatever is handy
+                COMMAND_GET_SINK_INFO_LIST => {
+                    for (index, module, monitor) in [(1, 3, 4), (2, 7, 5)] {
+                        reply.put_u32(index).put_string(Some("sink")).put_string(None).put_u32(0).put_u32(0);
+                        reply.put_u32(module).put_u32(0).put_bool(false).put_u32(monitor);
+                        reply.put_string(None).put_u32(0).put_string(None).put_u32(0).put_proplist(&[]).put_u32(0);
+                    }
+                }
+                COMMAND_GET_SINK_INPUT_INFO_LIST => {
+                    // Playing to the other sink
+                    reply.put_u32(0).put_string(None).put_u32(0).put_u32(0).put_u32(1);
+                    for _ in 0..9 {
+                        reply.put_u32(0);
+                    }
+                }
+                COMMAND_GET_SOURCE_OUTPUT_INFO_LIST => {
+                    if recorded {
+                        reply.put_u32(0).put_string(None).put_u32(0).put_u32(0).put_u32(5);
+                        for _ in 0..6 {
+                            reply.put_u32(0);
+                        }
+                        reply.put_proplist(&[("application.name", "OBS")]);
+                    }
+                }
+                COMMAND_LOAD_MODULE => {
+                    request.get_string().unwrap();
+                    log.lock().unwrap().push(format!("load {}", request.get_string().unwrap().unwrap()));
+                    reply.put_u32(9);
+                }
+                COMMAND_UNLOAD_MODULE => {
+                    log.lock().unwrap().push(format!("unload {}", request.get_u32().unwrap()));
+                }
+                COMMAND_CREATE_RECORD_STREAM => {
+                    request.skip().unwrap();
+                    request.skip().unwrap();
+                    request.get_u32().unwrap();
+                    log.lock().unwrap().push(format!("record {}", request.get_string().unwrap().unwrap()));
+                    reply.put_u32(4).put_u32(1);
+                    let samples: Vec<u8> = [0.5f32, -0.5].iter().flat_map(|s| s.to_le_bytes()).collect();
+                    audio = Some(encode_packet(Some(4), &samples));
+                }
+                COMMAND_DELETE_RECORD_STREAM => {
+                    log.lock().unwrap().push(format!("delete {}", request.get_u32().unwrap()));
+                }
+                other => panic!("unexpected command {}", other),
+            }
+
+            stream.write_all(&encode_packet(None, reply.as_bytes())).unwrap();
+            if let Some(audio) = audio {
+                stream.write_all(&audio).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_creates_captures_and_removes_sink() {
+        let path = std::env::temp_dir().join(format!("aaeq-pulse-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let server_log = log.clone();
+        let server = std::thread::spawn(move || mock_server(listener, server_log, false));
+
+        let cfg = OutputConfig {
+            sample_rate: 48000,
+            channels: 2,
+            format: SampleFormat::F32,
+            buffer_ms: 150,
+            exclusive: false,
+        };
+        let (tx, mut rx) = mpsc::channel(4);
+        let handle = VirtualSink::new("aaeq_test".to_string())
+            .with_server(path.clone())
+            .start(cfg, tx)
+            .unwrap();
+        assert_eq!(handle.sink_name(), "aaeq_test");
+
+        let block = rx.blocking_recv().unwrap();
+        assert_eq!(block, vec![0.5, -0.5]);
+
+        handle.stop();
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "unload 7".to_string(),
+                "load sink_name=aaeq_test sink_properties='device.description=\"AAEQ\"' rate=48000 channels=2".to_string(),
+                "record aaeq_test.monitor".to_string(),
+                "delete 4".to_string(),
+                "unload 9".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keeps_a_stale_sink_that_is_in_use() {
+        let path = std::env::temp_dir().join(format!("aaeq-pulse-busy-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let server_log = log.clone();
+        let server = std::thread::spawn(move || mock_server(listener, server_log, true));
+
+        let (tx, _rx) = mpsc::channel(4);
+        let result = VirtualSink::new("aaeq_test".to_string())
+            .with_server(path.clone())
+            .start(OutputConfig::default(), tx);
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let error = result.err().unwrap().to_string();
+        assert!(error.contains("already in use"), "{}", error);
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rejects_sink_names_that_are_not_plain() {
+        let cfg = OutputConfig::default();
+        let args = VirtualSink::new("aaeq-2.main_out".to_string()).module_args(&cfg).unwrap();
+        assert!(args.starts_with("sink_name=aaeq-2.main_out "));
+
+        for name in ["", "aaeq sink", "aaeq rate=8000", "aaeq'", "aux/1", "änd"] {
+            assert!(VirtualSink::new(name.to_string()).module_args(&cfg).is_err(), "{:?}", name);
+        }
+    }
+}
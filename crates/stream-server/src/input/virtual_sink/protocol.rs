@@ -0,0 +1,522 @@
+//! Minimal PulseAudio native protocol client
+//!
+//! Just enough of the protocol to authenticate, load and unload modules and
+//! record a stream. PipeWire's `pipewire-pulse` speaks the same protocol, so
+//! this works against either server.
+use anyhow::{anyhow, Context, Result};
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Protocol version we claim; the server speaks min(ours, its own).
+///
+/// 13 is the first version with property lists, and keeps
+/// `CREATE_RECORD_STREAM` free of the later format negotiation fields.
+pub const PROTOCOL_VERSION: u32 = 13;
+
+pub const COMMAND_ERROR: u32 = 0;
+pub const COMMAND_REPLY: u32 = 2;
+pub const COMMAND_CREATE_RECORD_STREAM: u32 = 5;
+pub const COMMAND_DELETE_RECORD_STREAM: u32 = 6;
+pub const COMMAND_AUTH: u32 = 8;
+pub const COMMAND_SET_CLIENT_NAME: u32 = 9;
+pub const COMMAND_GET_SINK_INFO_LIST: u32 = 22;
+pub const COMMAND_GET_MODULE_INFO_LIST: u32 = 26;
+pub const COMMAND_GET_SINK_INPUT_INFO_LIST: u32 = 30;
+pub const COMMAND_GET_SOURCE_OUTPUT_INFO_LIST: u32 = 32;
+pub const COMMAND_LOAD_MODULE: u32 = 51;
+pub const COMMAND_UNLOAD_MODULE: u32 = 52;
+
+/// `PA_INVALID_INDEX`
+pub const INVALID_INDEX: u32 = u32::MAX;
+/// `PA_SAMPLE_FLOAT32LE`
+pub const SAMPLE_FLOAT32LE: u8 = 5;
+/// Length of the authentication cookie
+pub const COOKIE_LENGTH: usize = 256;
+
+/// Channel of control packets; data packets carry the stream's channel
+const CONTROL_CHANNEL: u32 = u32::MAX;
+const DESCRIPTOR_LENGTH: usize = 20;
+/// Largest packet accepted from the server
+const MAX_PACKET: usize = 16 * 1024 * 1024;
+/// How long to wait for the reply to a command
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const TAG_STRING: u8 = b't';
+const TAG_STRING_NULL: u8 = b'N';
+const TAG_U32: u8 = b'L';
+const TAG_U8: u8 = b'B';
+const TAG_U64: u8 = b'R';
+const TAG_S64: u8 = b'r';
+const TAG_SAMPLE_SPEC: u8 = b'a';
+const TAG_ARBITRARY: u8 = b'x';
+const TAG_BOOLEAN_TRUE: u8 = b'1';
+const TAG_BOOLEAN_FALSE: u8 = b'0';
+const TAG_TIMEVAL: u8 = b'T';
+const TAG_USEC: u8 = b'U';
+const TAG_CHANNEL_MAP: u8 = b'm';
+const TAG_CVOLUME: u8 = b'v';
+const TAG_PROPLIST: u8 = b'P';
+const TAG_VOLUME: u8 = b'V';
+
+/// Typed, tagged message body
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TagStruct(Vec<u8>);
+
+impl TagStruct {
+    /// Start a command message
+    pub fn command(command: u32, tag: u32) -> Self {
+        let mut ts = Self::default();
+        ts.put_u32(command);
+        ts.put_u32(tag);
+        ts
+    }
+
+    pub fn put_u32(&mut self, value: u32) -> &mut Self {
+        self.0.push(TAG_U32);
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn put_bool(&mut self, value: bool) -> &mut Self {
+        self.0.push(if value { TAG_BOOLEAN_TRUE } else { TAG_BOOLEAN_FALSE });
+        self
+    }
+
+    pub fn put_string(&mut self, value: Option<&str>) -> &mut Self {
+        match value {
+            Some(value) => {
+                self.0.push(TAG_STRING);
+                self.0.extend_from_slice(value.as_bytes());
+                self.0.push(0);
+            }
+            None => self.0.push(TAG_STRING_NULL),
+        }
+        self
+    }
+
+    pub fn put_arbitrary(&mut self, data: &[u8]) -> &mut Self {
+        self.0.push(TAG_ARBITRARY);
+        self.0.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        self.0.extend_from_slice(data);
+        self
+    }
+
+    pub fn put_sample_spec(&mut self, format: u8, channels: u8, rate: u32) -> &mut Self {
+        self.0.push(TAG_SAMPLE_SPEC);
+        self.0.push(format);
+        self.0.push(channels);
+        self.0.extend_from_slice(&rate.to_be_bytes());
+        self
+    }
+
+    /// Standard channel map: mono, front left/right, or auxiliary channels
+    pub fn put_channel_map(&mut self, channels: u8) -> &mut Self {
+        self.0.push(TAG_CHANNEL_MAP);
+        self.0.push(channels);
+        match channels {
+            1 => self.0.push(0),
+            2 => self.0.extend_from_slice(&[1, 2]),
+            // PA_CHANNEL_POSITION_AUX0 onwards
+            n => self.0.extend((0..n).map(|i| 12 + i)),
+        }
+        self
+    }
+
+    /// Property list of string values
+    pub fn put_proplist(&mut self, properties: &[(&str, &str)]) -> &mut Self {
+        self.0.push(TAG_PROPLIST);
+        for (key, value) in properties {
+            let mut data = value.as_bytes().to_vec();
+            data.push(0);
+            self.put_string(Some(key));
+            self.put_u32(data.len() as u32);
+            self.put_arbitrary(&data);
+        }
+        self.put_string(None)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Reads the values of a received [`TagStruct`] in order
+pub struct TagReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> TagReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| anyhow!("Truncated PulseAudio message"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn expect(&mut self, tag: u8) -> Result<()> {
+        let found = self.take(1)?[0];
+        if found != tag {
+            return Err(anyhow!(
+                "Unexpected PulseAudio tag '{}', expected '{}'",
+                found as char,
+                tag as char
+            ));
+        }
+        Ok(())
+    }
+
+    fn be_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32> {
+        self.expect(TAG_U32)?;
+        self.be_u32()
+    }
+
+    pub fn get_string(&mut self) -> Result<Option<String>> {
+        match self.take(1)?[0] {
+            TAG_STRING_NULL => Ok(None),
+            TAG_STRING => {
+                let rest = &self.data[self.pos..];
+                let len = rest
+                    .iter()
+                    .position(|&b| b == 0)
+                    .ok_or_else(|| anyhow!("Unterminated PulseAudio string"))?;
+                let value = String::from_utf8_lossy(&rest[..len]).into_owned();
+                self.pos += len + 1;
+                Ok(Some(value))
+            }
+            tag => Err(anyhow!("Unexpected PulseAudio tag '{}', expected a string", tag as char)),
+        }
+    }
+
+    pub fn get_arbitrary(&mut self) -> Result<&'a [u8]> {
+        self.expect(TAG_ARBITRARY)?;
+        let len = self.be_u32()? as usize;
+        self.take(len)
+    }
+
+    /// Skip one value of any type
+    pub fn skip(&mut self) -> Result<()> {
+        let tag = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| anyhow!("Truncated PulseAudio message"))?;
+        match tag {
+            TAG_STRING | TAG_STRING_NULL => {
+                self.get_string()?;
+            }
+            TAG_ARBITRARY => {
+                self.get_arbitrary()?;
+            }
+            TAG_PROPLIST => {
+                self.pos += 1;
+                while self.get_string()?.is_some() {
+                    self.get_u32()?;
+                    self.get_arbitrary()?;
+                }
+            }
+            _ => {
+                self.pos += 1;
+                let len = match tag {
+                    TAG_U32 | TAG_VOLUME => 4,
+                    TAG_U8 => 1,
+                    TAG_U64 | TAG_S64 | TAG_USEC | TAG_TIMEVAL => 8,
+                    TAG_BOOLEAN_TRUE | TAG_BOOLEAN_FALSE => 0,
+                    TAG_SAMPLE_SPEC => 6,
+                    TAG_CHANNEL_MAP => self.take(1)?[0] as usize,
+                    TAG_CVOLUME => self.take(1)?[0] as usize * 4,
+                    tag => return Err(anyhow!("Unknown PulseAudio tag '{}'", tag as char)),
+                };
+                self.take(len)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A packet from the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    /// Stream channel for audio data, `None` for control messages
+    pub channel: Option<u32>,
+    pub payload: Vec<u8>,
+}
+
+/// Encode a control message with its frame descriptor
+pub fn encode_packet(channel: Option<u32>, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(DESCRIPTOR_LENGTH + payload.len());
+    packet.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    packet.extend_from_slice(&channel.unwrap_or(CONTROL_CHANNEL).to_be_bytes());
+    // Offset (hi, lo) and flags
+    packet.extend_from_slice(&[0u8; 12]);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Reassembles packets from a socket that may time out mid-packet
+#[derive(Debug, Default)]
+pub struct PacketReader {
+    buf: Vec<u8>,
+}
+
+impl PacketReader {
+    /// Next complete packet, or `None` if the read timed out first
+    pub fn next<R: Read>(&mut self, reader: &mut R) -> io::Result<Option<Packet>> {
+        loop {
+            if let Some(packet) = self.take()? {
+                return Ok(Some(packet));
+            }
+            let mut chunk = [0u8; 16 * 1024];
+            match reader.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn take(&mut self) -> io::Result<Option<Packet>> {
+        if self.buf.len() < DESCRIPTOR_LENGTH {
+            return Ok(None);
+        }
+        let word = |i: usize| u32::from_be_bytes(self.buf[i * 4..i * 4 + 4].try_into().unwrap());
+        let len = word(0) as usize;
+        if len > MAX_PACKET {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "PulseAudio packet too large"));
+        }
+        if self.buf.len() < DESCRIPTOR_LENGTH + len {
+            return Ok(None);
+        }
+        let channel = match word(1) {
+            CONTROL_CHANNEL => None,
+            channel => Some(channel),
+        };
+        let payload = self.buf[DESCRIPTOR_LENGTH..DESCRIPTOR_LENGTH + len].to_vec();
+        self.buf.drain(..DESCRIPTOR_LENGTH + len);
+        Ok(Some(Packet { channel, payload }))
+    }
+}
+
+/// Describe a `PA_ERR_*` code
+fn error_name(code: u32) -> &'static str {
+    match code {
+        1 => "access denied",
+        2 => "unknown command",
+        3 => "invalid argument",
+        4 => "entity exists",
+        5 => "no such entity",
+        9 => "bad authentication key",
+        14 => "module initialization failed",
+        17 => "bad protocol version",
+        19 => "operation not supported",
+        _ => "error",
+    }
+}
+
+/// Socket of the local sound server: `$PULSE_SERVER` if it names a unix
+/// socket, else the per-user socket under `$XDG_RUNTIME_DIR`
+pub fn server_path() -> Option<PathBuf> {
+    if let Ok(server) = std::env::var("PULSE_SERVER") {
+        return server
+            .split_whitespace()
+            .find_map(|s| s.strip_prefix("unix:").or_else(|| s.starts_with('/').then_some(s)))
+            .map(PathBuf::from);
+    }
+    let runtime = std::env::var_os("XDG_RUNTIME_DIR")?;
+    Some(PathBuf::from(runtime).join("pulse").join("native"))
+}
+
+/// Authentication cookie: `$PULSE_COOKIE`, `~/.config/pulse/cookie` or
+/// `~/.pulse-cookie`
+///
+/// PipeWire doesn't check it, and PulseAudio's default per-user socket
+/// accepts any local client of the same user, so zeros are sent if none is found.
+pub fn load_cookie() -> [u8; COOKIE_LENGTH] {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".config")));
+    let candidates = [
+        std::env::var_os("PULSE_COOKIE").map(PathBuf::from),
+        config.map(|c| c.join("pulse").join("cookie")),
+        home.map(|h| h.join(".pulse-cookie")),
+    ];
+
+    let mut cookie = [0u8; COOKIE_LENGTH];
+    if let Some(data) = candidates
+        .into_iter()
+        .flatten()
+        .find_map(|path| std::fs::read(path).ok())
+        .filter(|data| data.len() >= COOKIE_LENGTH)
+    {
+        cookie.copy_from_slice(&data[..COOKIE_LENGTH]);
+    }
+    cookie
+}
+
+/// An authenticated connection to the sound server
+pub struct Connection {
+    stream: UnixStream,
+    reader: PacketReader,
+    next_tag: u32,
+    /// Audio packets received while waiting for a reply
+    pending_data: Vec<Packet>,
+}
+
+impl Connection {
+    /// Connect, authenticate and name the client
+    pub fn connect(path: &std::path::Path, client_name: &str) -> Result<Self> {
+        let stream = UnixStream::connect(path)
+            .with_context(|| format!("Failed to connect to sound server at {}", path.display()))?;
+        let mut connection = Self {
+            stream,
+            reader: PacketReader::default(),
+            next_tag: 0,
+            pending_data: Vec::new(),
+        };
+
+        let cookie = load_cookie();
+        connection
+            .request(COMMAND_AUTH, |ts| {
+                ts.put_u32(PROTOCOL_VERSION).put_arbitrary(&cookie);
+            })
+            .context("Sound server rejected authentication")?;
+        connection.request(COMMAND_SET_CLIENT_NAME, |ts| {
+            ts.put_proplist(&[("application.name", client_name)]);
+        })?;
+        Ok(connection)
+    }
+
+    /// Send a command and wait for its reply, returning the reply body
+    pub fn request(&mut self, command: u32, build: impl FnOnce(&mut TagStruct)) -> Result<Vec<u8>> {
+        let tag = self.next_tag;
+        self.next_tag = self.next_tag.wrapping_add(1);
+        let mut ts = TagStruct::command(command, tag);
+        build(&mut ts);
+        self.stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+        self.stream.write_all(&encode_packet(None, ts.as_bytes()))?;
+
+        loop {
+            let Some(packet) = self.reader.next(&mut self.stream)? else {
+                return Err(anyhow!("Sound server didn't answer command {}", command));
+            };
+            if packet.channel.is_some() {
+                self.pending_data.push(packet);
+                continue;
+            }
+
+            let mut reply = TagReader::new(&packet.payload);
+            let (kind, reply_tag) = (reply.get_u32()?, reply.get_u32()?);
+            // Anything else is an unsolicited event for us to ignore
+            if reply_tag != tag {
+                continue;
+            }
+            return match kind {
+                COMMAND_REPLY => Ok(packet.payload[reply.pos..].to_vec()),
+                COMMAND_ERROR => {
+                    let code = reply.get_u32().unwrap_or(0);
+                    Err(anyhow!("Sound server error {} ({})", code, error_name(code)))
+                }
+                _ => continue,
+            };
+        }
+    }
+
+    /// Next audio packet, or `None` if none arrived within `timeout`
+    pub fn next_data(&mut self, timeout: Duration) -> Result<Option<Packet>> {
+        if !self.pending_data.is_empty() {
+            return Ok(Some(self.pending_data.remove(0)));
+        }
+        self.stream.set_read_timeout(Some(timeout))?;
+        loop {
+            match self.reader.next(&mut self.stream)? {
+                Some(packet) if packet.channel.is_some() => return Ok(Some(packet)),
+                // Control messages (overflows, events) aren't needed while recording
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tagstruct_roundtrip() {
+        let mut ts = TagStruct::command(COMMAND_LOAD_MODULE, 7);
+        ts.put_string(Some("module-null-sink"))
+            .put_string(None)
+            .put_bool(true)
+            .put_sample_spec(SAMPLE_FLOAT32LE, 2, 48000)
+            .put_channel_map(2)
+            .put_proplist(&[("application.name", "AAEQ")])
+            .put_arbitrary(&[1, 2, 3])
+            .put_u32(9);
+
+        let mut reader = TagReader::new(ts.as_bytes());
+        assert_eq!(reader.get_u32().unwrap(), COMMAND_LOAD_MODULE);
+        assert_eq!(reader.get_u32().unwrap(), 7);
+        assert_eq!(reader.get_string().unwrap().as_deref(), Some("module-null-sink"));
+        assert_eq!(reader.get_string().unwrap(), None);
+        reader.skip().unwrap(); // boolean
+        reader.skip().unwrap(); // sample spec
+        reader.skip().unwrap(); // channel map
+        reader.skip().unwrap(); // proplist
+        assert_eq!(reader.get_arbitrary().unwrap(), &[1, 2, 3]);
+        reader.skip().unwrap();
+        assert!(reader.is_empty());
+
+        // Wrong type is an error, not a misread
+        assert!(TagReader::new(&[TAG_U8, 1]).get_u32().is_err());
+    }
+
+    #[test]
+    fn test_packet_reader_reassembles_split_packets() {
+        let mut wire = encode_packet(None, b"control");
+        wire.extend(encode_packet(Some(3), &[0u8; 8]));
+
+        // Deliver in small pieces, as a timed-out socket might
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                let n = self.0.len().min(7).min(buf.len());
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let mut source = Trickle(&wire);
+        let mut reader = PacketReader::default();
+        let first = reader.next(&mut source).unwrap().unwrap();
+        assert_eq!(first, Packet { channel: None, payload: b"control".to_vec() });
+        let second = reader.next(&mut source).unwrap().unwrap();
+        assert_eq!(second.channel, Some(3));
+        assert_eq!(second.payload.len(), 8);
+        assert!(reader.next(&mut source).unwrap().is_none());
+    }
+}
@@ -1,17 +1,25 @@
 pub mod control_api;
 pub mod convert;
 pub mod dsp;
+pub mod events;
 pub mod input;
 pub mod manager;
+pub mod mqtt;
+pub mod pump;
 pub mod sink;
 pub mod sinks;
+pub mod telemetry;
 pub mod types;
 
 pub use control_api::*;
 pub use convert::*;
 pub use dsp::*;
+pub use events::*;
 pub use input::*;
 pub use manager::*;
+pub use mqtt::*;
+pub use pump::*;
 pub use sink::*;
 pub use sinks::*;
+pub use telemetry::*;
 pub use types::*;
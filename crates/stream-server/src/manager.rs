@@ -1,5 +1,7 @@
+use crate::events::{EventBus, SinkState, StreamEvent};
 use crate::sink::{OutputSink, SinkStats};
 use crate::types::{AudioBlock, OutputConfig};
+use aaeq_core::TrackMeta;
 use anyhow::{anyhow, Result};
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -8,6 +10,12 @@ use tokio::sync::RwLock;
 pub struct OutputManager {
     sinks: Vec<SinkEntry>,
     active_idx: Option<usize>,
+    /// Last sink selected and its config, for [`OutputManager::reopen`]
+    last_selected: Option<(usize, OutputConfig)>,
+    /// Where sinks opening and closing are announced
+    events: Option<EventBus>,
+    /// Current track, passed on to sinks as they open
+    track: Option<TrackMeta>,
 }
 
 struct SinkEntry {
@@ -16,12 +24,42 @@ struct SinkEntry {
     config: Option<OutputConfig>,
 }
 
+/// Snapshot of one registered sink
+#[derive(Clone, Debug)]
+pub struct SinkStatus {
+    pub name: &'static str,
+    pub is_open: bool,
+    pub is_active: bool,
+    /// Config the sink was opened with, while open
+    pub config: Option<OutputConfig>,
+    pub latency_ms: u32,
+    pub stats: SinkStats,
+}
+
 impl OutputManager {
     /// Create a new OutputManager
     pub fn new() -> Self {
         Self {
             sinks: Vec::new(),
             active_idx: None,
+            last_selected: None,
+            events: None,
+            track: None,
+        }
+    }
+
+    /// Publish sinks opening and closing on `events`
+    pub fn set_events(&mut self, events: EventBus) {
+        self.events = Some(events);
+    }
+
+    fn publish_state(&self, idx: usize, state: SinkState) {
+        if let Some(events) = &self.events {
+            events.publish(StreamEvent::SinkState {
+                sink: self.sinks[idx].sink.name().to_string(),
+                state,
+                config: self.sinks[idx].config.clone(),
+            });
         }
     }
 
@@ -55,17 +93,38 @@ impl OutputManager {
             if active_idx != idx {
                 self.sinks[active_idx].sink.close().await?;
                 self.sinks[active_idx].config = None;
+                self.publish_state(active_idx, SinkState::Closed);
             }
         }
 
         // Open the new sink
         self.sinks[idx].sink.open(config.clone()).await?;
-        self.sinks[idx].config = Some(config);
+        if let Some(track) = &self.track {
+            self.sinks[idx].sink.set_track(track).await?;
+        }
+        self.sinks[idx].config = Some(config.clone());
         self.active_idx = Some(idx);
+        self.last_selected = Some((idx, config));
+        self.publish_state(idx, SinkState::Opened);
 
         Ok(())
     }
 
+    /// Open the last selected sink again with its previous config
+    ///
+    /// Does nothing if a sink is active. Returns whether a sink is active
+    /// afterwards, i.e. false if none has ever been selected.
+    pub async fn reopen(&mut self) -> Result<bool> {
+        if self.active_idx.is_some() {
+            return Ok(true);
+        }
+        let Some((idx, config)) = self.last_selected.clone() else {
+            return Ok(false);
+        };
+        self.select_sink(idx, config).await?;
+        Ok(true)
+    }
+
     /// Select a sink by name
     pub async fn select_sink_by_name(&mut self, name: &str, config: OutputConfig) -> Result<()> {
         let idx = self
@@ -90,6 +149,28 @@ impl OutputManager {
         Ok(())
     }
 
+    /// Tell the active sink, and sinks opened later, about the current track
+    pub async fn set_track(&mut self, track: TrackMeta) -> Result<()> {
+        if self.track.as_ref() == Some(&track) {
+            return Ok(());
+        }
+        if let Some(idx) = self.active_idx {
+            self.sinks[idx].sink.set_track(&track).await?;
+        }
+        self.track = Some(track);
+        Ok(())
+    }
+
+    /// Turn per-track files on or off for the sink named `name`; false if there is none
+    pub fn set_split_tracks(&mut self, name: &str, split: bool) -> bool {
+        let mut found = false;
+        for entry in self.sinks.iter_mut().filter(|entry| entry.sink.name() == name) {
+            entry.sink.set_split_tracks(split);
+            found = true;
+        }
+        found
+    }
+
     /// Drain the active sink
     pub async fn drain(&mut self) -> Result<()> {
         let active_idx = self
@@ -105,6 +186,7 @@ impl OutputManager {
             self.sinks[idx].sink.close().await?;
             self.sinks[idx].config = None;
             self.active_idx = None;
+            self.publish_state(idx, SinkState::Closed);
         }
         Ok(())
     }
@@ -137,6 +219,30 @@ impl OutputManager {
         self.active_idx
             .map(|idx| self.sinks[idx].sink.latency_ms())
     }
+
+    /// Status of every registered sink, in registration order
+    pub fn sink_status(&self) -> Vec<SinkStatus> {
+        (0..self.sinks.len()).map(|idx| self.status_of(idx)).collect()
+    }
+
+    /// Status of the active sink
+    pub fn active_sink_status(&self) -> Option<SinkStatus> {
+        self.active_idx.map(|idx| self.status_of(idx))
+    }
+
+    fn status_of(&self, idx: usize) -> SinkStatus {
+        let entry = &self.sinks[idx];
+        let mut stats = entry.sink.stats();
+        stats.frames_written = entry.stats.frames_written;
+        SinkStatus {
+            name: entry.sink.name(),
+            is_open: entry.sink.is_open(),
+            is_active: self.active_idx == Some(idx),
+            config: entry.config.clone(),
+            latency_ms: entry.sink.latency_ms(),
+            stats,
+        }
+    }
 }
 
 impl Default for OutputManager {
@@ -273,4 +379,66 @@ mod tests {
         manager.close_active().await.unwrap();
         assert!(manager.active_sink_name().is_none());
     }
+
+    #[tokio::test]
+    async fn test_manager_sink_status() {
+        let mut manager = OutputManager::new();
+        manager.register_sink(Box::new(MockSink::new("sink1")));
+        manager.register_sink(Box::new(MockSink::new("sink2")));
+        assert!(manager.active_sink_status().is_none());
+
+        let config = OutputConfig::default();
+        manager.select_sink(1, config.clone()).await.unwrap();
+        let frames = vec![0.0; 480];
+        manager.write(AudioBlock::new(&frames, 48000, 2)).await.unwrap();
+
+        let status = manager.sink_status();
+        assert_eq!(status.len(), 2);
+        assert!(!status[0].is_open && !status[0].is_active);
+        assert!(status[0].config.is_none());
+        assert!(status[1].is_open && status[1].is_active);
+        assert_eq!(status[1].config.as_ref().unwrap().sample_rate, config.sample_rate);
+        assert_eq!(status[1].latency_ms, 50);
+        assert_eq!(status[1].stats.frames_written, 240);
+        assert_eq!(manager.active_sink_status().unwrap().name, "sink2");
+    }
+
+    #[tokio::test]
+    async fn test_manager_reopen() {
+        let mut manager = OutputManager::new();
+        manager.register_sink(Box::new(MockSink::new("test")));
+        assert!(!manager.reopen().await.unwrap());
+
+        manager.select_sink(0, OutputConfig::default()).await.unwrap();
+        manager.close_active().await.unwrap();
+        assert!(manager.reopen().await.unwrap());
+        assert_eq!(manager.active_sink_name(), Some("test"));
+        assert!(manager.active_sink_status().unwrap().is_open);
+    }
+
+    #[tokio::test]
+    async fn test_manager_publishes_sink_state() {
+        let mut manager = OutputManager::new();
+        manager.register_sink(Box::new(MockSink::new("sink1")));
+        manager.register_sink(Box::new(MockSink::new("sink2")));
+        let events = EventBus::new();
+        let mut rx = events.subscribe();
+        manager.set_events(events);
+
+        let state = |event: StreamEvent| match event {
+            StreamEvent::SinkState { sink, state, config } => (sink, state, config.is_some()),
+            other => panic!("unexpected {:?}", other),
+        };
+        manager.select_sink(0, OutputConfig::default()).await.unwrap();
+        assert_eq!(state(rx.try_recv().unwrap()), ("sink1".to_string(), SinkState::Opened, true));
+
+        // Switching closes the previous sink first
+        manager.select_sink(1, OutputConfig::default()).await.unwrap();
+        assert_eq!(state(rx.try_recv().unwrap()), ("sink1".to_string(), SinkState::Closed, false));
+        assert_eq!(state(rx.try_recv().unwrap()), ("sink2".to_string(), SinkState::Opened, true));
+
+        manager.close_active().await.unwrap();
+        assert_eq!(state(rx.try_recv().unwrap()), ("sink2".to_string(), SinkState::Closed, false));
+        assert!(rx.try_recv().is_err());
+    }
 }
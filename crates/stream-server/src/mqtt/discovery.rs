@@ -0,0 +1,171 @@
+/// Home Assistant MQTT discovery
+///
+/// Each entity gets a retained config on
+/// `<discovery prefix>/<component>/<node>/<object>/config` pointing at the
+/// bridge's state and command topics. An empty config removes the entity,
+/// which is what selects without options and an unsupported volume get.
+use super::{MqttState, Topics};
+use serde_json::{json, Value};
+
+/// Template turning the track JSON into the now playing sensor's state
+const NOW_PLAYING_TEMPLATE: &str =
+    "{% if value_json.title is defined %}{{ value_json.artist }} – {{ value_json.title }}{% else %}Nothing playing{% endif %}";
+
+/// Discovery config topics and payloads for the entities of `state`
+pub fn discovery_configs(topics: &Topics, discovery_prefix: &str, state: &MqttState) -> Vec<(String, String)> {
+    let node = topics.node_id();
+    let device = json!({
+        "identifiers": [node],
+        "name": "AAEQ",
+        "manufacturer": "AAEQ",
+        "model": "AAEQ",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+
+    let entity = |object: &str, name: &str, icon: &str, fields: Value| {
+        let mut config = json!({
+            "name": name,
+            "unique_id": format!("{}_{}", node, object),
+            "object_id": format!("{}_{}", node, object),
+            "icon": icon,
+            "availability_topic": topics.status(),
+            "device": device,
+        });
+        if let (Some(config), Value::Object(fields)) = (config.as_object_mut(), fields) {
+            config.extend(fields);
+        }
+        config.to_string()
+    };
+    let select = |object: &str, name: &str, icon: &str, options: &[String]| {
+        if options.is_empty() {
+            return String::new();
+        }
+        entity(
+            object,
+            name,
+            icon,
+            json!({
+                "state_topic": topics.state(object),
+                "command_topic": topics.command(object),
+                "options": options,
+            }),
+        )
+    };
+
+    let configs = [
+        (
+            "sensor",
+            "now_playing",
+            entity(
+                "now_playing",
+                "Now playing",
+                "mdi:music",
+                json!({
+                    "state_topic": topics.state("track"),
+                    "value_template": NOW_PLAYING_TEMPLATE,
+                    "json_attributes_topic": topics.state("track"),
+                }),
+            ),
+        ),
+        ("select", "preset", select("preset", "Preset", "mdi:tune-vertical", &state.presets)),
+        ("select", "profile", select("profile", "Profile", "mdi:folder-music", &state.profiles)),
+        (
+            "sensor",
+            "output",
+            entity("output", "Output", "mdi:speaker", json!({ "state_topic": topics.state("output") })),
+        ),
+        (
+            "switch",
+            "streaming",
+            entity(
+                "streaming",
+                "Streaming",
+                "mdi:play-network",
+                json!({
+                    "state_topic": topics.state("streaming"),
+                    "command_topic": topics.command("streaming"),
+                    "payload_on": "ON",
+                    "payload_off": "OFF",
+                }),
+            ),
+        ),
+        (
+            "number",
+            "volume",
+            match state.volume {
+                Some(_) => entity(
+                    "volume",
+                    "Volume",
+                    "mdi:volume-high",
+                    json!({
+                        "state_topic": topics.state("volume"),
+                        "command_topic": topics.command("volume"),
+                        "min": 0,
+                        "max": 100,
+                        "step": 1,
+                        "unit_of_measurement": "%",
+                        "mode": "slider",
+                    }),
+                ),
+                None => String::new(),
+            },
+        ),
+    ];
+
+    configs
+        .into_iter()
+        .map(|(component, object, config)| {
+            (format!("{}/{}/{}/{}/config", discovery_prefix, component, node, object), config)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config<'a>(configs: &'a [(String, String)], topic: &str) -> &'a str {
+        &configs.iter().find(|(t, _)| t == topic).unwrap().1
+    }
+
+    #[test]
+    fn test_discovery_configs() {
+        let state = MqttState {
+            presets: vec!["Flat".to_string(), "Rock".to_string()],
+            profiles: vec!["Default".to_string()],
+            volume: Some(0.5),
+            ..Default::default()
+        };
+        let configs = discovery_configs(&Topics::new("aaeq"), "homeassistant", &state);
+        assert_eq!(configs.len(), 6);
+
+        let preset: Value = serde_json::from_str(config(&configs, "homeassistant/select/aaeq/preset/config")).unwrap();
+        assert_eq!(preset["unique_id"], "aaeq_preset");
+        assert_eq!(preset["state_topic"], "aaeq/preset");
+        assert_eq!(preset["command_topic"], "aaeq/preset/set");
+        assert_eq!(preset["availability_topic"], "aaeq/status");
+        assert_eq!(preset["options"], json!(["Flat", "Rock"]));
+        assert_eq!(preset["device"]["identifiers"], json!(["aaeq"]));
+
+        let streaming: Value =
+            serde_json::from_str(config(&configs, "homeassistant/switch/aaeq/streaming/config")).unwrap();
+        assert_eq!(streaming["payload_on"], "ON");
+
+        let volume: Value = serde_json::from_str(config(&configs, "homeassistant/number/aaeq/volume/config")).unwrap();
+        assert_eq!(volume["max"], 100);
+    }
+
+    #[test]
+    fn test_unavailable_entities_are_removed() {
+        let state = MqttState {
+            presets: vec!["Flat".to_string()],
+            ..Default::default()
+        };
+        let configs = discovery_configs(&Topics::new("home/aaeq"), "ha", &state);
+
+        // Profiles and volume aren't there, so their entities get empty configs
+        assert_eq!(config(&configs, "ha/select/home_aaeq/profile/config"), "");
+        assert_eq!(config(&configs, "ha/number/home_aaeq/volume/config"), "");
+        assert!(!config(&configs, "ha/select/home_aaeq/preset/config").is_empty());
+    }
+}
@@ -0,0 +1,503 @@
+/// MQTT bridge for home automation
+///
+/// Publishes what AAEQ is doing as retained messages under a base topic and
+/// takes commands on `<base>/<name>/set`, so any MQTT client can follow and
+/// drive it. Home Assistant discovery configs are published alongside (see
+/// [`discovery`]), so its entities appear without any YAML.
+///
+/// | Topic              | Payload                            | Command topic          |
+/// |--------------------|------------------------------------|------------------------|
+/// | `<base>/status`    | `online` / `offline` (last will)   |                        |
+/// | `<base>/track`     | the playing track as JSON, or `{}` |                        |
+/// | `<base>/preset`    | preset name                        | `<base>/preset/set`    |
+/// | `<base>/profile`   | profile name                       | `<base>/profile/set`   |
+/// | `<base>/output`    | active output, or `None`           |                        |
+/// | `<base>/streaming` | `ON` / `OFF`                       | `<base>/streaming/set` |
+/// | `<base>/volume`    | 0 to 100                           | `<base>/volume/set`    |
+///
+/// State comes from an [`MqttHandler`], polled every second and whenever the
+/// [`EventBus`] reports a change; only what changed is published again.
+pub mod discovery;
+
+use crate::events::{EventBus, StreamEvent};
+use aaeq_core::{MqttSettings, TrackMeta};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use discovery::discovery_configs;
+use rumqttc::{AsyncClient, ConnectReturnCode, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+/// How often state is polled when no events say it changed
+const STATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wait between attempts to reach the broker
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// Discovery configs list every preset, so allow more than rumqttc's 10 KiB
+const MAX_PACKET_SIZE: usize = 256 * 1024;
+
+/// Requests queued for the connection before publishes are dropped
+const REQUEST_CAPACITY: usize = 64;
+
+/// How long stopping waits for `offline` to reach the broker
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// State topics that take commands
+const COMMANDS: [&str; 4] = ["profile", "preset", "streaming", "volume"];
+
+/// What the bridge publishes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MqttState {
+    pub track: Option<TrackMeta>,
+    pub preset: Option<String>,
+    pub profile: Option<String>,
+    /// Names offered by the profile select
+    pub profiles: Vec<String>,
+    /// Names offered by the preset select
+    pub presets: Vec<String>,
+    pub output: Option<String>,
+    pub streaming: bool,
+    /// 0.0 to 1.0, or `None` when volume can't be set
+    pub volume: Option<f32>,
+}
+
+/// Supplies the bridge's state and carries out its commands
+#[async_trait]
+pub trait MqttHandler: Send + Sync {
+    async fn state(&self) -> Result<MqttState>;
+
+    /// Switch to the profile with this name
+    async fn activate_profile(&self, name: &str) -> Result<()>;
+
+    /// Apply a preset until the track changes
+    async fn apply_preset(&self, name: &str) -> Result<()>;
+
+    /// Start or stop streaming to the active output
+    async fn set_streaming(&self, streaming: bool) -> Result<()>;
+
+    /// Set the output volume (0.0 to 1.0)
+    async fn set_volume(&self, volume: f32) -> Result<()>;
+}
+
+/// The bridge's topic names
+#[derive(Debug, Clone)]
+pub struct Topics {
+    base: String,
+}
+
+impl Topics {
+    pub fn new(base: &str) -> Self {
+        let base = base.trim().trim_matches('/');
+        Self {
+            base: if base.is_empty() { MqttSettings::default().base_topic } else { base.to_string() },
+        }
+    }
+
+    /// Availability: `online` while connected, `offline` otherwise
+    pub fn status(&self) -> String {
+        self.state("status")
+    }
+
+    pub fn state(&self, name: &str) -> String {
+        format!("{}/{}", self.base, name)
+    }
+
+    pub fn command(&self, name: &str) -> String {
+        format!("{}/{}/set", self.base, name)
+    }
+
+    /// The state a command topic sets, if it is one
+    pub fn command_name<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        let name = topic.strip_prefix(self.base.as_str())?.strip_prefix('/')?.strip_suffix("/set")?;
+        COMMANDS.contains(&name).then_some(name)
+    }
+
+    /// Identifies this AAEQ to Home Assistant (the base topic, made safe)
+    pub fn node_id(&self) -> String {
+        self.base
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect()
+    }
+}
+
+/// Publishes AAEQ's state over MQTT and takes commands
+pub struct MqttBridge {
+    settings: MqttSettings,
+    handler: Arc<dyn MqttHandler>,
+    events: Option<EventBus>,
+    state_interval: Duration,
+}
+
+impl MqttBridge {
+    pub fn new(settings: MqttSettings, handler: Arc<dyn MqttHandler>) -> Self {
+        Self {
+            settings,
+            handler,
+            events: None,
+            state_interval: STATE_INTERVAL,
+        }
+    }
+
+    /// Publish changes as soon as these events report them
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Poll state this often instead
+    pub fn with_state_interval(mut self, interval: Duration) -> Self {
+        self.state_interval = interval;
+        self
+    }
+
+    /// Connect in the background, reconnecting whenever the broker goes away
+    pub fn start(self) -> Result<MqttHandle> {
+        let host = self.settings.host.trim();
+        if host.is_empty() {
+            bail!("No MQTT broker host set");
+        }
+
+        let topics = Topics::new(&self.settings.base_topic);
+        let client_id = format!("{}-{}", topics.node_id(), &uuid::Uuid::new_v4().simple().to_string()[..8]);
+        let mut options = MqttOptions::new(client_id, host, self.settings.port);
+        options
+            .set_keep_alive(KEEP_ALIVE)
+            .set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE)
+            .set_last_will(LastWill::new(topics.status(), "offline", QoS::AtLeastOnce, true));
+        if let Some(username) = &self.settings.username {
+            options.set_credentials(username, self.settings.password.clone().unwrap_or_default());
+        }
+
+        let (client, eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+        let broker = format!("{}:{}", host, self.settings.port);
+        let poller = tokio::spawn(poll_event_loop(eventloop, incoming_tx, broker));
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let bridge = Bridge {
+            client,
+            topics,
+            discovery_prefix: self.settings.discovery_prefix.trim().trim_matches('/').to_string(),
+            handler: self.handler,
+            connected: false,
+            published: HashMap::new(),
+        };
+        let events = self.events.map(|events| events.subscribe());
+        let task = tokio::spawn(bridge.run(incoming_rx, events, self.state_interval, shutdown_rx));
+
+        Ok(MqttHandle {
+            shutdown: Some(shutdown_tx),
+            task,
+            poller,
+        })
+    }
+}
+
+/// A running [`MqttBridge`]; dropping it stops the bridge without saying goodbye
+pub struct MqttHandle {
+    shutdown: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+    poller: JoinHandle<()>,
+}
+
+impl MqttHandle {
+    /// Mark AAEQ offline and disconnect
+    pub async fn stop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let _ = tokio::time::timeout(STOP_TIMEOUT, &mut self.task).await;
+        let _ = tokio::time::timeout(STOP_TIMEOUT, &mut self.poller).await;
+        self.task.abort();
+        self.poller.abort();
+    }
+}
+
+impl Drop for MqttHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.poller.abort();
+    }
+}
+
+/// What the connection reports to the bridge
+enum Incoming {
+    Connected,
+    Disconnected,
+    Message { topic: String, payload: String },
+}
+
+/// Drives the connection, passing on what arrives
+async fn poll_event_loop(mut eventloop: EventLoop, tx: mpsc::UnboundedSender<Incoming>, broker: String) {
+    let mut online = true;
+    loop {
+        let incoming = match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(ack))) if ack.code == ConnectReturnCode::Success => {
+                info!("Connected to MQTT broker {}", broker);
+                online = true;
+                Some(Incoming::Connected)
+            }
+            // Retained commands would be carried out again on every reconnect
+            Ok(Event::Incoming(Packet::Publish(publish))) if !publish.retain => Some(Incoming::Message {
+                topic: publish.topic,
+                payload: String::from_utf8_lossy(&publish.payload).into_owned(),
+            }),
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+            Ok(_) => None,
+            Err(e) => {
+                if online {
+                    warn!("MQTT broker {} unavailable: {}; retrying every {:?}", broker, e, RECONNECT_DELAY);
+                    online = false;
+                } else {
+                    debug!("MQTT reconnect to {} failed: {}", broker, e);
+                }
+                let _ = tx.send(Incoming::Disconnected);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                None
+            }
+        };
+        if let Some(incoming) = incoming {
+            if tx.send(incoming).is_err() {
+                break;
+            }
+        }
+        if tx.is_closed() {
+            break;
+        }
+    }
+}
+
+/// The bridge's side of the connection
+struct Bridge {
+    client: AsyncClient,
+    topics: Topics,
+    discovery_prefix: String,
+    handler: Arc<dyn MqttHandler>,
+    connected: bool,
+    /// Retained payloads published since connecting, by topic
+    published: HashMap<String, String>,
+}
+
+impl Bridge {
+    async fn run(
+        mut self,
+        mut incoming: mpsc::UnboundedReceiver<Incoming>,
+        mut events: Option<broadcast::Receiver<StreamEvent>>,
+        state_interval: Duration,
+        mut shutdown: oneshot::Receiver<()>,
+    ) {
+        let mut ticker = tokio::time::interval(state_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                message = incoming.recv() => match message {
+                    Some(Incoming::Connected) => {
+                        // A new session: subscribe again and republish everything
+                        self.connected = true;
+                        self.published.clear();
+                        self.subscribe();
+                        self.refresh().await;
+                    }
+                    Some(Incoming::Disconnected) => self.connected = false,
+                    Some(Incoming::Message { topic, payload }) => {
+                        self.handle_message(&topic, payload.trim()).await;
+                        self.refresh().await;
+                    }
+                    None => return,
+                },
+                _ = ticker.tick() => self.refresh().await,
+                changed = next_change(&mut events) => {
+                    if changed {
+                        self.refresh().await;
+                    }
+                }
+            }
+        }
+
+        let _ = self.client.try_publish(self.topics.status(), QoS::AtLeastOnce, true, "offline");
+        let _ = self.client.try_disconnect();
+    }
+
+    fn subscribe(&self) {
+        for command in COMMANDS {
+            if let Err(e) = self.client.try_subscribe(self.topics.command(command), QoS::AtLeastOnce) {
+                warn!("Failed to subscribe to MQTT {} commands: {}", command, e);
+            }
+        }
+    }
+
+    async fn handle_message(&self, topic: &str, payload: &str) {
+        let Some(command) = self.topics.command_name(topic) else {
+            return;
+        };
+        debug!("MQTT command {}: {:?}", command, payload);
+        if let Err(e) = run_command(self.handler.as_ref(), command, payload).await {
+            warn!("MQTT {} command {:?} failed: {:#}", command, payload, e);
+        }
+    }
+
+    /// Publish whatever changed since it was last published
+    async fn refresh(&mut self) {
+        if !self.connected {
+            return;
+        }
+        let state = match self.handler.state().await {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("Failed to read state for MQTT: {:#}", e);
+                return;
+            }
+        };
+
+        let mut messages = discovery_configs(&self.topics, &self.discovery_prefix, &state);
+        messages.extend(state_messages(&self.topics, &state));
+        for (topic, payload) in messages {
+            if self.published.get(&topic) == Some(&payload) {
+                continue;
+            }
+            match self.client.try_publish(topic.as_str(), QoS::AtLeastOnce, true, payload.as_bytes()) {
+                Ok(()) => {
+                    self.published.insert(topic, payload);
+                }
+                // Tried again on the next refresh
+                Err(e) => debug!("MQTT publish to {} dropped: {}", topic, e),
+            }
+        }
+    }
+}
+
+/// Whether the next event may have changed the published state
+async fn next_change(events: &mut Option<broadcast::Receiver<StreamEvent>>) -> bool {
+    let Some(receiver) = events else {
+        return std::future::pending().await;
+    };
+    match receiver.recv().await {
+        Ok(event) => matches!(
+            event,
+            StreamEvent::TrackChanged { .. } | StreamEvent::PresetApplied { .. } | StreamEvent::SinkState { .. }
+        ),
+        Err(broadcast::error::RecvError::Lagged(_)) => true,
+        Err(broadcast::error::RecvError::Closed) => {
+            *events = None;
+            false
+        }
+    }
+}
+
+/// State topics and payloads for `state`
+fn state_messages(topics: &Topics, state: &MqttState) -> Vec<(String, String)> {
+    let track = state
+        .track
+        .as_ref()
+        .and_then(|track| serde_json::to_string(track).ok())
+        .unwrap_or_else(|| "{}".to_string());
+
+    let mut messages = vec![
+        (topics.status(), "online".to_string()),
+        (topics.state("track"), track),
+        (topics.state("preset"), state.preset.clone().unwrap_or_default()),
+        (topics.state("profile"), state.profile.clone().unwrap_or_default()),
+        (topics.state("output"), state.output.clone().unwrap_or_else(|| "None".to_string())),
+        (topics.state("streaming"), if state.streaming { "ON" } else { "OFF" }.to_string()),
+    ];
+    if let Some(volume) = state.volume {
+        messages.push((topics.state("volume"), format!("{}", (volume * 100.0).round() as u32)));
+    }
+    messages
+}
+
+async fn run_command(handler: &dyn MqttHandler, command: &str, payload: &str) -> Result<()> {
+    match command {
+        "profile" => handler.activate_profile(payload).await,
+        "preset" => handler.apply_preset(payload).await,
+        "streaming" => handler.set_streaming(parse_switch(payload)?).await,
+        "volume" => handler.set_volume(parse_volume(payload)?).await,
+        _ => Err(anyhow!("Unknown command")),
+    }
+}
+
+fn parse_switch(payload: &str) -> Result<bool> {
+    match payload.to_ascii_uppercase().as_str() {
+        "ON" | "TRUE" | "1" => Ok(true),
+        "OFF" | "FALSE" | "0" => Ok(false),
+        _ => Err(anyhow!("Expected ON or OFF")),
+    }
+}
+
+/// Percent as sent by Home Assistant's number entity, to 0.0 to 1.0
+fn parse_volume(payload: &str) -> Result<f32> {
+    let percent: f32 = payload.parse().map_err(|_| anyhow!("Expected a number from 0 to 100"))?;
+    if !(0.0..=100.0).contains(&percent) {
+        bail!("Volume must be from 0 to 100");
+    }
+    Ok(percent / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topics() {
+        let topics = Topics::new("/living room/aaeq/");
+        assert_eq!(topics.status(), "living room/aaeq/status");
+        assert_eq!(topics.command("preset"), "living room/aaeq/preset/set");
+        assert_eq!(topics.node_id(), "living_room_aaeq");
+
+        assert_eq!(topics.command_name("living room/aaeq/volume/set"), Some("volume"));
+        assert_eq!(topics.command_name("living room/aaeq/volume"), None);
+        assert_eq!(topics.command_name("living room/aaeq/track/set"), None);
+        assert_eq!(topics.command_name("other/volume/set"), None);
+
+        assert_eq!(Topics::new(" ").status(), "aaeq/status");
+    }
+
+    #[test]
+    fn test_state_messages() {
+        let state = MqttState {
+            track: Some(TrackMeta {
+                artist: "Artist".to_string(),
+                title: "Title".to_string(),
+                ..Default::default()
+            }),
+            preset: Some("Rock".to_string()),
+            streaming: true,
+            volume: Some(0.333),
+            ..Default::default()
+        };
+        let messages: HashMap<_, _> = state_messages(&Topics::new("aaeq"), &state).into_iter().collect();
+        assert_eq!(messages["aaeq/status"], "online");
+        assert!(messages["aaeq/track"].contains("\"title\":\"Title\""));
+        assert_eq!(messages["aaeq/preset"], "Rock");
+        assert_eq!(messages["aaeq/output"], "None");
+        assert_eq!(messages["aaeq/streaming"], "ON");
+        assert_eq!(messages["aaeq/volume"], "33");
+
+        let idle: HashMap<_, _> = state_messages(&Topics::new("aaeq"), &MqttState::default()).into_iter().collect();
+        assert_eq!(idle["aaeq/track"], "{}");
+        assert!(!idle.contains_key("aaeq/volume"));
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert!(parse_switch("on").unwrap());
+        assert!(!parse_switch("OFF").unwrap());
+        assert!(parse_switch("maybe").is_err());
+
+        assert_eq!(parse_volume("75").unwrap(), 0.75);
+        assert_eq!(parse_volume("12.5").unwrap(), 0.125);
+        assert!(parse_volume("101").is_err());
+        assert!(parse_volume("loud").is_err());
+    }
+}
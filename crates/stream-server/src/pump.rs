@@ -0,0 +1,360 @@
+//! The streaming loop shared by the Control API and the daemon
+//!
+//! A [`StreamPump`] takes blocks of interleaved audio from a channel, runs
+//! them through an optional [`StreamProcessor`] and writes them to the
+//! manager's active sink. Along the way it passes track changes on to the
+//! sink, publishes meters and clip counts, keeps the Control API's
+//! [`Metrics`] and logs when writing starts failing and recovers.
+use crate::control_api::routes::Metrics;
+use crate::events::{EventBus, MeterPublisher, StreamEvent, TrackFollower};
+use crate::manager::SharedOutputManager;
+use crate::types::{AudioBlock, OutputConfig};
+use aaeq_core::TrackMeta;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tracing::{info, warn};
+
+/// A piece of a stream's input: audio, or the track the audio after it belongs to
+///
+/// Sending track changes with the audio lets a sink split at the exact frame.
+#[derive(Debug, Clone)]
+pub enum Chunk {
+    /// Interleaved samples
+    Audio(Vec<f64>),
+    Track(TrackMeta),
+}
+
+impl From<Vec<f64>> for Chunk {
+    fn from(samples: Vec<f64>) -> Self {
+        Chunk::Audio(samples)
+    }
+}
+
+/// Processing applied to the audio before it's written, e.g. a DSP chain
+pub trait StreamProcessor: Send {
+    /// Process a block; may return fewer samples, or none, while it fills up
+    fn process(&mut self, samples: &[f64]) -> Result<Vec<f64>>;
+
+    /// Samples clipped so far
+    fn clip_count(&self) -> u64 {
+        0
+    }
+
+    /// Whatever is still held back once the input has ended
+    fn finish(&mut self) -> Result<Vec<f64>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Writes a stream's input to the active sink until shut down or the input ends
+pub struct StreamPump {
+    manager: SharedOutputManager,
+    /// The active sink's config
+    config: OutputConfig,
+    metrics: Option<Arc<RwLock<Metrics>>>,
+    meters: Option<MeterPublisher>,
+    events: Option<EventBus>,
+    tracks: Option<TrackFollower>,
+    processor: Option<Box<dyn StreamProcessor>>,
+}
+
+impl StreamPump {
+    /// Write to `manager`'s active sink, which is open with `config`
+    pub fn new(manager: SharedOutputManager, config: OutputConfig) -> Self {
+        Self {
+            manager,
+            config,
+            metrics: None,
+            meters: None,
+            events: None,
+            tracks: None,
+            processor: None,
+        }
+    }
+
+    /// Publish meters and clip counts on `events`, and announce tracks that
+    /// come with the audio there
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.meters = Some(MeterPublisher::new(events.clone(), self.config.sample_rate, self.config.channels as usize));
+        self.events = Some(events);
+        self
+    }
+
+    /// Pass on the tracks `tracks` follows as well as the ones in the input
+    pub fn with_track_follower(mut self, tracks: TrackFollower) -> Self {
+        self.tracks = Some(tracks);
+        self
+    }
+
+    /// Count underruns, overruns and bytes written in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<RwLock<Metrics>>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Run the audio through `processor` before writing it
+    pub fn with_processor(mut self, processor: Box<dyn StreamProcessor>) -> Self {
+        self.processor = Some(processor);
+        self
+    }
+
+    /// Write `input` until `shutdown` fires or the input ends, then write
+    /// what the processor still holds
+    ///
+    /// An underrun is counted when the input goes quiet for longer than the
+    /// output buffer, an overrun when the input queue fills up because the
+    /// output can't keep pace.
+    pub async fn run<T: Into<Chunk>>(mut self, mut input: mpsc::Receiver<T>, mut shutdown: oneshot::Receiver<()>) {
+        let starve_after = Duration::from_millis(self.config.buffer_ms.max(20) as u64);
+        let mut started = false;
+        let mut starving = false;
+        let mut failing = false;
+
+        loop {
+            let chunk = tokio::select! {
+                // A track published before a block applies to it
+                biased;
+                _ = &mut shutdown => break,
+                Some(track) = next_track(&mut self.tracks) => Chunk::Track(track),
+                received = tokio::time::timeout(starve_after, input.recv()) => match received {
+                    Ok(Some(chunk)) => chunk.into(),
+                    Ok(None) => break,
+                    Err(_) => {
+                        if started && !starving {
+                            self.count(|metrics| metrics.underruns += 1).await;
+                            starving = true;
+                        }
+                        continue;
+                    }
+                },
+            };
+            let samples = match chunk {
+                Chunk::Audio(samples) => samples,
+                Chunk::Track(track) => {
+                    self.set_track(track).await;
+                    continue;
+                }
+            };
+
+            started = true;
+            starving = false;
+            if input.len() + 1 >= input.max_capacity() {
+                self.count(|metrics| metrics.overruns += 1).await;
+            }
+            let result = match self.processor.as_mut().map(|processor| processor.process(&samples)) {
+                Some(Ok(processed)) => self.write(&processed).await,
+                Some(Err(e)) => Err(e),
+                None => self.write(&samples).await,
+            };
+            match result {
+                Err(e) if !failing => {
+                    warn!("Stream write failed: {:#}", e);
+                    failing = true;
+                }
+                Ok(()) if failing => {
+                    info!("Stream recovered");
+                    failing = false;
+                }
+                _ => {}
+            }
+        }
+
+        // Flush what the processor holds back
+        if let Some(Ok(tail)) = self.processor.as_mut().map(|processor| processor.finish()) {
+            let _ = self.write(&tail).await;
+        }
+    }
+
+    /// Pass a track on to the sink, announcing it if it came with the audio
+    async fn set_track(&mut self, track: TrackMeta) {
+        if let Err(e) = self.manager.write().await.set_track(track.clone()).await {
+            warn!("Failed to pass the track to the output: {:#}", e);
+        }
+        // A followed track is already on the bus
+        if let Some(events) = self.events.as_ref().filter(|_| self.tracks.is_none()) {
+            events.publish(StreamEvent::TrackChanged { track });
+        }
+    }
+
+    async fn write(&mut self, samples: &[f64]) -> Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        if let Some(meters) = &mut self.meters {
+            meters.process(samples);
+            if let Some(processor) = &self.processor {
+                meters.report_clips(processor.clip_count());
+            }
+        }
+        self.manager
+            .write()
+            .await
+            .write(AudioBlock::new(samples, self.config.sample_rate, self.config.channels))
+            .await?;
+        let bytes = samples.len() as u64 * self.config.format.bytes_per_sample() as u64;
+        self.count(|metrics| metrics.bytes_written += bytes).await;
+        Ok(())
+    }
+
+    async fn count(&mut self, update: impl FnOnce(&mut Metrics)) {
+        if let Some(metrics) = &self.metrics {
+            update(&mut *metrics.write().await);
+        }
+    }
+}
+
+/// The next track `tracks` follows; never resolves without one
+async fn next_track(tracks: &mut Option<TrackFollower>) -> Option<TrackMeta> {
+    match tracks {
+        Some(tracks) => Some(tracks.next().await),
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manager::OutputManager;
+    use crate::sink::OutputSink;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// What a sink was given, in order
+    #[derive(Debug, PartialEq)]
+    enum Received {
+        Samples(Vec<f64>),
+        Track(String),
+    }
+
+    struct RecordingSink {
+        received: Arc<Mutex<Vec<Received>>>,
+    }
+
+    #[async_trait]
+    impl OutputSink for RecordingSink {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        async fn open(&mut self, _cfg: OutputConfig) -> Result<()> {
+            Ok(())
+        }
+
+        async fn write(&mut self, block: AudioBlock<'_>) -> Result<()> {
+            self.received.lock().unwrap().push(Received::Samples(block.frames.to_vec()));
+            Ok(())
+        }
+
+        async fn drain(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn close(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn latency_ms(&self) -> u32 {
+            0
+        }
+
+        fn is_open(&self) -> bool {
+            true
+        }
+
+        async fn set_track(&mut self, track: &TrackMeta) -> Result<()> {
+            self.received.lock().unwrap().push(Received::Track(track.title.clone()));
+            Ok(())
+        }
+    }
+
+    async fn recording_manager() -> (SharedOutputManager, Arc<Mutex<Vec<Received>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut manager = OutputManager::new();
+        manager.register_sink(Box::new(RecordingSink {
+            received: received.clone(),
+        }));
+        manager.select_sink(0, OutputConfig::default()).await.unwrap();
+        (Arc::new(RwLock::new(manager)), received)
+    }
+
+    fn track(title: &str) -> TrackMeta {
+        TrackMeta {
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Halves the audio, holding back the last sample of each block until the end
+    struct HoldBack(Vec<f64>);
+
+    impl StreamProcessor for HoldBack {
+        fn process(&mut self, samples: &[f64]) -> Result<Vec<f64>> {
+            self.0.extend(samples.iter().map(|s| s * 0.5));
+            let keep = self.0.len() - 1;
+            Ok(self.0.drain(..keep).collect())
+        }
+
+        fn finish(&mut self) -> Result<Vec<f64>> {
+            Ok(std::mem::take(&mut self.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pump_writes_tracks_in_place() {
+        let (manager, received) = recording_manager().await;
+        let events = EventBus::new();
+        let mut announced = events.subscribe();
+        let (tx, rx) = mpsc::channel(8);
+        let (_shutdown, shutdown_rx) = oneshot::channel();
+
+        tx.send(Chunk::Audio(vec![0.1, 0.1])).await.unwrap();
+        tx.send(Chunk::Track(track("Two"))).await.unwrap();
+        tx.send(Chunk::Audio(vec![0.2, 0.2])).await.unwrap();
+        drop(tx);
+        StreamPump::new(manager, OutputConfig::default())
+            .with_events(events)
+            .run(rx, shutdown_rx)
+            .await;
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            [
+                Received::Samples(vec![0.1, 0.1]),
+                Received::Track("Two".to_string()),
+                Received::Samples(vec![0.2, 0.2]),
+            ]
+        );
+        // Tracks in the input are announced
+        assert!(matches!(announced.try_recv(), Ok(StreamEvent::TrackChanged { track }) if track.title == "Two"));
+    }
+
+    #[tokio::test]
+    async fn test_pump_processes_and_flushes() {
+        let (manager, received) = recording_manager().await;
+        let metrics = Arc::new(RwLock::new(Metrics::default()));
+        let (tx, rx) = mpsc::channel(8);
+        let (_shutdown, shutdown_rx) = oneshot::channel();
+
+        tx.send(vec![0.2, 0.4]).await.unwrap();
+        tx.send(vec![0.6, 0.8]).await.unwrap();
+        drop(tx);
+        StreamPump::new(manager, OutputConfig::default())
+            .with_metrics(metrics.clone())
+            .with_processor(Box::new(HoldBack(Vec::new())))
+            .run(rx, shutdown_rx)
+            .await;
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            [
+                Received::Samples(vec![0.1]),
+                Received::Samples(vec![0.2, 0.3]),
+                Received::Samples(vec![0.4]),
+            ]
+        );
+        // Four 24-bit samples
+        assert_eq!(metrics.read().await.bytes_written, 12);
+    }
+}
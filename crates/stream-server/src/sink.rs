@@ -1,4 +1,5 @@
 use crate::types::{AudioBlock, OutputConfig};
+use aaeq_core::TrackMeta;
 use anyhow::Result;
 use async_trait::async_trait;
 
@@ -31,6 +32,14 @@ pub trait OutputSink: Send + Sync {
     fn stats(&self) -> SinkStats {
         SinkStats::default()
     }
+
+    /// The current track changed; sinks that tag or split their output use it
+    async fn set_track(&mut self, _track: &TrackMeta) -> Result<()> {
+        Ok(())
+    }
+
+    /// Start a new file on every track change, for sinks that record
+    fn set_split_tracks(&mut self, _split: bool) {}
 }
 
 /// Statistics for monitoring output sink performance
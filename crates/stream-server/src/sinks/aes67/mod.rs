@@ -0,0 +1,167 @@
+//! AES67 / RTP networking shared by the AES67 sink and input
+//!
+//! Streams are uncompressed L16 or L24 RTP, described by an SDP and announced
+//! with SAP on `239.255.255.255:9875`, which is how AES67 devices, Ravenna,
+//! Dante (in AES67 mode) and PipeWire find each other. AAEQ doesn't run PTP:
+//! timestamps follow the send clock, so receivers must accept a free-running
+//! stream (most treat `ts-refclk:ptp=traceable` that way).
+mod sap;
+mod sdp;
+
+pub use sap::{discover_streams, parse_sap_packet, sap_packet, SapAnnouncer, SapMessage, SAP_ADDR, SAP_INTERVAL};
+pub use sdp::Aes67Stream;
+
+use crate::types::SampleFormat;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+
+/// Default RTP port for AES67 streams
+pub const AES67_PORT: u16 = 5004;
+
+/// Default packet time (AES67's mandatory 1 ms)
+pub const DEFAULT_PACKET_TIME_US: u32 = 1000;
+
+/// Sample encoding on the wire (big-endian PCM)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Aes67Encoding {
+    L16,
+    #[default]
+    L24,
+}
+
+impl Aes67Encoding {
+    /// Encoding name used in the SDP `rtpmap`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Aes67Encoding::L16 => "L16",
+            Aes67Encoding::L24 => "L24",
+        }
+    }
+
+    /// Parse an `rtpmap` encoding name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "L16" => Some(Aes67Encoding::L16),
+            "L24" => Some(Aes67Encoding::L24),
+            _ => None,
+        }
+    }
+
+    /// Bytes per sample on the wire
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            Aes67Encoding::L16 => 2,
+            Aes67Encoding::L24 => 3,
+        }
+    }
+
+    /// Little-endian format with the same sample width (byte-swapped for the wire)
+    pub fn sample_format(&self) -> SampleFormat {
+        match self {
+            Aes67Encoding::L16 => SampleFormat::S16LE,
+            Aes67Encoding::L24 => SampleFormat::S24LE,
+        }
+    }
+
+    /// Decode big-endian samples to interleaved f64
+    pub fn decode(&self, payload: &[u8]) -> Vec<f64> {
+        match self {
+            Aes67Encoding::L16 => payload
+                .chunks_exact(2)
+                .map(|s| i16::from_be_bytes([s[0], s[1]]) as f64 / 32768.0)
+                .collect(),
+            Aes67Encoding::L24 => payload
+                .chunks_exact(3)
+                .map(|s| (i32::from_be_bytes([s[0], s[1], s[2], 0]) >> 8) as f64 / 8_388_608.0)
+                .collect(),
+        }
+    }
+}
+
+/// Bind a socket receiving `addr`, joining it on `interface` when it is a
+/// multicast group
+///
+/// The port is shared (`SO_REUSEADDR`) so several receivers on one host can
+/// listen to the same group.
+pub fn bind_multicast(addr: SocketAddr, interface: Ipv4Addr) -> Result<UdpSocket> {
+    let IpAddr::V4(ip) = addr.ip() else {
+        return Err(anyhow!("Only IPv4 AES67 streams are supported ({})", addr));
+    };
+
+    let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+
+    let bind_addr = if ip.is_multicast() {
+        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), addr.port())
+    } else {
+        addr
+    };
+    socket.bind(&bind_addr.into())?;
+    if ip.is_multicast() {
+        socket.join_multicast_v4(&ip, &interface)?;
+    }
+    socket.set_nonblocking(true)?;
+
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Make a sending socket use `interface` for multicast, looping packets back
+/// so receivers on this host hear them too
+pub fn set_multicast_sender(socket: &UdpSocket, interface: Ipv4Addr, ttl: u32) -> Result<()> {
+    let sock = socket2::SockRef::from(socket);
+    if !interface.is_unspecified() {
+        sock.set_multicast_if_v4(&interface)?;
+    }
+    sock.set_multicast_ttl_v4(ttl)?;
+    sock.set_multicast_loop_v4(true)?;
+    Ok(())
+}
+
+/// Address this host sends to `dest` from (for the SDP origin)
+pub fn source_address(dest: SocketAddr, interface: Ipv4Addr) -> Ipv4Addr {
+    if !interface.is_unspecified() {
+        return interface;
+    }
+    let probe = std::net::UdpSocket::bind("0.0.0.0:0").and_then(|s| {
+        s.connect(dest)?;
+        s.local_addr()
+    });
+    match probe.map(|addr| addr.ip()) {
+        Ok(IpAddr::V4(ip)) if !ip.is_unspecified() => ip,
+        _ => Ipv4Addr::LOCALHOST,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_names() {
+        assert_eq!(Aes67Encoding::from_name("l24"), Some(Aes67Encoding::L24));
+        assert_eq!(Aes67Encoding::from_name("L16"), Some(Aes67Encoding::L16));
+        assert_eq!(Aes67Encoding::from_name("L8"), None);
+        assert_eq!(Aes67Encoding::default().as_str(), "L24");
+    }
+
+    #[test]
+    fn test_decode_big_endian() {
+        assert_eq!(Aes67Encoding::L16.decode(&[0x40, 0x00, 0xC0, 0x00]), vec![0.5, -0.5]);
+        assert_eq!(
+            Aes67Encoding::L24.decode(&[0x40, 0x00, 0x00, 0xC0, 0x00, 0x00, 0x00]),
+            vec![0.5, -0.5]
+        );
+    }
+
+    #[test]
+    fn test_source_address_prefers_interface() {
+        let dest = "239.69.1.1:5004".parse().unwrap();
+        assert_eq!(source_address(dest, Ipv4Addr::new(10, 0, 0, 2)), Ipv4Addr::new(10, 0, 0, 2));
+        let local = source_address("127.0.0.1:5004".parse().unwrap(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(local, Ipv4Addr::LOCALHOST);
+    }
+}
@@ -0,0 +1,205 @@
+//! SAP (RFC 2974) announcements of AES67 streams
+use super::{bind_multicast, set_multicast_sender, Aes67Stream};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Where AES67 devices listen for announcements
+pub const SAP_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 255), 9875);
+
+/// How often a stream is re-announced
+pub const SAP_INTERVAL: Duration = Duration::from_secs(5);
+
+const SAP_VERSION: u8 = 0x20;
+const SAP_IPV6: u8 = 0x10;
+const SAP_DELETION: u8 = 0x04;
+const SAP_ENCRYPTED: u8 = 0x02;
+const SAP_COMPRESSED: u8 = 0x01;
+
+const SDP_MIME_TYPE: &str = "application/sdp";
+
+/// A received announcement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SapMessage {
+    /// The stream is going away
+    pub deletion: bool,
+    /// Message id hash; with the origin it identifies the announcement
+    pub hash: u16,
+    pub origin: IpAddr,
+    pub sdp: String,
+}
+
+/// Build a SAP packet carrying an SDP
+pub fn sap_packet(origin: Ipv4Addr, hash: u16, sdp: &str, deletion: bool) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(8 + SDP_MIME_TYPE.len() + 1 + sdp.len());
+    packet.push(SAP_VERSION | if deletion { SAP_DELETION } else { 0 });
+    // No authentication data
+    packet.push(0);
+    packet.extend_from_slice(&hash.to_be_bytes());
+    packet.extend_from_slice(&origin.octets());
+    packet.extend_from_slice(SDP_MIME_TYPE.as_bytes());
+    packet.push(0);
+    packet.extend_from_slice(sdp.as_bytes());
+    packet
+}
+
+/// Parse a SAP packet; encrypted, compressed and non-SDP payloads are ignored
+pub fn parse_sap_packet(packet: &[u8]) -> Option<SapMessage> {
+    let flags = *packet.first()?;
+    if flags >> 5 != 1 || flags & (SAP_ENCRYPTED | SAP_COMPRESSED) != 0 {
+        return None;
+    }
+
+    let auth_len = *packet.get(1)? as usize * 4;
+    let hash = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]);
+    let (origin, mut rest): (IpAddr, &[u8]) = if flags & SAP_IPV6 != 0 {
+        let octets: [u8; 16] = packet.get(4..20)?.try_into().ok()?;
+        (Ipv6Addr::from(octets).into(), packet.get(20..)?)
+    } else {
+        let octets: [u8; 4] = packet.get(4..8)?.try_into().ok()?;
+        (Ipv4Addr::from(octets).into(), packet.get(8..)?)
+    };
+    rest = rest.get(auth_len..)?;
+
+    // The payload type is optional when the payload is SDP
+    if !rest.starts_with(b"v=0") {
+        let end = rest.iter().position(|&b| b == 0)?;
+        if &rest[..end] != SDP_MIME_TYPE.as_bytes() {
+            return None;
+        }
+        rest = &rest[end + 1..];
+    }
+
+    Some(SapMessage {
+        deletion: flags & SAP_DELETION != 0,
+        hash,
+        origin,
+        sdp: String::from_utf8_lossy(rest).into_owned(),
+    })
+}
+
+/// Announces a stream every [`SAP_INTERVAL`] until stopped
+pub struct SapAnnouncer {
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl SapAnnouncer {
+    /// Start announcing `stream` to `destination` (normally [`SAP_ADDR`]) from `interface`
+    pub async fn start(stream: &Aes67Stream, interface: Ipv4Addr, destination: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind((interface, 0)).await?;
+        set_multicast_sender(&socket, interface, stream.ttl)?;
+
+        let announcement = sap_packet(stream.origin, stream.session_id as u16, &stream.to_sdp(), false);
+        let deletion = sap_packet(stream.origin, stream.session_id as u16, &stream.to_sdp(), true);
+        let (shutdown, mut shutdown_rx) = watch::channel(false);
+        let name = stream.name.clone();
+
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAP_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        if let Err(e) = socket.send_to(&announcement, destination).await {
+                            warn!("SAP announcement failed: {}", e);
+                        }
+                    }
+                    _ = shutdown_rx.changed() => break,
+                }
+            }
+            if let Err(e) = socket.send_to(&deletion, destination).await {
+                debug!("SAP deletion failed: {}", e);
+            }
+        });
+
+        info!("Announcing AES67 stream '{}' with SAP", name);
+        Ok(Self { shutdown, task })
+    }
+
+    /// Send the deletion announcement and stop
+    pub async fn stop(mut self) {
+        self.shutdown.send_replace(true);
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for SapAnnouncer {
+    fn drop(&mut self) {
+        // Still lets a running task send its deletion
+        self.shutdown.send_replace(true);
+    }
+}
+
+/// Listen for SAP announcements on `interface` and return the AES67 streams heard
+///
+/// Streams whose SDP isn't L16/L24 audio are skipped, as are ones deleted
+/// before `duration` ran out.
+pub async fn discover_streams(interface: Ipv4Addr, duration: Duration) -> Result<Vec<Aes67Stream>> {
+    let socket = bind_multicast(SAP_ADDR.into(), interface)?;
+    let mut streams: HashMap<(IpAddr, u16), Aes67Stream> = HashMap::new();
+    let mut buf = vec![0u8; 4096];
+
+    let deadline = tokio::time::Instant::now() + duration;
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await {
+        let Some(message) = received.ok().and_then(|len| parse_sap_packet(&buf[..len])) else {
+            continue;
+        };
+        let key = (message.origin, message.hash);
+        if message.deletion {
+            streams.remove(&key);
+            continue;
+        }
+        match Aes67Stream::parse_sdp(&message.sdp) {
+            Ok(stream) => {
+                streams.insert(key, stream);
+            }
+            Err(e) => debug!("Ignoring SAP announcement from {}: {:#}", message.origin, e),
+        }
+    }
+
+    let mut streams: Vec<Aes67Stream> = streams.into_values().collect();
+    streams.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(streams)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sap_packet_roundtrip() {
+        let packet = sap_packet(Ipv4Addr::new(192, 168, 1, 10), 0x1234, "v=0\r\n", false);
+        assert_eq!(&packet[..8], &[0x20, 0x00, 0x12, 0x34, 192, 168, 1, 10]);
+
+        let message = parse_sap_packet(&packet).unwrap();
+        assert!(!message.deletion);
+        assert_eq!(message.hash, 0x1234);
+        assert_eq!(message.origin, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)));
+        assert_eq!(message.sdp, "v=0\r\n");
+
+        let deletion = sap_packet(Ipv4Addr::LOCALHOST, 1, "v=0\r\n", true);
+        assert!(parse_sap_packet(&deletion).unwrap().deletion);
+    }
+
+    #[test]
+    fn test_parse_sap_variants() {
+        // No payload type, with 4 bytes of authentication data
+        let mut packet = vec![0x20, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0xAA, 0xBB, 0xCC, 0xDD];
+        packet.extend_from_slice(b"v=0\r\ns=x\r\n");
+        assert_eq!(parse_sap_packet(&packet).unwrap().sdp, "v=0\r\ns=x\r\n");
+
+        // Other payload types, encryption and compression aren't understood
+        let mut other = vec![0x20, 0x00, 0x00, 0x01, 10, 0, 0, 1];
+        other.extend_from_slice(b"application/x-other\0data");
+        assert!(parse_sap_packet(&other).is_none());
+        let mut encrypted = sap_packet(Ipv4Addr::LOCALHOST, 1, "v=0", false);
+        encrypted[0] |= SAP_ENCRYPTED;
+        assert!(parse_sap_packet(&encrypted).is_none());
+        assert!(parse_sap_packet(&[0x20, 0x00]).is_none());
+    }
+}
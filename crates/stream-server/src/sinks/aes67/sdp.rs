@@ -0,0 +1,232 @@
+//! SDP descriptions of AES67 streams
+use super::{Aes67Encoding, AES67_PORT, DEFAULT_PACKET_TIME_US};
+use crate::sinks::anp::PT_L24;
+use anyhow::{anyhow, Context, Result};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// An AES67 stream as described by its SDP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aes67Stream {
+    /// Session name (`s=`), shown by receivers
+    pub name: String,
+    /// Session id from the origin line; stays the same for the stream's life
+    pub session_id: u64,
+    /// Address of the sender (`o=`)
+    pub origin: Ipv4Addr,
+    /// Multicast group (or unicast address) and RTP port
+    pub destination: SocketAddr,
+    /// Multicast TTL (`c=` suffix)
+    pub ttl: u32,
+    pub payload_type: u8,
+    pub encoding: Aes67Encoding,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Packet time in microseconds (`a=ptime`)
+    pub packet_time_us: u32,
+}
+
+impl Aes67Stream {
+    /// A stream with AES67's defaults: L24, 48 kHz stereo, 1 ms packets
+    pub fn new(name: impl Into<String>, origin: Ipv4Addr, destination: SocketAddr) -> Self {
+        Self {
+            name: name.into(),
+            session_id: rand::random::<u32>() as u64,
+            origin,
+            destination,
+            ttl: 32,
+            payload_type: PT_L24,
+            encoding: Aes67Encoding::L24,
+            sample_rate: 48000,
+            channels: 2,
+            packet_time_us: DEFAULT_PACKET_TIME_US,
+        }
+    }
+
+    /// Frames carried by each packet
+    pub fn frames_per_packet(&self) -> usize {
+        ((self.sample_rate as u64 * self.packet_time_us as u64).div_ceil(1_000_000)).max(1) as usize
+    }
+
+    /// Payload bytes of a full packet
+    pub fn packet_bytes(&self) -> usize {
+        self.frames_per_packet() * self.channels as usize * self.encoding.bytes_per_sample()
+    }
+
+    /// Render the SDP receivers are given (directly or through SAP)
+    pub fn to_sdp(&self) -> String {
+        let connection = match self.destination.ip() {
+            IpAddr::V4(ip) if ip.is_multicast() => format!("{}/{}", ip, self.ttl),
+            ip => ip.to_string(),
+        };
+        let pt = self.payload_type;
+
+        [
+            "v=0".to_string(),
+            format!("o=- {} {} IN IP4 {}", self.session_id, self.session_id, self.origin),
+            format!("s={}", self.name),
+            format!("c=IN IP4 {}", connection),
+            "t=0 0".to_string(),
+            format!("m=audio {} RTP/AVP {}", self.destination.port(), pt),
+            format!("i={} channels", self.channels),
+            format!("a=rtpmap:{} {}/{}/{}", pt, self.encoding.as_str(), self.sample_rate, self.channels),
+            format!("a=ptime:{}", format_ptime(self.packet_time_us)),
+            "a=recvonly".to_string(),
+            "a=ts-refclk:ptp=traceable".to_string(),
+            "a=mediaclk:direct=0".to_string(),
+        ]
+        .iter()
+        .map(|line| format!("{}\r\n", line))
+        .collect()
+    }
+
+    /// Parse the first audio stream of an SDP
+    pub fn parse_sdp(sdp: &str) -> Result<Self> {
+        let mut name = String::new();
+        let mut session_id = 0;
+        let mut origin = None;
+        let mut connection: Option<(Ipv4Addr, u32)> = None;
+        let mut media: Option<(u16, u8)> = None;
+        let mut rtpmap = None;
+        let mut packet_time_us = DEFAULT_PACKET_TIME_US;
+
+        for line in sdp.lines().map(str::trim) {
+            let Some((kind, value)) = line.split_once('=') else {
+                continue;
+            };
+            match kind {
+                "o" => {
+                    let fields: Vec<&str> = value.split_whitespace().collect();
+                    session_id = fields.get(1).and_then(|id| id.parse().ok()).unwrap_or(0);
+                    origin = fields.get(5).and_then(|ip| ip.parse().ok());
+                }
+                "s" => name = value.to_string(),
+                // A media-level `c=` overrides the session one, so the last wins
+                "c" => {
+                    let address = value
+                        .strip_prefix("IN IP4 ")
+                        .ok_or_else(|| anyhow!("Unsupported connection line: {}", value))?;
+                    let (ip, ttl) = match address.split_once('/') {
+                        Some((ip, rest)) => (ip, rest.split('/').next().and_then(|t| t.parse().ok()).unwrap_or(32)),
+                        None => (address, 32),
+                    };
+                    connection = Some((ip.parse().with_context(|| format!("Invalid address {}", ip))?, ttl));
+                }
+                // Only the first audio stream is used
+                "m" if media.is_none() => {
+                    let fields: Vec<&str> = value.split_whitespace().collect();
+                    if fields.first() == Some(&"audio") {
+                        let port = fields.get(1).and_then(|p| p.split('/').next()?.parse().ok());
+                        let pt = fields.get(3).and_then(|pt| pt.parse().ok());
+                        if let (Some(port), Some(pt)) = (port, pt) {
+                            media = Some((port, pt));
+                        }
+                    }
+                }
+                "a" => {
+                    if let Some(map) = value.strip_prefix("rtpmap:") {
+                        let Some((pt, format)) = map.split_once(' ') else {
+                            continue;
+                        };
+                        if media.is_some_and(|(_, media_pt)| pt.parse() == Ok(media_pt)) {
+                            rtpmap = Some(format.to_string());
+                        }
+                    } else if let Some(ptime) = value.strip_prefix("ptime:") {
+                        if let Ok(ms) = ptime.trim().parse::<f64>() {
+                            packet_time_us = (ms * 1000.0).round() as u32;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let (port, payload_type) = media.ok_or_else(|| anyhow!("SDP has no audio stream"))?;
+        let (ip, ttl) = connection.ok_or_else(|| anyhow!("SDP has no connection address"))?;
+        let rtpmap = rtpmap.ok_or_else(|| anyhow!("SDP has no rtpmap for payload type {}", payload_type))?;
+
+        let mut format = rtpmap.split('/');
+        let encoding_name = format.next().unwrap_or_default();
+        let encoding = Aes67Encoding::from_name(encoding_name)
+            .ok_or_else(|| anyhow!("Unsupported encoding {} (L16 or L24 only)", encoding_name))?;
+        let sample_rate = format
+            .next()
+            .and_then(|rate| rate.parse().ok())
+            .ok_or_else(|| anyhow!("Invalid rtpmap: {}", rtpmap))?;
+        let channels = format.next().and_then(|ch| ch.parse().ok()).unwrap_or(1);
+
+        Ok(Self {
+            name,
+            session_id,
+            origin: origin.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            destination: SocketAddr::new(ip.into(), if port == 0 { AES67_PORT } else { port }),
+            ttl,
+            payload_type,
+            encoding,
+            sample_rate,
+            channels,
+            packet_time_us: packet_time_us.max(1),
+        })
+    }
+}
+
+/// `a=ptime` value in milliseconds, e.g. `1`, `0.125` or `0.333`
+fn format_ptime(packet_time_us: u32) -> String {
+    let ms = format!("{:.3}", packet_time_us as f64 / 1000.0);
+    ms.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sdp_roundtrip() {
+        let mut stream = Aes67Stream::new("AAEQ", Ipv4Addr::new(192, 168, 1, 10), "239.69.1.1:5004".parse().unwrap());
+        stream.encoding = Aes67Encoding::L16;
+        stream.payload_type = 97;
+        stream.packet_time_us = 125;
+
+        let sdp = stream.to_sdp();
+        assert!(sdp.contains("c=IN IP4 239.69.1.1/32\r\n"));
+        assert!(sdp.contains("a=rtpmap:97 L16/48000/2\r\n"));
+        assert!(sdp.contains("a=ptime:0.125\r\n"));
+        assert_eq!(Aes67Stream::parse_sdp(&sdp).unwrap(), stream);
+        assert_eq!(stream.frames_per_packet(), 6);
+    }
+
+    #[test]
+    fn test_parse_third_party_sdp() {
+        // As sent by a Ravenna/Dante device in AES67 mode
+        let sdp = "v=0\r\n\
+            o=- 1311738121 1311738121 IN IP4 192.168.1.41\r\n\
+            s=Stage Box 1\r\n\
+            c=IN IP4 239.69.83.133/32\r\n\
+            t=0 0\r\n\
+            a=keywds:Dante\r\n\
+            m=audio 5004 RTP/AVP 97\r\n\
+            i=8 channels: 01, 02, 03, 04, 05, 06, 07, 08\r\n\
+            a=recvonly\r\n\
+            a=rtpmap:97 L24/48000/8\r\n\
+            a=ptime:1\r\n\
+            a=ts-refclk:ptp=IEEE1588-2008:00-1D-C1-FF-FE-12-34-56:0\r\n\
+            a=mediaclk:direct=142410716\r\n";
+        let stream = Aes67Stream::parse_sdp(sdp).unwrap();
+        assert_eq!(stream.name, "Stage Box 1");
+        assert_eq!(stream.session_id, 1311738121);
+        assert_eq!(stream.origin, Ipv4Addr::new(192, 168, 1, 41));
+        assert_eq!(stream.destination, "239.69.83.133:5004".parse().unwrap());
+        assert_eq!(stream.payload_type, 97);
+        assert_eq!(stream.encoding, Aes67Encoding::L24);
+        assert_eq!(stream.channels, 8);
+        assert_eq!(stream.packet_time_us, 1000);
+        assert_eq!(stream.packet_bytes(), 48 * 8 * 3);
+    }
+
+    #[test]
+    fn test_parse_rejects_unsupported() {
+        let sdp = "v=0\nc=IN IP4 239.1.1.1/32\nm=audio 5004 RTP/AVP 10\na=rtpmap:10 AM824/48000/2\n";
+        assert!(Aes67Stream::parse_sdp(sdp).is_err());
+        assert!(Aes67Stream::parse_sdp("v=0\nc=IN IP4 239.1.1.1\n").is_err());
+        assert!(Aes67Stream::parse_sdp("v=0\nm=audio 5004 RTP/AVP 96\na=rtpmap:96 L24/48000/2\n").is_err());
+    }
+}
@@ -0,0 +1,353 @@
+use crate::convert::convert_format;
+use crate::sink::{OutputSink, SinkStats};
+use crate::sinks::aes67::{
+    set_multicast_sender, source_address, Aes67Encoding, Aes67Stream, SapAnnouncer, AES67_PORT, DEFAULT_PACKET_TIME_US,
+    SAP_ADDR,
+};
+use crate::sinks::airplay::RtpStream;
+use crate::sinks::anp::{swap_sample_bytes, SendClock, MAX_PAYLOAD_BYTES, PT_L16, PT_L24};
+use crate::types::{AudioBlock, OutputConfig};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Default multicast group (in the 239.69/16 range AES67 devices commonly use)
+pub const DEFAULT_AES67_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 69, 0, 1), AES67_PORT);
+
+/// How far ahead of the send clock packets may go out (absorbs sleep jitter)
+const MAX_AHEAD: Duration = Duration::from_millis(5);
+
+/// AES67 / RTP output sink
+///
+/// Sends L24 (or L16) RTP to a multicast group or unicast address with
+/// fixed-size packets (1 ms by default), paced to the wall clock, and
+/// announces the stream's SDP with SAP so AES67 receivers list it. Use
+/// [`Aes67Sink::sdp`] to configure receivers that don't listen to SAP.
+pub struct Aes67Sink {
+    destination: SocketAddr,
+    name: String,
+    interface: Ipv4Addr,
+    encoding: Aes67Encoding,
+    payload_type: Option<u8>,
+    packet_time_us: u32,
+    ttl: u32,
+    announce: bool,
+    sap_destination: SocketAddr,
+    session: Option<Aes67Stream>,
+    rtp: Option<RtpStream>,
+    announcer: Option<SapAnnouncer>,
+    /// Network byte order PCM not yet sent as a full packet
+    pending: Vec<u8>,
+    clock: Option<SendClock>,
+    frames_written: u64,
+    underruns: u64,
+    is_open: bool,
+}
+
+impl Aes67Sink {
+    /// Create a sink sending to a multicast group (or unicast address) and port
+    pub fn new(destination: SocketAddr) -> Self {
+        Self {
+            destination,
+            name: "AAEQ".to_string(),
+            interface: Ipv4Addr::UNSPECIFIED,
+            encoding: Aes67Encoding::default(),
+            payload_type: None,
+            packet_time_us: DEFAULT_PACKET_TIME_US,
+            ttl: 32,
+            announce: true,
+            sap_destination: SAP_ADDR.into(),
+            session: None,
+            rtp: None,
+            announcer: None,
+            pending: Vec::new(),
+            clock: None,
+            frames_written: 0,
+            underruns: 0,
+            is_open: false,
+        }
+    }
+
+    /// Session name receivers show
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Network interface (by address) to send and announce on
+    pub fn with_interface(mut self, interface: Ipv4Addr) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    /// Wire encoding (L24 by default)
+    pub fn with_encoding(mut self, encoding: Aes67Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// RTP payload type (defaults to 96 for L24, 97 for L16)
+    pub fn with_payload_type(mut self, payload_type: u8) -> Self {
+        self.payload_type = Some(payload_type & 0x7F);
+        self
+    }
+
+    /// Packet time in microseconds (AES67 defines 125, 250, 333, 1000 and 4000)
+    pub fn with_packet_time_us(mut self, packet_time_us: u32) -> Self {
+        self.packet_time_us = packet_time_us.max(1);
+        self
+    }
+
+    /// Multicast TTL
+    pub fn with_ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Announce the stream with SAP (on by default)
+    pub fn with_announce(mut self, announce: bool) -> Self {
+        self.announce = announce;
+        self
+    }
+
+    /// Where SAP announcements go (defaults to [`SAP_ADDR`])
+    pub fn with_sap_destination(mut self, destination: SocketAddr) -> Self {
+        self.sap_destination = destination;
+        self
+    }
+
+    /// Stream being sent, while open
+    pub fn session(&self) -> Option<&Aes67Stream> {
+        self.session.as_ref()
+    }
+
+    /// SDP of the stream being sent, while open
+    pub fn sdp(&self) -> Option<String> {
+        self.session.as_ref().map(Aes67Stream::to_sdp)
+    }
+
+    /// Send one packet, pacing to the wall clock
+    async fn send_packet(&mut self, payload: &[u8], frames: u32) -> Result<()> {
+        let (Some(clock), Some(rtp)) = (self.clock.as_mut(), self.rtp.as_mut()) else {
+            return Err(anyhow!("Sink not open"));
+        };
+
+        if clock.pace(MAX_AHEAD).await {
+            debug!("AES67 stream fell behind, resyncing send clock");
+            self.underruns += 1;
+        }
+
+        rtp.send_packet(payload, frames).await?;
+        clock.sent(frames as u64);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OutputSink for Aes67Sink {
+    fn name(&self) -> &'static str {
+        "aes67"
+    }
+
+    async fn open(&mut self, cfg: OutputConfig) -> Result<()> {
+        info!("Opening AES67 sink to {}: {:?}", self.destination, cfg);
+
+        if !matches!(cfg.sample_rate, 44100 | 48000 | 96000) {
+            warn!("{} Hz isn't an AES67 rate; receivers may reject the stream", cfg.sample_rate);
+        }
+
+        let origin = source_address(self.destination, self.interface);
+        let mut session = Aes67Stream::new(self.name.clone(), origin, self.destination);
+        session.ttl = self.ttl;
+        session.encoding = self.encoding;
+        session.payload_type = self.payload_type.unwrap_or(match self.encoding {
+            Aes67Encoding::L16 => PT_L16,
+            Aes67Encoding::L24 => PT_L24,
+        });
+        session.sample_rate = cfg.sample_rate;
+        session.channels = cfg.channels;
+        session.packet_time_us = self.packet_time_us;
+        if session.packet_bytes() > MAX_PAYLOAD_BYTES {
+            return Err(anyhow!(
+                "{} channels at {} Hz don't fit a {} us packet in the MTU",
+                cfg.channels,
+                cfg.sample_rate,
+                self.packet_time_us
+            ));
+        }
+
+        let rtp = RtpStream::new(0, self.destination)
+            .await?
+            .with_payload_type(session.payload_type);
+        set_multicast_sender(rtp.socket(), self.interface, self.ttl)?;
+
+        self.announcer = if self.announce {
+            Some(SapAnnouncer::start(&session, self.interface, self.sap_destination).await?)
+        } else {
+            None
+        };
+
+        info!(
+            "AES67 sink streaming {} {}/{}/{} to {} ({} frames per packet)",
+            session.name,
+            session.encoding.as_str(),
+            session.sample_rate,
+            session.channels,
+            self.destination,
+            session.frames_per_packet()
+        );
+        self.session = Some(session);
+        self.rtp = Some(rtp);
+        self.pending.clear();
+        self.clock = Some(SendClock::new(cfg.sample_rate));
+        self.frames_written = 0;
+        self.underruns = 0;
+        self.is_open = true;
+        Ok(())
+    }
+
+    async fn write(&mut self, block: AudioBlock<'_>) -> Result<()> {
+        let Some(session) = self.session.as_ref().filter(|_| self.is_open) else {
+            return Err(anyhow!("Sink not open"));
+        };
+        let frames_per_packet = session.frames_per_packet();
+        let packet_bytes = session.packet_bytes();
+
+        let mut pcm = Vec::new();
+        convert_format(block, self.encoding.sample_format(), &mut pcm)?;
+        self.frames_written += block.num_frames() as u64;
+
+        // RTP carries samples in network byte order
+        self.pending.extend(swap_sample_bytes(&pcm, self.encoding.bytes_per_sample()));
+
+        while self.pending.len() >= packet_bytes {
+            let payload: Vec<u8> = self.pending.drain(..packet_bytes).collect();
+            self.send_packet(&payload, frames_per_packet as u32).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn drain(&mut self) -> Result<()> {
+        let Some(session) = self.session.as_ref().filter(|_| self.is_open && !self.pending.is_empty()) else {
+            return Ok(());
+        };
+
+        // Receivers expect every packet to be the announced size
+        let mut payload = std::mem::take(&mut self.pending);
+        payload.resize(session.packet_bytes(), 0);
+        let frames = session.frames_per_packet() as u32;
+        self.send_packet(&payload, frames).await
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        debug!("Closing AES67 sink");
+
+        let _ = self.drain().await;
+
+        if let Some(announcer) = self.announcer.take() {
+            announcer.stop().await;
+        }
+        self.rtp = None;
+        self.session = None;
+        self.pending.clear();
+        self.is_open = false;
+
+        info!("AES67 sink closed");
+        Ok(())
+    }
+
+    fn latency_ms(&self) -> u32 {
+        (self.packet_time_us / 1000 + MAX_AHEAD.as_millis() as u32).max(1)
+    }
+
+    fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+    fn stats(&self) -> SinkStats {
+        SinkStats {
+            frames_written: self.frames_written,
+            underruns: self.underruns,
+            overruns: 0,
+            buffer_fill: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::airplay::parse_rtp_packet;
+    use crate::types::SampleFormat;
+    use tokio::net::UdpSocket;
+
+    fn config(sample_rate: u32, channels: u16) -> OutputConfig {
+        OutputConfig {
+            sample_rate,
+            channels,
+            format: SampleFormat::S24LE,
+            buffer_ms: 50,
+            exclusive: false,
+        }
+    }
+
+    #[test]
+    fn test_aes67_sink_create() {
+        let sink = Aes67Sink::new(DEFAULT_AES67_GROUP.into()).with_packet_time_us(4000);
+        assert_eq!(sink.name(), "aes67");
+        assert!(!sink.is_open());
+        assert_eq!(sink.latency_ms(), 9);
+        assert!(sink.sdp().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sends_full_big_endian_packets() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut sink = Aes67Sink::new(receiver.local_addr().unwrap()).with_announce(false);
+        sink.open(config(48000, 2)).await.unwrap();
+
+        let sdp = sink.sdp().unwrap();
+        assert!(sdp.contains("a=rtpmap:96 L24/48000/2\r\n"));
+        assert!(sdp.contains("o=- "));
+        assert!(sdp.contains(" IN IP4 127.0.0.1\r\n"));
+
+        // 1.5 packets: one goes out now, the rest padded on drain
+        let samples: Vec<f64> = (0..72).flat_map(|_| [0.5, -0.5]).collect();
+        sink.write(AudioBlock::new(&samples, 48000, 2)).await.unwrap();
+        sink.drain().await.unwrap();
+
+        let mut buf = [0u8; 1500];
+        let len = receiver.recv(&mut buf).await.unwrap();
+        let (payload_type, first) = parse_rtp_packet(&buf[..len]).unwrap();
+        assert_eq!(payload_type, 96);
+        assert_eq!(first.payload.len(), 48 * 2 * 3);
+        let decoded = Aes67Encoding::L24.decode(&first.payload[..6]);
+        assert!((decoded[0] - 0.5).abs() < 1e-6 && (decoded[1] + 0.5).abs() < 1e-6);
+        let (first_seq, first_ts) = (first.sequence_number, first.timestamp);
+
+        let len = receiver.recv(&mut buf).await.unwrap();
+        let (_, second) = parse_rtp_packet(&buf[..len]).unwrap();
+        assert_eq!(second.sequence_number, first_seq.wrapping_add(1));
+        assert_eq!(second.timestamp, first_ts.wrapping_add(48));
+        assert_eq!(second.payload.len(), 48 * 2 * 3);
+        assert!(second.payload[24 * 6..].iter().all(|&b| b == 0));
+
+        assert_eq!(sink.stats().frames_written, 72);
+        sink.close().await.unwrap();
+        assert!(!sink.is_open());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_packets_over_mtu() {
+        let mut sink = Aes67Sink::new("127.0.0.1:5004".parse().unwrap())
+            .with_announce(false)
+            .with_packet_time_us(4000);
+        assert!(sink.open(config(96000, 2)).await.is_err());
+
+        let frames = vec![0.0; 96];
+        assert!(sink.write(AudioBlock::new(&frames, 48000, 2)).await.is_err());
+    }
+}
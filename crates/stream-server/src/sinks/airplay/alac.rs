@@ -1,8 +1,7 @@
-use anyhow::Result;
-use tracing::warn;
+use anyhow::{anyhow, Result};
 
 /// ALAC encoder configuration
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct AlacConfig {
     pub sample_rate: u32,
     pub channels: u16,
@@ -21,14 +20,33 @@ impl Default for AlacConfig {
     }
 }
 
-/// ALAC encoder
-///
-/// Note: This is a simplified implementation. For production use, you should:
-/// 1. Use Apple's official ALAC encoder (requires FFI to C library)
-/// 2. Use FFmpeg's ALAC encoder
-/// 3. Implement a pure Rust ALAC encoder (significant effort)
+/// Rice history multiplier (`pb`) advertised in the fmtp/magic cookie
+const RICE_HISTORY_MULT: u32 = 40;
+/// Initial Rice history (`mb`) advertised in the fmtp/magic cookie
+const RICE_INITIAL_HISTORY: u32 = 10;
+/// Rice parameter limit (`kb`) advertised in the fmtp/magic cookie
+const RICE_PARAM_LIMIT: u32 = 14;
+/// Maximum zero run advertised in the fmtp/magic cookie
+const MAX_RUN: u16 = 255;
+
+/// Prediction quantization shift (coefficients are Q9)
+const LPC_SHIFT: u32 = 9;
+/// LPC orders tried per channel (0 disables prediction)
+const LPC_ORDERS: [usize; 2] = [4, 8];
+
+/// Stereo mixing candidates as (shift, weight); weight 0 means independent channels
+const MIX_CANDIDATES: [(u32, i32); 2] = [(0, 0), (1, 1)];
+
+const ELEMENT_SCE: u64 = 0;
+const ELEMENT_CPE: u64 = 1;
+const ELEMENT_END: u64 = 7;
+
+/// Pure-Rust ALAC (Apple Lossless) encoder
 ///
-/// This implementation provides PCM passthrough with ALAC framing for testing.
+/// Each packet is encoded as a single ALAC frame: adaptive LPC prediction (the
+/// same sign-LMS adaptation the decoder runs), optional stereo mid/side mixing and
+/// adaptive Golomb-Rice residual coding. If compression doesn't help, the frame
+/// falls back to the uncompressed (escape) form, which is still valid ALAC.
 pub struct AlacEncoder {
     config: AlacConfig,
     frames_buffered: Vec<i16>,
@@ -48,18 +66,39 @@ impl AlacEncoder {
         // <rice history mult> <rice initial history> <rice parameter limit>
         // <num channels> <max run> <max coded frame size> <average bit rate> <sample rate>
         format!(
-            "{} 0 {} 40 10 14 {} 255 0 0 {}",
+            "{} 0 {} {} {} {} {} {} 0 0 {}",
             self.config.frames_per_packet,
             self.config.bit_depth,
+            RICE_HISTORY_MULT,
+            RICE_INITIAL_HISTORY,
+            RICE_PARAM_LIMIT,
             self.config.channels,
+            MAX_RUN,
             self.config.sample_rate
         )
     }
 
+    /// ALAC magic cookie (`ALACSpecificConfig`), as used in MP4/CAF containers
+    pub fn magic_cookie(&self) -> Vec<u8> {
+        let mut cookie = Vec::with_capacity(24);
+        cookie.extend_from_slice(&self.config.frames_per_packet.to_be_bytes());
+        cookie.push(0); // compatible version
+        cookie.push(self.config.bit_depth);
+        cookie.push(RICE_HISTORY_MULT as u8);
+        cookie.push(RICE_INITIAL_HISTORY as u8);
+        cookie.push(RICE_PARAM_LIMIT as u8);
+        cookie.push(self.config.channels as u8);
+        cookie.extend_from_slice(&MAX_RUN.to_be_bytes());
+        cookie.extend_from_slice(&0u32.to_be_bytes()); // max frame bytes (unknown)
+        cookie.extend_from_slice(&0u32.to_be_bytes()); // average bit rate (unknown)
+        cookie.extend_from_slice(&self.config.sample_rate.to_be_bytes());
+        cookie
+    }
+
     /// Encode PCM samples to ALAC
     ///
     /// Input: Interleaved 16-bit PCM samples
-    /// Output: ALAC-encoded packets (currently just framed PCM as a stub)
+    /// Output: One ALAC frame per complete packet of `frames_per_packet` frames
     pub fn encode(&mut self, pcm_data: &[i16]) -> Result<Vec<Vec<u8>>> {
         // Add samples to buffer
         self.frames_buffered.extend_from_slice(pcm_data);
@@ -69,68 +108,107 @@ impl AlacEncoder {
 
         // Process complete packets
         while self.frames_buffered.len() >= samples_per_packet {
-            let packet_samples: Vec<i16> = self.frames_buffered.drain(..samples_per_packet).collect();
+            let packet_samples: Vec<i32> = self
+                .frames_buffered
+                .drain(..samples_per_packet)
+                .map(i32::from)
+                .collect();
 
-            // Create ALAC packet
-            // Note: This is a simplified stub. Real ALAC encoding involves:
-            // - Adaptive Rice coding
-            // - LPC prediction
-            // - Frame header construction
-            // - Checksum calculation
-
-            let packet = self.create_alac_packet(&packet_samples)?;
-            packets.push(packet);
+            packets.push(self.encode_frame(&packet_samples)?);
         }
 
         Ok(packets)
     }
 
-    /// Create an ALAC packet from PCM samples
-    /// This is a stub implementation that creates a simple frame structure
-    fn create_alac_packet(&self, samples: &[i16]) -> Result<Vec<u8>> {
-        warn!(
-            "Using stub ALAC encoder - audio will be PCM, not true ALAC compression"
-        );
-
-        // Simple packet structure:
-        // - 4 bytes: packet size
-        // - 1 byte: channels
-        // - 2 bytes: unused
-        // - 1 byte: hasSize flag
-        // - 4 bytes: unused
-        // - 4 bytes: frame length
-        // - PCM data (big-endian 16-bit)
-
-        let frame_length = samples.len() / self.config.channels as usize;
-        let packet_size = 16 + (samples.len() * 2); // Header + data
-
-        let mut packet = Vec::with_capacity(packet_size);
-
-        // Packet size (big-endian)
-        packet.extend_from_slice(&(packet_size as u32).to_be_bytes());
-
-        // Channels
-        packet.push(self.config.channels as u8);
-
-        // Unused
-        packet.push(0);
-        packet.push(0);
+    /// Encode one frame of interleaved samples (at the configured bit depth)
+    ///
+    /// Frames shorter than `frames_per_packet` are marked as partial.
+    pub fn encode_frame(&self, samples: &[i32]) -> Result<Vec<u8>> {
+        let channels = self.config.channels as usize;
+        if channels == 0 || channels > 2 {
+            return Err(anyhow!("ALAC encoder supports mono or stereo, got {} channels", channels));
+        }
+        if !(8..=32).contains(&self.config.bit_depth) {
+            return Err(anyhow!("Unsupported ALAC bit depth: {}", self.config.bit_depth));
+        }
 
-        // hasSize flag
-        packet.push(1);
+        let num_frames = samples.len() / channels;
+        let partial = num_frames != self.config.frames_per_packet as usize;
+        let bit_depth = self.config.bit_depth as u32;
 
-        // Unused
-        packet.extend_from_slice(&[0u8; 4]);
+        // De-interleave
+        let mut planes = vec![Vec::with_capacity(num_frames); channels];
+        for frame in samples.chunks_exact(channels) {
+            for (ch, &s) in frame.iter().enumerate() {
+                planes[ch].push(s);
+            }
+        }
 
-        // Frame length (big-endian)
-        packet.extend_from_slice(&(frame_length as u32).to_be_bytes());
+        let mut w = BitWriter::new();
+        let element = if channels == 2 { ELEMENT_CPE } else { ELEMENT_SCE };
+
+        let compressed = if channels == 2 {
+            MIX_CANDIDATES
+                .iter()
+                .map(|&(mix_shift, mix_weight)| {
+                    let (u, v) = mix_stereo(&planes[0], &planes[1], mix_shift, mix_weight);
+                    let pred_bits = bit_depth + 1;
+                    let ch0 = encode_channel(&u, pred_bits);
+                    let ch1 = encode_channel(&v, pred_bits);
+                    (mix_shift, mix_weight, vec![ch0, ch1])
+                })
+                .min_by_key(|(_, _, chs)| chs.iter().map(EncodedChannel::cost_bits).sum::<usize>())
+                .unwrap()
+        } else {
+            (0, 0, vec![encode_channel(&planes[0], bit_depth)])
+        };
 
-        // PCM data (convert to big-endian)
-        for &sample in samples {
-            packet.extend_from_slice(&sample.to_be_bytes());
+        let compressed_bits: usize = compressed.2.iter().map(EncodedChannel::cost_bits).sum();
+        let uncompressed_bits = samples.len() * bit_depth as usize;
+
+        // Element header
+        w.write(element, 3);
+        w.write(0, 4); // element instance tag
+        w.write(0, 12); // unused
+        w.write(partial as u64, 1);
+        w.write(0, 2); // no shifted-out low bytes
+
+        if compressed_bits < uncompressed_bits {
+            w.write(0, 1); // compressed
+            if partial {
+                w.write(num_frames as u64, 32);
+            }
+
+            let (mix_shift, mix_weight, channels) = compressed;
+            w.write(mix_shift as u64, 8);
+            w.write((mix_weight as u8) as u64, 8);
+
+            for ch in &channels {
+                w.write(0, 4); // prediction mode
+                w.write(LPC_SHIFT as u64, 4);
+                w.write(4, 3); // pb factor (4 => full history multiplier)
+                w.write(ch.coefs.len() as u64, 5);
+                for &c in &ch.coefs {
+                    w.write((c as u16) as u64, 16);
+                }
+            }
+
+            for ch in &channels {
+                w.append(&ch.bits);
+            }
+        } else {
+            w.write(1, 1); // uncompressed escape
+            if partial {
+                w.write(num_frames as u64, 32);
+            }
+            let mask = if bit_depth == 32 { u32::MAX as u64 } else { (1u64 << bit_depth) - 1 };
+            for &s in samples {
+                w.write(s as u64 & mask, bit_depth);
+            }
         }
 
-        Ok(packet)
+        w.write(ELEMENT_END, 3);
+        Ok(w.into_bytes())
     }
 
     /// Flush any remaining buffered samples
@@ -139,7 +217,7 @@ impl AlacEncoder {
             return Ok(Vec::new());
         }
 
-        // Pad with zeros to complete packet
+        // Pad with zeros to complete packet (RAOP receivers expect fixed-size packets)
         let samples_per_packet = self.config.frames_per_packet as usize * self.config.channels as usize;
         while self.frames_buffered.len() < samples_per_packet {
             self.frames_buffered.push(0);
@@ -147,6 +225,292 @@ impl AlacEncoder {
 
         self.encode(&[])
     }
+
+    /// Drop buffered samples without encoding them
+    pub fn reset(&mut self) {
+        self.frames_buffered.clear();
+    }
+}
+
+/// Apply ALAC stereo mixing, inverse of the decoder's mid/side decorrelation
+fn mix_stereo(left: &[i32], right: &[i32], shift: u32, weight: i32) -> (Vec<i32>, Vec<i32>) {
+    if weight == 0 {
+        return (left.to_vec(), right.to_vec());
+    }
+
+    left.iter()
+        .zip(right)
+        .map(|(&l, &r)| {
+            let v = l - r;
+            let u = r + ((v * weight) >> shift);
+            (u, v)
+        })
+        .unzip()
+}
+
+/// Encoded residuals and the initial predictor coefficients for one channel
+struct EncodedChannel {
+    coefs: Vec<i32>,
+    bits: BitWriter,
+}
+
+impl EncodedChannel {
+    /// Size in bits including the 16-bit coefficients in the channel header
+    fn cost_bits(&self) -> usize {
+        self.bits.len_bits() + 16 * self.coefs.len()
+    }
+}
+
+/// Pick the best predictor for a channel and Rice-code its residuals
+fn encode_channel(samples: &[i32], pred_bits: u32) -> EncodedChannel {
+    let n = samples.len();
+
+    LPC_ORDERS
+        .iter()
+        .filter(|&&order| order + 1 < n)
+        .map(|&order| lpc_coefficients(samples, order))
+        .chain(std::iter::once(Vec::new()))
+        .map(|coefs| {
+            let residual = predict_residual(samples, &coefs, pred_bits);
+            let mut bits = BitWriter::new();
+            write_residuals(&mut bits, &residual, pred_bits);
+            EncodedChannel { coefs, bits }
+        })
+        .min_by_key(EncodedChannel::cost_bits)
+        .unwrap()
+}
+
+/// Compute Q9 LPC coefficients via autocorrelation and Levinson-Durbin
+fn lpc_coefficients(samples: &[i32], order: usize) -> Vec<i32> {
+    let n = samples.len();
+    let x: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+
+    let mut autocorr = vec![0.0f64; order + 1];
+    for (lag, r) in autocorr.iter_mut().enumerate() {
+        *r = (lag..n).map(|i| x[i] * x[i - lag]).sum();
+    }
+
+    let mut a = vec![0.0f64; order + 1];
+    if autocorr[0] > 0.0 {
+        let mut error = autocorr[0] * (1.0 + 1e-9);
+        for i in 1..=order {
+            let mut acc = autocorr[i];
+            for j in 1..i {
+                acc -= a[j] * autocorr[i - j];
+            }
+            let k = acc / error;
+
+            let prev = a.clone();
+            a[i] = k;
+            for j in 1..i {
+                a[j] = prev[j] - k * prev[i - j];
+            }
+
+            error *= 1.0 - k * k;
+            if error <= 0.0 {
+                break;
+            }
+        }
+    }
+
+    a[1..]
+        .iter()
+        .map(|&c| (c * (1 << LPC_SHIFT) as f64).round().clamp(-32768.0, 32767.0) as i32)
+        .collect()
+}
+
+/// Sign-extend the low `bits` bits of a value
+fn clip_msbs(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    value.wrapping_shl(shift).wrapping_shr(shift)
+}
+
+/// Compute prediction residuals, mirroring the decoder's adaptive predictor
+fn predict_residual(samples: &[i32], initial_coefs: &[i32], pred_bits: u32) -> Vec<i32> {
+    let n = samples.len();
+    let order = initial_coefs.len();
+    let x = samples;
+
+    if order == 0 {
+        return x.iter().map(|&s| clip_msbs(s, pred_bits)).collect();
+    }
+
+    let mut coefs = initial_coefs.to_vec();
+    let mut residual = Vec::with_capacity(n);
+
+    residual.push(x[0]);
+    for i in 1..=order.min(n - 1) {
+        residual.push(clip_msbs(x[i].wrapping_sub(x[i - 1]), pred_bits));
+    }
+
+    for i in order + 1..n {
+        let past0 = x[i - order - 1];
+        let window = &x[i - order..i];
+
+        let sum = coefs
+            .iter()
+            .rev()
+            .zip(window)
+            .map(|(&c, &s)| c.wrapping_mul(s.wrapping_sub(past0)))
+            .fold(0i32, |acc, v| acc.wrapping_add(v));
+        let prediction = sum.wrapping_add((1 << LPC_SHIFT) >> 1) >> LPC_SHIFT;
+
+        let res = clip_msbs(x[i].wrapping_sub(past0).wrapping_sub(prediction), pred_bits);
+        residual.push(res);
+
+        // Sign-LMS coefficient adaptation, identical to the decoder
+        let mut err = res;
+        if err > 0 {
+            for (j, (c, &s)) in coefs.iter_mut().rev().zip(window).enumerate() {
+                let val = past0.wrapping_sub(s);
+                let sign = val.signum();
+                *c -= sign;
+                err -= (1 + j as i32) * ((sign * val) >> LPC_SHIFT);
+                if err <= 0 {
+                    break;
+                }
+            }
+        } else if err < 0 {
+            for (j, (c, &s)) in coefs.iter_mut().rev().zip(window).enumerate() {
+                let val = past0.wrapping_sub(s);
+                let sign = val.signum();
+                *c += sign;
+                err -= (1 + j as i32) * ((-sign * val) >> LPC_SHIFT);
+                if err >= 0 {
+                    break;
+                }
+            }
+        }
+    }
+
+    residual
+}
+
+/// `lg3a` from the reference implementation: floor(log2((history >> 9) + 3))
+fn lg3a(history: u32) -> u32 {
+    31 - ((history >> 9) + 3).leading_zeros()
+}
+
+/// Write residuals using ALAC's adaptive Golomb-Rice coding with zero runs
+fn write_residuals(w: &mut BitWriter, residual: &[i32], pred_bits: u32) {
+    let n = residual.len();
+    let mut history = RICE_INITIAL_HISTORY;
+    let mut sign_toggle = 0u32;
+    let mut i = 0usize;
+
+    while i < n {
+        let k = lg3a(history).min(RICE_PARAM_LIMIT);
+        let folded = ((residual[i] << 1) ^ (residual[i] >> 31)) as u32;
+        let code = folded - sign_toggle;
+        write_rice(w, code, k, pred_bits);
+
+        let value = code + sign_toggle;
+        if value > 0xffff {
+            history = 0xffff;
+        } else {
+            history -= (RICE_HISTORY_MULT * history) >> 9;
+            history += RICE_HISTORY_MULT * value;
+        }
+
+        sign_toggle = 0;
+        i += 1;
+
+        // Low history switches to run-length coding of zeros
+        if history < 128 && i < n {
+            let k = (history.leading_zeros() - 24 + ((history + 16) >> 6)).min(RICE_PARAM_LIMIT);
+
+            let mut zeros = 0u32;
+            while i + (zeros as usize) < n && residual[i + zeros as usize] == 0 && zeros < 0xffff {
+                zeros += 1;
+            }
+
+            write_rice(w, zeros, k, 16);
+            if zeros < 0xffff {
+                sign_toggle = 1;
+            }
+
+            history = 0;
+            i += zeros as usize;
+        }
+    }
+}
+
+/// Write one adaptive Golomb code (`k` >= 1), escaping to `escape_bits` raw bits
+fn write_rice(w: &mut BitWriter, value: u32, k: u32, escape_bits: u32) {
+    let m = (1u32 << k) - 1;
+    let q = value / m;
+
+    if q >= 9 {
+        w.write(0x1FF, 9);
+        w.write(value as u64, escape_bits);
+        return;
+    }
+
+    // Unary prefix: q one bits terminated by a zero
+    w.write(((1u64 << q) - 1) << 1, q + 1);
+
+    if k > 1 {
+        let r = value % m;
+        if r == 0 {
+            w.write(0, k - 1);
+        } else {
+            w.write((r + 1) as u64, k);
+        }
+    }
+}
+
+/// MSB-first bit writer
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            acc: 0,
+            nbits: 0,
+        }
+    }
+
+    /// Write the low `bits` bits of `value` (up to 32 bits at a time)
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+
+        let mask = (1u64 << bits) - 1;
+        self.acc = (self.acc << bits) | (value & mask);
+        self.nbits += bits;
+
+        while self.nbits >= 8 {
+            self.nbits -= 8;
+            self.bytes.push((self.acc >> self.nbits) as u8);
+        }
+        self.acc &= (1u64 << self.nbits) - 1;
+    }
+
+    /// Append all bits written to another writer
+    fn append(&mut self, other: &BitWriter) {
+        for &b in &other.bytes {
+            self.write(b as u64, 8);
+        }
+        self.write(other.acc, other.nbits);
+    }
+
+    fn len_bits(&self) -> usize {
+        self.bytes.len() * 8 + self.nbits as usize
+    }
+
+    /// Pad with zero bits to a byte boundary and return the bytes
+    fn into_bytes(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            self.write(0, 8 - self.nbits);
+        }
+        self.bytes
+    }
 }
 
 /// Convert f64 PCM samples to i16
@@ -157,9 +521,85 @@ pub fn f64_to_i16(samples: &[f64]) -> Vec<i16> {
         .collect()
 }
 
+/// Parse the SDP `fmtp` of an ALAC stream into its config and magic cookie
+///
+/// Takes the fields written by [`AlacEncoder::fmtp_string`], optionally
+/// preceded by the payload type as in `a=fmtp:96 352 0 16 ...`.
+pub fn parse_fmtp(fmtp: &str) -> Result<(AlacConfig, Vec<u8>)> {
+    let fields: Vec<u32> = fmtp
+        .split_whitespace()
+        .map(|f| f.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow!("Invalid ALAC fmtp: {}", fmtp))?;
+    let fields = match fields.len() {
+        11 => &fields[..],
+        12 => &fields[1..],
+        n => return Err(anyhow!("ALAC fmtp has {} fields, expected 11", n)),
+    };
+
+    let mut cookie = Vec::with_capacity(24);
+    cookie.extend_from_slice(&fields[0].to_be_bytes());
+    cookie.extend(fields[1..7].iter().map(|&f| f as u8));
+    cookie.extend_from_slice(&(fields[7] as u16).to_be_bytes());
+    for &f in &fields[8..11] {
+        cookie.extend_from_slice(&f.to_be_bytes());
+    }
+
+    let config = AlacConfig {
+        sample_rate: fields[10],
+        channels: fields[6] as u16,
+        bit_depth: fields[2] as u8,
+        frames_per_packet: fields[0],
+    };
+    Ok((config, cookie))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use symphonia_codec_alac::AlacDecoder;
+    use symphonia_core::audio::{AudioBufferRef, Signal};
+    use symphonia_core::codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_ALAC};
+    use symphonia_core::formats::Packet;
+
+    /// Decode ALAC packets with an independent decoder, returning interleaved samples
+    fn decode_packets(encoder: &AlacEncoder, packets: &[Vec<u8>]) -> Vec<i32> {
+        let mut params = CodecParameters::new();
+        params
+            .for_codec(CODEC_TYPE_ALAC)
+            .with_extra_data(encoder.magic_cookie().into_boxed_slice());
+        let mut decoder = AlacDecoder::try_new(&params, &DecoderOptions::default()).unwrap();
+
+        let shift = 32 - encoder.config.bit_depth as u32;
+        let mut out = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            let packet = Packet::new_from_slice(0, i as u64, 0, packet);
+            match decoder.decode(&packet).unwrap() {
+                AudioBufferRef::S32(buf) => {
+                    for frame in 0..buf.frames() {
+                        for ch in 0..buf.spec().channels.count() {
+                            out.push(buf.chan(ch)[frame] >> shift);
+                        }
+                    }
+                }
+                _ => panic!("unexpected ALAC sample format"),
+            }
+        }
+        out
+    }
+
+    fn music_like_signal(frames: usize) -> Vec<i16> {
+        let mut samples = Vec::with_capacity(frames * 2);
+        for i in 0..frames {
+            let t = i as f64 / 44100.0;
+            let left = 0.5 * (2.0 * std::f64::consts::PI * 440.0 * t).sin()
+                + 0.2 * (2.0 * std::f64::consts::PI * 3000.0 * t).sin();
+            let right = 0.4 * (2.0 * std::f64::consts::PI * 660.0 * t).sin();
+            samples.push((left * 20000.0) as i16 + fastrand::i16(-4..=4));
+            samples.push((right * 20000.0) as i16 + fastrand::i16(-4..=4));
+        }
+        samples
+    }
 
     #[test]
     fn test_alac_config_default() {
@@ -175,10 +615,7 @@ mod tests {
         let encoder = AlacEncoder::new(config);
         let fmtp = encoder.fmtp_string();
 
-        assert!(fmtp.contains("352")); // frames per packet
-        assert!(fmtp.contains("16")); // bit depth
-        assert!(fmtp.contains("2")); // channels
-        assert!(fmtp.contains("44100")); // sample rate
+        assert_eq!(fmtp, "352 0 16 40 10 14 2 255 0 0 44100");
     }
 
     #[test]
@@ -224,4 +661,88 @@ mod tests {
         // Should not produce any packets yet
         assert_eq!(packets.len(), 0);
     }
+
+    #[test]
+    fn test_roundtrip_music_compresses() {
+        let mut encoder = AlacEncoder::new(AlacConfig::default());
+        let input = music_like_signal(352 * 20);
+
+        let packets = encoder.encode(&input).unwrap();
+        assert_eq!(packets.len(), 20);
+
+        let decoded = decode_packets(&encoder, &packets);
+        let expected: Vec<i32> = input.iter().map(|&s| s as i32).collect();
+        assert_eq!(decoded, expected);
+
+        // Real compression, not PCM passthrough
+        let encoded_bytes: usize = packets.iter().map(|p| p.len()).sum();
+        assert!(encoded_bytes < input.len() * 2 * 3 / 4);
+    }
+
+    #[test]
+    fn test_roundtrip_silence_and_noise() {
+        let mut encoder = AlacEncoder::new(AlacConfig::default());
+
+        // Silence exercises zero runs, full-scale noise exercises the escape path
+        let mut input = vec![0i16; 352 * 2 * 2];
+        input.extend((0..352 * 2).map(|_| fastrand::i16(..)));
+        input.extend((0..352).flat_map(|i| [i as i16, 0]));
+
+        let packets = encoder.encode(&input).unwrap();
+        let decoded = decode_packets(&encoder, &packets);
+        let expected: Vec<i32> = input.iter().map(|&s| s as i32).collect();
+        assert_eq!(decoded, expected);
+
+        // Silent packets should be tiny
+        assert!(packets[0].len() < 32);
+    }
+
+    #[test]
+    fn test_roundtrip_partial_frame() {
+        let encoder = AlacEncoder::new(AlacConfig::default());
+        let input: Vec<i32> = music_like_signal(100).iter().map(|&s| s as i32).collect();
+
+        let packet = encoder.encode_frame(&input).unwrap();
+        let decoded = decode_packets(&encoder, &[packet]);
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn test_roundtrip_24bit_mono() {
+        let config = AlacConfig {
+            sample_rate: 96000,
+            channels: 1,
+            bit_depth: 24,
+            frames_per_packet: 4096,
+        };
+        let encoder = AlacEncoder::new(config);
+        let input: Vec<i32> = (0..4096)
+            .map(|i| ((i as f64 * 0.01).sin() * 8_000_000.0) as i32 + fastrand::i32(-50..=50))
+            .collect();
+
+        let packet = encoder.encode_frame(&input).unwrap();
+        assert!(packet.len() < input.len() * 3);
+
+        let decoded = decode_packets(&encoder, &[packet]);
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn test_parse_fmtp_matches_magic_cookie() {
+        let encoder = AlacEncoder::new(AlacConfig {
+            sample_rate: 48000,
+            ..Default::default()
+        });
+
+        let (config, cookie) = parse_fmtp(&encoder.fmtp_string()).unwrap();
+        assert_eq!(cookie, encoder.magic_cookie());
+        assert_eq!(config.sample_rate, 48000);
+        assert_eq!(config.channels, 2);
+        assert_eq!(config.bit_depth, 16);
+        assert_eq!(config.frames_per_packet, 352);
+
+        let (_, with_payload_type) = parse_fmtp(&format!("96 {}", encoder.fmtp_string())).unwrap();
+        assert_eq!(with_payload_type, cookie);
+        assert!(parse_fmtp("352 0 16").is_err());
+    }
 }
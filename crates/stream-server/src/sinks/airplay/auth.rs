@@ -1,18 +1,33 @@
-use anyhow::Result;
+use aes::cipher::{block_padding::NoPadding, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use anyhow::{anyhow, Result};
 use base64::Engine;
-use tracing::warn;
+use rand::RngCore;
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::{Oaep, Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use std::net::IpAddr;
 
-/// AirPlay authentication (simplified)
+type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+/// AES block size used by RAOP audio encryption
+const AES_BLOCK_SIZE: usize = 16;
+
+/// AirPlay 1 (RAOP) audio encryption
 ///
-/// Note: This is a basic implementation. Full AirPlay authentication involves:
-/// - RSA key exchange
-/// - AES encryption
-/// - Fairplay DRM (for AirPlay 2)
+/// RAOP encrypts each RTP audio payload with AES-128-CBC, restarting from the
+/// session IV for every packet and leaving any trailing partial block in the
+/// clear. The AES key is sent to the receiver in the ANNOUNCE SDP, wrapped with
+/// the receiver's RSA public key using OAEP (SHA-1).
 ///
-/// This implementation provides basic setup for AirPlay 1 (RAOP)
+/// Receivers emulating an AirPort Express all share Apple's well-known public key;
+/// it must be supplied by the caller (PEM, PKCS#1 or SPKI) as AAEQ doesn't bundle it.
+/// Without an RSA key, audio is sent unencrypted.
+#[derive(Clone)]
 pub struct AirPlayAuth {
-    aes_key: Option<Vec<u8>>,
-    aes_iv: Option<Vec<u8>>,
+    aes_key: Option<[u8; 16]>,
+    aes_iv: Option<[u8; 16]>,
+    rsa_public_key: Option<RsaPublicKey>,
 }
 
 impl AirPlayAuth {
@@ -20,58 +35,196 @@ impl AirPlayAuth {
         Self {
             aes_key: None,
             aes_iv: None,
+            rsa_public_key: None,
+        }
+    }
+
+    /// Create with the receiver's RSA public key, enabling encrypted streams
+    pub fn with_rsa_public_key(public_key: RsaPublicKey) -> Self {
+        Self {
+            rsa_public_key: Some(public_key),
+            ..Self::new()
         }
     }
 
+    /// Parse a PEM-encoded RSA public key (PKCS#1 `RSA PUBLIC KEY` or SPKI `PUBLIC KEY`)
+    pub fn parse_rsa_public_key_pem(pem: &str) -> Result<RsaPublicKey> {
+        RsaPublicKey::from_pkcs1_pem(pem)
+            .or_else(|_| RsaPublicKey::from_public_key_pem(pem))
+            .map_err(|e| anyhow!("Invalid RSA public key: {}", e))
+    }
+
+    /// Parse a PEM-encoded RSA private key (PKCS#1 `RSA PRIVATE KEY` or PKCS#8 `PRIVATE KEY`),
+    /// as needed by a receiver to accept encrypted streams
+    pub fn parse_rsa_private_key_pem(pem: &str) -> Result<RsaPrivateKey> {
+        RsaPrivateKey::from_pkcs1_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs8_pem(pem))
+            .map_err(|e| anyhow!("Invalid RSA private key: {}", e))
+    }
+
+    /// Set the receiver's RSA public key
+    pub fn set_rsa_public_key(&mut self, public_key: RsaPublicKey) {
+        self.rsa_public_key = Some(public_key);
+    }
+
+    /// Whether an RSA key is available to send an encrypted stream
+    pub fn can_encrypt(&self) -> bool {
+        self.rsa_public_key.is_some()
+    }
+
+    /// Whether audio payloads are currently being encrypted
+    pub fn is_encrypting(&self) -> bool {
+        self.aes_key.is_some() && self.aes_iv.is_some()
+    }
+
     /// Generate AES key and IV for audio encryption
     pub fn generate_encryption_keys(&mut self) {
-        // Generate random 128-bit AES key
-        let mut key = vec![0u8; 16];
-        for byte in &mut key {
-            *byte = rand::random();
-        }
+        let mut rng = rand::thread_rng();
 
-        // Generate random 128-bit IV
-        let mut iv = vec![0u8; 16];
-        for byte in &mut iv {
-            *byte = rand::random();
-        }
+        let mut key = [0u8; 16];
+        rng.fill_bytes(&mut key);
+
+        let mut iv = [0u8; 16];
+        rng.fill_bytes(&mut iv);
 
         self.aes_key = Some(key);
         self.aes_iv = Some(iv);
     }
 
-    /// Get base64-encoded AES key for RTSP ANNOUNCE
+    /// Use a specific AES key and IV (e.g. as recovered by a receiver)
+    pub fn set_encryption_keys(&mut self, key: [u8; 16], iv: [u8; 16]) {
+        self.aes_key = Some(key);
+        self.aes_iv = Some(iv);
+    }
+
+    /// Get base64-encoded AES key (unwrapped)
     pub fn get_aes_key_base64(&self) -> Option<String> {
-        self.aes_key.as_ref().map(|key| {
-            base64::engine::general_purpose::STANDARD.encode(key)
-        })
+        self.aes_key
+            .as_ref()
+            .map(|key| base64::engine::general_purpose::STANDARD.encode(key))
     }
 
-    /// Get base64-encoded AES IV for RTSP ANNOUNCE
+    /// Get base64-encoded AES IV for the `a=aesiv` SDP attribute
     pub fn get_aes_iv_base64(&self) -> Option<String> {
-        self.aes_iv.as_ref().map(|iv| {
-            base64::engine::general_purpose::STANDARD.encode(iv)
-        })
+        self.aes_iv
+            .as_ref()
+            .map(|iv| base64::engine::general_purpose::STANDARD_NO_PAD.encode(iv))
+    }
+
+    /// Get the RSA-OAEP wrapped AES key for the `a=rsaaeskey` SDP attribute
+    pub fn get_rsa_aes_key_base64(&self) -> Result<String> {
+        let public_key = self
+            .rsa_public_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("No RSA public key configured"))?;
+        let key = self
+            .aes_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("Encryption keys not initialized"))?;
+
+        let wrapped = public_key
+            .encrypt(&mut rand::thread_rng(), Oaep::new::<sha1::Sha1>(), key)
+            .map_err(|e| anyhow!("RSA encryption of AES key failed: {}", e))?;
+
+        Ok(base64::engine::general_purpose::STANDARD_NO_PAD.encode(wrapped))
     }
 
-    /// Encrypt audio data with AES (stub implementation)
+    /// Encrypt an audio payload with AES-128-CBC (RAOP style)
     ///
-    /// Note: Real implementation would use AES-128 in CBC or CTR mode
+    /// Returns the data unchanged if encryption keys haven't been generated.
     pub fn encrypt_audio(&self, data: &[u8]) -> Result<Vec<u8>> {
-        if self.aes_key.is_none() || self.aes_iv.is_none() {
-            warn!("Encryption keys not initialized, returning unencrypted data");
+        let (Some(key), Some(iv)) = (&self.aes_key, &self.aes_iv) else {
             return Ok(data.to_vec());
-        }
+        };
+
+        let mut out = data.to_vec();
+        let aligned = data.len() - data.len() % AES_BLOCK_SIZE;
+
+        Aes128CbcEnc::new(key.into(), iv.into())
+            .encrypt_padded_mut::<NoPadding>(&mut out[..aligned], aligned)
+            .map_err(|e| anyhow!("AES encryption failed: {}", e))?;
+
+        Ok(out)
+    }
+
+    /// Decrypt an audio payload (receiver side of `encrypt_audio`)
+    pub fn decrypt_audio(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let (Some(key), Some(iv)) = (&self.aes_key, &self.aes_iv) else {
+            return Ok(data.to_vec());
+        };
+
+        let mut out = data.to_vec();
+        let aligned = data.len() - data.len() % AES_BLOCK_SIZE;
+
+        Aes128CbcDec::new(key.into(), iv.into())
+            .decrypt_padded_mut::<NoPadding>(&mut out[..aligned])
+            .map_err(|e| anyhow!("AES decryption failed: {}", e))?;
 
-        // Stub: In a real implementation, use the `aes` and `cbc` crates
-        // to encrypt the audio data
-        warn!("Using stub encryption - data is not actually encrypted");
+        Ok(out)
+    }
+
+    /// Recover the AES key and IV from ANNOUNCE SDP attributes (receiver side)
+    pub fn from_sdp_keys(
+        private_key: &RsaPrivateKey,
+        rsa_aes_key_base64: &str,
+        aes_iv_base64: &str,
+    ) -> Result<Self> {
+        let wrapped = decode_base64_lenient(rsa_aes_key_base64)?;
+        let key = private_key
+            .decrypt(Oaep::new::<sha1::Sha1>(), &wrapped)
+            .map_err(|e| anyhow!("RSA decryption of AES key failed: {}", e))?;
+        let iv = decode_base64_lenient(aes_iv_base64)?;
+
+        let key: [u8; 16] = key
+            .try_into()
+            .map_err(|_| anyhow!("AES key must be 16 bytes"))?;
+        let iv: [u8; 16] = iv
+            .try_into()
+            .map_err(|_| anyhow!("AES IV must be 16 bytes"))?;
 
-        Ok(data.to_vec())
+        let mut auth = Self::new();
+        auth.set_encryption_keys(key, iv);
+        Ok(auth)
     }
 }
 
+/// Answer a sender's `Apple-Challenge` (receiver side)
+///
+/// The challenge is signed together with the receiver's IP and hardware
+/// address, proving to the sender that the receiver holds the private key.
+pub fn apple_response(
+    private_key: &RsaPrivateKey,
+    challenge_base64: &str,
+    ip: IpAddr,
+    hw_addr: &[u8; 6],
+) -> Result<String> {
+    let mut message = decode_base64_lenient(challenge_base64)?;
+    match ip {
+        IpAddr::V4(ip) => message.extend_from_slice(&ip.octets()),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => message.extend_from_slice(&ip.octets()),
+            None => message.extend_from_slice(&ip.octets()),
+        },
+    }
+    message.extend_from_slice(hw_addr);
+    if message.len() < 32 {
+        message.resize(32, 0);
+    }
+
+    let signature = private_key
+        .sign(Pkcs1v15Sign::new_unprefixed(), &message)
+        .map_err(|e| anyhow!("Signing Apple-Challenge failed: {}", e))?;
+    Ok(base64::engine::general_purpose::STANDARD_NO_PAD.encode(signature))
+}
+
+/// Decode base64 with or without padding (RAOP senders commonly strip it)
+fn decode_base64_lenient(value: &str) -> Result<Vec<u8>> {
+    let trimmed = value.trim().trim_end_matches('=');
+    base64::engine::general_purpose::STANDARD_NO_PAD
+        .decode(trimmed)
+        .map_err(|e| anyhow!("Invalid base64: {}", e))
+}
+
 impl Default for AirPlayAuth {
     fn default() -> Self {
         Self::new()
@@ -82,6 +235,12 @@ impl Default for AirPlayAuth {
 mod tests {
     use super::*;
 
+    fn test_keypair() -> (RsaPrivateKey, RsaPublicKey) {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let public_key = RsaPublicKey::from(&private_key);
+        (private_key, public_key)
+    }
+
     #[test]
     fn test_generate_keys() {
         let mut auth = AirPlayAuth::new();
@@ -90,15 +249,13 @@ mod tests {
 
         auth.generate_encryption_keys();
 
+        assert!(auth.is_encrypting());
         assert!(auth.get_aes_key_base64().is_some());
-        assert!(auth.get_aes_iv_base64().is_some());
 
-        // Keys should be base64 encoded
-        let key = auth.get_aes_key_base64().unwrap();
+        // SDP IV is unpadded base64 of 16 bytes
         let iv = auth.get_aes_iv_base64().unwrap();
-
-        assert!(!key.is_empty());
-        assert!(!iv.is_empty());
+        assert_eq!(iv.len(), 22);
+        assert!(!iv.ends_with('='));
     }
 
     #[test]
@@ -115,10 +272,90 @@ mod tests {
         let mut auth = AirPlayAuth::new();
         auth.generate_encryption_keys();
 
-        let data = vec![1, 2, 3, 4];
+        // 2 full blocks + 5 trailing bytes
+        let data: Vec<u8> = (0..37).collect();
         let encrypted = auth.encrypt_audio(&data).unwrap();
 
-        // Stub implementation returns data as-is
-        assert_eq!(encrypted, data);
+        assert_eq!(encrypted.len(), data.len());
+        assert_ne!(&encrypted[..32], &data[..32]);
+        // Trailing partial block is sent in the clear
+        assert_eq!(&encrypted[32..], &data[32..]);
+
+        assert_eq!(auth.decrypt_audio(&encrypted).unwrap(), data);
+    }
+
+    #[test]
+    fn test_iv_resets_per_packet() {
+        let mut auth = AirPlayAuth::new();
+        auth.generate_encryption_keys();
+
+        let data = vec![7u8; 32];
+        assert_eq!(auth.encrypt_audio(&data).unwrap(), auth.encrypt_audio(&data).unwrap());
+    }
+
+    #[test]
+    fn test_rsa_wrapped_key_roundtrip() {
+        let (private_key, public_key) = test_keypair();
+
+        let mut sender = AirPlayAuth::with_rsa_public_key(public_key);
+        assert!(sender.can_encrypt());
+        assert!(sender.get_rsa_aes_key_base64().is_err()); // no AES key yet
+
+        sender.generate_encryption_keys();
+        let rsa_key = sender.get_rsa_aes_key_base64().unwrap();
+        let iv = sender.get_aes_iv_base64().unwrap();
+
+        let receiver = AirPlayAuth::from_sdp_keys(&private_key, &rsa_key, &iv).unwrap();
+        let payload: Vec<u8> = (0..100).collect();
+        let encrypted = sender.encrypt_audio(&payload).unwrap();
+        assert_eq!(receiver.decrypt_audio(&encrypted).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_parse_public_key_pem() {
+        use rsa::pkcs1::{EncodeRsaPublicKey, LineEnding};
+        use rsa::pkcs8::EncodePublicKey;
+
+        let (_, public_key) = test_keypair();
+
+        let pkcs1 = public_key.to_pkcs1_pem(LineEnding::LF).unwrap();
+        let spki = public_key.to_public_key_pem(LineEnding::LF).unwrap();
+
+        assert_eq!(AirPlayAuth::parse_rsa_public_key_pem(&pkcs1).unwrap(), public_key);
+        assert_eq!(AirPlayAuth::parse_rsa_public_key_pem(&spki).unwrap(), public_key);
+        assert!(AirPlayAuth::parse_rsa_public_key_pem("not a key").is_err());
+    }
+
+    #[test]
+    fn test_parse_private_key_pem() {
+        use rsa::pkcs1::EncodeRsaPrivateKey;
+        use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+
+        let (private_key, _) = test_keypair();
+
+        let pkcs1 = private_key.to_pkcs1_pem(LineEnding::LF).unwrap();
+        let pkcs8 = private_key.to_pkcs8_pem(LineEnding::LF).unwrap();
+
+        assert_eq!(AirPlayAuth::parse_rsa_private_key_pem(&pkcs1).unwrap(), private_key);
+        assert_eq!(AirPlayAuth::parse_rsa_private_key_pem(&pkcs8).unwrap(), private_key);
+        assert!(AirPlayAuth::parse_rsa_private_key_pem("not a key").is_err());
+    }
+
+    #[test]
+    fn test_apple_response_verifies() {
+        let (private_key, public_key) = test_keypair();
+        let challenge = base64::engine::general_purpose::STANDARD.encode([9u8; 16]);
+        let hw_addr = [0x02, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE];
+
+        let response = apple_response(&private_key, &challenge, "10.0.0.2".parse().unwrap(), &hw_addr).unwrap();
+        let signature = decode_base64_lenient(&response).unwrap();
+
+        let mut message = vec![9u8; 16];
+        message.extend_from_slice(&[10, 0, 0, 2]);
+        message.extend_from_slice(&hw_addr);
+        message.resize(32, 0);
+        public_key
+            .verify(Pkcs1v15Sign::new_unprefixed(), &message, &signature)
+            .unwrap();
     }
 }
@@ -14,6 +14,20 @@ pub struct AirPlayDevice {
     pub features: Option<String>,
 }
 
+impl AirPlayDevice {
+    /// Describe a receiver at a known address, bypassing mDNS discovery
+    pub fn from_address(name: &str, address: std::net::SocketAddr) -> Self {
+        Self {
+            name: name.to_string(),
+            hostname: address.ip().to_string(),
+            port: address.port(),
+            addresses: vec![address.ip()],
+            model: None,
+            features: None,
+        }
+    }
+}
+
 /// Discover AirPlay devices on the local network using mDNS
 pub async fn discover_devices(timeout_secs: u64) -> Result<Vec<AirPlayDevice>> {
     info!("Starting AirPlay device discovery...");
@@ -4,8 +4,15 @@ mod discovery;
 mod rtsp;
 mod rtp;
 
-pub use alac::{AlacConfig, AlacEncoder, f64_to_i16};
-pub use auth::AirPlayAuth;
+pub use alac::{AlacConfig, AlacEncoder, f64_to_i16, parse_fmtp};
+pub use auth::{AirPlayAuth, apple_response};
 pub use discovery::{AirPlayDevice, discover_devices, find_device_by_name};
-pub use rtsp::{RtspClient, RtspResponse, generate_sdp};
-pub use rtp::{RtpStream, RtcpStream, get_ntp_timestamp};
+pub use rtsp::{
+    RtspClient, RtspResponse, VOLUME_MUTE_DB, db_to_volume, decode_dmap_metadata, encode_dmap_metadata,
+    generate_encrypted_sdp, generate_sdp, volume_to_db,
+};
+pub use rtp::{
+    AudioPacket, ControlChannel, JITTER_MAX_PENDING, JitterBuffer, PacketHistory, RETRANSMIT_HISTORY,
+    RtpStream, TimingServer, get_ntp_timestamp, parse_audio_packet, parse_rtp_packet, retransmit_request,
+    retransmit_responses, sync_packet, timing_response,
+};
@@ -1,8 +1,11 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bytes::{BufMut, BytesMut};
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use tokio::net::UdpSocket;
-use tracing::debug;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
 
 /// RTP packet for audio streaming
 pub struct RtpStream {
@@ -11,6 +14,8 @@ pub struct RtpStream {
     sequence_number: u16,
     timestamp: u32,
     ssrc: u32,
+    payload_type: u8,
+    history: Arc<Mutex<PacketHistory>>,
 }
 
 impl RtpStream {
@@ -24,13 +29,25 @@ impl RtpStream {
             sequence_number: rand::random(),
             timestamp: rand::random(),
             ssrc: rand::random(),
+            payload_type: PT_AUDIO,
+            history: Arc::new(Mutex::new(PacketHistory::new(RETRANSMIT_HISTORY))),
         })
     }
 
+    /// Use a payload type other than RAOP's 96 (e.g. the one announced in an SDP)
+    pub fn with_payload_type(mut self, payload_type: u8) -> Self {
+        self.payload_type = payload_type & 0x7F;
+        self
+    }
+
     /// Send an RTP packet with audio payload
     pub async fn send_packet(&mut self, payload: &[u8], samples_in_payload: u32) -> Result<()> {
         let packet = self.create_rtp_packet(payload);
         self.socket.send_to(&packet, self.dest_addr).await?;
+        self.history
+            .lock()
+            .unwrap()
+            .push(self.sequence_number, packet.clone());
 
         // Update state
         self.sequence_number = self.sequence_number.wrapping_add(1);
@@ -54,8 +71,8 @@ impl RtpStream {
         // V=2, P=0, X=0, CC=0
         packet.put_u8(0x80);
 
-        // M=0, PT=96 (dynamic payload type for ALAC)
-        packet.put_u8(96);
+        // M=0, PT (96, the dynamic payload type for ALAC, unless overridden)
+        packet.put_u8(self.payload_type);
 
         // Sequence number
         packet.put_u16(self.sequence_number);
@@ -86,62 +103,428 @@ impl RtpStream {
     pub fn ssrc(&self) -> u32 {
         self.ssrc
     }
+
+    /// Payload type of sent packets
+    pub fn payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    /// Underlying socket, for multicast options
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Sent packets available for retransmission
+    pub fn history(&self) -> Arc<Mutex<PacketHistory>> {
+        self.history.clone()
+    }
+
+    /// Change where audio packets are sent (e.g. the `server_port` from SETUP)
+    pub fn set_destination(&mut self, dest_addr: SocketAddr) {
+        self.dest_addr = dest_addr;
+    }
 }
 
-/// RTP control stream (RTCP) for timing and feedback
-pub struct RtcpStream {
-    socket: UdpSocket,
-    dest_addr: SocketAddr,
+/// RTP payload type of RAOP audio packets
+const PT_AUDIO: u8 = 96;
+
+/// RAOP payload types (sent with the marker bit set on the control/timing ports)
+const PT_TIMING_REQUEST: u8 = 0x52;
+const PT_TIMING_RESPONSE: u8 = 0x53;
+const PT_SYNC: u8 = 0x54;
+const PT_RETRANSMIT_REQUEST: u8 = 0x55;
+const PT_RETRANSMIT_RESPONSE: u8 = 0x56;
+
+/// Number of sent audio packets kept for retransmission (~8s at 352 frames/packet)
+pub const RETRANSMIT_HISTORY: usize = 1024;
+
+/// Recently sent RTP packets, indexed by sequence number
+pub struct PacketHistory {
+    packets: VecDeque<(u16, Vec<u8>)>,
+    capacity: usize,
 }
 
-impl RtcpStream {
-    /// Create a new RTCP stream
-    pub async fn new(local_port: u16, dest_addr: SocketAddr) -> Result<Self> {
-        let socket = UdpSocket::bind(format!("0.0.0.0:{}", local_port)).await?;
+impl PacketHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            packets: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
 
-        Ok(Self { socket, dest_addr })
+    /// Remember a sent packet, evicting the oldest once full
+    pub fn push(&mut self, sequence_number: u16, packet: Vec<u8>) {
+        if self.packets.len() == self.capacity {
+            self.packets.pop_front();
+        }
+        self.packets.push_back((sequence_number, packet));
     }
 
-    /// Send a sender report
-    pub async fn send_sender_report(
-        &self,
-        ssrc: u32,
-        ntp_timestamp: u64,
-        rtp_timestamp: u32,
-        packet_count: u32,
-        octet_count: u32,
-    ) -> Result<()> {
-        let mut packet = BytesMut::with_capacity(28);
+    /// Look up a packet by sequence number
+    pub fn get(&self, sequence_number: u16) -> Option<&[u8]> {
+        // Sequence numbers are consecutive, so index relative to the oldest one
+        let (first, _) = self.packets.front()?;
+        let index = sequence_number.wrapping_sub(*first) as usize;
+        self.packets
+            .get(index)
+            .filter(|(seq, _)| *seq == sequence_number)
+            .map(|(_, packet)| packet.as_slice())
+    }
 
-        // RTCP header
-        packet.put_u8(0x80); // V=2, P=0, RC=0
-        packet.put_u8(200); // PT=200 (SR)
-        packet.put_u16(6); // Length in 32-bit words - 1
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
 
-        // SSRC of sender
-        packet.put_u32(ssrc);
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+}
 
-        // NTP timestamp
-        packet.put_u64(ntp_timestamp);
+/// RAOP control channel: sync packets to the receiver, retransmissions on request
+pub struct ControlChannel {
+    socket: Arc<UdpSocket>,
+    dest_addr: Option<SocketAddr>,
+    responder_task: JoinHandle<()>,
+}
 
-        // RTP timestamp
-        packet.put_u32(rtp_timestamp);
+impl ControlChannel {
+    /// Bind the control port and start answering retransmit requests from `history`
+    pub async fn new(local_port: u16, history: Arc<Mutex<PacketHistory>>) -> Result<Self> {
+        let socket = Arc::new(UdpSocket::bind(format!("0.0.0.0:{}", local_port)).await?);
+
+        let responder_socket = socket.clone();
+        let responder_task = tokio::spawn(async move {
+            let mut buf = [0u8; 1500];
+            loop {
+                let (len, src) = match responder_socket.recv_from(&mut buf).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("AirPlay control socket error: {}", e);
+                        break;
+                    }
+                };
+
+                let replies = {
+                    let history = history.lock().unwrap();
+                    retransmit_responses(&buf[..len], &history)
+                };
+
+                for reply in replies {
+                    if let Err(e) = responder_socket.send_to(&reply, src).await {
+                        warn!("Failed to send retransmitted packet: {}", e);
+                    }
+                }
+            }
+        });
 
-        // Sender's packet count
-        packet.put_u32(packet_count);
+        Ok(Self {
+            socket,
+            dest_addr: None,
+            responder_task,
+        })
+    }
 
-        // Sender's octet count
-        packet.put_u32(octet_count);
+    /// Local port to advertise as `control_port` in SETUP
+    pub fn local_port(&self) -> Result<u16> {
+        Ok(self.socket.local_addr()?.port())
+    }
 
-        self.socket.send_to(&packet, self.dest_addr).await?;
+    /// Set the receiver's control port address (from the SETUP response)
+    pub fn set_destination(&mut self, dest_addr: SocketAddr) {
+        self.dest_addr = Some(dest_addr);
+    }
+
+    /// Send a sync packet tying `rtp_timestamp - latency` to the current NTP time
+    pub async fn send_sync(&self, rtp_timestamp: u32, latency_frames: u32, first: bool) -> Result<()> {
+        let dest_addr = self
+            .dest_addr
+            .ok_or_else(|| anyhow!("Control channel has no destination"))?;
 
-        debug!("Sent RTCP sender report");
+        let packet = sync_packet(rtp_timestamp, latency_frames, get_ntp_timestamp(), first);
+        self.socket.send_to(&packet, dest_addr).await?;
+
+        debug!("Sent RAOP sync packet: rtptime={}", rtp_timestamp);
 
         Ok(())
     }
 }
 
-/// Get NTP timestamp (for RTCP)
+impl Drop for ControlChannel {
+    fn drop(&mut self) {
+        self.responder_task.abort();
+    }
+}
+
+/// RAOP timing server answering the receiver's NTP-style timing requests
+pub struct TimingServer {
+    local_port: u16,
+    task: JoinHandle<()>,
+}
+
+impl TimingServer {
+    /// Bind the timing port and start replying to timing requests
+    pub async fn new(local_port: u16) -> Result<Self> {
+        let socket = UdpSocket::bind(format!("0.0.0.0:{}", local_port)).await?;
+        let local_port = socket.local_addr()?.port();
+
+        let task = tokio::spawn(async move {
+            let mut buf = [0u8; 128];
+            loop {
+                let (len, src) = match socket.recv_from(&mut buf).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("AirPlay timing socket error: {}", e);
+                        break;
+                    }
+                };
+                let receive_time = get_ntp_timestamp();
+
+                if let Some(reply) = timing_response(&buf[..len], receive_time, get_ntp_timestamp()) {
+                    if let Err(e) = socket.send_to(&reply, src).await {
+                        warn!("Failed to send timing reply: {}", e);
+                    }
+                }
+            }
+        });
+
+        Ok(Self { local_port, task })
+    }
+
+    /// Local port to advertise as `timing_port` in SETUP
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+}
+
+impl Drop for TimingServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Build a sync packet for the control port
+///
+/// The first sync after RECORD or FLUSH carries the extension bit.
+pub fn sync_packet(rtp_timestamp: u32, latency_frames: u32, ntp_timestamp: u64, first: bool) -> Vec<u8> {
+    let mut packet = BytesMut::with_capacity(20);
+
+    packet.put_u8(if first { 0x90 } else { 0x80 });
+    packet.put_u8(0x80 | PT_SYNC);
+    packet.put_u16(7);
+
+    // Timestamp currently being played
+    packet.put_u32(rtp_timestamp.wrapping_sub(latency_frames));
+
+    // NTP time at which it is played
+    packet.put_u64(ntp_timestamp);
+
+    // Timestamp of the next packet to be sent
+    packet.put_u32(rtp_timestamp);
+
+    packet.to_vec()
+}
+
+/// Build the reply to a timing request, or `None` if `request` isn't one
+pub fn timing_response(request: &[u8], receive_time: u64, send_time: u64) -> Option<Vec<u8>> {
+    if request.len() < 32 || request[1] & 0x7F != PT_TIMING_REQUEST {
+        return None;
+    }
+
+    let mut packet = BytesMut::with_capacity(32);
+    packet.put_u8(0x80);
+    packet.put_u8(0x80 | PT_TIMING_RESPONSE);
+    packet.put_u16(7);
+    packet.put_u32(0);
+
+    // Origin timestamp is the requester's send time
+    packet.put_slice(&request[24..32]);
+    packet.put_u64(receive_time);
+    packet.put_u64(send_time);
+
+    Some(packet.to_vec())
+}
+
+/// Build retransmission replies for a retransmit request
+///
+/// Each reply wraps the original RTP packet (header included) in a 4-byte header.
+/// Packets that have already left the history are skipped.
+pub fn retransmit_responses(request: &[u8], history: &PacketHistory) -> Vec<Vec<u8>> {
+    if request.len() < 8 || request[1] & 0x7F != PT_RETRANSMIT_REQUEST {
+        return Vec::new();
+    }
+
+    let first_seq = u16::from_be_bytes([request[4], request[5]]);
+    let count = u16::from_be_bytes([request[6], request[7]]).min(history.capacity as u16);
+
+    debug!("Retransmit request: seq={}, count={}", first_seq, count);
+
+    (0..count)
+        .filter_map(|i| history.get(first_seq.wrapping_add(i)))
+        .map(|original| {
+            let mut packet = Vec::with_capacity(4 + original.len());
+            packet.extend_from_slice(&[0x80, 0x80 | PT_RETRANSMIT_RESPONSE, 0x00, 0x01]);
+            packet.extend_from_slice(original);
+            packet
+        })
+        .collect()
+}
+
+/// Build a retransmit request for `count` packets starting at `first_seq` (receiver side)
+pub fn retransmit_request(first_seq: u16, count: u16) -> Vec<u8> {
+    let mut packet = BytesMut::with_capacity(8);
+    packet.put_u8(0x80);
+    packet.put_u8(0x80 | PT_RETRANSMIT_REQUEST);
+    packet.put_u16(1);
+    packet.put_u16(first_seq);
+    packet.put_u16(count);
+    packet.to_vec()
+}
+
+/// A received RTP audio packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioPacket<'a> {
+    pub sequence_number: u16,
+    pub timestamp: u32,
+    pub payload: &'a [u8],
+}
+
+/// Parse an audio packet from the audio port, or a retransmitted one from the
+/// control port (unwrapping its 4-byte header)
+pub fn parse_audio_packet(packet: &[u8]) -> Option<AudioPacket<'_>> {
+    let packet = match packet.get(1).map(|pt| pt & 0x7F) {
+        Some(PT_RETRANSMIT_RESPONSE) => packet.get(4..)?,
+        _ => packet,
+    };
+    match parse_rtp_packet(packet)? {
+        (PT_AUDIO, audio) => Some(audio),
+        _ => None,
+    }
+}
+
+/// Parse any RTP packet, returning its payload type; CSRCs, a header
+/// extension and padding are skipped
+pub fn parse_rtp_packet(packet: &[u8]) -> Option<(u8, AudioPacket<'_>)> {
+    if packet.len() < 12 || packet[0] & 0xC0 != 0x80 {
+        return None;
+    }
+
+    let csrc_count = (packet[0] & 0x0F) as usize;
+    let mut start = 12 + 4 * csrc_count;
+    if packet[0] & 0x10 != 0 {
+        let extension = packet.get(start..start + 4)?;
+        start += 4 + 4 * u16::from_be_bytes([extension[2], extension[3]]) as usize;
+    }
+    let mut end = packet.len();
+    if packet[0] & 0x20 != 0 {
+        end = end.checked_sub(*packet.last()? as usize)?;
+    }
+
+    Some((
+        packet[1] & 0x7F,
+        AudioPacket {
+            sequence_number: u16::from_be_bytes([packet[2], packet[3]]),
+            timestamp: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            payload: packet.get(start..end)?,
+        },
+    ))
+}
+
+/// Packets held back waiting for a missing one before it is given up on
+pub const JITTER_MAX_PENDING: usize = 32;
+
+/// A sequence jump this large is a new stream rather than loss
+const RESYNC_GAP: u16 = 512;
+
+/// Puts received packets back in sequence order
+///
+/// Senders pace their streams in realtime, so packets are passed on as soon
+/// as they are in order; a gap is held open for up to
+/// [`JITTER_MAX_PENDING`] later packets and then skipped.
+#[derive(Debug)]
+pub struct JitterBuffer<T> {
+    /// Sequence number expected next
+    next: Option<u16>,
+    /// Highest sequence number seen, to report each gap only once
+    highest: u16,
+    pending: HashMap<u16, T>,
+}
+
+impl<T> Default for JitterBuffer<T> {
+    fn default() -> Self {
+        Self {
+            next: None,
+            highest: 0,
+            pending: HashMap::new(),
+        }
+    }
+}
+
+/// Signed distance from `b` to `a`, across sequence number wrap-around
+fn seq_diff(a: u16, b: u16) -> i16 {
+    a.wrapping_sub(b) as i16
+}
+
+impl<T> JitterBuffer<T> {
+    /// Add a packet; returns the packets now in order, and the range of
+    /// sequence numbers (`first`, `count`) newly found missing
+    pub fn insert(&mut self, seq: u16, packet: T) -> (Vec<T>, Option<(u16, u16)>) {
+        let next = *self.next.get_or_insert(seq);
+        let diff = seq_diff(seq, next);
+        if diff.unsigned_abs() >= RESYNC_GAP {
+            debug!("RTP stream jumped from {} to {}, resyncing", next, seq);
+            self.reset();
+            return self.insert(seq, packet);
+        }
+        if diff < 0 || self.pending.contains_key(&seq) {
+            // Late or duplicate
+            return (Vec::new(), None);
+        }
+
+        let mut missing = None;
+        if self.pending.is_empty() {
+            self.highest = next.wrapping_sub(1);
+        }
+        if seq_diff(seq, self.highest) > 0 {
+            let first = self.highest.wrapping_add(1);
+            if first != seq {
+                missing = Some((first, seq.wrapping_sub(first)));
+            }
+            self.highest = seq;
+        }
+        self.pending.insert(seq, packet);
+
+        let mut ready = self.drain();
+        if self.pending.len() > JITTER_MAX_PENDING {
+            // Give up on the gap and carry on from the oldest packet held
+            if let Some(&oldest) = self.pending.keys().min_by_key(|&&s| seq_diff(s, next)) {
+                debug!("RTP packets {}..{} lost", self.next.unwrap_or(next), oldest);
+                self.next = Some(oldest);
+                ready.extend(self.drain());
+            }
+        }
+        (ready, missing)
+    }
+
+    fn drain(&mut self) -> Vec<T> {
+        let mut ready = Vec::new();
+        while let Some(next) = self.next {
+            let Some(packet) = self.pending.remove(&next) else {
+                break;
+            };
+            ready.push(packet);
+            self.next = Some(next.wrapping_add(1));
+        }
+        ready
+    }
+
+    /// Forget the stream position, e.g. after a flush
+    pub fn reset(&mut self) {
+        self.next = None;
+        self.pending.clear();
+    }
+}
+
+/// Get the current time as a 64-bit NTP timestamp
 pub fn get_ntp_timestamp() -> u64 {
     use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -181,4 +564,231 @@ mod tests {
         assert!(ntp_seconds > 3_786_825_600); // 2020-01-01 in NTP
         assert!(ntp_seconds < 4_733_654_400); // 2050-01-01 in NTP
     }
+
+    #[test]
+    fn test_sync_packet_layout() {
+        let packet = sync_packet(100_000, 11025, 0x0102_0304_0506_0708, true);
+
+        assert_eq!(packet.len(), 20);
+        assert_eq!(&packet[..4], &[0x90, 0xD4, 0x00, 0x07]);
+        assert_eq!(u32::from_be_bytes(packet[4..8].try_into().unwrap()), 100_000 - 11025);
+        assert_eq!(u64::from_be_bytes(packet[8..16].try_into().unwrap()), 0x0102_0304_0506_0708);
+        assert_eq!(u32::from_be_bytes(packet[16..20].try_into().unwrap()), 100_000);
+
+        assert_eq!(sync_packet(0, 0, 0, false)[0], 0x80);
+    }
+
+    #[test]
+    fn test_timing_response() {
+        let mut request = vec![0x80, 0xD2, 0x00, 0x07, 0, 0, 0, 0];
+        request.extend_from_slice(&[0u8; 16]);
+        request.extend_from_slice(&42u64.to_be_bytes()); // requester's send time
+
+        let reply = timing_response(&request, 100, 200).unwrap();
+        assert_eq!(reply.len(), 32);
+        assert_eq!(&reply[..4], &[0x80, 0xD3, 0x00, 0x07]);
+        assert_eq!(u64::from_be_bytes(reply[8..16].try_into().unwrap()), 42);
+        assert_eq!(u64::from_be_bytes(reply[16..24].try_into().unwrap()), 100);
+        assert_eq!(u64::from_be_bytes(reply[24..32].try_into().unwrap()), 200);
+
+        // Not a timing request
+        request[1] = 0xD4;
+        assert!(timing_response(&request, 100, 200).is_none());
+        assert!(timing_response(&[0x80, 0xD2], 100, 200).is_none());
+    }
+
+    #[test]
+    fn test_packet_history_wraps() {
+        let mut history = PacketHistory::new(4);
+        for seq in 65533u16..=65535 {
+            history.push(seq, vec![seq as u8]);
+        }
+        history.push(0, vec![0]);
+        history.push(1, vec![1]);
+
+        assert_eq!(history.len(), 4);
+        assert!(history.get(65533).is_none()); // evicted
+        assert_eq!(history.get(65535), Some(&[0xFF][..]));
+        assert_eq!(history.get(1), Some(&[1][..]));
+        assert!(history.get(2).is_none());
+    }
+
+    #[test]
+    fn test_retransmit_responses() {
+        let mut history = PacketHistory::new(16);
+        for seq in 10u16..20 {
+            history.push(seq, vec![0x80, 0x60, 0, seq as u8]);
+        }
+
+        // Request seq 18..22; only 18 and 19 are known
+        let request = [0x80, 0xD5, 0x00, 0x01, 0x00, 18, 0x00, 4];
+        let replies = retransmit_responses(&request, &history);
+
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0], vec![0x80, 0xD6, 0x00, 0x01, 0x80, 0x60, 0, 18]);
+        assert_eq!(replies[1][7], 19);
+
+        assert!(retransmit_responses(&[0x80, 0xD4, 0, 0, 0, 0, 0, 1], &history).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_control_channel_retransmits_sent_packets() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut rtp = RtpStream::new(0, receiver.local_addr().unwrap()).await.unwrap();
+        let control = ControlChannel::new(0, rtp.history()).await.unwrap();
+
+        let first_seq = rtp.sequence_number();
+        for i in 0..3u8 {
+            rtp.send_packet(&[i; 8], 352).await.unwrap();
+        }
+
+        let mut buf = [0u8; 64];
+        for _ in 0..3 {
+            receiver.recv_from(&mut buf).await.unwrap();
+        }
+
+        // Ask for the second packet again
+        let seq = first_seq.wrapping_add(1).to_be_bytes();
+        let request = [0x80, 0xD5, 0x00, 0x01, seq[0], seq[1], 0x00, 0x01];
+        let control_addr = format!("127.0.0.1:{}", control.local_port().unwrap());
+        receiver.send_to(&request, control_addr).await.unwrap();
+
+        let (len, _) = receiver.recv_from(&mut buf).await.unwrap();
+        assert_eq!(len, 4 + 12 + 8);
+        assert_eq!(&buf[..2], &[0x80, 0xD6]);
+        assert_eq!(&buf[6..8], &seq);
+        assert_eq!(&buf[16..24], &[1u8; 8]);
+    }
+
+    #[tokio::test]
+    async fn test_timing_server_replies() {
+        let server = TimingServer::new(0).await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let mut request = vec![0x80, 0xD2, 0x00, 0x07, 0, 0, 0, 0];
+        request.extend_from_slice(&[0u8; 16]);
+        request.extend_from_slice(&7u64.to_be_bytes());
+        client
+            .send_to(&request, format!("127.0.0.1:{}", server.local_port()))
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 64];
+        let (len, _) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(len, 32);
+        assert_eq!(buf[1], 0xD3);
+        assert_eq!(u64::from_be_bytes(buf[8..16].try_into().unwrap()), 7);
+        assert!(u64::from_be_bytes(buf[16..24].try_into().unwrap()) >> 32 > 3_786_825_600);
+    }
+
+    #[test]
+    fn test_retransmit_request_roundtrip() {
+        let mut history = PacketHistory::new(16);
+        for seq in [65534u16, 65535, 0] {
+            let mut packet = vec![0x80, 0x60];
+            packet.extend_from_slice(&seq.to_be_bytes());
+            packet.extend_from_slice(&(seq as u32 * 352).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0, 1, 0xAA]);
+            history.push(seq, packet);
+        }
+
+        let request = retransmit_request(65535, 2);
+        assert_eq!(request, vec![0x80, 0xD5, 0x00, 0x01, 0xFF, 0xFF, 0x00, 0x02]);
+
+        let replies = retransmit_responses(&request, &history);
+        let packets: Vec<AudioPacket> = replies.iter().filter_map(|r| parse_audio_packet(r)).collect();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].sequence_number, 65535);
+        assert_eq!(packets[1].sequence_number, 0);
+        assert_eq!(packets[1].payload, &[0xAA]);
+
+        // Sync packets aren't audio
+        assert!(parse_audio_packet(&sync_packet(0, 0, 0, true)).is_none());
+        assert!(parse_audio_packet(&[0x80, 0x60, 0]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_payload_type_override() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = RtpStream::new(0, receiver.local_addr().unwrap())
+            .await
+            .unwrap()
+            .with_payload_type(98);
+        assert_eq!(stream.payload_type(), 98);
+        stream.send_packet(&[1, 2, 3], 1).await.unwrap();
+
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).await.unwrap();
+        let (payload_type, packet) = parse_rtp_packet(&buf[..len]).unwrap();
+        assert_eq!(payload_type, 98);
+        assert_eq!(packet.payload, &[1, 2, 3]);
+        assert!(parse_audio_packet(&buf[..len]).is_none());
+    }
+
+    #[test]
+    fn test_parse_rtp_packet_skips_csrcs_extension_and_padding() {
+        // CC=1, X=1, P=1
+        let mut packet = vec![0xB1, 0x61, 0x00, 0x07, 0, 0, 0x01, 0x00, 1, 2, 3, 4];
+        packet.extend_from_slice(&[9, 9, 9, 9]);
+        packet.extend_from_slice(&[0xBE, 0xDE, 0x00, 0x01, 0x10, 0xAB, 0, 0]);
+        packet.extend_from_slice(&[0x11, 0x22, 0x33]);
+        packet.extend_from_slice(&[0, 0, 3]);
+
+        let (payload_type, audio) = parse_rtp_packet(&packet).unwrap();
+        assert_eq!(payload_type, 97);
+        assert_eq!(audio.sequence_number, 7);
+        assert_eq!(audio.timestamp, 256);
+        assert_eq!(audio.payload, &[0x11, 0x22, 0x33]);
+
+        // Truncated extension or padding longer than the packet
+        assert!(parse_rtp_packet(&packet[..18]).is_none());
+        let mut bad_padding = vec![0xA0, 0x61, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 0x20];
+        assert!(parse_rtp_packet(&bad_padding).is_none());
+        bad_padding[12] = 1;
+        assert_eq!(parse_rtp_packet(&bad_padding).unwrap().1.payload, &[] as &[u8]);
+    }
+
+    fn seqs(ready: &[Vec<u8>]) -> Vec<u8> {
+        ready.iter().map(|p| p[0]).collect()
+    }
+
+    #[test]
+    fn test_jitter_buffer_reorders_and_requests_gaps() {
+        let mut jitter = JitterBuffer::default();
+        assert_eq!(seqs(&jitter.insert(10, vec![10]).0), vec![10]);
+
+        // 11 and 12 are missing
+        let (ready, missing) = jitter.insert(13, vec![13]);
+        assert!(ready.is_empty());
+        assert_eq!(missing, Some((11, 2)));
+
+        // The gap is only requested once
+        let (ready, missing) = jitter.insert(12, vec![12]);
+        assert!(ready.is_empty());
+        assert_eq!(missing, None);
+
+        let (ready, _) = jitter.insert(11, vec![11]);
+        assert_eq!(seqs(&ready), vec![11, 12, 13]);
+
+        // Late duplicates are dropped
+        assert!(jitter.insert(12, vec![12]).0.is_empty());
+    }
+
+    #[test]
+    fn test_jitter_buffer_skips_lost_packets_and_wraps() {
+        let mut jitter = JitterBuffer::default();
+        jitter.insert(65534, vec![0]);
+
+        // 65535 never arrives
+        let mut ready = Vec::new();
+        for seq in 0..=JITTER_MAX_PENDING as u16 {
+            ready.extend(jitter.insert(seq, vec![seq as u8]).0);
+        }
+        assert_eq!(seqs(&ready), (0..=JITTER_MAX_PENDING as u8).collect::<Vec<_>>());
+
+        // A big jump starts over rather than waiting
+        let (ready, missing) = jitter.insert(5000, vec![1]);
+        assert_eq!(seqs(&ready), vec![1]);
+        assert_eq!(missing, None);
+    }
 }
@@ -1,3 +1,4 @@
+use aaeq_core::TrackMeta;
 use anyhow::{anyhow, Result};
 use std::collections::HashMap;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
@@ -22,6 +23,18 @@ pub struct RtspResponse {
     pub body: Vec<u8>,
 }
 
+impl RtspResponse {
+    /// Read a port parameter (e.g. `server_port`, `control_port`) from the `Transport` header
+    pub fn transport_port(&self, key: &str) -> Option<u16> {
+        self.headers
+            .get("Transport")?
+            .split(';')
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim() == key)
+            .and_then(|(_, value)| value.trim().parse().ok())
+    }
+}
+
 impl RtspClient {
     pub fn new() -> Self {
         // Generate random client identifiers
@@ -147,7 +160,7 @@ impl RtspClient {
         let body = if let Some(length_str) = headers.get("Content-Length") {
             let length: usize = length_str.parse()?;
             let mut body_data = vec![0u8; length];
-            reader.get_mut().read_exact(&mut body_data).await?;
+            reader.read_exact(&mut body_data).await?;
             body_data
         } else {
             Vec::new()
@@ -215,6 +228,70 @@ impl RtspClient {
         self.send_request("FLUSH", uri, &headers, None).await
     }
 
+    /// Send SET_PARAMETER request
+    pub async fn set_parameter(
+        &mut self,
+        uri: &str,
+        content_type: &str,
+        rtp_info: Option<&str>,
+        body: &[u8],
+    ) -> Result<RtspResponse> {
+        let mut headers = vec![("Content-Type", content_type)];
+        if let Some(rtp_info) = rtp_info {
+            headers.push(("RTP-Info", rtp_info));
+        }
+        self.send_request("SET_PARAMETER", uri, &headers, Some(body))
+            .await
+    }
+
+    /// Set receiver volume in dB (-30.0 to 0.0, or -144.0 for mute)
+    pub async fn set_volume(&mut self, uri: &str, volume_db: f32) -> Result<RtspResponse> {
+        let body = format!("volume: {:.6}\r\n", volume_db);
+        self.set_parameter(uri, "text/parameters", None, body.as_bytes())
+            .await
+    }
+
+    /// Send track metadata (DMAP-encoded) for the audio starting at `rtptime`
+    pub async fn set_metadata(
+        &mut self,
+        uri: &str,
+        rtptime: u32,
+        title: &str,
+        artist: Option<&str>,
+        album: Option<&str>,
+    ) -> Result<RtspResponse> {
+        let rtp_info = format!("rtptime={}", rtptime);
+        let body = encode_dmap_metadata(title, artist, album);
+        self.set_parameter(uri, "application/x-dmap-tagged", Some(&rtp_info), &body)
+            .await
+    }
+
+    /// Send cover artwork (`image/jpeg` or `image/png`) for the audio starting at `rtptime`
+    pub async fn set_artwork(
+        &mut self,
+        uri: &str,
+        rtptime: u32,
+        content_type: &str,
+        image: &[u8],
+    ) -> Result<RtspResponse> {
+        let rtp_info = format!("rtptime={}", rtptime);
+        self.set_parameter(uri, content_type, Some(&rtp_info), image)
+            .await
+    }
+
+    /// Send playback progress as RTP timestamps (track start, current position, track end)
+    pub async fn set_progress(
+        &mut self,
+        uri: &str,
+        start: u32,
+        current: u32,
+        end: u32,
+    ) -> Result<RtspResponse> {
+        let body = format!("progress: {}/{}/{}\r\n", start, current, end);
+        self.set_parameter(uri, "text/parameters", None, body.as_bytes())
+            .await
+    }
+
     /// Send TEARDOWN request (close session)
     pub async fn teardown(&mut self, uri: &str) -> Result<RtspResponse> {
         self.send_request("TEARDOWN", uri, &[], None).await
@@ -235,6 +312,77 @@ impl Default for RtspClient {
     }
 }
 
+/// RAOP volume used to mute the receiver
+pub const VOLUME_MUTE_DB: f32 = -144.0;
+
+/// Map a linear volume (0.0 - 1.0) to the RAOP dB range (-30.0 to 0.0, -144.0 = mute)
+pub fn volume_to_db(volume: f32) -> f32 {
+    let volume = volume.clamp(0.0, 1.0);
+    if volume == 0.0 {
+        VOLUME_MUTE_DB
+    } else {
+        -30.0 + 30.0 * volume
+    }
+}
+
+/// Inverse of [`volume_to_db`]: RAOP dB to a linear volume (0.0 - 1.0)
+pub fn db_to_volume(volume_db: f32) -> f32 {
+    if volume_db <= -30.0 {
+        0.0
+    } else {
+        ((volume_db + 30.0) / 30.0).min(1.0)
+    }
+}
+
+/// Encode track metadata as a DMAP `mlit` listing item
+pub fn encode_dmap_metadata(title: &str, artist: Option<&str>, album: Option<&str>) -> Vec<u8> {
+    fn item(tag: &[u8; 4], value: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(tag);
+        out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        out.extend_from_slice(value);
+    }
+
+    let mut items = Vec::new();
+    item(b"minm", title.as_bytes(), &mut items);
+    if let Some(artist) = artist {
+        item(b"asar", artist.as_bytes(), &mut items);
+    }
+    if let Some(album) = album {
+        item(b"asal", album.as_bytes(), &mut items);
+    }
+
+    let mut listing = Vec::new();
+    item(b"mlit", &items, &mut listing);
+    listing
+}
+
+/// Decode DMAP track metadata (receiver side of [`encode_dmap_metadata`])
+///
+/// `mlit` containers are descended into and unknown tags skipped.
+pub fn decode_dmap_metadata(data: &[u8]) -> TrackMeta {
+    fn walk(mut data: &[u8], meta: &mut TrackMeta) {
+        while data.len() >= 8 {
+            let len = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+            let Some(value) = data.get(8..8 + len) else { break };
+            let text = String::from_utf8_lossy(value).trim().to_string();
+            match &data[..4] {
+                b"mlit" => walk(value, meta),
+                b"minm" => meta.title = text,
+                b"asar" => meta.artist = text,
+                b"asal" => meta.album = text,
+                b"asgn" => meta.genre = text,
+                _ => {}
+            }
+            data = &data[8 + len..];
+        }
+    }
+
+    let mut meta = TrackMeta::default();
+    walk(data, &mut meta);
+    meta.device_genre = meta.genre.clone();
+    meta
+}
+
 /// Generate SDP (Session Description Protocol) for AirPlay audio
 pub fn generate_sdp(
     _sample_rate: u32,
@@ -254,6 +402,23 @@ pub fn generate_sdp(
     )
 }
 
+/// Generate SDP for an encrypted RAOP stream
+///
+/// `rsa_aes_key` is the RSA-OAEP wrapped AES key and `aes_iv` the IV, both base64
+/// without padding (see `AirPlayAuth`).
+pub fn generate_encrypted_sdp(
+    sample_rate: u32,
+    channels: u16,
+    fmtp: &str,
+    rsa_aes_key: &str,
+    aes_iv: &str,
+) -> String {
+    let mut sdp = generate_sdp(sample_rate, channels, fmtp);
+    sdp.push_str(&format!("a=rsaaeskey:{}\r\n", rsa_aes_key));
+    sdp.push_str(&format!("a=aesiv:{}\r\n", aes_iv));
+    sdp
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -264,6 +429,15 @@ mod tests {
         assert!(sdp.contains("m=audio"));
         assert!(sdp.contains("AppleLossless"));
         assert!(sdp.contains("44100"));
+        assert!(!sdp.contains("rsaaeskey"));
+    }
+
+    #[test]
+    fn test_generate_encrypted_sdp() {
+        let sdp = generate_encrypted_sdp(44100, 2, "352 0 16 40 10 14 2 255 0 0 44100", "KEY", "IV");
+        assert!(sdp.contains("a=fmtp:96 352"));
+        assert!(sdp.contains("a=rsaaeskey:KEY\r\n"));
+        assert!(sdp.ends_with("a=aesiv:IV\r\n"));
     }
 
     #[tokio::test]
@@ -272,4 +446,119 @@ mod tests {
         assert_eq!(client.cseq, 1);
         assert!(client.session.is_none());
     }
+
+    #[test]
+    fn test_volume_to_db() {
+        assert_eq!(volume_to_db(0.0), VOLUME_MUTE_DB);
+        assert_eq!(volume_to_db(1.0), 0.0);
+        assert_eq!(volume_to_db(0.5), -15.0);
+        assert_eq!(volume_to_db(2.0), 0.0);
+        assert_eq!(volume_to_db(-1.0), VOLUME_MUTE_DB);
+    }
+
+    #[test]
+    fn test_encode_dmap_metadata() {
+        let dmap = encode_dmap_metadata("Song", Some("Band"), None);
+
+        assert_eq!(&dmap[..4], b"mlit");
+        assert_eq!(u32::from_be_bytes(dmap[4..8].try_into().unwrap()) as usize, dmap.len() - 8);
+        assert_eq!(&dmap[8..12], b"minm");
+        assert_eq!(u32::from_be_bytes(dmap[12..16].try_into().unwrap()), 4);
+        assert_eq!(&dmap[16..20], b"Song");
+        assert_eq!(&dmap[20..24], b"asar");
+        assert_eq!(&dmap[28..32], b"Band");
+        assert_eq!(dmap.len(), 32);
+    }
+
+    #[test]
+    fn test_decode_dmap_metadata() {
+        let mut dmap = encode_dmap_metadata("Song", Some("Band"), Some("Album"));
+        // Genre and an unknown tag after the listing item
+        dmap.extend_from_slice(b"asgn\0\0\0\x04Jazz");
+        dmap.extend_from_slice(b"caps\0\0\0\x01\x01");
+
+        let meta = decode_dmap_metadata(&dmap);
+        assert_eq!(meta.title, "Song");
+        assert_eq!(meta.artist, "Band");
+        assert_eq!(meta.album, "Album");
+        assert_eq!(meta.genre, "Jazz");
+        assert_eq!(meta.device_genre, "Jazz");
+
+        // Truncated items are ignored
+        assert_eq!(decode_dmap_metadata(&dmap[..20]).title, "");
+    }
+
+    #[test]
+    fn test_db_to_volume() {
+        assert_eq!(db_to_volume(VOLUME_MUTE_DB), 0.0);
+        assert_eq!(db_to_volume(0.0), 1.0);
+        assert_eq!(db_to_volume(volume_to_db(0.5)), 0.5);
+    }
+
+    #[test]
+    fn test_transport_port() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Transport".to_string(),
+            "RTP/AVP/UDP;unicast;mode=record;server_port=53561;control_port=63379;timing_port=50607"
+                .to_string(),
+        );
+        let response = RtspResponse {
+            status_code: 200,
+            status_text: "OK".to_string(),
+            headers,
+            body: Vec::new(),
+        };
+
+        assert_eq!(response.transport_port("server_port"), Some(53561));
+        assert_eq!(response.transport_port("control_port"), Some(63379));
+        assert_eq!(response.transport_port("timing_port"), Some(50607));
+        assert_eq!(response.transport_port("missing"), None);
+    }
+
+    #[tokio::test]
+    async fn test_set_volume_request() {
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                if let Some(len) = line.strip_prefix("Content-Length: ") {
+                    content_length = len.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).await.unwrap();
+
+            reader
+                .get_mut()
+                .write_all(b"RTSP/1.0 200 OK\r\nCSeq: 1\r\n\r\n")
+                .await
+                .unwrap();
+            (request, String::from_utf8(body).unwrap())
+        });
+
+        let mut client = RtspClient::new();
+        client.connect("127.0.0.1", port).await.unwrap();
+        let response = client.set_volume("rtsp://127.0.0.1", -15.0).await.unwrap();
+        assert_eq!(response.status_code, 200);
+
+        let (request, body) = server.await.unwrap();
+        assert!(request.starts_with("SET_PARAMETER rtsp://127.0.0.1 RTSP/1.0\r\n"));
+        assert!(request.contains("Content-Type: text/parameters\r\n"));
+        assert_eq!(body, "volume: -15.000000\r\n");
+    }
 }
@@ -1,251 +0,0 @@
-use crate::convert::convert_format;
-use crate::sink::OutputSink;
-use crate::types::{AudioBlock, OutputConfig, SampleFormat};
-use anyhow::{anyhow, Result};
-use async_trait::async_trait;
-use std::sync::{Arc, Mutex};
-use tracing::{debug, info, warn};
-
-/// AirPlay 2 output sink
-///
-/// This is a stub implementation that demonstrates the interface.
-/// Full AirPlay 2 support requires integration with libraries like:
-/// - shairport-sync (C library, would need FFI bindings)
-/// - airplay2-receiver-rs (if available)
-///
-/// AirPlay 2 uses ALAC (Apple Lossless) codec which is lossless but
-/// typically limited to 16-bit/44.1-48kHz.
-pub struct AirPlaySink {
-    device_name: String,
-    device_address: Option<String>,
-    config: Option<OutputConfig>,
-    buffer: Arc<Mutex<Vec<u8>>>,
-    is_open: bool,
-}
-
-impl AirPlaySink {
-    /// Create a new AirPlay sink
-    pub fn new(device_name: String) -> Self {
-        Self {
-            device_name,
-            device_address: None,
-            config: None,
-            buffer: Arc::new(Mutex::new(Vec::new())),
-            is_open: false,
-        }
-    }
-
-    /// Set the target AirPlay device address
-    pub fn set_device_address(&mut self, address: String) {
-        self.device_address = Some(address);
-    }
-
-    /// Discover AirPlay devices on the network
-    /// This would use mDNS/Bonjour to find _airplay._tcp services
-    pub async fn discover_devices() -> Result<Vec<String>> {
-        warn!("AirPlay device discovery not yet implemented");
-        // In a real implementation, this would:
-        // 1. Use mdns-sd to search for _airplay._tcp services
-        // 2. Parse TXT records to get device capabilities
-        // 3. Return list of device names and addresses
-        Ok(Vec::new())
-    }
-
-    /// Encode PCM audio to ALAC
-    /// This would use the ALAC encoder
-    fn encode_to_alac(&self, pcm_data: &[u8]) -> Result<Vec<u8>> {
-        warn!("ALAC encoding not yet implemented, returning raw PCM");
-        // In a real implementation, this would:
-        // 1. Use an ALAC encoder library (alac-encoder-rs or FFI to Apple's encoder)
-        // 2. Encode PCM to ALAC frames
-        // 3. Return compressed ALAC data
-        Ok(pcm_data.to_vec())
-    }
-
-    /// Send audio data to AirPlay device
-    async fn send_to_device(&self, data: &[u8]) -> Result<()> {
-        // In a real implementation, this would:
-        // 1. Establish RTSP connection to device
-        // 2. Negotiate audio format and encryption
-        // 3. Send audio frames via RTP
-        // 4. Handle timing and synchronization
-        debug!("Would send {} bytes to AirPlay device", data.len());
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl OutputSink for AirPlaySink {
-    fn name(&self) -> &'static str {
-        "airplay"
-    }
-
-    async fn open(&mut self, cfg: OutputConfig) -> Result<()> {
-        debug!("Opening AirPlay sink for device: {}", self.device_name);
-
-        // AirPlay 2 typically uses 16-bit/44.1-48kHz
-        if cfg.sample_rate > 48000 {
-            warn!(
-                "AirPlay typically supports up to 48kHz, requested {}Hz",
-                cfg.sample_rate
-            );
-        }
-
-        if cfg.format.bit_depth() > 16 {
-            warn!(
-                "AirPlay ALAC is typically 16-bit, requested {}-bit",
-                cfg.format.bit_depth()
-            );
-        }
-
-        if self.device_address.is_none() {
-            return Err(anyhow!(
-                "No AirPlay device address set. Use set_device_address() or discover_devices()"
-            ));
-        }
-
-        // In a real implementation, this would:
-        // 1. Connect to the AirPlay device via RTSP
-        // 2. Perform authentication if required
-        // 3. Set up RTP stream for audio
-        // 4. Initialize ALAC encoder
-
-        info!(
-            "AirPlay sink opened for device: {} (STUB IMPLEMENTATION)",
-            self.device_name
-        );
-        info!("Full AirPlay 2 support requires additional libraries");
-
-        self.config = Some(cfg);
-        self.is_open = true;
-
-        Ok(())
-    }
-
-    async fn write(&mut self, block: AudioBlock<'_>) -> Result<()> {
-        if !self.is_open {
-            return Err(anyhow!("AirPlay sink not open"));
-        }
-
-        let _cfg = self.config.as_ref().unwrap();
-
-        // Convert to 16-bit PCM (AirPlay's typical format)
-        let mut pcm_data = Vec::new();
-        convert_format(block, SampleFormat::S16LE, &mut pcm_data)?;
-
-        // Encode to ALAC
-        let alac_data = self.encode_to_alac(&pcm_data)?;
-
-        // Buffer the data and check if we should send
-        let data_to_send = {
-            let mut buffer = self.buffer.lock().unwrap();
-            buffer.extend_from_slice(&alac_data);
-
-            // In a real implementation, send when buffer reaches threshold
-            if buffer.len() >= 4096 {
-                let data = buffer.clone();
-                buffer.clear();
-                Some(data)
-            } else {
-                None
-            }
-        }; // Lock released here
-
-        // Send outside of lock
-        if let Some(data) = data_to_send {
-            self.send_to_device(&data).await?;
-        }
-
-        Ok(())
-    }
-
-    async fn drain(&mut self) -> Result<()> {
-        // Send any remaining buffered data
-        let data_to_send = {
-            let mut buffer = self.buffer.lock().unwrap();
-            let data = buffer.clone();
-            buffer.clear();
-            data
-        };
-
-        if !data_to_send.is_empty() {
-            self.send_to_device(&data_to_send).await?;
-        }
-
-        Ok(())
-    }
-
-    async fn close(&mut self) -> Result<()> {
-        debug!("Closing AirPlay sink");
-
-        // Drain remaining data
-        self.drain().await?;
-
-        // In a real implementation, this would:
-        // 1. Send RTSP TEARDOWN
-        // 2. Close RTP connection
-        // 3. Clean up encoder resources
-
-        self.config = None;
-        self.is_open = false;
-
-        info!("AirPlay sink closed");
-        Ok(())
-    }
-
-    fn latency_ms(&self) -> u32 {
-        // AirPlay typically has 2+ seconds of latency due to buffering
-        // This is intentional for network reliability and multi-room sync
-        if let Some(cfg) = &self.config {
-            2000 + cfg.buffer_ms // Base 2s + configured buffer
-        } else {
-            0
-        }
-    }
-
-    fn is_open(&self) -> bool {
-        self.is_open
-    }
-}
-
-/// Information about an AirPlay device
-#[derive(Debug, Clone)]
-pub struct AirPlayDevice {
-    pub name: String,
-    pub address: String,
-    pub model: Option<String>,
-    pub supports_audio: bool,
-    pub supports_video: bool,
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[tokio::test]
-    async fn test_airplay_sink_create() {
-        let sink = AirPlaySink::new("Living Room".to_string());
-        assert_eq!(sink.name(), "airplay");
-        assert!(!sink.is_open());
-    }
-
-    #[tokio::test]
-    async fn test_airplay_sink_requires_address() {
-        let mut sink = AirPlaySink::new("Test Device".to_string());
-        let config = OutputConfig::default();
-
-        let result = sink.open(config).await;
-        assert!(result.is_err());
-    }
-
-    #[tokio::test]
-    async fn test_airplay_sink_with_address() {
-        let mut sink = AirPlaySink::new("Test Device".to_string());
-        sink.set_device_address("192.168.1.100:7000".to_string());
-
-        let config = OutputConfig::default();
-        sink.open(config).await.unwrap();
-
-        assert!(sink.is_open());
-    }
-}
@@ -1,23 +1,45 @@
 use crate::convert::convert_format;
 use crate::sink::OutputSink;
 use crate::sinks::airplay::*;
+use crate::sinks::dlna::MediaMetadata;
 use crate::types::{AudioBlock, OutputConfig, SampleFormat};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use tracing::{debug, info};
+use std::net::SocketAddr;
+use tracing::{debug, info, warn};
+
+/// Audio frames per RAOP packet
+const FRAMES_PER_PACKET: u32 = 352;
+
+/// Ports assumed when the SETUP response doesn't name them
+const DEFAULT_SERVER_PORT: u16 = 6000;
+const DEFAULT_CONTROL_PORT: u16 = 6001;
+
+/// Playback latency announced in sync packets unless the receiver sends `Audio-Latency`
+const DEFAULT_LATENCY_FRAMES: u32 = 11025;
 
 /// AirPlay output sink with full RAOP protocol support
 pub struct AirPlaySink {
     device: Option<AirPlayDevice>,
     config: Option<OutputConfig>,
     rtsp_client: Option<RtspClient>,
+    rtsp_uri: Option<String>,
     rtp_stream: Option<RtpStream>,
-    rtcp_stream: Option<RtcpStream>,
+    control_channel: Option<ControlChannel>,
+    timing_server: Option<TimingServer>,
     encoder: Option<AlacEncoder>,
     auth: AirPlayAuth,
     is_open: bool,
     packets_sent: u32,
     bytes_sent: u32,
+    latency_frames: u32,
+    /// Next sync packet starts a new timeline (after RECORD or FLUSH)
+    sync_restart: bool,
+    /// Linear volume (0.0 - 1.0) to apply to the receiver
+    volume: Option<f32>,
+    metadata: Option<MediaMetadata>,
+    /// Cover artwork as (content type, image data)
+    artwork: Option<(String, Vec<u8>)>,
 }
 
 impl AirPlaySink {
@@ -27,13 +49,20 @@ impl AirPlaySink {
             device: None,
             config: None,
             rtsp_client: None,
+            rtsp_uri: None,
             rtp_stream: None,
-            rtcp_stream: None,
+            control_channel: None,
+            timing_server: None,
             encoder: None,
             auth: AirPlayAuth::new(),
             is_open: false,
             packets_sent: 0,
             bytes_sent: 0,
+            latency_frames: DEFAULT_LATENCY_FRAMES,
+            sync_restart: true,
+            volume: None,
+            metadata: None,
+            artwork: None,
         }
     }
 
@@ -47,6 +76,113 @@ impl AirPlaySink {
         self.device = Some(device);
     }
 
+    /// Set the receiver's RSA public key to enable encrypted (RAOP) audio
+    pub fn set_rsa_public_key(&mut self, public_key: rsa::RsaPublicKey) {
+        self.auth.set_rsa_public_key(public_key);
+    }
+
+    /// Set the receiver volume (0.0 - 1.0, 0.0 mutes)
+    ///
+    /// Applied immediately when connected, otherwise once the stream starts.
+    pub async fn set_volume(&mut self, volume: f32) -> Result<()> {
+        let volume = volume.clamp(0.0, 1.0);
+        self.volume = Some(volume);
+
+        if let (Some(rtsp), Some(uri)) = (self.rtsp_client.as_mut(), &self.rtsp_uri) {
+            let resp = rtsp.set_volume(uri, volume_to_db(volume)).await?;
+            if resp.status_code != 200 {
+                return Err(anyhow!("SET_PARAMETER volume failed: {}", resp.status_text));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Discard audio queued on the receiver (e.g. on seek or track change)
+    ///
+    /// Audio buffered in the encoder is dropped, and the next packet restarts
+    /// the receiver's sync timeline.
+    pub async fn flush(&mut self) -> Result<()> {
+        let (Some(rtsp), Some(uri), Some(rtp)) =
+            (self.rtsp_client.as_mut(), &self.rtsp_uri, &self.rtp_stream)
+        else {
+            return Err(anyhow!("Sink not open"));
+        };
+
+        if let Some(encoder) = self.encoder.as_mut() {
+            encoder.reset();
+        }
+
+        let resp = rtsp.flush(uri, rtp.sequence_number(), rtp.timestamp()).await?;
+        if resp.status_code != 200 {
+            return Err(anyhow!("FLUSH failed: {}", resp.status_text));
+        }
+
+        self.sync_restart = true;
+        Ok(())
+    }
+
+    /// Current receiver volume, if one has been set
+    pub fn volume(&self) -> Option<f32> {
+        self.volume
+    }
+
+    /// Set the track info shown on the receiver
+    pub async fn set_metadata(&mut self, metadata: MediaMetadata) -> Result<()> {
+        self.metadata = Some(metadata);
+        self.send_metadata().await
+    }
+
+    /// Set the cover artwork shown on the receiver (`image/jpeg` or `image/png`)
+    pub async fn set_artwork(&mut self, content_type: &str, image: Vec<u8>) -> Result<()> {
+        self.artwork = Some((content_type.to_string(), image));
+        self.send_artwork().await
+    }
+
+    async fn send_metadata(&mut self) -> Result<()> {
+        let (Some(rtsp), Some(uri), Some(rtp), Some(metadata)) = (
+            self.rtsp_client.as_mut(),
+            &self.rtsp_uri,
+            &self.rtp_stream,
+            &self.metadata,
+        ) else {
+            return Ok(());
+        };
+
+        let resp = rtsp
+            .set_metadata(
+                uri,
+                rtp.timestamp(),
+                &metadata.title,
+                metadata.artist.as_deref(),
+                metadata.album.as_deref(),
+            )
+            .await?;
+        if resp.status_code != 200 {
+            return Err(anyhow!("SET_PARAMETER metadata failed: {}", resp.status_text));
+        }
+
+        Ok(())
+    }
+
+    async fn send_artwork(&mut self) -> Result<()> {
+        let (Some(rtsp), Some(uri), Some(rtp), Some((content_type, image))) = (
+            self.rtsp_client.as_mut(),
+            &self.rtsp_uri,
+            &self.rtp_stream,
+            &self.artwork,
+        ) else {
+            return Ok(());
+        };
+
+        let resp = rtsp.set_artwork(uri, rtp.timestamp(), content_type, image).await?;
+        if resp.status_code != 200 {
+            return Err(anyhow!("SET_PARAMETER artwork failed: {}", resp.status_text));
+        }
+
+        Ok(())
+    }
+
     /// Find and set device by name
     pub async fn set_device_by_name(&mut self, name: &str, timeout_secs: u64) -> Result<()> {
         let device = find_device_by_name(name, timeout_secs)
@@ -72,7 +208,7 @@ impl AirPlaySink {
         let mut rtsp = RtspClient::new();
 
         // Get first IP address
-        let ip = device
+        let ip = *device
             .addresses
             .first()
             .ok_or_else(|| anyhow!("No IP address for device"))?;
@@ -122,27 +258,32 @@ impl AirPlaySink {
             return Err(anyhow!("Device requires Apple-Challenge/Response authentication, which is not yet implemented."));
         }
 
-        // Generate encryption keys (but don't send them for now - may trigger FairPlay rejection)
-        // self.auth.generate_encryption_keys();
-
         // Create ALAC encoder
         let alac_config = AlacConfig {
             sample_rate: cfg.sample_rate,
             channels: cfg.channels,
             bit_depth: 16,
-            frames_per_packet: 352,
+            frames_per_packet: FRAMES_PER_PACKET,
         };
 
         let encoder = AlacEncoder::new(alac_config.clone());
         let fmtp = encoder.fmtp_string();
 
-        // Create SDP (without encryption for now - Apple devices may reject encrypted streams
-        // from unauthorized clients due to FairPlay DRM)
-        let sdp = generate_sdp(cfg.sample_rate, cfg.channels, &fmtp);
-
-        // Note: Encryption keys omitted - Apple AirPlay 2 devices reject encrypted streams
-        // from non-paired clients. Try unencrypted first to see if device accepts it.
-        info!("Attempting connection without audio encryption (Apple devices may require pairing for encrypted streams)");
+        // Encrypt only when the receiver's RSA key is known; Apple AirPlay 2 devices
+        // reject encrypted streams from non-paired clients anyway
+        let sdp = if self.auth.can_encrypt() {
+            self.auth.generate_encryption_keys();
+            let rsa_aes_key = self.auth.get_rsa_aes_key_base64()?;
+            let aes_iv = self
+                .auth
+                .get_aes_iv_base64()
+                .ok_or_else(|| anyhow!("AES IV not initialized"))?;
+            info!("Using AES-128-CBC audio encryption (RSA-wrapped key)");
+            generate_encrypted_sdp(cfg.sample_rate, cfg.channels, &fmtp, &rsa_aes_key, &aes_iv)
+        } else {
+            info!("Attempting connection without audio encryption (no RSA public key configured)");
+            generate_sdp(cfg.sample_rate, cfg.channels, &fmtp)
+        };
 
         // Send ANNOUNCE
         info!("Sending ANNOUNCE with SDP:\n{}", sdp);
@@ -158,20 +299,17 @@ impl AirPlaySink {
             return Err(anyhow!("ANNOUNCE failed: {} (status {})", announce_resp.status_text, announce_resp.status_code));
         }
 
-        // Setup RTP streams
-        let local_rtp_port = 6000;
-        let local_rtcp_port = 6001;
-
-        let rtp_dest = format!("{}:{}", ip, 6000).parse()?;
-        let rtcp_dest = format!("{}:{}", ip, 6001).parse()?;
-
-        let rtp_stream = RtpStream::new(local_rtp_port, rtp_dest).await?;
-        let rtcp_stream = RtcpStream::new(local_rtcp_port, rtcp_dest).await?;
+        // Bind audio, control and timing sockets on ephemeral ports
+        let mut rtp_stream =
+            RtpStream::new(0, SocketAddr::new(ip, DEFAULT_SERVER_PORT)).await?;
+        let mut control_channel = ControlChannel::new(0, rtp_stream.history()).await?;
+        let timing_server = TimingServer::new(0).await?;
 
         // Send SETUP
         let transport = format!(
             "RTP/AVP/UDP;unicast;interleaved=0-1;mode=record;control_port={};timing_port={}",
-            local_rtcp_port, local_rtcp_port
+            control_channel.local_port()?,
+            timing_server.local_port()
         );
 
         let setup_resp = rtsp.setup(&uri, &transport).await?;
@@ -179,6 +317,22 @@ impl AirPlaySink {
             return Err(anyhow!("SETUP failed: {}", setup_resp.status_text));
         }
 
+        let server_port = setup_resp
+            .transport_port("server_port")
+            .unwrap_or(DEFAULT_SERVER_PORT);
+        let control_port = setup_resp
+            .transport_port("control_port")
+            .unwrap_or(DEFAULT_CONTROL_PORT);
+        info!(
+            "Receiver ports: server={}, control={}, timing={:?}",
+            server_port,
+            control_port,
+            setup_resp.transport_port("timing_port")
+        );
+
+        rtp_stream.set_destination(SocketAddr::new(ip, server_port));
+        control_channel.set_destination(SocketAddr::new(ip, control_port));
+
         // Send RECORD to start streaming
         let seq = rtp_stream.sequence_number();
         let rtptime = rtp_stream.timestamp();
@@ -188,16 +342,70 @@ impl AirPlaySink {
             return Err(anyhow!("RECORD failed: {}", record_resp.status_text));
         }
 
+        self.latency_frames = record_resp
+            .headers
+            .get("Audio-Latency")
+            .and_then(|latency| latency.trim().parse().ok())
+            .unwrap_or(DEFAULT_LATENCY_FRAMES);
+
         self.rtsp_client = Some(rtsp);
+        self.rtsp_uri = Some(uri);
         self.rtp_stream = Some(rtp_stream);
-        self.rtcp_stream = Some(rtcp_stream);
+        self.control_channel = Some(control_channel);
+        self.timing_server = Some(timing_server);
         self.encoder = Some(encoder);
+        self.packets_sent = 0;
+        self.bytes_sent = 0;
+        self.sync_restart = true;
+
+        // Re-apply receiver state set before the stream started
+        if let Some(volume) = self.volume {
+            if let Err(e) = self.set_volume(volume).await {
+                warn!("Failed to set AirPlay volume: {}", e);
+            }
+        }
+        if let Err(e) = self.send_metadata().await {
+            warn!("Failed to send AirPlay metadata: {}", e);
+        }
+        if let Err(e) = self.send_artwork().await {
+            warn!("Failed to send AirPlay artwork: {}", e);
+        }
 
         info!("AirPlay connection established");
         Ok(())
     }
 }
 
+impl AirPlaySink {
+    /// Encrypt and send one ALAC packet, with a sync packet about once a second
+    async fn send_audio_packet(&mut self, packet: &[u8]) -> Result<()> {
+        let rtp = self
+            .rtp_stream
+            .as_mut()
+            .ok_or_else(|| anyhow!("Sink not open"))?;
+
+        let sample_rate = self.config.as_ref().map_or(44100, |c| c.sample_rate);
+        let sync_interval = (sample_rate / FRAMES_PER_PACKET).max(1);
+
+        if self.sync_restart || self.packets_sent.is_multiple_of(sync_interval) {
+            if let Some(control) = self.control_channel.as_ref() {
+                control
+                    .send_sync(rtp.timestamp(), self.latency_frames, self.sync_restart)
+                    .await?;
+            }
+            self.sync_restart = false;
+        }
+
+        let encrypted = self.auth.encrypt_audio(packet)?;
+        rtp.send_packet(&encrypted, FRAMES_PER_PACKET).await?;
+
+        self.packets_sent = self.packets_sent.wrapping_add(1);
+        self.bytes_sent = self.bytes_sent.wrapping_add(encrypted.len() as u32);
+
+        Ok(())
+    }
+}
+
 impl Default for AirPlaySink {
     fn default() -> Self {
         Self::new()
@@ -247,45 +455,21 @@ impl OutputSink for AirPlaySink {
         let encoder = self.encoder.as_mut().unwrap();
         let packets = encoder.encode(&samples)?;
 
-        // Send packets via RTP
-        let rtp = self.rtp_stream.as_mut().unwrap();
-        let config = self.config.as_ref().unwrap();
-
         for packet in packets {
-            // Encrypt if needed
-            let encrypted = self.auth.encrypt_audio(&packet)?;
-
-            // Send RTP packet
-            let samples_in_packet = config.sample_rate / 100; // Approximate
-            rtp.send_packet(&encrypted, samples_in_packet).await?;
-
-            self.packets_sent += 1;
-            self.bytes_sent += encrypted.len() as u32;
-
-            // Send RTCP sender report periodically
-            if self.packets_sent % 100 == 0 {
-                let rtcp = self.rtcp_stream.as_ref().unwrap();
-                let ntp_ts = get_ntp_timestamp();
-                let rtp_ts = rtp.timestamp();
-                let ssrc = rtp.ssrc();
-
-                rtcp.send_sender_report(ssrc, ntp_ts, rtp_ts, self.packets_sent, self.bytes_sent)
-                    .await?;
-            }
+            self.send_audio_packet(&packet).await?;
         }
 
         Ok(())
     }
 
     async fn drain(&mut self) -> Result<()> {
-        if let Some(encoder) = self.encoder.as_mut() {
-            let final_packets = encoder.flush()?;
+        if self.rtp_stream.is_none() {
+            return Ok(());
+        }
 
-            if let Some(rtp) = self.rtp_stream.as_mut() {
-                for packet in final_packets {
-                    let encrypted = self.auth.encrypt_audio(&packet)?;
-                    rtp.send_packet(&encrypted, 352).await?;
-                }
+        if let Some(encoder) = self.encoder.as_mut() {
+            for packet in encoder.flush()? {
+                self.send_audio_packet(&packet).await?;
             }
         }
 
@@ -299,16 +483,16 @@ impl OutputSink for AirPlaySink {
         self.drain().await?;
 
         // Send TEARDOWN
-        if let (Some(rtsp), Some(device)) = (self.rtsp_client.as_mut(), &self.device) {
-            let ip = device.addresses.first().unwrap();
-            let uri = format!("rtsp://{}:{}", ip, device.port);
-            let _ = rtsp.teardown(&uri).await;
+        if let (Some(rtsp), Some(uri)) = (self.rtsp_client.as_mut(), &self.rtsp_uri) {
+            let _ = rtsp.teardown(uri).await;
             let _ = rtsp.close().await;
         }
 
         self.rtsp_client = None;
+        self.rtsp_uri = None;
         self.rtp_stream = None;
-        self.rtcp_stream = None;
+        self.control_channel = None;
+        self.timing_server = None;
         self.encoder = None;
         self.config = None;
         self.is_open = false;
@@ -346,4 +530,19 @@ mod tests {
         let result = sink.open(config).await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_volume_stored_before_open() {
+        let mut sink = AirPlaySink::new();
+        assert!(sink.volume().is_none());
+
+        // Not connected: stored for when the stream starts
+        sink.set_volume(1.5).await.unwrap();
+        assert_eq!(sink.volume(), Some(1.0));
+
+        sink.set_metadata(MediaMetadata::default()).await.unwrap();
+        sink.set_artwork("image/png", vec![0x89, b'P', b'N', b'G']).await.unwrap();
+        assert!(sink.metadata.is_some());
+        assert!(sink.artwork.is_some());
+    }
 }
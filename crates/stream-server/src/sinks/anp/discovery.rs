@@ -0,0 +1,227 @@
+use anyhow::Result;
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// mDNS service type advertised by ANP nodes
+pub const ANP_SERVICE_TYPE: &str = "_aaeq-anp._tcp.local.";
+
+/// Information about a discovered ANP node, from its mDNS TXT record (§3)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnpNode {
+    pub name: String,
+    pub uuid: String,
+    pub hostname: String,
+    pub port: u16,
+    pub addresses: Vec<IpAddr>,
+    /// Protocol version (`v`)
+    pub version: String,
+    /// WebSocket control URL (`ctrl`)
+    pub control_url: String,
+    pub sample_rates: Vec<u32>,
+    /// Abbreviated formats such as `S16`, `S24`, `F32` (`bd`)
+    pub formats: Vec<String>,
+    pub channels: u16,
+    /// Abbreviated core features (`ft`)
+    pub features: Vec<String>,
+    /// Abbreviated optional features (`opt`)
+    pub optional_features: Vec<String>,
+    /// Node state (`st`), e.g. `idle` or `playing`
+    pub state: Option<String>,
+    /// Volume in percent (`vol`)
+    pub volume: Option<u8>,
+    pub dac: Option<String>,
+    pub hardware: Option<String>,
+}
+
+impl AnpNode {
+    /// Build a node description from its TXT record properties
+    pub fn from_txt(
+        name: &str,
+        hostname: &str,
+        addresses: Vec<IpAddr>,
+        port: u16,
+        txt: &HashMap<String, String>,
+    ) -> Self {
+        let get = |key: &str| txt.get(key).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let list = |key: &str| -> Vec<String> {
+            get(key)
+                .map(|v| v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+                .unwrap_or_default()
+        };
+
+        let host = addresses
+            .first()
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| hostname.trim_end_matches('.').to_string());
+        let control_url = get("ctrl").unwrap_or_else(|| format!("ws://{}:{}", host, port));
+
+        Self {
+            name: name.to_string(),
+            uuid: get("uuid").unwrap_or_default(),
+            hostname: hostname.to_string(),
+            port,
+            addresses,
+            version: get("v").unwrap_or_default(),
+            control_url,
+            sample_rates: list("sr").iter().filter_map(|s| s.parse().ok()).collect(),
+            formats: list("bd"),
+            channels: get("ch").and_then(|v| v.parse().ok()).unwrap_or(2),
+            features: list("ft"),
+            optional_features: list("opt"),
+            state: get("st"),
+            volume: get("vol").and_then(|v| v.parse().ok()),
+            dac: get("dac"),
+            hardware: get("hw"),
+        }
+    }
+
+    /// Describe a node at a known control address, bypassing mDNS discovery
+    pub fn from_address(name: &str, address: SocketAddr) -> Self {
+        let mut txt = HashMap::new();
+        txt.insert("ctrl".to_string(), format!("ws://{}", address));
+        Self::from_txt(name, &address.ip().to_string(), vec![address.ip()], address.port(), &txt)
+    }
+
+    pub fn supports_sample_rate(&self, sample_rate: u32) -> bool {
+        self.sample_rates.is_empty() || self.sample_rates.contains(&sample_rate)
+    }
+}
+
+/// Discover ANP nodes on the local network using mDNS
+pub async fn discover_nodes(timeout_secs: u64) -> Result<Vec<AnpNode>> {
+    info!("Starting ANP node discovery...");
+
+    let mdns = ServiceDaemon::new()?;
+    let receiver = mdns.browse(ANP_SERVICE_TYPE)?;
+
+    let mut nodes: Vec<AnpNode> = Vec::new();
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < Duration::from_secs(timeout_secs) {
+        match tokio::time::timeout(
+            Duration::from_millis(100),
+            tokio::task::spawn_blocking({
+                let receiver = receiver.clone();
+                move || receiver.recv_timeout(Duration::from_millis(100))
+            }),
+        )
+        .await
+        {
+            Ok(Ok(Ok(ServiceEvent::ServiceResolved(info)))) => {
+                debug!("Discovered ANP node: {}", info.get_fullname());
+
+                let txt: HashMap<String, String> = info
+                    .get_properties()
+                    .iter()
+                    .map(|p| (p.key().to_string(), p.val_str().to_string()))
+                    .collect();
+                let name = info
+                    .get_fullname()
+                    .trim_end_matches(ANP_SERVICE_TYPE)
+                    .trim_end_matches('.')
+                    .to_string();
+
+                let node = AnpNode::from_txt(
+                    &name,
+                    info.get_hostname(),
+                    info.get_addresses().iter().copied().collect(),
+                    info.get_port(),
+                    &txt,
+                );
+
+                info!("Found ANP node: {} ({}) at {}", node.name, node.uuid, node.control_url);
+
+                // Nodes re-announce; keep one entry per UUID
+                nodes.retain(|n| n.uuid != node.uuid || node.uuid.is_empty());
+                nodes.push(node);
+            }
+            Ok(Ok(Ok(ServiceEvent::ServiceRemoved(_, fullname)))) => {
+                debug!("ANP node removed: {}", fullname);
+            }
+            Ok(Ok(Ok(_))) => {}
+            Ok(Ok(Err(_))) | Ok(Err(_)) | Err(_) => {
+                // Timeout or task error, continue
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    if let Err(e) = mdns.shutdown() {
+        debug!("mDNS shutdown error (harmless): {}", e);
+    }
+
+    info!("Discovery complete. Found {} ANP node(s)", nodes.len());
+    Ok(nodes)
+}
+
+/// Find a node by UUID or (partial) name
+pub async fn find_node(name_or_uuid: &str, timeout_secs: u64) -> Result<Option<AnpNode>> {
+    let nodes = discover_nodes(timeout_secs).await?;
+    let needle = name_or_uuid.to_lowercase();
+
+    Ok(nodes
+        .into_iter()
+        .find(|n| n.uuid.eq_ignore_ascii_case(name_or_uuid) || n.name.to_lowercase().contains(&needle)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec_txt_record() {
+        let txt: HashMap<String, String> = [
+            ("uuid", "550e8400-e29b-41d4-a716-446655440000"),
+            ("v", "0.4.0"),
+            ("sr", "44100,48000,96000,192000"),
+            ("bd", "S16,S24,F32"),
+            ("ch", "2"),
+            ("ft", "pll,crc,vol,gap,cap"),
+            ("opt", "dsp,conv"),
+            ("ctrl", "ws://10.0.0.10:7443"),
+            ("st", "idle"),
+            ("vol", "75"),
+            ("dac", "HiFiBerry DAC+"),
+            ("hw", "RPi4"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let node = AnpNode::from_txt("Living Room", "pi.local.", vec!["10.0.0.10".parse().unwrap()], 7443, &txt);
+        assert_eq!(node.uuid, "550e8400-e29b-41d4-a716-446655440000");
+        assert_eq!(node.sample_rates, vec![44100, 48000, 96000, 192000]);
+        assert_eq!(node.formats, vec!["S16", "S24", "F32"]);
+        assert_eq!(node.features.len(), 5);
+        assert_eq!(node.optional_features, vec!["dsp", "conv"]);
+        assert_eq!(node.control_url, "ws://10.0.0.10:7443");
+        assert_eq!(node.volume, Some(75));
+        assert_eq!(node.dac.as_deref(), Some("HiFiBerry DAC+"));
+        assert!(node.supports_sample_rate(96000));
+        assert!(!node.supports_sample_rate(22050));
+    }
+
+    #[test]
+    fn test_missing_ctrl_falls_back_to_service_address() {
+        let node = AnpNode::from_txt("Node", "node.local.", vec!["192.168.1.5".parse().unwrap()], 7443, &HashMap::new());
+        assert_eq!(node.control_url, "ws://192.168.1.5:7443");
+        assert_eq!(node.channels, 2);
+        assert!(node.supports_sample_rate(44100));
+
+        let direct = AnpNode::from_address("Direct", "127.0.0.1:9000".parse().unwrap());
+        assert_eq!(direct.control_url, "ws://127.0.0.1:9000");
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network and actual ANP nodes
+    async fn test_discover_nodes() {
+        let nodes = discover_nodes(5).await.unwrap();
+        println!("Found {} nodes", nodes.len());
+        for node in nodes {
+            println!("  - {} ({}) at {}", node.name, node.uuid, node.control_url);
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use super::protocol::*;
+use crate::dsp::{Dither, DitherMode, FilterType, NoiseShaping, ParametricBand};
+use aaeq_core::{DspSettings, EqPreset};
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Longest impulse response sent inline in `convolution_response`
+pub const MAX_INLINE_TAPS: usize = 65_536;
+
+/// FIR filter offered to nodes for convolution
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirFilterData {
+    pub sample_rate: u32,
+    pub taps: Vec<f32>,
+}
+
+/// `sha256:<hex>` checksum of inline filter taps
+pub fn fir_checksum(taps: &[f32]) -> String {
+    let mut hasher = Sha256::new();
+    for tap in taps {
+        hasher.update(tap.to_le_bytes());
+    }
+    let digest = hasher.finalize();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+impl DspUpdate {
+    /// Profile carrying a preset's bands as Q=1 peaking filters
+    pub fn from_preset(profile_id: i64, profile_name: &str, preset: &EqPreset) -> Self {
+        Self {
+            profile_id,
+            profile_name: profile_name.to_string(),
+            headroom_db: 0.0,
+            dithering: "none".to_string(),
+            equalizer: EqualizerConfig {
+                name: preset.name.clone(),
+                enabled: !preset.bands.is_empty(),
+                bands: preset
+                    .bands
+                    .iter()
+                    .map(|band| DspBand {
+                        frequency: band.frequency as f64,
+                        gain: band.gain as f64,
+                        q: 1.0,
+                        band_type: BandType::Peak,
+                    })
+                    .collect(),
+            },
+            convolution: None,
+        }
+    }
+
+    /// Profile for a stored DSP configuration and its EQ preset
+    pub fn from_settings(profile_name: &str, settings: &DspSettings, preset: &EqPreset) -> Self {
+        let mut update = Self::from_preset(settings.profile_id, profile_name, preset);
+        update.headroom_db = settings.headroom_db;
+        update.dithering = dithering_name(settings.dither_enabled, &settings.dither_mode, settings.target_bits);
+        update
+    }
+
+    /// Ask the node to convolve with a filter registered on [`RemoteDsp`]
+    pub fn with_convolution(mut self, filter_id: &str, delay_samples: u32, gain_db: f32) -> Self {
+        self.convolution = Some(ConvolutionConfig {
+            enabled: true,
+            filter_id: filter_id.to_string(),
+            delay_samples,
+            gain_db,
+        });
+        self
+    }
+
+    /// Equalizer bands as the DSP chain takes them (empty when disabled)
+    pub fn eq_bands(&self) -> Vec<ParametricBand> {
+        if !self.equalizer.enabled {
+            return Vec::new();
+        }
+        self.equalizer
+            .bands
+            .iter()
+            .map(|band| ParametricBand {
+                filter_type: match band.band_type {
+                    BandType::Peak => FilterType::Peaking,
+                    BandType::LowShelf => FilterType::LowShelf,
+                    BandType::HighShelf => FilterType::HighShelf,
+                },
+                frequency: band.frequency,
+                gain_db: band.gain,
+                q: band.q,
+            })
+            .collect()
+    }
+
+    /// Dither stage described by `dithering`
+    pub fn dither(&self) -> Result<Option<Dither>> {
+        if self.dithering.is_empty() || self.dithering == "none" {
+            return Ok(None);
+        }
+        let parsed = self
+            .dithering
+            .split_once('_')
+            .and_then(|(mode, bits)| Some((mode, bits.strip_suffix("bit")?.parse::<u8>().ok()?)));
+        let Some((mode, bits)) = parsed else {
+            bail!("Invalid dithering '{}'", self.dithering);
+        };
+        let mode = match mode {
+            "tpdf" => DitherMode::Triangular,
+            "rpdf" => DitherMode::Rectangular,
+            "gaussian" => DitherMode::Gaussian,
+            other => bail!("Unsupported dither mode '{}'", other),
+        };
+        Ok(Some(Dither::new(mode, NoiseShaping::None, bits)))
+    }
+}
+
+/// Wire name for a dither configuration, e.g. `tpdf_24bit`
+fn dithering_name(enabled: bool, mode: &str, bits: u8) -> String {
+    let mode = match mode {
+        "Triangular" => "tpdf",
+        "Rectangular" => "rpdf",
+        "Gaussian" => "gaussian",
+        _ => return "none".to_string(),
+    };
+    if enabled {
+        format!("{}_{}bit", mode, bits)
+    } else {
+        "none".to_string()
+    }
+}
+
+#[derive(Debug, Default)]
+struct RemoteDspState {
+    profile: Option<DspUpdate>,
+    ack: Option<DspUpdateAck>,
+    filters: HashMap<String, FirFilterData>,
+    /// Whether the current profile was already re-sent after a hash mismatch
+    resent: bool,
+    /// Control channel of the current session, when it negotiated `dsp_transfer`
+    outgoing: Option<mpsc::UnboundedSender<ControlMessage>>,
+}
+
+/// Handle for offloading DSP to an ANP node
+///
+/// Clones share state, so a handle taken from the sink keeps working after
+/// the sink has been moved into an output manager. The latest profile is
+/// re-sent whenever a new session is established.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteDsp {
+    state: Arc<Mutex<RemoteDspState>>,
+}
+
+impl RemoteDsp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send a DSP profile to the node (or queue it until the session opens)
+    pub fn push(&self, update: DspUpdate) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        info!(
+            "Pushing DSP profile '{}' ({} EQ bands) to ANP node",
+            update.profile_name,
+            update.equalizer.bands.len()
+        );
+        state.ack = None;
+        state.resent = false;
+        state.profile = Some(update.clone());
+        match state.outgoing.as_ref() {
+            Some(outgoing) => outgoing
+                .send(ControlMessage::DspUpdate(update))
+                .map_err(|_| anyhow!("ANP control channel closed")),
+            None => Ok(()),
+        }
+    }
+
+    /// Make a filter available to `convolution_request`
+    pub fn add_filter(&self, filter_id: &str, filter: FirFilterData) {
+        self.state.lock().unwrap().filters.insert(filter_id.to_string(), filter);
+    }
+
+    /// Profile most recently pushed
+    pub fn profile(&self) -> Option<DspUpdate> {
+        self.state.lock().unwrap().profile.clone()
+    }
+
+    /// Node's acknowledgement of the current profile
+    pub fn last_ack(&self) -> Option<DspUpdateAck> {
+        self.state.lock().unwrap().ack.clone()
+    }
+
+    /// Whether the current session can receive DSP profiles
+    pub fn is_available(&self) -> bool {
+        self.state.lock().unwrap().outgoing.is_some()
+    }
+
+    /// Whether the node is running the current profile's equalizer, so the
+    /// server should bypass its own
+    pub fn eq_offloaded(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        match (&state.profile, &state.ack) {
+            (Some(profile), Some(ack)) => {
+                ack.profile_id == profile.profile_id && ack.status != DspStatus::Failed && ack.applied.equalizer
+            }
+            _ => false,
+        }
+    }
+
+    /// Start using a session's control channel, re-sending the current profile
+    pub(crate) fn attach(&self, outgoing: mpsc::UnboundedSender<ControlMessage>) {
+        let mut state = self.state.lock().unwrap();
+        state.ack = None;
+        state.resent = false;
+        if let Some(profile) = state.profile.clone() {
+            if outgoing.send(ControlMessage::DspUpdate(profile)).is_err() {
+                debug!("ANP control channel closed before DSP profile was sent");
+            }
+        }
+        state.outgoing = Some(outgoing);
+    }
+
+    /// Stop using a session's control channel (unless another session
+    /// has attached since)
+    pub(crate) fn detach(&self, outgoing: &mpsc::UnboundedSender<ControlMessage>) {
+        let mut state = self.state.lock().unwrap();
+        if state.outgoing.as_ref().is_some_and(|current| current.same_channel(outgoing)) {
+            state.outgoing = None;
+        }
+    }
+
+    pub(crate) fn handle_ack(&self, ack: DspUpdateAck) {
+        let mut state = self.state.lock().unwrap();
+        for error in &ack.errors {
+            warn!("ANP node DSP error {}: {}", error.code, error.message);
+        }
+        if let Some(profile) = state.profile.clone() {
+            if profile.profile_id == ack.profile_id && profile.profile_hash() != ack.profile_hash {
+                warn!(
+                    "ANP node applied profile {} with hash {:08x}, expected {:08x} (E504)",
+                    ack.profile_id,
+                    ack.profile_hash,
+                    profile.profile_hash()
+                );
+                // §13.5: resend once; a second mismatch is only reported
+                if !state.resent {
+                    state.resent = true;
+                    if let Some(outgoing) = state.outgoing.as_ref() {
+                        outgoing.send(ControlMessage::DspUpdate(profile)).ok();
+                    }
+                }
+            }
+        }
+        info!("ANP node DSP profile {}: {:?} {:?}", ack.profile_id, ack.status, ack.applied);
+        state.ack = Some(ack);
+    }
+
+    /// Answer a node's request for a convolution filter
+    pub(crate) fn convolution_response(&self, request: &ConvolutionRequest) -> ControlMessage {
+        let state = self.state.lock().unwrap();
+        match state.filters.get(&request.filter_id) {
+            Some(filter) if filter.taps.len() <= MAX_INLINE_TAPS => {
+                ControlMessage::ConvolutionResponse(ConvolutionResponse {
+                    filter_id: request.filter_id.clone(),
+                    ir_url: None,
+                    format: "f32le".to_string(),
+                    sample_rate: filter.sample_rate,
+                    length_samples: filter.taps.len() as u32,
+                    checksum: fir_checksum(&filter.taps),
+                    taps: Some(filter.taps.clone()),
+                })
+            }
+            Some(filter) => ControlMessage::Error(ErrorReport::new(
+                "E502",
+                ErrorSeverity::Warning,
+                format!(
+                    "Filter '{}' has {} taps, more than can be sent inline",
+                    request.filter_id,
+                    filter.taps.len()
+                ),
+            )),
+            None => ControlMessage::Error(ErrorReport::new(
+                "E502",
+                ErrorSeverity::Warning,
+                format!("Unknown convolution filter '{}'", request.filter_id),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aaeq_core::EqBand;
+
+    fn preset() -> EqPreset {
+        EqPreset {
+            name: "Rock".to_string(),
+            bands: vec![
+                EqBand { frequency: 62, gain: 4.0 },
+                EqBand { frequency: 16000, gain: 3.5 },
+            ],
+            curve_data: None,
+        }
+    }
+
+    #[test]
+    fn test_update_from_settings() {
+        let settings = DspSettings {
+            profile_id: 42,
+            headroom_db: -6.0,
+            dither_enabled: true,
+            dither_mode: "Triangular".to_string(),
+            target_bits: 24,
+            ..Default::default()
+        };
+        let update = DspUpdate::from_settings("Rock - Living Room", &settings, &preset());
+
+        assert_eq!(update.profile_id, 42);
+        assert_eq!(update.dithering, "tpdf_24bit");
+        assert_eq!(update.headroom_db, -6.0);
+        assert!(update.equalizer.enabled);
+        assert_eq!(update.equalizer.bands[1].frequency, 16000.0);
+        assert_eq!(update.eq_bands()[0].filter_type, FilterType::Peaking);
+        assert_eq!(update.dither().unwrap().unwrap().target_bits(), 24);
+
+        let json = ControlMessage::DspUpdate(update.clone()).to_json();
+        assert!(json.starts_with(r#"{"dsp_update":{"profile_id":42"#));
+        assert!(json.contains(r#""type":"peak""#));
+        match ControlMessage::from_json(&json).unwrap() {
+            ControlMessage::DspUpdate(parsed) => assert_eq!(parsed.profile_hash(), update.profile_hash()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dithering_names() {
+        let mut update = DspUpdate::default();
+        assert!(update.dither().unwrap().is_none());
+
+        update.dithering = "rpdf_16bit".to_string();
+        assert_eq!(update.dither().unwrap().unwrap().mode(), DitherMode::Rectangular);
+
+        update.dithering = "shaped_16bit".to_string();
+        assert!(update.dither().is_err());
+        update.dithering = "tpdf".to_string();
+        assert!(update.dither().is_err());
+
+        assert_eq!(dithering_name(false, "Triangular", 24), "none");
+        assert_eq!(dithering_name(true, "None", 24), "none");
+        assert_eq!(dithering_name(true, "Gaussian", 16), "gaussian_16bit");
+    }
+
+    #[test]
+    fn test_parse_spec_ack() {
+        let json = r#"{"dsp_update_ack":{"profile_id":42,"status":"partial","profile_hash":12345678,
+            "applied":{"equalizer":true,"headroom":true,"dithering":true,"convolution":false},
+            "errors":[{"code":"E502","message":"Convolution: insufficient CPU for FFT processing"}],
+            "fallback":{"convolution":"server_side"}}}"#;
+        let ControlMessage::DspUpdateAck(ack) = ControlMessage::from_json(json).unwrap() else {
+            panic!("expected dsp_update_ack");
+        };
+        assert_eq!(ack.status, DspStatus::Partial);
+        assert!(ack.applied.equalizer && !ack.applied.convolution);
+        assert_eq!(ack.fallback.get("convolution").map(String::as_str), Some("server_side"));
+    }
+
+    #[test]
+    fn test_remote_dsp_resends_profile_on_attach() {
+        let dsp = RemoteDsp::new();
+        dsp.push(DspUpdate::from_preset(7, "Flat", &EqPreset::default())).unwrap();
+        assert!(!dsp.is_available());
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        dsp.attach(tx.clone());
+        assert!(matches!(rx.try_recv(), Ok(ControlMessage::DspUpdate(u)) if u.profile_id == 7));
+
+        assert!(!dsp.eq_offloaded());
+        let hash = dsp.profile().unwrap().profile_hash();
+        dsp.handle_ack(DspUpdateAck {
+            profile_id: 7,
+            profile_hash: hash,
+            applied: AppliedDsp {
+                equalizer: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert!(dsp.eq_offloaded());
+        assert!(rx.try_recv().is_err());
+
+        // A hash mismatch makes the server resend the profile, once
+        for _ in 0..2 {
+            dsp.handle_ack(DspUpdateAck {
+                profile_id: 7,
+                profile_hash: hash.wrapping_add(1),
+                ..Default::default()
+            });
+        }
+        assert!(matches!(rx.try_recv(), Ok(ControlMessage::DspUpdate(_))));
+        assert!(rx.try_recv().is_err());
+
+        let (other, _) = mpsc::unbounded_channel();
+        dsp.detach(&other);
+        assert!(dsp.is_available());
+        dsp.detach(&tx);
+        assert!(!dsp.is_available());
+    }
+
+    #[test]
+    fn test_convolution_response() {
+        let dsp = RemoteDsp::new();
+        dsp.add_filter("room", FirFilterData { sample_rate: 48000, taps: vec![1.0, 0.5] });
+
+        let request = ConvolutionRequest {
+            cmd: "get_ir_url".to_string(),
+            filter_id: "room".to_string(),
+        };
+        match dsp.convolution_response(&request) {
+            ControlMessage::ConvolutionResponse(response) => {
+                assert_eq!(response.length_samples, 2);
+                assert_eq!(response.checksum, fir_checksum(&[1.0, 0.5]));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let missing = ConvolutionRequest {
+            filter_id: "other".to_string(),
+            ..request
+        };
+        assert!(matches!(dsp.convolution_response(&missing), ControlMessage::Error(e) if e.code == "E502"));
+    }
+}
@@ -0,0 +1,14 @@
+mod discovery;
+mod dsp;
+mod protocol;
+mod rtp;
+mod session;
+
+pub use discovery::{discover_nodes, find_node, AnpNode, ANP_SERVICE_TYPE};
+pub use dsp::{fir_checksum, FirFilterData, RemoteDsp, MAX_INLINE_TAPS};
+pub use protocol::*;
+pub use rtp::{
+    frames_per_packet, sender_report, swap_sample_bytes, RtpPacket, RtpPacketizer, SendClock, TrackMarkers,
+    MAX_PAYLOAD_BYTES, PT_L16, PT_L24,
+};
+pub use session::{negotiate_session, ControlSession, SessionOptions};
@@ -0,0 +1,754 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// Protocol version spoken by this implementation
+pub const PROTOCOL_VERSION: &str = "0.4";
+
+/// Core features this server can run with a node
+pub const SUPPORTED_FEATURES: &[&str] = &[
+    "micro_pll",
+    "crc_verify",
+    "volume_control",
+    "gapless",
+    "capabilities",
+    "latency_cal",
+];
+
+/// Optional features this server can run with a node
+pub const SUPPORTED_OPTIONAL_FEATURES: &[&str] = &["rtcp_sr", "dsp_transfer", "convolution"];
+
+/// RTP port used when the node doesn't announce one in `session_init`
+pub const DEFAULT_RTP_PORT: u16 = 5004;
+
+/// WebSocket control port a node listens on unless configured otherwise
+pub const DEFAULT_CONTROL_PORT: u16 = 7443;
+
+/// Control message exchanged over the WebSocket channel
+///
+/// Every message is a JSON object with a single key naming its type, e.g.
+/// `{"volume_set": {"level": 0.5, ...}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlMessage {
+    SessionInit(SessionInit),
+    SessionAccept(SessionAccept),
+    VolumeSet(VolumeSet),
+    VolumeGet {},
+    VolumeResult(VolumeResult),
+    Health(Health),
+    Error(ErrorReport),
+    StreamPause {},
+    StreamPaused {
+        #[serde(default)]
+        buffer_flushed: bool,
+    },
+    StreamResume {},
+    StreamStop {},
+    StreamStopped {},
+    GetStatus {},
+    DspUpdate(DspUpdate),
+    DspUpdateAck(DspUpdateAck),
+    ConvolutionRequest(ConvolutionRequest),
+    ConvolutionResponse(ConvolutionResponse),
+}
+
+impl ControlMessage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    /// Parse a frame that may bundle several messages, such as a `health`
+    /// report with an embedded `error` (§13.1)
+    pub fn parse_all(text: &str) -> Result<Vec<Self>> {
+        let value: serde_json::Value = serde_json::from_str(text)?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| anyhow!("ANP control message is not a JSON object"))?;
+
+        object
+            .iter()
+            .map(|(key, body)| {
+                let single = serde_json::Value::Object([(key.clone(), body.clone())].into_iter().collect());
+                serde_json::from_value(single).map_err(|e| anyhow!("Invalid ANP '{}' message: {}", key, e))
+            })
+            .collect()
+    }
+}
+
+/// Session proposal from the node (§4.1)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionInit {
+    pub protocol_version: String,
+    pub node_uuid: String,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub optional_features: Vec<String>,
+    #[serde(default)]
+    pub latency_comp: bool,
+    #[serde(default)]
+    pub node_capabilities: NodeCapabilities,
+    /// UDP port the node receives RTP on (RTCP goes to the next port up)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtp_port: Option<u16>,
+}
+
+/// Hardware and format capabilities reported by the node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeCapabilities {
+    pub hardware: String,
+    pub dac_name: String,
+    pub dac_chip: String,
+    pub max_sample_rate: u32,
+    pub supported_formats: Vec<String>,
+    pub native_format: String,
+    pub max_channels: u16,
+    pub buffer_range_ms: [u32; 2],
+    pub has_hardware_volume: bool,
+    pub volume_range: [f32; 2],
+    pub volume_curve: VolumeCurve,
+    /// DAC and pipeline latency the node measured, used for latency compensation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dac_latency_ms: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pipeline_latency_ms: Option<f32>,
+}
+
+impl Default for NodeCapabilities {
+    fn default() -> Self {
+        Self {
+            hardware: String::new(),
+            dac_name: String::new(),
+            dac_chip: String::new(),
+            max_sample_rate: 48000,
+            supported_formats: vec!["S16LE".to_string()],
+            native_format: "S16LE".to_string(),
+            max_channels: 2,
+            buffer_range_ms: [50, 500],
+            has_hardware_volume: false,
+            volume_range: [0.0, 1.0],
+            volume_curve: VolumeCurve::default(),
+            dac_latency_ms: None,
+            pipeline_latency_ms: None,
+        }
+    }
+}
+
+impl NodeCapabilities {
+    pub fn supports_format(&self, format: &str) -> bool {
+        self.supported_formats.iter().any(|f| f.eq_ignore_ascii_case(format))
+    }
+}
+
+/// Session parameters chosen by the server (§4.2)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionAccept {
+    pub protocol_version: String,
+    pub session_id: String,
+    pub active_features: Vec<String>,
+    pub optional_features: Vec<String>,
+    pub rtp_config: RtpConfig,
+    pub rtp_extensions: RtpExtensions,
+    pub recommended_config: RecommendedConfig,
+    pub latency: LatencyConfig,
+    pub micro_pll: MicroPllConfig,
+    pub volume: VolumeConfig,
+    pub buffer: BufferConfig,
+}
+
+impl SessionAccept {
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.active_features.iter().any(|f| f == feature)
+            || self.optional_features.iter().any(|f| f == feature)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RtpConfig {
+    pub ssrc: u32,
+    pub payload_type: u8,
+    pub timestamp_rate: u32,
+    pub initial_sequence: u16,
+    pub initial_timestamp: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RtpExtensions {
+    pub gapless: GaplessExtension,
+    pub crc32: Crc32Extension,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GaplessExtension {
+    pub enabled: bool,
+    pub extension_id: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Crc32Extension {
+    pub enabled: bool,
+    pub extension_id: u8,
+    /// A CRC is attached to every `window`th packet
+    pub window: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecommendedConfig {
+    pub sample_rate: u32,
+    pub format: String,
+    /// Channel count of the RTP stream (extension to §4.2, defaults to stereo)
+    #[serde(default = "default_channels")]
+    pub channels: u16,
+    pub buffer_ms: u32,
+    pub reason: String,
+}
+
+fn default_channels() -> u16 {
+    2
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LatencyConfig {
+    pub dac_ms: f32,
+    pub pipeline_ms: f32,
+    pub comp_mode: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MicroPllConfig {
+    pub enabled: bool,
+    pub ppm_limit: f64,
+    pub adjustment_interval_ms: u32,
+    pub slew_rate_ppm_per_sec: f64,
+    pub ema_window: u32,
+}
+
+impl Default for MicroPllConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ppm_limit: 150.0,
+            adjustment_interval_ms: 100,
+            slew_rate_ppm_per_sec: 10.0,
+            ema_window: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VolumeConfig {
+    pub initial_level: f32,
+    pub mute: bool,
+    pub control_mode: String,
+    pub curve_type: VolumeCurve,
+}
+
+/// Node jitter buffer parameters (§9)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BufferConfig {
+    pub target_ms: u32,
+    pub min_ms: u32,
+    pub max_ms: u32,
+    pub start_threshold_ms: u32,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self::with_target(150, 50, 500)
+    }
+}
+
+impl BufferConfig {
+    /// Buffer around `target_ms`, starting playback at two thirds of the target
+    pub fn with_target(target_ms: u32, min_ms: u32, max_ms: u32) -> Self {
+        let target_ms = target_ms.clamp(min_ms, max_ms);
+        Self {
+            target_ms,
+            min_ms,
+            max_ms,
+            start_threshold_ms: target_ms * 2 / 3,
+        }
+    }
+}
+
+/// Mapping from normalized volume level to gain (§5.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeCurve {
+    Linear,
+    #[default]
+    Logarithmic,
+    Exponential,
+}
+
+impl VolumeCurve {
+    /// Gain in dB for a level in [0.0, 1.0]; silence is `-inf`
+    pub fn gain_db(&self, level: f32) -> f32 {
+        let level = level.clamp(0.0, 1.0);
+        match self {
+            VolumeCurve::Linear if level > 0.0 => 20.0 * level.log10(),
+            VolumeCurve::Logarithmic if level > 0.0 => 40.0 * level.log10(),
+            VolumeCurve::Exponential => 60.0 * (level - 1.0),
+            _ => f32::NEG_INFINITY,
+        }
+    }
+}
+
+/// Shape of a volume fade (§5.3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RampShape {
+    #[default]
+    Linear,
+    SCurve,
+    Exponential,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VolumeSet {
+    pub level: f32,
+    pub mute: bool,
+    #[serde(default)]
+    pub ramp_ms: u32,
+    #[serde(default)]
+    pub ramp_shape: RampShape,
+}
+
+/// Node's reply to `volume_set` / `volume_get`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VolumeResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    pub level: f32,
+    pub mute: bool,
+    pub gain_db: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ramp_complete: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardware_control: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dac_volume_db: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub curve_type: Option<VolumeCurve>,
+}
+
+/// Periodic node telemetry (§8.2)
+///
+/// Counters are lifetime totals since session start, not deltas.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Health {
+    pub timestamp_us: u64,
+    pub connection: ConnectionHealth,
+    pub playback: PlaybackHealth,
+    pub latency: LatencyBreakdown,
+    pub clock_sync: ClockSync,
+    pub integrity: Integrity,
+    pub errors: ErrorCounters,
+    pub volume: VolumeHealth,
+    pub dsp: DspHealth,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectionHealth {
+    pub state: String,
+    pub uptime_seconds: u64,
+    pub packets_received: u64,
+    pub packets_lost: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaybackHealth {
+    pub state: String,
+    pub buffer_ms: f32,
+    pub buffer_health: BufferHealth,
+    pub buffer_fill_percent: f32,
+}
+
+/// Jitter buffer fill relative to its target (§9.3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BufferHealth {
+    Critical,
+    Low,
+    #[default]
+    Good,
+    High,
+}
+
+impl BufferHealth {
+    pub fn from_fill_percent(percent: f32) -> Self {
+        match percent {
+            p if p < 30.0 => BufferHealth::Critical,
+            p if p < 60.0 => BufferHealth::Low,
+            p if p <= 90.0 => BufferHealth::Good,
+            _ => BufferHealth::High,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LatencyBreakdown {
+    pub network_ms: f32,
+    pub jitter_buffer_ms: f32,
+    pub dac_ms: f32,
+    pub pipeline_ms: f32,
+    pub total_ms: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PllState {
+    #[default]
+    Seeking,
+    Locked,
+    Unlocked,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClockSync {
+    pub drift_ppm: f64,
+    pub phase_us: f64,
+    pub pll_state: PllState,
+    pub adjustment_ppm: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Integrity {
+    pub crc_ok: u64,
+    pub crc_fail: u64,
+    pub last_crc_fail_seq: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErrorCounters {
+    pub xruns: u64,
+    pub buffer_underruns: u64,
+    pub buffer_overruns: u64,
+    pub last_xrun_timestamp_us: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VolumeHealth {
+    pub level: f32,
+    pub mute: bool,
+    pub hardware_control: bool,
+    pub gain_db: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DspHealth {
+    pub current_profile_hash: u32,
+    pub eq_active: bool,
+    pub convolution_active: bool,
+}
+
+/// DSP profile pushed to a node that negotiated `dsp_transfer` (§12.1)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DspUpdate {
+    pub profile_id: i64,
+    pub profile_name: String,
+    pub headroom_db: f32,
+    /// `none`, or `<mode>_<bits>bit` such as `tpdf_24bit`
+    pub dithering: String,
+    pub equalizer: EqualizerConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub convolution: Option<ConvolutionConfig>,
+}
+
+impl DspUpdate {
+    /// CRC32 of the serialized profile, echoed in `dsp_update_ack`
+    pub fn profile_hash(&self) -> u32 {
+        crc32fast::hash(&serde_json::to_vec(self).unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EqualizerConfig {
+    pub name: String,
+    pub enabled: bool,
+    pub bands: Vec<DspBand>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DspBand {
+    pub frequency: f64,
+    pub gain: f64,
+    #[serde(default = "default_q")]
+    pub q: f64,
+    #[serde(rename = "type", default)]
+    pub band_type: BandType,
+}
+
+fn default_q() -> f64 {
+    1.0
+}
+
+/// Biquad shape of an equalizer band
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BandType {
+    #[default]
+    Peak,
+    LowShelf,
+    HighShelf,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConvolutionConfig {
+    pub enabled: bool,
+    pub filter_id: String,
+    pub delay_samples: u32,
+    pub gain_db: f32,
+}
+
+/// Node's answer to `dsp_update` (§12.2)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DspUpdateAck {
+    pub profile_id: i64,
+    pub status: DspStatus,
+    pub profile_hash: u32,
+    pub applied: AppliedDsp,
+    pub errors: Vec<DspError>,
+    /// Suggested fallback per feature, e.g. `convolution: server_side`
+    pub fallback: std::collections::BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DspStatus {
+    #[default]
+    Success,
+    Partial,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppliedDsp {
+    pub equalizer: bool,
+    pub headroom: bool,
+    pub dithering: bool,
+    pub convolution: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DspError {
+    pub code: String,
+    pub message: String,
+}
+
+/// Node asking the server for a convolution filter it hasn't cached (§12.3)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConvolutionRequest {
+    pub cmd: String,
+    pub filter_id: String,
+}
+
+/// Where (or what) the requested convolution filter is
+///
+/// `taps` carries the impulse response inline; it is an extension to §12.3
+/// for filters short enough to send over the control channel. `checksum`
+/// is `sha256:<hex>` over the little-endian `f32` taps when inline.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConvolutionResponse {
+    pub filter_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ir_url: Option<String>,
+    pub format: String,
+    pub sample_rate: u32,
+    pub length_samples: u32,
+    pub checksum: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taps: Option<Vec<f32>>,
+}
+
+/// How serious an ANP error is (§10.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorSeverity {
+    Fatal,
+    #[default]
+    Warning,
+    Info,
+}
+
+/// Error notification with a standard code such as `E304` (§10)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErrorReport {
+    pub code: String,
+    pub category: String,
+    pub severity: ErrorSeverity,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_action: Option<String>,
+}
+
+impl ErrorReport {
+    pub fn new(code: &str, severity: ErrorSeverity, message: impl Into<String>) -> Self {
+        Self {
+            code: code.to_string(),
+            category: error_category(code).to_string(),
+            severity,
+            message: message.into(),
+            details: None,
+            recovery_action: None,
+        }
+    }
+}
+
+/// Category of a standard error code, from its leading digit
+pub fn error_category(code: &str) -> &'static str {
+    match code.as_bytes().get(1) {
+        Some(b'1') => "connection",
+        Some(b'2') => "protocol",
+        Some(b'3') => "audio",
+        Some(b'4') => "clock",
+        Some(b'5') => "dsp",
+        Some(b'6') => "volume",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_envelope() {
+        let message = ControlMessage::VolumeSet(VolumeSet {
+            level: 0.5,
+            mute: false,
+            ramp_ms: 100,
+            ramp_shape: RampShape::SCurve,
+        });
+        assert_eq!(
+            message.to_json(),
+            r#"{"volume_set":{"level":0.5,"mute":false,"ramp_ms":100,"ramp_shape":"s_curve"}}"#
+        );
+        assert_eq!(ControlMessage::StreamStop {}.to_json(), r#"{"stream_stop":{}}"#);
+        assert_eq!(
+            ControlMessage::from_json(r#"{"stream_stopped":{}}"#).unwrap(),
+            ControlMessage::StreamStopped {}
+        );
+    }
+
+    #[test]
+    fn test_parse_bundled_messages() {
+        let json = r#"{"health":{"connection":{"state":"interrupted"}},"error":{"code":"E102","message":"Connection timeout"}}"#;
+        let messages = ControlMessage::parse_all(json).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages
+            .iter()
+            .any(|m| matches!(m, ControlMessage::Health(h) if h.connection.state == "interrupted")));
+        assert!(messages
+            .iter()
+            .any(|m| matches!(m, ControlMessage::Error(e) if e.code == "E102")));
+
+        assert!(ControlMessage::parse_all(r#"{"bogus":{}}"#).is_err());
+        assert!(ControlMessage::parse_all("[]").is_err());
+    }
+
+    #[test]
+    fn test_parse_spec_session_init() {
+        let json = r#"{
+          "session_init": {
+            "protocol_version": "0.4",
+            "node_uuid": "550e8400-e29b-41d4-a716-446655440000",
+            "features": ["micro_pll", "crc_verify", "volume_control", "gapless", "capabilities"],
+            "optional_features": ["dsp_transfer"],
+            "latency_comp": true,
+            "node_capabilities": {
+              "hardware": "Raspberry Pi 4 Model B",
+              "dac_name": "HiFiBerry DAC+ Pro",
+              "dac_chip": "PCM5122",
+              "max_sample_rate": 192000,
+              "supported_formats": ["F32", "S24LE", "S16LE"],
+              "native_format": "S24LE",
+              "max_channels": 2,
+              "buffer_range_ms": [50, 500],
+              "has_hardware_volume": true,
+              "volume_range": [0.0, 1.0],
+              "volume_curve": "logarithmic",
+              "cpu_info": {"arch": "ARMv8", "cores": 4, "freq_mhz": 1500},
+              "dsp_capabilities": {"can_eq": false, "can_resample": false, "can_convolve": false}
+            }
+          }
+        }"#;
+
+        let ControlMessage::SessionInit(init) = ControlMessage::from_json(json).unwrap() else {
+            panic!("expected session_init");
+        };
+        assert_eq!(init.node_uuid, "550e8400-e29b-41d4-a716-446655440000");
+        assert_eq!(init.features.len(), 5);
+        assert_eq!(init.node_capabilities.max_sample_rate, 192000);
+        assert!(init.node_capabilities.supports_format("s24le"));
+        assert_eq!(init.rtp_port, None);
+    }
+
+    #[test]
+    fn test_parse_partial_health() {
+        let json = r#"{"health":{"playback":{"buffer_fill_percent":93},"integrity":{"crc_ok":64,"crc_fail":1}}}"#;
+        let ControlMessage::Health(health) = ControlMessage::from_json(json).unwrap() else {
+            panic!("expected health");
+        };
+        assert_eq!(health.playback.buffer_fill_percent, 93.0);
+        assert_eq!(health.integrity.crc_fail, 1);
+        assert_eq!(health.errors.xruns, 0);
+    }
+
+    #[test]
+    fn test_volume_curves() {
+        let db = |curve: VolumeCurve, level: f32| (curve.gain_db(level) * 10.0).round() / 10.0;
+
+        assert_eq!(db(VolumeCurve::Logarithmic, 0.1), -40.0);
+        assert_eq!(db(VolumeCurve::Logarithmic, 0.75), -5.0);
+        assert_eq!(db(VolumeCurve::Logarithmic, 1.0), 0.0);
+        assert_eq!(db(VolumeCurve::Linear, 0.5), -6.0);
+        assert_eq!(db(VolumeCurve::Exponential, 0.0), -60.0);
+        assert_eq!(VolumeCurve::Logarithmic.gain_db(0.0), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_buffer_config() {
+        let buffer = BufferConfig::default();
+        assert_eq!(buffer.start_threshold_ms, 100);
+
+        let clamped = BufferConfig::with_target(1000, 50, 500);
+        assert_eq!(clamped.target_ms, 500);
+        assert_eq!(BufferHealth::from_fill_percent(25.0), BufferHealth::Critical);
+        assert_eq!(BufferHealth::from_fill_percent(93.0), BufferHealth::High);
+    }
+
+    #[test]
+    fn test_error_report() {
+        let error = ErrorReport::new("E304", ErrorSeverity::Warning, "Buffer underrun detected");
+        assert_eq!(error.category, "audio");
+        let json = ControlMessage::Error(error).to_json();
+        assert!(json.starts_with(r#"{"error":{"code":"E304","category":"audio","severity":"warning""#));
+    }
+}
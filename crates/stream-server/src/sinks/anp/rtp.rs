@@ -0,0 +1,515 @@
+use super::protocol::{RtpConfig, RtpExtensions};
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BufMut, BytesMut};
+use std::time::{Duration, Instant};
+
+/// L24 big-endian PCM
+pub const PT_L24: u8 = 96;
+/// L16 big-endian PCM
+pub const PT_L16: u8 = 97;
+
+/// Largest audio payload per packet, keeping packets within a 1500-byte MTU
+pub const MAX_PAYLOAD_BYTES: usize = 1440;
+
+/// RFC 5285 one-byte header extension profile
+const ONE_BYTE_PROFILE: u16 = 0xBEDE;
+
+const RTP_HEADER_SIZE: usize = 12;
+
+/// Gapless extension flags
+const TRACK_END: u8 = 0x80;
+const TRACK_START: u8 = 0x40;
+
+/// RTCP sender report packet type
+const RTCP_SR: u8 = 200;
+
+/// How far behind real time a stream may fall before its send clock resyncs
+const MAX_BEHIND: Duration = Duration::from_millis(200);
+
+/// Track boundary markers carried by the gapless extension
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrackMarkers {
+    /// Last packet of the current track
+    pub track_end: bool,
+    /// First packet of the next track
+    pub track_start: bool,
+}
+
+impl TrackMarkers {
+    pub fn is_empty(&self) -> bool {
+        !self.track_end && !self.track_start
+    }
+
+    fn to_byte(self) -> u8 {
+        (if self.track_end { TRACK_END } else { 0 }) | (if self.track_start { TRACK_START } else { 0 })
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        Self {
+            track_end: byte & TRACK_END != 0,
+            track_start: byte & TRACK_START != 0,
+        }
+    }
+}
+
+/// Builds ANP RTP packets with the negotiated header extensions
+///
+/// Sequence numbers and timestamps start from the values announced in
+/// `session_accept`; the timestamp advances by frames, not samples.
+pub struct RtpPacketizer {
+    config: RtpConfig,
+    extensions: RtpExtensions,
+    sequence: u16,
+    timestamp: u32,
+    packet_count: u32,
+    octet_count: u32,
+}
+
+impl RtpPacketizer {
+    pub fn new(config: RtpConfig, extensions: RtpExtensions) -> Self {
+        Self {
+            config,
+            extensions,
+            sequence: config.initial_sequence,
+            timestamp: config.initial_timestamp,
+            packet_count: 0,
+            octet_count: 0,
+        }
+    }
+
+    /// Sequence number of the next packet
+    pub fn sequence(&self) -> u16 {
+        self.sequence
+    }
+
+    /// RTP timestamp of the next packet
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.config.ssrc
+    }
+
+    pub fn packet_count(&self) -> u32 {
+        self.packet_count
+    }
+
+    pub fn octet_count(&self) -> u32 {
+        self.octet_count
+    }
+
+    /// Build the next packet for `payload` (network byte order PCM holding `frames` frames)
+    pub fn packetize(&mut self, payload: &[u8], frames: u32, markers: TrackMarkers) -> Vec<u8> {
+        let mut elements: Vec<(u8, Vec<u8>)> = Vec::new();
+
+        let gapless = self.extensions.gapless;
+        if gapless.enabled && !markers.is_empty() {
+            elements.push((gapless.extension_id, vec![markers.to_byte()]));
+        }
+
+        let crc = self.extensions.crc32;
+        if crc.enabled && self.packet_count.is_multiple_of(crc.window.max(1)) {
+            elements.push((crc.extension_id, crc32fast::hash(payload).to_be_bytes().to_vec()));
+        }
+
+        let mut packet = BytesMut::with_capacity(RTP_HEADER_SIZE + 16 + payload.len());
+        let extension_bit = if elements.is_empty() { 0 } else { 0x10 };
+        packet.put_u8(0x80 | extension_bit);
+        packet.put_u8(self.config.payload_type & 0x7F);
+        packet.put_u16(self.sequence);
+        packet.put_u32(self.timestamp);
+        packet.put_u32(self.config.ssrc);
+
+        if !elements.is_empty() {
+            let mut block = BytesMut::new();
+            for (id, data) in &elements {
+                // One-byte header: 4-bit ID, 4-bit (length - 1)
+                block.put_u8((id << 4) | (data.len() as u8 - 1));
+                block.put_slice(data);
+            }
+            while !block.len().is_multiple_of(4) {
+                block.put_u8(0);
+            }
+            packet.put_u16(ONE_BYTE_PROFILE);
+            packet.put_u16((block.len() / 4) as u16);
+            packet.put_slice(&block);
+        }
+
+        packet.put_slice(payload);
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(frames);
+        self.packet_count = self.packet_count.wrapping_add(1);
+        self.octet_count = self.octet_count.wrapping_add(payload.len() as u32);
+
+        packet.to_vec()
+    }
+
+    /// RTCP sender report for the stream so far (§11)
+    pub fn sender_report(&self, ntp_timestamp: u64) -> Vec<u8> {
+        sender_report(
+            self.config.ssrc,
+            ntp_timestamp,
+            self.timestamp,
+            self.packet_count,
+            self.octet_count,
+        )
+    }
+}
+
+/// RTCP SR packet (RFC 3550) without reception report blocks
+pub fn sender_report(ssrc: u32, ntp_timestamp: u64, rtp_timestamp: u32, packets: u32, octets: u32) -> Vec<u8> {
+    let mut packet = BytesMut::with_capacity(28);
+    packet.put_u8(0x80);
+    packet.put_u8(RTCP_SR);
+    // Length in 32-bit words minus one
+    packet.put_u16(6);
+    packet.put_u32(ssrc);
+    packet.put_u64(ntp_timestamp);
+    packet.put_u32(rtp_timestamp);
+    packet.put_u32(packets);
+    packet.put_u32(octets);
+    packet.to_vec()
+}
+
+/// A received ANP RTP packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtpPacket {
+    pub payload_type: u8,
+    pub marker: bool,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    /// One-byte header extension elements as (id, data)
+    pub extensions: Vec<(u8, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+impl RtpPacket {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < RTP_HEADER_SIZE {
+            return Err(anyhow!("RTP packet too short: {} bytes", data.len()));
+        }
+
+        let mut buf = data;
+        let first = buf.get_u8();
+        if first >> 6 != 2 {
+            return Err(anyhow!("Unsupported RTP version {}", first >> 6));
+        }
+        let second = buf.get_u8();
+        let sequence = buf.get_u16();
+        let timestamp = buf.get_u32();
+        let ssrc = buf.get_u32();
+
+        let csrc_bytes = (first & 0x0F) as usize * 4;
+        if buf.len() < csrc_bytes {
+            return Err(anyhow!("RTP CSRC list truncated"));
+        }
+        buf.advance(csrc_bytes);
+
+        let mut extensions = Vec::new();
+        if first & 0x10 != 0 {
+            if buf.len() < 4 {
+                return Err(anyhow!("RTP extension header truncated"));
+            }
+            let profile = buf.get_u16();
+            let length = buf.get_u16() as usize * 4;
+            if buf.len() < length {
+                return Err(anyhow!("RTP extension block truncated"));
+            }
+            let (mut block, rest) = buf.split_at(length);
+            buf = rest;
+
+            if profile == ONE_BYTE_PROFILE {
+                while let Some(&header) = block.first() {
+                    block = &block[1..];
+                    // Zero bytes are padding
+                    if header == 0 {
+                        continue;
+                    }
+                    let id = header >> 4;
+                    // ID 15 terminates the block
+                    if id == 15 {
+                        break;
+                    }
+                    let len = (header & 0x0F) as usize + 1;
+                    if block.len() < len {
+                        return Err(anyhow!("RTP extension element truncated"));
+                    }
+                    extensions.push((id, block[..len].to_vec()));
+                    block = &block[len..];
+                }
+            }
+        }
+
+        let mut payload = buf.to_vec();
+        if first & 0x20 != 0 {
+            // Padding: last byte holds the number of padding bytes
+            let padding = *payload.last().unwrap_or(&0) as usize;
+            payload.truncate(payload.len().saturating_sub(padding));
+        }
+
+        Ok(Self {
+            payload_type: second & 0x7F,
+            marker: second & 0x80 != 0,
+            sequence,
+            timestamp,
+            ssrc,
+            extensions,
+            payload,
+        })
+    }
+
+    fn extension(&self, id: u8) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|(ext_id, _)| *ext_id == id)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Gapless markers, if the packet carries the extension with this ID
+    pub fn track_markers(&self, id: u8) -> Option<TrackMarkers> {
+        self.extension(id)
+            .and_then(|data| data.first())
+            .map(|&b| TrackMarkers::from_byte(b))
+    }
+
+    /// Payload CRC32, if the packet carries the extension with this ID
+    pub fn crc32(&self, id: u8) -> Option<u32> {
+        self.extension(id)
+            .and_then(|data| data.get(..4))
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+/// Reverse the byte order of each sample, converting little-endian PCM to
+/// network order (and back)
+pub fn swap_sample_bytes(pcm: &[u8], bytes_per_sample: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pcm.len());
+    for sample in pcm.chunks_exact(bytes_per_sample) {
+        out.extend(sample.iter().rev());
+    }
+    out
+}
+
+/// Frames per packet: 10ms, limited so a packet fits in the MTU
+pub fn frames_per_packet(sample_rate: u32, frame_bytes: usize) -> usize {
+    let ten_ms = (sample_rate / 100).max(1) as usize;
+    ten_ms.min(MAX_PAYLOAD_BYTES / frame_bytes.max(1)).max(1)
+}
+
+/// Paces RTP packets to the wall clock, for the ANP and AES67 sinks
+///
+/// Counts the frames sent since the first packet went out. A stream that
+/// falls more than 200 ms behind restarts the clock instead of bursting to
+/// catch up.
+#[derive(Debug)]
+pub struct SendClock {
+    sample_rate: u64,
+    start: Option<Instant>,
+    frames_sent: u64,
+}
+
+impl SendClock {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate.max(1) as u64,
+            start: None,
+            frames_sent: 0,
+        }
+    }
+
+    /// Wait until the next packet is due, letting it go out up to
+    /// `max_ahead` early; returns whether the clock had to resync
+    pub async fn pace(&mut self, max_ahead: Duration) -> bool {
+        let now = Instant::now();
+        let sent = Duration::from_micros(self.frames_sent * 1_000_000 / self.sample_rate);
+        let start = *self.start.get_or_insert(now);
+        let elapsed = now - start;
+        if elapsed > sent + MAX_BEHIND {
+            self.start = Some(now - sent);
+            return true;
+        }
+        if sent > elapsed + max_ahead {
+            tokio::time::sleep(sent - elapsed - max_ahead).await;
+        }
+        false
+    }
+
+    /// Count `frames` as sent
+    pub fn sent(&mut self, frames: u64) {
+        self.frames_sent += frames;
+    }
+
+    pub fn frames_sent(&self) -> u64 {
+        self.frames_sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::anp::protocol::{Crc32Extension, GaplessExtension};
+
+    fn rtp_config() -> RtpConfig {
+        RtpConfig {
+            ssrc: 0x1234_5678,
+            payload_type: PT_L24,
+            timestamp_rate: 48000,
+            initial_sequence: 65535,
+            initial_timestamp: u32::MAX - 100,
+        }
+    }
+
+    fn extensions(window: u32) -> RtpExtensions {
+        RtpExtensions {
+            gapless: GaplessExtension {
+                enabled: true,
+                extension_id: 1,
+            },
+            crc32: Crc32Extension {
+                enabled: true,
+                extension_id: 2,
+                window,
+            },
+        }
+    }
+
+    #[test]
+    fn test_header_and_wraparound() {
+        let mut packetizer = RtpPacketizer::new(rtp_config(), extensions(64));
+        let payload = vec![1u8; 240 * 6];
+
+        let first = RtpPacket::parse(&packetizer.packetize(&payload, 240, TrackMarkers::default())).unwrap();
+        assert_eq!(first.payload_type, PT_L24);
+        assert_eq!(first.sequence, 65535);
+        assert_eq!(first.timestamp, u32::MAX - 100);
+        assert_eq!(first.ssrc, 0x1234_5678);
+        assert_eq!(first.payload, payload);
+
+        let second = RtpPacket::parse(&packetizer.packetize(&payload, 240, TrackMarkers::default())).unwrap();
+        assert_eq!(second.sequence, 0);
+        assert_eq!(second.timestamp, 139);
+        assert_eq!(packetizer.packet_count(), 2);
+        assert_eq!(packetizer.octet_count(), 2 * payload.len() as u32);
+    }
+
+    #[test]
+    fn test_crc_extension_window() {
+        let mut packetizer = RtpPacketizer::new(rtp_config(), extensions(4));
+        let payload: Vec<u8> = (0..=255).collect();
+
+        let packets: Vec<RtpPacket> = (0..8)
+            .map(|_| RtpPacket::parse(&packetizer.packetize(&payload, 1, TrackMarkers::default())).unwrap())
+            .collect();
+
+        let with_crc: Vec<usize> = (0..8).filter(|&i| packets[i].crc32(2).is_some()).collect();
+        assert_eq!(with_crc, vec![0, 4]);
+        assert_eq!(packets[0].crc32(2), Some(crc32fast::hash(&payload)));
+
+        // Extension uses profile 0xBEDE, len=3 and pads to a word boundary
+        let raw = RtpPacketizer::new(rtp_config(), extensions(1)).packetize(&payload, 1, TrackMarkers::default());
+        assert_eq!(raw[0], 0x90);
+        assert_eq!(&raw[12..16], &[0xBE, 0xDE, 0x00, 0x02]);
+        assert_eq!(raw[16], 0x23);
+        assert_eq!(&raw[21..24], &[0, 0, 0]);
+    }
+
+    #[test]
+    fn test_gapless_markers() {
+        let mut packetizer = RtpPacketizer::new(rtp_config(), extensions(1000));
+        packetizer.packetize(&[0; 6], 1, TrackMarkers::default());
+
+        let plain = RtpPacket::parse(&packetizer.packetize(&[0; 6], 1, TrackMarkers::default())).unwrap();
+        assert!(plain.extensions.is_empty());
+
+        let end = TrackMarkers {
+            track_end: true,
+            track_start: false,
+        };
+        let packet = RtpPacket::parse(&packetizer.packetize(&[0; 6], 1, end)).unwrap();
+        assert_eq!(packet.track_markers(1), Some(end));
+
+        let raw = packetizer.packetize(
+            &[0; 6],
+            1,
+            TrackMarkers {
+                track_end: false,
+                track_start: true,
+            },
+        );
+        assert_eq!(&raw[16..18], &[0x10, 0x40]);
+    }
+
+    #[test]
+    fn test_disabled_extensions() {
+        let mut disabled = extensions(1);
+        disabled.gapless.enabled = false;
+        disabled.crc32.enabled = false;
+        let mut packetizer = RtpPacketizer::new(rtp_config(), disabled);
+
+        let raw = packetizer.packetize(&[0; 6], 1, TrackMarkers { track_end: true, track_start: false });
+        assert_eq!(raw[0], 0x80);
+        assert_eq!(raw.len(), RTP_HEADER_SIZE + 6);
+    }
+
+    #[test]
+    fn test_sender_report() {
+        let report = sender_report(0xAABBCCDD, 0x0102030405060708, 9600, 20, 28800);
+        assert_eq!(report.len(), 28);
+        assert_eq!(&report[..4], &[0x80, 200, 0, 6]);
+        assert_eq!(&report[4..8], &0xAABBCCDDu32.to_be_bytes());
+        assert_eq!(&report[8..16], &0x0102030405060708u64.to_be_bytes());
+        assert_eq!(&report[16..20], &9600u32.to_be_bytes());
+        assert_eq!(&report[20..24], &20u32.to_be_bytes());
+        assert_eq!(&report[24..28], &28800u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_network_byte_order() {
+        // 24-bit little-endian 0x123456 and -1
+        let le = [0x56, 0x34, 0x12, 0xFF, 0xFF, 0xFF];
+        let be = swap_sample_bytes(&le, 3);
+        assert_eq!(be, vec![0x12, 0x34, 0x56, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(swap_sample_bytes(&be, 3), le.to_vec());
+    }
+
+    #[test]
+    fn test_frames_per_packet() {
+        // 48kHz stereo L24: 10ms would exceed the MTU
+        assert_eq!(frames_per_packet(48000, 6), 240);
+        // 44.1kHz stereo L16 fits 10ms
+        assert_eq!(frames_per_packet(44100, 4), 360);
+        assert_eq!(frames_per_packet(8000, 2), 80);
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage() {
+        assert!(RtpPacket::parse(&[0x80; 4]).is_err());
+        assert!(RtpPacket::parse(&[0x40; 12]).is_err());
+        // Extension flag set but no extension header
+        let mut truncated = vec![0x90, 96];
+        truncated.extend_from_slice(&[0; 10]);
+        assert!(RtpPacket::parse(&truncated).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_clock_paces_and_resyncs() {
+        let mut clock = SendClock::new(48000);
+        let started = Instant::now();
+
+        // 20 ms of audio: the second packet waits for the wall clock, less what it may run ahead
+        assert!(!clock.pace(Duration::from_millis(5)).await);
+        clock.sent(960);
+        assert!(!clock.pace(Duration::from_millis(5)).await);
+        assert!(started.elapsed() >= Duration::from_millis(15), "{:?}", started.elapsed());
+        clock.sent(960);
+        assert_eq!(clock.frames_sent(), 1920);
+
+        // Falling far behind restarts the clock
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(clock.pace(Duration::ZERO).await);
+        assert!(!clock.pace(Duration::ZERO).await);
+    }
+}
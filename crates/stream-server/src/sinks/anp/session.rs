@@ -0,0 +1,606 @@
+use super::dsp::RemoteDsp;
+use super::protocol::*;
+use super::rtp::{PT_L16, PT_L24};
+use anyhow::{anyhow, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{debug, error, info, warn};
+
+/// How long to wait for the WebSocket connection and the node's `session_init`
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for `stream_stopped` when closing
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+type NodeSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Stream parameters the server offers a node
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionOptions {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Prefer 24-bit (L24) when the node supports it
+    pub prefer_24bit: bool,
+    pub buffer: BufferConfig,
+    pub volume: f32,
+    pub mute: bool,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48000,
+            channels: 2,
+            prefer_24bit: true,
+            buffer: BufferConfig::default(),
+            volume: 1.0,
+            mute: false,
+        }
+    }
+}
+
+/// Choose session parameters for a node's `session_init`
+///
+/// Returns the standard error to report to the node if the session can't be
+/// established.
+pub fn negotiate_session(init: &SessionInit, options: &SessionOptions) -> Result<SessionAccept, Box<ErrorReport>> {
+    if !is_compatible_version(&init.protocol_version) {
+        return Err(Box::new(ErrorReport::new(
+            "E201",
+            ErrorSeverity::Fatal,
+            format!(
+                "Protocol version {} not supported (server speaks {})",
+                init.protocol_version, PROTOCOL_VERSION
+            ),
+        )));
+    }
+
+    let caps = &init.node_capabilities;
+    if options.sample_rate > caps.max_sample_rate {
+        return Err(Box::new(ErrorReport::new(
+            "E301",
+            ErrorSeverity::Fatal,
+            format!(
+                "Sample rate {} exceeds node maximum {}",
+                options.sample_rate, caps.max_sample_rate
+            ),
+        )));
+    }
+    if options.channels > caps.max_channels {
+        return Err(Box::new(ErrorReport::new(
+            "E302",
+            ErrorSeverity::Fatal,
+            format!("{} channels exceeds node maximum {}", options.channels, caps.max_channels),
+        )));
+    }
+
+    let (format, payload_type) = if options.prefer_24bit && caps.supports_format("S24LE") {
+        ("S24LE", PT_L24)
+    } else if caps.supports_format("S16LE") {
+        ("S16LE", PT_L16)
+    } else if caps.supports_format("S24LE") {
+        ("S24LE", PT_L24)
+    } else {
+        return Err(Box::new(ErrorReport::new(
+            "E302",
+            ErrorSeverity::Fatal,
+            format!("No common sample format in {:?}", caps.supported_formats),
+        )));
+    };
+
+    let active_features: Vec<String> = init
+        .features
+        .iter()
+        .filter(|f| SUPPORTED_FEATURES.contains(&f.as_str()))
+        .cloned()
+        .collect();
+    let optional_features: Vec<String> = init
+        .optional_features
+        .iter()
+        .filter(|f| SUPPORTED_OPTIONAL_FEATURES.contains(&f.as_str()))
+        .cloned()
+        .collect();
+    let active = |feature: &str| active_features.iter().any(|f| f == feature);
+
+    let [min_ms, max_ms] = caps.buffer_range_ms;
+    let buffer = BufferConfig::with_target(
+        options.buffer.target_ms,
+        options.buffer.min_ms.max(min_ms),
+        options.buffer.max_ms.min(max_ms).max(min_ms),
+    );
+
+    Ok(SessionAccept {
+        protocol_version: PROTOCOL_VERSION.to_string(),
+        session_id: format!("srv-{}", uuid::Uuid::new_v4().simple()),
+        rtp_config: RtpConfig {
+            ssrc: rand::random(),
+            payload_type,
+            timestamp_rate: options.sample_rate,
+            initial_sequence: 0,
+            initial_timestamp: 0,
+        },
+        rtp_extensions: RtpExtensions {
+            gapless: GaplessExtension {
+                enabled: active("gapless"),
+                extension_id: 1,
+            },
+            crc32: Crc32Extension {
+                enabled: active("crc_verify"),
+                extension_id: 2,
+                window: 64,
+            },
+        },
+        recommended_config: RecommendedConfig {
+            sample_rate: options.sample_rate,
+            format: format.to_string(),
+            channels: options.channels,
+            buffer_ms: buffer.target_ms,
+            reason: "Matches the server output configuration".to_string(),
+        },
+        latency: LatencyConfig {
+            dac_ms: caps.dac_latency_ms.unwrap_or(0.0),
+            pipeline_ms: caps.pipeline_latency_ms.unwrap_or(0.0),
+            comp_mode: if init.latency_comp { "exact" } else { "none" }.to_string(),
+        },
+        micro_pll: MicroPllConfig {
+            enabled: active("micro_pll"),
+            ..Default::default()
+        },
+        volume: VolumeConfig {
+            initial_level: options.volume.clamp(0.0, 1.0),
+            mute: options.mute,
+            control_mode: if caps.has_hardware_volume { "hardware" } else { "software" }.to_string(),
+            curve_type: caps.volume_curve,
+        },
+        buffer,
+        active_features,
+        optional_features,
+    })
+}
+
+/// v0.4 nodes, plus v0.3 nodes which share the core wire format
+fn is_compatible_version(version: &str) -> bool {
+    let mut parts = version.split('.');
+    matches!((parts.next(), parts.next()), (Some("0"), Some("4" | "3")))
+}
+
+/// Node telemetry and replies collected by the session task
+#[derive(Debug, Default)]
+struct SessionState {
+    health: Option<Health>,
+    volume: Option<VolumeResult>,
+    last_error: Option<ErrorReport>,
+    connected: bool,
+}
+
+/// Server side of an ANP WebSocket control channel
+///
+/// The server connects to the node's control URL, receives its
+/// `session_init`, replies with `session_accept` and then keeps the channel
+/// open for volume commands and the node's health reports.
+pub struct ControlSession {
+    init: SessionInit,
+    accept: SessionAccept,
+    peer: SocketAddr,
+    outgoing: Option<mpsc::UnboundedSender<ControlMessage>>,
+    state: Arc<Mutex<SessionState>>,
+    stopped: Arc<Notify>,
+    dsp: Option<RemoteDsp>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl ControlSession {
+    /// Connect to a node and negotiate a session
+    pub async fn connect(control_url: &str, options: &SessionOptions) -> Result<Self> {
+        Self::connect_with(control_url, options, None).await
+    }
+
+    /// Connect and, if the node negotiates `dsp_transfer`, hand the control
+    /// channel to `dsp` so its profile is pushed to the node
+    pub async fn connect_with(control_url: &str, options: &SessionOptions, dsp: Option<RemoteDsp>) -> Result<Self> {
+        info!("Connecting to ANP node at {}", control_url);
+
+        let (mut socket, _) = tokio::time::timeout(HANDSHAKE_TIMEOUT, tokio_tungstenite::connect_async(control_url))
+            .await
+            .map_err(|_| anyhow!("Timed out connecting to ANP node {}", control_url))?
+            .map_err(|e| anyhow!("Failed to connect to ANP node {}: {}", control_url, e))?;
+
+        let peer = match socket.get_ref() {
+            MaybeTlsStream::Plain(stream) => stream.peer_addr()?,
+            _ => return Err(anyhow!("Unsupported ANP control transport for {}", control_url)),
+        };
+
+        let init = tokio::time::timeout(HANDSHAKE_TIMEOUT, read_session_init(&mut socket))
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for session_init from {}", control_url))??;
+
+        let accept = match negotiate_session(&init, options) {
+            Ok(accept) => accept,
+            Err(report) => {
+                let message = format!("{}: {}", report.code, report.message);
+                let _ = socket
+                    .send(Message::Text(ControlMessage::Error(*report).to_json()))
+                    .await;
+                let _ = socket.close(None).await;
+                return Err(anyhow!("ANP session rejected: {}", message));
+            }
+        };
+
+        socket
+            .send(Message::Text(ControlMessage::SessionAccept(accept.clone()).to_json()))
+            .await?;
+
+        info!(
+            "ANP session {} established with node {} ({} Hz {}, features {:?})",
+            accept.session_id,
+            init.node_uuid,
+            accept.rtp_config.timestamp_rate,
+            accept.recommended_config.format,
+            accept.active_features
+        );
+
+        let state = Arc::new(Mutex::new(SessionState {
+            connected: true,
+            ..Default::default()
+        }));
+        let stopped = Arc::new(Notify::new());
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let dsp = dsp.filter(|_| accept.optional_features.iter().any(|f| f == "dsp_transfer"));
+        let task = tokio::spawn(run_session(
+            socket,
+            outgoing_rx,
+            state.clone(),
+            stopped.clone(),
+            dsp.clone(),
+        ));
+        if let Some(dsp) = &dsp {
+            dsp.attach(outgoing.clone());
+        }
+
+        Ok(Self {
+            init,
+            accept,
+            peer,
+            outgoing: Some(outgoing),
+            state,
+            stopped,
+            dsp,
+            task: Some(task),
+        })
+    }
+
+    /// The node's session proposal
+    pub fn init(&self) -> &SessionInit {
+        &self.init
+    }
+
+    /// Parameters the session was accepted with
+    pub fn accept(&self) -> &SessionAccept {
+        &self.accept
+    }
+
+    /// Where the node receives RTP
+    pub fn rtp_destination(&self) -> SocketAddr {
+        SocketAddr::new(self.peer.ip(), self.init.rtp_port.unwrap_or(DEFAULT_RTP_PORT))
+    }
+
+    /// Send a control message to the node
+    pub fn send(&self, message: ControlMessage) -> Result<()> {
+        self.outgoing
+            .as_ref()
+            .ok_or_else(|| anyhow!("ANP session closed"))?
+            .send(message)
+            .map_err(|_| anyhow!("ANP control channel closed"))
+    }
+
+    pub fn set_volume(&self, level: f32, mute: bool, ramp_ms: u32) -> Result<()> {
+        self.send(ControlMessage::VolumeSet(VolumeSet {
+            level: level.clamp(0.0, 1.0),
+            mute,
+            ramp_ms,
+            ramp_shape: if ramp_ms > 0 { RampShape::SCurve } else { RampShape::Linear },
+        }))
+    }
+
+    /// Most recent health report from the node
+    pub fn health(&self) -> Option<Health> {
+        self.state.lock().unwrap().health.clone()
+    }
+
+    /// Most recent `volume_result` from the node
+    pub fn volume(&self) -> Option<VolumeResult> {
+        self.state.lock().unwrap().volume.clone()
+    }
+
+    /// Most recent error reported by the node
+    pub fn last_error(&self) -> Option<ErrorReport> {
+        self.state.lock().unwrap().last_error.clone()
+    }
+
+    /// Whether the control channel is still up
+    pub fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().connected
+    }
+
+    /// Stop the stream and close the control channel
+    pub async fn close(&mut self) {
+        if self.is_connected()
+            && self.send(ControlMessage::StreamStop {}).is_ok()
+            && tokio::time::timeout(STOP_TIMEOUT, self.stopped.notified()).await.is_err()
+        {
+            debug!("ANP node did not confirm stream_stop");
+        }
+
+        // Dropping the last sender makes the task close the WebSocket
+        self.detach_dsp();
+        self.outgoing = None;
+        if let Some(task) = self.task.take() {
+            if tokio::time::timeout(STOP_TIMEOUT, task).await.is_err() {
+                debug!("ANP control task did not finish in time");
+            }
+        }
+        self.state.lock().unwrap().connected = false;
+    }
+
+    fn detach_dsp(&mut self) {
+        if let (Some(dsp), Some(outgoing)) = (self.dsp.take(), self.outgoing.as_ref()) {
+            dsp.detach(outgoing);
+        }
+    }
+}
+
+impl Drop for ControlSession {
+    fn drop(&mut self) {
+        self.detach_dsp();
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+async fn read_session_init(socket: &mut NodeSocket) -> Result<SessionInit> {
+    while let Some(frame) = socket.next().await {
+        let Message::Text(text) = frame? else {
+            continue;
+        };
+
+        match ControlMessage::from_json(&text) {
+            Ok(ControlMessage::SessionInit(init)) => return Ok(init),
+            Ok(other) => debug!("Ignoring ANP message before session_init: {:?}", other),
+            Err(e) => {
+                let report = ErrorReport::new("E202", ErrorSeverity::Fatal, format!("Invalid session_init: {}", e));
+                let _ = socket.send(Message::Text(ControlMessage::Error(report).to_json())).await;
+                return Err(anyhow!("Invalid session_init from ANP node: {}", e));
+            }
+        }
+    }
+
+    Err(anyhow!("ANP node closed the connection before session_init"))
+}
+
+async fn run_session(
+    socket: NodeSocket,
+    mut outgoing: mpsc::UnboundedReceiver<ControlMessage>,
+    state: Arc<Mutex<SessionState>>,
+    stopped: Arc<Notify>,
+    dsp: Option<RemoteDsp>,
+) {
+    let (mut writer, mut reader) = socket.split();
+
+    loop {
+        tokio::select! {
+            message = outgoing.recv() => {
+                let Some(message) = message else {
+                    let _ = writer.send(Message::Close(None)).await;
+                    break;
+                };
+                if let Err(e) = writer.send(Message::Text(message.to_json())).await {
+                    warn!("ANP control send failed: {}", e);
+                    break;
+                }
+            }
+            frame = reader.next() => match frame {
+                Some(Ok(Message::Text(text))) => match ControlMessage::parse_all(&text) {
+                    Ok(messages) => {
+                        for message in messages {
+                            let Some(reply) = handle_message(message, &state, &stopped, dsp.as_ref()) else {
+                                continue;
+                            };
+                            if let Err(e) = writer.send(Message::Text(reply.to_json())).await {
+                                warn!("ANP control send failed: {}", e);
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Ignoring invalid ANP control message: {}", e),
+                },
+                Some(Ok(Message::Close(_))) | None => {
+                    info!("ANP node closed the control channel");
+                    break;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    warn!("ANP control channel error: {}", e);
+                    break;
+                }
+            },
+        }
+    }
+
+    state.lock().unwrap().connected = false;
+    // Nothing more will arrive; don't leave close() waiting
+    stopped.notify_one();
+}
+
+/// Record a node message, returning the reply to send if it needs one
+fn handle_message(
+    message: ControlMessage,
+    state: &Mutex<SessionState>,
+    stopped: &Notify,
+    dsp: Option<&RemoteDsp>,
+) -> Option<ControlMessage> {
+    let mut state = state.lock().unwrap();
+    match message {
+        ControlMessage::Health(health) => {
+            // Counters are lifetime totals; compare with the previous report
+            if let Some(previous) = &state.health {
+                let crc_failures = health.integrity.crc_fail.saturating_sub(previous.integrity.crc_fail);
+                if crc_failures > 0 {
+                    warn!(
+                        "ANP node reported {} CRC failure(s) (last seq {:?})",
+                        crc_failures, health.integrity.last_crc_fail_seq
+                    );
+                }
+                let xruns = health.errors.xruns.saturating_sub(previous.errors.xruns);
+                if xruns > 0 {
+                    warn!(
+                        "ANP node reported {} xrun(s), buffer {:.1}ms",
+                        xruns, health.playback.buffer_ms
+                    );
+                }
+            }
+            debug!(
+                "ANP health: buffer {:.1}ms ({:?}), drift {:.1}ppm ({:?})",
+                health.playback.buffer_ms,
+                health.playback.buffer_health,
+                health.clock_sync.drift_ppm,
+                health.clock_sync.pll_state
+            );
+            state.health = Some(health);
+        }
+        ControlMessage::VolumeResult(result) => {
+            debug!("ANP volume: {} (mute {})", result.level, result.mute);
+            state.volume = Some(result);
+        }
+        ControlMessage::Error(report) => {
+            match report.severity {
+                ErrorSeverity::Fatal => error!("ANP node error {}: {}", report.code, report.message),
+                ErrorSeverity::Warning => warn!("ANP node error {}: {}", report.code, report.message),
+                ErrorSeverity::Info => info!("ANP node notice {}: {}", report.code, report.message),
+            }
+            state.last_error = Some(report);
+        }
+        ControlMessage::StreamStopped {} => {
+            stopped.notify_one();
+        }
+        ControlMessage::StreamPaused { buffer_flushed } => {
+            debug!("ANP node paused (buffer flushed: {})", buffer_flushed);
+        }
+        ControlMessage::DspUpdateAck(ack) => match dsp {
+            Some(dsp) => dsp.handle_ack(ack),
+            None => debug!("Ignoring dsp_update_ack for profile {}", ack.profile_id),
+        },
+        ControlMessage::ConvolutionRequest(request) => {
+            debug!("ANP node requested convolution filter '{}'", request.filter_id);
+            return Some(match dsp {
+                Some(dsp) => dsp.convolution_response(&request),
+                None => ControlMessage::Error(ErrorReport::new(
+                    "E502",
+                    ErrorSeverity::Warning,
+                    "DSP transfer is not active on this session",
+                )),
+            });
+        }
+        other => debug!("Ignoring ANP message {:?}", other),
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_init() -> SessionInit {
+        SessionInit {
+            protocol_version: "0.4".to_string(),
+            node_uuid: "550e8400-e29b-41d4-a716-446655440000".to_string(),
+            features: vec![
+                "micro_pll".to_string(),
+                "crc_verify".to_string(),
+                "gapless".to_string(),
+                "ptp_sync".to_string(),
+            ],
+            optional_features: vec!["dsp_transfer".to_string(), "rtcp_sr".to_string()],
+            latency_comp: true,
+            node_capabilities: NodeCapabilities {
+                max_sample_rate: 96000,
+                supported_formats: vec!["S24LE".to_string(), "S16LE".to_string()],
+                buffer_range_ms: [80, 300],
+                dac_latency_ms: Some(1.5),
+                ..Default::default()
+            },
+            rtp_port: Some(6000),
+        }
+    }
+
+    #[test]
+    fn test_negotiate_session() {
+        let accept = negotiate_session(&node_init(), &SessionOptions::default()).unwrap();
+
+        assert_eq!(accept.active_features, vec!["micro_pll", "crc_verify", "gapless"]);
+        assert_eq!(accept.optional_features, vec!["dsp_transfer", "rtcp_sr"]);
+        assert!(accept.rtp_extensions.gapless.enabled);
+        assert!(accept.rtp_extensions.crc32.enabled);
+        assert!(accept.micro_pll.enabled);
+        assert_eq!(accept.rtp_config.payload_type, PT_L24);
+        assert_eq!(accept.rtp_config.timestamp_rate, 48000);
+        assert_eq!(accept.recommended_config.format, "S24LE");
+        assert_eq!(accept.latency.dac_ms, 1.5);
+        assert_eq!(accept.latency.comp_mode, "exact");
+        assert!(accept.session_id.starts_with("srv-"));
+
+        // Buffer is clamped to what the node can hold
+        assert_eq!(accept.buffer.min_ms, 80);
+        assert_eq!(accept.buffer.max_ms, 300);
+        assert_eq!(accept.buffer.target_ms, 150);
+    }
+
+    #[test]
+    fn test_negotiate_16bit() {
+        let options = SessionOptions {
+            prefer_24bit: false,
+            ..Default::default()
+        };
+        let accept = negotiate_session(&node_init(), &options).unwrap();
+        assert_eq!(accept.rtp_config.payload_type, PT_L16);
+
+        let mut init = node_init();
+        init.node_capabilities.supported_formats = vec!["S16LE".to_string()];
+        let accept = negotiate_session(&init, &SessionOptions::default()).unwrap();
+        assert_eq!(accept.recommended_config.format, "S16LE");
+    }
+
+    #[test]
+    fn test_negotiate_rejections() {
+        let mut init = node_init();
+        init.protocol_version = "1.0".to_string();
+        assert_eq!(negotiate_session(&init, &SessionOptions::default()).unwrap_err().code, "E201");
+
+        let options = SessionOptions {
+            sample_rate: 192000,
+            ..Default::default()
+        };
+        assert_eq!(negotiate_session(&node_init(), &options).unwrap_err().code, "E301");
+
+        let mut init = node_init();
+        init.node_capabilities.supported_formats = vec!["F32".to_string()];
+        let error = negotiate_session(&init, &SessionOptions::default()).unwrap_err();
+        assert_eq!(error.code, "E302");
+        assert_eq!(error.severity, ErrorSeverity::Fatal);
+    }
+
+    #[test]
+    fn test_version_compatibility() {
+        assert!(is_compatible_version("0.4"));
+        assert!(is_compatible_version("0.4.0"));
+        assert!(is_compatible_version("0.3"));
+        assert!(!is_compatible_version("0.5"));
+        assert!(!is_compatible_version(""));
+    }
+}
@@ -0,0 +1,389 @@
+use crate::convert::convert_format;
+use crate::sink::{OutputSink, SinkStats};
+use crate::sinks::airplay::get_ntp_timestamp;
+use crate::sinks::anp::{
+    frames_per_packet, swap_sample_bytes, AnpNode, BufferConfig, ControlSession, ErrorReport, Health,
+    RemoteDsp, RtpPacketizer, SendClock, SessionAccept, SessionOptions, TrackMarkers, VolumeResult, PT_L16,
+};
+use crate::types::{AudioBlock, OutputConfig, SampleFormat};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+/// Interval between RTCP sender reports when `rtcp_sr` is negotiated
+const SENDER_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// AAEQ Node Protocol (ANP) output sink
+///
+/// Connects to an ANP node's WebSocket control channel, negotiates a session
+/// and streams L24/L16 RTP with the negotiated gapless and CRC32 header
+/// extensions. The node reports health once a second; its buffer counters are
+/// surfaced through [`OutputSink::stats`]. Nodes offering `dsp_transfer`
+/// receive the profile set through [`AnpSink::remote_dsp`].
+pub struct AnpSink {
+    node: AnpNode,
+    dsp: RemoteDsp,
+    buffer_ms: Option<u32>,
+    volume: f32,
+    mute: bool,
+    config: Option<OutputConfig>,
+    session: Option<ControlSession>,
+    socket: Option<UdpSocket>,
+    rtp_destination: Option<SocketAddr>,
+    packetizer: Option<RtpPacketizer>,
+    /// Bytes per sample on the wire (2 for L16, 3 for L24)
+    wire_bytes: usize,
+    frames_per_packet: usize,
+    /// Network byte order PCM not yet sent as a full packet
+    pending: Vec<u8>,
+    /// Markers for the next packet (set after a track boundary)
+    next_markers: TrackMarkers,
+    clock: Option<SendClock>,
+    last_sender_report: Option<Instant>,
+    frames_written: u64,
+    is_open: bool,
+}
+
+impl AnpSink {
+    /// Create a sink streaming to the given node
+    pub fn new(node: AnpNode) -> Self {
+        Self {
+            node,
+            dsp: RemoteDsp::new(),
+            buffer_ms: None,
+            volume: 1.0,
+            mute: false,
+            config: None,
+            session: None,
+            socket: None,
+            rtp_destination: None,
+            packetizer: None,
+            wire_bytes: 3,
+            frames_per_packet: 0,
+            pending: Vec::new(),
+            next_markers: TrackMarkers::default(),
+            clock: None,
+            last_sender_report: None,
+            frames_written: 0,
+            is_open: false,
+        }
+    }
+
+    /// Node jitter buffer target (defaults to the output config's `buffer_ms`)
+    pub fn with_buffer_ms(mut self, buffer_ms: u32) -> Self {
+        self.buffer_ms = Some(buffer_ms);
+        self
+    }
+
+    /// Initial volume (0.0-1.0) sent in `session_accept`
+    pub fn with_volume(mut self, level: f32) -> Self {
+        self.volume = level.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn node(&self) -> &AnpNode {
+        &self.node
+    }
+
+    /// Handle for pushing DSP profiles to the node
+    ///
+    /// The handle stays valid after the sink is moved into an
+    /// [`OutputManager`](crate::OutputManager) and across reconnects.
+    pub fn remote_dsp(&self) -> RemoteDsp {
+        self.dsp.clone()
+    }
+
+    /// Negotiated session parameters, while open
+    pub fn session(&self) -> Option<&SessionAccept> {
+        self.session.as_ref().map(|s| s.accept())
+    }
+
+    /// Latest health report from the node
+    pub fn health(&self) -> Option<Health> {
+        self.session.as_ref().and_then(|s| s.health())
+    }
+
+    /// Latest error reported by the node
+    pub fn last_error(&self) -> Option<ErrorReport> {
+        self.session.as_ref().and_then(|s| s.last_error())
+    }
+
+    /// Latest volume state confirmed by the node
+    pub fn node_volume(&self) -> Option<VolumeResult> {
+        self.session.as_ref().and_then(|s| s.volume())
+    }
+
+    /// Set the node volume (0.0-1.0) and mute state, fading over `ramp_ms`
+    pub fn set_volume(&mut self, level: f32, mute: bool, ramp_ms: u32) -> Result<()> {
+        self.volume = level.clamp(0.0, 1.0);
+        self.mute = mute;
+        match self.session.as_ref() {
+            Some(session) => session.set_volume(self.volume, mute, ramp_ms),
+            // Applied in session_accept on open
+            None => Ok(()),
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Mark a gapless track boundary
+    ///
+    /// Audio written so far is flushed with the track-end marker and the next
+    /// packet carries the track-start marker.
+    pub async fn mark_track_boundary(&mut self) -> Result<()> {
+        if !self.is_open {
+            return Err(anyhow!("Sink not open"));
+        }
+
+        if self.pending.is_empty() {
+            // Nothing buffered to carry the end marker; the start marker still goes out
+            self.next_markers.track_start = true;
+            return Ok(());
+        }
+
+        let payload = std::mem::take(&mut self.pending);
+        self.send_packet(
+            &payload,
+            TrackMarkers {
+                track_end: true,
+                ..self.next_markers
+            },
+        )
+        .await?;
+        self.next_markers.track_start = true;
+        Ok(())
+    }
+
+    fn frame_bytes(&self) -> usize {
+        let channels = self.config.as_ref().map_or(2, |c| c.channels as usize);
+        channels * self.wire_bytes
+    }
+
+    /// Send one RTP packet, pacing so the node's buffer stays near its target
+    async fn send_packet(&mut self, payload: &[u8], markers: TrackMarkers) -> Result<()> {
+        let frames = (payload.len() / self.frame_bytes()) as u64;
+        let ahead_limit = self
+            .session
+            .as_ref()
+            .map_or(0, |s| s.accept().buffer.target_ms as u64);
+
+        let (Some(clock), Some(packetizer), Some(socket), Some(dest)) = (
+            self.clock.as_mut(),
+            self.packetizer.as_mut(),
+            self.socket.as_ref(),
+            self.rtp_destination,
+        ) else {
+            return Err(anyhow!("Sink not open"));
+        };
+
+        if clock.pace(Duration::from_millis(ahead_limit)).await {
+            debug!("ANP stream fell behind, resyncing send clock");
+        }
+
+        let packet = packetizer.packetize(payload, frames as u32, markers);
+        socket.send_to(&packet, dest).await?;
+        clock.sent(frames);
+        self.next_markers = TrackMarkers::default();
+
+        let rtcp = self.session.as_ref().is_some_and(|s| s.accept().has_feature("rtcp_sr"));
+        let due = self
+            .last_sender_report
+            .is_none_or(|last| last.elapsed() >= SENDER_REPORT_INTERVAL);
+        if rtcp && due {
+            let report = packetizer.sender_report(get_ntp_timestamp());
+            let rtcp_dest = SocketAddr::new(dest.ip(), dest.port().wrapping_add(1));
+            if let Err(e) = socket.send_to(&report, rtcp_dest).await {
+                debug!("ANP sender report failed: {}", e);
+            }
+            self.last_sender_report = Some(Instant::now());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OutputSink for AnpSink {
+    fn name(&self) -> &'static str {
+        "anp"
+    }
+
+    async fn open(&mut self, cfg: OutputConfig) -> Result<()> {
+        info!("Opening ANP sink for {} ({}): {:?}", self.node.name, self.node.control_url, cfg);
+
+        if !self.node.supports_sample_rate(cfg.sample_rate) {
+            warn!(
+                "ANP node {} doesn't advertise {} Hz (supports {:?})",
+                self.node.name, cfg.sample_rate, self.node.sample_rates
+            );
+        }
+
+        let options = SessionOptions {
+            sample_rate: cfg.sample_rate,
+            channels: cfg.channels,
+            prefer_24bit: cfg.format != SampleFormat::S16LE,
+            buffer: BufferConfig::with_target(self.buffer_ms.unwrap_or(cfg.buffer_ms), 50, 500),
+            volume: self.volume,
+            mute: self.mute,
+        };
+        let session = ControlSession::connect_with(&self.node.control_url, &options, Some(self.dsp.clone())).await?;
+        let accept = session.accept().clone();
+
+        let bind_addr: SocketAddr = if session.rtp_destination().is_ipv6() {
+            "[::]:0".parse()?
+        } else {
+            "0.0.0.0:0".parse()?
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+
+        self.wire_bytes = if accept.rtp_config.payload_type == PT_L16 { 2 } else { 3 };
+        self.config = Some(cfg.clone());
+        self.frames_per_packet = frames_per_packet(cfg.sample_rate, self.frame_bytes());
+        self.rtp_destination = Some(session.rtp_destination());
+        self.packetizer = Some(RtpPacketizer::new(accept.rtp_config, accept.rtp_extensions));
+        self.socket = Some(socket);
+        self.session = Some(session);
+        self.pending.clear();
+        self.next_markers = TrackMarkers::default();
+        self.clock = Some(SendClock::new(cfg.sample_rate));
+        self.last_sender_report = None;
+        self.frames_written = 0;
+        self.is_open = true;
+
+        info!(
+            "ANP sink streaming to {} ({} packets of {} frames)",
+            self.rtp_destination.unwrap(),
+            accept.recommended_config.format,
+            self.frames_per_packet
+        );
+        Ok(())
+    }
+
+    async fn write(&mut self, block: AudioBlock<'_>) -> Result<()> {
+        if !self.is_open {
+            return Err(anyhow!("Sink not open"));
+        }
+
+        let wire_format = if self.wire_bytes == 2 {
+            SampleFormat::S16LE
+        } else {
+            SampleFormat::S24LE
+        };
+        let mut pcm = Vec::new();
+        convert_format(block, wire_format, &mut pcm)?;
+        self.frames_written += block.num_frames() as u64;
+
+        // RTP carries samples in network byte order
+        self.pending.extend(swap_sample_bytes(&pcm, self.wire_bytes));
+
+        let packet_bytes = self.frames_per_packet * self.frame_bytes();
+        while self.pending.len() >= packet_bytes {
+            let payload: Vec<u8> = self.pending.drain(..packet_bytes).collect();
+            self.send_packet(&payload, self.next_markers).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn drain(&mut self) -> Result<()> {
+        if self.is_open && !self.pending.is_empty() {
+            let payload = std::mem::take(&mut self.pending);
+            self.send_packet(&payload, self.next_markers).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        debug!("Closing ANP sink");
+
+        let _ = self.drain().await;
+
+        if let Some(mut session) = self.session.take() {
+            session.close().await;
+        }
+        self.socket = None;
+        self.packetizer = None;
+        self.rtp_destination = None;
+        self.pending.clear();
+        self.config = None;
+        self.is_open = false;
+
+        info!("ANP sink closed");
+        Ok(())
+    }
+
+    fn latency_ms(&self) -> u32 {
+        match self.session() {
+            Some(accept) => {
+                accept.buffer.target_ms + (accept.latency.dac_ms + accept.latency.pipeline_ms).round() as u32
+            }
+            None => self.buffer_ms.unwrap_or(BufferConfig::default().target_ms),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+    fn stats(&self) -> SinkStats {
+        let health = self.health().unwrap_or_default();
+        SinkStats {
+            frames_written: self.frames_written,
+            underruns: health.errors.buffer_underruns,
+            overruns: health.errors.buffer_overruns,
+            buffer_fill: (health.playback.buffer_fill_percent / 100.0).clamp(0.0, 1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node() -> AnpNode {
+        AnpNode::from_address("Test Node", "127.0.0.1:9".parse().unwrap())
+    }
+
+    #[test]
+    fn test_anp_sink_create() {
+        let sink = AnpSink::new(node()).with_buffer_ms(200).with_volume(1.5);
+        assert_eq!(sink.name(), "anp");
+        assert!(!sink.is_open());
+        assert_eq!(sink.latency_ms(), 200);
+        assert_eq!(sink.volume(), 1.0);
+        assert_eq!(sink.node().control_url, "ws://127.0.0.1:9");
+        assert!(sink.session().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_write_without_open() {
+        let mut sink = AnpSink::new(node());
+        let frames = vec![0.0; 480];
+        assert!(sink.write(AudioBlock::new(&frames, 48000, 2)).await.is_err());
+        assert!(sink.mark_track_boundary().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_open_fails_without_node() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut sink = AnpSink::new(AnpNode::from_address("Gone", addr));
+        assert!(sink.open(OutputConfig::default()).await.is_err());
+        assert!(!sink.is_open());
+    }
+
+    #[test]
+    fn test_set_volume_before_open() {
+        let mut sink = AnpSink::new(node());
+        sink.set_volume(0.4, true, 0).unwrap();
+        assert_eq!(sink.volume(), 0.4);
+        assert!(sink.node_volume().is_none());
+    }
+}
@@ -4,6 +4,7 @@
 /// This module provides device profiles to handle these differences gracefully.
 use crate::types::{OutputConfig, SampleFormat};
 use super::discovery::DlnaDevice;
+use super::stream_encoding::StreamEncoding;
 
 /// Device profile with quirks and optimal settings
 #[derive(Debug, Clone)]
@@ -63,6 +64,9 @@ pub struct OptimalConfig {
 
     /// Recommended channels
     pub channels: u16,
+
+    /// Preferred HTTP stream encoding
+    pub encoding: StreamEncoding,
 }
 
 impl Default for OptimalConfig {
@@ -72,6 +76,7 @@ impl Default for OptimalConfig {
             format: SampleFormat::S24LE,
             buffer_ms: 200,
             channels: 2,
+            encoding: StreamEncoding::Wav,
         }
     }
 }
@@ -131,6 +136,7 @@ impl DeviceProfile {
                 format: SampleFormat::S24LE,
                 buffer_ms: 150,
                 channels: 2,
+                encoding: StreamEncoding::Wav,
             },
         }
     }
@@ -140,15 +146,14 @@ impl DeviceProfile {
         Self {
             name: device.name.clone(),
             manufacturer: device.manufacturer.clone(),
-            quirks: DeviceQuirks {
-                prefers_wav: true,
-                ..Default::default()
-            },
+            quirks: DeviceQuirks::default(),
             optimal_config: OptimalConfig {
                 sample_rate: 96000, // Bluesound supports high-res
                 format: SampleFormat::S24LE,
                 buffer_ms: 200,
                 channels: 2,
+                // FLAC keeps 24/96 well within typical Wi-Fi bandwidth
+                encoding: StreamEncoding::Flac,
             },
         }
     }
@@ -172,6 +177,7 @@ impl DeviceProfile {
                 format: SampleFormat::S16LE, // Sonos prefers 16-bit
                 buffer_ms: 250,
                 channels: 2,
+                encoding: StreamEncoding::Wav,
             },
         }
     }
@@ -190,6 +196,7 @@ impl DeviceProfile {
                 format: SampleFormat::S24LE,
                 buffer_ms: 200,
                 channels: 2,
+                encoding: StreamEncoding::Wav,
             },
         }
     }
@@ -277,6 +284,23 @@ mod tests {
         assert_eq!(profile.optimal_config.format, SampleFormat::S16LE);
     }
 
+    #[test]
+    fn test_bluesound_prefers_flac() {
+        let device = DlnaDevice {
+            name: "Node".to_string(),
+            manufacturer: Some("Bluesound".to_string()),
+            model: Some("Node 2i".to_string()),
+            uuid: "test".to_string(),
+            location: "http://test".to_string(),
+            ip: None,
+            services: vec![],
+        };
+
+        let profile = DeviceProfile::from_device(&device);
+        assert_eq!(profile.optimal_config.encoding, StreamEncoding::Flac);
+        assert_eq!(profile.optimal_config.sample_rate, 96000);
+    }
+
     #[test]
     fn test_config_adjustment() {
         let device = DlnaDevice {
@@ -319,5 +343,6 @@ mod tests {
         assert!(!profile.quirks.is_wiim);
         assert!(!profile.quirks.is_sonos);
         assert_eq!(profile.optimal_config.sample_rate, 48000);
+        assert_eq!(profile.optimal_config.encoding, StreamEncoding::Wav);
     }
 }
@@ -1,7 +1,11 @@
 /// DIDL-Lite XML metadata generation for UPnP/DLNA
 ///
 /// DIDL-Lite is the metadata format used by UPnP to describe media items.
+use super::stream_encoding::StreamEncoding;
 use crate::types::OutputConfig;
+use aaeq_core::TrackMeta;
+use quick_xml::events::Event;
+use quick_xml::Reader;
 
 /// Media metadata for DIDL-Lite generation
 #[derive(Debug, Clone)]
@@ -27,12 +31,41 @@ impl Default for MediaMetadata {
     }
 }
 
+impl MediaMetadata {
+    /// Metadata describing a track, e.g. one from a `track_changed` event
+    pub fn from_track_meta(track: &TrackMeta) -> Self {
+        let non_empty = |value: &str| (!value.is_empty()).then(|| value.to_string());
+        Self {
+            title: track.title.clone(),
+            artist: non_empty(&track.artist),
+            album: non_empty(&track.album),
+            genre: non_empty(&track.genre),
+            duration: None,
+            album_art_uri: track.album_art_url.clone(),
+        }
+    }
+
+    /// Track metadata for preset rule matching
+    pub fn to_track_meta(&self) -> TrackMeta {
+        let genre = self.genre.clone().unwrap_or_default();
+        TrackMeta {
+            artist: self.artist.clone().unwrap_or_default(),
+            title: self.title.clone(),
+            album: self.album.clone().unwrap_or_default(),
+            device_genre: genre.clone(),
+            genre,
+            album_art_url: self.album_art_uri.clone(),
+        }
+    }
+}
+
 /// Generate DIDL-Lite XML for a media item
 ///
 /// # Arguments
 /// * `uri` - The URL to the media stream
 /// * `metadata` - Metadata for the media item
 /// * `config` - Audio configuration (sample rate, channels, format)
+/// * `encoding` - Wire encoding of the stream (determines `protocolInfo`)
 ///
 /// # Returns
 /// DIDL-Lite XML string
@@ -40,12 +73,9 @@ pub fn generate_didl_lite(
     uri: &str,
     metadata: &MediaMetadata,
     config: &OutputConfig,
+    encoding: StreamEncoding,
 ) -> String {
-    let mime_type = mime_type_from_config(config);
-    let protocol_info = format!(
-        "http-get:*:{}:DLNA.ORG_PN=WAV;DLNA.ORG_OP=01;DLNA.ORG_FLAGS=01700000000000000000000000000000",
-        mime_type
-    );
+    let protocol_info = encoding.protocol_info(config);
 
     let mut didl = String::new();
 
@@ -117,25 +147,88 @@ pub fn generate_didl_lite(
 }
 
 /// Generate minimal DIDL-Lite for a simple stream
-pub fn generate_simple_didl_lite(uri: &str, title: &str, config: &OutputConfig) -> String {
+pub fn generate_simple_didl_lite(
+    uri: &str,
+    title: &str,
+    config: &OutputConfig,
+    encoding: StreamEncoding,
+) -> String {
     let metadata = MediaMetadata {
         title: title.to_string(),
         ..Default::default()
     };
 
-    generate_didl_lite(uri, &metadata, config)
+    generate_didl_lite(uri, &metadata, config, encoding)
 }
 
-/// Determine MIME type from audio configuration
-fn mime_type_from_config(config: &OutputConfig) -> &'static str {
-    match config.format {
-        crate::types::SampleFormat::S16LE | crate::types::SampleFormat::S24LE => "audio/wav",
-        crate::types::SampleFormat::F32 | crate::types::SampleFormat::F64 => "audio/wav",
+/// Parse the first item of a DIDL-Lite document, e.g. the `CurrentURIMetaData`
+/// a control point sends with `SetAVTransportURI`
+///
+/// Returns `None` for empty or malformed metadata, or if there is no item.
+pub fn parse_didl_lite(xml: &str) -> Option<MediaMetadata> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut metadata = MediaMetadata {
+        title: String::new(),
+        ..Default::default()
+    };
+    let mut creator = None;
+    let mut in_item = false;
+    let mut field = String::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                if name == "item" {
+                    in_item = true;
+                } else if name == "res" && metadata.duration.is_none() {
+                    metadata.duration = e
+                        .try_get_attribute("duration")
+                        .ok()
+                        .flatten()
+                        .and_then(|a| a.unescape_value().ok())
+                        .map(|v| v.into_owned());
+                }
+                field = name;
+            }
+            Ok(Event::Text(e)) if in_item => {
+                let Ok(text) = e.unescape() else { continue };
+                let text = text.trim().to_string();
+                match field.as_str() {
+                    "title" => metadata.title = text,
+                    // The first artist is usually the performer; later ones carry roles
+                    "artist" if metadata.artist.is_none() => metadata.artist = Some(text),
+                    "creator" => creator = Some(text),
+                    "album" => metadata.album = Some(text),
+                    "genre" if metadata.genre.is_none() => metadata.genre = Some(text),
+                    "albumArtURI" if metadata.album_art_uri.is_none() => metadata.album_art_uri = Some(text),
+                    _ => {}
+                }
+            }
+            Ok(Event::End(e)) => {
+                field.clear();
+                if e.local_name().as_ref() == b"item" {
+                    break;
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    if !in_item {
+        return None;
     }
+    if metadata.artist.is_none() {
+        metadata.artist = creator;
+    }
+    Some(metadata)
 }
 
 /// Escape XML special characters
-fn escape_xml(s: &str) -> String {
+pub(crate) fn escape_xml(s: &str) -> String {
     s.replace('&', "&amp;")
         .replace('<', "&lt;")
         .replace('>', "&gt;")
@@ -162,12 +255,14 @@ mod tests {
             "http://192.168.1.100:8090/stream.wav",
             "Test Stream",
             &config,
+            StreamEncoding::Wav,
         );
 
         assert!(didl.contains("<dc:title>Test Stream</dc:title>"));
         assert!(didl.contains("sampleFrequency=\"48000\""));
         assert!(didl.contains("nrAudioChannels=\"2\""));
         assert!(didl.contains("http://192.168.1.100:8090/stream.wav"));
+        assert!(didl.contains("http-get:*:audio/wav:DLNA.ORG_PN=WAV;"));
     }
 
     #[test]
@@ -189,7 +284,12 @@ mod tests {
             album_art_uri: None,
         };
 
-        let didl = generate_didl_lite("http://example.com/stream.wav", &metadata, &config);
+        let didl = generate_didl_lite(
+            "http://example.com/stream.wav",
+            &metadata,
+            &config,
+            StreamEncoding::Wav,
+        );
 
         assert!(didl.contains("<dc:title>Test Track</dc:title>"));
         assert!(didl.contains("<upnp:artist>Test Artist</upnp:artist>"));
@@ -200,6 +300,63 @@ mod tests {
         assert!(didl.contains("bitsPerSample=\"24\""));
     }
 
+    #[test]
+    fn test_flac_protocol_info() {
+        let config = OutputConfig {
+            sample_rate: 96000,
+            channels: 2,
+            format: SampleFormat::S24LE,
+            buffer_ms: 150,
+            exclusive: false,
+        };
+
+        let didl = generate_simple_didl_lite(
+            "http://192.168.1.100:8090/stream.flac",
+            "Test Stream",
+            &config,
+            StreamEncoding::Flac,
+        );
+
+        assert!(didl.contains("protocolInfo=\"http-get:*:audio/flac:DLNA.ORG_OP=01;"));
+        assert!(didl.contains("sampleFrequency=\"96000\""));
+        assert!(didl.contains("bitsPerSample=\"24\""));
+    }
+
+    #[test]
+    fn test_parse_didl_lite() {
+        let didl = r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">
+  <item id="42" parentID="1" restricted="1">
+    <dc:title>So What</dc:title>
+    <dc:creator>Davis</dc:creator>
+    <upnp:artist role="Performer">Miles Davis</upnp:artist>
+    <upnp:artist role="Composer">Someone Else</upnp:artist>
+    <upnp:album>Kind of Blue</upnp:album>
+    <upnp:genre>Jazz</upnp:genre>
+    <upnp:albumArtURI>http://server/art?id=42&amp;size=500</upnp:albumArtURI>
+    <upnp:class>object.item.audioItem.musicTrack</upnp:class>
+    <res protocolInfo="http-get:*:audio/flac:*" duration="0:09:22.000">http://server/42.flac</res>
+  </item>
+</DIDL-Lite>"#;
+
+        let metadata = parse_didl_lite(didl).unwrap();
+        assert_eq!(metadata.title, "So What");
+        assert_eq!(metadata.artist.as_deref(), Some("Miles Davis"));
+        assert_eq!(metadata.album.as_deref(), Some("Kind of Blue"));
+        assert_eq!(metadata.duration.as_deref(), Some("0:09:22.000"));
+        assert_eq!(metadata.album_art_uri.as_deref(), Some("http://server/art?id=42&size=500"));
+
+        let meta = metadata.to_track_meta();
+        assert_eq!(meta.genre, "Jazz");
+        assert_eq!(meta.device_genre, "Jazz");
+
+        // dc:creator stands in for a missing artist
+        let didl = r#"<DIDL-Lite><item><dc:title>T</dc:title><dc:creator>C</dc:creator></item></DIDL-Lite>"#;
+        assert_eq!(parse_didl_lite(didl).unwrap().artist.as_deref(), Some("C"));
+
+        assert!(parse_didl_lite("").is_none());
+        assert!(parse_didl_lite("NOT_IMPLEMENTED").is_none());
+    }
+
     #[test]
     fn test_escape_xml() {
         assert_eq!(
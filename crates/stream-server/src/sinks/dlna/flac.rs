@@ -0,0 +1,716 @@
+//! Streaming native FLAC encoder for the DLNA HTTP stream
+//!
+//! Produces a `fLaC` stream marker and STREAMINFO block followed by independent
+//! FLAC frames. Total sample count and MD5 are left unset since the stream length
+//! is unbounded, which decoders treat as "unknown".
+//!
+//! Each frame uses FIXED linear prediction (orders 0-4) with partitioned Rice
+//! coding, and picks the cheapest stereo decorrelation (independent, left/side,
+//! right/side or mid/side). This is lighter than full LPC but typically halves the
+//! bandwidth of raw PCM for music.
+
+/// Frames per FLAC block (~85ms at 48kHz, a good latency/efficiency balance)
+pub const DEFAULT_BLOCK_SIZE: usize = 4096;
+
+/// Maximum Rice partition order searched when encoding residuals
+const MAX_PARTITION_ORDER: u32 = 8;
+
+/// Maximum FIXED predictor order defined by the FLAC format
+const MAX_FIXED_ORDER: usize = 4;
+
+/// Streaming FLAC encoder
+pub struct FlacEncoder {
+    sample_rate: u32,
+    channels: u16,
+    bits_per_sample: u8,
+    block_size: usize,
+    frame_number: u64,
+    pending: Vec<i32>,
+}
+
+impl FlacEncoder {
+    /// Create a new encoder for interleaved integer PCM
+    pub fn new(sample_rate: u32, channels: u16, bits_per_sample: u8) -> Self {
+        Self::with_block_size(sample_rate, channels, bits_per_sample, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create a new encoder with a custom block size (16..=65535 frames)
+    pub fn with_block_size(
+        sample_rate: u32,
+        channels: u16,
+        bits_per_sample: u8,
+        block_size: usize,
+    ) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            bits_per_sample,
+            block_size: block_size.clamp(16, 65535),
+            frame_number: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Stream header: `fLaC` marker followed by the STREAMINFO metadata block
+    pub fn header(&self) -> Vec<u8> {
+        self.header_with_length(0)
+    }
+
+    /// Header for a finished file, declaring its total length in frames so
+    /// players can show the duration and seek
+    pub fn header_with_length(&self, total_frames: u64) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write_bytes(b"fLaC");
+
+        // Metadata block header: last-block flag, type 0 (STREAMINFO), length 34
+        w.write(1, 1);
+        w.write(0, 7);
+        w.write(34, 24);
+
+        w.write(self.block_size as u64, 16); // min block size
+        w.write(self.block_size as u64, 16); // max block size
+        w.write(0, 24); // min frame size (unknown)
+        w.write(0, 24); // max frame size (unknown)
+        w.write(self.sample_rate as u64, 20);
+        w.write((self.channels - 1) as u64, 3);
+        w.write((self.bits_per_sample - 1) as u64, 5);
+        w.write(total_frames & 0xF_FFFF_FFFF, 36); // total samples (0 = unknown, e.g. a live stream)
+        w.write_bytes(&[0u8; 16]); // MD5 (not computed)
+
+        w.into_bytes()
+    }
+
+    /// Encode interleaved samples, returning any complete FLAC frames
+    pub fn encode(&mut self, samples: &[i32]) -> Vec<u8> {
+        self.pending.extend_from_slice(samples);
+
+        let samples_per_block = self.block_size * self.channels as usize;
+        let mut output = Vec::new();
+
+        while self.pending.len() >= samples_per_block {
+            let block: Vec<i32> = self.pending.drain(..samples_per_block).collect();
+            output.extend_from_slice(&self.encode_frame(&block));
+        }
+
+        output
+    }
+
+    /// Encode any remaining buffered samples as a final (shorter) frame
+    pub fn flush(&mut self) -> Vec<u8> {
+        let channels = self.channels as usize;
+        let whole = self.pending.len() - self.pending.len() % channels;
+        if whole == 0 {
+            self.pending.clear();
+            return Vec::new();
+        }
+
+        let block: Vec<i32> = self.pending.drain(..whole).collect();
+        self.pending.clear();
+        self.encode_frame(&block)
+    }
+
+    fn encode_frame(&mut self, interleaved: &[i32]) -> Vec<u8> {
+        let channels = self.channels as usize;
+        let block_size = interleaved.len() / channels;
+        let bps = self.bits_per_sample as u32;
+
+        // De-interleave
+        let mut planes: Vec<Vec<i64>> = vec![Vec::with_capacity(block_size); channels];
+        for frame in interleaved.chunks_exact(channels) {
+            for (ch, &s) in frame.iter().enumerate() {
+                planes[ch].push(s as i64);
+            }
+        }
+
+        // Choose channel assignment and plan subframes
+        let (assignment, subframes) = if channels == 2 {
+            plan_stereo(&planes[0], &planes[1], bps)
+        } else {
+            let subframes = planes
+                .iter()
+                .map(|p| (plan_subframe(p, bps), p.clone(), bps))
+                .collect();
+            ((channels - 1) as u64, subframes)
+        };
+
+        let mut w = BitWriter::new();
+
+        // Frame header
+        w.write(0x3FFE, 14); // sync code
+        w.write(0, 1); // reserved
+        w.write(0, 1); // fixed block size strategy
+
+        let (bs_code, bs_extra) = block_size_code(block_size);
+        let (sr_code, sr_extra) = sample_rate_code(self.sample_rate);
+        w.write(bs_code, 4);
+        w.write(sr_code, 4);
+        w.write(assignment, 4);
+        w.write(sample_size_code(self.bits_per_sample), 3);
+        w.write(0, 1); // reserved
+        write_utf8_number(&mut w, self.frame_number);
+        if let Some((value, bits)) = bs_extra {
+            w.write(value, bits);
+        }
+        if let Some((value, bits)) = sr_extra {
+            w.write(value, bits);
+        }
+        let crc8 = crc8(w.bytes());
+        w.write(crc8 as u64, 8);
+
+        // Subframes
+        for (plan, samples, sub_bps) in &subframes {
+            write_subframe(&mut w, plan, samples, *sub_bps);
+        }
+
+        // Byte-align and append CRC-16 of the whole frame
+        w.align();
+        let crc16 = crc16(w.bytes());
+        w.write(crc16 as u64, 16);
+
+        self.frame_number += 1;
+        w.into_bytes()
+    }
+}
+
+/// Chosen encoding for a single subframe
+#[derive(Debug, Clone)]
+enum SubframePlan {
+    Constant,
+    Verbatim,
+    Fixed {
+        order: usize,
+        residual: Vec<i64>,
+        partition_order: u32,
+        params: Vec<u32>,
+    },
+}
+
+/// A planned subframe together with its source samples and bit depth
+type PlannedSubframe = (SubframePlan, Vec<i64>, u32);
+
+/// Pick the cheapest stereo decorrelation mode
+fn plan_stereo(left: &[i64], right: &[i64], bps: u32) -> (u64, Vec<PlannedSubframe>) {
+    let mid: Vec<i64> = left.iter().zip(right).map(|(&l, &r)| (l + r) >> 1).collect();
+    let side: Vec<i64> = left.iter().zip(right).map(|(&l, &r)| l - r).collect();
+
+    let l_plan = plan_subframe(left, bps);
+    let r_plan = plan_subframe(right, bps);
+    let m_plan = plan_subframe(&mid, bps);
+    let s_plan = plan_subframe(&side, bps + 1);
+
+    let l_bits = subframe_bits(&l_plan, left.len(), bps);
+    let r_bits = subframe_bits(&r_plan, right.len(), bps);
+    let m_bits = subframe_bits(&m_plan, mid.len(), bps);
+    let s_bits = subframe_bits(&s_plan, side.len(), bps + 1);
+
+    let candidates = [
+        (0b0001, l_bits + r_bits),
+        (0b1000, l_bits + s_bits),
+        (0b1001, r_bits + s_bits),
+        (0b1010, m_bits + s_bits),
+    ];
+    let (assignment, _) = candidates
+        .iter()
+        .copied()
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap();
+
+    let subframes = match assignment {
+        0b1000 => vec![(l_plan, left.to_vec(), bps), (s_plan, side, bps + 1)],
+        0b1001 => vec![(s_plan, side, bps + 1), (r_plan, right.to_vec(), bps)],
+        0b1010 => vec![(m_plan, mid, bps), (s_plan, side, bps + 1)],
+        _ => vec![(l_plan, left.to_vec(), bps), (r_plan, right.to_vec(), bps)],
+    };
+
+    (assignment, subframes)
+}
+
+/// Choose the cheapest subframe type for a channel
+fn plan_subframe(samples: &[i64], bps: u32) -> SubframePlan {
+    let n = samples.len();
+    if n == 0 || samples.iter().all(|&s| s == samples[0]) {
+        return SubframePlan::Constant;
+    }
+
+    // Pick the FIXED order with the smallest absolute residual sum
+    let max_order = MAX_FIXED_ORDER.min(n - 1);
+    let (order, residual) = (0..=max_order)
+        .map(|order| (order, fixed_residual(samples, order)))
+        .min_by_key(|(_, res)| res.iter().map(|r| r.unsigned_abs()).sum::<u64>())
+        .unwrap();
+
+    let (partition_order, params, _) = best_partitioning(&residual, n, order);
+    let fixed = SubframePlan::Fixed {
+        order,
+        residual,
+        partition_order,
+        params,
+    };
+
+    if subframe_bits(&fixed, n, bps) < subframe_bits(&SubframePlan::Verbatim, n, bps) {
+        fixed
+    } else {
+        SubframePlan::Verbatim
+    }
+}
+
+/// Compute FIXED prediction residuals for the given order
+fn fixed_residual(x: &[i64], order: usize) -> Vec<i64> {
+    (order..x.len())
+        .map(|i| match order {
+            0 => x[i],
+            1 => x[i] - x[i - 1],
+            2 => x[i] - 2 * x[i - 1] + x[i - 2],
+            3 => x[i] - 3 * x[i - 1] + 3 * x[i - 2] - x[i - 3],
+            _ => x[i] - 4 * x[i - 1] + 6 * x[i - 2] - 4 * x[i - 3] + x[i - 4],
+        })
+        .collect()
+}
+
+/// Find the Rice partition order and parameters that minimise the residual size
+///
+/// Returns `(partition_order, params, bits)`.
+fn best_partitioning(residual: &[i64], block_size: usize, order: usize) -> (u32, Vec<u32>, usize) {
+    let folded: Vec<u64> = residual.iter().map(|&r| zigzag(r)).collect();
+    let mut best: Option<(u32, Vec<u32>, usize)> = None;
+
+    for porder in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1usize << porder;
+        if !block_size.is_multiple_of(partitions) || (block_size >> porder) <= order {
+            break;
+        }
+
+        let partition_len = block_size >> porder;
+        let mut params = Vec::with_capacity(partitions);
+        let mut bits = 0usize;
+        let mut start = 0usize;
+
+        for p in 0..partitions {
+            // The first partition excludes the warm-up samples
+            let len = if p == 0 { partition_len - order } else { partition_len };
+            let slice = &folded[start..start + len];
+            start += len;
+
+            let (param, cost) = best_rice_param(slice);
+            params.push(param);
+            bits += cost;
+        }
+
+        let method_bits = if params.iter().any(|&k| k > 14) { 5 } else { 4 };
+        bits += 2 + 4 + partitions * method_bits;
+
+        if best.as_ref().is_none_or(|(_, _, b)| bits < *b) {
+            best = Some((porder, params, bits));
+        }
+    }
+
+    best.unwrap_or((0, vec![0], usize::MAX))
+}
+
+/// Choose a Rice parameter for a partition, returning `(param, bits)`
+fn best_rice_param(folded: &[u64]) -> (u32, usize) {
+    if folded.is_empty() {
+        return (0, 0);
+    }
+
+    let sum: u64 = folded.iter().sum();
+    let mean = sum / folded.len() as u64;
+    let estimate = if mean > 0 { 63 - mean.leading_zeros() } else { 0 };
+
+    let lo = estimate.saturating_sub(1);
+    let hi = (estimate + 1).min(30);
+
+    (lo..=hi)
+        .map(|k| {
+            let bits: u64 = folded.iter().map(|&u| (u >> k) + 1 + k as u64).sum();
+            (k, bits as usize)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap()
+}
+
+/// Exact size in bits of a planned subframe (including its 8-bit header)
+fn subframe_bits(plan: &SubframePlan, n: usize, bps: u32) -> usize {
+    let bps = bps as usize;
+    match plan {
+        SubframePlan::Constant => 8 + bps,
+        SubframePlan::Verbatim => 8 + n * bps,
+        SubframePlan::Fixed {
+            order,
+            residual,
+            partition_order,
+            params,
+        } => {
+            let block_size = n;
+            let partition_len = block_size >> partition_order;
+            let method_bits = if params.iter().any(|&k| k > 14) { 5 } else { 4 };
+            let mut bits = 8 + order * bps + 2 + 4 + params.len() * method_bits;
+            let mut start = 0usize;
+            for (p, &k) in params.iter().enumerate() {
+                let len = if p == 0 { partition_len - order } else { partition_len };
+                bits += residual[start..start + len]
+                    .iter()
+                    .map(|&r| (zigzag(r) >> k) as usize + 1 + k as usize)
+                    .sum::<usize>();
+                start += len;
+            }
+            bits
+        }
+    }
+}
+
+fn write_subframe(w: &mut BitWriter, plan: &SubframePlan, samples: &[i64], bps: u32) {
+    match plan {
+        SubframePlan::Constant => {
+            w.write(0, 1);
+            w.write(0b000000, 6);
+            w.write(0, 1); // no wasted bits
+            w.write_signed(samples.first().copied().unwrap_or(0), bps);
+        }
+        SubframePlan::Verbatim => {
+            w.write(0, 1);
+            w.write(0b000001, 6);
+            w.write(0, 1);
+            for &s in samples {
+                w.write_signed(s, bps);
+            }
+        }
+        SubframePlan::Fixed {
+            order,
+            residual,
+            partition_order,
+            params,
+        } => {
+            w.write(0, 1);
+            w.write(0b001000 | *order as u64, 6);
+            w.write(0, 1);
+
+            // Warm-up samples
+            for &s in &samples[..*order] {
+                w.write_signed(s, bps);
+            }
+
+            // Residual: method 0 (4-bit params) or method 1 (5-bit params)
+            let wide = params.iter().any(|&k| k > 14);
+            let param_bits = if wide { 5 } else { 4 };
+            w.write(if wide { 1 } else { 0 }, 2);
+            w.write(*partition_order as u64, 4);
+
+            let partition_len = samples.len() >> partition_order;
+            let mut start = 0usize;
+            for (p, &k) in params.iter().enumerate() {
+                let len = if p == 0 { partition_len - order } else { partition_len };
+                w.write(k as u64, param_bits);
+                for &r in &residual[start..start + len] {
+                    let u = zigzag(r);
+                    w.write_unary((u >> k) as u32);
+                    if k > 0 {
+                        w.write(u & ((1u64 << k) - 1), k);
+                    }
+                }
+                start += len;
+            }
+        }
+    }
+}
+
+/// Map a signed residual to an unsigned value (0, -1, 1, -2, ... -> 0, 1, 2, 3, ...)
+fn zigzag(r: i64) -> u64 {
+    ((r << 1) ^ (r >> 63)) as u64
+}
+
+fn block_size_code(block_size: usize) -> (u64, Option<(u64, u32)>) {
+    match block_size {
+        192 => (0b0001, None),
+        576 => (0b0010, None),
+        1152 => (0b0011, None),
+        2304 => (0b0100, None),
+        4608 => (0b0101, None),
+        256 => (0b1000, None),
+        512 => (0b1001, None),
+        1024 => (0b1010, None),
+        2048 => (0b1011, None),
+        4096 => (0b1100, None),
+        8192 => (0b1101, None),
+        16384 => (0b1110, None),
+        32768 => (0b1111, None),
+        n if n <= 256 => (0b0110, Some(((n - 1) as u64, 8))),
+        n => (0b0111, Some(((n - 1) as u64, 16))),
+    }
+}
+
+fn sample_rate_code(sample_rate: u32) -> (u64, Option<(u64, u32)>) {
+    match sample_rate {
+        88200 => (0b0001, None),
+        176400 => (0b0010, None),
+        192000 => (0b0011, None),
+        8000 => (0b0100, None),
+        16000 => (0b0101, None),
+        22050 => (0b0110, None),
+        24000 => (0b0111, None),
+        32000 => (0b1000, None),
+        44100 => (0b1001, None),
+        48000 => (0b1010, None),
+        96000 => (0b1011, None),
+        r if r % 1000 == 0 && r / 1000 <= 255 => (0b1100, Some(((r / 1000) as u64, 8))),
+        r if r <= 65535 => (0b1101, Some((r as u64, 16))),
+        r if r % 10 == 0 && r / 10 <= 65535 => (0b1110, Some(((r / 10) as u64, 16))),
+        // Fall back to the rate in STREAMINFO
+        _ => (0b0000, None),
+    }
+}
+
+fn sample_size_code(bits_per_sample: u8) -> u64 {
+    match bits_per_sample {
+        8 => 0b001,
+        12 => 0b010,
+        16 => 0b100,
+        20 => 0b101,
+        24 => 0b110,
+        32 => 0b111,
+        // Fall back to the bit depth in STREAMINFO
+        _ => 0b000,
+    }
+}
+
+/// Write a frame number using FLAC's extended UTF-8 coding
+fn write_utf8_number(w: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        w.write(value, 8);
+        return;
+    }
+
+    // Number of continuation bytes needed (each carries 6 bits)
+    let mut extra = 1;
+    while extra < 6 && value >= 1u64 << (5 * extra + 6) {
+        extra += 1;
+    }
+
+    // Lead byte: (extra + 1) one bits, a zero bit, then the top payload bits
+    let lead_marker = (0xFF00u64 >> (extra + 1)) & 0xFF;
+    w.write(lead_marker | (value >> (6 * extra)), 8);
+    for i in (0..extra).rev() {
+        w.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+/// CRC-8 (polynomial x^8 + x^2 + x + 1) used for FLAC frame headers
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-16 (polynomial x^16 + x^15 + x^2 + 1) used for FLAC frame footers
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// MSB-first bit writer
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            acc: 0,
+            nbits: 0,
+        }
+    }
+
+    /// Write the low `bits` bits of `value` (up to 32 bits at a time)
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits > 32 {
+            self.write(value >> 32, bits - 32);
+            self.write(value & 0xFFFF_FFFF, 32);
+            return;
+        }
+        if bits == 0 {
+            return;
+        }
+
+        let mask = (1u64 << bits) - 1;
+        self.acc = (self.acc << bits) | (value & mask);
+        self.nbits += bits;
+
+        while self.nbits >= 8 {
+            self.nbits -= 8;
+            self.bytes.push((self.acc >> self.nbits) as u8);
+        }
+        self.acc &= (1u64 << self.nbits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// Write `zeros` zero bits followed by a terminating one bit
+    fn write_unary(&mut self, mut zeros: u32) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros + 1);
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) {
+        for &b in data {
+            self.write(b as u64, 8);
+        }
+    }
+
+    /// Pad with zero bits up to the next byte boundary
+    fn align(&mut self) {
+        if self.nbits > 0 {
+            self.write(0, 8 - self.nbits);
+        }
+    }
+
+    /// Completed bytes written so far (excludes any partial byte)
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn decode(data: &[u8]) -> (claxon::metadata::StreamInfo, Vec<i32>) {
+        let mut reader = claxon::FlacReader::new(Cursor::new(data)).unwrap();
+        let info = reader.streaminfo();
+        let samples = reader.samples().map(|s| s.unwrap()).collect();
+        (info, samples)
+    }
+
+    fn test_signal(frames: usize, bits: u32) -> Vec<i32> {
+        let amplitude = ((1i64 << (bits - 1)) - 1) as f64 * 0.8;
+        let mut samples = Vec::with_capacity(frames * 2);
+        for i in 0..frames {
+            let t = i as f64 / 48000.0;
+            let left = (2.0 * std::f64::consts::PI * 440.0 * t).sin() * amplitude;
+            let right = (2.0 * std::f64::consts::PI * 1000.0 * t).sin() * amplitude * 0.5;
+            samples.push(left as i32 + fastrand::i32(-3..=3));
+            samples.push(right as i32 + fastrand::i32(-3..=3));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_roundtrip_16bit_stereo() {
+        let input = test_signal(10_000, 16);
+        let mut encoder = FlacEncoder::new(48000, 2, 16);
+
+        let mut stream = encoder.header();
+        stream.extend(encoder.encode(&input));
+        stream.extend(encoder.flush());
+
+        let (info, decoded) = decode(&stream);
+        assert_eq!(info.sample_rate, 48000);
+        assert_eq!(info.channels, 2);
+        assert_eq!(info.bits_per_sample, 16);
+        assert_eq!(decoded, input);
+
+        // Should compress well below raw PCM size
+        assert!(stream.len() < input.len() * 2);
+    }
+
+    #[test]
+    fn test_roundtrip_24bit_96k() {
+        let input = test_signal(9_000, 24);
+        let mut encoder = FlacEncoder::new(96000, 2, 24);
+
+        let mut stream = encoder.header();
+        stream.extend(encoder.encode(&input));
+        stream.extend(encoder.flush());
+
+        let (info, decoded) = decode(&stream);
+        assert_eq!(info.sample_rate, 96000);
+        assert_eq!(info.bits_per_sample, 24);
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn test_header_with_length() {
+        let input = test_signal(5_000, 16);
+        let mut encoder = FlacEncoder::new(48000, 2, 16);
+
+        let mut stream = encoder.header_with_length(5_000);
+        stream.extend(encoder.encode(&input));
+        stream.extend(encoder.flush());
+
+        let (info, decoded) = decode(&stream);
+        assert_eq!(info.samples, Some(5_000));
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn test_roundtrip_silence_and_extremes() {
+        let mut input = vec![0i32; 4096 * 2];
+        for i in 0..4096 {
+            let v = if i % 2 == 0 { 32767 } else { -32768 };
+            input.push(v);
+            input.push(-v - 1);
+        }
+
+        let mut encoder = FlacEncoder::new(44100, 2, 16);
+        let mut stream = encoder.header();
+        stream.extend(encoder.encode(&input));
+        stream.extend(encoder.flush());
+
+        let (_, decoded) = decode(&stream);
+        assert_eq!(decoded, input);
+    }
+
+    #[test]
+    fn test_encode_buffers_until_full_block() {
+        let mut encoder = FlacEncoder::new(48000, 2, 16);
+        assert!(encoder.encode(&[0i32; 100]).is_empty());
+
+        let frames = encoder.encode(&vec![0i32; DEFAULT_BLOCK_SIZE * 2]);
+        assert!(!frames.is_empty());
+        assert_eq!(frames[0], 0xFF);
+        assert_eq!(frames[1] & 0xFC, 0xF8);
+    }
+
+    #[test]
+    fn test_utf8_frame_numbers() {
+        for &n in &[0u64, 0x7F, 0x80, 0x7FF, 0x800, 0xFFFF, 0x10000, 0x1F_FFFF, 0x20_0000] {
+            let mut w = BitWriter::new();
+            write_utf8_number(&mut w, n);
+            let bytes = w.into_bytes();
+            if n < 0x80 {
+                assert_eq!(bytes, vec![n as u8]);
+            } else {
+                let expected = char::from_u32(n as u32)
+                    .map(|c| c.to_string().into_bytes())
+                    .unwrap_or_default();
+                if !expected.is_empty() {
+                    assert_eq!(bytes, expected, "frame number {:#x}", n);
+                }
+            }
+        }
+    }
+}
@@ -7,19 +7,24 @@
 /// - Device description XML generation
 /// - AVTransport control (push mode)
 /// - DIDL-Lite metadata generation
+/// - Stream encodings (WAV, FLAC, raw LPCM) and a streaming FLAC encoder
 /// - Proper XML parsing with quick-xml
 pub mod avtransport;
 pub mod device_description;
 pub mod device_profiles;
 pub mod didl;
 pub mod discovery;
+pub mod flac;
 pub mod ssdp_server;
+pub mod stream_encoding;
 pub mod xml_parser;
 
 pub use avtransport::{AVTransport, PositionInfo, TransportInfo};
 pub use device_description::{generate_device_uuid, generate_device_description};
 pub use device_profiles::{DeviceProfile, DeviceQuirks, OptimalConfig};
-pub use didl::{generate_didl_lite, generate_simple_didl_lite, MediaMetadata};
+pub use didl::{generate_didl_lite, generate_simple_didl_lite, parse_didl_lite, MediaMetadata};
 pub use discovery::{create_device_from_ip, discover_devices, find_device_by_name, DlnaDevice, DlnaService};
+pub use flac::FlacEncoder;
 pub use ssdp_server::SsdpServer;
+pub use stream_encoding::{create_wav_header, StreamEncoder, StreamEncoding};
 pub use xml_parser::parse_device_xml_proper;
@@ -15,11 +15,22 @@ use tracing::{debug, error, info, warn};
 const SSDP_MULTICAST_ADDR: &str = "239.255.255.250:1900";
 const SSDP_ALIVE_INTERVAL_SECS: u64 = 1800; // 30 minutes (UPnP spec: max 1800)
 
+/// Device and service types announced by default (media server for pull streaming)
+const DEFAULT_TARGETS: &[&str] = &[
+    "urn:schemas-upnp-org:device:MediaServer:1",
+    "urn:schemas-upnp-org:device:MediaRenderer:1",
+    "urn:schemas-upnp-org:service:ContentDirectory:1",
+    "urn:schemas-upnp-org:service:ConnectionManager:1",
+    "urn:schemas-upnp-org:service:AVTransport:1",
+];
+
 /// SSDP Server that announces AAEQ as a UPnP device
 pub struct SsdpServer {
     device_uuid: String,
     friendly_name: String,
     port: u16,
+    /// Device/service types announced besides the root device and UUID
+    targets: Arc<Vec<String>>,
     running: Arc<AtomicBool>,
     notify_task: Option<JoinHandle<()>>,
     search_task: Option<JoinHandle<()>>,
@@ -32,12 +43,19 @@ impl SsdpServer {
             device_uuid,
             friendly_name,
             port,
+            targets: Arc::new(DEFAULT_TARGETS.iter().map(|t| t.to_string()).collect()),
             running: Arc::new(AtomicBool::new(false)),
             notify_task: None,
             search_task: None,
         }
     }
 
+    /// Announce these device and service types instead of the defaults
+    pub fn with_targets(mut self, targets: &[&str]) -> Self {
+        self.targets = Arc::new(targets.iter().map(|t| t.to_string()).collect());
+        self
+    }
+
     /// Start the SSDP server
     ///
     /// This will:
@@ -61,16 +79,18 @@ impl SsdpServer {
         let running = self.running.clone();
         let device_uuid = self.device_uuid.clone();
         let port = self.port;
+        let targets = self.targets.clone();
         self.notify_task = Some(tokio::spawn(async move {
-            periodic_notify_task(device_uuid, port, running).await;
+            periodic_notify_task(device_uuid, port, targets, running).await;
         }));
 
         // Start M-SEARCH response task
         let running = self.running.clone();
         let device_uuid = self.device_uuid.clone();
         let port = self.port;
+        let targets = self.targets.clone();
         self.search_task = Some(tokio::spawn(async move {
-            msearch_response_task(device_uuid, port, running).await;
+            msearch_response_task(device_uuid, port, targets, running).await;
         }));
 
         info!("SSDP server started successfully");
@@ -107,16 +127,7 @@ impl SsdpServer {
 
         info!("Sending SSDP NOTIFY alive messages from {}", local_ip);
 
-        let notification_types = vec![
-            "upnp:rootdevice",
-            &self.device_uuid,
-            "urn:schemas-upnp-org:device:MediaServer:1",
-            "urn:schemas-upnp-org:device:MediaRenderer:1",
-            "urn:schemas-upnp-org:service:ContentDirectory:1",
-            "urn:schemas-upnp-org:service:ConnectionManager:1",
-            "urn:schemas-upnp-org:service:AVTransport:1",
-        ];
-
+        let notification_types = notification_types(&self.device_uuid, &self.targets);
         for nt in &notification_types {
             self.send_notify_message(nt, "ssdp:alive", local_ip)?;
         }
@@ -131,13 +142,7 @@ impl SsdpServer {
 
         info!("Sending SSDP NOTIFY byebye messages");
 
-        let notification_types = vec![
-            "upnp:rootdevice",
-            &self.device_uuid,
-            "urn:schemas-upnp-org:device:MediaServer:1",
-            "urn:schemas-upnp-org:device:MediaRenderer:1",
-        ];
-
+        let notification_types = notification_types(&self.device_uuid, &self.targets);
         for nt in &notification_types {
             self.send_notify_message(nt, "ssdp:byebye", local_ip)?;
         }
@@ -181,15 +186,10 @@ impl Drop for SsdpServer {
         let rt = tokio::runtime::Handle::try_current();
         if let Ok(rt) = rt {
             let device_uuid = self.device_uuid.clone();
+            let targets = self.targets.clone();
             rt.spawn(async move {
                 if let Ok(_local_ip) = get_local_ip() {
-                    let notification_types = vec![
-                        "upnp:rootdevice",
-                        &device_uuid,
-                        "urn:schemas-upnp-org:device:MediaServer:1",
-                    ];
-
-                    for nt in notification_types {
+                    for nt in notification_types(&device_uuid, &targets) {
                         let message = format!(
                             "NOTIFY * HTTP/1.1\r\n\
                              HOST: {}\r\n\
@@ -208,7 +208,7 @@ impl Drop for SsdpServer {
 }
 
 /// Background task to send periodic NOTIFY alive messages
-async fn periodic_notify_task(device_uuid: String, port: u16, running: Arc<AtomicBool>) {
+async fn periodic_notify_task(device_uuid: String, port: u16, targets: Arc<Vec<String>>, running: Arc<AtomicBool>) {
     info!("Starting periodic NOTIFY alive task");
 
     while running.load(Ordering::Relaxed) {
@@ -221,14 +221,7 @@ async fn periodic_notify_task(device_uuid: String, port: u16, running: Arc<Atomi
 
         // Send NOTIFY alive messages
         if let Ok(local_ip) = get_local_ip() {
-            let notification_types = vec![
-                "upnp:rootdevice",
-                &device_uuid,
-                "urn:schemas-upnp-org:device:MediaServer:1",
-                "urn:schemas-upnp-org:device:MediaRenderer:1",
-            ];
-
-            for nt in &notification_types {
+            for nt in &notification_types(&device_uuid, &targets) {
                 let location = format!("http://{}:{}/device.xml", local_ip, port);
                 let message = format!(
                     "NOTIFY * HTTP/1.1\r\n\
@@ -256,7 +249,7 @@ async fn periodic_notify_task(device_uuid: String, port: u16, running: Arc<Atomi
 }
 
 /// Background task to respond to M-SEARCH requests
-async fn msearch_response_task(device_uuid: String, port: u16, running: Arc<AtomicBool>) {
+async fn msearch_response_task(device_uuid: String, port: u16, targets: Arc<Vec<String>>, running: Arc<AtomicBool>) {
     info!("Starting M-SEARCH response task");
 
     // Create socket for listening to M-SEARCH requests
@@ -285,7 +278,7 @@ async fn msearch_response_task(device_uuid: String, port: u16, running: Arc<Atom
                     let st = extract_search_target(&request);
 
                     // Check if we should respond to this search target
-                    if should_respond_to_st(&st, &device_uuid) {
+                    if should_respond_to_st(&st, &device_uuid, &targets) {
                         if let Err(e) = send_msearch_response(&socket, addr, &device_uuid, &st, port)
                         {
                             warn!("Failed to send M-SEARCH response: {}", e);
@@ -384,18 +377,20 @@ fn extract_search_target(request: &str) -> String {
     String::new()
 }
 
+/// Every NT value announced: root device, UUID, then the device/service types
+fn notification_types(device_uuid: &str, targets: &[String]) -> Vec<String> {
+    let mut types = vec!["upnp:rootdevice".to_string(), device_uuid.to_string()];
+    types.extend(targets.iter().cloned());
+    types
+}
+
 /// Check if we should respond to a given search target
-fn should_respond_to_st(st: &str, device_uuid: &str) -> bool {
+fn should_respond_to_st(st: &str, device_uuid: &str, targets: &[String]) -> bool {
     match st {
         "ssdp:all" => true,
         "upnp:rootdevice" => true,
         st if st == device_uuid => true,
-        "urn:schemas-upnp-org:device:MediaServer:1" => true,
-        "urn:schemas-upnp-org:device:MediaRenderer:1" => true,
-        "urn:schemas-upnp-org:service:ContentDirectory:1" => true,
-        "urn:schemas-upnp-org:service:ConnectionManager:1" => true,
-        "urn:schemas-upnp-org:service:AVTransport:1" => true,
-        _ => false,
+        st => targets.iter().any(|t| t == st),
     }
 }
 
@@ -425,15 +420,20 @@ mod tests {
     #[test]
     fn test_should_respond_to_st() {
         let uuid = "uuid:12345678-1234-1234-1234-123456789012";
+        let targets: Vec<String> = DEFAULT_TARGETS.iter().map(|t| t.to_string()).collect();
 
-        assert!(should_respond_to_st("ssdp:all", uuid));
-        assert!(should_respond_to_st("upnp:rootdevice", uuid));
-        assert!(should_respond_to_st(uuid, uuid));
+        assert!(should_respond_to_st("ssdp:all", uuid, &targets));
+        assert!(should_respond_to_st("upnp:rootdevice", uuid, &targets));
+        assert!(should_respond_to_st(uuid, uuid, &targets));
         assert!(should_respond_to_st(
             "urn:schemas-upnp-org:device:MediaServer:1",
-            uuid
+            uuid,
+            &targets
         ));
-        assert!(!should_respond_to_st("some:other:device", uuid));
+        assert!(!should_respond_to_st("some:other:device", uuid, &targets));
+
+        let renderer = vec!["urn:schemas-upnp-org:device:MediaRenderer:1".to_string()];
+        assert!(!should_respond_to_st("urn:schemas-upnp-org:device:MediaServer:1", uuid, &renderer));
     }
 
     #[test]
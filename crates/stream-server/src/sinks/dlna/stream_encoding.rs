@@ -0,0 +1,309 @@
+/// Wire encodings for the DLNA HTTP stream
+///
+/// The sink buffers little-endian PCM in the configured `SampleFormat`; each HTTP
+/// connection wraps that PCM in the chosen container on the way out.
+use super::flac::FlacEncoder;
+use crate::types::{OutputConfig, SampleFormat};
+use serde::{Deserialize, Serialize};
+
+/// Encoding used for the DLNA HTTP stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StreamEncoding {
+    /// RIFF/WAV header followed by little-endian PCM (most compatible)
+    #[default]
+    Wav,
+    /// Native FLAC frames (lossless, roughly half the bandwidth of PCM)
+    Flac,
+    /// Raw big-endian PCM (`audio/L16` or `audio/L24`), no header
+    Lpcm,
+}
+
+impl StreamEncoding {
+    /// All supported encodings
+    pub fn all() -> &'static [StreamEncoding] {
+        &[StreamEncoding::Wav, StreamEncoding::Flac, StreamEncoding::Lpcm]
+    }
+
+    /// HTTP path the stream is served on
+    pub fn path(&self) -> &'static str {
+        match self {
+            StreamEncoding::Wav => "/stream.wav",
+            StreamEncoding::Flac => "/stream.flac",
+            StreamEncoding::Lpcm => "/stream.pcm",
+        }
+    }
+
+    /// MIME type for the given output configuration
+    pub fn mime_type(&self, config: &OutputConfig) -> String {
+        match self {
+            StreamEncoding::Wav => "audio/wav".to_string(),
+            StreamEncoding::Flac => "audio/flac".to_string(),
+            StreamEncoding::Lpcm => {
+                let bits = match config.format {
+                    SampleFormat::S24LE => 24,
+                    _ => 16,
+                };
+                format!(
+                    "audio/L{};rate={};channels={}",
+                    bits, config.sample_rate, config.channels
+                )
+            }
+        }
+    }
+
+    /// DLNA.ORG_PN media profile, if one applies
+    ///
+    /// FLAC has no official DLNA profile, and LPCM is only defined for
+    /// 16-bit stereo at 44.1/48kHz.
+    pub fn dlna_profile(&self, config: &OutputConfig) -> Option<&'static str> {
+        match self {
+            StreamEncoding::Wav => Some("WAV"),
+            StreamEncoding::Flac => None,
+            StreamEncoding::Lpcm => {
+                let is_lpcm = config.format == SampleFormat::S16LE
+                    && config.channels <= 2
+                    && matches!(config.sample_rate, 44100 | 48000);
+                is_lpcm.then_some("LPCM")
+            }
+        }
+    }
+
+    /// Full UPnP `protocolInfo` string for DIDL-Lite `<res>` elements
+    pub fn protocol_info(&self, config: &OutputConfig) -> String {
+        let mut info = format!("http-get:*:{}:", self.mime_type(config));
+        if let Some(profile) = self.dlna_profile(config) {
+            info.push_str(&format!("DLNA.ORG_PN={};", profile));
+        }
+        info.push_str("DLNA.ORG_OP=01;DLNA.ORG_FLAGS=01700000000000000000000000000000");
+        info
+    }
+}
+
+impl std::fmt::Display for StreamEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamEncoding::Wav => write!(f, "WAV"),
+            StreamEncoding::Flac => write!(f, "FLAC"),
+            StreamEncoding::Lpcm => write!(f, "LPCM"),
+        }
+    }
+}
+
+/// Per-connection encoder turning buffered little-endian PCM into the wire format
+pub struct StreamEncoder {
+    encoding: StreamEncoding,
+    config: OutputConfig,
+    flac: Option<FlacEncoder>,
+    /// Bytes of an incomplete sample carried over between chunks
+    remainder: Vec<u8>,
+}
+
+impl StreamEncoder {
+    /// Create an encoder for the given encoding and output configuration
+    pub fn new(encoding: StreamEncoding, config: &OutputConfig) -> Self {
+        let flac = (encoding == StreamEncoding::Flac).then(|| {
+            FlacEncoder::new(config.sample_rate, config.channels, config.format.bit_depth())
+        });
+
+        Self {
+            encoding,
+            config: config.clone(),
+            flac,
+            remainder: Vec::new(),
+        }
+    }
+
+    /// Bytes sent before any audio data
+    pub fn header(&self) -> Vec<u8> {
+        match self.encoding {
+            StreamEncoding::Wav => create_wav_header_for_config(&self.config),
+            StreamEncoding::Flac => self.flac.as_ref().map(|f| f.header()).unwrap_or_default(),
+            StreamEncoding::Lpcm => Vec::new(),
+        }
+    }
+
+    /// Encode a chunk of little-endian PCM in the configured sample format
+    pub fn encode(&mut self, pcm: &[u8]) -> Vec<u8> {
+        if self.encoding == StreamEncoding::Wav {
+            return pcm.to_vec();
+        }
+
+        let bytes_per_sample = self.config.format.bytes_per_sample();
+        self.remainder.extend_from_slice(pcm);
+        let whole = self.remainder.len() - self.remainder.len() % bytes_per_sample;
+        let data: Vec<u8> = self.remainder.drain(..whole).collect();
+
+        match self.encoding {
+            StreamEncoding::Lpcm => {
+                // Network byte order: reverse the bytes of each sample
+                let mut out = Vec::with_capacity(data.len());
+                for sample in data.chunks_exact(bytes_per_sample) {
+                    out.extend(sample.iter().rev());
+                }
+                out
+            }
+            StreamEncoding::Flac => {
+                let samples = pcm_to_i32(&data, self.config.format);
+                match self.flac.as_mut() {
+                    Some(flac) => flac.encode(&samples),
+                    None => Vec::new(),
+                }
+            }
+            StreamEncoding::Wav => unreachable!(),
+        }
+    }
+}
+
+/// Decode little-endian integer PCM bytes to sign-extended i32 samples
+pub(crate) fn pcm_to_i32(data: &[u8], format: SampleFormat) -> Vec<i32> {
+    match format {
+        SampleFormat::S16LE => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as i32)
+            .collect(),
+        SampleFormat::S24LE => data
+            .chunks_exact(3)
+            .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8)
+            .collect(),
+        // Float formats are rejected when the DLNA sink is opened
+        SampleFormat::F32 | SampleFormat::F64 => Vec::new(),
+    }
+}
+
+/// WAV header for a stream of unknown length (both sizes set to the maximum)
+pub fn create_wav_header_for_config(cfg: &OutputConfig) -> Vec<u8> {
+    create_wav_header(cfg, u32::MAX)
+}
+
+/// Canonical 44-byte WAV header for `data_bytes` of audio
+pub fn create_wav_header(cfg: &OutputConfig, data_bytes: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(44);
+
+    // RIFF header
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&data_bytes.saturating_add(36).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+
+    // fmt chunk
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&wave_format(cfg));
+
+    // data chunk
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_bytes.to_le_bytes());
+
+    header
+}
+
+/// `WAVEFORMAT` fields shared by WAV and W64 (16 bytes)
+pub(crate) fn wave_format(cfg: &OutputConfig) -> Vec<u8> {
+    let bits = cfg.format.bit_depth() as u16;
+    let block_align = cfg.channels * bits / 8;
+    let byte_rate = cfg.sample_rate * block_align as u32;
+    let format_tag: u16 = if cfg.format.is_float() { 3 } else { 1 }; // IEEE float / PCM
+
+    let mut fmt = Vec::with_capacity(16);
+    fmt.extend_from_slice(&format_tag.to_le_bytes());
+    fmt.extend_from_slice(&cfg.channels.to_le_bytes());
+    fmt.extend_from_slice(&cfg.sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&byte_rate.to_le_bytes());
+    fmt.extend_from_slice(&block_align.to_le_bytes());
+    fmt.extend_from_slice(&bits.to_le_bytes());
+    fmt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(format: SampleFormat, sample_rate: u32) -> OutputConfig {
+        OutputConfig {
+            sample_rate,
+            channels: 2,
+            format,
+            buffer_ms: 150,
+            exclusive: false,
+        }
+    }
+
+    #[test]
+    fn test_wav_header_creation() {
+        let header = create_wav_header_for_config(&config(SampleFormat::S16LE, 48000));
+
+        // WAV header should be 44 bytes
+        assert_eq!(header.len(), 44);
+        assert_eq!(&header[0..4], b"RIFF");
+        assert_eq!(&header[8..12], b"WAVE");
+        assert_eq!(&header[12..16], b"fmt ");
+        assert_eq!(&header[4..8], &u32::MAX.to_le_bytes());
+        assert_eq!(&header[40..44], &u32::MAX.to_le_bytes());
+
+        let header = create_wav_header(&config(SampleFormat::S24LE, 44100), 600);
+        assert_eq!(&header[4..8], &636u32.to_le_bytes());
+        assert_eq!(&header[28..32], &(44100u32 * 6).to_le_bytes());
+        assert_eq!(&header[40..44], &600u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_mime_types() {
+        let cfg16 = config(SampleFormat::S16LE, 44100);
+        let cfg24 = config(SampleFormat::S24LE, 96000);
+
+        assert_eq!(StreamEncoding::Wav.mime_type(&cfg16), "audio/wav");
+        assert_eq!(StreamEncoding::Flac.mime_type(&cfg24), "audio/flac");
+        assert_eq!(
+            StreamEncoding::Lpcm.mime_type(&cfg16),
+            "audio/L16;rate=44100;channels=2"
+        );
+        assert_eq!(
+            StreamEncoding::Lpcm.mime_type(&cfg24),
+            "audio/L24;rate=96000;channels=2"
+        );
+    }
+
+    #[test]
+    fn test_protocol_info_profiles() {
+        let cfg16 = config(SampleFormat::S16LE, 48000);
+        let cfg24 = config(SampleFormat::S24LE, 96000);
+
+        assert!(StreamEncoding::Lpcm.protocol_info(&cfg16).contains("DLNA.ORG_PN=LPCM;"));
+        assert!(!StreamEncoding::Lpcm.protocol_info(&cfg24).contains("DLNA.ORG_PN"));
+        assert!(!StreamEncoding::Flac.protocol_info(&cfg24).contains("DLNA.ORG_PN"));
+        assert!(StreamEncoding::Flac
+            .protocol_info(&cfg24)
+            .starts_with("http-get:*:audio/flac:"));
+    }
+
+    #[test]
+    fn test_lpcm_swaps_byte_order_across_chunks() {
+        let mut encoder = StreamEncoder::new(StreamEncoding::Lpcm, &config(SampleFormat::S24LE, 48000));
+        assert!(encoder.header().is_empty());
+
+        // Split a 24-bit sample across two chunks
+        let first = encoder.encode(&[0x01, 0x02, 0x03, 0x04]);
+        let second = encoder.encode(&[0x05, 0x06]);
+
+        assert_eq!(first, vec![0x03, 0x02, 0x01]);
+        assert_eq!(second, vec![0x06, 0x05, 0x04]);
+    }
+
+    #[test]
+    fn test_flac_stream_decodes() {
+        let cfg = config(SampleFormat::S24LE, 48000);
+        let mut encoder = StreamEncoder::new(StreamEncoding::Flac, &cfg);
+
+        let input: Vec<i32> = (0..8192 * 2).map(|i| ((i * 997) % 65536) - 32768).collect();
+        let mut pcm = Vec::new();
+        for s in &input {
+            pcm.extend_from_slice(&s.to_le_bytes()[0..3]);
+        }
+
+        let mut stream = encoder.header();
+        stream.extend(encoder.encode(&pcm));
+
+        let mut reader = claxon::FlacReader::new(std::io::Cursor::new(stream)).unwrap();
+        let decoded: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded, input);
+    }
+}
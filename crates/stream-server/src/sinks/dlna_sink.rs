@@ -6,9 +6,11 @@ use crate::sinks::dlna::{
         generate_av_transport_scpd, generate_connection_manager_scpd, generate_content_directory_scpd,
         generate_device_description, generate_device_uuid,
     },
+    device_profiles::DeviceProfile,
     didl::{generate_didl_lite, MediaMetadata},
     discovery::DlnaDevice,
     ssdp_server::SsdpServer,
+    stream_encoding::{StreamEncoder, StreamEncoding},
 };
 use crate::types::{AudioBlock, OutputConfig, SampleFormat};
 use anyhow::{anyhow, Result};
@@ -55,6 +57,7 @@ pub struct DlnaSink {
     device_name: String,
     device: Option<DlnaDevice>,
     mode: DlnaMode,
+    encoding: StreamEncoding,
     config: Option<OutputConfig>,
     server_addr: SocketAddr,
     shutdown_tx: Option<mpsc::Sender<()>>,
@@ -74,6 +77,7 @@ impl DlnaSink {
             device_name,
             device: None,
             mode: DlnaMode::Pull,
+            encoding: StreamEncoding::default(),
             config: None,
             server_addr: bind_addr,
             shutdown_tx: None,
@@ -86,14 +90,18 @@ impl DlnaSink {
     }
 
     /// Create a new DLNA sink with a discovered device (supports both modes)
+    ///
+    /// The stream encoding is taken from the device's profile.
     pub fn with_device(device: DlnaDevice, bind_addr: SocketAddr, mode: DlnaMode) -> Self {
         let device_name = device.name.clone();
         let device_uuid = generate_device_uuid().unwrap_or_else(|_| format!("uuid:{}", uuid::Uuid::new_v4()));
+        let encoding = DeviceProfile::from_device(&device).optimal_config.encoding;
 
         Self {
             device_name,
             device: Some(device),
             mode,
+            encoding,
             config: None,
             server_addr: bind_addr,
             shutdown_tx: None,
@@ -105,13 +113,24 @@ impl DlnaSink {
         }
     }
 
-    /// Set the DLNA device to use
+    /// Set the DLNA device to use (also applies the device profile's stream encoding)
     pub fn set_device(&mut self, device: DlnaDevice, mode: DlnaMode) {
         self.device_name = device.name.clone();
+        self.encoding = DeviceProfile::from_device(&device).optimal_config.encoding;
         self.device = Some(device);
         self.mode = mode;
     }
 
+    /// Override the stream encoding (takes effect on the next open)
+    pub fn set_encoding(&mut self, encoding: StreamEncoding) {
+        self.encoding = encoding;
+    }
+
+    /// Get the stream encoding used for the advertised stream URL
+    pub fn encoding(&self) -> StreamEncoding {
+        self.encoding
+    }
+
     /// Get the stream URL that clients should connect to
     pub fn stream_url(&self) -> Option<String> {
         if self.is_open {
@@ -119,12 +138,17 @@ impl DlnaSink {
             if let Some(device) = &self.device {
                 if let Some(device_ip) = &device.ip {
                     if let Some(local_ip) = get_local_ip_for_device(device_ip) {
-                        return Some(format!("http://{}:{}/stream.wav", local_ip, self.server_addr.port()));
+                        return Some(format!(
+                            "http://{}:{}{}",
+                            local_ip,
+                            self.server_addr.port(),
+                            self.encoding.path()
+                        ));
                     }
                 }
             }
             // Fallback to bind address
-            Some(format!("http://{}/stream.wav", self.server_addr))
+            Some(format!("http://{}{}", self.server_addr, self.encoding.path()))
         } else {
             None
         }
@@ -149,7 +173,9 @@ impl DlnaSink {
         };
 
         let app = Router::new()
-            .route("/stream.wav", get(stream_handler))
+            .route(StreamEncoding::Wav.path(), get(wav_stream_handler))
+            .route(StreamEncoding::Flac.path(), get(flac_stream_handler))
+            .route(StreamEncoding::Lpcm.path(), get(lpcm_stream_handler))
             .route("/status", get(status_handler))
             .route("/album_art.png", get(album_art_handler))
             .route("/device.xml", get(device_description_handler))
@@ -189,18 +215,33 @@ struct AppState {
     port: u16,
 }
 
-async fn stream_handler(State(state): State<AppState>) -> Response {
-    info!("Client connected to DLNA stream");
+async fn wav_stream_handler(State(state): State<AppState>) -> Response {
+    stream_response(state, StreamEncoding::Wav)
+}
+
+async fn flac_stream_handler(State(state): State<AppState>) -> Response {
+    stream_response(state, StreamEncoding::Flac)
+}
+
+async fn lpcm_stream_handler(State(state): State<AppState>) -> Response {
+    stream_response(state, StreamEncoding::Lpcm)
+}
+
+fn stream_response(state: AppState, encoding: StreamEncoding) -> Response {
+    info!("Client connected to DLNA stream ({})", encoding);
 
-    // Create WAV header
-    let header = create_wav_header_for_config(&state.config);
+    let mut encoder = StreamEncoder::new(encoding, &state.config);
+    let header = encoder.header();
+    let content_type = encoding.mime_type(&state.config);
 
     // Create streaming response
     use axum::body::Bytes;
 
     let stream = async_stream::stream! {
-        // Send WAV header first
-        yield Ok::<Bytes, std::io::Error>(Bytes::from(header));
+        // Send container header first (empty for raw LPCM)
+        if !header.is_empty() {
+            yield Ok::<Bytes, std::io::Error>(Bytes::from(header));
+        }
 
         // Stream audio data in small chunks for low latency
         loop {
@@ -223,7 +264,11 @@ async fn stream_handler(State(state): State<AppState>) -> Response {
             };
 
             if !data.is_empty() {
-                yield Ok(Bytes::from(data));
+                // FLAC only emits once a full block has been buffered
+                let encoded = encoder.encode(&data);
+                if !encoded.is_empty() {
+                    yield Ok::<Bytes, std::io::Error>(Bytes::from(encoded));
+                }
             } else {
                 // No data available, wait a bit
                 tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
@@ -233,7 +278,7 @@ async fn stream_handler(State(state): State<AppState>) -> Response {
 
     Response::builder()
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "audio/wav")
+        .header(header::CONTENT_TYPE, content_type)
         .header(header::TRANSFER_ENCODING, "chunked")
         .body(Body::from_stream(stream))
         .unwrap()
@@ -250,6 +295,7 @@ async fn status_handler(State(state): State<AppState>) -> impl IntoResponse {
         "sample_rate": state.config.sample_rate,
         "channels": state.config.channels,
         "format": format!("{:?}", state.config.format),
+        "encodings": StreamEncoding::all().iter().map(|e| e.to_string()).collect::<Vec<_>>(),
         "buffer_bytes": buffer_size,
     });
 
@@ -327,37 +373,6 @@ async fn av_transport_handler() -> Response {
         .unwrap()
 }
 
-fn create_wav_header_for_config(cfg: &OutputConfig) -> Vec<u8> {
-    let sample_rate = cfg.sample_rate;
-    let channels = cfg.channels;
-    let bits_per_sample = cfg.format.bit_depth() as u16;
-    let byte_rate = sample_rate * channels as u32 * (bits_per_sample as u32 / 8);
-    let block_align = channels * (bits_per_sample / 8);
-
-    let mut header = Vec::new();
-
-    // RIFF header
-    header.extend_from_slice(b"RIFF");
-    header.extend_from_slice(&0xFFFFFFFFu32.to_le_bytes());
-    header.extend_from_slice(b"WAVE");
-
-    // fmt chunk
-    header.extend_from_slice(b"fmt ");
-    header.extend_from_slice(&16u32.to_le_bytes());
-    header.extend_from_slice(&1u16.to_le_bytes());
-    header.extend_from_slice(&channels.to_le_bytes());
-    header.extend_from_slice(&sample_rate.to_le_bytes());
-    header.extend_from_slice(&byte_rate.to_le_bytes());
-    header.extend_from_slice(&block_align.to_le_bytes());
-    header.extend_from_slice(&bits_per_sample.to_le_bytes());
-
-    // data chunk
-    header.extend_from_slice(b"data");
-    header.extend_from_slice(&0xFFFFFFFFu32.to_le_bytes());
-
-    header
-}
-
 #[async_trait]
 impl OutputSink for DlnaSink {
     fn name(&self) -> &'static str {
@@ -390,7 +405,7 @@ impl OutputSink for DlnaSink {
         self.is_open = true;
 
         let stream_url = self.stream_url().unwrap();
-        info!("DLNA stream available at: {}", stream_url);
+        info!("DLNA stream available at: {} ({})", stream_url, self.encoding);
 
         // Start SSDP server for automatic device discovery
         let mut ssdp_server = SsdpServer::new(
@@ -440,7 +455,7 @@ impl OutputSink for DlnaSink {
                     album_art_uri: Some(album_art_url),
                 };
 
-                let didl = generate_didl_lite(&stream_url, &metadata, &cfg);
+                let didl = generate_didl_lite(&stream_url, &metadata, &cfg, self.encoding);
 
                 // Set the URI on the renderer
                 avtransport
@@ -629,27 +644,13 @@ mod tests {
     }
 
     #[test]
-    fn test_wav_header_creation() {
-        let config = OutputConfig {
-            sample_rate: 48000,
-            channels: 2,
-            format: SampleFormat::S16LE,
-            buffer_ms: 150,
-            exclusive: false,
-        };
-
-        let header = create_wav_header_for_config(&config);
-
-        // WAV header should be 44 bytes
-        assert_eq!(header.len(), 44);
-
-        // Check RIFF signature
-        assert_eq!(&header[0..4], b"RIFF");
-
-        // Check WAVE signature
-        assert_eq!(&header[8..12], b"WAVE");
-
-        // Check fmt chunk
-        assert_eq!(&header[12..16], b"fmt ");
+    fn test_stream_url_follows_encoding() {
+        let addr: SocketAddr = "127.0.0.1:8090".parse().unwrap();
+        let mut sink = DlnaSink::new("Test Device".to_string(), addr);
+        assert_eq!(sink.encoding(), StreamEncoding::Wav);
+
+        sink.set_encoding(StreamEncoding::Flac);
+        sink.is_open = true;
+        assert_eq!(sink.stream_url().unwrap(), "http://127.0.0.1:8090/stream.flac");
     }
 }
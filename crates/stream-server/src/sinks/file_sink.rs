@@ -0,0 +1,385 @@
+use crate::convert::convert_format;
+use crate::sink::{OutputSink, SinkStats};
+use crate::sinks::dlna::stream_encoding::{pcm_to_i32, wave_format};
+use crate::sinks::dlna::{create_wav_header, FlacEncoder, MediaMetadata};
+use crate::types::{AudioBlock, OutputConfig};
+use aaeq_core::TrackMeta;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Sony Wave64 chunk GUIDs (the first four bytes spell the RIFF chunk id)
+const W64_RIFF: [u8; 16] = [
+    b'r', b'i', b'f', b'f', 0x2E, 0x91, 0xCF, 0x11, 0xA5, 0xD6, 0x28, 0xDB, 0x04, 0xC1, 0x00, 0x00,
+];
+const W64_WAVE: [u8; 16] = [
+    b'w', b'a', b'v', b'e', 0xF3, 0xAC, 0xD3, 0x11, 0x8C, 0xD1, 0x00, 0xC0, 0x4F, 0x8E, 0xDB, 0x8A,
+];
+const W64_FMT: [u8; 16] = [
+    b'f', b'm', b't', b' ', 0xF3, 0xAC, 0xD3, 0x11, 0x8C, 0xD1, 0x00, 0xC0, 0x4F, 0x8E, 0xDB, 0x8A,
+];
+const W64_DATA: [u8; 16] = [
+    b'd', b'a', b't', b'a', 0xF3, 0xAC, 0xD3, 0x11, 0x8C, 0xD1, 0x00, 0xC0, 0x4F, 0x8E, 0xDB, 0x8A,
+];
+
+/// Container written by the file sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FileFormat {
+    /// RIFF/WAV (sizes are 32-bit, so files stop being valid past 4 GB)
+    #[default]
+    Wav,
+    /// Lossless FLAC (S16LE and S24LE only)
+    Flac,
+    /// Sony Wave64, WAV with 64-bit sizes for long captures
+    W64,
+}
+
+impl FileFormat {
+    /// File extension without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Wav => "wav",
+            FileFormat::Flac => "flac",
+            FileFormat::W64 => "w64",
+        }
+    }
+
+    /// Guess the format from a file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "wav" => Some(FileFormat::Wav),
+            "flac" => Some(FileFormat::Flac),
+            "w64" => Some(FileFormat::W64),
+            _ => None,
+        }
+    }
+}
+
+/// One output file being written
+struct Recording {
+    path: PathBuf,
+    file: BufWriter<File>,
+    flac: Option<FlacEncoder>,
+    data_bytes: u64,
+    frames: u64,
+}
+
+/// Output sink that records the processed stream to disk
+///
+/// Writes exactly what would be sent to a renderer, converted to
+/// `OutputConfig.format` by `convert_format`. By default everything goes into
+/// one file at `path`; with [`FileSink::with_split_tracks`] `path` is a
+/// directory and each metadata change starts a new numbered file.
+pub struct FileSink {
+    path: PathBuf,
+    format: FileFormat,
+    split_tracks: bool,
+    config: Option<OutputConfig>,
+    metadata: Option<MediaMetadata>,
+    recording: Option<Recording>,
+    /// Files written since the sink was opened
+    files: Vec<PathBuf>,
+    frames_written: u64,
+    scratch: Vec<u8>,
+}
+
+impl FileSink {
+    /// Record to `path`, choosing the container from its extension (WAV if unknown)
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            format: FileFormat::from_path(&path).unwrap_or_default(),
+            path,
+            split_tracks: false,
+            config: None,
+            metadata: None,
+            recording: None,
+            files: Vec::new(),
+            frames_written: 0,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Override the container format
+    pub fn with_format(mut self, format: FileFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Treat the path as a directory and start a new file on every track change
+    pub fn with_split_tracks(mut self, split_tracks: bool) -> Self {
+        self.split_tracks = split_tracks;
+        self
+    }
+
+    pub fn format(&self) -> FileFormat {
+        self.format
+    }
+
+    /// Files written since the sink was opened, in order
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Update the current track
+    ///
+    /// When splitting per track, a change of title, artist or album finishes
+    /// the current file; the next one is named after the new track. A file
+    /// with no audio yet is replaced rather than kept.
+    pub fn set_metadata(&mut self, metadata: MediaMetadata) -> Result<()> {
+        let changed = self.metadata.as_ref().is_none_or(|current| {
+            current.title != metadata.title || current.artist != metadata.artist || current.album != metadata.album
+        });
+        self.metadata = Some(metadata);
+
+        if self.split_tracks && changed && self.recording.is_some() {
+            if self.recording.as_ref().is_some_and(|recording| recording.frames == 0) {
+                self.discard_recording()?;
+            } else {
+                self.finish_recording()?;
+            }
+            self.start_recording()?;
+        }
+        Ok(())
+    }
+
+    /// Path for the next file
+    fn next_path(&self) -> PathBuf {
+        if !self.split_tracks {
+            return self.path.clone();
+        }
+
+        let mut name = format!("{:03}", self.files.len() + 1);
+        if let Some(metadata) = &self.metadata {
+            if let Some(artist) = metadata.artist.as_deref().filter(|a| !a.is_empty()) {
+                name.push_str(" - ");
+                name.push_str(artist);
+            }
+            if !metadata.title.is_empty() {
+                name.push_str(" - ");
+                name.push_str(&metadata.title);
+            }
+        }
+        self.path
+            .join(format!("{}.{}", sanitize_file_name(&name), self.format.extension()))
+    }
+
+    fn start_recording(&mut self) -> Result<()> {
+        let cfg = self.config.as_ref().ok_or_else(|| anyhow!("Sink not open"))?;
+        let path = self.next_path();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+
+        let file = File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let flac = (self.format == FileFormat::Flac)
+            .then(|| FlacEncoder::new(cfg.sample_rate, cfg.channels, cfg.format.bit_depth()));
+        let mut recording = Recording {
+            path: path.clone(),
+            file: BufWriter::new(file),
+            flac,
+            data_bytes: 0,
+            frames: 0,
+        };
+        recording.file.write_all(&header(self.format, cfg, &recording))?;
+
+        info!("Recording to {}", path.display());
+        self.files.push(path);
+        self.recording = Some(recording);
+        Ok(())
+    }
+
+    /// Delete the current file, which has no audio yet
+    fn discard_recording(&mut self) -> Result<()> {
+        if let Some(Recording { path, file, .. }) = self.recording.take() {
+            drop(file);
+            std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+            self.files.retain(|written| *written != path);
+        }
+        Ok(())
+    }
+
+    /// Flush the current file and patch its header with the final sizes
+    fn finish_recording(&mut self) -> Result<()> {
+        let (Some(mut recording), Some(cfg)) = (self.recording.take(), self.config.as_ref()) else {
+            return Ok(());
+        };
+
+        if let Some(flac) = recording.flac.as_mut() {
+            let tail = flac.flush();
+            recording.file.write_all(&tail)?;
+        }
+        if self.format == FileFormat::W64 && !recording.data_bytes.is_multiple_of(8) {
+            // Chunks are 8-byte aligned; the padding isn't part of the data size
+            let padding = 8 - recording.data_bytes % 8;
+            recording.file.write_all(&vec![0; padding as usize])?;
+        }
+        if self.format == FileFormat::Wav && recording.data_bytes > (u32::MAX - 36) as u64 {
+            warn!(
+                "{} is larger than 4 GB; its WAV header is invalid (use W64 for long captures)",
+                recording.path.display()
+            );
+        }
+
+        recording.file.seek(SeekFrom::Start(0))?;
+        recording.file.write_all(&header(self.format, cfg, &recording))?;
+        recording.file.flush()?;
+        info!("Finished {} ({} frames)", recording.path.display(), recording.frames);
+        Ok(())
+    }
+}
+
+/// Replace characters that aren't allowed in file names
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim_end_matches(['.', ' '])
+        .to_string()
+}
+
+/// File header for the recording's current length
+fn header(format: FileFormat, cfg: &OutputConfig, recording: &Recording) -> Vec<u8> {
+    match format {
+        FileFormat::Wav => create_wav_header(cfg, recording.data_bytes.min((u32::MAX - 36) as u64) as u32),
+        FileFormat::W64 => {
+            // riff(40) + fmt(40) + data chunk header(24)
+            let padded = recording.data_bytes.div_ceil(8) * 8;
+            let mut header = Vec::with_capacity(104);
+            header.extend_from_slice(&W64_RIFF);
+            header.extend_from_slice(&(104 + padded).to_le_bytes());
+            header.extend_from_slice(&W64_WAVE);
+            header.extend_from_slice(&W64_FMT);
+            header.extend_from_slice(&40u64.to_le_bytes());
+            header.extend_from_slice(&wave_format(cfg));
+            header.extend_from_slice(&W64_DATA);
+            header.extend_from_slice(&(24 + recording.data_bytes).to_le_bytes());
+            header
+        }
+        FileFormat::Flac => recording
+            .flac
+            .as_ref()
+            .map(|flac| flac.header_with_length(recording.frames))
+            .unwrap_or_default(),
+    }
+}
+
+#[async_trait]
+impl OutputSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn open(&mut self, cfg: OutputConfig) -> Result<()> {
+        info!("Opening file sink ({:?}): {:?}", self.format, cfg);
+
+        if self.format == FileFormat::Flac && cfg.format.is_float() {
+            return Err(anyhow!("FLAC recording supports S16LE and S24LE only (got {:?})", cfg.format));
+        }
+
+        self.finish_recording()?;
+        self.config = Some(cfg);
+        self.files.clear();
+        self.frames_written = 0;
+        self.start_recording()
+    }
+
+    async fn write(&mut self, block: AudioBlock<'_>) -> Result<()> {
+        let (Some(recording), Some(cfg)) = (self.recording.as_mut(), self.config.as_ref()) else {
+            return Err(anyhow!("Sink not open"));
+        };
+
+        convert_format(block, cfg.format, &mut self.scratch)?;
+        match recording.flac.as_mut() {
+            Some(flac) => {
+                let frames = flac.encode(&pcm_to_i32(&self.scratch, cfg.format));
+                recording.file.write_all(&frames)?;
+            }
+            None => recording.file.write_all(&self.scratch)?,
+        }
+
+        recording.data_bytes += self.scratch.len() as u64;
+        recording.frames += block.num_frames() as u64;
+        self.frames_written += block.num_frames() as u64;
+        Ok(())
+    }
+
+    async fn drain(&mut self) -> Result<()> {
+        if let Some(recording) = self.recording.as_mut() {
+            recording.file.flush()?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.finish_recording()?;
+        self.config = None;
+        Ok(())
+    }
+
+    fn latency_ms(&self) -> u32 {
+        0
+    }
+
+    fn is_open(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    fn stats(&self) -> SinkStats {
+        SinkStats {
+            frames_written: self.frames_written,
+            ..Default::default()
+        }
+    }
+
+    async fn set_track(&mut self, track: &TrackMeta) -> Result<()> {
+        self.set_metadata(MediaMetadata::from_track_meta(track))
+    }
+
+    fn set_split_tracks(&mut self, split: bool) {
+        self.split_tracks = split;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::SampleFormat;
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(FileFormat::from_path(Path::new("out.FLAC")), Some(FileFormat::Flac));
+        assert_eq!(FileFormat::from_path(Path::new("out.w64")), Some(FileFormat::W64));
+        assert_eq!(FileFormat::from_path(Path::new("out")), None);
+        assert_eq!(FileSink::new("capture.mp3").format(), FileFormat::Wav);
+    }
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("001 - AC/DC - T.N.T."), "001 - AC_DC - T.N.T");
+        assert_eq!(sanitize_file_name("What?: \"Yes\""), "What__ _Yes_");
+    }
+
+    #[tokio::test]
+    async fn test_flac_rejects_float() {
+        let mut sink = FileSink::new(std::env::temp_dir().join("aaeq-file-sink-float.flac"));
+        let result = sink
+            .open(OutputConfig {
+                format: SampleFormat::F32,
+                ..Default::default()
+            })
+            .await;
+        assert!(result.is_err());
+        assert!(!sink.is_open());
+    }
+}
@@ -1,9 +1,20 @@
+pub mod aes67;
+pub mod aes67_sink;
 pub mod airplay;
+pub mod anp;
+pub mod anp_sink;
 pub mod airplay_sink;
 pub mod dlna;
 pub mod dlna_sink;
+pub mod file_sink;
 pub mod local_dac;
+pub mod snapcast;
+pub mod snapcast_sink;
 
+pub use aes67_sink::{Aes67Sink, DEFAULT_AES67_GROUP};
 pub use airplay_sink::AirPlaySink;
+pub use anp_sink::AnpSink;
 pub use dlna_sink::{DlnaMode, DlnaSink};
+pub use file_sink::{FileFormat, FileSink};
 pub use local_dac::LocalDacSink;
+pub use snapcast_sink::{SnapcastCodec, SnapcastMode, SnapcastSink};
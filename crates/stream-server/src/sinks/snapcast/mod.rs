@@ -0,0 +1,5 @@
+mod protocol;
+mod server;
+
+pub use protocol::{BaseMessage, Hello, MessageType, ServerSettings, Tv, HEADER_SIZE};
+pub use server::{SnapcastClient, SnapcastServer, DEFAULT_STREAM_PORT};
@@ -0,0 +1,343 @@
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BufMut, BytesMut};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Size of the base message header preceding every Snapcast message
+pub const HEADER_SIZE: usize = 26;
+
+/// Largest message payload accepted from a client
+const MAX_PAYLOAD_SIZE: u32 = 1 << 20;
+
+/// Snapcast binary protocol message types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum MessageType {
+    Base = 0,
+    CodecHeader = 1,
+    WireChunk = 2,
+    ServerSettings = 3,
+    Time = 4,
+    Hello = 5,
+    StreamTags = 6,
+    ClientInfo = 7,
+}
+
+impl MessageType {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        Some(match value {
+            0 => MessageType::Base,
+            1 => MessageType::CodecHeader,
+            2 => MessageType::WireChunk,
+            3 => MessageType::ServerSettings,
+            4 => MessageType::Time,
+            5 => MessageType::Hello,
+            6 => MessageType::StreamTags,
+            7 => MessageType::ClientInfo,
+            _ => return None,
+        })
+    }
+}
+
+/// Timestamp as sent on the wire (seconds + microseconds)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Tv {
+    pub sec: i32,
+    pub usec: i32,
+}
+
+impl Tv {
+    /// Current server time
+    ///
+    /// Like snapserver this is a monotonic clock, so wall clock changes can't
+    /// disturb client synchronization. Clients only ever use it relative to
+    /// the offset learned from `Time` messages.
+    pub fn now() -> Self {
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        // Start well above zero so differences never need negative seconds
+        let elapsed = EPOCH.get_or_init(Instant::now).elapsed() + Duration::from_secs(1000);
+        Self::from_duration(elapsed)
+    }
+
+    pub fn from_duration(duration: Duration) -> Self {
+        Self {
+            sec: duration.as_secs() as i32,
+            usec: duration.subsec_micros() as i32,
+        }
+    }
+
+    pub fn to_micros(self) -> i64 {
+        self.sec as i64 * 1_000_000 + self.usec as i64
+    }
+
+    pub fn from_micros(micros: i64) -> Self {
+        Self {
+            sec: micros.div_euclid(1_000_000) as i32,
+            usec: micros.rem_euclid(1_000_000) as i32,
+        }
+    }
+
+    fn write(self, buf: &mut BytesMut) {
+        buf.put_i32_le(self.sec);
+        buf.put_i32_le(self.usec);
+    }
+
+    fn read(buf: &mut &[u8]) -> Self {
+        Self {
+            sec: buf.get_i32_le(),
+            usec: buf.get_i32_le(),
+        }
+    }
+}
+
+/// Header preceding every message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaseMessage {
+    pub msg_type: u16,
+    pub id: u16,
+    pub refers_to: u16,
+    pub sent: Tv,
+    pub received: Tv,
+    pub size: u32,
+}
+
+impl BaseMessage {
+    /// Parse a header from exactly `HEADER_SIZE` bytes
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE {
+            return Err(anyhow!("Snapcast header too short: {} bytes", data.len()));
+        }
+
+        let mut buf = data;
+        let header = Self {
+            msg_type: buf.get_u16_le(),
+            id: buf.get_u16_le(),
+            refers_to: buf.get_u16_le(),
+            sent: Tv::read(&mut buf),
+            received: Tv::read(&mut buf),
+            size: buf.get_u32_le(),
+        };
+
+        if header.size > MAX_PAYLOAD_SIZE {
+            return Err(anyhow!("Snapcast message too large: {} bytes", header.size));
+        }
+
+        Ok(header)
+    }
+
+    pub fn message_type(&self) -> Option<MessageType> {
+        MessageType::from_u16(self.msg_type)
+    }
+}
+
+/// Settings pushed to each client after its Hello
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerSettings {
+    /// End-to-end buffer: clients play each chunk this long after its timestamp
+    pub buffer_ms: i32,
+    /// Additional per-client latency in ms
+    pub latency: i32,
+    pub muted: bool,
+    /// Client volume (0-100)
+    pub volume: u16,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            buffer_ms: 1000,
+            latency: 0,
+            muted: false,
+            volume: 100,
+        }
+    }
+}
+
+/// Hello sent by a client when it connects
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Hello {
+    #[serde(default)]
+    pub arch: String,
+    #[serde(default)]
+    pub client_name: String,
+    #[serde(default)]
+    pub host_name: String,
+    #[serde(default, rename = "ID")]
+    pub id: String,
+    #[serde(default)]
+    pub instance: u32,
+    #[serde(default, rename = "MAC")]
+    pub mac: String,
+    #[serde(default, rename = "OS")]
+    pub os: String,
+    #[serde(default)]
+    pub snap_stream_protocol_version: u32,
+    #[serde(default)]
+    pub version: String,
+}
+
+impl Hello {
+    /// Parse a Hello payload (length-prefixed JSON)
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let json = read_string(payload)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+/// Serialize a complete message (header + payload), stamping `sent` with the current time
+pub fn encode_message(msg_type: MessageType, id: u16, refers_to: u16, received: Tv, payload: &[u8]) -> Vec<u8> {
+    let mut buf = BytesMut::with_capacity(HEADER_SIZE + payload.len());
+    buf.put_u16_le(msg_type as u16);
+    buf.put_u16_le(id);
+    buf.put_u16_le(refers_to);
+    Tv::now().write(&mut buf);
+    received.write(&mut buf);
+    buf.put_u32_le(payload.len() as u32);
+    buf.put_slice(payload);
+    buf.to_vec()
+}
+
+/// `CodecHeader` message: codec name plus the decoder initialization data
+pub fn codec_header(codec: &str, header: &[u8]) -> Vec<u8> {
+    let mut payload = BytesMut::new();
+    write_string(&mut payload, codec);
+    payload.put_u32_le(header.len() as u32);
+    payload.put_slice(header);
+    encode_message(MessageType::CodecHeader, 0, 0, Tv::default(), &payload)
+}
+
+/// `WireChunk` message: encoded audio whose first sample was captured at `timestamp`
+pub fn wire_chunk(timestamp: Tv, data: &[u8]) -> Vec<u8> {
+    let mut payload = BytesMut::with_capacity(12 + data.len());
+    timestamp.write(&mut payload);
+    payload.put_u32_le(data.len() as u32);
+    payload.put_slice(data);
+    encode_message(MessageType::WireChunk, 0, 0, Tv::default(), &payload)
+}
+
+/// `ServerSettings` message, in reply to the client's Hello
+pub fn server_settings(settings: &ServerSettings, refers_to: u16) -> Vec<u8> {
+    let json = serde_json::to_string(settings).unwrap_or_default();
+    let mut payload = BytesMut::new();
+    write_string(&mut payload, &json);
+    encode_message(MessageType::ServerSettings, 0, refers_to, Tv::default(), &payload)
+}
+
+/// Reply to a client's `Time` request
+///
+/// The payload carries the client-to-server latency (our receive time minus the
+/// client's send time); the client combines it with the reply's own transit to
+/// estimate the clock offset.
+pub fn time_reply(request: &BaseMessage) -> Vec<u8> {
+    let latency = Tv::from_micros(request.received.to_micros() - request.sent.to_micros());
+    let mut payload = BytesMut::with_capacity(8);
+    latency.write(&mut payload);
+    encode_message(MessageType::Time, request.id, request.id, request.received, &payload)
+}
+
+fn write_string(buf: &mut BytesMut, value: &str) {
+    buf.put_u32_le(value.len() as u32);
+    buf.put_slice(value.as_bytes());
+}
+
+fn read_string(payload: &[u8]) -> Result<String> {
+    if payload.len() < 4 {
+        return Err(anyhow!("Snapcast string payload too short"));
+    }
+    let mut buf = payload;
+    let len = buf.get_u32_le() as usize;
+    let bytes = buf
+        .get(..len)
+        .ok_or_else(|| anyhow!("Snapcast string length {} exceeds payload", len))?;
+    Ok(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let message = encode_message(MessageType::Hello, 3, 1, Tv { sec: 5, usec: 6 }, b"abc");
+        assert_eq!(message.len(), HEADER_SIZE + 3);
+
+        let header = BaseMessage::parse(&message).unwrap();
+        assert_eq!(header.message_type(), Some(MessageType::Hello));
+        assert_eq!(header.id, 3);
+        assert_eq!(header.refers_to, 1);
+        assert_eq!(header.received, Tv { sec: 5, usec: 6 });
+        assert_eq!(header.size, 3);
+        assert_eq!(&message[HEADER_SIZE..], b"abc");
+    }
+
+    #[test]
+    fn test_header_rejects_oversized_payload() {
+        let mut message = encode_message(MessageType::Base, 0, 0, Tv::default(), &[]);
+        message[22..26].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(BaseMessage::parse(&message).is_err());
+        assert!(BaseMessage::parse(&message[..10]).is_err());
+    }
+
+    #[test]
+    fn test_tv_micros() {
+        let tv = Tv::from_micros(-1_500_000);
+        assert_eq!(tv, Tv { sec: -2, usec: 500_000 });
+        assert_eq!(tv.to_micros(), -1_500_000);
+
+        assert!(Tv::now() <= Tv::now());
+    }
+
+    #[test]
+    fn test_time_reply_latency() {
+        let request = BaseMessage {
+            msg_type: MessageType::Time as u16,
+            id: 42,
+            refers_to: 0,
+            sent: Tv { sec: 10, usec: 900_000 },
+            received: Tv { sec: 11, usec: 100_000 },
+            size: 8,
+        };
+
+        let reply = time_reply(&request);
+        let header = BaseMessage::parse(&reply).unwrap();
+        assert_eq!(header.message_type(), Some(MessageType::Time));
+        assert_eq!(header.refers_to, 42);
+        assert_eq!(header.received, request.received);
+
+        let mut payload = &reply[HEADER_SIZE..];
+        assert_eq!(Tv::read(&mut payload), Tv { sec: 0, usec: 200_000 });
+    }
+
+    #[test]
+    fn test_server_settings_json() {
+        let message = server_settings(&ServerSettings::default(), 7);
+        let json = read_string(&message[HEADER_SIZE..]).unwrap();
+        assert_eq!(json, r#"{"bufferMs":1000,"latency":0,"muted":false,"volume":100}"#);
+        assert_eq!(BaseMessage::parse(&message).unwrap().refers_to, 7);
+    }
+
+    #[test]
+    fn test_parse_hello() {
+        let json = r#"{"Arch":"armv7l","ClientName":"Snapclient","HostName":"kitchen","ID":"b8:27:eb:00:00:01","Instance":1,"MAC":"b8:27:eb:00:00:01","OS":"Raspbian","SnapStreamProtocolVersion":2,"Version":"0.27.0"}"#;
+        let mut payload = BytesMut::new();
+        write_string(&mut payload, json);
+
+        let hello = Hello::parse(&payload).unwrap();
+        assert_eq!(hello.host_name, "kitchen");
+        assert_eq!(hello.id, "b8:27:eb:00:00:01");
+        assert_eq!(hello.snap_stream_protocol_version, 2);
+    }
+
+    #[test]
+    fn test_wire_chunk_layout() {
+        let message = wire_chunk(Tv { sec: 1, usec: 2 }, &[9, 9, 9]);
+        let payload = &message[HEADER_SIZE..];
+        assert_eq!(&payload[..4], &1i32.to_le_bytes());
+        assert_eq!(&payload[4..8], &2i32.to_le_bytes());
+        assert_eq!(&payload[8..12], &3u32.to_le_bytes());
+        assert_eq!(&payload[12..], &[9, 9, 9]);
+    }
+}
@@ -0,0 +1,231 @@
+use super::protocol::*;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Default port snapclients connect to for audio
+pub const DEFAULT_STREAM_PORT: u16 = 1704;
+
+/// Chunks buffered per client before it's considered too slow and skips ahead
+const CLIENT_QUEUE_CHUNKS: usize = 256;
+
+/// A connected snapclient
+#[derive(Debug, Clone)]
+pub struct SnapcastClient {
+    pub address: SocketAddr,
+    /// Client ID from its Hello (usually the MAC address)
+    pub id: String,
+    pub host_name: String,
+    pub version: String,
+}
+
+/// State shared between the server handle and client tasks
+struct Shared {
+    settings: Mutex<ServerSettings>,
+    codec_header: Mutex<Option<Arc<Vec<u8>>>>,
+    clients: Mutex<HashMap<u64, SnapcastClient>>,
+}
+
+/// Snapcast stream server speaking the binary protocol to snapclients
+///
+/// Clients receive `ServerSettings` and the current `CodecHeader` after their
+/// Hello, then every `WireChunk` sent from that point on. Time requests are
+/// answered so clients can track the server clock.
+pub struct SnapcastServer {
+    local_addr: SocketAddr,
+    shared: Arc<Shared>,
+    tx: broadcast::Sender<Arc<Vec<u8>>>,
+    accept_task: JoinHandle<()>,
+}
+
+impl SnapcastServer {
+    /// Bind and start accepting snapclients
+    pub async fn bind(addr: SocketAddr, settings: ServerSettings) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        info!("Snapcast server listening on {}", local_addr);
+
+        let shared = Arc::new(Shared {
+            settings: Mutex::new(settings),
+            codec_header: Mutex::new(None),
+            clients: Mutex::new(HashMap::new()),
+        });
+        let (tx, _) = broadcast::channel(CLIENT_QUEUE_CHUNKS);
+
+        let accept_shared = shared.clone();
+        let accept_tx = tx.clone();
+        let accept_task = tokio::spawn(async move {
+            let mut next_id = 0u64;
+            loop {
+                match listener.accept().await {
+                    Ok((stream, address)) => {
+                        info!("Snapclient connected from {}", address);
+                        let id = next_id;
+                        next_id += 1;
+                        let rx = accept_tx.subscribe();
+                        tokio::spawn(handle_client(stream, address, id, accept_shared.clone(), rx));
+                    }
+                    Err(e) => {
+                        warn!("Snapcast accept failed: {}", e);
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            shared,
+            tx,
+            accept_task,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Set the codec header and announce it to connected clients
+    pub fn set_codec_header(&self, codec: &str, header: &[u8]) {
+        let message = Arc::new(codec_header(codec, header));
+        *self.shared.codec_header.lock().unwrap() = Some(message.clone());
+        let _ = self.tx.send(message);
+    }
+
+    /// Send an encoded audio chunk to all clients
+    pub fn send_chunk(&self, timestamp: Tv, data: &[u8]) {
+        // No receivers just means no clients are connected
+        let _ = self.tx.send(Arc::new(wire_chunk(timestamp, data)));
+    }
+
+    /// Settings sent to clients when they connect
+    pub fn settings(&self) -> ServerSettings {
+        self.shared.settings.lock().unwrap().clone()
+    }
+
+    /// Currently connected clients that have said Hello
+    pub fn clients(&self) -> Vec<SnapcastClient> {
+        self.shared.clients.lock().unwrap().values().cloned().collect()
+    }
+}
+
+impl Drop for SnapcastServer {
+    fn drop(&mut self) {
+        // Client tasks end once the broadcast sender is gone
+        self.accept_task.abort();
+    }
+}
+
+/// A message read from a client, stamped with its arrival time
+struct Incoming {
+    header: BaseMessage,
+    payload: Vec<u8>,
+}
+
+async fn read_messages(mut reader: OwnedReadHalf, tx: mpsc::Sender<Incoming>) -> Result<()> {
+    let mut header_buf = [0u8; HEADER_SIZE];
+    loop {
+        reader.read_exact(&mut header_buf).await?;
+        let received = Tv::now();
+
+        let mut header = BaseMessage::parse(&header_buf)?;
+        header.received = received;
+
+        let mut payload = vec![0u8; header.size as usize];
+        reader.read_exact(&mut payload).await?;
+
+        if tx.send(Incoming { header, payload }).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn handle_client(
+    stream: TcpStream,
+    address: SocketAddr,
+    id: u64,
+    shared: Arc<Shared>,
+    mut chunks: broadcast::Receiver<Arc<Vec<u8>>>,
+) {
+    let _ = stream.set_nodelay(true);
+    let (reader, mut writer) = stream.into_split();
+
+    let (incoming_tx, mut incoming_rx) = mpsc::channel(16);
+    let reader_task = tokio::spawn(read_messages(reader, incoming_tx));
+
+    // Audio is only sent once the client has introduced itself
+    let mut streaming = false;
+
+    loop {
+        let outgoing = tokio::select! {
+            message = incoming_rx.recv() => {
+                let Some(Incoming { header, payload }) = message else {
+                    break;
+                };
+
+                match header.message_type() {
+                    Some(MessageType::Time) => vec![time_reply(&header)],
+                    Some(MessageType::Hello) => {
+                        let hello = Hello::parse(&payload).unwrap_or_default();
+                        info!(
+                            "Snapclient hello from {} ({}, v{})",
+                            address, hello.host_name, hello.version
+                        );
+                        shared.clients.lock().unwrap().insert(
+                            id,
+                            SnapcastClient {
+                                address,
+                                id: hello.id,
+                                host_name: hello.host_name,
+                                version: hello.version,
+                            },
+                        );
+
+                        streaming = true;
+                        let settings = shared.settings.lock().unwrap().clone();
+                        let mut replies = vec![server_settings(&settings, header.id)];
+                        if let Some(codec) = shared.codec_header.lock().unwrap().as_ref() {
+                            replies.push(codec.to_vec());
+                        }
+                        replies
+                    }
+                    other => {
+                        debug!("Ignoring snapclient message {:?} from {}", other, address);
+                        Vec::new()
+                    }
+                }
+            }
+            chunk = chunks.recv() => match chunk {
+                Ok(message) if streaming => vec![message.to_vec()],
+                Ok(_) => Vec::new(),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Snapclient {} fell behind, skipped {} messages", address, skipped);
+                    Vec::new()
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+
+        let mut failed = false;
+        for message in outgoing {
+            if let Err(e) = writer.write_all(&message).await {
+                debug!("Snapclient {} write failed: {}", address, e);
+                failed = true;
+                break;
+            }
+        }
+        if failed {
+            break;
+        }
+    }
+
+    reader_task.abort();
+    shared.clients.lock().unwrap().remove(&id);
+    info!("Snapclient {} disconnected", address);
+}
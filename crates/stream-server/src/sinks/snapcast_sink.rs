@@ -0,0 +1,391 @@
+use crate::convert::convert_format;
+use crate::sink::{OutputSink, SinkStats};
+use crate::sinks::dlna::stream_encoding::create_wav_header_for_config;
+use crate::sinks::dlna::FlacEncoder;
+use crate::sinks::snapcast::*;
+use crate::types::{AudioBlock, OutputConfig, SampleFormat};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::{debug, info};
+
+/// Duration of each audio chunk sent to snapclients (snapserver's default)
+const CHUNK_MS: u32 = 20;
+
+/// How far chunk timestamps may run ahead of the clock before writes are paced
+const MAX_AHEAD_MS: i64 = 500;
+
+/// How far chunk timestamps may fall behind the clock before resyncing to it
+const MAX_BEHIND_MS: i64 = 200;
+
+/// Buffer assumed for an external snapserver (its default `buffer`)
+const EXTERNAL_SERVER_BUFFER_MS: u32 = 1000;
+
+/// Where the Snapcast sink sends audio
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapcastMode {
+    /// Built-in snapserver that snapclients connect to directly
+    Server(SocketAddr),
+    /// Raw PCM into a snapserver `pipe://` source (FIFO)
+    Pipe(PathBuf),
+    /// Raw PCM to a snapserver `tcp://` source in server mode
+    Tcp(SocketAddr),
+}
+
+/// Codec used by the built-in server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SnapcastCodec {
+    /// Uncompressed PCM (lowest CPU on the clients)
+    #[default]
+    Pcm,
+    /// Lossless FLAC (roughly half the bandwidth, good for Wi-Fi)
+    Flac,
+}
+
+impl SnapcastCodec {
+    /// Codec name used in the `CodecHeader` message
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapcastCodec::Pcm => "pcm",
+            SnapcastCodec::Flac => "flac",
+        }
+    }
+}
+
+/// Snapcast output sink for synchronized multi-room playback
+///
+/// Either runs a built-in Snapcast server that snapclients connect to, or
+/// feeds raw PCM into an existing snapserver's pipe or TCP source. Audio is sent
+/// as 16-bit PCM; configure the external snapserver source with a matching
+/// `sampleformat` (e.g. `48000:16:2`).
+pub struct SnapcastSink {
+    mode: SnapcastMode,
+    codec: SnapcastCodec,
+    settings: ServerSettings,
+    config: Option<OutputConfig>,
+    server: Option<SnapcastServer>,
+    writer: Option<Box<dyn AsyncWrite + Send + Sync + Unpin>>,
+    flac: Option<FlacEncoder>,
+    /// PCM not yet sent as a complete chunk
+    pending: Vec<u8>,
+    /// Capture timestamp of the next chunk (server time, microseconds)
+    next_chunk_us: Option<i64>,
+    frames_written: u64,
+    is_open: bool,
+}
+
+impl SnapcastSink {
+    /// Create a sink with the given mode
+    pub fn new(mode: SnapcastMode) -> Self {
+        Self {
+            mode,
+            codec: SnapcastCodec::default(),
+            settings: ServerSettings::default(),
+            config: None,
+            server: None,
+            writer: None,
+            flac: None,
+            pending: Vec::new(),
+            next_chunk_us: None,
+            frames_written: 0,
+            is_open: false,
+        }
+    }
+
+    /// Built-in server listening on `addr` (snapclients use port 1704 by default)
+    pub fn server(addr: SocketAddr) -> Self {
+        Self::new(SnapcastMode::Server(addr))
+    }
+
+    /// Write into a snapserver pipe source (e.g. `/tmp/snapfifo`)
+    pub fn pipe(path: impl Into<PathBuf>) -> Self {
+        Self::new(SnapcastMode::Pipe(path.into()))
+    }
+
+    /// Connect to a snapserver TCP source (`tcp://0.0.0.0:4953?mode=server`)
+    pub fn tcp(addr: SocketAddr) -> Self {
+        Self::new(SnapcastMode::Tcp(addr))
+    }
+
+    /// Use the given codec for the built-in server
+    pub fn with_codec(mut self, codec: SnapcastCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Client buffer for the built-in server (default 1000ms)
+    pub fn with_buffer_ms(mut self, buffer_ms: u32) -> Self {
+        self.settings.buffer_ms = buffer_ms as i32;
+        self
+    }
+
+    pub fn mode(&self) -> &SnapcastMode {
+        &self.mode
+    }
+
+    pub fn codec(&self) -> SnapcastCodec {
+        self.codec
+    }
+
+    /// Address the built-in server is listening on, while open
+    pub fn server_addr(&self) -> Option<SocketAddr> {
+        self.server.as_ref().map(|s| s.local_addr())
+    }
+
+    /// Snapclients connected to the built-in server
+    pub fn clients(&self) -> Vec<SnapcastClient> {
+        self.server.as_ref().map(|s| s.clients()).unwrap_or_default()
+    }
+
+    fn chunk_bytes(cfg: &OutputConfig) -> usize {
+        let frames = (cfg.sample_rate * CHUNK_MS / 1000) as usize;
+        frames * cfg.channels as usize * cfg.format.bytes_per_sample()
+    }
+
+    /// Encode and send one chunk from the built-in server, pacing to real time
+    async fn send_chunk(&mut self, pcm: &[u8]) -> Result<()> {
+        let (Some(server), Some(cfg)) = (self.server.as_ref(), self.config.as_ref()) else {
+            return Err(anyhow!("Sink not open"));
+        };
+
+        let frames = pcm.len() / (cfg.channels as usize * cfg.format.bytes_per_sample());
+        let duration_us = frames as i64 * 1_000_000 / cfg.sample_rate as i64;
+
+        // Timestamp chunks from a running sample clock so they stay contiguous
+        let now = Tv::now().to_micros();
+        let mut timestamp = self.next_chunk_us.unwrap_or(now);
+        if timestamp < now - MAX_BEHIND_MS * 1000 {
+            debug!("Snapcast stream fell behind, resyncing chunk clock");
+            timestamp = now;
+        }
+
+        // Don't let the source run further ahead than the clients can buffer
+        let ahead_us = timestamp - now - MAX_AHEAD_MS * 1000;
+        if ahead_us > 0 {
+            tokio::time::sleep(Duration::from_micros(ahead_us as u64)).await;
+        }
+
+        let data = match self.flac.as_mut() {
+            Some(flac) => {
+                let samples: Vec<i32> = pcm
+                    .chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]) as i32)
+                    .collect();
+                flac.encode(&samples)
+            }
+            None => pcm.to_vec(),
+        };
+
+        server.send_chunk(Tv::from_micros(timestamp), &data);
+        self.next_chunk_us = Some(timestamp + duration_us);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OutputSink for SnapcastSink {
+    fn name(&self) -> &'static str {
+        "snapcast"
+    }
+
+    async fn open(&mut self, cfg: OutputConfig) -> Result<()> {
+        info!("Opening Snapcast sink ({:?}): {:?}", self.mode, cfg);
+
+        if cfg.format != SampleFormat::S16LE {
+            return Err(anyhow!(
+                "Snapcast sink supports S16LE only (got {:?})",
+                cfg.format
+            ));
+        }
+
+        match &self.mode {
+            SnapcastMode::Server(addr) => {
+                let server = SnapcastServer::bind(*addr, self.settings.clone()).await?;
+
+                match self.codec {
+                    SnapcastCodec::Pcm => {
+                        server.set_codec_header("pcm", &create_wav_header_for_config(&cfg));
+                        self.flac = None;
+                    }
+                    SnapcastCodec::Flac => {
+                        // One FLAC frame per chunk keeps chunk timestamps exact
+                        let block_size = (cfg.sample_rate * CHUNK_MS / 1000) as usize;
+                        let flac = FlacEncoder::with_block_size(cfg.sample_rate, cfg.channels, 16, block_size);
+                        server.set_codec_header("flac", &flac.header());
+                        self.flac = Some(flac);
+                    }
+                }
+
+                self.server = Some(server);
+            }
+            SnapcastMode::Pipe(path) => {
+                // Opening a FIFO blocks until snapserver has it open for reading
+                let file = tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| anyhow!("Failed to open Snapcast pipe {}: {}", path.display(), e))?;
+                self.writer = Some(Box::new(file));
+            }
+            SnapcastMode::Tcp(addr) => {
+                let stream = tokio::net::TcpStream::connect(addr)
+                    .await
+                    .map_err(|e| anyhow!("Failed to connect to Snapcast TCP source {}: {}", addr, e))?;
+                stream.set_nodelay(true)?;
+                self.writer = Some(Box::new(stream));
+            }
+        }
+
+        self.pending.clear();
+        self.next_chunk_us = None;
+        self.frames_written = 0;
+        self.config = Some(cfg);
+        self.is_open = true;
+
+        Ok(())
+    }
+
+    async fn write(&mut self, block: AudioBlock<'_>) -> Result<()> {
+        if !self.is_open {
+            return Err(anyhow!("Sink not open"));
+        }
+
+        let cfg = self.config.clone().ok_or_else(|| anyhow!("Sink not open"))?;
+        self.frames_written += block.num_frames() as u64;
+
+        let mut pcm = Vec::new();
+        convert_format(block, cfg.format, &mut pcm)?;
+
+        if let Some(writer) = self.writer.as_mut() {
+            // External snapserver reads at its own pace; the pipe/socket provides backpressure
+            writer.write_all(&pcm).await?;
+            return Ok(());
+        }
+
+        self.pending.extend_from_slice(&pcm);
+        let chunk_bytes = Self::chunk_bytes(&cfg);
+        while self.pending.len() >= chunk_bytes {
+            let chunk: Vec<u8> = self.pending.drain(..chunk_bytes).collect();
+            self.send_chunk(&chunk).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn drain(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush().await?;
+            return Ok(());
+        }
+
+        if self.server.is_some() && !self.pending.is_empty() {
+            let chunk = std::mem::take(&mut self.pending);
+            let frame_bytes = self
+                .config
+                .as_ref()
+                .map_or(4, |c| c.channels as usize * c.format.bytes_per_sample());
+            let whole = chunk.len() - chunk.len() % frame_bytes;
+            self.send_chunk(&chunk[..whole]).await?;
+
+            // Emit the final short FLAC frame too
+            if let (Some(flac), Some(server)) = (self.flac.as_mut(), self.server.as_ref()) {
+                let tail = flac.flush();
+                if !tail.is_empty() {
+                    let timestamp = self.next_chunk_us.unwrap_or_else(|| Tv::now().to_micros());
+                    server.send_chunk(Tv::from_micros(timestamp), &tail);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        debug!("Closing Snapcast sink");
+
+        let _ = self.drain().await;
+
+        if let Some(mut writer) = self.writer.take() {
+            let _ = writer.shutdown().await;
+        }
+        self.server = None;
+        self.flac = None;
+        self.pending.clear();
+        self.config = None;
+        self.is_open = false;
+
+        info!("Snapcast sink closed");
+        Ok(())
+    }
+
+    fn latency_ms(&self) -> u32 {
+        match self.mode {
+            SnapcastMode::Server(_) => self.settings.buffer_ms.max(0) as u32,
+            _ => EXTERNAL_SERVER_BUFFER_MS,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.is_open
+    }
+
+    fn stats(&self) -> SinkStats {
+        SinkStats {
+            frames_written: self.frames_written,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(format: SampleFormat) -> OutputConfig {
+        OutputConfig {
+            sample_rate: 48000,
+            channels: 2,
+            format,
+            buffer_ms: 150,
+            exclusive: false,
+        }
+    }
+
+    #[test]
+    fn test_snapcast_sink_create() {
+        let sink = SnapcastSink::server("127.0.0.1:0".parse().unwrap()).with_buffer_ms(500);
+        assert_eq!(sink.name(), "snapcast");
+        assert!(!sink.is_open());
+        assert_eq!(sink.latency_ms(), 500);
+        assert_eq!(sink.codec(), SnapcastCodec::Pcm);
+
+        let pipe = SnapcastSink::pipe("/tmp/snapfifo");
+        assert_eq!(pipe.mode(), &SnapcastMode::Pipe("/tmp/snapfifo".into()));
+        assert_eq!(pipe.latency_ms(), EXTERNAL_SERVER_BUFFER_MS);
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        // 20ms of 48kHz stereo 16-bit
+        assert_eq!(SnapcastSink::chunk_bytes(&config(SampleFormat::S16LE)), 960 * 4);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_non_s16() {
+        let mut sink = SnapcastSink::server("127.0.0.1:0".parse().unwrap());
+        assert!(sink.open(config(SampleFormat::F32)).await.is_err());
+        assert!(!sink.is_open());
+    }
+
+    #[tokio::test]
+    async fn test_write_without_open() {
+        let mut sink = SnapcastSink::server("127.0.0.1:0".parse().unwrap());
+        let frames = vec![0.0; 480];
+        assert!(sink.write(AudioBlock::new(&frames, 48000, 2)).await.is_err());
+    }
+}
@@ -0,0 +1,379 @@
+//! Stream health metrics for Prometheus
+//!
+//! [`Telemetry`] keeps what the output manager doesn't: clips, preset
+//! switches, resampler latency, device poll failures and discovery times.
+//! [`Telemetry::render`] writes them together with every sink's
+//! [`SinkStats`](crate::sink::SinkStats) in the Prometheus text format or
+//! OpenMetrics, for the Control API's `/metrics`.
+//!
+//! Resampler latency, poll failures and discovery times are only recorded by
+//! `aaeqd`, which resamples, polls devices for the playing track and
+//! discovers its output. The Control API's own [`CaptureStreamer`] and the
+//! desktop app do none of these, so those series stay empty without it.
+//!
+//! [`CaptureStreamer`]: crate::control_api::streaming::CaptureStreamer
+use crate::control_api::routes::Metrics;
+use crate::events::{EventBus, StreamEvent};
+use crate::manager::SinkStatus;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+/// Upper bounds of the discovery duration buckets, in seconds
+pub const DISCOVERY_BUCKETS: [f64; 8] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Name, help and value of a per-sink counter
+type SinkCounter = (&'static str, &'static str, fn(&SinkStatus) -> u64);
+
+/// Exposition format of a scrape
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpositionFormat {
+    /// Prometheus text format 0.0.4
+    Prometheus,
+    /// OpenMetrics 1.0
+    OpenMetrics,
+}
+
+impl ExpositionFormat {
+    /// OpenMetrics if the scraper's `Accept` header asks for it
+    pub fn from_accept(accept: &str) -> Self {
+        if accept.contains("application/openmetrics-text") {
+            ExpositionFormat::OpenMetrics
+        } else {
+            ExpositionFormat::Prometheus
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExpositionFormat::Prometheus => "text/plain; version=0.0.4; charset=utf-8",
+            ExpositionFormat::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+/// Health counters shared by everything that streams, polls or discovers
+///
+/// Cloning is cheap; clones record into the same counters.
+#[derive(Debug, Clone, Default)]
+pub struct Telemetry {
+    inner: Arc<Mutex<Counters>>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    clips: u64,
+    /// Last clip count reported by the current stream's chain
+    stream_clips: u64,
+    preset_switches: u64,
+    resampler_latency_ms: Option<f32>,
+    poll_failures: BTreeMap<String, u64>,
+    discoveries: BTreeMap<String, Histogram>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Cumulative, one per [`DISCOVERY_BUCKETS`] bound
+    buckets: [u64; DISCOVERY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Report a stream's clip count so far
+    ///
+    /// A count lower than the last one reported starts a new stream.
+    pub fn report_clips(&self, clip_count: u64) {
+        let mut counters = self.counters();
+        let last = if clip_count < counters.stream_clips { 0 } else { counters.stream_clips };
+        counters.clips += clip_count - last;
+        counters.stream_clips = clip_count;
+    }
+
+    pub fn record_preset_switch(&self) {
+        self.counters().preset_switches += 1;
+    }
+
+    /// Latency of the running stream's resampler; `None` when it doesn't resample
+    ///
+    /// Recorded by `aaeqd`'s DSP stream.
+    pub fn set_resampler_latency(&self, latency_ms: Option<f32>) {
+        self.counters().resampler_latency_ms = latency_ms;
+    }
+
+    /// Reading the playing track from `device` failed
+    ///
+    /// Recorded by `aaeqd`'s track watcher.
+    pub fn record_poll_failure(&self, device: &str) {
+        *self.counters().poll_failures.entry(device.to_string()).or_default() += 1;
+    }
+
+    /// A `protocol` discovery (e.g. "dlna", "airplay") took `duration`
+    ///
+    /// Recorded by `aaeqd` when it looks up its configured output.
+    pub fn record_discovery(&self, protocol: &str, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut counters = self.counters();
+        let histogram = counters.discoveries.entry(protocol.to_string()).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(DISCOVERY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// Run a `protocol` discovery, recording how long it took
+    pub async fn time_discovery<T>(&self, protocol: &str, discovery: impl Future<Output = T>) -> T {
+        let started = Instant::now();
+        let result = discovery.await;
+        self.record_discovery(protocol, started.elapsed());
+        result
+    }
+
+    /// Count clips and preset switches published on `events` until the task is aborted
+    pub fn watch(&self, events: &EventBus) -> JoinHandle<()> {
+        let telemetry = self.clone();
+        let mut rx = events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(StreamEvent::Clipping { clip_count }) => telemetry.report_clips(clip_count),
+                    Ok(StreamEvent::PresetApplied { .. }) => telemetry.record_preset_switch(),
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// Every metric, with the sinks' statistics and the flow's `metrics`
+    pub fn render(&self, sinks: &[SinkStatus], flow: &Metrics, streaming: bool, format: ExpositionFormat) -> String {
+        let mut out = Encoder::new(format);
+
+        out.gauge("aaeq_streaming", "Whether audio is flowing into the active output");
+        out.sample("aaeq_streaming", &[], streaming as u64 as f64);
+
+        let sink_labels = |sink: &SinkStatus| [("sink", sink.name.to_string())];
+        out.gauge("aaeq_sink_open", "Whether the output is open");
+        for sink in sinks {
+            out.sample("aaeq_sink_open", &sink_labels(sink), sink.is_open as u64 as f64);
+        }
+        out.gauge("aaeq_sink_active", "Whether the output is the one being streamed to");
+        for sink in sinks {
+            out.sample("aaeq_sink_active", &sink_labels(sink), sink.is_active as u64 as f64);
+        }
+        let sink_counters: [SinkCounter; 3] = [
+            ("aaeq_sink_frames_written", "Frames written to the output since it opened", |s| s.stats.frames_written),
+            ("aaeq_sink_underruns", "Times the output's buffer ran dry", |s| s.stats.underruns),
+            ("aaeq_sink_overruns", "Times the output's buffer overflowed", |s| s.stats.overruns),
+        ];
+        for (name, help, value) in sink_counters {
+            out.counter(name, help);
+            for sink in sinks {
+                out.sample(&format!("{}_total", name), &sink_labels(sink), value(sink) as f64);
+            }
+        }
+        out.gauge("aaeq_sink_buffer_fill_ratio", "Output buffer fill level, from 0 to 1");
+        for sink in sinks {
+            out.sample("aaeq_sink_buffer_fill_ratio", &sink_labels(sink), sink.stats.buffer_fill as f64);
+        }
+        out.gauge("aaeq_sink_latency_seconds", "Latency the output reports");
+        for sink in sinks {
+            out.sample("aaeq_sink_latency_seconds", &sink_labels(sink), sink.latency_ms as f64 / 1000.0);
+        }
+
+        out.counter("aaeq_input_underruns", "Times the input stopped delivering audio mid-stream");
+        out.sample("aaeq_input_underruns_total", &[], flow.underruns as f64);
+        out.counter("aaeq_input_overruns", "Times the queue from the input to the output filled up");
+        out.sample("aaeq_input_overruns_total", &[], flow.overruns as f64);
+        out.counter("aaeq_stream_bytes_written", "Bytes of audio streamed to the active output");
+        out.sample("aaeq_stream_bytes_written_total", &[], flow.bytes_written as f64);
+
+        let counters = self.counters();
+        out.counter("aaeq_clips", "Samples the DSP chain clipped");
+        out.sample("aaeq_clips_total", &[], counters.clips as f64);
+        out.counter("aaeq_preset_switches", "Presets applied");
+        out.sample("aaeq_preset_switches_total", &[], counters.preset_switches as f64);
+        out.gauge("aaeq_resampler_latency_seconds", "Latency of the running stream's resampler (aaeqd only)");
+        if let Some(latency_ms) = counters.resampler_latency_ms {
+            out.sample("aaeq_resampler_latency_seconds", &[], latency_ms as f64 / 1000.0);
+        }
+
+        out.counter("aaeq_device_poll_failures", "Failed reads of a device's playing track (aaeqd only)");
+        for (device, failures) in &counters.poll_failures {
+            out.sample("aaeq_device_poll_failures_total", &[("device", device.clone())], *failures as f64);
+        }
+
+        out.histogram("aaeq_discovery_duration_seconds", "Time spent discovering devices (aaeqd only)");
+        for (protocol, histogram) in &counters.discoveries {
+            let bounds = DISCOVERY_BUCKETS.iter().map(|bound| format!("{:?}", bound));
+            for (le, count) in bounds.chain(["+Inf".to_string()]).zip(histogram.buckets.iter().chain([&histogram.count])) {
+                let labels = [("protocol", protocol.clone()), ("le", le)];
+                out.sample("aaeq_discovery_duration_seconds_bucket", &labels, *count as f64);
+            }
+            let labels = [("protocol", protocol.clone())];
+            out.sample("aaeq_discovery_duration_seconds_sum", &labels, histogram.sum);
+            out.sample("aaeq_discovery_duration_seconds_count", &labels, histogram.count as f64);
+        }
+
+        out.finish()
+    }
+}
+
+/// Writes metric families in either format
+struct Encoder {
+    out: String,
+    format: ExpositionFormat,
+}
+
+impl Encoder {
+    fn new(format: ExpositionFormat) -> Self {
+        Self {
+            out: String::new(),
+            format,
+        }
+    }
+
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    fn gauge(&mut self, name: &str, help: &str) {
+        self.family(name, "gauge", help);
+    }
+
+    /// A counter family; its samples are named `<name>_total`
+    fn counter(&mut self, name: &str, help: &str) {
+        match self.format {
+            // OpenMetrics names the family without the suffix
+            ExpositionFormat::OpenMetrics => self.family(name, "counter", help),
+            ExpositionFormat::Prometheus => self.family(&format!("{}_total", name), "counter", help),
+        }
+    }
+
+    fn histogram(&mut self, name: &str, help: &str) {
+        self.family(name, "histogram", help);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, String)], value: f64) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {}", format_value(value));
+    }
+
+    fn finish(mut self) -> String {
+        if self.format == ExpositionFormat::OpenMetrics {
+            self.out.push_str("# EOF\n");
+        }
+        self.out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::SinkStats;
+
+    fn sink() -> SinkStatus {
+        SinkStatus {
+            name: "dlna",
+            is_open: true,
+            is_active: true,
+            config: None,
+            latency_ms: 150,
+            stats: SinkStats {
+                frames_written: 48000,
+                underruns: 2,
+                overruns: 0,
+                buffer_fill: 0.5,
+            },
+        }
+    }
+
+    #[test]
+    fn test_clip_counts_span_streams() {
+        let telemetry = Telemetry::new();
+        telemetry.report_clips(3);
+        telemetry.report_clips(5);
+        // A new stream's chain counts from zero again
+        telemetry.report_clips(2);
+        assert_eq!(telemetry.counters().clips, 7);
+    }
+
+    #[test]
+    fn test_discovery_histogram() {
+        let telemetry = Telemetry::new();
+        telemetry.record_discovery("dlna", Duration::from_millis(300));
+        telemetry.record_discovery("dlna", Duration::from_secs(5));
+        let text = telemetry.render(&[], &Metrics::default(), false, ExpositionFormat::Prometheus);
+        assert!(text.contains("aaeq_discovery_duration_seconds_bucket{protocol=\"dlna\",le=\"0.25\"} 0\n"));
+        assert!(text.contains("aaeq_discovery_duration_seconds_bucket{protocol=\"dlna\",le=\"0.5\"} 1\n"));
+        assert!(text.contains("aaeq_discovery_duration_seconds_bucket{protocol=\"dlna\",le=\"5.0\"} 2\n"));
+        assert!(text.contains("aaeq_discovery_duration_seconds_bucket{protocol=\"dlna\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("aaeq_discovery_duration_seconds_count{protocol=\"dlna\"} 2\n"));
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let telemetry = Telemetry::new();
+        telemetry.record_poll_failure("Living \"Room\"");
+        let text = telemetry.render(&[sink()], &Metrics::default(), true, ExpositionFormat::Prometheus);
+        assert!(text.contains("# TYPE aaeq_sink_underruns_total counter\n"));
+        assert!(text.contains("aaeq_sink_underruns_total{sink=\"dlna\"} 2\n"));
+        assert!(text.contains("aaeq_sink_buffer_fill_ratio{sink=\"dlna\"} 0.5\n"));
+        assert!(text.contains("aaeq_sink_latency_seconds{sink=\"dlna\"} 0.15\n"));
+        assert!(text.contains("aaeq_streaming 1\n"));
+        assert!(text.contains("aaeq_device_poll_failures_total{device=\"Living \\\"Room\\\"\"} 1\n"));
+        assert!(!text.contains("# EOF"));
+
+        let text = telemetry.render(&[sink()], &Metrics::default(), true, ExpositionFormat::OpenMetrics);
+        assert!(text.contains("# TYPE aaeq_sink_underruns counter\n"));
+        assert!(text.contains("aaeq_sink_underruns_total{sink=\"dlna\"} 2\n"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_format_from_accept() {
+        assert_eq!(
+            ExpositionFormat::from_accept("application/openmetrics-text;version=1.0.0,text/plain;q=0.5"),
+            ExpositionFormat::OpenMetrics
+        );
+        assert_eq!(ExpositionFormat::from_accept("text/plain"), ExpositionFormat::Prometheus);
+        assert_eq!(ExpositionFormat::from_accept("*/*"), ExpositionFormat::Prometheus);
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 /// Interleaved stereo 64-bit float frames from DSP
 #[derive(Clone, Copy, Debug)]
@@ -33,7 +34,7 @@ impl<'a> AudioBlock<'a> {
 }
 
 /// Sample format for output conversion
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub enum SampleFormat {
     /// 64-bit float (native DSP format)
     F64,
@@ -73,7 +74,7 @@ impl SampleFormat {
 }
 
 /// Configuration for an output sink
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct OutputConfig {
     /// Target sample rate in Hz
     pub sample_rate: u32,
@@ -0,0 +1,135 @@
+//! AES67 tests: send with the AES67 sink to a multicast group over loopback,
+//! receive it with the AES67 receiver and check the audio and SAP announcement.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use stream_server::aes67::{discover_streams, Aes67Encoding, SAP_ADDR};
+use stream_server::*;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A group and port of its own for each test, so parallel tests don't hear each other
+fn group(last_octet: u8) -> SocketAddr {
+    SocketAddr::new(Ipv4Addr::new(239, 69, 250, last_octet).into(), 15000 + last_octet as u16)
+}
+
+fn output_config(sample_rate: u32, channels: u16) -> OutputConfig {
+    OutputConfig {
+        sample_rate,
+        channels,
+        format: SampleFormat::S24LE,
+        buffer_ms: 50,
+        exclusive: false,
+    }
+}
+
+fn sine(frames: usize, channels: usize) -> Vec<f64> {
+    (0..frames)
+        .flat_map(|i| {
+            let s = (2.0 * std::f64::consts::PI * 1000.0 * i as f64 / 48000.0).sin() * 0.5;
+            std::iter::repeat_n(s, channels)
+        })
+        .collect()
+}
+
+async fn receive(rx: &mut mpsc::Receiver<Vec<f64>>, samples: usize) -> Vec<f64> {
+    let mut received = Vec::new();
+    while received.len() < samples {
+        received.extend(timeout(RECEIVE_TIMEOUT, rx.recv()).await.unwrap().unwrap());
+    }
+    received
+}
+
+#[tokio::test]
+async fn test_multicast_roundtrip() {
+    for (octet, encoding, tolerance) in [(1, Aes67Encoding::L24, 1e-6), (2, Aes67Encoding::L16, 1e-4)] {
+        let mut sink = Aes67Sink::new(group(octet))
+            .with_interface(Ipv4Addr::LOCALHOST)
+            .with_encoding(encoding)
+            .with_announce(false);
+        sink.open(output_config(48000, 2)).await.unwrap();
+
+        // The receiver takes the sink's SDP, as a receiver configured by hand would
+        let (tx, mut rx) = mpsc::channel(256);
+        let receiver = Aes67Receiver::from_sdp(&sink.sdp().unwrap())
+            .unwrap()
+            .with_interface(Ipv4Addr::LOCALHOST)
+            .start(output_config(48000, 2), tx)
+            .await
+            .unwrap();
+
+        let input = sine(4800, 2);
+        sink.write(AudioBlock::new(&input, 48000, 2)).await.unwrap();
+
+        let received = receive(&mut rx, input.len()).await;
+        assert_eq!(received.len(), input.len());
+        for (got, sent) in received.iter().zip(&input) {
+            assert!((got - sent).abs() < tolerance, "{:?}: {} vs {}", encoding, got, sent);
+        }
+        assert_eq!(receiver.packets_received(), 100);
+        assert_eq!(receiver.packets_lost(), 0);
+
+        sink.close().await.unwrap();
+        receiver.stop().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_receiver_converts_channels_and_rate() {
+    let mut sink = Aes67Sink::new(group(3))
+        .with_interface(Ipv4Addr::LOCALHOST)
+        .with_announce(false);
+    sink.open(output_config(48000, 1)).await.unwrap();
+
+    let (tx, mut rx) = mpsc::channel(256);
+    let receiver = Aes67Receiver::new(sink.session().unwrap().clone())
+        .with_interface(Ipv4Addr::LOCALHOST)
+        .start(output_config(96000, 2), tx)
+        .await
+        .unwrap();
+
+    let input = sine(4800, 1);
+    sink.write(AudioBlock::new(&input, 48000, 1)).await.unwrap();
+
+    // Upsampled 2x and duplicated to both channels (less what the resampler holds back)
+    let received = receive(&mut rx, 8000 * 2).await;
+    assert!(received.chunks_exact(2).all(|frame| frame[0] == frame[1]));
+    let peak = received.iter().fold(0.0f64, |peak, s| peak.max(s.abs()));
+    assert!((peak - 0.5).abs() < 0.05, "peak {}", peak);
+
+    sink.close().await.unwrap();
+    receiver.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sap_announcement_discovered() {
+    let discovery = tokio::spawn(discover_streams(Ipv4Addr::LOCALHOST, Duration::from_millis(1500)));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut sink = Aes67Sink::new(group(4))
+        .with_name("AAEQ Discovery Test")
+        .with_interface(Ipv4Addr::LOCALHOST)
+        .with_sap_destination(SAP_ADDR.into());
+    sink.open(output_config(48000, 2)).await.unwrap();
+
+    let streams = discovery.await.unwrap().unwrap();
+    let stream = streams
+        .iter()
+        .find(|s| s.name == "AAEQ Discovery Test")
+        .expect("announced stream not discovered");
+    assert_eq!(stream, sink.session().unwrap());
+    assert_eq!(stream.origin, Ipv4Addr::LOCALHOST);
+
+    sink.close().await.unwrap();
+
+    // A stream announced and then closed while listening is dropped again
+    let discovery = tokio::spawn(discover_streams(Ipv4Addr::LOCALHOST, Duration::from_millis(1000)));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    sink.open(output_config(48000, 2)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    sink.close().await.unwrap();
+    let streams = discovery.await.unwrap().unwrap();
+    assert!(streams.iter().all(|s| s.name != "AAEQ Discovery Test"));
+}
@@ -0,0 +1,157 @@
+//! End-to-end check of the RAOP audio path against a shairport-sync style stand-in:
+//! ALAC encode -> AES-128-CBC encrypt -> RTP over UDP -> decrypt -> ALAC decode.
+
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use stream_server::airplay::*;
+use symphonia_codec_alac::AlacDecoder;
+use symphonia_core::audio::{AudioBufferRef, Signal};
+use symphonia_core::codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_ALAC};
+use symphonia_core::formats::Packet;
+use tokio::net::UdpSocket;
+
+/// Minimal RAOP receiver: holds the private key and decodes what ANNOUNCE describes
+struct StandInReceiver {
+    private_key: RsaPrivateKey,
+    auth: Option<AirPlayAuth>,
+    decoder: Option<AlacDecoder>,
+}
+
+impl StandInReceiver {
+    fn new() -> Self {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        Self {
+            private_key,
+            auth: None,
+            decoder: None,
+        }
+    }
+
+    fn public_key(&self) -> RsaPublicKey {
+        RsaPublicKey::from(&self.private_key)
+    }
+
+    /// Handle the ANNOUNCE SDP like shairport-sync does
+    fn announce(&mut self, sdp: &str) {
+        let attr = |name: &str| {
+            sdp.lines()
+                .find_map(|l| l.strip_prefix(&format!("a={}:", name)))
+                .map(|v| v.trim().to_string())
+        };
+
+        let fmtp = attr("fmtp").expect("fmtp attribute");
+        let fields: Vec<u32> = fmtp
+            .split_whitespace()
+            .skip(1) // payload type
+            .map(|f| f.parse().unwrap())
+            .collect();
+        assert_eq!(fields.len(), 11);
+
+        // Rebuild the ALAC magic cookie from the fmtp fields
+        let mut cookie = Vec::new();
+        cookie.extend_from_slice(&fields[0].to_be_bytes());
+        for &f in &fields[1..6] {
+            cookie.push(f as u8);
+        }
+        cookie.push(fields[6] as u8);
+        cookie.extend_from_slice(&(fields[7] as u16).to_be_bytes());
+        cookie.extend_from_slice(&fields[8].to_be_bytes());
+        cookie.extend_from_slice(&fields[9].to_be_bytes());
+        cookie.extend_from_slice(&fields[10].to_be_bytes());
+
+        let mut params = CodecParameters::new();
+        params
+            .for_codec(CODEC_TYPE_ALAC)
+            .with_extra_data(cookie.into_boxed_slice());
+        self.decoder = Some(AlacDecoder::try_new(&params, &DecoderOptions::default()).unwrap());
+
+        if let (Some(key), Some(iv)) = (attr("rsaaeskey"), attr("aesiv")) {
+            self.auth = Some(AirPlayAuth::from_sdp_keys(&self.private_key, &key, &iv).unwrap());
+        }
+    }
+
+    /// Decode one RTP audio packet into interleaved 16-bit samples
+    fn receive(&mut self, rtp_packet: &[u8]) -> Vec<i16> {
+        assert_eq!(rtp_packet[0], 0x80);
+        assert_eq!(rtp_packet[1] & 0x7F, 96);
+
+        let payload = &rtp_packet[12..];
+        let payload = match &self.auth {
+            Some(auth) => auth.decrypt_audio(payload).unwrap(),
+            None => payload.to_vec(),
+        };
+
+        let decoder = self.decoder.as_mut().unwrap();
+        let packet = Packet::new_from_slice(0, 0, 0, &payload);
+        match decoder.decode(&packet).unwrap() {
+            AudioBufferRef::S32(buf) => (0..buf.frames())
+                .flat_map(|i| [(buf.chan(0)[i] >> 16) as i16, (buf.chan(1)[i] >> 16) as i16])
+                .collect(),
+            _ => panic!("unexpected sample format"),
+        }
+    }
+}
+
+fn test_audio(frames: usize) -> Vec<i16> {
+    (0..frames)
+        .flat_map(|i| {
+            let t = i as f64 / 44100.0;
+            let l = (2.0 * std::f64::consts::PI * 440.0 * t).sin() * 12000.0;
+            let r = (2.0 * std::f64::consts::PI * 880.0 * t).sin() * 9000.0;
+            [l as i16, r as i16]
+        })
+        .collect()
+}
+
+async fn stream_to_receiver(receiver: &mut StandInReceiver, auth: &AirPlayAuth, input: &[i16]) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut rtp = RtpStream::new(0, socket.local_addr().unwrap()).await.unwrap();
+
+    let mut encoder = AlacEncoder::new(AlacConfig::default());
+    let packets = encoder.encode(input).unwrap();
+    assert_eq!(packets.len(), input.len() / (352 * 2));
+
+    let mut received = Vec::new();
+    let mut buf = vec![0u8; 4096];
+    for packet in &packets {
+        let encrypted = auth.encrypt_audio(packet).unwrap();
+        rtp.send_packet(&encrypted, 352).await.unwrap();
+
+        let (len, _) = socket.recv_from(&mut buf).await.unwrap();
+        received.extend(receiver.receive(&buf[..len]));
+    }
+
+    assert_eq!(received, input);
+}
+
+#[tokio::test]
+async fn test_encrypted_raop_audio_roundtrip() {
+    let mut receiver = StandInReceiver::new();
+
+    let mut auth = AirPlayAuth::with_rsa_public_key(receiver.public_key());
+    auth.generate_encryption_keys();
+
+    let encoder = AlacEncoder::new(AlacConfig::default());
+    let sdp = generate_encrypted_sdp(
+        44100,
+        2,
+        &encoder.fmtp_string(),
+        &auth.get_rsa_aes_key_base64().unwrap(),
+        &auth.get_aes_iv_base64().unwrap(),
+    );
+    receiver.announce(&sdp);
+    assert!(receiver.auth.is_some());
+
+    stream_to_receiver(&mut receiver, &auth, &test_audio(352 * 10)).await;
+}
+
+#[tokio::test]
+async fn test_unencrypted_raop_audio_roundtrip() {
+    let mut receiver = StandInReceiver::new();
+    let auth = AirPlayAuth::new();
+
+    let encoder = AlacEncoder::new(AlacConfig::default());
+    receiver.announce(&generate_sdp(44100, 2, &encoder.fmtp_string()));
+    assert!(receiver.auth.is_none());
+
+    stream_to_receiver(&mut receiver, &auth, &test_audio(352 * 4)).await;
+}
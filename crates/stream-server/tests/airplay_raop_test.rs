@@ -0,0 +1,434 @@
+//! AirPlay (RAOP) sink tests against a mock receiver, so the session flow and RTP
+//! stream can be checked without hardware.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use stream_server::airplay::AirPlayDevice;
+use stream_server::dlna::MediaMetadata;
+use stream_server::*;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An RTSP request as seen by the mock receiver
+#[derive(Debug, Clone)]
+struct RtspRequest {
+    method: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+/// An RTP packet received on the audio (server) port
+#[derive(Debug)]
+struct ReceivedRtp {
+    sequence_number: u16,
+    timestamp: u32,
+    payload_len: usize,
+}
+
+/// Mock RAOP receiver: RTSP on TCP plus audio/control/timing UDP ports
+struct MockReceiver {
+    address: SocketAddr,
+    requests: Arc<Mutex<Vec<RtspRequest>>>,
+    audio_rx: mpsc::UnboundedReceiver<ReceivedRtp>,
+    control_socket: Arc<UdpSocket>,
+    control_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    timing_socket: UdpSocket,
+}
+
+impl MockReceiver {
+    async fn start() -> Self {
+        Self::start_with_announce_status(200).await
+    }
+
+    async fn start_with_announce_status(announce_status: u16) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let audio_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let control_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let timing_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let transport = format!(
+            "RTP/AVP/UDP;unicast;mode=record;server_port={};control_port={};timing_port={}",
+            audio_socket.local_addr().unwrap().port(),
+            control_socket.local_addr().unwrap().port(),
+            timing_socket.local_addr().unwrap().port()
+        );
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        tokio::spawn(serve_rtsp(listener, transport, announce_status, requests.clone()));
+
+        let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            while let Ok(len) = audio_socket.recv(&mut buf).await {
+                assert!(len >= 12);
+                assert_eq!(buf[0], 0x80);
+                assert_eq!(buf[1] & 0x7F, 96);
+                let _ = audio_tx.send(ReceivedRtp {
+                    sequence_number: u16::from_be_bytes([buf[2], buf[3]]),
+                    timestamp: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+                    payload_len: len - 12,
+                });
+            }
+        });
+
+        let (control_tx, control_rx) = mpsc::unbounded_channel();
+        let control_reader = control_socket.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            while let Ok(len) = control_reader.recv(&mut buf).await {
+                let _ = control_tx.send(buf[..len].to_vec());
+            }
+        });
+
+        Self {
+            address,
+            requests,
+            audio_rx,
+            control_socket,
+            control_rx,
+            timing_socket,
+        }
+    }
+
+    fn device(&self) -> AirPlayDevice {
+        AirPlayDevice::from_address("Mock Receiver", self.address)
+    }
+
+    fn methods(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| r.method.clone())
+            .collect()
+    }
+
+    fn request(&self, method: &str) -> RtspRequest {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|r| r.method == method)
+            .cloned()
+            .unwrap_or_else(|| panic!("no {} request", method))
+    }
+
+    /// Port the sender advertised in its SETUP `Transport` header
+    fn sender_port(&self, key: &str) -> u16 {
+        self.request("SETUP").headers["Transport"]
+            .split(';')
+            .find_map(|p| p.strip_prefix(&format!("{}=", key)))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    async fn next_audio(&mut self) -> ReceivedRtp {
+        timeout(RECEIVE_TIMEOUT, self.audio_rx.recv())
+            .await
+            .expect("timed out waiting for audio")
+            .unwrap()
+    }
+
+    async fn next_control(&mut self) -> Vec<u8> {
+        timeout(RECEIVE_TIMEOUT, self.control_rx.recv())
+            .await
+            .expect("timed out waiting for control packet")
+            .unwrap()
+    }
+}
+
+/// Parse `key=value` pairs from an `RTP-Info` header
+fn rtp_info(request: &RtspRequest) -> (u16, u32) {
+    let info = &request.headers["RTP-Info"];
+    let value = |key: &str| {
+        info.split(';')
+            .find_map(|p| p.strip_prefix(&format!("{}=", key)))
+            .unwrap()
+            .to_string()
+    };
+    (value("seq").parse().unwrap(), value("rtptime").parse().unwrap())
+}
+
+async fn serve_rtsp(
+    listener: TcpListener,
+    transport: String,
+    announce_status: u16,
+    requests: Arc<Mutex<Vec<RtspRequest>>>,
+) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+            break;
+        }
+        let method = request_line.split_whitespace().next().unwrap().to_string();
+
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                headers.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+
+        let length = headers
+            .get("Content-Length")
+            .map_or(0, |l| l.parse().unwrap());
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).await.unwrap();
+
+        let (status, extra) = match method.as_str() {
+            "OPTIONS" => (
+                200,
+                "Public: ANNOUNCE, SETUP, RECORD, PAUSE, FLUSH, TEARDOWN, OPTIONS, SET_PARAMETER\r\n"
+                    .to_string(),
+            ),
+            "ANNOUNCE" => (announce_status, String::new()),
+            "SETUP" => (200, format!("Transport: {}\r\nSession: 1\r\n", transport)),
+            "RECORD" => (200, "Audio-Latency: 11025\r\n".to_string()),
+            _ => (200, String::new()),
+        };
+
+        let response = format!(
+            "RTSP/1.0 {} {}\r\nCSeq: {}\r\nServer: MockRAOP/1.0\r\n{}\r\n",
+            status,
+            if status == 200 { "OK" } else { "Not Acceptable" },
+            headers.get("CSeq").cloned().unwrap_or_default(),
+            extra
+        );
+
+        // Record before replying so the sender sees it once its request returns
+        requests.lock().unwrap().push(RtspRequest {
+            method,
+            headers,
+            body,
+        });
+        reader.get_mut().write_all(response.as_bytes()).await.unwrap();
+    }
+}
+
+fn output_config() -> OutputConfig {
+    OutputConfig {
+        sample_rate: 44100,
+        channels: 2,
+        format: SampleFormat::S16LE,
+        buffer_ms: 150,
+        exclusive: false,
+    }
+}
+
+fn sine(frames: usize) -> Vec<f64> {
+    (0..frames)
+        .flat_map(|i| {
+            let s = (2.0 * std::f64::consts::PI * 440.0 * i as f64 / 44100.0).sin() * 0.5;
+            [s, s]
+        })
+        .collect()
+}
+
+async fn open_sink(receiver: &MockReceiver) -> AirPlaySink {
+    let mut sink = AirPlaySink::new();
+    sink.set_device(receiver.device());
+    sink.open(output_config()).await.unwrap();
+    sink
+}
+
+#[tokio::test]
+async fn test_session_handshake() {
+    let receiver = MockReceiver::start().await;
+    let mut sink = open_sink(&receiver).await;
+    assert!(sink.is_open());
+
+    assert_eq!(receiver.methods(), ["OPTIONS", "ANNOUNCE", "SETUP", "RECORD"]);
+
+    let announce = receiver.request("ANNOUNCE");
+    assert_eq!(announce.headers["Content-Type"], "application/sdp");
+    let sdp = String::from_utf8(announce.body).unwrap();
+    assert!(sdp.contains("a=rtpmap:96 AppleLossless"));
+    assert!(sdp.contains("a=fmtp:96 352 0 16"));
+
+    let setup = receiver.request("SETUP");
+    assert!(setup.headers["Transport"].contains("mode=record"));
+
+    // Session from the SETUP response is echoed on later requests
+    assert_eq!(receiver.request("RECORD").headers["Session"], "1");
+
+    sink.close().await.unwrap();
+    assert!(!sink.is_open());
+    assert_eq!(receiver.methods().last().unwrap(), "TEARDOWN");
+}
+
+#[tokio::test]
+async fn test_rtp_sequencing_and_timestamps() {
+    let mut receiver = MockReceiver::start().await;
+    let mut sink = open_sink(&receiver).await;
+
+    let (record_seq, record_rtptime) = rtp_info(&receiver.request("RECORD"));
+
+    let audio = sine(352 * 6);
+    sink.write(AudioBlock::new(&audio, 44100, 2)).await.unwrap();
+
+    for i in 0..6u16 {
+        let packet = receiver.next_audio().await;
+        assert_eq!(packet.sequence_number, record_seq.wrapping_add(i));
+        assert_eq!(packet.timestamp, record_rtptime.wrapping_add(352 * i as u32));
+        assert!(packet.payload_len > 0);
+    }
+
+    // The first sync starts the timeline at the RECORD rtptime
+    let sync = receiver.next_control().await;
+    assert_eq!(&sync[..2], &[0x90, 0xD4]);
+    let next_ts = u32::from_be_bytes(sync[16..20].try_into().unwrap());
+    let playing_ts = u32::from_be_bytes(sync[4..8].try_into().unwrap());
+    assert_eq!(next_ts, record_rtptime);
+    assert_eq!(next_ts.wrapping_sub(playing_ts), 11025);
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_flush_restarts_sync() {
+    let mut receiver = MockReceiver::start().await;
+    let mut sink = open_sink(&receiver).await;
+
+    let audio = sine(352 * 2);
+    sink.write(AudioBlock::new(&audio, 44100, 2)).await.unwrap();
+    receiver.next_audio().await;
+    let last = receiver.next_audio().await;
+    receiver.next_control().await;
+
+    sink.flush().await.unwrap();
+
+    // FLUSH names the next packet to be sent
+    let (seq, rtptime) = rtp_info(&receiver.request("FLUSH"));
+    assert_eq!(seq, last.sequence_number.wrapping_add(1));
+    assert_eq!(rtptime, last.timestamp.wrapping_add(352));
+
+    sink.write(AudioBlock::new(&audio, 44100, 2)).await.unwrap();
+    assert_eq!(receiver.next_audio().await.sequence_number, seq);
+    assert_eq!(receiver.next_control().await[0], 0x90);
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_retransmit_request() {
+    let mut receiver = MockReceiver::start().await;
+    let mut sink = open_sink(&receiver).await;
+
+    let audio = sine(352 * 4);
+    sink.write(AudioBlock::new(&audio, 44100, 2)).await.unwrap();
+    let mut packets = Vec::new();
+    for _ in 0..4 {
+        packets.push(receiver.next_audio().await);
+    }
+    receiver.next_control().await; // initial sync
+
+    // Report packets 2 and 3 as lost
+    let seq = packets[1].sequence_number.to_be_bytes();
+    let request = [0x80, 0xD5, 0x00, 0x01, seq[0], seq[1], 0x00, 0x02];
+    let sender_control = format!("127.0.0.1:{}", receiver.sender_port("control_port"));
+    receiver
+        .control_socket
+        .send_to(&request, sender_control)
+        .await
+        .unwrap();
+
+    for expected in &packets[1..3] {
+        let resent = receiver.next_control().await;
+        assert_eq!(&resent[..4], &[0x80, 0xD6, 0x00, 0x01]);
+        assert_eq!(u16::from_be_bytes([resent[6], resent[7]]), expected.sequence_number);
+        assert_eq!(resent.len() - 16, expected.payload_len);
+    }
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_timing_request() {
+    let receiver = MockReceiver::start().await;
+    let mut sink = open_sink(&receiver).await;
+
+    let mut request = vec![0x80, 0xD2, 0x00, 0x07, 0, 0, 0, 0];
+    request.extend_from_slice(&[0u8; 16]);
+    request.extend_from_slice(&0x1234u64.to_be_bytes());
+
+    let sender_timing = format!("127.0.0.1:{}", receiver.sender_port("timing_port"));
+    receiver
+        .timing_socket
+        .send_to(&request, sender_timing)
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 64];
+    let len = timeout(RECEIVE_TIMEOUT, receiver.timing_socket.recv(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(len, 32);
+    assert_eq!(buf[1], 0xD3);
+    assert_eq!(u64::from_be_bytes(buf[8..16].try_into().unwrap()), 0x1234);
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_volume_and_metadata() {
+    let receiver = MockReceiver::start().await;
+
+    let mut sink = AirPlaySink::new();
+    sink.set_device(receiver.device());
+    sink.set_volume(0.5).await.unwrap(); // applied after RECORD
+    sink.open(output_config()).await.unwrap();
+
+    let volume = receiver.request("SET_PARAMETER");
+    assert_eq!(volume.headers["Content-Type"], "text/parameters");
+    assert_eq!(volume.body, b"volume: -15.000000\r\n");
+
+    sink.set_metadata(MediaMetadata {
+        title: "Track".to_string(),
+        artist: Some("Artist".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let metadata = receiver.request("SET_PARAMETER");
+    assert_eq!(metadata.headers["Content-Type"], "application/x-dmap-tagged");
+    assert!(metadata.headers["RTP-Info"].starts_with("rtptime="));
+    assert_eq!(&metadata.body[..4], b"mlit");
+
+    sink.set_volume(0.0).await.unwrap();
+    assert_eq!(receiver.request("SET_PARAMETER").body, b"volume: -144.000000\r\n");
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_announce_rejected() {
+    let receiver = MockReceiver::start_with_announce_status(406).await;
+
+    let mut sink = AirPlaySink::new();
+    sink.set_device(receiver.device());
+    let err = sink.open(output_config()).await.unwrap_err();
+
+    assert!(err.to_string().contains("ANNOUNCE failed"));
+    assert!(!sink.is_open());
+    assert!(!receiver.methods().contains(&"SETUP".to_string()));
+}
@@ -0,0 +1,119 @@
+//! AirPlay receiver tests: stream to the receiver with the AirPlay sink over
+//! localhost and check the decoded audio, metadata and volume that come out.
+
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use std::time::Duration;
+use stream_server::airplay::AirPlayDevice;
+use stream_server::dlna::MediaMetadata;
+use stream_server::*;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn output_config() -> OutputConfig {
+    OutputConfig {
+        sample_rate: 44100,
+        channels: 2,
+        format: SampleFormat::S16LE,
+        buffer_ms: 150,
+        exclusive: false,
+    }
+}
+
+fn sine(frames: usize) -> Vec<f64> {
+    (0..frames)
+        .flat_map(|i| {
+            let s = (2.0 * std::f64::consts::PI * 440.0 * i as f64 / 44100.0).sin() * 0.5;
+            [s, s]
+        })
+        .collect()
+}
+
+async fn start_receiver(
+    private_key: Option<RsaPrivateKey>,
+) -> (
+    AirPlayReceiverHandle,
+    mpsc::Receiver<Vec<f64>>,
+    mpsc::UnboundedReceiver<AirPlayReceiverEvent>,
+) {
+    let (tx, rx) = mpsc::channel(256);
+    let (events_tx, events_rx) = mpsc::unbounded_channel();
+    let mut receiver = AirPlayReceiver::new("Test Receiver".to_string(), "127.0.0.1:0".parse().unwrap())
+        .with_advertise(false);
+    if let Some(private_key) = private_key {
+        receiver = receiver.with_rsa_private_key(private_key);
+    }
+    let handle = receiver.start(output_config(), tx, events_tx).await.unwrap();
+    (handle, rx, events_rx)
+}
+
+async fn next_event(events: &mut mpsc::UnboundedReceiver<AirPlayReceiverEvent>) -> AirPlayReceiverEvent {
+    timeout(RECEIVE_TIMEOUT, events.recv()).await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn test_receives_encrypted_stream_with_metadata() {
+    let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    let (receiver, mut rx, mut events) = start_receiver(Some(private_key.clone())).await;
+
+    let mut sink = AirPlaySink::new();
+    sink.set_device(AirPlayDevice::from_address("Test Receiver", receiver.local_addr()));
+    sink.set_rsa_public_key(RsaPublicKey::from(&private_key));
+    sink.set_volume(0.5).await.unwrap();
+    sink.set_metadata(MediaMetadata {
+        title: "So What".to_string(),
+        artist: Some("Miles Davis".to_string()),
+        album: Some("Kind of Blue".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    sink.open(output_config()).await.unwrap();
+
+    assert!(matches!(next_event(&mut events).await, AirPlayReceiverEvent::SessionStarted { .. }));
+    assert_eq!(next_event(&mut events).await, AirPlayReceiverEvent::VolumeChanged { volume: 0.5 });
+    let AirPlayReceiverEvent::TrackChanged { meta } = next_event(&mut events).await else {
+        panic!("expected track metadata");
+    };
+    assert_eq!(meta.title, "So What");
+    assert_eq!(meta.artist, "Miles Davis");
+    assert_eq!(meta.album, "Kind of Blue");
+
+    // Whole packets only; the remainder is sent on close
+    let input = sine(44100);
+    sink.write(AudioBlock::new(&input, 44100, 2)).await.unwrap();
+    let expected_frames = 44100 / 352 * 352;
+
+    let mut received = Vec::new();
+    while received.len() < expected_frames * 2 {
+        received.extend(timeout(RECEIVE_TIMEOUT, rx.recv()).await.unwrap().unwrap());
+    }
+
+    // Half volume is a quarter of the amplitude with the squared taper
+    assert_eq!(received.len(), expected_frames * 2);
+    for (got, sent) in received.iter().zip(&input) {
+        assert!((got - sent * 0.25).abs() < 1e-3, "{} vs {}", got, sent * 0.25);
+    }
+
+    sink.close().await.unwrap();
+    assert!(matches!(next_event(&mut events).await, AirPlayReceiverEvent::SessionEnded { .. }));
+
+    receiver.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rejects_encrypted_stream_without_key() {
+    let (receiver, _rx, mut events) = start_receiver(None).await;
+
+    let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    let mut sink = AirPlaySink::new();
+    sink.set_device(AirPlayDevice::from_address("Test Receiver", receiver.local_addr()));
+    sink.set_rsa_public_key(RsaPublicKey::from(&private_key));
+
+    let err = sink.open(output_config()).await.unwrap_err();
+    assert!(err.to_string().contains("ANNOUNCE failed"));
+    assert!(events.try_recv().is_err());
+
+    receiver.stop().await.unwrap();
+}
@@ -0,0 +1,419 @@
+//! ANP sink tests against an in-process node stand-in: a WebSocket control
+//! server plus RTP/RTCP receive sockets, speaking the v0.4 spec.
+
+use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+use stream_server::anp::*;
+use stream_server::*;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn output_config(format: SampleFormat) -> OutputConfig {
+    OutputConfig {
+        sample_rate: 48000,
+        channels: 2,
+        format,
+        buffer_ms: 150,
+        exclusive: false,
+    }
+}
+
+/// Interleaved stereo ramp
+fn ramp(frames: usize) -> Vec<f64> {
+    (0..frames * 2)
+        .map(|i| ((i % 512) as f64 - 256.0) / 1024.0)
+        .collect()
+}
+
+/// Decode big-endian L24/L16 samples
+fn decode_payload(payload: &[u8], bytes_per_sample: usize) -> Vec<i32> {
+    payload
+        .chunks_exact(bytes_per_sample)
+        .map(|b| match bytes_per_sample {
+            3 => i32::from_be_bytes([b[0], b[1], b[2], 0]) >> 8,
+            _ => i16::from_be_bytes([b[0], b[1]]) as i32,
+        })
+        .collect()
+}
+
+/// Conversion is dithered, so allow a couple of LSBs of difference
+fn assert_matches_audio(samples: &[i32], audio: &[f64], full_scale: f64) {
+    assert_eq!(samples.len(), audio.len());
+    for (&s, &a) in samples.iter().zip(audio) {
+        let expected = (a * full_scale) as i32;
+        assert!((s - expected).abs() <= 2, "{} vs {}", s, expected);
+    }
+}
+
+fn node_init(rtp_port: u16) -> SessionInit {
+    SessionInit {
+        protocol_version: "0.4".to_string(),
+        node_uuid: "550e8400-e29b-41d4-a716-446655440000".to_string(),
+        features: ["micro_pll", "crc_verify", "volume_control", "gapless", "capabilities"]
+            .map(String::from)
+            .to_vec(),
+        optional_features: vec!["rtcp_sr".to_string()],
+        latency_comp: true,
+        node_capabilities: NodeCapabilities {
+            hardware: "Test Rig".to_string(),
+            dac_name: "Null DAC".to_string(),
+            max_sample_rate: 192000,
+            supported_formats: ["S24LE", "S16LE"].map(String::from).to_vec(),
+            native_format: "S24LE".to_string(),
+            dac_latency_ms: Some(2.0),
+            ..Default::default()
+        },
+        rtp_port: Some(rtp_port),
+    }
+}
+
+/// Minimal ANP node: accepts the server's control connection and receives RTP
+struct StandInNode {
+    ws: WebSocketStream<TcpStream>,
+    rtp: UdpSocket,
+    rtcp: UdpSocket,
+    accept: SessionAccept,
+}
+
+impl StandInNode {
+    /// Listen for the server; the returned task completes once the session is negotiated
+    async fn listen(customize: impl FnOnce(&mut SessionInit) + Send + 'static) -> (AnpNode, JoinHandle<Option<Self>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let node = AnpNode::from_address("Stand-in", listener.local_addr().unwrap());
+
+        // RTCP goes to the port after RTP
+        let (rtp, rtcp) = loop {
+            let rtp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let port = rtp.local_addr().unwrap().port();
+            if let Ok(rtcp) = UdpSocket::bind(("127.0.0.1", port.wrapping_add(1))).await {
+                break (rtp, rtcp);
+            }
+        };
+
+        let task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+
+            let mut init = node_init(rtp.local_addr().unwrap().port());
+            customize(&mut init);
+            ws.send(Message::Text(ControlMessage::SessionInit(init).to_json()))
+                .await
+                .unwrap();
+
+            let accept = match next_message(&mut ws).await {
+                ControlMessage::SessionAccept(accept) => accept,
+                _ => return None,
+            };
+            Some(Self { ws, rtp, rtcp, accept })
+        });
+
+        (node, task)
+    }
+
+    async fn send(&mut self, message: ControlMessage) {
+        self.ws.send(Message::Text(message.to_json())).await.unwrap();
+    }
+
+    async fn receive(&mut self) -> ControlMessage {
+        next_message(&mut self.ws).await
+    }
+
+    async fn receive_packet(&self) -> RtpPacket {
+        let mut buf = [0u8; 2048];
+        let (len, _) = timeout(RECEIVE_TIMEOUT, self.rtp.recv_from(&mut buf))
+            .await
+            .expect("timed out waiting for RTP")
+            .unwrap();
+        RtpPacket::parse(&buf[..len]).unwrap()
+    }
+
+    /// Answer stream_stop and wait for the server to close the channel
+    async fn expect_stop(&mut self) {
+        assert_eq!(self.receive().await, ControlMessage::StreamStop {});
+        self.send(ControlMessage::StreamStopped {}).await;
+
+        let closed = timeout(RECEIVE_TIMEOUT, async {
+            while let Some(Ok(frame)) = self.ws.next().await {
+                if frame.is_close() {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(closed.is_ok(), "server did not close the control channel");
+    }
+}
+
+/// Next control message; panics on close
+async fn next_message(ws: &mut WebSocketStream<TcpStream>) -> ControlMessage {
+    loop {
+        let frame = timeout(RECEIVE_TIMEOUT, ws.next())
+            .await
+            .expect("timed out waiting for control message")
+            .expect("control channel closed")
+            .unwrap();
+        if let Message::Text(text) = frame {
+            return ControlMessage::from_json(&text).unwrap();
+        }
+    }
+}
+
+/// Open a sink against a stand-in node that accepted the session
+async fn open_session(format: SampleFormat) -> (AnpSink, StandInNode) {
+    let (node, task) = StandInNode::listen(|_| {}).await;
+    let mut sink = AnpSink::new(node);
+    sink.open(output_config(format)).await.unwrap();
+    let node = task.await.unwrap().expect("node did not receive session_accept");
+    (sink, node)
+}
+
+#[tokio::test]
+async fn test_session_negotiation() {
+    let (mut sink, mut node) = open_session(SampleFormat::S24LE).await;
+
+    let accept = &node.accept;
+    assert_eq!(accept.protocol_version, "0.4");
+    assert_eq!(
+        accept.active_features,
+        vec!["micro_pll", "crc_verify", "volume_control", "gapless", "capabilities"]
+    );
+    assert_eq!(accept.optional_features, vec!["rtcp_sr"]);
+    assert_eq!(accept.rtp_config.payload_type, PT_L24);
+    assert_eq!(accept.rtp_config.timestamp_rate, 48000);
+    assert_eq!(accept.rtp_extensions.crc32.window, 64);
+    assert_eq!(accept.buffer.target_ms, 150);
+    assert_eq!(accept.buffer.start_threshold_ms, 100);
+    assert_eq!(accept.latency.dac_ms, 2.0);
+
+    assert_eq!(sink.session(), Some(accept));
+    assert_eq!(sink.latency_ms(), 152);
+
+    let (closed, _) = tokio::join!(sink.close(), node.expect_stop());
+    closed.unwrap();
+}
+
+#[tokio::test]
+async fn test_rtp_stream_l24() {
+    let (mut sink, node) = open_session(SampleFormat::S24LE).await;
+    let rtp_config = node.accept.rtp_config;
+
+    // 20ms: four 5ms packets (240 frames keeps L24 stereo within the MTU)
+    let audio = ramp(960);
+    sink.write(AudioBlock::new(&audio, 48000, 2)).await.unwrap();
+
+    let mut samples = Vec::new();
+    for i in 0..4u16 {
+        let packet = node.receive_packet().await;
+        assert_eq!(packet.payload_type, PT_L24);
+        assert_eq!(packet.ssrc, rtp_config.ssrc);
+        assert_eq!(packet.sequence, rtp_config.initial_sequence.wrapping_add(i));
+        assert_eq!(packet.timestamp, rtp_config.initial_timestamp + 240 * i as u32);
+        assert_eq!(packet.payload.len(), 240 * 6);
+
+        // CRC on the first packet of each 64-packet window
+        if i == 0 {
+            assert_eq!(packet.crc32(2), Some(crc32fast::hash(&packet.payload)));
+        } else {
+            assert_eq!(packet.crc32(2), None);
+        }
+        samples.extend(decode_payload(&packet.payload, 3));
+    }
+    assert_matches_audio(&samples, &audio, 8388607.0);
+
+    // A partial packet goes out on drain
+    let tail = ramp(100);
+    sink.write(AudioBlock::new(&tail, 48000, 2)).await.unwrap();
+    sink.drain().await.unwrap();
+    let packet = node.receive_packet().await;
+    assert_eq!(packet.timestamp, rtp_config.initial_timestamp + 960);
+    assert_matches_audio(&decode_payload(&packet.payload, 3), &tail, 8388607.0);
+
+    assert_eq!(sink.stats().frames_written, 1060);
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rtp_stream_l16() {
+    let (mut sink, node) = open_session(SampleFormat::S16LE).await;
+    assert_eq!(node.accept.rtp_config.payload_type, PT_L16);
+
+    let audio = ramp(360);
+    sink.write(AudioBlock::new(&audio, 48000, 2)).await.unwrap();
+
+    let packet = node.receive_packet().await;
+    assert_eq!(packet.payload_type, PT_L16);
+    assert_matches_audio(&decode_payload(&packet.payload, 2), &audio, 32767.0);
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_gapless_markers() {
+    let (mut sink, node) = open_session(SampleFormat::S24LE).await;
+    let gapless_id = node.accept.rtp_extensions.gapless.extension_id;
+
+    // One full packet plus a partial that ends the track
+    sink.write(AudioBlock::new(&ramp(300), 48000, 2)).await.unwrap();
+    sink.mark_track_boundary().await.unwrap();
+    sink.write(AudioBlock::new(&ramp(480), 48000, 2)).await.unwrap();
+
+    let packets = [
+        node.receive_packet().await,
+        node.receive_packet().await,
+        node.receive_packet().await,
+        node.receive_packet().await,
+    ];
+
+    assert_eq!(packets[0].track_markers(gapless_id), None);
+    let end = packets[1].track_markers(gapless_id).unwrap();
+    assert!(end.track_end && !end.track_start);
+    assert_eq!(packets[1].payload.len(), 60 * 6);
+    let start = packets[2].track_markers(gapless_id).unwrap();
+    assert!(start.track_start && !start.track_end);
+    assert_eq!(packets[3].track_markers(gapless_id), None);
+
+    // Timestamps stay contiguous across the boundary
+    assert_eq!(packets[2].timestamp - packets[1].timestamp, 60);
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_health_updates_stats() {
+    let (mut sink, mut node) = open_session(SampleFormat::S24LE).await;
+
+    let health = |fill: f32, underruns: u64, crc_fail: u64| {
+        let mut health = Health::default();
+        health.playback.buffer_fill_percent = fill;
+        health.playback.buffer_health = BufferHealth::from_fill_percent(fill);
+        health.errors.buffer_underruns = underruns;
+        health.errors.xruns = underruns;
+        health.integrity.crc_ok = 64;
+        health.integrity.crc_fail = crc_fail;
+        health.clock_sync.pll_state = PllState::Locked;
+        ControlMessage::Health(health)
+    };
+
+    node.send(health(93.0, 0, 0)).await;
+    node.send(health(45.0, 2, 1)).await;
+
+    timeout(RECEIVE_TIMEOUT, async {
+        while sink.stats().underruns != 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("health was not applied");
+
+    let stats = sink.stats();
+    assert!((stats.buffer_fill - 0.45).abs() < 1e-6);
+    let latest = sink.health().unwrap();
+    assert_eq!(latest.integrity.crc_fail, 1);
+    assert_eq!(latest.playback.buffer_health, BufferHealth::Low);
+    assert_eq!(latest.clock_sync.pll_state, PllState::Locked);
+
+    // Errors are kept for inspection, including ones bundled with health
+    node.ws
+        .send(Message::Text(
+            r#"{"health":{"connection":{"state":"interrupted"}},"error":{"code":"E102","severity":"warning","message":"Connection timeout"}}"#.to_string(),
+        ))
+        .await
+        .unwrap();
+    timeout(RECEIVE_TIMEOUT, async {
+        while sink.last_error().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("error was not recorded");
+    assert_eq!(sink.last_error().unwrap().code, "E102");
+    assert_eq!(sink.health().unwrap().connection.state, "interrupted");
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_volume_control() {
+    let (mut sink, mut node) = open_session(SampleFormat::S24LE).await;
+    assert_eq!(node.accept.volume.curve_type, VolumeCurve::Logarithmic);
+
+    sink.set_volume(0.5, false, 100).unwrap();
+    let ControlMessage::VolumeSet(set) = node.receive().await else {
+        panic!("expected volume_set");
+    };
+    assert_eq!(set.level, 0.5);
+    assert_eq!(set.ramp_ms, 100);
+    assert_eq!(set.ramp_shape, RampShape::SCurve);
+
+    node.send(ControlMessage::VolumeResult(VolumeResult {
+        status: Some("success".to_string()),
+        level: 0.5,
+        mute: false,
+        gain_db: Some(VolumeCurve::Logarithmic.gain_db(0.5)),
+        ..Default::default()
+    }))
+    .await;
+
+    timeout(RECEIVE_TIMEOUT, async {
+        while sink.node_volume().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("volume_result was not applied");
+    let gain = sink.node_volume().unwrap().gain_db.unwrap();
+    assert!((gain + 12.04).abs() < 0.01);
+
+    let (closed, _) = tokio::join!(sink.close(), node.expect_stop());
+    closed.unwrap();
+}
+
+#[tokio::test]
+async fn test_sender_reports() {
+    let (mut sink, node) = open_session(SampleFormat::S24LE).await;
+
+    sink.write(AudioBlock::new(&ramp(240), 48000, 2)).await.unwrap();
+
+    let mut buf = [0u8; 64];
+    let (len, _) = timeout(RECEIVE_TIMEOUT, node.rtcp.recv_from(&mut buf))
+        .await
+        .expect("no RTCP sender report")
+        .unwrap();
+    assert_eq!(len, 28);
+    assert_eq!(buf[1], 200);
+    assert_eq!(&buf[4..8], &node.accept.rtp_config.ssrc.to_be_bytes());
+    // One packet of 240 L24 stereo frames so far
+    assert_eq!(&buf[20..24], &1u32.to_be_bytes());
+    assert_eq!(&buf[24..28], &1440u32.to_be_bytes());
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rejects_incompatible_node() {
+    let (node, task) = StandInNode::listen(|init| init.protocol_version = "1.0".to_string()).await;
+    let mut sink = AnpSink::new(node);
+
+    let error = sink.open(output_config(SampleFormat::S24LE)).await.unwrap_err();
+    assert!(error.to_string().contains("E201"), "{}", error);
+    assert!(!sink.is_open());
+
+    // The node is told why rather than being sent session_accept
+    assert!(task.await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_unsupported_sample_rate() {
+    let (node, _task) = StandInNode::listen(|init| init.node_capabilities.max_sample_rate = 48000).await;
+    let mut sink = AnpSink::new(node);
+
+    let mut cfg = output_config(SampleFormat::S24LE);
+    cfg.sample_rate = 96000;
+    let error = sink.open(cfg).await.unwrap_err();
+    assert!(error.to_string().contains("E301"), "{}", error);
+}
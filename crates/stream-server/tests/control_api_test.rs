@@ -0,0 +1,572 @@
+//! Control API tests: mock sinks behind a real server, fed by a test input
+//! through `/v1/outputs/start` and `/v1/outputs/stop`, with the `/v1/events`
+//! feed following along.
+
+use aaeq_core::TrackMeta;
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use stream_server::*;
+use tokio::sync::{mpsc, RwLock};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Blocks the test input sends before going quiet
+const INPUT_BLOCKS: usize = 20;
+const BLOCK_FRAMES: usize = 480;
+
+struct MockSink {
+    name: &'static str,
+    open: bool,
+    written: Arc<AtomicU64>,
+}
+
+impl MockSink {
+    fn new(name: &'static str) -> (Self, Arc<AtomicU64>) {
+        let written = Arc::new(AtomicU64::new(0));
+        (
+            Self {
+                name,
+                open: false,
+                written: written.clone(),
+            },
+            written,
+        )
+    }
+}
+
+#[async_trait]
+impl OutputSink for MockSink {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn open(&mut self, _cfg: OutputConfig) -> Result<()> {
+        self.open = true;
+        Ok(())
+    }
+
+    async fn write(&mut self, block: AudioBlock<'_>) -> Result<()> {
+        self.written.fetch_add(block.num_frames() as u64, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn drain(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.open = false;
+        Ok(())
+    }
+
+    fn latency_ms(&self) -> u32 {
+        25
+    }
+
+    fn is_open(&self) -> bool {
+        self.open
+    }
+}
+
+/// Input that sends `INPUT_BLOCKS` blocks every 10 ms, then stays silent until stopped
+#[derive(Default)]
+struct TestInput {
+    devices: Mutex<Vec<Option<String>>>,
+    running: AtomicBool,
+}
+
+impl TestInput {
+    fn starter(self: &Arc<Self>) -> InputStarter {
+        let input = self.clone();
+        Arc::new(move |device, cfg: OutputConfig, tx: mpsc::Sender<Vec<f64>>| {
+            input.devices.lock().unwrap().push(device);
+            input.running.store(true, Ordering::SeqCst);
+
+            let (stop_tx, mut stop_rx) = mpsc::channel::<()>(1);
+            let input = input.clone();
+            tokio::spawn(async move {
+                for _ in 0..INPUT_BLOCKS {
+                    tokio::select! {
+                        _ = stop_rx.recv() => break,
+                        _ = tokio::time::sleep(Duration::from_millis(10)) => {}
+                    }
+                    if tx.send(vec![0.1; BLOCK_FRAMES * cfg.channels as usize]).await.is_err() {
+                        break;
+                    }
+                }
+                if !stop_rx.is_closed() {
+                    let _ = stop_rx.recv().await;
+                }
+                input.running.store(false, Ordering::SeqCst);
+            });
+            Ok(stop_tx)
+        })
+    }
+}
+
+async fn get(addr: SocketAddr, path: &str) -> Value {
+    let body = reqwest::get(format!("http://{}{}", addr, path)).await.unwrap().text().await.unwrap();
+    serde_json::from_str(&body).unwrap()
+}
+
+async fn post(addr: SocketAddr, path: &str, body: Value) -> (u16, Value) {
+    let response = reqwest::Client::new()
+        .post(format!("http://{}{}", addr, path))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .unwrap();
+    let status = response.status().as_u16();
+    (status, serde_json::from_str(&response.text().await.unwrap()).unwrap())
+}
+
+async fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !done() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("timed out waiting for {}", what));
+}
+
+fn config() -> Value {
+    json!({
+        "sample_rate": 48000,
+        "channels": 2,
+        "format": "S24LE",
+        "buffer_ms": 20,
+        "exclusive": false
+    })
+}
+
+#[tokio::test]
+async fn test_control_api_streaming_lifecycle() {
+    let (alpha, alpha_written) = MockSink::new("alpha");
+    let (beta, beta_written) = MockSink::new("beta");
+    let manager = Arc::new(RwLock::new(OutputManager::new()));
+    manager.write().await.register_sink(Box::new(alpha));
+    manager.write().await.register_sink(Box::new(beta));
+
+    let input = Arc::new(TestInput::default());
+    let server = ControlServer::new("127.0.0.1:0".parse().unwrap(), manager.clone());
+    let streamer = CaptureStreamer::new(manager.clone(), server.metrics()).with_input(input.starter());
+    let mut server = server.with_stream_controller(Arc::new(streamer));
+    server.start().await.unwrap();
+    let addr = server.addr();
+
+    // Nothing selected yet: nothing to start
+    let (status, _) = post(addr, "/v1/outputs/start", json!({})).await;
+    assert_eq!(status, 409);
+    let outputs = get(addr, "/v1/outputs").await;
+    assert_eq!(outputs["active"], Value::Null);
+    assert_eq!(outputs["outputs"][0]["is_open"], false);
+    assert_eq!(outputs["outputs"][0]["latency_ms"], 25);
+
+    // Route from a named input, select alpha: open but not streaming
+    let (status, _) = post(addr, "/v1/route", json!({"input": "Test Mic", "output": "alpha"})).await;
+    assert_eq!(status, 200);
+    let (status, _) = post(addr, "/v1/outputs/select", json!({"name": "alpha", "config": config()})).await;
+    assert_eq!(status, 200);
+    let outputs = get(addr, "/v1/outputs").await;
+    assert_eq!(outputs["active"], "alpha");
+    assert_eq!(outputs["outputs"][0]["is_open"], true);
+    assert_eq!(outputs["outputs"][0]["is_active"], true);
+    assert_eq!(outputs["outputs"][0]["config"]["sample_rate"], 48000);
+    assert_eq!(outputs["outputs"][1]["is_open"], false);
+    assert_eq!(get(addr, "/v1/route").await["is_active"], false);
+    assert_eq!(alpha_written.load(Ordering::SeqCst), 0);
+
+    // Start: the input flows into alpha and the metrics follow
+    let (status, _) = post(addr, "/v1/outputs/start", json!({})).await;
+    assert_eq!(status, 200);
+    assert_eq!(*input.devices.lock().unwrap(), [Some("Test Mic".to_string())]);
+    wait_for("alpha to receive the input", || {
+        alpha_written.load(Ordering::SeqCst) == (INPUT_BLOCKS * BLOCK_FRAMES) as u64
+    })
+    .await;
+
+    let metrics = get(addr, "/v1/outputs/metrics").await;
+    assert_eq!(metrics["output_name"], "alpha");
+    assert_eq!(metrics["sample_rate"], 48000);
+    assert_eq!(metrics["channels"], 2);
+    assert_eq!(metrics["format"], "S24LE");
+    assert_eq!(metrics["latency_ms"], 25);
+    assert_eq!(metrics["streaming"], true);
+    assert_eq!(metrics["frames_written"], (INPUT_BLOCKS * BLOCK_FRAMES) as u64);
+    assert_eq!(metrics["bytes_written"], (INPUT_BLOCKS * BLOCK_FRAMES * 2 * 3) as u64);
+    assert_eq!(get(addr, "/v1/route").await["is_active"], true);
+
+    // The input has gone quiet for longer than the 20 ms buffer
+    let mut underruns = 0;
+    for _ in 0..100 {
+        underruns = get(addr, "/v1/outputs/metrics").await["underruns"].as_u64().unwrap();
+        if underruns > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(underruns >= 1);
+
+    // Starting again while streaming is a no-op
+    let (status, _) = post(addr, "/v1/outputs/start", json!({})).await;
+    assert_eq!(status, 200);
+    assert_eq!(input.devices.lock().unwrap().len(), 1);
+
+    // Selecting another output moves the flow over to it
+    let (status, _) = post(addr, "/v1/outputs/select", json!({"name": "beta", "config": config()})).await;
+    assert_eq!(status, 200);
+    wait_for("beta to receive the input", || {
+        beta_written.load(Ordering::SeqCst) == (INPUT_BLOCKS * BLOCK_FRAMES) as u64
+    })
+    .await;
+    assert_eq!(alpha_written.load(Ordering::SeqCst), (INPUT_BLOCKS * BLOCK_FRAMES) as u64);
+    let outputs = get(addr, "/v1/outputs").await;
+    assert_eq!(outputs["active"], "beta");
+    assert_eq!(outputs["outputs"][0]["is_open"], false);
+
+    // Stop: the input is stopped and the output closed
+    let (status, _) = post(addr, "/v1/outputs/stop", json!({})).await;
+    assert_eq!(status, 200);
+    wait_for("the input to stop", || !input.running.load(Ordering::SeqCst)).await;
+    let metrics = get(addr, "/v1/outputs/metrics").await;
+    assert_eq!(metrics["streaming"], false);
+    assert_eq!(metrics["output_name"], Value::Null);
+    let outputs = get(addr, "/v1/outputs").await;
+    assert_eq!(outputs["active"], Value::Null);
+    assert_eq!(outputs["outputs"][1]["is_open"], false);
+
+    // Start again reopens the last selected output
+    let (status, _) = post(addr, "/v1/outputs/start", json!({})).await;
+    assert_eq!(status, 200);
+    wait_for("beta to receive the input again", || {
+        beta_written.load(Ordering::SeqCst) == (2 * INPUT_BLOCKS * BLOCK_FRAMES) as u64
+    })
+    .await;
+    assert_eq!(get(addr, "/v1/outputs").await["active"], "beta");
+
+    // Stopping the server stops the flow
+    server.stop().await;
+    wait_for("the input to stop on shutdown", || !input.running.load(Ordering::SeqCst)).await;
+}
+
+type EventSocket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+async fn next_event(socket: &mut EventSocket) -> Value {
+    loop {
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("timed out waiting for an event")
+            .unwrap()
+            .unwrap();
+        if let Message::Text(text) = message {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_control_api_event_stream() {
+    let (alpha, _) = MockSink::new("alpha");
+    let manager = Arc::new(RwLock::new(OutputManager::new()));
+    manager.write().await.register_sink(Box::new(alpha));
+
+    let input = Arc::new(TestInput::default());
+    let server = ControlServer::new("127.0.0.1:0".parse().unwrap(), manager.clone());
+    let streamer = CaptureStreamer::new(manager.clone(), server.metrics())
+        .with_input(input.starter())
+        .with_events(server.events());
+    let mut server = server.with_stream_controller(Arc::new(streamer));
+    server.start().await.unwrap();
+    let addr = server.addr();
+    let events = server.events();
+
+    let (mut all, _) = tokio_tungstenite::connect_async(format!("ws://{}/v1/events", addr)).await.unwrap();
+    let (mut tracks, _) = tokio_tungstenite::connect_async(format!("ws://{}/v1/events?types=track_changed", addr))
+        .await
+        .unwrap();
+
+    // Output transitions
+    post(addr, "/v1/outputs/select", json!({"name": "alpha", "config": config()})).await;
+    let event = next_event(&mut all).await;
+    assert_eq!(event["type"], "sink_state");
+    assert_eq!(event["sink"], "alpha");
+    assert_eq!(event["state"], "opened");
+    assert_eq!(event["config"]["sample_rate"], 48000);
+
+    // Meters of the streamed audio: a constant 0.1 is -20 dBFS
+    post(addr, "/v1/outputs/start", json!({})).await;
+    let event = next_event(&mut all).await;
+    assert_eq!(event["type"], "meters");
+    let rms = event["rms_dbfs"][0].as_f64().unwrap();
+    assert!((rms - -20.0).abs() < 0.01, "{} dBFS", rms);
+    assert_eq!(event["peak_dbfs"].as_array().unwrap().len(), 2);
+    assert!(event["lufs_momentary"].is_number());
+
+    // Events published by whoever drives playback reach clients too
+    let track = TrackMeta {
+        artist: "Band".to_string(),
+        title: "Song".to_string(),
+        ..Default::default()
+    };
+    events.publish(StreamEvent::TrackChanged { track: track.clone() });
+    let event = next_event(&mut tracks).await;
+    assert_eq!(event["type"], "track_changed");
+    assert_eq!(event["track"]["title"], "Song");
+
+    // The filtered client only hears about tracks
+    post(addr, "/v1/outputs/stop", json!({})).await;
+    events.publish(StreamEvent::TrackChanged { track });
+    assert_eq!(next_event(&mut tracks).await["type"], "track_changed");
+
+    loop {
+        let event = next_event(&mut all).await;
+        if event["type"] == "sink_state" {
+            assert_eq!(event["state"], "closed");
+            break;
+        }
+    }
+
+    // Clients connecting later hear what's playing first
+    let (mut late, _) = tokio_tungstenite::connect_async(format!("ws://{}/v1/events", addr)).await.unwrap();
+    let event = next_event(&mut late).await;
+    assert_eq!(event["type"], "track_changed");
+    assert_eq!(event["track"]["artist"], "Band");
+
+    server.stop().await;
+}
+
+/// A 48 kHz stereo 16-bit WAV of silence
+fn write_silence(path: &std::path::Path, frames: u64) {
+    write_level(path, frames, 0);
+}
+
+/// A 48 kHz stereo 16-bit WAV holding `level` in every sample
+fn write_level(path: &std::path::Path, frames: u64, level: i16) {
+    let config = OutputConfig {
+        format: SampleFormat::S16LE,
+        ..Default::default()
+    };
+    let mut wav = dlna::create_wav_header(&config, frames as u32 * 4);
+    for _ in 0..frames * 2 {
+        wav.extend_from_slice(&level.to_le_bytes());
+    }
+    std::fs::write(path, wav).unwrap();
+}
+
+/// The samples of a 24-bit WAV recording, scaled to ±1.0
+fn read_s24(path: &std::path::Path) -> Vec<f64> {
+    let wav = std::fs::read(path).unwrap();
+    let data = wav.windows(4).position(|id| id == b"data").unwrap() + 8;
+    wav[data..]
+        .chunks_exact(3)
+        .map(|s| (i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8) as f64 / 8_388_608.0)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_control_api_file_input() {
+    let dir = std::env::temp_dir().join(format!("aaeq-control-file-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("Tone.wav");
+    let frames = 4800;
+    write_silence(&path, frames);
+
+    let (alpha, alpha_written) = MockSink::new("alpha");
+    let manager = Arc::new(RwLock::new(OutputManager::new()));
+    manager.write().await.register_sink(Box::new(alpha));
+    let mut server = ControlServer::new("127.0.0.1:0".parse().unwrap(), manager.clone());
+    server.start().await.unwrap();
+    let addr = server.addr();
+    let mut events = server.events().subscribe();
+
+    // The File input needs something to play
+    let (status, body) = post(addr, "/v1/route", json!({"input": "File", "output": "alpha"})).await;
+    assert_eq!(status, 400);
+    assert!(body["details"].as_str().unwrap().contains("files"));
+
+    let files = json!([path.to_string_lossy()]);
+    let (status, _) = post(addr, "/v1/route", json!({"input": "File", "output": "alpha", "files": files})).await;
+    assert_eq!(status, 200);
+    assert_eq!(get(addr, "/v1/route").await["files"], files);
+
+    // The default streamer plays the files and announces the track
+    post(addr, "/v1/outputs/select", json!({"name": "alpha", "config": config()})).await;
+    let (status, _) = post(addr, "/v1/outputs/start", json!({})).await;
+    assert_eq!(status, 200);
+    let track = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(StreamEvent::TrackChanged { track }) = events.recv().await {
+                return track;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(track.title, "Tone");
+    wait_for("alpha to receive the file", || alpha_written.load(Ordering::SeqCst) == frames).await;
+
+    server.stop().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_control_api_records_a_file_per_track() {
+    let dir = std::env::temp_dir().join(format!("aaeq-control-split-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let recordings = dir.join("recordings");
+    std::fs::create_dir_all(&dir).unwrap();
+    // Distinct levels show which track each frame came from
+    write_level(&dir.join("One.wav"), 2400, 0x1000);
+    write_level(&dir.join("Two.wav"), 2400, 0x2000);
+
+    let manager = Arc::new(RwLock::new(OutputManager::new()));
+    manager.write().await.register_sink(Box::new(FileSink::new(&recordings)));
+    let mut server = ControlServer::new("127.0.0.1:0".parse().unwrap(), manager.clone());
+    server.start().await.unwrap();
+    let addr = server.addr();
+
+    // The route turns splitting on for the file output and opens it
+    let files = json!([dir.join("One.wav").to_string_lossy(), dir.join("Two.wav").to_string_lossy()]);
+    let route = json!({"input": "File", "output": "file", "files": files, "split_tracks": true, "config": config()});
+    let (status, _) = post(addr, "/v1/route", route).await;
+    assert_eq!(status, 200);
+    assert_eq!(get(addr, "/v1/route").await["split_tracks"], true);
+
+    // Track changes from the playing files start new recordings
+    let (status, _) = post(addr, "/v1/outputs/start", json!({})).await;
+    assert_eq!(status, 200);
+    let second = recordings.join("002 - Two.wav");
+    wait_for("the second recording", || second.exists()).await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while get(addr, "/v1/route").await["is_active"] == true {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    post(addr, "/v1/outputs/stop", json!({})).await;
+    manager.write().await.close_active().await.unwrap();
+
+    let mut recorded: Vec<_> = std::fs::read_dir(&recordings).unwrap().map(|entry| entry.unwrap().path()).collect();
+    recorded.sort();
+    assert_eq!(recorded, [recordings.join("001 - One.wav"), second.clone()]);
+
+    // Each recording ends at the track boundary, to the frame
+    for (path, level) in [(&recorded[0], 0.125), (&second, 0.25)] {
+        let samples = read_s24(path);
+        assert_eq!(samples.len(), 2400 * 2, "{}", path.display());
+        assert!(samples.iter().all(|s| (s - level).abs() < 1e-4), "{}", path.display());
+    }
+
+    server.stop().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_control_api_serves_web_ui() {
+    let manager = Arc::new(RwLock::new(OutputManager::new()));
+    let mut server = ControlServer::new("127.0.0.1:0".parse().unwrap(), manager);
+    server.start().await.unwrap();
+    let addr = server.addr();
+
+    for (path, content_type, marker) in [
+        ("/", "text/html", "<title>AAEQ</title>"),
+        ("/ui/app.js", "text/javascript", "/v1/events"),
+        ("/ui/style.css", "text/css", "--accent"),
+    ] {
+        let response = reqwest::get(format!("http://{}{}", addr, path)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200, "{}", path);
+        let header = response.headers()["content-type"].to_str().unwrap().to_string();
+        assert!(header.starts_with(content_type), "{}: {}", path, header);
+        assert!(response.text().await.unwrap().contains(marker), "{}", path);
+    }
+    let missing = reqwest::get(format!("http://{}/ui/missing.js", addr)).await.unwrap();
+    assert_eq!(missing.status().as_u16(), 404);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_control_api_prometheus_metrics() {
+    let (alpha, alpha_written) = MockSink::new("alpha");
+    let manager = Arc::new(RwLock::new(OutputManager::new()));
+    manager.write().await.register_sink(Box::new(alpha));
+
+    let input = Arc::new(TestInput::default());
+    let server = ControlServer::new("127.0.0.1:0".parse().unwrap(), manager.clone());
+    let streamer = CaptureStreamer::new(manager.clone(), server.metrics()).with_input(input.starter());
+    let mut server = server.with_stream_controller(Arc::new(streamer));
+    server.start().await.unwrap();
+    let addr = server.addr();
+
+    post(addr, "/v1/outputs/select", json!({"name": "alpha", "config": config()})).await;
+    post(addr, "/v1/outputs/start", json!({})).await;
+    wait_for("alpha to receive the input", || {
+        alpha_written.load(Ordering::SeqCst) == (INPUT_BLOCKS * BLOCK_FRAMES) as u64
+    })
+    .await;
+
+    // Clips and preset switches come from the events; the rest is recorded directly
+    let events = server.events();
+    events.publish(StreamEvent::Clipping { clip_count: 4 });
+    events.publish(StreamEvent::PresetApplied {
+        preset: "Rock".to_string(),
+        rule: None,
+    });
+    let telemetry = server.telemetry();
+    telemetry.record_poll_failure("Living Room");
+    telemetry.record_discovery("dlna", Duration::from_millis(1500));
+    telemetry.set_resampler_latency(Some(21.0));
+
+    let scrape = || async {
+        let response = reqwest::get(format!("http://{}/metrics", addr)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let content_type = response.headers()["content-type"].to_str().unwrap().to_string();
+        assert!(content_type.starts_with("text/plain; version=0.0.4"), "{}", content_type);
+        response.text().await.unwrap()
+    };
+    let mut text = scrape().await;
+    for _ in 0..100 {
+        if text.contains("aaeq_preset_switches_total 1\n") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        text = scrape().await;
+    }
+    for line in [
+        "aaeq_streaming 1".to_string(),
+        format!("aaeq_sink_frames_written_total{{sink=\"alpha\"}} {}", INPUT_BLOCKS * BLOCK_FRAMES),
+        "aaeq_sink_active{sink=\"alpha\"} 1".to_string(),
+        "aaeq_sink_latency_seconds{sink=\"alpha\"} 0.025".to_string(),
+        "aaeq_sink_underruns_total{sink=\"alpha\"} 0".to_string(),
+        "aaeq_clips_total 4".to_string(),
+        "aaeq_preset_switches_total 1".to_string(),
+        "aaeq_resampler_latency_seconds 0.021".to_string(),
+        "aaeq_device_poll_failures_total{device=\"Living Room\"} 1".to_string(),
+        "aaeq_discovery_duration_seconds_bucket{protocol=\"dlna\",le=\"2.5\"} 1".to_string(),
+        "aaeq_discovery_duration_seconds_sum{protocol=\"dlna\"} 1.5".to_string(),
+    ] {
+        assert!(text.lines().any(|l| l == line), "missing {:?} in\n{}", line, text);
+    }
+
+    // OpenMetrics for scrapers that ask for it
+    let response = reqwest::Client::new()
+        .get(format!("http://{}/metrics", addr))
+        .header(reqwest::header::ACCEPT, "application/openmetrics-text; version=1.0.0")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("application/openmetrics-text"));
+    let text = response.text().await.unwrap();
+    assert!(text.contains("# TYPE aaeq_clips counter\n"));
+    assert!(text.ends_with("# EOF\n"));
+
+    server.stop().await;
+}
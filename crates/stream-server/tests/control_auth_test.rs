@@ -0,0 +1,204 @@
+//! Control API security tests: bearer tokens and their scopes, token
+//! management, CORS preflight and HTTPS with a generated certificate.
+
+use aaeq_core::TokenScope;
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use stream_server::*;
+use tokio::sync::RwLock;
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aaeq-control-auth-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn start_server(name: &str, configure: impl FnOnce(ControlServer, SqlitePool) -> ControlServer) -> (ControlServer, SqlitePool) {
+    let pool = aaeq_persistence::init_db(&test_dir(name).join("aaeq.db")).await.unwrap();
+    let manager = Arc::new(RwLock::new(OutputManager::new()));
+    let server = ControlServer::new("127.0.0.1:0".parse().unwrap(), manager).with_database(pool.clone());
+    let mut server = configure(server, pool.clone());
+    server.start().await.unwrap();
+    (server, pool)
+}
+
+async fn request(
+    addr: SocketAddr,
+    method: reqwest::Method,
+    path: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+) -> (u16, Value) {
+    let mut request = reqwest::Client::new().request(method, format!("http://{}{}", addr, path));
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    if let Some(body) = body {
+        request = request
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status().as_u16();
+    let text = response.text().await.unwrap();
+    (status, serde_json::from_str(&text).unwrap_or(Value::Null))
+}
+
+async fn get(addr: SocketAddr, path: &str, token: Option<&str>) -> u16 {
+    request(addr, reqwest::Method::GET, path, token, None).await.0
+}
+
+#[tokio::test]
+async fn test_token_scopes() {
+    let (mut server, pool) = start_server("scopes", |server, pool| server.with_auth(pool)).await;
+    let addr = server.addr();
+
+    let reader = issue_token(&pool, "dashboard", TokenScope::Read).await.unwrap();
+    let controller = issue_token(&pool, "phone", TokenScope::Control).await.unwrap();
+
+    // Health and the web UI's files stay open; everything else needs a valid token
+    assert_eq!(get(addr, "/v1/health", None).await, 200);
+    assert_eq!(get(addr, "/", None).await, 200);
+    let response = reqwest::get(format!("http://{}/v1/outputs", addr)).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(response.headers()["www-authenticate"], "Bearer");
+    assert_eq!(get(addr, "/v1/outputs", Some("aaeq_not-a-token")).await, 401);
+
+    // Read tokens can read but not change anything
+    let route = json!({"input": "file", "output": "file"});
+    assert_eq!(get(addr, "/v1/outputs", Some(&reader.token)).await, 200);
+    assert_eq!(get(addr, "/v1/profiles", Some(&reader.token)).await, 200);
+    let (status, _) = request(addr, reqwest::Method::POST, "/v1/route", Some(&reader.token), Some(route.clone())).await;
+    assert_eq!(status, 403);
+    assert_eq!(get(addr, "/v1/tokens", Some(&reader.token)).await, 403);
+
+    // Control tokens can do both
+    let (status, _) = request(addr, reqwest::Method::POST, "/v1/route", Some(&controller.token), Some(route)).await;
+    assert_eq!(status, 200);
+
+    let (status, tokens) = request(addr, reqwest::Method::GET, "/v1/tokens", Some(&controller.token), None).await;
+    assert_eq!(status, 200);
+    let tokens = tokens.as_array().unwrap();
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens[0]["name"], "dashboard");
+    assert_eq!(tokens[0]["scope"], "read");
+    assert!(tokens[0]["last_used_at"].is_i64());
+    // Only hashes are stored, and they're never served
+    assert!(tokens[0].get("token").is_none());
+    assert!(!tokens.iter().any(|t| t.to_string().contains(&reader.token)));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_token_management() {
+    let (mut server, pool) = start_server("manage", |server, pool| server.with_auth(pool)).await;
+    let addr = server.addr();
+    let admin = issue_token(&pool, "admin", TokenScope::Control).await.unwrap();
+
+    let (status, issued) = request(
+        addr,
+        reqwest::Method::POST,
+        "/v1/tokens",
+        Some(&admin.token),
+        Some(json!({"name": "kiosk", "scope": "read"})),
+    )
+    .await;
+    assert_eq!(status, 201);
+    assert_eq!(issued["scope"], "read");
+    let kiosk = issued["token"].as_str().unwrap().to_string();
+    let kiosk_id = issued["id"].as_i64().unwrap();
+    assert_eq!(get(addr, "/v1/outputs", Some(&kiosk)).await, 200);
+
+    let bad = json!({"name": "x", "scope": "admin"});
+    let (status, _) = request(addr, reqwest::Method::POST, "/v1/tokens", Some(&admin.token), Some(bad)).await;
+    assert_eq!(status, 422);
+    let blank = json!({"name": " ", "scope": "read"});
+    let (status, _) = request(addr, reqwest::Method::POST, "/v1/tokens", Some(&admin.token), Some(blank)).await;
+    assert_eq!(status, 400);
+
+    // Revoked tokens stop working at once, and stay listed
+    let path = format!("/v1/tokens/{}", kiosk_id);
+    assert_eq!(request(addr, reqwest::Method::DELETE, &path, Some(&admin.token), None).await.0, 200);
+    assert_eq!(get(addr, "/v1/outputs", Some(&kiosk)).await, 401);
+    assert_eq!(request(addr, reqwest::Method::DELETE, &path, Some(&admin.token), None).await.0, 404);
+    let (_, tokens) = request(addr, reqwest::Method::GET, "/v1/tokens", Some(&admin.token), None).await;
+    assert!(tokens[1]["revoked_at"].is_i64());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_event_stream_token_parameter() {
+    let (mut server, pool) = start_server("events", |server, pool| server.with_auth(pool)).await;
+    let addr = server.addr();
+    let reader = issue_token(&pool, "browser", TokenScope::Read).await.unwrap();
+
+    // Browsers can't set headers on WebSockets, so the token can go in the query
+    assert!(tokio_tungstenite::connect_async(format!("ws://{}/v1/events", addr)).await.is_err());
+    let url = format!("ws://{}/v1/events?types=meters&access_token={}", addr, reader.token);
+    assert!(tokio_tungstenite::connect_async(url).await.is_ok());
+
+    // Only there; elsewhere a token in the URL would end up in logs and history
+    let url = format!("http://{}/v1/outputs?access_token={}", addr, reader.token);
+    assert_eq!(reqwest::get(url).await.unwrap().status().as_u16(), 401);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_cors_preflight() {
+    let (mut server, _) = start_server("cors", |server, pool| {
+        server.with_auth(pool).with_cors(vec!["http://ui.example".to_string()])
+    })
+    .await;
+    let addr = server.addr();
+
+    let preflight = |origin: &'static str| {
+        reqwest::Client::new()
+            .request(reqwest::Method::OPTIONS, format!("http://{}/v1/dsp", addr))
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", "PATCH")
+            .header("Access-Control-Request-Headers", "authorization, content-type")
+            .send()
+    };
+
+    // Answered without a token
+    let response = preflight("http://ui.example").await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["access-control-allow-origin"], "http://ui.example");
+    let methods = response.headers()["access-control-allow-methods"].to_str().unwrap().to_string();
+    assert!(methods.contains("PATCH"));
+
+    let response = preflight("http://elsewhere.example").await.unwrap();
+    assert!(response.headers().get("access-control-allow-origin").is_none());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_https_with_generated_certificate() {
+    let dir = test_dir("tls");
+    let tls = TlsConfig::self_signed(&dir, &[]).unwrap();
+    let cert = std::fs::read(&tls.cert_path).unwrap();
+
+    let (mut server, _) = start_server("tls-db", |server, _| server.with_tls(tls)).await;
+    let port = server.addr().port();
+
+    // Clients trust the generated certificate like any other
+    let client = reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(&cert).unwrap())
+        .build()
+        .unwrap();
+    let response = client.get(format!("https://localhost:{}/v1/health", port)).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Plain HTTP is not served
+    assert!(reqwest::get(format!("http://localhost:{}/v1/health", port)).await.is_err());
+
+    server.stop().await;
+}
@@ -0,0 +1,226 @@
+//! DLNA renderer tests: drive the renderer like a control point would, over
+//! SOAP and GENA on localhost, and check what comes out of the audio channel.
+
+use axum::{body::Bytes, http::HeaderMap, routing::any, Router};
+use std::net::SocketAddr;
+use std::time::Duration;
+use stream_server::*;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+const RENDERING_CONTROL: &str = "urn:schemas-upnp-org:service:RenderingControl:1";
+
+fn config() -> OutputConfig {
+    OutputConfig {
+        sample_rate: 48000,
+        channels: 2,
+        format: SampleFormat::F32,
+        buffer_ms: 150,
+        exclusive: false,
+    }
+}
+
+/// 16-bit stereo WAV with every sample at `value`
+fn wav(frames: usize, value: i16) -> Vec<u8> {
+    let data: Vec<u8> = std::iter::repeat_n(value.to_le_bytes(), frames * 2).flatten().collect();
+    let mut wav = b"RIFF".to_vec();
+    wav.extend_from_slice(&((36 + data.len()) as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&48000u32.to_le_bytes());
+    wav.extend_from_slice(&(48000u32 * 4).to_le_bytes());
+    wav.extend_from_slice(&4u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&data);
+    wav
+}
+
+/// Serve `body` as `/track.wav` and return its URL
+async fn serve_track(body: Vec<u8>) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new().route(
+        "/track.wav",
+        axum::routing::get(move || async move { ([("content-type", "audio/wav")], body) }),
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/track.wav", addr)
+}
+
+async fn start_renderer() -> (
+    DlnaRendererHandle,
+    mpsc::Receiver<Vec<f64>>,
+    mpsc::UnboundedReceiver<DlnaRendererEvent>,
+) {
+    let (tx, rx) = mpsc::channel(16);
+    let (events_tx, events_rx) = mpsc::unbounded_channel();
+    let renderer = DlnaRenderer::new("Test Renderer".to_string(), "127.0.0.1:0".parse().unwrap())
+        .with_advertise(false)
+        .start(config(), tx, events_tx)
+        .await
+        .unwrap();
+    (renderer, rx, events_rx)
+}
+
+/// Invoke an action; returns the HTTP status and response body
+async fn invoke(addr: SocketAddr, service: &str, action: &str, args: &[(&str, &str)]) -> (u16, String) {
+    let service_type = if service == "AVTransport" { AV_TRANSPORT } else { RENDERING_CONTROL };
+    let args: String = args
+        .iter()
+        .map(|(name, value)| format!("<{name}>{value}</{name}>"))
+        .collect();
+    let body = format!(
+        r#"<?xml version="1.0"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><u:{action} xmlns:u="{service_type}"><InstanceID>0</InstanceID>{args}</u:{action}></s:Body></s:Envelope>"#
+    );
+    let response = reqwest::Client::new()
+        .post(format!("http://{}/upnp/control/{}", addr, service))
+        .header("SOAPACTION", format!("\"{}#{}\"", service_type, action))
+        .header("content-type", "text/xml; charset=\"utf-8\"")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    (response.status().as_u16(), response.text().await.unwrap())
+}
+
+async fn transport_state(addr: SocketAddr) -> String {
+    let (_, body) = invoke(addr, "AVTransport", "GetTransportInfo", &[]).await;
+    let start = body.find("<CurrentTransportState>").unwrap() + "<CurrentTransportState>".len();
+    body[start..start + body[start..].find('<').unwrap()].to_string()
+}
+
+#[tokio::test]
+async fn test_plays_uri_with_didl_metadata() {
+    let url = serve_track(wav(9600, 16384)).await;
+    let (renderer, mut rx, mut events) = start_renderer().await;
+    let addr = renderer.local_addr();
+
+    let description = reqwest::get(format!("http://{}/device.xml", addr)).await.unwrap().text().await.unwrap();
+    assert!(description.contains("urn:schemas-upnp-org:device:MediaRenderer:1"));
+    assert!(description.contains("Test Renderer"));
+
+    let didl = r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/"><item id="1" parentID="0" restricted="1"><dc:title>So What</dc:title><upnp:artist>Miles Davis</upnp:artist><upnp:genre>Jazz</upnp:genre><upnp:class>object.item.audioItem.musicTrack</upnp:class></item></DIDL-Lite>"#;
+    let escaped = didl.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+    let (status, _) = invoke(
+        addr,
+        "AVTransport",
+        "SetAVTransportURI",
+        &[("CurrentURI", &url), ("CurrentURIMetaData", &escaped)],
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(transport_state(addr).await, "STOPPED");
+
+    // Half volume is a quarter of the amplitude with the squared taper
+    assert_eq!(invoke(addr, "RenderingControl", "SetVolume", &[("Channel", "Master"), ("DesiredVolume", "50")]).await.0, 200);
+    assert_eq!(invoke(addr, "AVTransport", "Play", &[("Speed", "1")]).await.0, 200);
+
+    let mut frames = 0;
+    while frames < 9600 {
+        let block = timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+        assert!(block.iter().all(|s| (s - 0.125).abs() < 1e-6));
+        frames += block.len() / 2;
+    }
+    assert_eq!(frames, 9600);
+
+    let mut track = None;
+    let mut stopped = false;
+    while !stopped {
+        match timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap() {
+            DlnaRendererEvent::TrackChanged { uri, meta } => track = Some((uri, meta)),
+            DlnaRendererEvent::TransportStateChanged(TransportState::Stopped) => stopped = track.is_some(),
+            _ => {}
+        }
+    }
+    let (uri, meta) = track.unwrap();
+    assert_eq!(uri, url);
+    assert_eq!(meta.title, "So What");
+    assert_eq!(meta.artist, "Miles Davis");
+    assert_eq!(meta.genre, "Jazz");
+    assert_eq!(renderer.state(), TransportState::Stopped);
+    assert_eq!(transport_state(addr).await, "STOPPED");
+
+    renderer.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_errors_are_soap_faults() {
+    let (renderer, _rx, _events) = start_renderer().await;
+    let addr = renderer.local_addr();
+
+    let (status, body) = invoke(addr, "AVTransport", "Play", &[("Speed", "1")]).await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>701</errorCode>"));
+
+    let (status, body) = invoke(addr, "RenderingControl", "SetVolume", &[("Channel", "Master"), ("DesiredVolume", "150")]).await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>402</errorCode>"));
+
+    let (status, body) = invoke(addr, "AVTransport", "Record", &[]).await;
+    assert_eq!(status, 500);
+    assert!(body.contains("<errorCode>401</errorCode>"));
+
+    let (status, body) = invoke(addr, "RenderingControl", "GetVolume", &[("Channel", "Master")]).await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<CurrentVolume>100</CurrentVolume>"));
+
+    renderer.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_volume_changes_are_evented() {
+    let (renderer, _rx, mut events) = start_renderer().await;
+    let addr = renderer.local_addr();
+
+    // Control point's event callback
+    let (notify_tx, mut notify_rx) = mpsc::unbounded_channel();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let callback = format!("http://{}/event", listener.local_addr().unwrap());
+    let app = Router::new().route(
+        "/event",
+        any(move |headers: HeaderMap, body: Bytes| {
+            let notify_tx = notify_tx.clone();
+            async move {
+                let seq = headers.get("SEQ").unwrap().to_str().unwrap().to_string();
+                let _ = notify_tx.send((seq, String::from_utf8_lossy(&body).into_owned()));
+            }
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let response = reqwest::Client::new()
+        .request(
+            reqwest::Method::from_bytes(b"SUBSCRIBE").unwrap(),
+            format!("http://{}/upnp/event/RenderingControl", addr),
+        )
+        .header("CALLBACK", format!("<{}>", callback))
+        .header("NT", "upnp:event")
+        .header("TIMEOUT", "Second-300")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.headers()["SID"].to_str().unwrap().starts_with("uuid:"));
+
+    let (seq, body) = timeout(Duration::from_secs(5), notify_rx.recv()).await.unwrap().unwrap();
+    assert_eq!(seq, "0");
+    assert!(body.contains("val=&quot;100&quot;"));
+
+    invoke(addr, "RenderingControl", "SetMute", &[("Channel", "Master"), ("DesiredMute", "1")]).await;
+    let (seq, body) = timeout(Duration::from_secs(5), notify_rx.recv()).await.unwrap().unwrap();
+    assert_eq!(seq, "1");
+    assert!(body.contains("Mute channel=&quot;Master&quot; val=&quot;1&quot;"));
+
+    assert_eq!(
+        timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap(),
+        DlnaRendererEvent::VolumeChanged { volume: 100, muted: true }
+    );
+    assert_eq!(renderer.volume(), (100, true));
+
+    renderer.stop().await.unwrap();
+}
@@ -0,0 +1,174 @@
+//! File input tests: decode generated WAV/FLAC files offline and check that
+//! tracks join without gaps and carry their tags.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use stream_server::dlna::FlacEncoder;
+use stream_server::*;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn output_config(sample_rate: u32) -> OutputConfig {
+    OutputConfig {
+        sample_rate,
+        channels: 2,
+        format: SampleFormat::S16LE,
+        buffer_ms: 150,
+        exclusive: false,
+    }
+}
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aaeq-file-input-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Interleaved stereo 16-bit ramp starting at `offset`
+fn ramp(frames: usize, offset: usize) -> Vec<i16> {
+    (0..frames * 2)
+        .map(|i| (((offset * 2 + i) % 2000) as i16 - 1000) * 16)
+        .collect()
+}
+
+/// Write a 16-bit stereo WAV with RIFF INFO tags
+fn write_wav(path: &Path, sample_rate: u32, samples: &[i16], tags: &[(&[u8; 4], &str)]) {
+    let mut info = b"INFO".to_vec();
+    for (id, value) in tags {
+        let mut text = value.as_bytes().to_vec();
+        text.push(0);
+        if text.len() % 2 == 1 {
+            text.push(0);
+        }
+        info.extend_from_slice(*id);
+        info.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        info.extend_from_slice(&text);
+    }
+
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let mut wav = b"RIFF".to_vec();
+    wav.extend_from_slice(&((4 + 24 + 8 + info.len() + 8 + data.len()) as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 4).to_le_bytes());
+    wav.extend_from_slice(&4u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"LIST");
+    wav.extend_from_slice(&(info.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&info);
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&data);
+    std::fs::write(path, wav).unwrap();
+}
+
+fn write_flac(path: &Path, sample_rate: u32, samples: &[i16]) {
+    let mut encoder = FlacEncoder::new(sample_rate, 2, 16);
+    let samples: Vec<i32> = samples.iter().map(|&s| s as i32).collect();
+    let mut flac = encoder.header();
+    flac.extend(encoder.encode(&samples));
+    flac.extend(encoder.flush());
+    std::fs::write(path, flac).unwrap();
+}
+
+/// Play to the end and collect all audio and events
+async fn play(input: FileInput, cfg: OutputConfig) -> (Vec<f64>, Vec<FileInputEvent>) {
+    let (tx, mut rx) = mpsc::channel(16);
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+    let _stop = input.with_realtime(false).start_playback(cfg, tx, events_tx).unwrap();
+
+    let mut audio = Vec::new();
+    while let Some(block) = timeout(RECEIVE_TIMEOUT, rx.recv()).await.expect("playback stalled") {
+        audio.extend(block);
+    }
+
+    let mut events = Vec::new();
+    while let Ok(event) = events_rx.try_recv() {
+        events.push(event);
+    }
+    (audio, events)
+}
+
+#[tokio::test]
+async fn test_tracks_join_gaplessly_with_tags() {
+    let dir = test_dir("gapless");
+    let first = ramp(3000, 0);
+    let second = ramp(2500, 3000);
+    write_wav(
+        &dir.join("01.wav"),
+        48000,
+        &first,
+        &[(b"IART", "Miles Davis"), (b"INAM", "So What"), (b"IPRD", "Kind of Blue"), (b"IGNR", "Jazz")],
+    );
+    write_flac(&dir.join("02.flac"), 48000, &second);
+
+    let input = FileInput::new(&[&dir]).unwrap();
+    assert_eq!(input.tracks().len(), 2);
+    let (audio, events) = play(input, output_config(48000)).await;
+
+    // Every sample comes through, in order, with nothing inserted at the join
+    let expected: Vec<f64> = first.iter().chain(&second).map(|&s| s as f64 / 32768.0).collect();
+    assert_eq!(audio.len(), expected.len());
+    for (i, (a, e)) in audio.iter().zip(&expected).enumerate() {
+        assert!((a - e).abs() < 1e-6, "sample {} differs: {} vs {}", i, a, e);
+    }
+
+    let FileInputEvent::TrackStarted { index: 0, meta, position: 0, .. } = &events[0] else {
+        panic!("unexpected first event {:?}", events[0]);
+    };
+    assert_eq!(meta.artist, "Miles Davis");
+    assert_eq!(meta.title, "So What");
+    assert_eq!(meta.album, "Kind of Blue");
+    assert_eq!(meta.genre, "Jazz");
+
+    let FileInputEvent::TrackStarted { index: 1, meta, position, .. } = &events[1] else {
+        panic!("unexpected second event {:?}", events[1]);
+    };
+    assert_eq!(*position, 3000);
+    assert_eq!(meta.title, "02");
+    assert_eq!(events[2], FileInputEvent::Finished { frames: 5500 });
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_resamples_and_skips_unreadable_tracks() {
+    let dir = test_dir("resample");
+    write_wav(&dir.join("a.wav"), 44100, &ramp(44100, 0), &[]);
+    std::fs::write(dir.join("b.mp3"), b"not an mp3").unwrap();
+    std::fs::write(dir.join("list.m3u"), "a.wav\nb.mp3\n").unwrap();
+
+    let input = FileInput::new(&[dir.join("list.m3u")]).unwrap();
+    let (audio, events) = play(input, output_config(48000)).await;
+
+    // One second at 44.1 kHz becomes about one second at 48 kHz
+    let frames = audio.len() / 2;
+    assert!((47000..=50000).contains(&frames), "got {} frames", frames);
+
+    assert!(matches!(&events[1], FileInputEvent::TrackFailed { index: 1, .. }));
+    assert!(matches!(events[2], FileInputEvent::Finished { .. }));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_read_track_meta() {
+    let dir = test_dir("meta");
+    let path = dir.join("track.wav");
+    write_wav(&path, 44100, &ramp(100, 0), &[(b"IART", "Daft Punk"), (b"INAM", "Get Lucky")]);
+
+    let meta = read_track_meta(&path).unwrap();
+    assert_eq!(meta.song_key(), aaeq_core::TrackMeta {
+        artist: "Daft Punk".to_string(),
+        title: "Get Lucky".to_string(),
+        ..Default::default()
+    }.song_key());
+    assert!(read_track_meta(&dir.join("missing.flac")).is_err());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
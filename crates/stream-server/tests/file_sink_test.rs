@@ -0,0 +1,146 @@
+//! File sink tests: record through the `OutputSink` interface and read the
+//! files back with the file input's decoder.
+
+use std::path::{Path, PathBuf};
+use stream_server::dlna::MediaMetadata;
+use stream_server::*;
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aaeq-file-sink-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn config(format: SampleFormat) -> OutputConfig {
+    OutputConfig {
+        sample_rate: 48000,
+        channels: 2,
+        format,
+        buffer_ms: 150,
+        exclusive: false,
+    }
+}
+
+fn metadata(artist: &str, title: &str) -> MediaMetadata {
+    MediaMetadata {
+        title: title.to_string(),
+        artist: Some(artist.to_string()),
+        ..Default::default()
+    }
+}
+
+async fn write_frames(sink: &mut FileSink, frames: usize, value: f64) {
+    let samples = vec![value; frames * 2];
+    for block in samples.chunks(960 * 2) {
+        sink.write(AudioBlock::new(block, 48000, 2)).await.unwrap();
+    }
+}
+
+fn frames_in(path: &Path) -> Option<u64> {
+    read_track_info(path).unwrap().frames
+}
+
+#[tokio::test]
+async fn test_wav_recording_round_trips() {
+    let dir = test_dir("wav");
+    for (format, name) in [(SampleFormat::S16LE, "s16.wav"), (SampleFormat::S24LE, "s24.wav"), (SampleFormat::F32, "f32.wav")] {
+        let path = dir.join(name);
+        let mut sink = FileSink::new(&path);
+        sink.open(config(format)).await.unwrap();
+        write_frames(&mut sink, 4800, 0.25).await;
+        assert_eq!(sink.stats().frames_written, 4800);
+        sink.close().await.unwrap();
+
+        let info = read_track_info(&path).unwrap();
+        assert_eq!(info.sample_rate, 48000);
+        assert_eq!(info.frames, Some(4800), "{}", name);
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_flac_split_per_track() {
+    let dir = test_dir("split");
+    let mut sink = FileSink::new(&dir).with_format(FileFormat::Flac).with_split_tracks(true);
+    sink.set_metadata(metadata("Miles Davis", "So What")).unwrap();
+    sink.open(config(SampleFormat::S24LE)).await.unwrap();
+    write_frames(&mut sink, 3000, 0.5).await;
+
+    // Same track again doesn't split
+    sink.set_metadata(metadata("Miles Davis", "So What")).unwrap();
+    write_frames(&mut sink, 1000, 0.5).await;
+
+    sink.set_metadata(metadata("AC/DC", "T.N.T.")).unwrap();
+    write_frames(&mut sink, 2000, -0.5).await;
+    sink.close().await.unwrap();
+
+    let files = sink.files().to_vec();
+    assert_eq!(files, vec![dir.join("001 - Miles Davis - So What.flac"), dir.join("002 - AC_DC - T.N.T.flac")]);
+    assert_eq!(frames_in(&files[0]), Some(4000));
+    assert_eq!(frames_in(&files[1]), Some(2000));
+    assert_eq!(sink.stats().frames_written, 6000);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_split_follows_the_manager_track() {
+    let dir = test_dir("manager");
+    let track = |artist: &str, title: &str| aaeq_core::TrackMeta {
+        artist: artist.to_string(),
+        title: title.to_string(),
+        ..Default::default()
+    };
+    let mut manager = OutputManager::new();
+    manager.register_sink(Box::new(FileSink::new(&dir)));
+    assert!(manager.set_split_tracks("file", true));
+    assert!(!manager.set_split_tracks("dac", true));
+
+    // A track known before the output opens names its first file
+    manager.set_track(track("Miles Davis", "So What")).await.unwrap();
+    manager.select_sink_by_name("file", config(SampleFormat::S16LE)).await.unwrap();
+    let samples = vec![0.25; 960 * 2];
+    manager.write(AudioBlock::new(&samples, 48000, 2)).await.unwrap();
+
+    // The same track again is ignored, a new one starts the next file
+    manager.set_track(track("Miles Davis", "So What")).await.unwrap();
+    manager.set_track(track("Bill Evans", "Peace Piece")).await.unwrap();
+    manager.write(AudioBlock::new(&samples, 48000, 2)).await.unwrap();
+    manager.close_active().await.unwrap();
+
+    let mut files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    files.sort();
+    assert_eq!(files, [dir.join("001 - Miles Davis - So What.wav"), dir.join("002 - Bill Evans - Peace Piece.wav")]);
+    assert_eq!(frames_in(&files[0]), Some(960));
+    assert_eq!(frames_in(&files[1]), Some(960));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_w64_header_sizes() {
+    let dir = test_dir("w64");
+    let path = dir.join("capture.w64");
+    let mut sink = FileSink::new(&path);
+    assert_eq!(sink.format(), FileFormat::W64);
+    sink.open(config(SampleFormat::S24LE)).await.unwrap();
+    write_frames(&mut sink, 101, 0.1).await;
+    sink.close().await.unwrap();
+
+    // 101 stereo 24-bit frames = 606 data bytes, padded to 608
+    let bytes = std::fs::read(&path).unwrap();
+    assert_eq!(bytes.len(), 104 + 608);
+    assert_eq!(&bytes[0..4], b"riff");
+    assert_eq!(u64::from_le_bytes(bytes[16..24].try_into().unwrap()), 712);
+    assert_eq!(&bytes[80..84], b"data");
+    assert_eq!(u64::from_le_bytes(bytes[96..104].try_into().unwrap()), 24 + 606);
+    // WAVEFORMAT: PCM, 2 channels, 48 kHz, 24 bits
+    assert_eq!(u16::from_le_bytes([bytes[64], bytes[65]]), 1);
+    assert_eq!(u16::from_le_bytes([bytes[66], bytes[67]]), 2);
+    assert_eq!(u32::from_le_bytes(bytes[68..72].try_into().unwrap()), 48000);
+    assert_eq!(u16::from_le_bytes([bytes[78], bytes[79]]), 24);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,275 @@
+//! Control API library tests: profiles, mappings, presets, DSP settings and
+//! devices served from a fresh database, with a recording profile controller.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use stream_server::*;
+use tokio::sync::RwLock;
+
+/// Records what the API asked it to do
+#[derive(Default)]
+struct TestController {
+    profiles: Mutex<Vec<i64>>,
+    presets: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl ProfileController for TestController {
+    async fn activate_profile(&self, profile_id: i64) -> Result<()> {
+        self.profiles.lock().unwrap().push(profile_id);
+        Ok(())
+    }
+
+    async fn apply_preset(&self, preset: &str) -> Result<String> {
+        if preset == "Broken" {
+            return Err(anyhow!("no such preset"));
+        }
+        self.presets.lock().unwrap().push(preset.to_string());
+        Ok(preset.to_string())
+    }
+}
+
+async fn request(addr: SocketAddr, method: reqwest::Method, path: &str, body: Option<Value>) -> (u16, Value) {
+    let mut request = reqwest::Client::new().request(method, format!("http://{}{}", addr, path));
+    if let Some(body) = body {
+        request = request
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status().as_u16();
+    let text = response.text().await.unwrap();
+    (status, serde_json::from_str(&text).unwrap_or(Value::Null))
+}
+
+async fn get(addr: SocketAddr, path: &str) -> (u16, Value) {
+    request(addr, reqwest::Method::GET, path, None).await
+}
+
+async fn post(addr: SocketAddr, path: &str, body: Value) -> (u16, Value) {
+    request(addr, reqwest::Method::POST, path, Some(body)).await
+}
+
+async fn put(addr: SocketAddr, path: &str, body: Value) -> (u16, Value) {
+    request(addr, reqwest::Method::PUT, path, Some(body)).await
+}
+
+async fn patch(addr: SocketAddr, path: &str, body: Value) -> (u16, Value) {
+    request(addr, reqwest::Method::PATCH, path, Some(body)).await
+}
+
+async fn delete(addr: SocketAddr, path: &str) -> (u16, Value) {
+    request(addr, reqwest::Method::DELETE, path, None).await
+}
+
+async fn start_server(name: &str, controller: Option<Arc<TestController>>) -> ControlServer {
+    let dir = std::env::temp_dir().join(format!("aaeq-library-api-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let pool = aaeq_persistence::init_db(&dir.join("aaeq.db")).await.unwrap();
+
+    let manager = Arc::new(RwLock::new(OutputManager::new()));
+    let mut server = ControlServer::new("127.0.0.1:0".parse().unwrap(), manager).with_database(pool);
+    if let Some(controller) = controller {
+        server = server.with_profile_controller(controller);
+    }
+    server.start().await.unwrap();
+    server
+}
+
+#[tokio::test]
+async fn test_profiles_and_mappings() {
+    let controller = Arc::new(TestController::default());
+    let mut server = start_server("profiles", Some(controller.clone())).await;
+    let addr = server.addr();
+
+    let (status, profiles) = get(addr, "/v1/profiles").await;
+    assert_eq!(status, 200);
+    assert_eq!(profiles["active_id"], 1);
+    assert_eq!(profiles["profiles"].as_array().unwrap().len(), 2);
+
+    // Create, with defaults for icon and color; names are unique
+    let (status, car) = post(addr, "/v1/profiles", json!({"name": "Car"})).await;
+    assert_eq!(status, 201);
+    assert_eq!(car["icon"], "📁");
+    let car_id = car["id"].as_i64().unwrap();
+    assert_eq!(post(addr, "/v1/profiles", json!({"name": "Car"})).await.0, 409);
+    assert_eq!(post(addr, "/v1/profiles", json!({"name": "  "})).await.0, 400);
+
+    let (status, renamed) = put(addr, &format!("/v1/profiles/{}", car_id), json!({"name": "Road Trip"})).await;
+    assert_eq!(status, 200);
+    assert_eq!(renamed["name"], "Road Trip");
+    assert_eq!(put(addr, "/v1/profiles/1", json!({"name": "Mine"})).await.0, 409);
+    assert_eq!(delete(addr, "/v1/profiles/2").await.0, 409);
+    assert_eq!(get(addr, "/v1/profiles/999").await.0, 404);
+
+    // Activate tells the controller
+    let (status, _) = post(addr, &format!("/v1/profiles/{}/activate", car_id), json!({})).await;
+    assert_eq!(status, 200);
+    assert_eq!(get(addr, "/v1/profiles").await.1["active_id"], car_id);
+    assert_eq!(*controller.profiles.lock().unwrap(), [car_id]);
+
+    // Mappings default to the active profile; keys are normalized
+    let (status, mapping) = post(
+        addr,
+        "/v1/mappings",
+        json!({"scope": "song", "key": "Daft Punk - One More Time", "preset": "Rock"}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(mapping["profile_id"], car_id);
+    assert_eq!(mapping["key_normalized"], "daft punk - one more time");
+    let song_id = mapping["id"].as_i64().unwrap();
+    assert_eq!(post(addr, "/v1/mappings", json!({"scope": "genre", "preset": "Rock"})).await.0, 400);
+    assert_eq!(
+        post(addr, "/v1/mappings", json!({"scope": "default", "key": "x", "preset": "Rock"})).await.0,
+        400
+    );
+
+    // The default rule is replaced, not duplicated
+    post(addr, "/v1/mappings", json!({"scope": "default", "preset": "Rock"})).await;
+    post(addr, "/v1/mappings", json!({"scope": "default", "preset": "Jazz"})).await;
+    let (_, mappings) = get(addr, "/v1/mappings").await;
+    let mappings = mappings.as_array().unwrap();
+    assert_eq!(mappings.len(), 2);
+    assert!(mappings.iter().any(|m| m["scope"] == "default" && m["preset_name"] == "Jazz"));
+    assert_eq!(get(addr, "/v1/mappings?profile_id=1").await.1.as_array().unwrap().len(), 0);
+
+    assert_eq!(delete(addr, &format!("/v1/mappings/{}", song_id)).await.0, 200);
+    assert_eq!(delete(addr, &format!("/v1/mappings/{}", song_id)).await.0, 404);
+
+    // Deleting the active profile falls back to the default one
+    let (status, _) = delete(addr, &format!("/v1/profiles/{}", car_id)).await;
+    assert_eq!(status, 200);
+    assert_eq!(get(addr, "/v1/profiles").await.1["active_id"], 1);
+    assert_eq!(*controller.profiles.lock().unwrap(), [car_id, 1]);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_presets() {
+    let controller = Arc::new(TestController::default());
+    let mut server = start_server("presets", Some(controller.clone())).await;
+    let addr = server.addr();
+
+    let (status, presets) = get(addr, "/v1/presets").await;
+    assert_eq!(status, 200);
+    assert!(presets["builtin"].as_array().unwrap().contains(&json!("Rock")));
+    assert_eq!(presets["custom"], json!([]));
+    assert_eq!(get(addr, "/v1/presets/Rock").await.1["name"], "Rock");
+    assert_eq!(get(addr, "/v1/presets/Nope").await.0, 404);
+
+    // Custom presets: bands are validated, built-in names are taken
+    let bands = json!([{"frequency": 100, "gain": 3.0}, {"frequency": 8000, "gain": -2.5}]);
+    let (status, preset) = put(addr, "/v1/presets/Mine", json!({"bands": bands})).await;
+    assert_eq!(status, 201);
+    assert_eq!(preset["bands"][1]["gain"], -2.5);
+    assert_eq!(put(addr, "/v1/presets/Mine", json!({"bands": bands})).await.0, 200);
+    assert_eq!(put(addr, "/v1/presets/Rock", json!({"bands": bands})).await.0, 409);
+    let loud = json!([{"frequency": 100, "gain": 20.0}]);
+    assert_eq!(put(addr, "/v1/presets/Loud", json!({"bands": loud})).await.0, 400);
+    assert_eq!(get(addr, "/v1/presets").await.1["custom"], json!(["Mine"]));
+
+    // Deleting a custom preset moves its mappings to Flat
+    post(addr, "/v1/mappings", json!({"scope": "genre", "key": "Jazz", "preset": "Mine"})).await;
+    assert_eq!(delete(addr, "/v1/presets/Rock").await.0, 409);
+    assert_eq!(delete(addr, "/v1/presets/Mine").await.0, 200);
+    assert_eq!(delete(addr, "/v1/presets/Mine").await.0, 404);
+    assert_eq!(get(addr, "/v1/mappings").await.1[0]["preset_name"], "Flat");
+
+    // Apply goes through the controller
+    assert_eq!(post(addr, "/v1/presets/Rock/apply", json!({})).await.0, 200);
+    assert_eq!(*controller.presets.lock().unwrap(), ["Rock"]);
+    assert_eq!(post(addr, "/v1/presets/Broken/apply", json!({})).await.0, 500);
+
+    server.stop().await;
+
+    // Without a controller there's nothing to apply to
+    let mut server = start_server("presets-uncontrolled", None).await;
+    assert_eq!(post(server.addr(), "/v1/presets/Rock/apply", json!({})).await.0, 409);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_dsp_settings() {
+    let controller = Arc::new(TestController::default());
+    let mut server = start_server("dsp", Some(controller.clone())).await;
+    let addr = server.addr();
+
+    let (status, dsp) = get(addr, "/v1/dsp").await;
+    assert_eq!(status, 200);
+    assert_eq!(dsp["profile_id"], 1);
+
+    // Changing the active profile's settings applies them
+    let (status, dsp) = patch(addr, "/v1/dsp", json!({"crossfeed_enabled": true, "buffer_ms": 200})).await;
+    assert_eq!(status, 200);
+    assert_eq!(dsp["crossfeed_enabled"], true);
+    assert_eq!(dsp["buffer_ms"], 200);
+    assert_eq!(*controller.profiles.lock().unwrap(), [1]);
+
+    // Another profile's settings are saved without switching
+    let (status, dsp) = patch(addr, "/v1/dsp?profile_id=2", json!({"limiter_enabled": true})).await;
+    assert_eq!(status, 200);
+    assert_eq!(dsp["profile_id"], 2);
+    assert_eq!(controller.profiles.lock().unwrap().len(), 1);
+
+    // Limiter and compressor are mutually exclusive
+    patch(addr, "/v1/dsp", json!({"limiter_enabled": true})).await;
+    let (status, error) = patch(addr, "/v1/dsp", json!({"compressor_enabled": true})).await;
+    assert_eq!(status, 409);
+    assert!(error["error"].as_str().unwrap().contains("Limiter"));
+    assert_eq!(get(addr, "/v1/dsp").await.1["compressor_enabled"], false);
+
+    assert_eq!(patch(addr, "/v1/dsp", json!({"sample_rate": 12345})).await.0, 400);
+    assert_eq!(patch(addr, "/v1/dsp", json!({"profile_id": 2})).await.0, 400);
+    assert_eq!(patch(addr, "/v1/dsp", json!({"warp_drive": true})).await.0, 400);
+    assert_eq!(patch(addr, "/v1/dsp", json!({"buffer_ms": "lots"})).await.0, 400);
+    assert_eq!(get(addr, "/v1/dsp?profile_id=99").await.0, 404);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_devices() {
+    let mut server = start_server("devices", None).await;
+    let addr = server.addr();
+
+    let device = json!({"name": "Living Room", "protocol": "WiimApi", "address": "192.168.1.20"});
+    let (status, created) = post(addr, "/v1/devices", device.clone()).await;
+    assert_eq!(status, 201);
+    assert_eq!(created["source"], "Manual");
+    assert_eq!(created["profile_id"], 1);
+    let id = created["id"].as_i64().unwrap();
+
+    assert_eq!(
+        post(addr, "/v1/devices", json!({"name": "X", "protocol": "Carrier Pigeon", "address": "roof"})).await.0,
+        400
+    );
+    assert_eq!(post(addr, "/v1/devices", json!({"name": "X", "protocol": "Dlna", "address": ""})).await.0, 400);
+
+    let (status, updated) = put(
+        addr,
+        &format!("/v1/devices/{}", id),
+        json!({"name": "Lounge", "protocol": "WiimApi", "address": "192.168.1.21", "favorite": true}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(updated["name"], "Lounge");
+    assert_eq!(updated["favorite"], true);
+
+    // Two devices can't share an address
+    let (_, other) = post(addr, "/v1/devices", json!({"name": "Den", "protocol": "WiimApi", "address": "10.0.0.2"})).await;
+    let other_id = other["id"].as_i64().unwrap();
+    assert_eq!(put(addr, &format!("/v1/devices/{}", other_id), updated.clone()).await.0, 409);
+
+    assert_eq!(get(addr, "/v1/devices").await.1.as_array().unwrap().len(), 2);
+    assert_eq!(delete(addr, &format!("/v1/devices/{}", id)).await.0, 200);
+    assert_eq!(get(addr, &format!("/v1/devices/{}", id)).await.0, 404);
+    assert_eq!(get(addr, "/v1/devices").await.1.as_array().unwrap().len(), 1);
+
+    server.stop().await;
+}
@@ -0,0 +1,351 @@
+//! MQTT bridge tests against a minimal in-process broker standing in for
+//! mosquitto: state and Home Assistant discovery are published retained,
+//! commands reach the handler, and stopping marks AAEQ offline.
+
+use aaeq_core::{MqttSettings, TrackMeta};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use rumqttc::{
+    AsyncClient, ConnAck, ConnectReturnCode, Event, MqttOptions, Packet, PubAck, Publish, QoS, SubAck,
+    SubscribeReasonCode,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use stream_server::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+const MAX_PACKET: usize = 1024 * 1024;
+
+// --- Broker stand-in ---
+
+#[derive(Default)]
+struct BrokerState {
+    retained: HashMap<String, Publish>,
+    subscribers: Vec<(String, mpsc::UnboundedSender<Packet>)>,
+}
+
+/// Accepts MQTT 3.1.1 clients, keeps retained messages and routes publishes
+async fn start_broker() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let state = Arc::new(Mutex::new(BrokerState::default()));
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve_client(stream, state.clone()));
+        }
+    });
+    port
+}
+
+fn matches_filter(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+async fn serve_client(stream: TcpStream, state: Arc<Mutex<BrokerState>>) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Packet>();
+    tokio::spawn(async move {
+        while let Some(packet) = rx.recv().await {
+            let mut buf = BytesMut::new();
+            packet.write(&mut buf, MAX_PACKET).unwrap();
+            if writer.write_all(&buf).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut buf = BytesMut::new();
+    loop {
+        let packet = match Packet::read(&mut buf, MAX_PACKET) {
+            Ok(packet) => packet,
+            Err(rumqttc::Error::InsufficientBytes(_)) => {
+                if reader.read_buf(&mut buf).await.unwrap_or(0) == 0 {
+                    break;
+                }
+                continue;
+            }
+            Err(_) => break,
+        };
+        match packet {
+            Packet::Connect(_) => {
+                let _ = tx.send(Packet::ConnAck(ConnAck::new(ConnectReturnCode::Success, false)));
+            }
+            Packet::Subscribe(subscribe) => {
+                let mut state = state.lock().unwrap();
+                for filter in &subscribe.filters {
+                    state.subscribers.push((filter.path.clone(), tx.clone()));
+                }
+                let codes = subscribe.filters.iter().map(|_| SubscribeReasonCode::Success(QoS::AtMostOnce)).collect();
+                let _ = tx.send(Packet::SubAck(SubAck::new(subscribe.pkid, codes)));
+                for filter in &subscribe.filters {
+                    for retained in state.retained.values().filter(|p| matches_filter(&filter.path, &p.topic)) {
+                        let _ = tx.send(Packet::Publish(retained.clone()));
+                    }
+                }
+            }
+            Packet::Publish(publish) => {
+                if publish.qos == QoS::AtLeastOnce {
+                    let _ = tx.send(Packet::PubAck(PubAck::new(publish.pkid)));
+                }
+                let mut delivered = Publish::new(publish.topic.clone(), QoS::AtMostOnce, publish.payload.to_vec());
+                let mut state = state.lock().unwrap();
+                for (filter, subscriber) in &state.subscribers {
+                    if matches_filter(filter, &publish.topic) {
+                        let _ = subscriber.send(Packet::Publish(delivered.clone()));
+                    }
+                }
+                if publish.retain {
+                    delivered.retain = true;
+                    state.retained.insert(publish.topic.clone(), delivered);
+                }
+            }
+            Packet::PingReq => {
+                let _ = tx.send(Packet::PingResp);
+            }
+            Packet::Disconnect => break,
+            _ => {}
+        }
+    }
+}
+
+// --- Observer ---
+
+/// Another client of the broker, like Home Assistant
+struct Observer {
+    client: AsyncClient,
+    messages: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl Observer {
+    async fn connect(port: u16) -> Self {
+        let (client, mut eventloop) = AsyncClient::new(MqttOptions::new("observer", "127.0.0.1", port), 16);
+        client.subscribe("#", QoS::AtMostOnce).await.unwrap();
+        let messages = Arc::new(Mutex::new(HashMap::new()));
+        let received = messages.clone();
+        tokio::spawn(async move {
+            while let Ok(event) = eventloop.poll().await {
+                if let Event::Incoming(Packet::Publish(publish)) = event {
+                    let payload = String::from_utf8_lossy(&publish.payload).into_owned();
+                    received.lock().unwrap().insert(publish.topic, payload);
+                }
+            }
+        });
+        Self { client, messages }
+    }
+
+    /// Wait for `topic` to hold `expected`
+    async fn expect(&self, topic: &str, expected: &str) {
+        for _ in 0..100 {
+            if self.get(topic).as_deref() == Some(expected) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("{} is {:?}, expected {:?}", topic, self.get(topic), expected);
+    }
+
+    fn get(&self, topic: &str) -> Option<String> {
+        self.messages.lock().unwrap().get(topic).cloned()
+    }
+
+    fn json(&self, topic: &str) -> Value {
+        serde_json::from_str(&self.get(topic).unwrap()).unwrap()
+    }
+
+    async fn send(&self, topic: &str, payload: &str) {
+        self.client.publish(topic, QoS::AtLeastOnce, false, payload).await.unwrap();
+    }
+}
+
+// --- Handler ---
+
+#[derive(Default)]
+struct MockHandler {
+    state: Mutex<MqttState>,
+}
+
+#[async_trait]
+impl MqttHandler for MockHandler {
+    async fn state(&self) -> Result<MqttState> {
+        Ok(self.state.lock().unwrap().clone())
+    }
+
+    async fn activate_profile(&self, name: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.profiles.iter().any(|p| p == name) {
+            bail!("No profile named {}", name);
+        }
+        state.profile = Some(name.to_string());
+        Ok(())
+    }
+
+    async fn apply_preset(&self, name: &str) -> Result<()> {
+        self.state.lock().unwrap().preset = Some(name.to_string());
+        Ok(())
+    }
+
+    async fn set_streaming(&self, streaming: bool) -> Result<()> {
+        self.state.lock().unwrap().streaming = streaming;
+        Ok(())
+    }
+
+    async fn set_volume(&self, volume: f32) -> Result<()> {
+        self.state.lock().unwrap().volume = Some(volume);
+        Ok(())
+    }
+}
+
+fn handler() -> Arc<MockHandler> {
+    Arc::new(MockHandler {
+        state: Mutex::new(MqttState {
+            track: Some(TrackMeta {
+                artist: "Miles Davis".to_string(),
+                title: "So What".to_string(),
+                genre: "Jazz".to_string(),
+                ..Default::default()
+            }),
+            preset: Some("Flat".to_string()),
+            profile: Some("Default".to_string()),
+            profiles: vec!["Default".to_string(), "Headphones".to_string()],
+            presets: vec!["Flat".to_string(), "Jazz".to_string(), "Rock".to_string()],
+            output: Some("Local DAC".to_string()),
+            streaming: false,
+            volume: Some(0.5),
+        }),
+    })
+}
+
+fn settings(port: u16) -> MqttSettings {
+    MqttSettings {
+        enabled: true,
+        host: "127.0.0.1".to_string(),
+        port,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_publishes_state_and_discovery() {
+    let port = start_broker().await;
+    let mut bridge = MqttBridge::new(settings(port), handler()).start().unwrap();
+
+    let observer = Observer::connect(port).await;
+    observer.expect("aaeq/status", "online").await;
+    observer.expect("aaeq/preset", "Flat").await;
+    observer.expect("aaeq/profile", "Default").await;
+    observer.expect("aaeq/output", "Local DAC").await;
+    observer.expect("aaeq/streaming", "OFF").await;
+    observer.expect("aaeq/volume", "50").await;
+    assert_eq!(observer.json("aaeq/track")["title"], "So What");
+
+    let preset = observer.json("homeassistant/select/aaeq/preset/config");
+    assert_eq!(preset["command_topic"], "aaeq/preset/set");
+    assert_eq!(preset["options"].as_array().unwrap().len(), 3);
+    assert_eq!(preset["availability_topic"], "aaeq/status");
+    let streaming = observer.json("homeassistant/switch/aaeq/streaming/config");
+    assert_eq!(streaming["state_topic"], "aaeq/streaming");
+    for entity in ["sensor/aaeq/now_playing", "select/aaeq/profile", "sensor/aaeq/output", "number/aaeq/volume"] {
+        assert!(observer.get(&format!("homeassistant/{}/config", entity)).is_some(), "{}", entity);
+    }
+
+    // Stopping marks AAEQ unavailable
+    bridge.stop().await;
+    observer.expect("aaeq/status", "offline").await;
+}
+
+#[tokio::test]
+async fn test_commands_reach_handler() {
+    let port = start_broker().await;
+    let handler = handler();
+    let mut bridge = MqttBridge::new(settings(port), handler.clone()).start().unwrap();
+    let observer = Observer::connect(port).await;
+    observer.expect("aaeq/status", "online").await;
+
+    observer.send("aaeq/preset/set", "Rock").await;
+    observer.expect("aaeq/preset", "Rock").await;
+
+    observer.send("aaeq/profile/set", "Headphones").await;
+    observer.expect("aaeq/profile", "Headphones").await;
+
+    observer.send("aaeq/streaming/set", "ON").await;
+    observer.expect("aaeq/streaming", "ON").await;
+
+    observer.send("aaeq/volume/set", "75").await;
+    observer.expect("aaeq/volume", "75").await;
+
+    // Bad commands are ignored
+    observer.send("aaeq/profile/set", "Nowhere").await;
+    observer.send("aaeq/volume/set", "loud").await;
+    observer.send("aaeq/streaming/set", "OFF").await;
+    observer.expect("aaeq/streaming", "OFF").await;
+    let state = handler.state().await.unwrap();
+    assert_eq!(state.profile.as_deref(), Some("Headphones"));
+    assert_eq!(state.volume, Some(0.75));
+
+    bridge.stop().await;
+}
+
+#[tokio::test]
+async fn test_events_trigger_publish() {
+    let port = start_broker().await;
+    let handler = handler();
+    let events = EventBus::new();
+    let mut bridge = MqttBridge::new(
+        MqttSettings {
+            base_topic: "home/aaeq".to_string(),
+            discovery_prefix: "ha".to_string(),
+            ..settings(port)
+        },
+        handler.clone(),
+    )
+    .with_events(events.clone())
+    // Only events could publish the change in time
+    .with_state_interval(Duration::from_secs(3600))
+    .start()
+    .unwrap();
+
+    let observer = Observer::connect(port).await;
+    observer.expect("home/aaeq/status", "online").await;
+    assert!(observer.get("ha/select/home_aaeq/preset/config").is_some());
+
+    let track = TrackMeta {
+        artist: "Nina Simone".to_string(),
+        title: "Feeling Good".to_string(),
+        ..Default::default()
+    };
+    handler.state.lock().unwrap().track = Some(track.clone());
+    events.publish(StreamEvent::TrackChanged { track });
+
+    for _ in 0..100 {
+        if observer.get("home/aaeq/track").is_some_and(|t| t.contains("Feeling Good")) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(observer.json("home/aaeq/track")["artist"], "Nina Simone");
+
+    bridge.stop().await;
+}
+
+#[tokio::test]
+async fn test_start_requires_host() {
+    let settings = MqttSettings {
+        host: " ".to_string(),
+        ..Default::default()
+    };
+    assert!(MqttBridge::new(settings, handler()).start().is_err());
+}
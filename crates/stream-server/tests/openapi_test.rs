@@ -0,0 +1,154 @@
+//! Contract test for `/v1/openapi.json`: the documented operations are
+//! exactly the ones served, and what the server answers has the fields the
+//! schema requires.
+
+use aaeq_core::TokenScope;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use stream_server::*;
+use tokio::sync::RwLock;
+
+/// Stand-ins for path parameters; ids that don't exist so nothing is deleted
+fn fill_params(path: &str) -> String {
+    path.replace("{id}", "999999").replace("{name}", "Flat")
+}
+
+/// `/v1/presets/:name` as OpenAPI writes it, `/v1/presets/{name}`
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(param) => format!("{{{}}}", param),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The schema a `$ref` points at
+fn resolve<'a>(document: &'a Value, schema: &'a Value) -> &'a Value {
+    match schema["$ref"].as_str() {
+        Some(reference) => {
+            let name = reference.strip_prefix("#/components/schemas/").unwrap();
+            &document["components"]["schemas"][name]
+        }
+        None => schema,
+    }
+}
+
+/// Check `value` has the properties `schema` requires, and so do its array items
+fn check_shape(document: &Value, schema: &Value, value: &Value, at: &str) {
+    let schema = resolve(document, schema);
+    if let Some(items) = schema.get("items") {
+        for item in value.as_array().unwrap_or_else(|| panic!("{}: expected an array, got {}", at, value)) {
+            check_shape(document, items, item, at);
+        }
+        return;
+    }
+    for required in schema["required"].as_array().into_iter().flatten() {
+        let field = required.as_str().unwrap();
+        assert!(value.get(field).is_some(), "{}: response lacks required '{}': {}", at, field, value);
+    }
+}
+
+async fn request(addr: SocketAddr, method: &str, path: &str, token: &str) -> (u16, String) {
+    let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+    let mut request = reqwest::Client::new()
+        .request(method.clone(), format!("http://{}{}", addr, path))
+        .bearer_auth(token);
+    if method != reqwest::Method::GET && method != reqwest::Method::DELETE {
+        request = request
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body("{}");
+    }
+    let response = request.send().await.unwrap();
+    (response.status().as_u16(), response.text().await.unwrap())
+}
+
+#[tokio::test]
+async fn test_openapi_document_matches_server() {
+    let dir = std::env::temp_dir().join(format!("aaeq-openapi-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let pool = aaeq_persistence::init_db(&dir.join("aaeq.db")).await.unwrap();
+    let token = issue_token(&pool, "contract", TokenScope::Control).await.unwrap().token;
+
+    let manager = Arc::new(RwLock::new(OutputManager::new()));
+    let mut server = ControlServer::new("127.0.0.1:0".parse().unwrap(), manager)
+        .with_database(pool.clone())
+        .with_auth(pool);
+    server.start().await.unwrap();
+    let addr = server.addr();
+
+    // Served without a token, like /v1/health
+    let response = reqwest::get(format!("http://{}/v1/openapi.json", addr)).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let document: Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+    assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+    assert_eq!(document["info"]["version"], env!("CARGO_PKG_VERSION"));
+
+    // What the checks below rely on to tell unrouted requests apart
+    assert_eq!(request(addr, "get", "/v1/nope", &token).await, (404, String::new()));
+    assert_eq!(request(addr, "put", "/v1/health", &token).await.0, 405);
+
+    let paths = document["paths"].as_object().unwrap();
+    assert!(paths.len() > 20);
+    for (path, item) in paths {
+        for (method, operation) in item.as_object().unwrap() {
+            let at = format!("{} {}", method.to_uppercase(), path);
+            let (status, body) = request(addr, method, &fill_params(path), &token).await;
+
+            // Unrouted requests get an empty 404 or a 405; the API's own errors have a body
+            assert_ne!(status, 405, "{}: method not routed", at);
+            assert!(status != 404 || !body.is_empty(), "{}: path not routed", at);
+
+            // Reads without parameters answer with what the schema promises
+            if method == "get" && !path.contains('{') && status == 200 {
+                let content = &operation["responses"]["200"]["content"];
+                if let Some(schema) = content.get("application/json").map(|json| &json["schema"]) {
+                    let value: Value = serde_json::from_str(&body).unwrap();
+                    check_shape(&document, schema, &value, &at);
+                }
+            }
+        }
+    }
+
+    // And everything served is documented: the methods a route allows are
+    // listed in the `Allow` header of a 405 for one it doesn't
+    let documented: BTreeSet<(String, String)> = paths
+        .iter()
+        .flat_map(|(path, item)| item.as_object().unwrap().keys().map(move |method| (method.clone(), path.clone())))
+        .collect();
+    let mut served = BTreeSet::new();
+    assert!(server.api_paths().len() > 20);
+    for path in server.api_paths() {
+        let path = openapi_path(path);
+        let response = reqwest::Client::new()
+            .request(reqwest::Method::TRACE, format!("http://{}{}", addr, fill_params(&path)))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 405, "TRACE {}", path);
+        let allow = response.headers()["allow"].to_str().unwrap().to_lowercase();
+        for method in allow.split(',').map(str::trim).filter(|method| *method != "head") {
+            served.insert((method.to_string(), path.clone()));
+        }
+    }
+    assert_eq!(
+        served.difference(&documented).collect::<Vec<_>>(),
+        Vec::<&(String, String)>::new(),
+        "served operations missing from the document"
+    );
+    assert_eq!(served, documented);
+
+    // Documented errors are what the server sends
+    let (status, body) = request(addr, "get", "/v1/presets/Nope", &token).await;
+    assert_eq!(status, 404);
+    let error = &document["paths"]["/v1/presets/{name}"]["get"]["responses"]["404"]["content"]["application/json"]["schema"];
+    check_shape(&document, error, &serde_json::from_str(&body).unwrap(), "GET /v1/presets/Nope");
+    assert_eq!(resolve(&document, error)["required"], json!(["error"]));
+
+    server.stop().await;
+}
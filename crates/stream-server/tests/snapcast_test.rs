@@ -0,0 +1,266 @@
+//! Snapcast sink tests: a fake snapclient against the built-in server, plus the
+//! raw PCM pipe and TCP source modes.
+
+use std::time::Duration;
+use stream_server::snapcast::{BaseMessage, MessageType, Tv, HEADER_SIZE};
+use stream_server::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn output_config() -> OutputConfig {
+    OutputConfig {
+        sample_rate: 48000,
+        channels: 2,
+        format: SampleFormat::S16LE,
+        buffer_ms: 150,
+        exclusive: false,
+    }
+}
+
+/// Interleaved stereo ramp
+fn ramp(frames: usize) -> Vec<f64> {
+    (0..frames * 2)
+        .map(|i| ((i % 512) as f64 - 256.0) / 1024.0)
+        .collect()
+}
+
+fn s16_samples(pcm: &[u8]) -> Vec<i16> {
+    pcm.chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect()
+}
+
+/// Conversion to 16-bit is dithered, so allow a couple of LSBs of difference
+fn assert_matches_audio(samples: &[i16], audio: &[f64]) {
+    assert_eq!(samples.len(), audio.len());
+    for (&s, &a) in samples.iter().zip(audio) {
+        let expected = (a * 32767.0) as i32;
+        assert!((s as i32 - expected).abs() <= 2, "{} vs {}", s, expected);
+    }
+}
+
+/// Minimal snapclient speaking the binary protocol
+struct FakeClient {
+    stream: TcpStream,
+    next_id: u16,
+}
+
+impl FakeClient {
+    async fn connect(addr: std::net::SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        Self { stream, next_id: 1 }
+    }
+
+    async fn send(&mut self, msg_type: MessageType, sent: Tv, payload: &[u8]) -> u16 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut message = Vec::new();
+        message.extend_from_slice(&(msg_type as u16).to_le_bytes());
+        message.extend_from_slice(&id.to_le_bytes());
+        message.extend_from_slice(&0u16.to_le_bytes());
+        message.extend_from_slice(&sent.sec.to_le_bytes());
+        message.extend_from_slice(&sent.usec.to_le_bytes());
+        message.extend_from_slice(&[0u8; 8]);
+        message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        message.extend_from_slice(payload);
+        self.stream.write_all(&message).await.unwrap();
+        id
+    }
+
+    async fn hello(&mut self) -> u16 {
+        let json = r#"{"Arch":"armv7l","ClientName":"Snapclient","HostName":"kitchen","ID":"b8:27:eb:00:00:01","Instance":1,"MAC":"b8:27:eb:00:00:01","OS":"Raspbian","SnapStreamProtocolVersion":2,"Version":"0.27.0"}"#;
+        let mut payload = (json.len() as u32).to_le_bytes().to_vec();
+        payload.extend_from_slice(json.as_bytes());
+        self.send(MessageType::Hello, Tv::default(), &payload).await
+    }
+
+    async fn receive(&mut self) -> (BaseMessage, Vec<u8>) {
+        timeout(RECEIVE_TIMEOUT, async {
+            let mut header = [0u8; HEADER_SIZE];
+            self.stream.read_exact(&mut header).await.unwrap();
+            let header = BaseMessage::parse(&header).unwrap();
+            let mut payload = vec![0u8; header.size as usize];
+            self.stream.read_exact(&mut payload).await.unwrap();
+            (header, payload)
+        })
+        .await
+        .expect("timed out waiting for message")
+    }
+
+    /// Receive, skipping messages of other types
+    async fn receive_type(&mut self, msg_type: MessageType) -> (BaseMessage, Vec<u8>) {
+        loop {
+            let (header, payload) = self.receive().await;
+            if header.message_type() == Some(msg_type) {
+                return (header, payload);
+            }
+        }
+    }
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes(data[..4].try_into().unwrap())
+}
+
+fn wire_chunk_parts(payload: &[u8]) -> (Tv, Vec<u8>) {
+    let sec = i32::from_le_bytes(payload[0..4].try_into().unwrap());
+    let usec = i32::from_le_bytes(payload[4..8].try_into().unwrap());
+    let size = read_u32(&payload[8..]) as usize;
+    (Tv { sec, usec }, payload[12..12 + size].to_vec())
+}
+
+async fn open_server(codec: SnapcastCodec) -> (SnapcastSink, FakeClient) {
+    let mut sink = SnapcastSink::server("127.0.0.1:0".parse().unwrap()).with_codec(codec);
+    sink.open(output_config()).await.unwrap();
+
+    let client = FakeClient::connect(sink.server_addr().unwrap()).await;
+    (sink, client)
+}
+
+#[tokio::test]
+async fn test_client_handshake() {
+    let (mut sink, mut client) = open_server(SnapcastCodec::Pcm).await;
+    let hello_id = client.hello().await;
+
+    let (settings, payload) = client.receive().await;
+    assert_eq!(settings.message_type(), Some(MessageType::ServerSettings));
+    assert_eq!(settings.refers_to, hello_id);
+    let json = String::from_utf8(payload[4..].to_vec()).unwrap();
+    assert!(json.contains(r#""bufferMs":1000"#));
+
+    let (codec, payload) = client.receive().await;
+    assert_eq!(codec.message_type(), Some(MessageType::CodecHeader));
+    let name_len = read_u32(&payload) as usize;
+    assert_eq!(&payload[4..4 + name_len], b"pcm");
+    let header = &payload[8 + name_len..];
+    assert_eq!(&header[..4], b"RIFF");
+
+    // Clients are listed once they've said hello
+    timeout(RECEIVE_TIMEOUT, async {
+        while sink.clients().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(sink.clients()[0].host_name, "kitchen");
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_pcm_chunks_are_contiguous() {
+    let (mut sink, mut client) = open_server(SnapcastCodec::Pcm).await;
+    client.hello().await;
+    client.receive_type(MessageType::CodecHeader).await;
+
+    // 50ms: two full 20ms chunks, 10ms left pending
+    let audio = ramp(2400);
+    sink.write(AudioBlock::new(&audio, 48000, 2)).await.unwrap();
+
+    let (_, first) = client.receive_type(MessageType::WireChunk).await;
+    let (_, second) = client.receive_type(MessageType::WireChunk).await;
+    let (ts1, data1) = wire_chunk_parts(&first);
+    let (ts2, data2) = wire_chunk_parts(&second);
+
+    assert_eq!(data1.len(), 960 * 4);
+    assert_eq!(ts2.to_micros() - ts1.to_micros(), 20_000);
+
+    assert_matches_audio(&s16_samples(&data1), &audio[..960 * 2]);
+    assert_matches_audio(&s16_samples(&data2), &audio[960 * 2..960 * 4]);
+
+    // The remaining 10ms goes out on drain
+    sink.drain().await.unwrap();
+    let (_, last) = client.receive_type(MessageType::WireChunk).await;
+    assert_matches_audio(&s16_samples(&wire_chunk_parts(&last).1), &audio[960 * 4..]);
+
+    assert_eq!(sink.stats().frames_written, 2400);
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_flac_chunks_decode() {
+    let (mut sink, mut client) = open_server(SnapcastCodec::Flac).await;
+    client.hello().await;
+
+    let (_, payload) = client.receive_type(MessageType::CodecHeader).await;
+    let name_len = read_u32(&payload) as usize;
+    assert_eq!(&payload[4..4 + name_len], b"flac");
+    let mut stream = payload[8 + name_len..].to_vec();
+    assert_eq!(&stream[..4], b"fLaC");
+
+    let audio = ramp(960 * 3);
+    sink.write(AudioBlock::new(&audio, 48000, 2)).await.unwrap();
+    for _ in 0..3 {
+        let (_, chunk) = client.receive_type(MessageType::WireChunk).await;
+        stream.extend(wire_chunk_parts(&chunk).1);
+    }
+
+    let mut reader = claxon::FlacReader::new(std::io::Cursor::new(stream)).unwrap();
+    let decoded: Vec<i16> = reader.samples().map(|s| s.unwrap() as i16).collect();
+    assert_matches_audio(&decoded, &audio);
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_time_sync() {
+    let (mut sink, mut client) = open_server(SnapcastCodec::Pcm).await;
+
+    let sent = Tv { sec: 5, usec: 0 };
+    let id = client.send(MessageType::Time, sent, &[0u8; 8]).await;
+
+    let (reply, payload) = client.receive_type(MessageType::Time).await;
+    assert_eq!(reply.refers_to, id);
+
+    // Latency = server receive time - client send time
+    let latency = Tv {
+        sec: i32::from_le_bytes(payload[0..4].try_into().unwrap()),
+        usec: i32::from_le_bytes(payload[4..8].try_into().unwrap()),
+    };
+    assert_eq!(latency.to_micros(), reply.received.to_micros() - sent.to_micros());
+    assert!(reply.sent >= reply.received);
+
+    sink.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp_source_receives_raw_pcm() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut sink = SnapcastSink::tcp(listener.local_addr().unwrap());
+
+    sink.open(output_config()).await.unwrap();
+    let (mut source, _) = listener.accept().await.unwrap();
+
+    let audio = ramp(100);
+    sink.write(AudioBlock::new(&audio, 48000, 2)).await.unwrap();
+    sink.close().await.unwrap();
+
+    let mut received = Vec::new();
+    timeout(RECEIVE_TIMEOUT, source.read_to_end(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_matches_audio(&s16_samples(&received), &audio);
+}
+
+#[tokio::test]
+async fn test_pipe_receives_raw_pcm() {
+    let path = std::env::temp_dir().join(format!("aaeq-snapfifo-test-{}", std::process::id()));
+    std::fs::write(&path, b"").unwrap();
+
+    let mut sink = SnapcastSink::pipe(&path);
+    sink.open(output_config()).await.unwrap();
+
+    let audio = ramp(100);
+    sink.write(AudioBlock::new(&audio, 48000, 2)).await.unwrap();
+    sink.close().await.unwrap();
+
+    let written = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_matches_audio(&s16_samples(&written), &audio);
+}
@@ -0,0 +1,306 @@
+// AAEQ web UI: a remote for the Control API it is served from.
+// State is loaded over REST once, then kept current by the /v1/events feed.
+'use strict';
+
+const TOKEN_KEY = 'aaeq.token';
+
+// Matches the DSP chain: peaking bands with Q 1.0, drawn at 48 kHz
+const CURVE_SAMPLE_RATE = 48000;
+const BAND_Q = 1.0;
+const CURVE_RANGE_DB = 15;
+
+// Used when selecting an output that hasn't been opened yet
+const DEFAULT_OUTPUT_CONFIG = { sample_rate: 48000, channels: 2, format: 'S24LE', buffer_ms: 150, exclusive: false };
+
+const RECONNECT_MS = 2000;
+const CLIPPING_SHOWN_MS = 3000;
+
+const $ = (id) => document.getElementById(id);
+
+let token = localStorage.getItem(TOKEN_KEY);
+let outputs = [];
+let events = null;
+let clippingTimer = null;
+
+// --- API ---
+
+async function api(method, path, body) {
+  const headers = {};
+  if (token) headers.Authorization = `Bearer ${token}`;
+  if (body !== undefined) headers['Content-Type'] = 'application/json';
+
+  const response = await fetch(path, {
+    method,
+    headers,
+    body: body === undefined ? undefined : JSON.stringify(body),
+  });
+  if (response.status === 401) {
+    askForToken();
+    throw new Error('A token is needed');
+  }
+  const data = await response.json().catch(() => null);
+  if (!response.ok) {
+    const error = new Error(response.status === 403 ? 'This token can only read' : (data && data.error) || response.statusText);
+    error.status = response.status;
+    throw error;
+  }
+  return data;
+}
+
+function showMessage(text) {
+  const message = $('message');
+  message.textContent = text || '';
+  message.hidden = !text;
+}
+
+async function attempt(action) {
+  try {
+    showMessage('');
+    await action();
+  } catch (e) {
+    showMessage(e.message);
+  }
+}
+
+function askForToken() {
+  const dialog = $('login');
+  if (!dialog.open) dialog.showModal();
+}
+
+$('login').addEventListener('close', () => {
+  const value = $('token').value.trim();
+  if ($('login').returnValue !== 'save' || !value) return;
+  token = value;
+  localStorage.setItem(TOKEN_KEY, token);
+  load();
+});
+
+// --- Now playing ---
+
+function showTrack(track) {
+  $('title').textContent = track.title || 'Unknown title';
+  $('artist').textContent = [track.artist, track.album].filter(Boolean).join(' · ');
+  $('genre').textContent = track.genre || '';
+  const art = $('art');
+  art.hidden = !track.album_art_url;
+  if (track.album_art_url) art.src = track.album_art_url;
+}
+
+// --- Presets and the EQ curve ---
+
+async function showPreset(name, rule) {
+  $('preset').textContent = name;
+  $('rule').textContent = rule ? `(${rule.scope}${rule.key ? `: ${rule.key}` : ''})` : '';
+  $('presets').value = name;
+  try {
+    const preset = await api('GET', `/v1/presets/${encodeURIComponent(name)}`);
+    drawCurve(preset.bands);
+  } catch (e) {
+    drawCurve([]);
+  }
+}
+
+// Gain in dB of an RBJ peaking filter at `frequency`
+function peakingGain(band, frequency) {
+  const a = Math.pow(10, band.gain / 40);
+  const w0 = (2 * Math.PI * band.frequency) / CURVE_SAMPLE_RATE;
+  const alpha = Math.sin(w0) / (2 * BAND_Q);
+  const cos = Math.cos(w0);
+  const b = [1 + alpha * a, -2 * cos, 1 - alpha * a];
+  const d = [1 + alpha / a, -2 * cos, 1 - alpha / a];
+
+  const w = (2 * Math.PI * frequency) / CURVE_SAMPLE_RATE;
+  const magnitude = (c) => {
+    const re = c[0] + c[1] * Math.cos(w) + c[2] * Math.cos(2 * w);
+    const im = -c[1] * Math.sin(w) - c[2] * Math.sin(2 * w);
+    return Math.hypot(re, im);
+  };
+  return 20 * Math.log10(magnitude(b) / magnitude(d));
+}
+
+function drawCurve(bands) {
+  const canvas = $('curve');
+  const ctx = canvas.getContext('2d');
+  const { width, height } = canvas;
+  const x = (frequency) => (Math.log10(frequency / 20) / Math.log10(1000)) * width;
+  const y = (db) => height / 2 - (db / CURVE_RANGE_DB) * (height / 2);
+
+  ctx.clearRect(0, 0, width, height);
+  ctx.strokeStyle = '#30343c';
+  ctx.fillStyle = '#9aa0ab';
+  ctx.font = '11px system-ui, sans-serif';
+  ctx.lineWidth = 1;
+  for (const db of [-12, -6, 0, 6, 12]) {
+    ctx.beginPath();
+    ctx.moveTo(0, y(db));
+    ctx.lineTo(width, y(db));
+    ctx.stroke();
+    ctx.fillText(`${db > 0 ? '+' : ''}${db}`, 4, y(db) - 3);
+  }
+  for (const [frequency, label] of [[100, '100'], [1000, '1k'], [10000, '10k']]) {
+    ctx.beginPath();
+    ctx.moveTo(x(frequency), 0);
+    ctx.lineTo(x(frequency), height);
+    ctx.stroke();
+    ctx.fillText(label, x(frequency) + 3, height - 4);
+  }
+
+  const usable = (bands || []).filter((band) => band.frequency > 0 && band.frequency < CURVE_SAMPLE_RATE / 2);
+  ctx.strokeStyle = '#4a90e2';
+  ctx.lineWidth = 2.5;
+  ctx.beginPath();
+  for (let px = 0; px <= width; px += 2) {
+    const frequency = 20 * Math.pow(1000, px / width);
+    const db = usable.reduce((sum, band) => sum + peakingGain(band, frequency), 0);
+    const clamped = Math.max(-CURVE_RANGE_DB, Math.min(CURVE_RANGE_DB, db));
+    if (px === 0) ctx.moveTo(px, y(clamped));
+    else ctx.lineTo(px, y(clamped));
+  }
+  ctx.stroke();
+}
+
+async function loadPresets() {
+  const presets = await api('GET', '/v1/presets');
+  const select = $('presets');
+  select.replaceChildren();
+  for (const [label, names] of [['Built-in', presets.builtin], ['Custom', presets.custom]]) {
+    if (!names.length) continue;
+    const group = document.createElement('optgroup');
+    group.label = label;
+    for (const name of names) group.append(new Option(name, name));
+    select.append(group);
+  }
+  const current = $('preset').textContent;
+  if (current !== '–') select.value = current;
+}
+
+$('apply').addEventListener('click', () =>
+  attempt(() => api('POST', `/v1/presets/${encodeURIComponent($('presets').value)}/apply`)));
+
+// --- Profiles ---
+
+async function loadProfiles() {
+  const response = await api('GET', '/v1/profiles');
+  const select = $('profiles');
+  select.replaceChildren(...response.profiles.map((profile) =>
+    new Option(`${profile.icon || ''} ${profile.name}`.trim(), profile.id)));
+  select.value = response.active_id;
+}
+
+$('profiles').addEventListener('change', (event) =>
+  attempt(() => api('POST', `/v1/profiles/${event.target.value}/activate`)));
+
+// --- Outputs ---
+
+async function loadOutputs() {
+  const [response, metrics] = await Promise.all([api('GET', '/v1/outputs'), api('GET', '/v1/outputs/metrics')]);
+  outputs = response.outputs;
+
+  const list = $('outputs');
+  list.replaceChildren(...outputs.map((output) => {
+    const input = document.createElement('input');
+    input.type = 'radio';
+    input.name = 'output';
+    input.value = output.name;
+    input.checked = output.is_active;
+    input.addEventListener('change', () => attempt(() => selectOutput(output)));
+
+    const detail = document.createElement('span');
+    detail.className = 'muted small';
+    detail.textContent = output.config ? `${output.config.sample_rate / 1000} kHz ${output.config.format}` : '';
+
+    const label = document.createElement('label');
+    label.append(input, output.name, detail);
+    const item = document.createElement('li');
+    item.append(label);
+    return item;
+  }));
+  if (!outputs.length) list.append(Object.assign(document.createElement('li'), { className: 'muted', textContent: 'No outputs' }));
+
+  $('streaming').hidden = !metrics.streaming;
+  $('start').disabled = metrics.streaming || !response.active;
+  $('stop').disabled = !metrics.streaming;
+}
+
+async function selectOutput(output) {
+  await api('POST', '/v1/outputs/select', { name: output.name, config: output.config || DEFAULT_OUTPUT_CONFIG });
+  await loadOutputs();
+}
+
+$('start').addEventListener('click', () => attempt(async () => {
+  await api('POST', '/v1/outputs/start');
+  await loadOutputs();
+}));
+
+$('stop').addEventListener('click', () => attempt(async () => {
+  await api('POST', '/v1/outputs/stop');
+  await loadOutputs();
+}));
+
+// --- Events ---
+
+function showMeters(reading) {
+  // -60 LUFS and below reads as empty
+  const level = Math.max(0, Math.min(1, (reading.lufs_momentary + 60) / 60));
+  $('meter-fill').style.width = `${level * 100}%`;
+}
+
+function showClipping() {
+  $('clipping').hidden = false;
+  clearTimeout(clippingTimer);
+  clippingTimer = setTimeout(() => { $('clipping').hidden = true; }, CLIPPING_SHOWN_MS);
+}
+
+function handleEvent(event) {
+  switch (event.type) {
+    case 'track_changed': showTrack(event.track); break;
+    case 'preset_applied': showPreset(event.preset, event.rule); break;
+    case 'sink_state': attempt(loadOutputs); break;
+    case 'clipping': showClipping(); break;
+    case 'meters': showMeters(event); break;
+    case 'lagged': attempt(loadOutputs); break;
+  }
+}
+
+function connectEvents() {
+  if (events) events.close();
+  const scheme = location.protocol === 'https:' ? 'wss' : 'ws';
+  const query = token ? `?access_token=${encodeURIComponent(token)}` : '';
+  const socket = new WebSocket(`${scheme}://${location.host}/v1/events${query}`);
+  events = socket;
+
+  socket.onopen = () => {
+    $('connection').classList.add('online');
+    $('connection').title = 'Connected';
+  };
+  socket.onmessage = (message) => handleEvent(JSON.parse(message.data));
+  socket.onclose = () => {
+    $('connection').classList.remove('online');
+    $('connection').title = 'Disconnected';
+    if (events === socket) setTimeout(connectEvents, RECONNECT_MS);
+  };
+}
+
+// --- Startup ---
+
+async function load() {
+  drawCurve([]);
+  try {
+    await loadOutputs();
+  } catch (e) {
+    showMessage(e.message);
+    return;
+  }
+  showMessage('');
+
+  // Profiles and presets are only served when the server has a database
+  try {
+    await Promise.all([loadProfiles(), loadPresets()]);
+  } catch (e) {
+    if (e.status !== 404) showMessage(e.message);
+    $('profile-card').hidden = true;
+  }
+  connectEvents();
+}
+
+load();
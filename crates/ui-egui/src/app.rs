@@ -16,6 +16,7 @@ use stream_server::dsp::{
     StereoWidth, Crossfeed, RoomAmbience,
     HeadroomControl,
 };
+use stream_server::sinks::anp::{AnpNode, DspStatus, DspUpdate, DspUpdateAck, RemoteDsp, DEFAULT_CONTROL_PORT};
 
 /// Application mode tabs
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -45,6 +46,7 @@ enum ProfileDialogMode {
 /// DSP runtime configuration
 #[derive(Clone, Debug)]
 struct DspRuntimeConfig {
+    profile_id: i64,
     dither_enabled: bool,
     dither_mode: DitherMode,
     noise_shaping: NoiseShaping,
@@ -256,6 +258,7 @@ enum AppResponse {
     PresetCurveLoaded(Option<aaeq_core::EqPreset>), // EQ curve for display
     ProfilesLoaded(Vec<aaeq_core::Profile>), // Reloaded profiles from database
     DspPresetChanged(String), // Preset changed during streaming
+    AnpDspAck(DspUpdateAck), // ANP node acknowledged (or rejected) the pushed DSP profile
     ThemeSaved, // Theme saved to database
     DspSettingsSaved, // DSP settings saved successfully
 }
@@ -815,6 +818,9 @@ impl AaeqApp {
         // AirPlay device cache
         let mut discovered_airplay_devices: Vec<stream_server::sinks::airplay::AirPlayDevice> = Vec::new();
 
+        // ANP node cache
+        let mut discovered_anp_nodes: Vec<AnpNode> = Vec::new();
+
         while let Some(cmd) = command_rx.recv().await {
             // Log all commands for debugging profile switching
             if matches!(cmd, AppCommand::ReapplyPresetForCurrentTrack) {
@@ -1664,6 +1670,22 @@ impl AaeqApp {
                                 }
                             }
                         }
+                        SinkType::Anp => {
+                            match stream_server::sinks::anp::discover_nodes(5).await {
+                                Ok(nodes) => {
+                                    // Cache the discovered nodes for later use
+                                    discovered_anp_nodes = nodes.clone();
+                                    tracing::info!("Cached {} ANP node(s)", discovered_anp_nodes.len());
+
+                                    let device_names: Vec<String> = nodes.iter().map(|n| n.name.clone()).collect();
+                                    let _ = response_tx.send(AppResponse::DspDevicesDiscovered(SinkType::Anp, device_names));
+                                }
+                                Err(e) => {
+                                    tracing::error!("Failed to discover ANP nodes: {}", e);
+                                    let _ = response_tx.send(AppResponse::Error(format!("Failed to discover ANP nodes: {}", e)));
+                                }
+                            }
+                        }
                     }
                 }
 
@@ -1687,6 +1709,9 @@ impl AaeqApp {
                     // Wait a moment for port to be released
                     tokio::time::sleep(Duration::from_millis(100)).await;
 
+                    // Handle for pushing the DSP profile when streaming to an ANP node
+                    let mut remote_dsp: Option<RemoteDsp> = None;
+
                     // Create and register the appropriate sink
                     let sink_result: Result<(), String> = match sink_type {
                         SinkType::LocalDac => {
@@ -1745,6 +1770,32 @@ impl AaeqApp {
                                 Err(format!("Device '{}' not found in cache. Starting auto-discovery...", device_name))
                             }
                         }
+                        SinkType::Anp => {
+                            use stream_server::AnpSink;
+
+                            // Discovered nodes first, then manually added "Name (address)" entries
+                            let node = discovered_anp_nodes.iter()
+                                .find(|n| n.name == device_name)
+                                .cloned()
+                                .or_else(|| manual_anp_node(&device_name));
+
+                            if let Some(node) = node {
+                                let sink = AnpSink::new(node);
+                                remote_dsp = Some(sink.remote_dsp());
+                                output_manager.register_sink(Box::new(sink));
+                                output_manager.select_sink(0, config.clone()).await
+                                    .map_err(|e| format!("Failed to open ANP sink: {}", e))
+                            } else {
+                                tracing::error!("ANP node '{}' not found in cache. Available nodes: {:?}",
+                                    device_name,
+                                    discovered_anp_nodes.iter().map(|n| &n.name).collect::<Vec<_>>());
+                                let _ = response_tx.send(AppResponse::DeviceNotFoundAutoDiscover(
+                                    SinkType::Anp,
+                                    device_name.clone()
+                                ));
+                                Err(format!("Device '{}' not found in cache. Starting auto-discovery...", device_name))
+                            }
+                        }
                     };
 
                     match sink_result {
@@ -1871,13 +1922,28 @@ impl AaeqApp {
 
                                 // Initialize EQ processor if preset is provided
                                 let mut eq_processor = EqProcessor::new(sample_rate, channels);
+                                let mut initial_preset = None;
                                 if let Some(ref preset_name) = preset_name {
                                     tracing::info!("Loading EQ preset: {}", preset_name);
                                     if let Some(preset) = load_preset_curve(preset_name) {
                                         eq_processor.load_preset(&preset);
                                         tracing::info!("EQ preset loaded: {} ({} bands)", preset_name, eq_processor.band_count());
+                                        initial_preset = Some(preset);
+                                    }
+                                }
+
+                                // Offer the profile to the ANP node; it's sent once the node negotiates dsp_transfer
+                                if let Some(dsp) = remote_dsp.as_ref() {
+                                    let preset = initial_preset.unwrap_or_else(|| aaeq_core::EqPreset {
+                                        name: "Flat".to_string(),
+                                        ..Default::default()
+                                    });
+                                    if let Err(e) = dsp.push(anp_dsp_update(&dsp_config, &preset)) {
+                                        tracing::warn!("Failed to push DSP profile to ANP node: {}", e);
                                     }
                                 }
+                                let mut reported_ack: Option<DspUpdateAck> = None;
+                                let mut ack_interval = tokio::time::interval(Duration::from_millis(250));
 
                                 // Initialize Dither processor (currently unused - see NOTE below)
                                 let _dither = Dither::new(
@@ -2012,6 +2078,11 @@ impl AaeqApp {
                                             if let Some(preset) = load_preset_curve(&new_preset_name) {
                                                 eq_processor.load_preset(&preset);
                                                 tracing::info!("EQ preset changed to: {} ({} bands)", new_preset_name, eq_processor.band_count());
+                                                if let Some(dsp) = remote_dsp.as_ref() {
+                                                    if let Err(e) = dsp.push(anp_dsp_update(&dsp_config, &preset)) {
+                                                        tracing::warn!("Failed to push DSP profile to ANP node: {}", e);
+                                                    }
+                                                }
                                             } else {
                                                 tracing::warn!("Failed to load preset: {}", new_preset_name);
                                             }
@@ -2019,6 +2090,11 @@ impl AaeqApp {
                                         Some(preset_data) = preset_data_rx.recv() => {
                                             tracing::info!("Direct preset data received: {} ({} bands)", preset_data.name, preset_data.bands.len());
                                             eq_processor.load_preset(&preset_data);
+                                            if let Some(dsp) = remote_dsp.as_ref() {
+                                                if let Err(e) = dsp.push(anp_dsp_update(&dsp_config, &preset_data)) {
+                                                    tracing::warn!("Failed to push DSP profile to ANP node: {}", e);
+                                                }
+                                            }
                                             tracing::info!("Live EQ preview applied");
                                         }
                                         Some((enabled, quality, target_rate)) = resampler_config_rx.recv() => {
@@ -2041,6 +2117,16 @@ impl AaeqApp {
                                                 }
                                             }
                                         }
+                                        _ = ack_interval.tick(), if remote_dsp.is_some() => {
+                                            // Report each new dsp_update_ack once
+                                            let ack = remote_dsp.as_ref().and_then(|d| d.last_ack());
+                                            if ack.is_some() && ack != reported_ack {
+                                                reported_ack = ack.clone();
+                                                if let Some(ack) = ack {
+                                                    let _ = tx.send(AppResponse::AnpDspAck(ack));
+                                                }
+                                            }
+                                        }
                                         Some(states) = dsp_enhancer_rx.recv() => {
                                             tracing::info!("DSP enhancer states update received");
 
@@ -2081,13 +2167,18 @@ impl AaeqApp {
                                             // Calculate pre-EQ metrics
                                             let (pre_rms_l, pre_rms_r, pre_peak_l, pre_peak_r) = calculate_metrics(&captured_samples);
 
+                                            // Headroom and EQ run on the ANP node once it has acknowledged the profile
+                                            let dsp_offloaded = remote_dsp.as_ref().is_some_and(|d| d.eq_offloaded());
+
                                             // Apply DSP enhancers in fixed order
 
                                             // 1. Expander (gate/noise reduction before processing)
                                             expander.process(&mut captured_samples);
 
                                             // 2. Headroom (volume reduction to prevent clipping)
-                                            headroom.process(&mut captured_samples);
+                                            if !dsp_offloaded {
+                                                headroom.process(&mut captured_samples);
+                                            }
 
                                             // 3. Tone enhancers (mutually exclusive - only one should be enabled)
                                             tube_warmth.process(&mut captured_samples);
@@ -2096,7 +2187,9 @@ impl AaeqApp {
                                             transient_enhancer.process(&mut captured_samples);
 
                                             // 4. Apply EQ processing
-                                            eq_processor.process(&mut captured_samples);
+                                            if !dsp_offloaded {
+                                                eq_processor.process(&mut captured_samples);
+                                            }
 
                                             // 5. Dynamics (compressor/limiter after EQ)
                                             compressor.process(&mut captured_samples);
@@ -2231,13 +2324,18 @@ impl AaeqApp {
                                             // Calculate pre-EQ metrics
                                             let (pre_rms_l, pre_rms_r, pre_peak_l, pre_peak_r) = calculate_metrics(&audio_data);
 
+                                            // Headroom and EQ run on the ANP node once it has acknowledged the profile
+                                            let dsp_offloaded = remote_dsp.as_ref().is_some_and(|d| d.eq_offloaded());
+
                                             // Apply DSP enhancers in fixed order
 
                                             // 1. Expander (gate/noise reduction before processing)
                                             expander.process(&mut audio_data);
 
                                             // 2. Headroom (volume reduction to prevent clipping)
-                                            headroom.process(&mut audio_data);
+                                            if !dsp_offloaded {
+                                                headroom.process(&mut audio_data);
+                                            }
 
                                             // 3. Tone enhancers (mutually exclusive - only one should be enabled)
                                             tube_warmth.process(&mut audio_data);
@@ -2246,7 +2344,9 @@ impl AaeqApp {
                                             transient_enhancer.process(&mut audio_data);
 
                                             // 4. Apply EQ processing
-                                            eq_processor.process(&mut audio_data);
+                                            if !dsp_offloaded {
+                                                eq_processor.process(&mut audio_data);
+                                            }
 
                                             // 5. Dynamics (compressor/limiter after EQ)
                                             compressor.process(&mut audio_data);
@@ -2611,6 +2711,34 @@ impl AaeqApp {
     }
 }
 
+/// Build the `dsp_update` pushed to an ANP node from the streaming DSP config
+fn anp_dsp_update(config: &DspRuntimeConfig, preset: &aaeq_core::EqPreset) -> DspUpdate {
+    let settings = aaeq_core::DspSettings {
+        profile_id: config.profile_id,
+        headroom_db: config.headroom_db,
+        dither_enabled: config.dither_enabled,
+        dither_mode: config.dither_mode.as_str().to_string(),
+        target_bits: config.target_bits,
+        ..Default::default()
+    };
+    DspUpdate::from_settings(&preset.name, &settings, preset)
+}
+
+/// Parse a manually added ANP node entry ("Name (host:port)" or "Name (ip)")
+fn manual_anp_node(device_name: &str) -> Option<AnpNode> {
+    let (name, address) = device_name.strip_suffix(')')?.rsplit_once(" (")?;
+    let address = address
+        .parse::<std::net::SocketAddr>()
+        .ok()
+        .or_else(|| {
+            address
+                .parse::<std::net::IpAddr>()
+                .ok()
+                .map(|ip| std::net::SocketAddr::new(ip, DEFAULT_CONTROL_PORT))
+        })?;
+    Some(AnpNode::from_address(name, address))
+}
+
 impl eframe::App for AaeqApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Apply current theme
@@ -2778,6 +2906,9 @@ impl eframe::App for AaeqApp {
                         SinkType::AirPlay => {
                             self.dsp_view.available_airplay_devices = devices.clone();
                         }
+                        SinkType::Anp => {
+                            self.dsp_view.available_anp_devices = devices.clone();
+                        }
                     }
                     // Also update legacy list for backwards compatibility
                     self.dsp_view.available_devices = devices.clone();
@@ -2792,6 +2923,7 @@ impl eframe::App for AaeqApp {
                 AppResponse::DspStreamingStopped => {
                     self.dsp_view.is_streaming = false;
                     self.dsp_view.stream_status = None;
+                    self.dsp_view.remote_dsp_status = None;
                     self.dsp_view.clear_buffers(); // Clear visualization buffers when stopping
                     self.dsp_view.reset_auto_delay(); // Reset auto-detection for next session
 
@@ -2824,6 +2956,7 @@ impl eframe::App for AaeqApp {
 
                         if let Some(device) = &self.dsp_view.selected_device {
                             let dsp_config = DspRuntimeConfig {
+                                profile_id: self.active_profile_id,
                                 dither_enabled: self.dsp_view.dither_enabled,
                                 dither_mode: self.dsp_view.dither_mode,
                                 noise_shaping: self.dsp_view.noise_shaping,
@@ -2868,6 +3001,22 @@ impl eframe::App for AaeqApp {
                     }
                     self.dsp_view.stream_status = Some(status);
                 }
+                AppResponse::AnpDspAck(ack) => {
+                    let errors = ack.errors.iter()
+                        .map(|e| format!("{} {}", e.code, e.message))
+                        .collect::<Vec<_>>()
+                        .join("; ");
+                    let summary = match ack.status {
+                        DspStatus::Success if ack.applied.equalizer => "EQ running on node".to_string(),
+                        DspStatus::Success => "profile applied".to_string(),
+                        DspStatus::Partial => format!("partially applied ({})", errors),
+                        DspStatus::Failed => format!("failed, processing locally ({})", errors),
+                    };
+                    if ack.status != DspStatus::Success {
+                        self.status_message = Some(format!("ANP node DSP: {}", summary));
+                    }
+                    self.dsp_view.remote_dsp_status = Some((summary, ack.status == DspStatus::Success));
+                }
                 AppResponse::DspAudioSamples(samples) => {
                     // Buffer samples for delayed visualization (for network streaming sync)
                     self.dsp_view.buffer_samples(samples);
@@ -3528,6 +3677,10 @@ impl eframe::App for AaeqApp {
                                             self.dsp_view.last_airplay_device = Some(device.clone());
                                             tracing::info!("Saved AirPlay device: {}", device);
                                         }
+                                        SinkType::Anp => {
+                                            self.dsp_view.last_anp_device = Some(device.clone());
+                                            tracing::info!("Saved ANP node: {}", device);
+                                        }
                                     }
                                 }
 
@@ -3557,6 +3710,13 @@ impl eframe::App for AaeqApp {
                                         }
                                         saved
                                     }
+                                    SinkType::Anp => {
+                                        let saved = self.dsp_view.last_anp_device.clone();
+                                        if let Some(ref device) = saved {
+                                            tracing::info!("Restored ANP node: {}", device);
+                                        }
+                                        saved
+                                    }
                                 };
 
                                 // Load DSP sink settings for the new sink type
@@ -3602,6 +3762,13 @@ impl eframe::App for AaeqApp {
                                     SinkType::AirPlay => {
                                         // AirPlay supports all formats
                                     }
+                                    SinkType::Anp => {
+                                        // ANP streams L16/L24 PCM
+                                        if self.dsp_view.format == FormatOption::F32 {
+                                            self.dsp_view.format = FormatOption::S24LE;
+                                            tracing::info!("Switched to S24LE format for ANP compatibility");
+                                        }
+                                    }
                                 }
 
                                 // Clear visualization buffers when switching sink types
@@ -3631,6 +3798,9 @@ impl eframe::App for AaeqApp {
                                     SinkType::AirPlay => {
                                         self.dsp_view.last_airplay_device = Some(device.clone());
                                     }
+                                    SinkType::Anp => {
+                                        self.dsp_view.last_anp_device = Some(device.clone());
+                                    }
                                 }
 
                                 // Also save the selected output device to settings (for initial restore on startup)
@@ -3694,6 +3864,11 @@ impl eframe::App for AaeqApp {
                                             self.dsp_view.available_airplay_devices.push(device_display.clone());
                                         }
                                     }
+                                    "Anp" => {
+                                        if !self.dsp_view.available_anp_devices.contains(&device_display) {
+                                            self.dsp_view.available_anp_devices.push(device_display.clone());
+                                        }
+                                    }
                                     _ => {
                                         tracing::warn!("Unknown protocol: {}", protocol);
                                     }
@@ -3728,6 +3903,7 @@ impl eframe::App for AaeqApp {
 
                                 if let Some(device) = &self.dsp_view.selected_device {
                                     let dsp_config = DspRuntimeConfig {
+                                        profile_id: self.active_profile_id,
                                         dither_enabled: self.dsp_view.dither_enabled,
                                         dither_mode: self.dsp_view.dither_mode,
                                         noise_shaping: self.dsp_view.noise_shaping,
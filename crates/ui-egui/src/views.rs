@@ -952,11 +952,13 @@ pub struct DspView {
     pub available_local_devices: Vec<String>, // Local DAC devices
     pub available_dlna_devices: Vec<String>, // DLNA/UPnP devices
     pub available_airplay_devices: Vec<String>, // AirPlay devices
+    pub available_anp_devices: Vec<String>, // ANP nodes
     pub selected_device: Option<String>,
     // Store last selected device per sink type
     pub last_local_dac_device: Option<String>,
     pub last_dlna_device: Option<String>,
     pub last_airplay_device: Option<String>,
+    pub last_anp_device: Option<String>,
     pub available_input_devices: Vec<String>,
     pub selected_input_device: Option<String>,
     pub sample_rate: u32,
@@ -966,6 +968,7 @@ pub struct DspView {
     pub is_starting: bool, // True while waiting for streaming to start (for loading spinner)
     pub needs_restart: bool, // True when stream needs to be restarted after settings change
     pub stream_status: Option<StreamStatus>,
+    pub remote_dsp_status: Option<(String, bool)>, // (summary, ok) from the ANP node's last dsp_update_ack
     pub show_device_discovery: bool,
     pub discovering: bool,
     pub show_add_device_dialog: bool, // Show manual device entry dialog
@@ -1040,6 +1043,7 @@ pub enum SinkType {
     LocalDac,
     Dlna,
     AirPlay,
+    Anp,
 }
 
 impl SinkType {
@@ -1049,6 +1053,7 @@ impl SinkType {
             SinkType::LocalDac => "Local DAC",
             SinkType::Dlna => "DLNA/UPnP",
             SinkType::AirPlay => "AirPlay",
+            SinkType::Anp => "ANP Node",
         }
     }
 
@@ -1058,6 +1063,7 @@ impl SinkType {
             SinkType::LocalDac => "LocalDac",
             SinkType::Dlna => "Dlna",
             SinkType::AirPlay => "AirPlay",
+            SinkType::Anp => "Anp",
         }
     }
 }
@@ -1097,10 +1103,12 @@ impl Default for DspView {
             available_local_devices: vec![],
             available_dlna_devices: vec![],
             available_airplay_devices: vec![],
+            available_anp_devices: vec![],
             selected_device: None,
             last_local_dac_device: None,
             last_dlna_device: None,
             last_airplay_device: None,
+            last_anp_device: None,
             available_input_devices: vec![],
             selected_input_device: None,
             sample_rate: 48000,
@@ -1110,6 +1118,7 @@ impl Default for DspView {
             is_starting: false,
             needs_restart: false,
             stream_status: None,
+            remote_dsp_status: None,
             show_device_discovery: false,
             discovering: false,
             show_add_device_dialog: false, // Dialog hidden by default
@@ -1231,6 +1240,7 @@ impl DspView {
                     SinkType::LocalDac => "Audio Output (Local DAC)",
                     SinkType::Dlna => "Audio Output (DLNA)",
                     SinkType::AirPlay => "Audio Output (AirPlay)",
+                    SinkType::Anp => "Audio Output (ANP Node)",
                 };
                 ui.heading(heading_text);
 
@@ -1334,6 +1344,17 @@ impl DspView {
                                 .color(egui::Color32::GRAY)
                                 .italics()
                         );
+                        // Result of pushing the DSP profile to an ANP node
+                        if self.is_streaming && self.selected_sink == SinkType::Anp {
+                            if let Some((summary, ok)) = &self.remote_dsp_status {
+                                let color = if *ok {
+                                    egui::Color32::from_rgb(50, 205, 50)
+                                } else {
+                                    egui::Color32::from_rgb(255, 200, 0)
+                                };
+                                ui.label(egui::RichText::new(format!("Node DSP: {}", summary)).color(color).size(10.0));
+                            }
+                        }
                     });
                 });
             });
@@ -1358,6 +1379,10 @@ impl DspView {
                     self.selected_sink = SinkType::AirPlay;
                     action = Some(DspAction::SinkTypeChanged(SinkType::AirPlay));
                 }
+                if ui.selectable_label(self.selected_sink == SinkType::Anp, "ANP Node").clicked() {
+                    self.selected_sink = SinkType::Anp;
+                    action = Some(DspAction::SinkTypeChanged(SinkType::Anp));
+                }
             });
 
             // Warning about feedback loop for Local DAC without test tone
@@ -1403,7 +1428,7 @@ impl DspView {
             }
 
             // Visualization delay control (only for network streaming)
-            if matches!(self.selected_sink, SinkType::Dlna | SinkType::AirPlay) {
+            if matches!(self.selected_sink, SinkType::Dlna | SinkType::AirPlay | SinkType::Anp) {
                 ui.add_space(5.0);
 
                 ui.horizontal(|ui| {
@@ -1488,7 +1513,7 @@ impl DspView {
                 #[cfg(target_os = "linux")]
                 {
                     let has_loopback_device = self.available_input_devices.iter()
-                        .any(|d| d.contains("aaeq_capture") || d.contains("aaeq_monitor"));
+                        .any(|d| d == stream_server::VIRTUAL_SINK_DEVICE || d.contains("aaeq_capture") || d.contains("aaeq_monitor"));
 
                     if !has_loopback_device && !self.available_input_devices.is_empty() {
                         ui.add_space(5.0);
@@ -1498,13 +1523,12 @@ impl DspView {
                                     .color(egui::Color32::LIGHT_BLUE)
                             );
                             ui.label(
-                                egui::RichText::new("Tip: To capture system audio, run setup-audio-loopback.sh")
+                                egui::RichText::new("System audio capture needs a running PulseAudio or PipeWire server")
                                     .color(egui::Color32::LIGHT_GRAY)
                                     .italics()
                             ).on_hover_text(
-                                "This script sets up a virtual audio sink that captures system audio.\n\
-                                Run: ./setup-audio-loopback.sh\n\
-                                Then discover devices again to see 'aaeq_capture' or 'aaeq_monitor'."
+                                "AAEQ creates its own virtual sink on PulseAudio or PipeWire (pipewire-pulse).\n\
+                                Start one of them, then discover devices again to see 'AAEQ Virtual Sink'."
                             );
                         });
                     }
@@ -1582,6 +1606,7 @@ impl DspView {
                     SinkType::LocalDac => &self.available_local_devices,
                     SinkType::Dlna => &self.available_dlna_devices,
                     SinkType::AirPlay => &self.available_airplay_devices,
+                    SinkType::Anp => &self.available_anp_devices,
                 };
 
                 // Show appropriate text based on cache state
@@ -1623,10 +1648,11 @@ impl DspView {
             });
 
             // Show prominent warning if device cache is empty for network devices
-            if matches!(self.selected_sink, SinkType::Dlna | SinkType::AirPlay) {
+            if matches!(self.selected_sink, SinkType::Dlna | SinkType::AirPlay | SinkType::Anp) {
                 let devices_list = match self.selected_sink {
                     SinkType::Dlna => &self.available_dlna_devices,
                     SinkType::AirPlay => &self.available_airplay_devices,
+                    SinkType::Anp => &self.available_anp_devices,
                     _ => &Vec::new(),
                 };
 
@@ -2298,6 +2324,7 @@ impl DspView {
                 SinkType::LocalDac => &self.available_local_devices,
                 SinkType::Dlna => &self.available_dlna_devices,
                 SinkType::AirPlay => &self.available_airplay_devices,
+                SinkType::Anp => &self.available_anp_devices,
             };
 
             egui::Window::new("Discover Devices")
@@ -2369,6 +2396,7 @@ impl DspView {
                         SinkType::LocalDac => "Local DAC",
                         SinkType::Dlna => "DLNA/UPnP",
                         SinkType::AirPlay => "AirPlay",
+                        SinkType::Anp => "ANP",
                     };
                     ui.label(format!("Protocol: {}", protocol_str));
 
@@ -2383,6 +2411,7 @@ impl DspView {
                                 SinkType::LocalDac => "LocalDac",
                                 SinkType::Dlna => "Dlna",
                                 SinkType::AirPlay => "AirPlay",
+                                SinkType::Anp => "Anp",
                             };
 
                             action = Some(DspAction::AddManualDevice {
@@ -2554,8 +2583,8 @@ impl DspView {
             self.selected_sink
         );
 
-        // Only auto-detect for network streaming (DLNA/AirPlay)
-        if !matches!(self.selected_sink, SinkType::Dlna | SinkType::AirPlay) {
+        // Only auto-detect for network streaming (DLNA/AirPlay/ANP)
+        if !matches!(self.selected_sink, SinkType::Dlna | SinkType::AirPlay | SinkType::Anp) {
             tracing::debug!("Not auto-detecting: not a network sink");
             return false;
         }
@@ -2603,6 +2632,9 @@ impl DspView {
                 SinkType::AirPlay => {
                     self.selected_device.as_deref().unwrap_or("AirPlay")
                 }
+                SinkType::Anp => {
+                    self.selected_device.as_deref().unwrap_or("ANP Node")
+                }
             }
         } else {
             "Stopped"